@@ -0,0 +1,322 @@
+//! Account alerts
+//!
+//! Rules from the `alerts` config object are evaluated every time the
+//! account is fetched, whichever export fetched it. A threshold rule
+//! raises one alert when it is crossed and re-arms once the account is
+//! back on the right side of it, so a breach that lasts all afternoon is
+//! reported once. Raised alerts are pushed as `alert` events and kept for
+//! `get_alerts`.
+
+use crate::alpaca::AlpacaAccount;
+use crate::decimal::{self, Decimal};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::Mutex;
+
+/// Raised alerts kept for `get_alerts`
+const HISTORY: usize = 500;
+
+#[derive(Clone, Debug, Default)]
+pub struct AlertRules {
+    /// Percent drop of equity from `last_equity` (previous close)
+    pub equity_drawdown_pct: Option<Decimal>,
+    pub cash_below: Option<Decimal>,
+    /// Maintenance margin as a percent of equity
+    pub margin_utilization_above: Option<Decimal>,
+    /// Alert whenever the day-trade count changes
+    pub pdt_count_change: bool,
+}
+
+impl AlertRules {
+    pub fn from_config(value: Option<&serde_json::Value>) -> Result<Self, String> {
+        let Some(value) = value else {
+            return Ok(Self::default());
+        };
+        let object = value.as_object().ok_or("alerts must be an object")?;
+
+        let positive = |key: &str| -> Result<Option<Decimal>, String> {
+            match object.get(key) {
+                None | Some(serde_json::Value::Null) => Ok(None),
+                Some(v) => v
+                    .as_f64()
+                    .filter(|n| *n > 0.0)
+                    .and_then(decimal::from_f64)
+                    .map(Some)
+                    .ok_or_else(|| format!("alerts.{} must be a positive number", key)),
+            }
+        };
+        Ok(Self {
+            equity_drawdown_pct: positive("equity_drawdown_pct")?,
+            cash_below: positive("cash_below")?,
+            margin_utilization_above: positive("margin_utilization_above")?,
+            pdt_count_change: object
+                .get("pdt_count_change")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.equity_drawdown_pct.is_none()
+            && self.cash_below.is_none()
+            && self.margin_utilization_above.is_none()
+            && !self.pdt_count_change
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Alert {
+    pub id: u64,
+    pub rule: &'static str,
+    pub message: String,
+    /// The observed value, as a decimal string
+    pub value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<String>,
+    pub raised_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+pub struct AlertMonitor {
+    rules: AlertRules,
+    /// Threshold rules currently breached
+    breached: BTreeSet<&'static str>,
+    last_daytrade_count: Option<i32>,
+    next_id: u64,
+    history: VecDeque<Alert>,
+}
+
+impl AlertMonitor {
+    /// Replace the rules, re-arming every rule
+    pub fn configure(&mut self, rules: AlertRules) {
+        self.rules = rules;
+        self.breached.clear();
+        self.last_daytrade_count = None;
+    }
+
+    /// Evaluate the rules against a freshly fetched account; returns the
+    /// alerts raised by this observation
+    pub fn observe(&mut self, account: &AlpacaAccount) -> Vec<Alert> {
+        if self.rules.is_empty() {
+            return Vec::new();
+        }
+        let equity = decimal::parse_or_zero(&account.equity);
+        let mut raised = Vec::new();
+
+        if let Some(max) = self.rules.equity_drawdown_pct {
+            let last_equity = decimal::parse_or_zero(&account.last_equity);
+            let drawdown = (last_equity > Decimal::ZERO)
+                .then(|| ((last_equity - equity) / last_equity * Decimal::ONE_HUNDRED).round_dp(2));
+            if let Some(alert) = self.threshold(
+                "equity_drawdown",
+                drawdown.filter(|d| *d >= max),
+                max,
+                "Equity is {}% below the previous close",
+            ) {
+                raised.push(alert);
+            }
+        }
+        if let Some(min) = self.rules.cash_below {
+            let cash = decimal::parse(&account.cash);
+            if let Some(alert) = self.threshold(
+                "cash_below",
+                cash.filter(|c| *c < min),
+                min,
+                "Cash is down to {}",
+            ) {
+                raised.push(alert);
+            }
+        }
+        if let Some(max) = self.rules.margin_utilization_above {
+            let utilization = account
+                .maintenance_margin
+                .as_deref()
+                .and_then(decimal::parse)
+                .filter(|_| equity > Decimal::ZERO)
+                .map(|m| (m / equity * Decimal::ONE_HUNDRED).round_dp(2));
+            if let Some(alert) = self.threshold(
+                "margin_utilization",
+                utilization.filter(|u| *u > max),
+                max,
+                "Margin utilization is {}%",
+            ) {
+                raised.push(alert);
+            }
+        }
+        if self.rules.pdt_count_change {
+            if let Some(count) = account.daytrade_count {
+                let previous = self.last_daytrade_count.replace(count);
+                if previous.is_some_and(|p| p != count) {
+                    raised.push(self.raise(
+                        "pdt_count_change",
+                        format!(
+                            "Day-trade count changed from {} to {}",
+                            previous.unwrap_or_default(),
+                            count
+                        ),
+                        count.to_string(),
+                        None,
+                    ));
+                }
+            }
+        }
+        raised
+    }
+
+    /// Raise `rule` when `breach` holds a value and it was not already
+    /// breached; clear it otherwise
+    fn threshold(
+        &mut self,
+        rule: &'static str,
+        breach: Option<Decimal>,
+        threshold: Decimal,
+        message: &str,
+    ) -> Option<Alert> {
+        let Some(value) = breach else {
+            self.breached.remove(rule);
+            return None;
+        };
+        if !self.breached.insert(rule) {
+            return None;
+        }
+        let value = decimal::to_wire(value);
+        Some(self.raise(
+            rule,
+            message.replace("{}", &value),
+            value,
+            Some(decimal::to_wire(threshold)),
+        ))
+    }
+
+    fn raise(
+        &mut self,
+        rule: &'static str,
+        message: String,
+        value: String,
+        threshold: Option<String>,
+    ) -> Alert {
+        self.next_id += 1;
+        let alert = Alert {
+            id: self.next_id,
+            rule,
+            message,
+            value,
+            threshold,
+            raised_at: Utc::now(),
+        };
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(alert.clone());
+        alert
+    }
+
+    /// Alerts raised after `since_id`, oldest first
+    pub fn history(&self, since_id: u64) -> Vec<Alert> {
+        self.history
+            .iter()
+            .filter(|a| a.id > since_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Threshold rules that are breached right now
+    pub fn breached(&self) -> Vec<&'static str> {
+        self.breached.iter().copied().collect()
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref MONITOR: Mutex<AlertMonitor> = Mutex::new(AlertMonitor::default());
+}
+
+pub fn monitor() -> std::sync::MutexGuard<'static, AlertMonitor> {
+    MONITOR.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Evaluate a fetched account and queue any raised alerts for push
+pub fn observe(account: &AlpacaAccount) {
+    let raised = monitor().observe(account);
+    raised.into_iter().for_each(publish);
+}
+
+/// Raise an alert detected outside the account rules, such as a tripped
+/// daily loss limit
+pub fn raise(rule: &'static str, message: String, value: String, threshold: Option<String>) {
+    let alert = monitor().raise(rule, message, value, threshold);
+    publish(alert);
+}
+
+fn publish(alert: Alert) {
+    crate::logging::warn("alerts", alert.message.as_str())
+        .field("rule", alert.rule)
+        .emit();
+    crate::events::push("alert", serde_json::json!({ "alert": alert }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(equity: &str, cash: &str, daytrades: i32) -> AlpacaAccount {
+        serde_json::from_value(serde_json::json!({
+            "id": "acct",
+            "account_number": "PA123",
+            "status": "ACTIVE",
+            "currency": "USD",
+            "cash": cash,
+            "portfolio_value": equity,
+            "buying_power": cash,
+            "equity": equity,
+            "last_equity": "100000",
+            "daytrade_count": daytrades,
+            "maintenance_margin": "30000",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn threshold_rules_fire_once_per_breach() {
+        let mut monitor = AlertMonitor::default();
+        monitor.configure(
+            AlertRules::from_config(Some(&serde_json::json!({
+                "equity_drawdown_pct": 5,
+                "cash_below": 1000,
+                "pdt_count_change": true,
+            })))
+            .unwrap(),
+        );
+
+        assert!(monitor.observe(&account("99000", "5000", 0)).is_empty());
+        let raised = monitor.observe(&account("94000", "500", 0));
+        let rules: Vec<_> = raised.iter().map(|a| a.rule).collect();
+        assert_eq!(rules, ["equity_drawdown", "cash_below"]);
+        assert_eq!(raised[0].value, "6");
+
+        // Still breached: nothing new
+        assert!(monitor.observe(&account("93000", "400", 0)).is_empty());
+        assert_eq!(monitor.breached(), ["cash_below", "equity_drawdown"]);
+
+        // Recovered, then breached again
+        monitor.observe(&account("99000", "5000", 1));
+        assert!(monitor.breached().is_empty());
+        let raised = monitor.observe(&account("94000", "5000", 1));
+        assert_eq!(raised.len(), 1);
+        assert_eq!(monitor.history(0).len(), 4);
+        assert_eq!(monitor.history(3).len(), 1);
+    }
+
+    #[test]
+    fn margin_utilization_uses_maintenance_margin() {
+        let mut monitor = AlertMonitor::default();
+        monitor.configure(
+            AlertRules::from_config(Some(&serde_json::json!({ "margin_utilization_above": 25 })))
+                .unwrap(),
+        );
+        let raised = monitor.observe(&account("100000", "5000", 0));
+        assert_eq!(raised[0].rule, "margin_utilization");
+        assert_eq!(raised[0].value, "30");
+        assert!(AlertRules::from_config(Some(&serde_json::json!({ "cash_below": -1 }))).is_err());
+    }
+}
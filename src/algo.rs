@@ -0,0 +1,610 @@
+//! Execution algorithms
+//!
+//! A parent order submitted with `extensions.algo` is worked locally as a
+//! series of child orders. The parent is virtual: it never reaches Alpaca.
+//! Its children are ordinary orders that go through the same checks as
+//! `submit_order`. The `tick` export drives the schedule. It rolls any
+//! unfilled child quantity into the next slice, so at most one child is
+//! working at a time.
+//!
+//! TWAP sends equal slices. VWAP sizes each slice from recent minute-bar
+//! volume at a target participation rate, so a large order in a thin name
+//! trades no faster than the market. Iceberg keeps only a display quantity
+//! working and sends the next slice when the previous one is done.
+
+use crate::decimal::{self, Decimal};
+use crate::market_data::PriceSource;
+use crate::orders;
+use chrono::{DateTime, Duration, Utc};
+use models::order::{Order, OrderRequest, OrderSide, OrderStatus, OrderType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlgoSpec {
+    /// Equal slices at equal intervals across the window
+    Twap {
+        duration_minutes: i64,
+        slices: u32,
+        #[serde(default)]
+        start_at: Option<DateTime<Utc>>,
+    },
+    /// One slice per interval, sized at `participation_pct` of the average
+    /// minute volume over the last `lookback_minutes`
+    Vwap {
+        duration_minutes: i64,
+        participation_pct: Decimal,
+        #[serde(default = "default_interval_minutes")]
+        interval_minutes: i64,
+        #[serde(default = "default_lookback_minutes")]
+        lookback_minutes: i64,
+        #[serde(default)]
+        start_at: Option<DateTime<Utc>>,
+    },
+    /// Limit order showing at most `display_qty` at a time. With
+    /// `reprice_to`, each new slice is priced from the latest quote but
+    /// never beyond the parent's limit.
+    Iceberg {
+        display_qty: Decimal,
+        #[serde(default)]
+        reprice_to: Option<PriceSource>,
+    },
+}
+
+fn default_interval_minutes() -> i64 {
+    1
+}
+
+fn default_lookback_minutes() -> i64 {
+    5
+}
+
+/// Read `extensions.algo`; `Ok(None)` for an ordinary order
+pub fn requested(order: &OrderRequest) -> Result<Option<AlgoSpec>, String> {
+    let spec: AlgoSpec = match order.extensions.as_ref().and_then(|e| e.get("algo")) {
+        None | Some(serde_json::Value::Null) => return Ok(None),
+        Some(v) => serde_json::from_value(v.clone()).map_err(|e| format!("Invalid algo: {}", e))?,
+    };
+    match &spec {
+        AlgoSpec::Twap {
+            duration_minutes,
+            slices,
+            ..
+        } => {
+            if *duration_minutes <= 0 {
+                return Err("algo.duration_minutes must be positive".to_string());
+            }
+            if *slices == 0 {
+                return Err("algo.slices must be positive".to_string());
+            }
+        }
+        AlgoSpec::Vwap {
+            duration_minutes,
+            participation_pct,
+            interval_minutes,
+            lookback_minutes,
+            ..
+        } => {
+            if *duration_minutes <= 0 || *interval_minutes <= 0 || *lookback_minutes <= 0 {
+                return Err(
+                    "algo.duration_minutes, interval_minutes, and lookback_minutes must be positive"
+                        .to_string(),
+                );
+            }
+            if *participation_pct <= Decimal::ZERO || *participation_pct > Decimal::ONE_HUNDRED {
+                return Err("algo.participation_pct must be in (0, 100]".to_string());
+            }
+        }
+        AlgoSpec::Iceberg { display_qty, .. } => {
+            if *display_qty <= Decimal::ZERO {
+                return Err("algo.display_qty must be positive".to_string());
+            }
+            if order.order_type != OrderType::Limit || order.limit_price.is_none() {
+                return Err("Iceberg orders must be limit orders".to_string());
+            }
+        }
+    }
+    if order.quantity <= 0.0 {
+        return Err("Invalid quantity for algo order".to_string());
+    }
+    Ok(Some(spec))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlgoStatus {
+    Working,
+    /// Fully filled
+    Completed,
+    /// The window ended with quantity unfilled
+    Expired,
+    Canceled,
+    /// An iceberg slice was rejected, so the parent stopped
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AlgoOrder {
+    pub id: String,
+    pub request: OrderRequest,
+    pub spec: AlgoSpec,
+    pub status: AlgoStatus,
+    pub total_qty: Decimal,
+    pub filled_qty: Decimal,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_fill_price: Option<Decimal>,
+    pub start_at: DateTime<Utc>,
+    /// End of the window for timed algos; icebergs work until filled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_at: Option<DateTime<Utc>>,
+    /// Index of the next slice to send
+    pub next_slice: u32,
+    /// Child order IDs, oldest first
+    pub children: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl AlgoOrder {
+    pub fn new(id: String, request: &OrderRequest, spec: AlgoSpec, now: DateTime<Utc>) -> Self {
+        let (start_at, end_at) = match &spec {
+            AlgoSpec::Twap {
+                duration_minutes,
+                start_at,
+                ..
+            }
+            | AlgoSpec::Vwap {
+                duration_minutes,
+                start_at,
+                ..
+            } => {
+                let start = start_at.unwrap_or(now).max(now);
+                (start, Some(start + Duration::minutes(*duration_minutes)))
+            }
+            AlgoSpec::Iceberg { .. } => (now, None),
+        };
+        let mut template = request.clone();
+        if let Some(ext) = template.extensions.as_mut() {
+            ext.remove("algo");
+        }
+        Self {
+            id,
+            request: template,
+            spec,
+            status: AlgoStatus::Working,
+            total_qty: decimal::from_f64(request.quantity).unwrap_or_default(),
+            filled_qty: Decimal::ZERO,
+            avg_fill_price: None,
+            start_at,
+            end_at,
+            next_slice: 0,
+            children: Vec::new(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn is_working(&self) -> bool {
+        self.status == AlgoStatus::Working
+    }
+
+    pub fn remaining_qty(&self) -> Decimal {
+        (self.total_qty - self.filled_qty).max(Decimal::ZERO)
+    }
+
+    fn slice_count(&self) -> u32 {
+        match &self.spec {
+            AlgoSpec::Twap { slices, .. } => *slices,
+            AlgoSpec::Vwap {
+                duration_minutes,
+                interval_minutes,
+                ..
+            } => (duration_minutes / interval_minutes).max(1) as u32,
+            AlgoSpec::Iceberg { .. } => u32::MAX,
+        }
+    }
+
+    /// Timed algos cancel the working child when the next slice is due;
+    /// icebergs wait for it to finish
+    pub fn is_timed(&self) -> bool {
+        self.end_at.is_some()
+    }
+
+    /// Whether the window has ended with every slice sent
+    pub fn window_over(&self, now: DateTime<Utc>) -> bool {
+        self.next_slice > 0 && self.end_at.is_some_and(|end| now >= end)
+    }
+
+    /// Quote side used to price the next slice, if repricing
+    pub fn reprice_source(&self) -> Option<PriceSource> {
+        match &self.spec {
+            AlgoSpec::Iceberg { reprice_to, .. } => *reprice_to,
+            _ => None,
+        }
+    }
+
+    /// Minutes of minute-bar history the next slice needs, if any
+    pub fn volume_lookback(&self) -> Option<i64> {
+        match &self.spec {
+            AlgoSpec::Twap { .. } => None,
+            AlgoSpec::Vwap {
+                lookback_minutes, ..
+            } => Some(*lookback_minutes),
+            AlgoSpec::Iceberg { .. } => None,
+        }
+    }
+
+    /// When slice `index` of a timed algo is due
+    pub fn slice_time(&self, index: u32) -> DateTime<Utc> {
+        let Some(end_at) = self.end_at else {
+            return self.start_at;
+        };
+        let window = end_at - self.start_at;
+        self.start_at + window * index as i32 / self.slice_count() as i32
+    }
+
+    pub fn slice_due(&self, now: DateTime<Utc>, child_working: bool) -> bool {
+        if !self.is_working() {
+            return false;
+        }
+        if !self.is_timed() {
+            return !child_working && !self.remaining_qty().is_zero();
+        }
+        self.next_slice < self.slice_count() && now >= self.slice_time(self.next_slice)
+    }
+
+    /// Cumulative quantity that should be filled once slice `index` is done.
+    /// Whole-share parents are sliced in whole shares.
+    fn target_after(&self, index: u32) -> Decimal {
+        let slices = self.slice_count();
+        if index + 1 >= slices {
+            return self.total_qty;
+        }
+        self.round_qty(self.total_qty * Decimal::from(index + 1) / Decimal::from(slices))
+    }
+
+    fn round_qty(&self, qty: Decimal) -> Decimal {
+        if self.total_qty.fract().is_zero() {
+            qty.floor()
+        } else {
+            qty.round_dp(6)
+        }
+    }
+
+    /// Quantity for the next slice, given everything filled so far. VWAP
+    /// needs the average minute volume over its lookback; without it (no
+    /// recent trades) the slice is skipped.
+    pub fn next_slice_qty(&self, avg_minute_volume: Option<Decimal>) -> Decimal {
+        match &self.spec {
+            AlgoSpec::Twap { .. } => {
+                (self.target_after(self.next_slice) - self.filled_qty).max(Decimal::ZERO)
+            }
+            AlgoSpec::Vwap {
+                participation_pct,
+                interval_minutes,
+                ..
+            } => {
+                let Some(volume) = avg_minute_volume else {
+                    return Decimal::ZERO;
+                };
+                let qty = volume * *participation_pct / Decimal::ONE_HUNDRED
+                    * Decimal::from(*interval_minutes);
+                self.round_qty(qty).min(self.remaining_qty())
+            }
+            AlgoSpec::Iceberg { display_qty, .. } => {
+                self.round_qty(*display_qty).min(self.remaining_qty())
+            }
+        }
+    }
+
+    /// The child order for a slice of `qty`. A quoted `price` reprices the
+    /// limit, capped at the parent's limit.
+    pub fn child_request(&self, qty: Decimal, price: Option<Decimal>) -> OrderRequest {
+        let mut child = self.request.clone();
+        child.quantity = decimal::to_f64(qty);
+        let parent_limit = self.request.limit_price.and_then(decimal::from_f64);
+        if let (Some(price), Some(limit)) = (price, parent_limit) {
+            let capped = match self.request.side {
+                OrderSide::Buy => price.min(limit),
+                OrderSide::Sell => price.max(limit),
+            };
+            child.limit_price = Some(decimal::to_f64(capped));
+        }
+        let ext = child.extensions.get_or_insert_with(HashMap::new);
+        ext.insert("algo_parent_id".to_string(), self.id.clone().into());
+        ext.insert("algo_slice".to_string(), self.next_slice.into());
+        // Equal slices are identical orders by design
+        ext.insert("allow_duplicate".to_string(), true.into());
+        child
+    }
+
+    /// Recompute fills from the children and complete the parent when done
+    pub fn apply_fills(&mut self, children: &[&Order], now: DateTime<Utc>) {
+        let mut filled = Decimal::ZERO;
+        let mut notional = Decimal::ZERO;
+        for child in children {
+            let qty = decimal::from_f64(child.filled_quantity).unwrap_or_default();
+            filled += qty;
+            if let Some(price) = child.average_filled_price.and_then(decimal::from_f64) {
+                notional += qty * price;
+            }
+        }
+        if filled != self.filled_qty {
+            self.updated_at = now;
+        }
+        self.filled_qty = filled;
+        self.avg_fill_price = (!filled.is_zero()).then(|| (notional / filled).round_dp(6));
+        if self.is_working() && self.remaining_qty().is_zero() {
+            self.status = AlgoStatus::Completed;
+            self.updated_at = now;
+        }
+    }
+
+    /// Host-facing view of the virtual parent
+    pub fn to_order(&self) -> Order {
+        let status = match self.status {
+            AlgoStatus::Completed => OrderStatus::Filled,
+            AlgoStatus::Canceled | AlgoStatus::Expired => OrderStatus::Canceled,
+            AlgoStatus::Failed if self.filled_qty.is_zero() => OrderStatus::Rejected,
+            AlgoStatus::Failed => OrderStatus::Canceled,
+            AlgoStatus::Working if !self.filled_qty.is_zero() => OrderStatus::PartiallyFilled,
+            AlgoStatus::Working => OrderStatus::Submitted,
+        };
+        let mut order = Order {
+            id: self.id.clone(),
+            request: self.request.clone(),
+            status,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            average_filled_price: self.avg_fill_price.map(decimal::to_f64),
+            filled_quantity: decimal::to_f64(self.filled_qty),
+            extensions: None,
+            persona_id: self.request.persona_id.clone(),
+        };
+        orders::set_ext(
+            &mut order,
+            "algo",
+            serde_json::json!({
+                "spec": self.spec,
+                "status": self.status,
+                "start_at": self.start_at,
+                "end_at": self.end_at,
+                "slices_sent": self.next_slice,
+                "children": self.children,
+            }),
+        );
+        orders::set_ext(&mut order, "filled_qty", decimal::to_wire(self.filled_qty));
+        orders::set_ext(
+            &mut order,
+            "remaining_qty",
+            decimal::to_wire(self.remaining_qty()),
+        );
+        orders::set_ext(&mut order, "is_terminal", !self.is_working());
+        order
+    }
+}
+
+#[derive(Default)]
+pub struct AlgoBook {
+    orders: Vec<AlgoOrder>,
+    next_id: u64,
+}
+
+impl AlgoBook {
+    pub fn rename_symbol(&mut self, old: &str, new: &str) -> usize {
+        self.orders
+            .iter_mut()
+            .map(|a| crate::orders::rename_symbol(&mut a.request, old, new))
+            .filter(|renamed| *renamed)
+            .count()
+    }
+
+    pub fn create(&mut self, request: &OrderRequest, spec: AlgoSpec) -> &AlgoOrder {
+        self.next_id += 1;
+        let now = Utc::now();
+        let id = format!("algo_{}_{}", now.timestamp_millis(), self.next_id);
+        self.orders.push(AlgoOrder::new(id, request, spec, now));
+        self.orders.last().expect("just pushed")
+    }
+
+    pub fn get(&self, id: &str) -> Option<&AlgoOrder> {
+        self.orders.iter().find(|a| a.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut AlgoOrder> {
+        self.orders.iter_mut().find(|a| a.id == id)
+    }
+
+    pub fn list(&self) -> &[AlgoOrder] {
+        &self.orders
+    }
+
+    pub fn working_ids(&self) -> Vec<String> {
+        self.orders
+            .iter()
+            .filter(|a| a.is_working())
+            .map(|a| a.id.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+
+    fn d(s: &str) -> Decimal {
+        decimal::parse(s).unwrap()
+    }
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    fn algo(side: OrderSide, qty: f64, limit: Option<f64>, spec: serde_json::Value) -> AlgoOrder {
+        let request = order_request("AAPL")
+            .side(side)
+            .qty(qty)
+            .extension("algo", spec);
+        let request = match limit {
+            Some(limit) => request.limit(limit),
+            None => request,
+        }
+        .build();
+        let spec = requested(&request).unwrap().unwrap();
+        AlgoOrder::new(
+            "algo_1".to_string(),
+            &request,
+            spec,
+            at("2024-03-01T15:00:00Z"),
+        )
+    }
+
+    fn child(filled: f64, price: Option<f64>) -> Order {
+        serde_json::from_value(serde_json::json!({
+            "id": "child",
+            "request": order_request("AAPL").qty(filled).build(),
+            "status": OrderStatus::Filled,
+            "created_at": at("2024-03-01T15:00:00Z"),
+            "updated_at": at("2024-03-01T15:00:00Z"),
+            "average_filled_price": price,
+            "filled_quantity": filled,
+            "extensions": null,
+            "persona_id": "default",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn twap_slices_catch_up_and_the_last_takes_the_rest() {
+        let twap = serde_json::json!({ "type": "twap", "duration_minutes": 60, "slices": 3 });
+        let mut order = algo(OrderSide::Buy, 10.0, None, twap);
+        assert_eq!(
+            (0..3).map(|i| order.target_after(i)).collect::<Vec<_>>(),
+            [d("3"), d("6"), d("10")]
+        );
+        assert_eq!(order.next_slice_qty(None), d("3"));
+        // A short fill rolls into the next slice
+        order.next_slice = 1;
+        order.filled_qty = d("2");
+        assert_eq!(order.next_slice_qty(None), d("4"));
+        order.next_slice = 2;
+        order.filled_qty = d("6");
+        assert_eq!(order.next_slice_qty(None), d("4"));
+        // Ahead of schedule sends nothing
+        order.filled_qty = d("10");
+        assert_eq!(order.next_slice_qty(None), Decimal::ZERO);
+
+        let twap = serde_json::json!({ "type": "twap", "duration_minutes": 60, "slices": 4 });
+        let fractional = algo(OrderSide::Buy, 1.5, None, twap);
+        assert_eq!(fractional.next_slice_qty(None), d("0.375"));
+    }
+
+    #[test]
+    fn timed_slices_fall_due_across_the_window() {
+        let start = at("2024-03-01T15:00:00Z");
+        let twap = serde_json::json!({
+            "type": "twap", "duration_minutes": 60, "slices": 4, "start_at": "2024-03-01T14:00:00Z",
+        });
+        let mut order = algo(OrderSide::Buy, 100.0, None, twap);
+        // A start in the past begins now
+        assert_eq!(order.start_at, start);
+        assert_eq!(order.end_at, Some(start + Duration::minutes(60)));
+        assert_eq!(order.slice_time(0), start);
+        assert_eq!(order.slice_time(1), start + Duration::minutes(15));
+        assert_eq!(order.slice_time(3), start + Duration::minutes(45));
+
+        assert!(order.slice_due(start, true));
+        order.next_slice = 1;
+        assert!(!order.slice_due(start + Duration::minutes(14), false));
+        assert!(order.slice_due(start + Duration::minutes(15), true));
+        order.next_slice = 4;
+        assert!(!order.slice_due(start + Duration::minutes(90), false));
+        assert!(order.window_over(start + Duration::minutes(60)));
+
+        let iceberg = serde_json::json!({ "type": "iceberg", "display_qty": "10" });
+        let iceberg = algo(OrderSide::Buy, 100.0, Some(100.0), iceberg);
+        assert_eq!(iceberg.slice_time(5), start);
+        assert!(iceberg.slice_due(start, false));
+        assert!(!iceberg.slice_due(start, true));
+    }
+
+    #[test]
+    fn vwap_and_iceberg_slices_are_sized_and_capped() {
+        let vwap = serde_json::json!({
+            "type": "vwap", "duration_minutes": 30, "participation_pct": "10", "interval_minutes": 2,
+        });
+        let mut order = algo(OrderSide::Buy, 500.0, None, vwap);
+        assert_eq!(order.next_slice_qty(Some(d("1000"))), d("200"));
+        // Whole-share parents round down
+        assert_eq!(order.next_slice_qty(Some(d("1234.5"))), d("246"));
+        assert_eq!(order.next_slice_qty(None), Decimal::ZERO);
+        order.filled_qty = d("450");
+        assert_eq!(order.next_slice_qty(Some(d("1000"))), d("50"));
+
+        let iceberg = serde_json::json!({ "type": "iceberg", "display_qty": "30" });
+        let mut order = algo(OrderSide::Buy, 100.0, Some(100.0), iceberg);
+        assert_eq!(order.next_slice_qty(None), d("30"));
+        order.filled_qty = d("90");
+        assert_eq!(order.next_slice_qty(None), d("10"));
+    }
+
+    #[test]
+    fn child_prices_never_go_past_the_parent_limit() {
+        let iceberg =
+            serde_json::json!({ "type": "iceberg", "display_qty": "10", "reprice_to": "ask" });
+        let buy = algo(OrderSide::Buy, 100.0, Some(100.0), iceberg.clone());
+        assert_eq!(
+            buy.child_request(d("10"), Some(d("101"))).limit_price,
+            Some(100.0)
+        );
+        assert_eq!(
+            buy.child_request(d("10"), Some(d("99.5"))).limit_price,
+            Some(99.5)
+        );
+        assert_eq!(buy.child_request(d("10"), None).limit_price, Some(100.0));
+
+        let sell = algo(OrderSide::Sell, 100.0, Some(100.0), iceberg);
+        assert_eq!(
+            sell.child_request(d("10"), Some(d("99"))).limit_price,
+            Some(100.0)
+        );
+        assert_eq!(
+            sell.child_request(d("10"), Some(d("100.5"))).limit_price,
+            Some(100.5)
+        );
+
+        let child = buy.child_request(d("10"), None);
+        let ext = child.extensions.unwrap();
+        assert_eq!(child.quantity, 10.0);
+        assert_eq!(ext["algo_parent_id"], "algo_1");
+        assert_eq!(ext["algo_slice"], 0);
+        assert_eq!(ext["allow_duplicate"], true);
+        assert!(!ext.contains_key("algo"));
+    }
+
+    #[test]
+    fn fills_are_summed_across_children_and_complete_the_parent() {
+        let twap = serde_json::json!({ "type": "twap", "duration_minutes": 10, "slices": 2 });
+        let mut order = algo(OrderSide::Buy, 10.0, None, twap);
+        let now = at("2024-03-01T15:05:00Z");
+
+        let first = child(4.0, Some(10.0));
+        order.apply_fills(&[&first], now);
+        assert_eq!(order.filled_qty, d("4"));
+        assert_eq!(order.status, AlgoStatus::Working);
+        assert_eq!(order.to_order().status, OrderStatus::PartiallyFilled);
+
+        let second = child(6.0, Some(11.0));
+        let unfilled = child(0.0, None);
+        order.apply_fills(&[&first, &second, &unfilled], now);
+        assert_eq!(order.filled_qty, d("10"));
+        assert_eq!(order.avg_fill_price, Some(d("10.6")));
+        assert_eq!(order.status, AlgoStatus::Completed);
+        assert_eq!(order.updated_at, now);
+        let parent = order.to_order();
+        assert_eq!(parent.status, OrderStatus::Filled);
+        assert_eq!(parent.extensions.unwrap()["is_terminal"], true);
+    }
+}
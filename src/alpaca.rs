@@ -3,141 +3,717 @@
 //! Implements Alpaca's Trading API with API Key authentication.
 //! Documentation: https://docs.alpaca.markets/
 
-use crate::http::{execute, HttpMethod, HttpRequest};
-use chrono::{DateTime, Utc};
-use models::order::{Order, OrderRequest, OrderSide, OrderStatus, OrderType};
+use crate::cashflows::{CashFlow, CashFlowCategory, CASH_ACTIVITY_TYPES};
+use crate::currency;
+use crate::decimal::{self, Decimal, FieldParser};
+use crate::delisting::{self, DelistedFlag, DelistedMarks};
+use crate::executions::Execution;
+use crate::fees::AssetKind;
+use crate::http::{
+    percent_encode, HostTransport, HttpMethod, HttpRequest, HttpResponse, HttpTransport, Pipeline,
+    QueryParams,
+};
+use crate::intent::{self, PositionIntent};
+use crate::logging;
+use crate::market_data::{Bar, Snapshot};
+use crate::marks::{self, Mark, MarkSource};
+use crate::metrics::MetricsRegistry;
+use crate::middleware::{
+    AuthHeaders, BreakerSettings, CircuitBreaker, Compression, Connectivity, Logging, Metrics,
+    RateLimit, ResponseCache, Retry, DEFAULT_RATE_LIMIT_PER_MINUTE,
+};
+use crate::order_status::AlpacaOrderStatus;
+use crate::orders::LegSummary;
+use crate::paging::{fetch_pages, DEFAULT_PAGE_PREFETCH};
+use crate::recording::Recorder;
+use crate::simulator::{Simulate, Simulator};
+use crate::tags;
+use crate::trace::{self, ALPACA_REQUEST_ID_HEADER};
+use chrono::{DateTime, NaiveDate, Utc};
+use models::order::{Order, OrderRequest, OrderSide, OrderType};
 use models::portfolio::{AccountBalance, AccountSummary, Position};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 const LIVE_API_URL: &str = "https://api.alpaca.markets";
 const PAPER_API_URL: &str = "https://paper-api.alpaca.markets";
+pub const DATA_API_URL: &str = "https://data.alpaca.markets";
+
+/// Sent on every request so Alpaca and network teams can tell this
+/// client's traffic apart
+pub const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Headers the client sets itself, which `extra_headers` may not replace
+const RESERVED_HEADERS: &[&str] = &[
+    "accept",
+    "accept-encoding",
+    "authorization",
+    "content-length",
+    "content-type",
+    "host",
+    "if-none-match",
+    "user-agent",
+];
+
+/// Time-in-force values accepted in `extensions.time_in_force`
+pub const TIME_IN_FORCE_VALUES: &[&str] = &["day", "gtc", "opg", "cls", "ioc", "fok"];
+
+/// Default reuse window for positions in account summaries
+pub const DEFAULT_POSITIONS_CACHE: Duration = Duration::from_secs(2);
+
+/// How an account summary's `positions` are filled in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccountPositions {
+    /// Leave them out: one request instead of two. The summary carries
+    /// `extensions.positions_omitted` so an empty list is not read as flat.
+    Omit,
+    /// Reuse positions fetched within the cache window
+    Cached,
+    /// Always fetch them
+    Fresh,
+}
+
+/// Optional client behaviour configured at initialize
+#[derive(Clone, Debug)]
+pub struct ClientOptions {
+    /// Request gzip/deflate and decompress bodies locally. Leave off when
+    /// the host already decompresses responses.
+    pub decompress_responses: bool,
+    /// Fail order and position quantity parsing with a decode error instead
+    /// of silently substituting zero. Display-only fields stay lenient.
+    pub strict_parsing: bool,
+    /// Market data feed (`iex` or `sip`); Alpaca's default when unset
+    pub data_feed: Option<String>,
+    /// Answer trading requests from a local simulator instead of Alpaca
+    pub simulator: Option<Arc<Simulator>>,
+    /// Record traffic to, or replay it from, a replay log
+    pub recorder: Option<Arc<Recorder>>,
+    /// Pages fetched ahead of mapping on paginated endpoints; 0 disables
+    /// pipelining
+    pub page_prefetch: usize,
+    /// Per-path TTLs for the response cache; `None` disables it
+    pub response_cache: Option<Vec<(String, Duration)>>,
+    /// How long account summaries may reuse fetched positions
+    pub positions_cache: Duration,
+    /// Trading API base URL in place of the paper or live one
+    pub api_base_url: Option<String>,
+    /// Market data API base URL in place of Alpaca's
+    pub data_base_url: Option<String>,
+    /// Sent on every request besides the client's own headers
+    pub extra_headers: Vec<(String, String)>,
+    /// Authenticate with HTTP Basic auth instead of the APCA key headers,
+    /// for the Broker API
+    pub basic_auth: bool,
+    /// Where position prices come from
+    pub mark_source: MarkSource,
+    /// How positions in delisted assets are valued
+    pub delisted_marks: DelistedMarks,
+    /// Refuse requests while Alpaca looks down; `None` disables it
+    pub circuit_breaker: Option<BreakerSettings>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            decompress_responses: false,
+            strict_parsing: true,
+            data_feed: None,
+            simulator: None,
+            recorder: None,
+            page_prefetch: DEFAULT_PAGE_PREFETCH,
+            response_cache: Some(ResponseCache::ttls_with_overrides(&[])),
+            positions_cache: DEFAULT_POSITIONS_CACHE,
+            api_base_url: None,
+            data_base_url: None,
+            extra_headers: Vec::new(),
+            basic_auth: false,
+            mark_source: MarkSource::Alpaca,
+            delisted_marks: DelistedMarks::Flag,
+            circuit_breaker: None,
+        }
+    }
+}
+
+/// Check a configured base URL and strip any trailing slash. Only https
+/// is accepted unless `allow_insecure`; a path prefix is kept, for proxies
+/// that mount the API under one.
+pub fn validate_base_url(field: &str, url: &str, allow_insecure: bool) -> Result<String, String> {
+    let rest = match url.split_once("://") {
+        Some(("https", rest)) => rest,
+        Some(("http", rest)) if allow_insecure => rest,
+        Some(("http", _)) => {
+            return Err(format!(
+                "{} must use https (set allow_insecure_urls for local testing)",
+                field
+            ))
+        }
+        _ => return Err(format!("{} must be an https URL", field)),
+    };
+    let host = rest.split('/').next().unwrap_or_default();
+    if host.is_empty()
+        || url.contains(['?', '#'])
+        || url.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(format!("{} is not a valid base URL: {}", field, url));
+    }
+    Ok(url.trim_end_matches('/').to_string())
+}
+
+/// Check the `extra_headers` config: an object of header names to string
+/// values. Names must be plain tokens and may not replace a header the
+/// client sets itself or the APCA auth headers.
+pub fn parse_extra_headers(value: &serde_json::Value) -> Result<Vec<(String, String)>, String> {
+    let headers = value
+        .as_object()
+        .ok_or("extra_headers must be an object of header names to values")?;
+    let mut parsed = Vec::with_capacity(headers.len());
+    for (name, value) in headers {
+        let valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+        if !valid_name {
+            return Err(format!("extra_headers: invalid header name {:?}", name));
+        }
+        let lower = name.to_ascii_lowercase();
+        if RESERVED_HEADERS.contains(&lower.as_str()) || lower.starts_with("apca-") {
+            return Err(format!("extra_headers: {} is set by the plugin", name));
+        }
+        let value = value
+            .as_str()
+            .ok_or_else(|| format!("extra_headers: {} must be a string", name))?;
+        if value.chars().any(|c| c.is_control()) {
+            return Err(format!(
+                "extra_headers: {} contains a control character",
+                name
+            ));
+        }
+        parsed.push((name.clone(), value.to_string()));
+    }
+    Ok(parsed)
+}
 
 pub struct AlpacaClient {
-    api_key: String,
-    api_secret: String,
     base_url: String,
+    data_url: String,
+    data_feed: Option<String>,
     is_paper: bool,
+    parser: FieldParser,
+    mark_source: MarkSource,
+    delisted_marks: DelistedMarks,
+    page_prefetch: usize,
+    extra_headers: Vec<(String, String)>,
+    /// Last fetched positions and when; cleared by any write
+    positions: Mutex<Option<(Instant, PositionSet)>>,
+    positions_ttl: Duration,
+    /// Account currency from the last account fetch; anything but USD
+    /// means local currency trading
+    currency: Mutex<Option<String>>,
+    /// Account number and account ID from the last account fetch
+    identity: Mutex<Option<(String, String)>>,
+    /// [`AlpacaAccount::trading_block`] as of the last account fetch
+    trading_block: Mutex<Option<String>>,
+    pipeline: Pipeline,
+    /// Handles to the pipeline's stateful middleware, for [`Self::housekeeping`]
+    response_cache: Option<Arc<ResponseCache>>,
+    rate_limit: Arc<RateLimit>,
+    /// Handle to the circuit breaker, for [`Self::probe_breaker`]
+    breaker: Option<Arc<CircuitBreaker>>,
+    auth: Arc<AuthHeaders>,
+    metrics: Arc<MetricsRegistry>,
+}
+
+/// What one [`AlpacaClient::housekeeping`] pass cleaned up
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct Housekeeping {
+    pub cache_evicted: usize,
+    pub positions_cache_cleared: bool,
+    /// Requests still counted against the rate limit window
+    pub rate_limit_in_window: usize,
 }
 
 impl AlpacaClient {
     pub fn new(api_key: String, api_secret: String, is_paper: bool) -> Self {
-        let base_url = if is_paper {
-            PAPER_API_URL
-        } else {
-            LIVE_API_URL
+        Self::with_options(api_key, api_secret, is_paper, ClientOptions::default())
+    }
+
+    pub fn with_options(
+        api_key: String,
+        api_secret: String,
+        is_paper: bool,
+        options: ClientOptions,
+    ) -> Self {
+        Self::with_transport(api_key, api_secret, is_paper, options, HostTransport)
+    }
+
+    /// Client that delivers requests through `transport` instead of the
+    /// host import, after the usual middleware
+    pub fn with_transport<T: HttpTransport + 'static>(
+        api_key: String,
+        api_secret: String,
+        is_paper: bool,
+        options: ClientOptions,
+        transport: T,
+    ) -> Self {
+        let base_url = match &options.api_base_url {
+            Some(url) => url.as_str(),
+            None if is_paper => PAPER_API_URL,
+            None => LIVE_API_URL,
+        };
+        let metrics = Arc::new(MetricsRegistry::default());
+        let mut pipeline = Pipeline::with_transport(transport).with(Logging);
+        // Simulated state changes with every call, so it is never cached
+        let response_cache = match (options.response_cache, &options.simulator) {
+            (Some(ttls), None) => Some(Arc::new(
+                ResponseCache::new(ttls).with_metrics(metrics.clone()),
+            )),
+            _ => None,
         };
+        if let Some(cache) = &response_cache {
+            pipeline = pipeline.with(cache.clone());
+        }
+        // Inside the cache, so cached answers are still served while open
+        let breaker = options
+            .circuit_breaker
+            .map(|settings| Arc::new(CircuitBreaker::new(settings)));
+        if let Some(breaker) = &breaker {
+            pipeline = pipeline.with(breaker.clone());
+        }
+        let rate_limit = Arc::new(RateLimit::per_minute(DEFAULT_RATE_LIMIT_PER_MINUTE));
+        pipeline = pipeline
+            .with(Connectivity)
+            .with(Metrics::new(metrics.clone()))
+            .with(Retry::default().with_metrics(metrics.clone()))
+            .with(rate_limit.clone())
+            .with(trace::Propagate);
+        if options.decompress_responses {
+            pipeline = pipeline.with(Compression);
+        }
+        if let Some(simulator) = options.simulator {
+            pipeline = pipeline.with(Simulate(simulator));
+        }
+        // Innermost but for auth, so what is recorded is what went out, and
+        // replay answers without credentials
+        if let Some(recorder) = options.recorder {
+            pipeline = pipeline.with(recorder);
+        }
+        let auth = Arc::new(if options.basic_auth {
+            AuthHeaders::basic(api_key, api_secret)
+        } else {
+            AuthHeaders::new(api_key, api_secret)
+        });
+        let pipeline = pipeline.with(auth.clone());
         Self {
-            api_key,
-            api_secret,
             base_url: base_url.to_string(),
+            data_url: options
+                .data_base_url
+                .unwrap_or_else(|| DATA_API_URL.to_string()),
+            data_feed: options.data_feed,
             is_paper,
+            mark_source: options.mark_source,
+            delisted_marks: options.delisted_marks,
+            parser: FieldParser {
+                strict: options.strict_parsing,
+            },
+            page_prefetch: options.page_prefetch,
+            extra_headers: options.extra_headers,
+            positions: Mutex::new(None),
+            positions_ttl: options.positions_cache,
+            currency: Mutex::new(None),
+            identity: Mutex::new(None),
+            trading_block: Mutex::new(None),
+            pipeline,
+            response_cache,
+            rate_limit,
+            breaker,
+            auth,
+            metrics,
         }
     }
 
+    /// Drop expired cache entries and stale rate limit bookkeeping. Nothing
+    /// here is needed for correctness; it keeps an idle client from holding
+    /// on to memory between bursts of calls.
+    pub fn housekeeping(&self) -> Housekeeping {
+        let now = Instant::now();
+        let mut positions = self.positions.lock().unwrap_or_else(|e| e.into_inner());
+        let positions_cache_cleared = positions
+            .as_ref()
+            .is_some_and(|(fetched, _)| now.duration_since(*fetched) >= self.positions_ttl);
+        if positions_cache_cleared {
+            *positions = None;
+        }
+        drop(positions);
+        Housekeeping {
+            cache_evicted: self
+                .response_cache
+                .as_ref()
+                .map_or(0, |cache| cache.evict_expired(now)),
+            positions_cache_cleared,
+            rate_limit_in_window: self.rate_limit.prune(now),
+        }
+    }
+
+    /// Whether the circuit breaker is refusing requests
+    pub fn breaker_open(&self) -> bool {
+        self.breaker.as_ref().is_some_and(|b| b.is_open())
+    }
+
+    /// Send the circuit breaker's half-open probe if one is due: an
+    /// account request let through the open breaker. `None` when no probe
+    /// was due, otherwise whether the breaker closed.
+    pub fn probe_breaker(&self) -> Option<bool> {
+        let breaker = self.breaker.as_ref()?;
+        if !breaker.probe_due(Instant::now()) {
+            return None;
+        }
+        let mut request = HttpRequest {
+            method: HttpMethod::Get,
+            url: format!("{}/v2/account", self.base_url),
+            headers: self.default_headers(),
+            body: None,
+            timeout_ms: 30000,
+        };
+        CircuitBreaker::mark_probe(&mut request);
+        self.pipeline.send(request);
+        Some(!breaker.is_open())
+    }
+
     fn default_headers(&self) -> HashMap<String, String> {
         let mut headers = HashMap::new();
         headers.insert("Accept".to_string(), "application/json".to_string());
         headers.insert("Content-Type".to_string(), "application/json".to_string());
-        headers.insert("APCA-API-KEY-ID".to_string(), self.api_key.clone());
-        headers.insert("APCA-API-SECRET-KEY".to_string(), self.api_secret.clone());
+        headers.insert("User-Agent".to_string(), USER_AGENT.to_string());
+        for (name, value) in &self.extra_headers {
+            headers.insert(name.clone(), value.clone());
+        }
         headers
     }
 
-    fn api_get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T, String> {
-        let url = format!("{}{}", self.base_url, path);
+    /// Send a request through the middleware pipeline, returning any status
+    fn send_raw(&self, method: HttpMethod, path: &str, body: Option<String>) -> HttpResponse {
+        if method != HttpMethod::Get {
+            *self.positions.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        }
+        self.pipeline.send(HttpRequest {
+            method,
+            url: format!("{}{}", self.base_url, path),
+            headers: self.default_headers(),
+            body,
+            timeout_ms: 30000,
+        })
+    }
+
+    /// Send any request to the trading API, or to the market data API
+    /// with `data`, returning whatever Alpaca answers
+    pub fn raw_request(
+        &self,
+        method: HttpMethod,
+        path: &str,
+        body: Option<String>,
+        data: bool,
+    ) -> HttpResponse {
+        if !data {
+            return self.send_raw(method, path, body);
+        }
+        self.pipeline.send(HttpRequest {
+            method,
+            url: format!("{}{}", self.data_url, path),
+            headers: self.default_headers(),
+            body,
+            timeout_ms: 30000,
+        })
+    }
+
+    /// Send a request through the middleware pipeline, failing on non-2xx
+    fn send(
+        &self,
+        method: HttpMethod,
+        path: &str,
+        body: Option<String>,
+    ) -> Result<HttpResponse, String> {
+        Self::ensure_success(self.send_raw(method, path, body))
+    }
 
-        let response = execute(HttpRequest {
+    /// GET from the market data API; the configured feed is added to the query
+    fn data_get_with<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &QueryParams,
+    ) -> Result<T, String> {
+        let query = query.clone().push_opt("feed", self.data_feed.as_deref());
+        let response = self.pipeline.send(HttpRequest {
             method: HttpMethod::Get,
-            url,
+            url: format!("{}{}", self.data_url, query.apply(path)),
             headers: self.default_headers(),
             body: None,
             timeout_ms: 30000,
         });
+        Self::ensure_success(response)?.json::<T>()
+    }
 
+    fn ensure_success(response: HttpResponse) -> Result<HttpResponse, String> {
         if !response.is_success() {
+            let request_id = response
+                .header(ALPACA_REQUEST_ID_HEADER)
+                .map(|id| format!(" (alpaca request id: {})", id))
+                .unwrap_or_default();
             return Err(format!(
-                "API error {}: {}",
+                "API error {}: {}{}",
                 response.status,
-                response.error.unwrap_or(response.body)
+                response.error.unwrap_or(response.body),
+                request_id
             ));
         }
 
-        response.json::<T>()
+        Ok(response)
     }
 
-    fn api_post<T: serde::de::DeserializeOwned, B: serde::Serialize>(
+    pub(crate) fn api_get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T, String> {
+        self.send(HttpMethod::Get, path, None)?.json::<T>()
+    }
+
+    pub(crate) fn api_get_with<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &QueryParams,
+    ) -> Result<T, String> {
+        self.api_get(&query.apply(path))
+    }
+
+    pub(crate) fn api_post<T: serde::de::DeserializeOwned, B: serde::Serialize>(
         &self,
         path: &str,
         body: &B,
     ) -> Result<T, String> {
-        let url = format!("{}{}", self.base_url, path);
+        let body_str = serde_json::to_string(body).map_err(|e| e.to_string())?;
+        self.send(HttpMethod::Post, path, Some(body_str))?
+            .json::<T>()
+    }
 
+    fn api_patch<T: serde::de::DeserializeOwned, B: serde::Serialize>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, String> {
         let body_str = serde_json::to_string(body).map_err(|e| e.to_string())?;
+        self.send(HttpMethod::Patch, path, Some(body_str))?
+            .json::<T>()
+    }
 
-        let response = execute(HttpRequest {
-            method: HttpMethod::Post,
-            url,
-            headers: self.default_headers(),
-            body: Some(body_str),
-            timeout_ms: 30000,
-        });
+    pub(crate) fn api_delete(&self, path: &str) -> Result<(), String> {
+        self.send(HttpMethod::Delete, path, None).map(|_| ())
+    }
 
-        if !response.is_success() {
-            return Err(format!(
-                "API error {}: {}",
-                response.status,
-                response.error.unwrap_or(response.body)
-            ));
-        }
+    pub fn is_paper(&self) -> bool {
+        self.is_paper
+    }
 
-        response.json::<T>()
+    /// Switch to new credentials for the same account; caches and
+    /// tracked state are kept
+    pub fn rotate_credentials(&self, api_key: String, api_secret: String) {
+        self.auth.rotate(api_key, api_secret);
     }
 
-    fn api_delete(&self, path: &str) -> Result<(), String> {
-        let url = format!("{}{}", self.base_url, path);
+    /// Request and order metrics collected for this client
+    pub fn metrics(&self) -> &MetricsRegistry {
+        &self.metrics
+    }
 
-        let response = execute(HttpRequest {
-            method: HttpMethod::Delete,
-            url,
-            headers: self.default_headers(),
-            body: None,
-            timeout_ms: 30000,
-        });
+    /// Raw `/v2/account` payload, for pre-trade checks that need fields
+    /// the `AccountSummary` mapping does not carry
+    pub fn fetch_account(&self) -> Result<AlpacaAccount, String> {
+        let account: AlpacaAccount = self.api_get("/v2/account")?;
+        *self.currency.lock().unwrap_or_else(|e| e.into_inner()) = Some(account.currency.clone());
+        *self.trading_block.lock().unwrap_or_else(|e| e.into_inner()) = account.trading_block();
+        *self.identity.lock().unwrap_or_else(|e| e.into_inner()) =
+            Some((account.account_number.clone(), account.id.clone()));
+        crate::alerts::observe(&account);
+        Ok(account)
+    }
 
-        if !response.is_success() {
-            return Err(format!(
-                "API error {}: {}",
-                response.status,
-                response.error.unwrap_or(response.body)
-            ));
+    /// Whether `account_id` names the account behind these keys. Either
+    /// the account number (the summary `id`) or Alpaca's account UUID
+    /// matches, and an empty ID means the default account. The account is
+    /// fetched only when it has not been seen yet.
+    pub fn is_own_account(&self, account_id: &str) -> Result<bool, String> {
+        if account_id.is_empty() {
+            return Ok(true);
         }
+        let known = self
+            .identity
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let (number, id) = match known {
+            Some(identity) => identity,
+            None => {
+                let account = self.fetch_account()?;
+                (account.account_number, account.id)
+            }
+        };
+        Ok(account_id == number || account_id == id)
+    }
 
-        Ok(())
+    /// Why new orders would be refused, confirmed against a fresh account
+    /// fetch. Costs nothing until an account fetch has seen a block; if the
+    /// confirming fetch fails, the API is left to decide.
+    pub fn trading_block(&self) -> Option<String> {
+        let blocked = self
+            .trading_block
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_some();
+        if !blocked {
+            return None;
+        }
+        self.fetch_account().ok()?.trading_block()
     }
 
-    /// Get account information
-    pub fn get_account(&self) -> Result<AccountSummary, String> {
-        #[derive(Deserialize)]
-        struct AlpacaAccount {
-            id: String,
-            account_number: String,
-            status: String,
-            currency: String,
-            cash: String,
-            portfolio_value: String,
-            buying_power: String,
-            equity: String,
-            last_equity: String,
-            daytrade_count: Option<i32>,
-            pattern_day_trader: Option<bool>,
+    /// Account currency as of the last account fetch, `None` before one
+    pub fn account_currency(&self) -> Option<String> {
+        self.currency
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Flag the positions in `frozen` whose asset is no longer active,
+    /// marking them at zero when configured. A failed asset lookup leaves
+    /// the position unflagged until the next fetch.
+    fn flag_delisted(
+        &self,
+        positions: &mut [Position],
+        frozen: &HashSet<String>,
+    ) -> BTreeMap<String, DelistedFlag> {
+        positions
+            .iter_mut()
+            .filter(|p| frozen.contains(&p.symbol_id))
+            .filter_map(|position| {
+                let asset = self
+                    .get_asset(&position.symbol_id)
+                    .unwrap_or_else(|e| {
+                        logging::warn("positions", "Asset lookup for delisting check failed")
+                            .field("symbol", position.symbol_id.as_str())
+                            .field("error", e.as_str())
+                            .emit();
+                        None
+                    })
+                    .filter(delisting::is_delisted)?;
+                let flag = delisting::flag(position, &asset, self.delisted_marks);
+                Some((position.symbol_id.clone(), flag))
+            })
+            .collect()
+    }
+
+    /// Re-mark `positions` by the configured [`MarkSource`], returning how
+    /// each was marked. Only stock positions on USD accounts are re-marked:
+    /// the snapshot endpoint is for stocks and quotes in USD. When the
+    /// snapshot cannot be fetched, Alpaca's marks stand.
+    fn mark_positions(&self, positions: &mut [Position]) -> BTreeMap<String, Mark> {
+        if !self.mark_source.uses_snapshots() {
+            return BTreeMap::new();
         }
+        let usd = self
+            .account_currency()
+            .is_none_or(|c| c.eq_ignore_ascii_case("USD"));
+        let symbols: Vec<String> = positions
+            .iter()
+            .map(|p| p.symbol_id.clone())
+            .filter(|s| usd && AssetKind::of(s) == AssetKind::Equity)
+            .collect();
+        let snapshots = self.get_snapshots(&symbols).unwrap_or_else(|e| {
+            logging::warn("positions", "Snapshot for position marks failed")
+                .field("error", e.as_str())
+                .emit();
+            HashMap::new()
+        });
+        positions
+            .iter_mut()
+            .map(|position| {
+                let priced = snapshots
+                    .get(&position.symbol_id)
+                    .and_then(|s| self.mark_source.price(s));
+                let mark = match priced {
+                    Some((price, at)) => {
+                        marks::remark(position, price);
+                        Mark {
+                            source: self.mark_source,
+                            price: decimal::to_wire(price),
+                            at,
+                        }
+                    }
+                    None => Mark::alpaca(position),
+                };
+                (position.symbol_id.clone(), mark)
+            })
+            .collect()
+    }
 
-        let account: AlpacaAccount = self.api_get("/v2/account")?;
+    /// Get account information, with positions from the cache window
+    pub fn get_account(&self) -> Result<AccountSummary, String> {
+        self.get_account_with(AccountPositions::Cached)
+    }
 
-        let parse_amount = |s: &str| -> f64 { s.parse::<f64>().unwrap_or(0.0) };
+    /// Get account information, choosing how positions are filled in
+    pub fn get_account_with(&self, mode: AccountPositions) -> Result<AccountSummary, String> {
+        let account = self.fetch_account()?;
 
-        let positions = self.get_positions().unwrap_or_default();
+        // Margin figures are informational, so malformed values are dropped
+        // rather than failing the whole account
+        let margin_field = |raw: &Option<String>| raw.as_deref().and_then(decimal::parse);
+        let equity = decimal::parse_or_zero(&account.equity);
+        let maintenance = margin_field(&account.maintenance_margin);
+        let mut margin = serde_json::Map::new();
+        let margin_fields = [
+            ("multiplier", &account.multiplier),
+            ("initial_margin", &account.initial_margin),
+            ("maintenance_margin", &account.maintenance_margin),
+            ("last_maintenance_margin", &account.last_maintenance_margin),
+            ("sma", &account.sma),
+            ("regt_buying_power", &account.regt_buying_power),
+            ("daytrading_buying_power", &account.daytrading_buying_power),
+            (
+                "non_marginable_buying_power",
+                &account.non_marginable_buying_power,
+            ),
+            ("long_market_value", &account.long_market_value),
+            ("short_market_value", &account.short_market_value),
+        ];
+        for (key, raw) in margin_fields {
+            if let Some(value) = margin_field(raw) {
+                margin.insert(key.to_string(), decimal::to_wire(value).into());
+            }
+        }
+        if let Some(maintenance) = maintenance {
+            margin.insert(
+                "excess_equity".to_string(),
+                decimal::to_wire(equity - maintenance).into(),
+            );
+            if equity > Decimal::ZERO {
+                let utilization = (maintenance / equity * Decimal::ONE_HUNDRED).round_dp(2);
+                margin.insert(
+                    "margin_utilization_pct".to_string(),
+                    decimal::to_wire(utilization).into(),
+                );
+            }
+        }
+
+        let parse_amount = |s: &str| -> f64 { decimal::to_f64(decimal::parse_or_zero(s)) };
+        let trading_block = account.trading_block();
+        let cash_account = account.is_cash_account();
+
+        let set = match mode {
+            AccountPositions::Omit => None,
+            AccountPositions::Cached => Some(self.cached_positions().unwrap_or_default()),
+            AccountPositions::Fresh => Some(self.fetch_positions().unwrap_or_default()),
+        };
+        let positions = set
+            .as_ref()
+            .map(|set| set.positions.clone())
+            .unwrap_or_default();
 
         Ok(AccountSummary {
             id: account.account_number.clone(),
@@ -169,243 +745,1696 @@ impl AlpacaClient {
                         serde_json::Value::Bool(pdt),
                     );
                 }
+                let blocks = [
+                    ("trading_blocked", account.trading_blocked),
+                    ("transfers_blocked", account.transfers_blocked),
+                    ("account_blocked", account.account_blocked),
+                    ("trade_suspended_by_user", account.trade_suspended_by_user),
+                ];
+                for (flag, set) in blocks {
+                    map.insert(flag.to_string(), set.into());
+                }
+                map.insert("can_trade".to_string(), trading_block.is_none().into());
+                if let Some(reason) = trading_block {
+                    map.insert("trading_block".to_string(), reason.into());
+                }
                 if let Some(count) = account.daytrade_count {
                     map.insert(
                         "daytrade_count".to_string(),
                         serde_json::Value::Number(count.into()),
                     );
                 }
+                if !margin.is_empty() {
+                    map.insert("margin".to_string(), serde_json::Value::Object(margin));
+                }
+                map.insert("cash_account".to_string(), cash_account.into());
+                for (key, raw) in [
+                    ("pending_transfer_in", &account.pending_transfer_in),
+                    ("pending_transfer_out", &account.pending_transfer_out),
+                ] {
+                    if let Some(value) = margin_field(raw) {
+                        map.insert(key.to_string(), decimal::to_wire(value).into());
+                    }
+                }
+                let sidecars = set.as_ref().map(PositionSet::sidecars).unwrap_or_default();
+                for (key, sidecar) in sidecars {
+                    if sidecar.as_object().is_some_and(|m| !m.is_empty()) {
+                        map.insert(key.to_string(), sidecar);
+                    }
+                }
+                if mode == AccountPositions::Omit {
+                    map.insert(
+                        "positions_omitted".to_string(),
+                        serde_json::Value::Bool(true),
+                    );
+                }
                 map
             }),
         })
     }
 
     /// List accounts (Alpaca has single account per API key)
-    pub fn list_accounts(&self) -> Result<Vec<AccountSummary>, String> {
-        let account = self.get_account()?;
+    pub fn list_accounts(&self, mode: AccountPositions) -> Result<Vec<AccountSummary>, String> {
+        let account = self.get_account_with(mode)?;
         Ok(vec![account])
     }
 
-    /// Get all positions
+    /// Get all positions. Always fetched; the result also refreshes the
+    /// cache used by account summaries.
     pub fn get_positions(&self) -> Result<Vec<Position>, String> {
-        #[derive(Deserialize)]
-        struct AlpacaPosition {
-            symbol: String,
-            qty: String,
-            avg_entry_price: String,
-            current_price: String,
-            market_value: String,
-            unrealized_pl: String,
-            unrealized_plpc: String,
-            side: String,
-        }
+        self.fetch_positions().map(|set| set.positions)
+    }
 
+    /// [`Self::get_positions`] with each position's [`PositionExtras`]
+    pub fn fetch_positions(&self) -> Result<PositionSet, String> {
         let positions: Vec<AlpacaPosition> = self.api_get("/v2/positions")?;
+        let set = self.position_set(positions)?;
+        *self.positions.lock().unwrap_or_else(|e| e.into_inner()) =
+            Some((Instant::now(), set.clone()));
+        Ok(set)
+    }
 
-        Ok(positions
+    /// Map fetched positions, re-marking and flagging them as configured
+    fn position_set(&self, fetched: Vec<AlpacaPosition>) -> Result<PositionSet, String> {
+        let currency = self.account_currency();
+        let mut frozen = HashSet::new();
+        let mut extras = BTreeMap::new();
+        let mut positions = fetched
             .into_iter()
             .map(|p| {
-                let qty: f64 = p.qty.parse().unwrap_or(0.0);
-                let multiplier = if p.side == "short" { -1.0 } else { 1.0 };
-
-                Position {
-                    symbol_id: p.symbol,
-                    quantity: qty * multiplier,
-                    average_price: p.avg_entry_price.parse().unwrap_or(0.0),
-                    current_price: p.current_price.parse().unwrap_or(0.0),
-                    unrealized_pnl: p.unrealized_pl.parse().unwrap_or(0.0),
-                    unrealized_pnl_percent: p.unrealized_plpc.parse::<f64>().unwrap_or(0.0) * 100.0,
+                if price_frozen(&p) {
+                    frozen.insert(p.symbol.clone());
                 }
+                let asset = position_asset(&p);
+                let pnl = position_pnl(&p, self.parser);
+                let qty_available =
+                    p.qty_available
+                        .as_deref()
+                        .and_then(decimal::parse)
+                        .map(|qty| {
+                            if p.side == "short" {
+                                -qty.abs()
+                            } else {
+                                qty.abs()
+                            }
+                        });
+                let (position, local) = map_position(p, self.parser, currency.as_deref())?;
+                extras.insert(
+                    position.symbol_id.clone(),
+                    PositionExtras {
+                        currency: local.map(serde_json::Value::Object),
+                        asset,
+                        pnl,
+                        mark: None,
+                        delisted: None,
+                        qty_available,
+                    },
+                );
+                Ok(position)
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        for (symbol, mark) in self.mark_positions(&mut positions) {
+            if let Some(extras) = extras.get_mut(&symbol) {
+                extras.mark = Some(mark);
+            }
+        }
+        for (symbol, flag) in self.flag_delisted(&mut positions, &frozen) {
+            if let Some(extras) = extras.get_mut(&symbol) {
+                extras.delisted = Some(flag);
+            }
+        }
+        Ok(PositionSet { positions, extras })
+    }
+
+    /// Unrealized PnL of all open positions since the previous close
+    pub fn unrealized_intraday_pl(&self) -> Result<Decimal, String> {
+        let positions: Vec<AlpacaPosition> = self.api_get("/v2/positions")?;
+        positions
+            .iter()
+            .map(|p| {
+                self.parser
+                    .optional(
+                        "unrealized_intraday_pl",
+                        p.unrealized_intraday_pl.as_deref(),
+                    )
+                    .map(Option::unwrap_or_default)
             })
-            .collect())
+            .sum()
+    }
+
+    /// Positions fetched within the cache window, or fresh ones
+    fn cached_positions(&self) -> Result<PositionSet, String> {
+        if let Some((fetched, set)) = &*self.positions.lock().unwrap_or_else(|e| e.into_inner()) {
+            if fetched.elapsed() < self.positions_ttl {
+                return Ok(set.clone());
+            }
+        }
+        self.fetch_positions()
+    }
+
+    /// Get the open position in one symbol, `None` when flat
+    pub fn get_position(&self, symbol: &str) -> Result<Option<Position>, String> {
+        self.fetch_position(symbol)
+            .map(|found| found.map(|(position, _)| position))
+    }
+
+    /// [`Self::get_position`] with the position's [`PositionExtras`]
+    pub fn fetch_position(
+        &self,
+        symbol: &str,
+    ) -> Result<Option<(Position, PositionExtras)>, String> {
+        let path = format!("/v2/positions/{}", percent_encode(symbol));
+        let response = self.send_raw(HttpMethod::Get, &path, None);
+        if response.status == 404 {
+            return Ok(None);
+        }
+        if !response.is_success() {
+            return Err(format!(
+                "API error {}: {}",
+                response.status,
+                response.error.unwrap_or(response.body)
+            ));
+        }
+        let position: AlpacaPosition = response.json()?;
+        let mut set = self.position_set(vec![position])?;
+        Ok(set.positions.pop().map(|position| {
+            let extras = set.extras.remove(&position.symbol_id).unwrap_or_default();
+            (position, extras)
+        }))
+    }
+
+    /// Asset reference data for a symbol; `None` when Alpaca does not
+    /// know it. Served from the response cache for an hour.
+    pub fn get_asset(&self, symbol: &str) -> Result<Option<AlpacaAsset>, String> {
+        let path = format!("/v2/assets/{}", percent_encode(symbol));
+        let response = self.send_raw(HttpMethod::Get, &path, None);
+        if response.status == 404 {
+            return Ok(None);
+        }
+        Self::ensure_success(response)?.json().map(Some)
     }
 
     /// Submit an order
     pub fn submit_order(&self, order: &OrderRequest) -> Result<Order, String> {
-        #[derive(serde::Serialize)]
-        struct CreateOrderRequest {
-            symbol: String,
-            qty: String,
-            side: String,
-            #[serde(rename = "type")]
-            order_type: String,
-            time_in_force: String,
-            #[serde(skip_serializing_if = "Option::is_none")]
-            limit_price: Option<String>,
-            #[serde(skip_serializing_if = "Option::is_none")]
-            stop_price: Option<String>,
-            #[serde(skip_serializing_if = "Option::is_none")]
-            client_order_id: Option<String>,
-        }
-
-        let side = match order.side {
-            OrderSide::Buy => "buy",
-            OrderSide::Sell => "sell",
-        };
+        if let Some(reason) = self.trading_block() {
+            return Err(reason);
+        }
+        let req = create_order_request(order)?;
+        let resp: AlpacaOrder = self.api_post("/v2/orders", &req)?;
 
-        let order_type = match order.order_type {
-            OrderType::Market => "market",
-            OrderType::Limit => "limit",
-            OrderType::Stop => "stop",
-            OrderType::StopLimit => "stop_limit",
-        };
+        let mut mapped = map_order(resp, self.parser, self.account_currency().as_deref())?;
+        // Kept even when Alpaca does not echo it
+        if let Some(intent) = req.position_intent {
+            mapped
+                .extensions
+                .get_or_insert_with(HashMap::new)
+                .entry("position_intent".to_string())
+                .or_insert_with(|| intent.into());
+        }
+        mapped.request = order.clone();
+        mapped.persona_id = order.persona_id.clone();
+        Ok(mapped)
+    }
 
-        let client_order_id = format!("KL{:016x}", rand::random::<u64>());
-
-        let req = CreateOrderRequest {
-            symbol: order.symbol_id.clone(),
-            qty: order.quantity.to_string(),
-            side: side.to_string(),
-            order_type: order_type.to_string(),
-            time_in_force: "day".to_string(),
-            limit_price: order.limit_price.map(|p| p.to_string()),
-            stop_price: order.stop_price.map(|p| p.to_string()),
-            client_order_id: Some(client_order_id.clone()),
-        };
+    /// Latest trade, quote, and bars for each symbol. Symbols without data
+    /// are absent from the result.
+    pub fn get_snapshots(&self, symbols: &[String]) -> Result<HashMap<String, Snapshot>, String> {
+        if symbols.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let query = QueryParams::new().push_list("symbols", symbols);
+        self.data_get_with("/v2/stocks/snapshots", &query)
+    }
 
+    /// One-minute bars for a symbol since `start`, oldest first
+    pub fn get_minute_bars(&self, symbol: &str, start: DateTime<Utc>) -> Result<Vec<Bar>, String> {
+        self.get_bars(symbol, "1Min", start, None)
+    }
+
+    /// Bars of `timeframe` (`1Min`, `1Hour`, `1Day`, ...) for a symbol
+    /// from `start` to `end` (now when unset), oldest first
+    pub fn get_bars(
+        &self,
+        symbol: &str,
+        timeframe: &str,
+        start: DateTime<Utc>,
+        end: Option<DateTime<Utc>>,
+    ) -> Result<Vec<Bar>, String> {
         #[derive(Deserialize)]
-        struct OrderResponse {
-            id: String,
-            client_order_id: String,
-            status: String,
-            symbol: String,
-            qty: String,
-            filled_qty: String,
-            filled_avg_price: Option<String>,
-            created_at: String,
-            updated_at: String,
+        struct BarsResponse {
+            #[serde(default)]
+            bars: Option<Vec<Bar>>,
+            #[serde(default)]
+            next_page_token: Option<String>,
         }
 
-        let resp: OrderResponse = self.api_post("/v2/orders", &req)?;
+        let query = QueryParams::new()
+            .push("timeframe", timeframe)
+            .push(
+                "start",
+                start.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            )
+            .push_opt(
+                "end",
+                end.map(|e| e.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+            )
+            .push("limit", 1000);
+        let path = format!("/v2/stocks/{}/bars", percent_encode(symbol));
+        let mut bars = Vec::new();
+        fetch_pages(
+            self.page_prefetch,
+            |token| {
+                self.data_get_with::<BarsResponse>(
+                    &path,
+                    &query.clone().push_opt("page_token", token),
+                )
+            },
+            |page| page.next_page_token.clone().filter(|t| !t.is_empty()),
+            |page| {
+                bars.extend(page.bars.unwrap_or_default());
+                Ok(())
+            },
+        )?;
+        Ok(bars)
+    }
 
-        let status = match resp.status.as_str() {
-            "new" | "accepted" | "pending_new" => OrderStatus::Submitted,
-            "partially_filled" => OrderStatus::PartiallyFilled,
-            "filled" => OrderStatus::Filled,
-            "canceled" | "expired" | "rejected" => OrderStatus::Canceled,
-            _ => OrderStatus::Submitted,
-        };
+    /// Ticker changes processed from `start` to `end`, from the corporate
+    /// actions API
+    pub fn get_name_changes(
+        &self,
+        start: NaiveDate,
+        end: NaiveDate,
+    ) -> Result<Vec<NameChange>, String> {
+        #[derive(Deserialize, Default)]
+        struct CorporateActions {
+            #[serde(default)]
+            name_changes: Vec<NameChange>,
+        }
+        #[derive(Deserialize)]
+        struct CorporateActionsResponse {
+            #[serde(default)]
+            corporate_actions: CorporateActions,
+            #[serde(default)]
+            next_page_token: Option<String>,
+        }
 
-        let created_at = DateTime::parse_from_rfc3339(&resp.created_at)
-            .map(|dt| dt.with_timezone(&Utc))
-            .unwrap_or_else(|_| Utc::now());
-
-        let updated_at = DateTime::parse_from_rfc3339(&resp.updated_at)
-            .map(|dt| dt.with_timezone(&Utc))
-            .unwrap_or_else(|_| Utc::now());
-
-        Ok(Order {
-            id: resp.id.clone(),
-            request: order.clone(),
-            status,
-            created_at,
-            updated_at,
-            filled_quantity: resp.filled_qty.parse().unwrap_or(0.0),
-            average_filled_price: resp.filled_avg_price.and_then(|p| p.parse().ok()),
-            extensions: Some({
-                let mut map = HashMap::new();
-                map.insert(
-                    "client_order_id".to_string(),
-                    serde_json::Value::String(resp.client_order_id),
-                );
-                map.insert(
-                    "alpaca_status".to_string(),
-                    serde_json::Value::String(resp.status),
-                );
-                map
-            }),
-            persona_id: order.persona_id.clone(),
-        })
+        let query = QueryParams::new()
+            .push("types", "name_change")
+            .push("start", start.to_string())
+            .push("end", end.to_string())
+            .push("limit", 1000);
+        let mut changes = Vec::new();
+        fetch_pages(
+            self.page_prefetch,
+            |token| {
+                self.data_get_with::<CorporateActionsResponse>(
+                    "/v1/corporate-actions",
+                    &query.clone().push_opt("page_token", token),
+                )
+            },
+            |page| page.next_page_token.clone().filter(|t| !t.is_empty()),
+            |page| {
+                changes.extend(page.corporate_actions.name_changes);
+                Ok(())
+            },
+        )?;
+        Ok(changes)
+    }
+
+    /// Equity and PnL over time from `GET /v2/account/portfolio/history`
+    pub fn get_portfolio_history(&self, query: &QueryParams) -> Result<PortfolioHistory, String> {
+        self.api_get_with("/v2/account/portfolio/history", query)
+    }
+
+    /// Current market clock
+    pub fn get_clock(&self) -> Result<MarketClock, String> {
+        self.api_get("/v2/clock")
+    }
+
+    /// Trading days in `[start, end]` from `GET /v2/calendar`
+    pub fn get_calendar(
+        &self,
+        start: NaiveDate,
+        end: NaiveDate,
+    ) -> Result<Vec<CalendarDay>, String> {
+        let query = QueryParams::new()
+            .push("start", start.to_string())
+            .push("end", end.to_string());
+        self.api_get_with("/v2/calendar", &query)
+    }
+
+    /// Orders from `GET /v2/orders` with `status` `open`, `closed`, or
+    /// `all`, newest first. With `nested`, the legs of advanced orders come
+    /// inside their parent's `extensions.legs` rather than as orders of
+    /// their own.
+    pub fn list_orders(
+        &self,
+        status: &str,
+        symbol: Option<&str>,
+        nested: bool,
+    ) -> Result<Vec<Order>, String> {
+        let query = QueryParams::new()
+            .push("status", status)
+            .push("limit", 500)
+            .push("nested", nested)
+            .push_opt("symbols", symbol);
+        let resp: Vec<AlpacaOrder> = self.api_get_with("/v2/orders", &query)?;
+        let currency = self.account_currency();
+        resp.into_iter()
+            .map(|o| map_order(o, self.parser, currency.as_deref()))
+            .collect()
     }
 
     /// Cancel an order
     pub fn cancel_order(&self, order_id: &str) -> Result<(), String> {
-        self.api_delete(&format!("/v2/orders/{}", order_id))
+        self.api_delete(&format!("/v2/orders/{}", percent_encode(order_id)))
     }
 
-    /// Get order by ID
-    pub fn get_order(&self, order_id: &str) -> Result<Order, String> {
+    /// Cancel every open order. Returns the per-order result: order ID and
+    /// the HTTP status Alpaca reported for that cancel.
+    pub fn cancel_all_orders(&self) -> Result<Vec<(String, u16)>, String> {
         #[derive(Deserialize)]
-        struct OrderResponse {
+        struct CancelResult {
             id: String,
-            client_order_id: String,
-            status: String,
+            status: u16,
+        }
+
+        let response = self.send(HttpMethod::Delete, "/v2/orders", None)?;
+        if response.body.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        let results: Vec<CancelResult> = response.json()?;
+        Ok(results.into_iter().map(|r| (r.id, r.status)).collect())
+    }
+
+    /// Fetch account activities of the given types, following page tokens.
+    /// `after`/`until` are passed through as RFC 3339 timestamps or dates.
+    fn list_activities<T: serde::de::DeserializeOwned>(
+        &self,
+        activity_types: &[&str],
+        after: Option<String>,
+        until: Option<String>,
+    ) -> Result<Vec<T>, String> {
+        const PAGE_SIZE: usize = 100;
+        let mut activities = Vec::new();
+
+        fetch_pages(
+            self.page_prefetch,
+            |page_token| {
+                let query = QueryParams::new()
+                    .push_list("activity_types", activity_types)
+                    .push("direction", "asc")
+                    .push("page_size", PAGE_SIZE)
+                    .push_opt("after", after.as_deref())
+                    .push_opt("until", until.as_deref())
+                    .push_opt("page_token", page_token);
+                self.api_get_with::<Vec<serde_json::Value>>("/v2/account/activities", &query)
+            },
+            // A short page is the last one; otherwise the last ID is the token
+            |page| {
+                if page.len() < PAGE_SIZE {
+                    return None;
+                }
+                page.last()
+                    .and_then(|a| a.get("id"))
+                    .and_then(|id| id.as_str())
+                    .map(str::to_string)
+            },
+            |page| {
+                for activity in page {
+                    activities.push(
+                        serde_json::from_value(activity).map_err(|e| {
+                            format!("Decode error: malformed account activity: {}", e)
+                        })?,
+                    );
+                }
+                Ok(())
+            },
+        )?;
+
+        Ok(activities)
+    }
+
+    /// List FILL activities in `[after, until]`
+    pub fn list_fill_activities(
+        &self,
+        after: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Vec<Execution>, String> {
+        #[derive(Deserialize)]
+        struct FillActivity {
+            id: String,
+            order_id: String,
             symbol: String,
-            qty: String,
             side: String,
+            qty: String,
+            price: String,
+            transaction_time: String,
             #[serde(rename = "type")]
-            order_type: String,
-            filled_qty: String,
-            filled_avg_price: Option<String>,
-            limit_price: Option<String>,
-            stop_price: Option<String>,
-            created_at: String,
-            updated_at: String,
+            fill_type: String,
+            #[serde(default)]
+            cum_qty: Option<String>,
+            #[serde(default)]
+            leaves_qty: Option<String>,
+            #[serde(default)]
+            venue: Option<String>,
         }
 
-        let resp: OrderResponse = self.api_get(&format!("/v2/orders/{}", order_id))?;
+        let activities: Vec<FillActivity> = self.list_activities(
+            &["FILL"],
+            after.map(|t| t.to_rfc3339()),
+            until.map(|t| t.to_rfc3339()),
+        )?;
 
-        let side = match resp.side.as_str() {
-            "buy" => OrderSide::Buy,
-            _ => OrderSide::Sell,
-        };
+        activities
+            .into_iter()
+            .map(|activity| {
+                let qty = self.parser.required("qty", &activity.qty)?;
+                let price = self.parser.required("price", &activity.price)?;
+                Ok(Execution {
+                    timestamp: parse_timestamp(
+                        self.parser,
+                        "transaction_time",
+                        &activity.transaction_time,
+                    )?,
+                    id: activity.id,
+                    order_id: activity.order_id,
+                    symbol: activity.symbol,
+                    side: activity.side,
+                    qty: decimal::to_wire(qty),
+                    price: decimal::to_wire(price),
+                    fill_type: activity.fill_type,
+                    cum_qty: activity.cum_qty,
+                    leaves_qty: activity.leaves_qty,
+                    venue: activity.venue,
+                })
+            })
+            .collect()
+    }
+
+    /// List dividend, interest, fee, and transfer activities since `after`
+    pub fn list_cash_activities(&self, after: Option<NaiveDate>) -> Result<Vec<CashFlow>, String> {
+        #[derive(Deserialize)]
+        struct CashActivity {
+            id: String,
+            activity_type: String,
+            date: String,
+            net_amount: String,
+            #[serde(default)]
+            symbol: Option<String>,
+            #[serde(default)]
+            qty: Option<String>,
+            #[serde(default)]
+            per_share_amount: Option<String>,
+            #[serde(default)]
+            description: Option<String>,
+        }
+
+        let activities: Vec<CashActivity> =
+            self.list_activities(CASH_ACTIVITY_TYPES, after.map(|d| d.to_string()), None)?;
+
+        activities
+            .into_iter()
+            .map(|a| {
+                let date = NaiveDate::parse_from_str(&a.date, "%Y-%m-%d")
+                    .map_err(|_| decimal::decode_error("date", &a.date))?;
+                Ok(CashFlow {
+                    category: CashFlowCategory::from_activity_type(&a.activity_type),
+                    net_amount: self.parser.required("net_amount", &a.net_amount)?,
+                    qty: self.parser.optional("qty", a.qty.as_deref())?,
+                    per_share_amount: self
+                        .parser
+                        .optional("per_share_amount", a.per_share_amount.as_deref())?,
+                    id: a.id,
+                    activity_type: a.activity_type,
+                    date,
+                    symbol: a.symbol.filter(|s| !s.is_empty()),
+                    description: a.description,
+                })
+            })
+            .collect()
+    }
+
+    /// Replace a working order's limit price. Alpaca cancels the original
+    /// and returns the replacement, which has a new order ID.
+    pub fn replace_order(&self, order_id: &str, limit_price: Decimal) -> Result<Order, String> {
+        self.patch_order(
+            order_id,
+            &ReplaceOrderRequest {
+                qty: None,
+                limit_price: Some(decimal::to_wire(limit_price)),
+            },
+        )
+    }
+
+    /// Shrink a working order to `qty` shares (a partial cancel). Like any
+    /// replace, the result has a new order ID.
+    pub fn reduce_order(&self, order_id: &str, qty: Decimal) -> Result<Order, String> {
+        self.resize_order(order_id, qty)
+    }
+
+    /// Change a working order's quantity to `qty`, up or down
+    pub fn resize_order(&self, order_id: &str, qty: Decimal) -> Result<Order, String> {
+        self.patch_order(
+            order_id,
+            &ReplaceOrderRequest {
+                qty: Some(decimal::to_wire(qty)),
+                limit_price: None,
+            },
+        )
+    }
 
-        let order_type = match resp.order_type.as_str() {
-            "market" => OrderType::Market,
-            "limit" => OrderType::Limit,
-            "stop" => OrderType::Stop,
-            "stop_limit" => OrderType::StopLimit,
-            _ => OrderType::Market,
+    fn patch_order(&self, order_id: &str, body: &ReplaceOrderRequest) -> Result<Order, String> {
+        let resp: AlpacaOrder =
+            self.api_patch(&format!("/v2/orders/{}", percent_encode(order_id)), body)?;
+        map_order(resp, self.parser, self.account_currency().as_deref())
+    }
+
+    /// Get order by ID
+    pub fn get_order(&self, order_id: &str) -> Result<Order, String> {
+        let resp: AlpacaOrder =
+            self.api_get(&format!("/v2/orders/{}", percent_encode(order_id)))?;
+        map_order(resp, self.parser, self.account_currency().as_deref())
+    }
+}
+
+/// What Alpaca reports about a position beyond `Position`'s fields
+#[derive(Clone, Debug, Default)]
+pub struct PositionExtras {
+    /// `currency::describe` fields, on local currency accounts
+    pub currency: Option<serde_json::Value>,
+    /// `asset_class` and `exchange`
+    pub asset: serde_json::Value,
+    /// Cost basis and today's move, see [`position_pnl`]
+    pub pnl: serde_json::Value,
+    /// How the position was marked; `None` with Alpaca's marks
+    pub mark: Option<Mark>,
+    pub delisted: Option<DelistedFlag>,
+    /// Alpaca's `qty_available`, signed like `quantity`: what open sells
+    /// have not already claimed
+    pub qty_available: Option<Decimal>,
+}
+
+/// Positions from one fetch, with their extras by symbol
+#[derive(Clone, Debug, Default)]
+pub struct PositionSet {
+    pub positions: Vec<Position>,
+    pub extras: BTreeMap<String, PositionExtras>,
+}
+
+impl PositionSet {
+    /// One kind of extra, by symbol, for the positions that have it
+    pub fn by_symbol<T: serde::Serialize>(
+        &self,
+        pick: impl Fn(&PositionExtras) -> Option<&T>,
+    ) -> serde_json::Value {
+        let picked: BTreeMap<&str, &T> = self
+            .extras
+            .iter()
+            .filter_map(|(symbol, extras)| Some((symbol.as_str(), pick(extras)?)))
+            .collect();
+        serde_json::to_value(picked).unwrap_or_default()
+    }
+
+    /// The extras as the keyed maps that ride alongside `positions`
+    pub fn sidecars(&self) -> Vec<(&'static str, serde_json::Value)> {
+        [
+            ("position_currency", self.by_symbol(|e| e.currency.as_ref())),
+            ("position_assets", self.by_symbol(|e| Some(&e.asset))),
+            ("position_pnl", self.by_symbol(|e| Some(&e.pnl))),
+            ("position_marks", self.by_symbol(|e| e.mark.as_ref())),
+            (
+                "delisted_positions",
+                self.by_symbol(|e| e.delisted.as_ref()),
+            ),
+        ]
+        .into()
+    }
+}
+
+/// Position object as returned by the positions endpoints
+#[derive(Deserialize)]
+struct AlpacaPosition {
+    symbol: String,
+    qty: String,
+    avg_entry_price: String,
+    current_price: String,
+    market_value: String,
+    unrealized_pl: String,
+    unrealized_plpc: String,
+    /// Since the previous close, or since entry for positions opened today
+    #[serde(default)]
+    unrealized_intraday_pl: Option<String>,
+    #[serde(default)]
+    unrealized_intraday_plpc: Option<String>,
+    #[serde(default)]
+    cost_basis: Option<String>,
+    /// Previous close
+    #[serde(default)]
+    lastday_price: Option<String>,
+    /// Price change since the previous close, as a fraction
+    #[serde(default)]
+    change_today: Option<String>,
+    side: String,
+    /// Local currency units per USD, on local currency accounts
+    #[serde(default)]
+    swap_rate: Option<String>,
+    /// USD figures Alpaca reports alongside the local ones
+    #[serde(default)]
+    usd: Option<serde_json::Value>,
+    #[serde(default)]
+    asset_class: Option<String>,
+    #[serde(default)]
+    exchange: Option<String>,
+    /// `qty` less the shares held by open sell orders
+    #[serde(default)]
+    qty_available: Option<String>,
+}
+
+/// Whether an equity position's price has not moved since the previous
+/// close, the one sign of a delisting the positions endpoint gives
+fn price_frozen(p: &AlpacaPosition) -> bool {
+    AssetKind::from_class(p.asset_class.as_deref(), &p.symbol) == AssetKind::Equity
+        && delisting::price_frozen(
+            decimal::parse(&p.current_price),
+            p.lastday_price.as_deref().and_then(decimal::parse),
+            p.change_today.as_deref().and_then(decimal::parse),
+        )
+}
+
+/// `asset_class` and `exchange` of a position, which `Position` has no
+/// fields for
+fn position_asset(p: &AlpacaPosition) -> serde_json::Value {
+    let mut asset = serde_json::Map::new();
+    asset.insert(
+        "asset_class".to_string(),
+        AssetKind::from_class(p.asset_class.as_deref(), &p.symbol)
+            .name()
+            .into(),
+    );
+    if let Some(exchange) = p.exchange.as_deref().filter(|e| !e.is_empty()) {
+        asset.insert("exchange".to_string(), exchange.into());
+    }
+    serde_json::Value::Object(asset)
+}
+
+/// Cost basis and today's move next to the total unrealized PnL, which
+/// `Position` has no fields for. Percentages are scaled like
+/// `unrealized_pnl_percent`; fields Alpaca left out, or sent malformed,
+/// are omitted.
+fn position_pnl(p: &AlpacaPosition, parser: FieldParser) -> serde_json::Value {
+    let fields = [
+        ("cost_basis", "cost_basis", p.cost_basis.as_deref(), false),
+        (
+            "lastday_price",
+            "lastday_price",
+            p.lastday_price.as_deref(),
+            false,
+        ),
+        (
+            "change_today_percent",
+            "change_today",
+            p.change_today.as_deref(),
+            true,
+        ),
+        (
+            "intraday_pnl",
+            "unrealized_intraday_pl",
+            p.unrealized_intraday_pl.as_deref(),
+            false,
+        ),
+        (
+            "intraday_pnl_percent",
+            "unrealized_intraday_plpc",
+            p.unrealized_intraday_plpc.as_deref(),
+            true,
+        ),
+    ];
+    let mut pnl = serde_json::Map::new();
+    for (key, field, raw, percent) in fields {
+        let value = match parser.optional(field, raw) {
+            Ok(Some(value)) => value,
+            Ok(None) => continue,
+            // Informational only; not worth failing the positions over
+            Err(e) => {
+                logging::warn("positions", "Malformed position field skipped")
+                    .field("symbol", p.symbol.as_str())
+                    .field("error", e.as_str())
+                    .emit();
+                continue;
+            }
         };
+        let value = if percent {
+            value * Decimal::ONE_HUNDRED
+        } else {
+            value
+        };
+        pnl.insert(key.to_string(), decimal::to_wire(value).into());
+    }
+    serde_json::Value::Object(pnl)
+}
+
+/// The position, plus its `currency::describe` fields when it is held in
+/// a local currency
+fn map_position(
+    p: AlpacaPosition,
+    parser: FieldParser,
+    currency: Option<&str>,
+) -> Result<(Position, Option<serde_json::Map<String, serde_json::Value>>), String> {
+    // Alpaca reports short quantities as negative already; normalize via side
+    let qty = parser.required("qty", &p.qty)?.abs();
+    let quantity = if p.side == "short" { -qty } else { qty };
+    let plpc = decimal::parse_or_zero(&p.unrealized_plpc);
+    let swap_rate = parser.optional("swap_rate", p.swap_rate.as_deref())?;
+    let local = currency::describe(
+        currency,
+        &[
+            ("avg_entry_price", decimal::parse(&p.avg_entry_price)),
+            ("current_price", decimal::parse(&p.current_price)),
+            ("market_value", decimal::parse(&p.market_value)),
+            ("unrealized_pl", decimal::parse(&p.unrealized_pl)),
+        ],
+        swap_rate,
+        p.usd.as_ref(),
+    );
+
+    let position = Position {
+        symbol_id: p.symbol,
+        quantity: decimal::to_f64(quantity),
+        average_price: decimal::to_f64(decimal::parse_or_zero(&p.avg_entry_price)),
+        current_price: decimal::to_f64(decimal::parse_or_zero(&p.current_price)),
+        unrealized_pnl: decimal::to_f64(decimal::parse_or_zero(&p.unrealized_pl)),
+        unrealized_pnl_percent: decimal::to_f64(plpc * Decimal::ONE_HUNDRED),
+    };
+    Ok((position, local))
+}
+
+/// Account object as returned by `/v2/account`
+#[derive(Deserialize)]
+pub struct AlpacaAccount {
+    pub id: String,
+    pub account_number: String,
+    pub status: String,
+    pub currency: String,
+    pub cash: String,
+    pub portfolio_value: String,
+    pub buying_power: String,
+    pub equity: String,
+    pub last_equity: String,
+    pub daytrade_count: Option<i32>,
+    pub pattern_day_trader: Option<bool>,
+    #[serde(default)]
+    pub multiplier: Option<String>,
+    #[serde(default)]
+    pub initial_margin: Option<String>,
+    #[serde(default)]
+    pub maintenance_margin: Option<String>,
+    #[serde(default)]
+    pub last_maintenance_margin: Option<String>,
+    #[serde(default)]
+    pub sma: Option<String>,
+    #[serde(default)]
+    pub regt_buying_power: Option<String>,
+    #[serde(default)]
+    pub daytrading_buying_power: Option<String>,
+    #[serde(default)]
+    pub non_marginable_buying_power: Option<String>,
+    #[serde(default)]
+    pub long_market_value: Option<String>,
+    #[serde(default)]
+    pub short_market_value: Option<String>,
+    #[serde(default)]
+    pub pending_transfer_in: Option<String>,
+    #[serde(default)]
+    pub pending_transfer_out: Option<String>,
+    #[serde(default)]
+    pub trading_blocked: bool,
+    #[serde(default)]
+    pub transfers_blocked: bool,
+    #[serde(default)]
+    pub account_blocked: bool,
+    /// Set by the account holder from the dashboard
+    #[serde(default)]
+    pub trade_suspended_by_user: bool,
+}
+
+/// Account statuses that can never place orders
+const CLOSED_STATUSES: [&str; 4] = ["ACCOUNT_CLOSED", "DISABLED", "REJECTED", "INACTIVE"];
+
+impl AlpacaAccount {
+    /// Multiplier 1: a cash account, which may only spend settled cash
+    pub fn is_cash_account(&self) -> bool {
+        self.multiplier.as_deref().and_then(decimal::parse) == Some(Decimal::ONE)
+    }
+
+    /// Why Alpaca would refuse new orders on this account, if it would.
+    /// Blocked transfers do not stop trading.
+    pub fn trading_block(&self) -> Option<String> {
+        let flags = [
+            ("account_blocked", self.account_blocked),
+            ("trading_blocked", self.trading_blocked),
+            ("trade_suspended_by_user", self.trade_suspended_by_user),
+        ];
+        if let Some((flag, _)) = flags.iter().find(|(_, set)| *set) {
+            return Some(format!(
+                "Account blocked: {} is set on the Alpaca account",
+                flag
+            ));
+        }
+        CLOSED_STATUSES
+            .iter()
+            .any(|s| self.status.eq_ignore_ascii_case(s))
+            .then(|| format!("Account blocked: account status is {}", self.status))
+    }
+}
+
+/// Order object as returned by the orders endpoints
+#[derive(Deserialize)]
+struct AlpacaOrder {
+    id: String,
+    client_order_id: String,
+    status: String,
+    symbol: String,
+    #[serde(default)]
+    qty: Option<String>,
+    side: String,
+    #[serde(rename = "type")]
+    order_type: String,
+    #[serde(default)]
+    asset_class: Option<String>,
+    /// Not on every order response; present when Alpaca includes it
+    #[serde(default)]
+    exchange: Option<String>,
+    filled_qty: String,
+    filled_avg_price: Option<String>,
+    limit_price: Option<String>,
+    stop_price: Option<String>,
+    created_at: String,
+    updated_at: String,
+    #[serde(default)]
+    canceled_at: Option<String>,
+    #[serde(default)]
+    expired_at: Option<String>,
+    #[serde(default)]
+    failed_at: Option<String>,
+    #[serde(default)]
+    replaced_at: Option<String>,
+    #[serde(default)]
+    replaced_by: Option<String>,
+    #[serde(default)]
+    replaces: Option<String>,
+    #[serde(default)]
+    order_class: Option<String>,
+    #[serde(default)]
+    legs: Option<Vec<AlpacaOrder>>,
+    #[serde(default)]
+    position_intent: Option<String>,
+    #[serde(default)]
+    time_in_force: Option<String>,
+    #[serde(default)]
+    extended_hours: bool,
+    /// Trailing stops: the offset, and the high (or low) water mark the
+    /// stop price trails
+    #[serde(default)]
+    trail_percent: Option<String>,
+    #[serde(default)]
+    trail_price: Option<String>,
+    #[serde(default)]
+    hwm: Option<String>,
+    /// Local currency units per USD, on local currency accounts
+    #[serde(default)]
+    swap_rate: Option<String>,
+    #[serde(default)]
+    usd: Option<serde_json::Value>,
+}
+
+/// `GET /v2/assets/{symbol}` response
+#[derive(Clone, Debug, Deserialize, serde::Serialize)]
+pub struct AlpacaAsset {
+    pub symbol: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub exchange: Option<String>,
+    #[serde(default)]
+    pub class: Option<String>,
+    /// `active` or `inactive`; delisted symbols go inactive
+    pub status: String,
+    pub tradable: bool,
+    #[serde(default)]
+    pub marginable: bool,
+    #[serde(default)]
+    pub shortable: bool,
+    #[serde(default)]
+    pub easy_to_borrow: bool,
+    #[serde(default)]
+    pub fractionable: bool,
+    /// Crypto only: smallest quantity an order may be for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_order_size: Option<String>,
+    /// Crypto only: quantity step
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_trade_increment: Option<String>,
+}
+
+/// `GET /v2/account/portfolio/history` response: parallel series, one
+/// point per timestamp (Unix seconds)
+#[derive(Clone, Debug, Deserialize, serde::Serialize)]
+pub struct PortfolioHistory {
+    pub timestamp: Vec<i64>,
+    pub equity: Vec<Option<Decimal>>,
+    pub profit_loss: Vec<Option<Decimal>>,
+    /// Cumulative, as a fraction
+    pub profit_loss_pct: Vec<Option<Decimal>>,
+    #[serde(default)]
+    pub base_value: Option<Decimal>,
+    pub timeframe: String,
+}
+
+/// `GET /v2/clock` response
+#[derive(Clone, Debug, Deserialize, serde::Serialize)]
+pub struct MarketClock {
+    pub timestamp: DateTime<Utc>,
+    pub is_open: bool,
+    pub next_open: DateTime<Utc>,
+    pub next_close: DateTime<Utc>,
+}
+
+/// A ticker change from the corporate actions API
+#[derive(Clone, Debug, Deserialize, serde::Serialize)]
+pub struct NameChange {
+    pub id: String,
+    pub old_symbol: String,
+    pub new_symbol: String,
+    pub process_date: NaiveDate,
+    #[serde(default)]
+    pub old_cusip: Option<String>,
+    #[serde(default)]
+    pub new_cusip: Option<String>,
+}
+
+/// One trading day from `GET /v2/calendar`
+#[derive(Clone, Debug, Deserialize, serde::Serialize)]
+pub struct CalendarDay {
+    pub date: NaiveDate,
+    /// Settlement date of trades made that day
+    #[serde(default)]
+    pub settlement_date: Option<NaiveDate>,
+    /// Regular session close, `HH:MM` US Eastern
+    #[serde(default)]
+    pub close: Option<String>,
+}
+
+/// `extensions.time_in_force`, defaulting to `day`
+pub fn requested_time_in_force(order: &OrderRequest) -> Result<&str, String> {
+    match order
+        .extensions
+        .as_ref()
+        .and_then(|e| e.get("time_in_force"))
+    {
+        None | Some(serde_json::Value::Null) => Ok("day"),
+        Some(v) => v
+            .as_str()
+            .filter(|tif| TIME_IN_FORCE_VALUES.contains(tif))
+            .ok_or_else(|| format!("Unsupported time_in_force: {}", v)),
+    }
+}
+
+/// `extensions.extended_hours`: allow pre-market and after-hours fills
+pub fn requested_extended_hours(order: &OrderRequest) -> bool {
+    order
+        .extensions
+        .as_ref()
+        .and_then(|e| e.get("extended_hours"))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
+
+/// Trailing offset of a stop order: `extensions.trail_percent` or
+/// `extensions.trail_price`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trail {
+    Percent(Decimal),
+    Price(Decimal),
+}
+
+/// The trailing offset a stop order asks for, if any. Either offset turns
+/// the order into an Alpaca `trailing_stop`, which moves its own stop
+/// price, so it cannot have a `stop_price` too.
+pub fn requested_trail(order: &OrderRequest) -> Result<Option<Trail>, String> {
+    let ext = order.extensions.as_ref();
+    let offset = |key: &str| -> Result<Option<Decimal>, String> {
+        match ext.and_then(|e| e.get(key)) {
+            None | Some(serde_json::Value::Null) => Ok(None),
+            Some(v) => v
+                .as_f64()
+                .and_then(decimal::from_f64)
+                .or_else(|| v.as_str().and_then(decimal::parse))
+                .filter(|d| *d > Decimal::ZERO)
+                .map(Some)
+                .ok_or_else(|| format!("Invalid {}: {}", key, v)),
+        }
+    };
+    let trail = match (offset("trail_percent")?, offset("trail_price")?) {
+        (None, None) => return Ok(None),
+        (Some(_), Some(_)) => {
+            return Err("Specify trail_percent or trail_price, not both".to_string())
+        }
+        (Some(pct), None) => Trail::Percent(pct),
+        (None, Some(price)) => Trail::Price(price),
+    };
+    if order.order_type != OrderType::Stop {
+        return Err("trailing stops must be stop orders".to_string());
+    }
+    if order.stop_price.is_some() {
+        return Err("trailing stops cannot have a stop_price".to_string());
+    }
+    Ok(Some(trail))
+}
+
+/// Bracket/OCO/OTO parameters taken from `OrderRequest.extensions`:
+/// `order_class`, `take_profit: {limit_price}`, and
+/// `stop_loss: {stop_price, limit_price?}`
+struct AdvancedOrder {
+    order_class: Option<String>,
+    take_profit: Option<serde_json::Value>,
+    stop_loss: Option<serde_json::Value>,
+}
+
+impl AdvancedOrder {
+    fn from_request(order: &OrderRequest) -> Result<Self, String> {
+        let ext = order.extensions.as_ref();
+        let order_class = ext
+            .and_then(|e| e.get("order_class"))
+            .and_then(|v| v.as_str())
+            .map(str::to_string);
+        if let Some(class) = order_class.as_deref() {
+            if !matches!(class, "simple" | "bracket" | "oco" | "oto") {
+                return Err(format!("Unsupported order_class: {}", class));
+            }
+        }
 
-        let status = match resp.status.as_str() {
-            "new" | "accepted" | "pending_new" => OrderStatus::Submitted,
-            "partially_filled" => OrderStatus::PartiallyFilled,
-            "filled" => OrderStatus::Filled,
-            "canceled" | "expired" | "rejected" => OrderStatus::Canceled,
-            _ => OrderStatus::Submitted,
+        let leg = |key: &str, fields: &[&str]| -> Result<Option<serde_json::Value>, String> {
+            let Some(value) = ext.and_then(|e| e.get(key)) else {
+                return Ok(None);
+            };
+            let mut out = serde_json::Map::new();
+            for field in fields {
+                match value.get(*field) {
+                    None | Some(serde_json::Value::Null) => {}
+                    Some(v) => {
+                        let price = v
+                            .as_f64()
+                            .and_then(decimal::from_f64)
+                            .or_else(|| v.as_str().and_then(decimal::parse))
+                            .ok_or_else(|| format!("Invalid {}.{}: {}", key, field, v))?;
+                        out.insert(field.to_string(), decimal::to_wire(price).into());
+                    }
+                }
+            }
+            Ok(Some(serde_json::Value::Object(out)))
         };
 
-        let created_at = DateTime::parse_from_rfc3339(&resp.created_at)
-            .map(|dt| dt.with_timezone(&Utc))
-            .unwrap_or_else(|_| Utc::now());
-
-        let updated_at = DateTime::parse_from_rfc3339(&resp.updated_at)
-            .map(|dt| dt.with_timezone(&Utc))
-            .unwrap_or_else(|_| Utc::now());
-
-        Ok(Order {
-            id: resp.id.clone(),
-            request: OrderRequest {
-                symbol_id: resp.symbol,
-                quantity: resp.qty.parse().unwrap_or(0.0),
-                side,
-                order_type,
-                limit_price: resp.limit_price.and_then(|p| p.parse().ok()),
-                stop_price: resp.stop_price.and_then(|p| p.parse().ok()),
-                reference_price: None,
-                time_in_force: None,
-                extensions: None,
-                persona_id: String::new(),
-            },
-            status,
-            created_at,
-            updated_at,
-            filled_quantity: resp.filled_qty.parse().unwrap_or(0.0),
-            average_filled_price: resp.filled_avg_price.and_then(|p| p.parse().ok()),
-            extensions: Some({
-                let mut map = HashMap::new();
-                map.insert(
-                    "client_order_id".to_string(),
-                    serde_json::Value::String(resp.client_order_id),
-                );
-                map.insert(
-                    "alpaca_status".to_string(),
-                    serde_json::Value::String(resp.status),
-                );
-                map
-            }),
+        Ok(Self {
+            order_class,
+            take_profit: leg("take_profit", &["limit_price"])?,
+            stop_loss: leg("stop_loss", &["stop_price", "limit_price"])?,
+        })
+    }
+}
+
+fn parse_timestamp(parser: FieldParser, field: &str, raw: &str) -> Result<DateTime<Utc>, String> {
+    match DateTime::parse_from_rfc3339(raw) {
+        Ok(dt) => Ok(dt.with_timezone(&Utc)),
+        Err(_) if parser.strict => Err(decimal::decode_error(field, raw)),
+        Err(_) => Ok(Utc::now()),
+    }
+}
+
+/// Body of `PATCH /v2/orders/{id}`; omitted fields keep their values
+#[derive(serde::Serialize)]
+struct ReplaceOrderRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    qty: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit_price: Option<String>,
+}
+
+/// Body of `POST /v2/orders`
+#[derive(serde::Serialize)]
+struct CreateOrderRequest {
+    symbol: String,
+    qty: String,
+    side: String,
+    #[serde(rename = "type")]
+    order_type: String,
+    time_in_force: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit_price: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_price: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extended_hours: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_order_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order_class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    take_profit: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_loss: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position_intent: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trail_percent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trail_price: Option<String>,
+}
+
+fn create_order_request(order: &OrderRequest) -> Result<CreateOrderRequest, String> {
+    let side = match order.side {
+        OrderSide::Buy => "buy",
+        OrderSide::Sell => "sell",
+    };
+
+    let trail = requested_trail(order)?;
+    let order_type = match order.order_type {
+        OrderType::Market => "market",
+        OrderType::Limit => "limit",
+        OrderType::Stop if trail.is_some() => "trailing_stop",
+        OrderType::Stop => "stop",
+        OrderType::StopLimit => "stop_limit",
+    };
+
+    let client_order_id = tags::client_order_id(tags::requested(order)?);
+
+    let qty = decimal::from_f64(order.quantity)
+        .ok_or_else(|| format!("Invalid quantity: {}", order.quantity))?;
+    let wire_price = |name: &str, price: Option<f64>| -> Result<Option<String>, String> {
+        price
+            .map(|p| {
+                decimal::from_f64(p)
+                    .map(decimal::to_wire)
+                    .ok_or_else(|| format!("Invalid {}: {}", name, p))
+            })
+            .transpose()
+    };
+
+    let advanced = AdvancedOrder::from_request(order)?;
+
+    Ok(CreateOrderRequest {
+        symbol: order.symbol_id.clone(),
+        qty: decimal::to_wire(qty),
+        side: side.to_string(),
+        order_type: order_type.to_string(),
+        time_in_force: requested_time_in_force(order)?.to_string(),
+        limit_price: wire_price("limit_price", order.limit_price)?,
+        stop_price: wire_price("stop_price", order.stop_price)?,
+        extended_hours: requested_extended_hours(order).then_some(true),
+        client_order_id: Some(client_order_id),
+        order_class: advanced.order_class,
+        take_profit: advanced.take_profit,
+        stop_loss: advanced.stop_loss,
+        position_intent: intent::requested(order)?.map(PositionIntent::as_str),
+        trail_percent: match trail {
+            Some(Trail::Percent(pct)) => Some(decimal::to_wire(pct)),
+            _ => None,
+        },
+        trail_price: match trail {
+            Some(Trail::Price(price)) => Some(decimal::to_wire(price)),
+            _ => None,
+        },
+    })
+}
+
+/// The exact `POST /v2/orders` body `submit_order` would send
+pub fn order_payload(order: &OrderRequest) -> Result<serde_json::Value, String> {
+    serde_json::to_value(create_order_request(order)?).map_err(|e| e.to_string())
+}
+
+/// Map an Alpaca order into the plugin_api `Order`, keeping the exact
+/// decimal strings in extensions alongside the `f64` fields
+/// `currency` is the account currency; prices stay in it and their USD
+/// equivalents go in `extensions.usd` on local currency accounts
+fn map_order(
+    resp: AlpacaOrder,
+    parser: FieldParser,
+    currency: Option<&str>,
+) -> Result<Order, String> {
+    let side = match resp.side.as_str() {
+        "buy" => OrderSide::Buy,
+        _ => OrderSide::Sell,
+    };
+
+    let order_type = match resp.order_type.as_str() {
+        "market" => OrderType::Market,
+        "limit" => OrderType::Limit,
+        "stop" | "trailing_stop" => OrderType::Stop,
+        "stop_limit" => OrderType::StopLimit,
+        _ => OrderType::Market,
+    };
+
+    let qty = parser.optional("qty", resp.qty.as_deref())?;
+    let filled_qty = parser.required("filled_qty", &resp.filled_qty)?;
+    let filled_avg_price = parser.optional("filled_avg_price", resp.filled_avg_price.as_deref())?;
+    let limit_price = parser.optional("limit_price", resp.limit_price.as_deref())?;
+    let stop_price = parser.optional("stop_price", resp.stop_price.as_deref())?;
+    let created_at = parse_timestamp(parser, "created_at", &resp.created_at)?;
+    let updated_at = parse_timestamp(parser, "updated_at", &resp.updated_at)?;
+
+    let alpaca_status = AlpacaOrderStatus::parse(&resp.status);
+    let status = alpaca_status.to_order_status(!filled_qty.is_zero());
+
+    let swap_rate = parser.optional("swap_rate", resp.swap_rate.as_deref())?;
+
+    let mut extensions = HashMap::new();
+    if let Some(local) = currency::describe(
+        currency,
+        &[
+            ("limit_price", limit_price),
+            ("stop_price", stop_price),
+            ("filled_avg_price", filled_avg_price),
+        ],
+        swap_rate,
+        resp.usd.as_ref(),
+    ) {
+        extensions.extend(local);
+    }
+    if let Some(tag) = tags::from_client_order_id(&resp.client_order_id) {
+        extensions.insert("tag".to_string(), tag.into());
+    }
+    extensions.insert(
+        "client_order_id".to_string(),
+        serde_json::Value::String(resp.client_order_id),
+    );
+    extensions.insert(
+        "alpaca_status".to_string(),
+        serde_json::Value::String(resp.status),
+    );
+    extensions.insert(
+        "is_terminal".to_string(),
+        alpaca_status.is_terminal().into(),
+    );
+    if let Some(reason) = alpaca_status.terminal_reason() {
+        extensions.insert("status_reason".to_string(), reason.into());
+    }
+    let optional_fields = [
+        ("canceled_at", resp.canceled_at),
+        ("expired_at", resp.expired_at),
+        ("failed_at", resp.failed_at),
+        ("replaced_at", resp.replaced_at),
+        ("replaced_by", resp.replaced_by),
+        ("replaces", resp.replaces),
+        ("position_intent", resp.position_intent),
+    ];
+    for (key, value) in optional_fields {
+        if let Some(value) = value {
+            extensions.insert(key.to_string(), value.into());
+        }
+    }
+    if let Some(class) = resp.order_class.filter(|c| !c.is_empty()) {
+        extensions.insert("order_class".to_string(), class.into());
+    }
+    if resp.extended_hours {
+        extensions.insert("extended_hours".to_string(), true.into());
+    }
+    for (key, raw) in [
+        ("trail_percent", resp.trail_percent.as_deref()),
+        ("trail_price", resp.trail_price.as_deref()),
+        ("hwm", resp.hwm.as_deref()),
+    ] {
+        if let Some(value) = parser.optional(key, raw)? {
+            extensions.insert(key.to_string(), decimal::to_wire(value).into());
+        }
+    }
+    extensions.insert(
+        "asset_class".to_string(),
+        AssetKind::from_class(resp.asset_class.as_deref(), &resp.symbol)
+            .name()
+            .into(),
+    );
+    if let Some(exchange) = resp.exchange.filter(|e| !e.is_empty()) {
+        extensions.insert("exchange".to_string(), exchange.into());
+    }
+    if let Some(legs) = resp.legs.filter(|legs| !legs.is_empty()) {
+        let summaries = legs
+            .into_iter()
+            .map(|leg| leg_summary(leg, parser))
+            .collect::<Result<Vec<_>, _>>()?;
+        extensions.insert(
+            "legs".to_string(),
+            serde_json::to_value(summaries).unwrap_or_default(),
+        );
+    }
+    if let Some(qty) = qty {
+        extensions.insert("qty".to_string(), decimal::to_wire(qty).into());
+    }
+    extensions.insert(
+        "filled_qty".to_string(),
+        decimal::to_wire(filled_qty).into(),
+    );
+    if let Some(price) = filled_avg_price {
+        extensions.insert(
+            "filled_avg_price".to_string(),
+            decimal::to_wire(price).into(),
+        );
+    }
+
+    Ok(Order {
+        id: resp.id,
+        request: OrderRequest {
+            symbol_id: resp.symbol,
+            quantity: qty.map(decimal::to_f64).unwrap_or(0.0),
+            side,
+            order_type,
+            limit_price: limit_price.map(decimal::to_f64),
+            stop_price: stop_price.map(decimal::to_f64),
+            reference_price: None,
+            time_in_force: resp.time_in_force,
+            extensions: None,
             persona_id: String::new(),
+        },
+        status,
+        created_at,
+        updated_at,
+        filled_quantity: decimal::to_f64(filled_qty),
+        average_filled_price: filled_avg_price.map(decimal::to_f64),
+        extensions: Some(extensions),
+        persona_id: String::new(),
+    })
+}
+
+/// A leg of an advanced order, keeping any legs nested under it
+fn leg_summary(leg: AlpacaOrder, parser: FieldParser) -> Result<LegSummary, String> {
+    let wire = |field: &str, raw: Option<&str>| -> Result<Option<String>, String> {
+        Ok(parser.optional(field, raw)?.map(decimal::to_wire))
+    };
+    let role = match leg.order_type.as_str() {
+        "limit" => Some("take_profit"),
+        "stop" | "stop_limit" | "trailing_stop" => Some("stop_loss"),
+        _ => None,
+    };
+    let legs = leg
+        .legs
+        .unwrap_or_default()
+        .into_iter()
+        .map(|l| leg_summary(l, parser))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(LegSummary {
+        role: role.map(str::to_string),
+        qty: wire("legs.qty", leg.qty.as_deref())?,
+        legs,
+        filled_qty: decimal::to_wire(parser.required("legs.filled_qty", &leg.filled_qty)?),
+        filled_avg_price: wire("legs.filled_avg_price", leg.filled_avg_price.as_deref())?,
+        limit_price: wire("legs.limit_price", leg.limit_price.as_deref())?,
+        stop_price: wire("legs.stop_price", leg.stop_price.as_deref())?,
+        id: leg.id,
+        symbol: leg.symbol,
+        side: leg.side,
+        order_type: leg.order_type,
+        alpaca_status: leg.status,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, fixture, response, MockTransport};
+    use proptest::prelude::*;
+    use serde_json::json;
+
+    fn decimal_f64(max_units: u64, max_scale: u32) -> impl Strategy<Value = f64> {
+        (1..max_units, 0..=max_scale).prop_map(|(units, scale)| {
+            Decimal::from_i128_with_scale(units as i128, scale)
+                .to_string()
+                .parse::<f64>()
+                .unwrap()
         })
     }
+
+    fn symbol() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[A-Z]{1,5}",
+            "[A-Z]{1,4}\\.[A-Z]",
+            "[A-Z]{2,5}/USD",
+            "[A-Z]{1,5}[0-9]{6}[CP][0-9]{8}",
+            Just("BRK.B".to_string()),
+            Just("BTC/USD".to_string()),
+        ]
+    }
+
+    prop_compose! {
+        fn order_request()(
+            symbol in symbol(),
+            quantity in decimal_f64(1_000_000_000_000, 9),
+            side in prop_oneof![Just(OrderSide::Buy), Just(OrderSide::Sell)],
+            order_type in prop_oneof![
+                Just(OrderType::Market),
+                Just(OrderType::Limit),
+                Just(OrderType::Stop),
+                Just(OrderType::StopLimit),
+            ],
+            limit in decimal_f64(100_000_000, 4),
+            stop in decimal_f64(100_000_000, 4),
+            tif in proptest::sample::select(TIME_IN_FORCE_VALUES),
+            tag in proptest::option::of("[A-Za-z0-9_-]{1,32}"),
+        ) -> OrderRequest {
+            let mut request = mock::order_request(&symbol)
+                .side(side)
+                .qty(quantity)
+                .extension("time_in_force", tif)
+                .extension("tag", tag);
+            if matches!(order_type, OrderType::Limit | OrderType::StopLimit) {
+                request = request.limit(limit);
+            }
+            if matches!(order_type, OrderType::Stop | OrderType::StopLimit) {
+                request = request.stop(stop);
+            }
+            request.order_type(order_type).build()
+        }
+    }
+
+    fn client(mock: &MockTransport) -> AlpacaClient {
+        AlpacaClient::with_transport(
+            "key".to_string(),
+            "secret".to_string(),
+            true,
+            ClientOptions::default(),
+            mock.clone(),
+        )
+    }
+
+    /// What Alpaca echoes back for a freshly accepted order
+    fn accepted(payload: &serde_json::Value) -> AlpacaOrder {
+        serde_json::from_value(json!({
+            "id": "61e69015-8549-4bfd-b9c3-01e75843f47d",
+            "client_order_id": payload["client_order_id"],
+            "status": "accepted",
+            "symbol": payload["symbol"],
+            "qty": payload["qty"],
+            "side": payload["side"],
+            "type": payload["type"],
+            "time_in_force": payload["time_in_force"],
+            "filled_qty": "0",
+            "filled_avg_price": null,
+            "limit_price": payload.get("limit_price"),
+            "stop_price": payload.get("stop_price"),
+            "created_at": "2024-06-13T14:31:02.418093Z",
+            "updated_at": "2024-06-13T14:31:02.418093Z",
+        }))
+        .unwrap()
+    }
+
+    proptest! {
+        #[test]
+        fn order_round_trips_through_alpaca(request in order_request()) {
+            let payload = order_payload(&request).unwrap();
+            prop_assert_eq!(
+                payload["time_in_force"].as_str(),
+                Some(requested_time_in_force(&request).unwrap())
+            );
+
+            let order = map_order(accepted(&payload), FieldParser::strict(), None).unwrap();
+            prop_assert_eq!(&order.request.symbol_id, &request.symbol_id);
+            prop_assert_eq!(order.request.quantity, request.quantity);
+            prop_assert_eq!(order.request.side, request.side);
+            prop_assert_eq!(order.request.order_type, request.order_type);
+            prop_assert_eq!(order.request.limit_price, request.limit_price);
+            prop_assert_eq!(order.request.stop_price, request.stop_price);
+
+            let ext = order.extensions.unwrap();
+            prop_assert_eq!(&ext["qty"], &payload["qty"]);
+            prop_assert_eq!(
+                ext.get("tag").and_then(|t| t.as_str()),
+                tags::requested(&request).unwrap()
+            );
+        }
+
+        #[test]
+        fn wire_prices_have_no_float_noise(request in order_request()) {
+            let payload = order_payload(&request).unwrap();
+            for field in ["qty", "limit_price", "stop_price"] {
+                if let Some(wire) = payload.get(field).and_then(|v| v.as_str()) {
+                    // At most the 9 places generated, never a binary expansion
+                    let places = wire.split_once('.').map_or(0, |(_, f)| f.len());
+                    prop_assert!(places <= 9, "{} = {}", field, wire);
+                    prop_assert!(!wire.contains('e'), "{} = {}", field, wire);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn orders_carry_their_asset_class() {
+        let request = |symbol: &str| mock::order_request(symbol).build();
+        let class_of = |resp: AlpacaOrder| {
+            let order = map_order(resp, FieldParser::strict(), None).unwrap();
+            order.extensions.unwrap()["asset_class"].clone()
+        };
+
+        // Alpaca's class is used as given
+        let payload = order_payload(&request("BTCUSD")).unwrap();
+        let mut resp = accepted(&payload);
+        resp.asset_class = Some("crypto".to_string());
+        assert_eq!(class_of(resp), "crypto");
+
+        // Without one, the symbol decides
+        let payload = order_payload(&request("AAPL240119C00150000")).unwrap();
+        assert_eq!(class_of(accepted(&payload)), "us_option");
+        let payload = order_payload(&request("AAPL")).unwrap();
+        assert_eq!(class_of(accepted(&payload)), "us_equity");
+    }
+
+    #[test]
+    fn malformed_position_extras_are_skipped() {
+        let position: AlpacaPosition = serde_json::from_value(json!({
+            "symbol": "AAPL",
+            "qty": "100",
+            "avg_entry_price": "180.00",
+            "current_price": "188.1",
+            "market_value": "18810",
+            "unrealized_pl": "810",
+            "unrealized_plpc": "0.045",
+            "cost_basis": "18000",
+            "lastday_price": "n/a",
+            "change_today": "0.0065",
+            "side": "long",
+        }))
+        .unwrap();
+        let pnl = position_pnl(&position, FieldParser::strict());
+        assert_eq!(
+            pnl,
+            json!({ "cost_basis": "18000", "change_today_percent": "0.65" })
+        );
+    }
+
+    #[test]
+    fn base_urls_must_be_https_unless_allowed() {
+        assert_eq!(
+            validate_base_url("api_base_url", "https://proxy.corp/alpaca/", false).unwrap(),
+            "https://proxy.corp/alpaca"
+        );
+        assert!(validate_base_url("api_base_url", "http://localhost:8080", false).is_err());
+        assert_eq!(
+            validate_base_url("api_base_url", "http://localhost:8080", true).unwrap(),
+            "http://localhost:8080"
+        );
+        for url in [
+            "paper-api.alpaca.markets",
+            "https://",
+            "https://a.test?x=1",
+            "ftp://a.test",
+        ] {
+            assert!(
+                validate_base_url("api_base_url", url, true).is_err(),
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn requests_carry_user_agent_and_extra_headers() {
+        let mock = MockTransport::new();
+        mock.on_fixture(HttpMethod::Get, "/v2/account", 200, "account");
+        let client = AlpacaClient::with_transport(
+            "key".to_string(),
+            "secret".to_string(),
+            true,
+            ClientOptions {
+                extra_headers: vec![("X-Correlation-Id".to_string(), "desk-7".to_string())],
+                ..ClientOptions::default()
+            },
+            mock.clone(),
+        );
+        client.fetch_account().unwrap();
+        let sent = &mock.requests()[0];
+        assert_eq!(
+            sent.headers.get("User-Agent").map(String::as_str),
+            Some(USER_AGENT)
+        );
+        assert_eq!(
+            sent.headers.get("X-Correlation-Id").map(String::as_str),
+            Some("desk-7")
+        );
+    }
+
+    #[test]
+    fn account_ids_match_number_or_uuid() {
+        let mock = MockTransport::new();
+        mock.on_fixture(HttpMethod::Get, "/v2/account", 200, "account");
+        let client = client(&mock);
+
+        assert!(client.is_own_account("").unwrap());
+        assert!(mock.requests().is_empty());
+        assert!(client.is_own_account("PA3ZQ8X1KD2M").unwrap());
+        assert!(client
+            .is_own_account("904837e3-3b76-47ec-b432-046db621571b")
+            .unwrap());
+        assert!(!client.is_own_account("PA000000").unwrap());
+        // The account is remembered after the first lookup
+        assert_eq!(mock.requests_to(HttpMethod::Get, "/v2/account").len(), 1);
+    }
+
+    #[test]
+    fn blocked_account_fails_fast_until_unblocked() {
+        let mut account: serde_json::Value = serde_json::from_str(&fixture("account")).unwrap();
+        account["trading_blocked"] = true.into();
+        let blocked = account.to_string();
+        account["trading_blocked"] = false.into();
+        let mock = MockTransport::new();
+        mock.on(
+            HttpMethod::Get,
+            "/v2/account",
+            response(200, blocked.clone()),
+        )
+        .on(HttpMethod::Get, "/v2/account", response(200, blocked))
+        .on(
+            HttpMethod::Get,
+            "/v2/account",
+            response(200, account.to_string()),
+        )
+        .on_fixture(HttpMethod::Post, "/v2/orders", 200, "order_new");
+        let client = client(&mock);
+
+        let summary = client.get_account_with(AccountPositions::Omit).unwrap();
+        let ext = summary.extensions.unwrap();
+        assert_eq!(ext["trading_blocked"], true);
+        assert_eq!(ext["can_trade"], false);
+
+        let err = client
+            .submit_order(&mock::order_request("AAPL").qty(10.0).limit(187.25).build())
+            .unwrap_err();
+        assert!(
+            err.starts_with("Account blocked: trading_blocked"),
+            "{}",
+            err
+        );
+        assert!(mock.requests_to(HttpMethod::Post, "/v2/orders").is_empty());
+
+        // The block is re-checked, so lifting it lets orders through
+        assert!(client
+            .submit_order(&mock::order_request("AAPL").qty(10.0).limit(187.25).build())
+            .is_ok());
+    }
 }
@@ -0,0 +1,163 @@
+//! Guest memory for the host boundary
+//!
+//! Two kinds of buffers cross the boundary:
+//!
+//! - Buffers the host asks for with `alloc` (request payloads, and the
+//!   `http_request` import's response). The plugin frees the import's
+//!   response once parsed; hosts may return request buffers with
+//!   `dealloc`.
+//! - Response buffers. These come from a small ring of reusable slots
+//!   instead of a fresh allocation per call, so a long-lived instance
+//!   settles on a fixed set of buffers rather than leaking one per
+//!   response. A response stays valid until [`RESPONSE_SLOTS`] more
+//!   responses have been written; hosts copy it out before the next call.
+//!
+//! Counters for both are reported by `get_metrics` under `memory`.
+
+use serde::Serialize;
+use std::alloc::Layout;
+use std::ptr::NonNull;
+use std::sync::Mutex;
+
+/// Response buffers kept for reuse
+pub const RESPONSE_SLOTS: usize = 4;
+
+/// A slot grown past this by one large response is released again once
+/// responses are back to a quarter of its size
+const SHRINK_ABOVE_BYTES: usize = 1 << 20;
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AllocStats {
+    /// `alloc` calls and the bytes they handed out
+    pub allocs: u64,
+    pub alloc_bytes: u64,
+    /// Buffers given back through `dealloc` or after parsing an import
+    /// response
+    pub frees: u64,
+    pub freed_bytes: u64,
+    /// Allocated and not yet freed
+    pub outstanding_bytes: u64,
+    pub responses: u64,
+    pub response_bytes: u64,
+    /// Capacity currently held by the response slots
+    pub arena_bytes: u64,
+    /// Responses that needed a slot to grow; the rest reused its buffer
+    pub arena_grows: u64,
+}
+
+#[derive(Default)]
+struct Arena {
+    slots: Vec<Vec<u8>>,
+    next: usize,
+    stats: AllocStats,
+}
+
+lazy_static::lazy_static! {
+    static ref ARENA: Mutex<Arena> = Mutex::new(Arena {
+        slots: vec![Vec::new(); RESPONSE_SLOTS],
+        ..Arena::default()
+    });
+}
+
+fn arena() -> std::sync::MutexGuard<'static, Arena> {
+    ARENA.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Allocate `len` bytes for the host to write into
+pub fn alloc(len: usize) -> *mut u8 {
+    if len == 0 {
+        return NonNull::dangling().as_ptr();
+    }
+    let layout = Layout::array::<u8>(len).expect("allocation too large");
+    // SAFETY: the layout has a non-zero size
+    let ptr = unsafe { std::alloc::alloc(layout) };
+    if ptr.is_null() {
+        std::alloc::handle_alloc_error(layout);
+    }
+    let mut arena = arena();
+    arena.stats.allocs += 1;
+    arena.stats.alloc_bytes += len as u64;
+    arena.stats.outstanding_bytes += len as u64;
+    ptr
+}
+
+/// Free a buffer from [`alloc`].
+///
+/// # Safety
+///
+/// `ptr` and `len` must be exactly those of one earlier [`alloc`] that has
+/// not been freed yet.
+pub unsafe fn free(ptr: *mut u8, len: usize) {
+    if len == 0 || ptr.is_null() {
+        return;
+    }
+    let layout = Layout::array::<u8>(len).expect("allocation too large");
+    // SAFETY: upheld by the caller
+    unsafe { std::alloc::dealloc(ptr, layout) };
+    let mut arena = arena();
+    arena.stats.frees += 1;
+    arena.stats.freed_bytes += len as u64;
+    arena.stats.outstanding_bytes = arena.stats.outstanding_bytes.saturating_sub(len as u64);
+}
+
+/// Copy a response into the next slot and return where it lives
+pub fn write_response(bytes: &[u8]) -> (*const u8, usize) {
+    let mut arena = arena();
+    let index = arena.next;
+    arena.next = (index + 1) % RESPONSE_SLOTS;
+
+    let slot = &mut arena.slots[index];
+    if slot.capacity() > SHRINK_ABOVE_BYTES && bytes.len() * 4 < slot.capacity() {
+        *slot = Vec::new();
+    }
+    let grew = slot.capacity() < bytes.len();
+    slot.clear();
+    slot.extend_from_slice(bytes);
+    let out = (slot.as_ptr(), slot.len());
+
+    let arena_bytes = arena.slots.iter().map(|s| s.capacity() as u64).sum();
+    let stats = &mut arena.stats;
+    stats.responses += 1;
+    stats.response_bytes += bytes.len() as u64;
+    stats.arena_bytes = arena_bytes;
+    if grew {
+        stats.arena_grows += 1;
+    }
+    out
+}
+
+pub fn stats() -> AllocStats {
+    arena().stats.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_are_written_to_reusable_slots() {
+        let before = stats();
+        let (ptr, len) = write_response(b"{\"b\":2}");
+        // SAFETY: the slot was just written; the other tests write fewer
+        // than RESPONSE_SLOTS responses
+        let written = unsafe { std::slice::from_raw_parts(ptr, len) };
+        assert_eq!(written, b"{\"b\":2}");
+        let after = stats();
+        assert!(after.responses > before.responses);
+        assert!(after.arena_bytes >= len as u64);
+    }
+
+    #[test]
+    fn alloc_and_free_are_counted() {
+        let ptr = alloc(64);
+        // SAFETY: 64 bytes were just allocated
+        unsafe {
+            ptr.write_bytes(7, 64);
+            free(ptr, 64);
+        }
+        let stats = stats();
+        assert!(stats.allocs >= 1 && stats.frees >= 1);
+        assert!(stats.freed_bytes >= 64);
+        assert!(alloc(0) == NonNull::dangling().as_ptr());
+    }
+}
@@ -0,0 +1,210 @@
+//! Opening and closing auction orders
+//!
+//! `extensions.auction: "open"` turns a market or limit order into a
+//! market- or limit-on-open order (`opg`), and `"close"` into a market- or
+//! limit-on-close order (`cls`). Alpaca only accepts these in a window:
+//! OPG orders until 09:28 ET, CLS orders until 10 minutes before the
+//! close (earlier on half days), and both again from 19:00 ET for the
+//! next session. An auction order placed between the cutoff and 19:00 on
+//! a trading day is rejected locally with `auction_cutoff` instead of
+//! being sent to a certain rejection.
+
+use crate::alpaca::{self, CalendarDay, MarketClock};
+use crate::market_time;
+use crate::pretrade::Finding;
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use models::order::{OrderRequest, OrderType};
+use serde::Serialize;
+
+/// Last time Alpaca accepts OPG orders for the session
+const OPG_CUTOFF: (u32, u32) = (9, 28);
+/// Alpaca rejects CLS orders in the last 10 minutes before the close
+const CLS_CUTOFF_MINUTES: i64 = 10;
+/// When auction orders are accepted again, for the next session
+const REOPEN: (u32, u32) = (19, 0);
+const REGULAR_CLOSE: (u32, u32) = (16, 0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Auction {
+    Open,
+    Close,
+}
+
+impl Auction {
+    pub fn time_in_force(self) -> &'static str {
+        match self {
+            Self::Open => "opg",
+            Self::Close => "cls",
+        }
+    }
+
+    /// The auction an order's time in force targets, if any
+    pub fn of(order: &OrderRequest) -> Option<Self> {
+        match alpaca::requested_time_in_force(order) {
+            Ok("opg") => Some(Self::Open),
+            Ok("cls") => Some(Self::Close),
+            _ => None,
+        }
+    }
+}
+
+fn hm((h, m): (u32, u32)) -> NaiveTime {
+    NaiveTime::from_hms_opt(h, m, 0).expect("valid time")
+}
+
+/// Read `extensions.auction` and return the order with the matching time
+/// in force; `Ok(None)` when the order does not ask for an auction
+pub fn requested(order: &OrderRequest) -> Result<Option<OrderRequest>, String> {
+    let auction = match order.extensions.as_ref().and_then(|e| e.get("auction")) {
+        None | Some(serde_json::Value::Null) => return Ok(None),
+        Some(serde_json::Value::String(s)) if s == "open" => Auction::Open,
+        Some(serde_json::Value::String(s)) if s == "close" => Auction::Close,
+        Some(v) => return Err(format!("Invalid auction: {} (expected open or close)", v)),
+    };
+    if !matches!(order.order_type, OrderType::Market | OrderType::Limit) {
+        return Err("auction orders must be market or limit orders".to_string());
+    }
+    if alpaca::requested_extended_hours(order) {
+        return Err("auction cannot be combined with extended_hours".to_string());
+    }
+    let tif = alpaca::requested_time_in_force(order)?;
+    if tif != "day" && tif != auction.time_in_force() {
+        return Err(format!(
+            "auction {} cannot be combined with time_in_force {}",
+            match auction {
+                Auction::Open => "open",
+                Auction::Close => "close",
+            },
+            tif
+        ));
+    }
+    let mut converted = order.clone();
+    let ext = converted.extensions.get_or_insert_with(Default::default);
+    ext.remove("auction");
+    ext.insert("time_in_force".to_string(), auction.time_in_force().into());
+    Ok(Some(converted))
+}
+
+/// Whether a trading day today has to come from the calendar: the clock
+/// only tells when the market is open or opens later today
+pub fn needs_calendar(clock: &MarketClock, now: DateTime<Utc>) -> bool {
+    let time = market_time::to_eastern(now).time();
+    !clock.is_open
+        && market_time::eastern_date(clock.next_open) != market_time::eastern_date(now)
+        && time >= hm(OPG_CUTOFF)
+        && time < hm(REOPEN)
+}
+
+/// Reject an auction order placed after today's cutoff and before the
+/// window for the next session opens. `today` is today's session from the
+/// calendar when [`needs_calendar`] asked for it; without it a closed
+/// market is taken to have had no session today.
+pub fn check_cutoff(
+    auction: Auction,
+    clock: &MarketClock,
+    today: Option<&CalendarDay>,
+    now: DateTime<Utc>,
+) -> Option<Finding> {
+    let date = market_time::eastern_date(now);
+    let close = if clock.is_open {
+        Some(clock.next_close)
+    } else if market_time::eastern_date(clock.next_open) == date {
+        // Before today's open; the close is later and far from the cutoff
+        Some(market_time::eastern_at(date, hm(REGULAR_CLOSE)))
+    } else {
+        today.filter(|day| day.date == date).map(|day| {
+            let close = day
+                .close
+                .as_deref()
+                .and_then(|c| NaiveTime::parse_from_str(c, "%H:%M").ok())
+                .unwrap_or_else(|| hm(REGULAR_CLOSE));
+            market_time::eastern_at(date, close)
+        })
+    }?;
+    let cutoff = match auction {
+        Auction::Open => market_time::eastern_at(date, hm(OPG_CUTOFF)),
+        Auction::Close => close - Duration::minutes(CLS_CUTOFF_MINUTES),
+    };
+    let reopen = market_time::eastern_at(date, hm(REOPEN));
+    if now < cutoff || now >= reopen {
+        return None;
+    }
+    let what = match auction {
+        Auction::Open => "opening",
+        Auction::Close => "closing",
+    };
+    Some(Finding::new(
+        "auction_cutoff",
+        format!(
+            "Too late for today's {} auction; {} orders are accepted again from 19:00 ET for the next session",
+            what,
+            auction.time_in_force()
+        ),
+        serde_json::json!({
+            "auction": auction,
+            "time_in_force": auction.time_in_force(),
+            "cutoff": cutoff,
+            "accepted_from": reopen,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+
+    fn clock(now: &str, is_open: bool, next_open: &str, next_close: &str) -> MarketClock {
+        serde_json::from_value(serde_json::json!({
+            "timestamp": now,
+            "is_open": is_open,
+            "next_open": next_open,
+            "next_close": next_close,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn auction_orders_are_refused_between_the_cutoff_and_the_evening_window() {
+        let order = order_request("AAPL")
+            .qty(10.0)
+            .limit(180.0)
+            .extension("auction", "close")
+            .build();
+        let converted = requested(&order).unwrap().unwrap();
+        assert_eq!(Auction::of(&converted), Some(Auction::Close));
+
+        // Friday 2024-03-01, 15:55 ET: five minutes past the CLS cutoff
+        let now: DateTime<Utc> = "2024-03-01T20:55:00Z".parse().unwrap();
+        let open = clock(
+            "2024-03-01T20:55:00Z",
+            true,
+            "2024-03-04T14:30:00Z",
+            "2024-03-01T21:00:00Z",
+        );
+        let finding = check_cutoff(Auction::Close, &open, None, now).unwrap();
+        assert_eq!(finding.code, "auction_cutoff");
+        assert!(check_cutoff(Auction::Close, &open, None, now - Duration::minutes(10)).is_none());
+
+        // After the close an OPG order waits for 19:00; the calendar says
+        // there was a session today
+        let after: DateTime<Utc> = "2024-03-01T22:00:00Z".parse().unwrap();
+        let closed = clock(
+            "2024-03-01T22:00:00Z",
+            false,
+            "2024-03-04T14:30:00Z",
+            "2024-03-04T21:00:00Z",
+        );
+        assert!(needs_calendar(&closed, after));
+        let friday = CalendarDay {
+            date: "2024-03-01".parse().unwrap(),
+            settlement_date: None,
+            close: Some("16:00".to_string()),
+        };
+        assert!(check_cutoff(Auction::Open, &closed, Some(&friday), after).is_some());
+        assert!(check_cutoff(Auction::Open, &closed, None, after).is_none());
+        let evening = after + Duration::hours(2);
+        assert!(check_cutoff(Auction::Open, &closed, Some(&friday), evening).is_none());
+    }
+}
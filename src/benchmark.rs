@@ -0,0 +1,158 @@
+//! Benchmark-relative portfolio history
+//!
+//! `get_portfolio_history` can fetch a benchmark's bars (SPY unless
+//! `benchmark_symbol` says otherwise) over the same period and line them up
+//! with the equity series, so the host can chart the account against the
+//! market from one response. Both are cumulative returns from the first
+//! point, as fractions like Alpaca's `profit_loss_pct`; the benchmark at a
+//! point is the close of the latest bar at or before it.
+
+use crate::alpaca::PortfolioHistory;
+use crate::decimal::{self, Decimal};
+use crate::market_data::Bar;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+pub const DEFAULT_BENCHMARK: &str = "SPY";
+
+/// Bar timeframe matching a portfolio history timeframe (`1Min`, `5Min`,
+/// `15Min`, `1H`, `1D`)
+pub fn bar_timeframe(timeframe: &str) -> Option<&'static str> {
+    match timeframe {
+        "1Min" => Some("1Min"),
+        "5Min" => Some("5Min"),
+        "15Min" => Some("15Min"),
+        "1H" => Some("1Hour"),
+        "1D" => Some("1Day"),
+        _ => None,
+    }
+}
+
+/// How far before the first point to fetch bars, so it has one at or
+/// before it across a weekend or holiday
+pub fn lookback(timeframe: &str) -> Duration {
+    match timeframe {
+        "1D" => Duration::days(10),
+        _ => Duration::days(4),
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BenchmarkSeries {
+    pub symbol: String,
+    /// Benchmark close at each point; `None` before its first bar
+    pub price: Vec<Option<String>>,
+    /// Cumulative benchmark return at each point
+    pub returns: Vec<Option<String>>,
+    /// Portfolio return minus benchmark return at each point
+    pub excess_returns: Vec<Option<String>>,
+    /// Returns at the last point where both are known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_return: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub portfolio_return: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excess_return: Option<String>,
+}
+
+fn growth(value: Decimal, base: Decimal) -> Option<Decimal> {
+    (!base.is_zero()).then(|| (value / base - Decimal::ONE).round_dp(6))
+}
+
+/// Line `bars` (oldest first) up with `history`
+pub fn align(symbol: &str, history: &PortfolioHistory, bars: &[Bar]) -> BenchmarkSeries {
+    let mut next_bar = 0;
+    let mut last_close = None;
+    let prices: Vec<Option<Decimal>> = history
+        .timestamp
+        .iter()
+        .map(|ts| {
+            let at = DateTime::<Utc>::from_timestamp(*ts, 0)?;
+            while next_bar < bars.len() && bars[next_bar].timestamp <= at {
+                last_close = Some(bars[next_bar].close);
+                next_bar += 1;
+            }
+            last_close
+        })
+        .collect();
+
+    let base_equity = history
+        .equity
+        .iter()
+        .flatten()
+        .find(|e| !e.is_zero())
+        .copied();
+    let base_price = prices.iter().flatten().next().copied();
+    let mut series = BenchmarkSeries {
+        symbol: symbol.to_string(),
+        price: Vec::with_capacity(prices.len()),
+        returns: Vec::with_capacity(prices.len()),
+        excess_returns: Vec::with_capacity(prices.len()),
+        total_return: None,
+        portfolio_return: None,
+        excess_return: None,
+    };
+    for (i, price) in prices.iter().enumerate() {
+        let benchmark = price.zip(base_price).and_then(|(p, b)| growth(p, b));
+        let portfolio = history
+            .equity
+            .get(i)
+            .copied()
+            .flatten()
+            .zip(base_equity)
+            .and_then(|(e, b)| growth(e, b));
+        let excess = benchmark.zip(portfolio).map(|(b, p)| p - b);
+        if let Some(excess) = excess {
+            series.total_return = benchmark.map(decimal::to_wire);
+            series.portfolio_return = portfolio.map(decimal::to_wire);
+            series.excess_return = Some(decimal::to_wire(excess));
+        }
+        series.price.push(price.map(decimal::to_wire));
+        series.returns.push(benchmark.map(decimal::to_wire));
+        series.excess_returns.push(excess.map(decimal::to_wire));
+    }
+    series
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar(t: &str, close: i64) -> Bar {
+        serde_json::from_value(serde_json::json!({
+            "t": t, "o": close, "h": close, "l": close, "c": close, "v": 100,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn benchmark_returns_line_up_with_the_equity_series() {
+        let history: PortfolioHistory = serde_json::from_value(serde_json::json!({
+            // Fri 2024-03-01, Mon 2024-03-04, Tue 2024-03-05 at 00:00 ET
+            "timestamp": [1709269200, 1709528400, 1709614800],
+            "equity": [10000, 10500, null],
+            "profit_loss": [0, 500, null],
+            "profit_loss_pct": [0, 0.05, null],
+            "base_value": 10000,
+            "timeframe": "1D",
+        }))
+        .unwrap();
+        let bars = [
+            bar("2024-02-29T05:00:00Z", 490),
+            bar("2024-03-01T05:00:00Z", 500),
+            bar("2024-03-04T05:00:00Z", 510),
+            bar("2024-03-05T05:00:00Z", 505),
+        ];
+        let series = align("SPY", &history, &bars);
+        assert_eq!(
+            series.price,
+            [Some("500".into()), Some("510".into()), Some("505".into())]
+        );
+        assert_eq!(series.returns[1].as_deref(), Some("0.02"));
+        assert_eq!(series.excess_returns[1].as_deref(), Some("0.03"));
+        // No equity on the last point, so the totals stop at the one before
+        assert_eq!(series.excess_returns[2], None);
+        assert_eq!(series.excess_return.as_deref(), Some("0.03"));
+        assert_eq!(series.total_return.as_deref(), Some("0.02"));
+    }
+}
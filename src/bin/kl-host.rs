@@ -0,0 +1,274 @@
+//! Minimal plugin host for local development
+//!
+//! Loads the compiled plugin, provides the `http_request` (and `host_log`,
+//! `emit_event`, `kv_get`, `kv_put`) imports backed by real HTTP and local
+//! files, calls `initialize` with a config file, then
+//! invokes exports in order and prints each response:
+//!
+//! ```bash
+//! cargo build --target wasm32-wasip1 --release
+//! cargo run --features dev-host --bin kl-host -- \
+//!     target/wasm32-wasip1/release/broker_alpaca.wasm config.json \
+//!     get_accounts get_positions submit_order=order.json
+//! ```
+//!
+//! `${VAR}` in the config and request files is replaced from the
+//! environment, so credentials can stay out of the files. The key-value
+//! store is one file per key in `$KL_HOST_KV_DIR` (default `kl-host-kv`).
+
+use anyhow::{anyhow, bail, Context, Result};
+use broker_alpaca::native::{HttpRequest, HttpTransport, ReqwestTransport};
+use wasmtime::{Caller, Engine, Extern, Instance, Linker, Memory, Module, Store, TypedFunc};
+use wasmtime_wasi::preview1::{self, WasiP1Ctx};
+use wasmtime_wasi::WasiCtxBuilder;
+
+const USAGE: &str = "usage: kl-host <plugin.wasm> <config.json> [<export>[=<request.json>] ...]";
+
+struct Host {
+    wasi: WasiP1Ctx,
+    transport: ReqwestTransport,
+}
+
+/// Replace `${VAR}` with the variable's value; unknown variables are an
+/// error rather than an empty string
+fn expand_env(text: &str) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .map(|i| start + i)
+            .ok_or_else(|| anyhow!("unterminated ${{ in input"))?;
+        let name = &rest[start + 2..end];
+        let value = std::env::var(name).with_context(|| format!("${{{}}} is not set", name))?;
+        out.push_str(&rest[..start]);
+        out.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn read_input(path: &str) -> Result<String> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path))?;
+    expand_env(&text)
+}
+
+fn memory(caller: &mut Caller<'_, Host>) -> Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| anyhow!("plugin does not export its memory"))
+}
+
+fn unpack(packed: i64) -> (usize, usize) {
+    let packed = packed as u64;
+    ((packed >> 32) as u32 as usize, packed as u32 as usize)
+}
+
+/// `http_request(ptr, len) -> u64`: run the request and hand back the
+/// response JSON in guest memory allocated through the plugin's `alloc`
+fn http_request(mut caller: Caller<'_, Host>, ptr: i32, len: i32) -> Result<i64> {
+    let memory = memory(&mut caller)?;
+    let mut bytes = vec![0u8; len as u32 as usize];
+    memory.read(&caller, ptr as u32 as usize, &mut bytes)?;
+    let request: HttpRequest =
+        serde_json::from_slice(&bytes).context("plugin sent a malformed http_request")?;
+    eprintln!("-> {} {}", request.method.as_str(), request.url);
+
+    let response = caller.data().transport.send(request);
+    eprintln!("<- {}", response.status);
+    let body = serde_json::to_vec(&response)?;
+
+    let alloc = caller
+        .get_export("alloc")
+        .and_then(Extern::into_func)
+        .ok_or_else(|| anyhow!("plugin does not export alloc"))?
+        .typed::<i32, i32>(&caller)?;
+    let out_ptr = alloc.call(&mut caller, body.len() as i32)?;
+    memory.write(&mut caller, out_ptr as u32 as usize, &body)?;
+    Ok(((out_ptr as u32 as i64) << 32) | body.len() as i64)
+}
+
+/// `host_log(level, ptr, len)` for plugins built with `host-log`
+fn host_log(mut caller: Caller<'_, Host>, _level: i32, ptr: i32, len: i32) -> Result<()> {
+    let memory = memory(&mut caller)?;
+    let mut bytes = vec![0u8; len as u32 as usize];
+    memory.read(&caller, ptr as u32 as usize, &mut bytes)?;
+    eprintln!("{}", String::from_utf8_lossy(&bytes));
+    Ok(())
+}
+
+/// `emit_event(ptr, len)` for plugins built with `host-events`; prints
+/// each event and accepts it
+fn emit_event(mut caller: Caller<'_, Host>, ptr: i32, len: i32) -> Result<i32> {
+    let memory = memory(&mut caller)?;
+    let mut bytes = vec![0u8; len as u32 as usize];
+    memory.read(&caller, ptr as u32 as usize, &mut bytes)?;
+    eprintln!("event: {}", String::from_utf8_lossy(&bytes));
+    Ok(0)
+}
+
+/// File holding a key-value store entry; anything but `[A-Za-z0-9._-]` in
+/// the key is hex-escaped so keys cannot leave the directory
+fn kv_path(key: &str) -> std::path::PathBuf {
+    let dir = std::env::var("KL_HOST_KV_DIR").unwrap_or_else(|_| "kl-host-kv".to_string());
+    let name: String = key
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'_' | b'-' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    std::path::Path::new(&dir).join(name)
+}
+
+/// `kv_get(ptr, len) -> u64` for plugins built with `host-kv`: the stored
+/// bytes in guest memory, or 0 when the key is absent
+fn kv_get(mut caller: Caller<'_, Host>, ptr: i32, len: i32) -> Result<i64> {
+    let memory = memory(&mut caller)?;
+    let mut key = vec![0u8; len as u32 as usize];
+    memory.read(&caller, ptr as u32 as usize, &mut key)?;
+    let Ok(value) = std::fs::read(kv_path(&String::from_utf8_lossy(&key))) else {
+        return Ok(0);
+    };
+    let alloc = caller
+        .get_export("alloc")
+        .and_then(Extern::into_func)
+        .ok_or_else(|| anyhow!("plugin does not export alloc"))?
+        .typed::<i32, i32>(&caller)?;
+    let out_ptr = alloc.call(&mut caller, value.len() as i32)?;
+    memory.write(&mut caller, out_ptr as u32 as usize, &value)?;
+    Ok(((out_ptr as u32 as i64) << 32) | value.len() as i64)
+}
+
+/// `kv_put(key_ptr, key_len, value_ptr, value_len) -> i32`: write the
+/// value to its file; 0 on success
+fn kv_put(
+    mut caller: Caller<'_, Host>,
+    key_ptr: i32,
+    key_len: i32,
+    value_ptr: i32,
+    value_len: i32,
+) -> Result<i32> {
+    let memory = memory(&mut caller)?;
+    let mut key = vec![0u8; key_len as u32 as usize];
+    memory.read(&caller, key_ptr as u32 as usize, &mut key)?;
+    let mut value = vec![0u8; value_len as u32 as usize];
+    memory.read(&caller, value_ptr as u32 as usize, &mut value)?;
+    let path = kv_path(&String::from_utf8_lossy(&key));
+    let written = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&path, &value));
+    Ok(match written {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("kv_put {}: {}", path.display(), e);
+            1
+        }
+    })
+}
+
+struct Plugin {
+    store: Store<Host>,
+    instance: Instance,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+}
+
+impl Plugin {
+    fn load(path: &str) -> Result<Self> {
+        let engine = Engine::default();
+        let module =
+            Module::from_file(&engine, path).with_context(|| format!("loading {}", path))?;
+
+        let mut linker: Linker<Host> = Linker::new(&engine);
+        preview1::add_to_linker_sync(&mut linker, |host| &mut host.wasi)?;
+        linker.func_wrap("env", "http_request", http_request)?;
+        linker.func_wrap("env", "host_log", host_log)?;
+        linker.func_wrap("env", "emit_event", emit_event)?;
+        linker.func_wrap("env", "kv_get", kv_get)?;
+        linker.func_wrap("env", "kv_put", kv_put)?;
+
+        let host = Host {
+            wasi: WasiCtxBuilder::new()
+                .inherit_stdout()
+                .inherit_stderr()
+                .build_p1(),
+            transport: ReqwestTransport::new(),
+        };
+        let mut store = Store::new(&engine, host);
+        let instance = linker.instantiate(&mut store, &module)?;
+        // cdylib modules built for WASI are reactors
+        if let Ok(init) = instance.get_typed_func::<(), ()>(&mut store, "_initialize") {
+            init.call(&mut store, ())?;
+        }
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| anyhow!("plugin does not export its memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        Ok(Self {
+            store,
+            instance,
+            memory,
+            alloc,
+        })
+    }
+
+    /// Call an export with a JSON request (empty for none) and return the
+    /// response JSON
+    fn call(&mut self, export: &str, request: &str) -> Result<serde_json::Value> {
+        let func = self
+            .instance
+            .get_typed_func::<(i32, i32), i64>(&mut self.store, export)
+            .with_context(|| format!("no export named {}", export))?;
+        let (ptr, len) = if request.is_empty() {
+            (0, 0)
+        } else {
+            let len = request.len() as i32;
+            let ptr = self.alloc.call(&mut self.store, len)?;
+            self.memory
+                .write(&mut self.store, ptr as u32 as usize, request.as_bytes())?;
+            (ptr, len)
+        };
+
+        let packed = func.call(&mut self.store, (ptr, len))?;
+        if len > 0 {
+            if let Ok(dealloc) = self
+                .instance
+                .get_typed_func::<(i32, i32), ()>(&mut self.store, "dealloc")
+            {
+                dealloc.call(&mut self.store, (ptr, len))?;
+            }
+        }
+        let (out_ptr, out_len) = unpack(packed);
+        let mut bytes = vec![0u8; out_len];
+        self.memory.read(&self.store, out_ptr, &mut bytes)?;
+        serde_json::from_slice(&bytes).with_context(|| format!("{} returned invalid JSON", export))
+    }
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [wasm, config, calls @ ..] = args.as_slice() else {
+        bail!(USAGE);
+    };
+
+    let mut plugin = Plugin::load(wasm)?;
+    let init = plugin.call("initialize", &read_input(config)?)?;
+    println!("initialize: {}", serde_json::to_string_pretty(&init)?);
+    if init.get("success") == Some(&serde_json::Value::Bool(false)) {
+        bail!("initialize failed");
+    }
+
+    for call in calls {
+        let (export, request) = match call.split_once('=') {
+            Some((export, path)) => (export, read_input(path)?),
+            None => (call.as_str(), String::new()),
+        };
+        let response = plugin.call(export, &request)?;
+        println!("{}: {}", export, serde_json::to_string_pretty(&response)?);
+    }
+    Ok(())
+}
@@ -0,0 +1,627 @@
+//! Broker API mode
+//!
+//! Correspondents on Alpaca's Broker API manage customer sub-accounts with
+//! a separate key pair, sent as HTTP Basic auth to a separate host
+//! (`broker-api.sandbox.alpaca.markets` for paper, `broker-api.alpaca.markets`
+//! for live). With `broker_api` in the config, the plugin keeps a second
+//! client for that API next to the trading one, acting for the sub-account
+//! in `broker_api.account_id`. Trading still goes through the trading API
+//! credentials; the Broker API client serves the account-administration
+//! exports built on it.
+
+use crate::alpaca::{AlpacaClient, ClientOptions};
+use crate::config::Credentials;
+use crate::decimal::{self, Decimal};
+use crate::http::{percent_encode, HttpMethod, QueryParams};
+use crate::onboarding::{AccountApplication, AccountStatus};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const SANDBOX_URL: &str = "https://broker-api.sandbox.alpaca.markets";
+const LIVE_URL: &str = "https://broker-api.alpaca.markets";
+
+pub struct BrokerApiConfig {
+    pub credentials: Credentials,
+    pub account_id: String,
+    /// In place of the sandbox or live Broker API host
+    pub base_url: Option<String>,
+}
+
+pub struct BrokerApi {
+    pub client: Arc<AlpacaClient>,
+    pub account_id: String,
+}
+
+impl BrokerApi {
+    /// Client for the Broker API: sandbox when `is_paper`, with the trading
+    /// client's options otherwise
+    pub fn new(
+        api_key: String,
+        api_secret: String,
+        is_paper: bool,
+        account_id: String,
+        base_url: Option<String>,
+        options: ClientOptions,
+    ) -> Self {
+        let base_url =
+            base_url.unwrap_or_else(|| if is_paper { SANDBOX_URL } else { LIVE_URL }.to_string());
+        let options = ClientOptions {
+            api_base_url: Some(base_url),
+            basic_auth: true,
+            // The cache rules cover trading API paths only
+            response_cache: None,
+            // Its probe is a trading API request
+            circuit_breaker: None,
+            ..options
+        };
+        Self {
+            client: Arc::new(AlpacaClient::with_options(
+                api_key, api_secret, is_paper, options,
+            )),
+            account_id,
+        }
+    }
+
+    fn account_path(&self, rest: &str) -> String {
+        format!("/v1/accounts/{}{}", percent_encode(&self.account_id), rest)
+    }
+
+    /// Statements, confirmations, and tax forms of the sub-account
+    pub fn list_documents(&self, query: &DocumentQuery) -> Result<Vec<Document>, String> {
+        let params = QueryParams::new()
+            .push_opt("start", query.start)
+            .push_opt("end", query.end)
+            .push_opt("type", query.document_type.as_deref());
+        self.client
+            .api_get_with(&self.account_path("/documents"), &params)
+    }
+
+    /// Fetch one document. Alpaca answers with a redirect to a short-lived
+    /// download link, which is returned as is; a transport that follows
+    /// the redirect yields the content instead.
+    pub fn download_document(&self, document_id: &str) -> Result<DocumentContent, String> {
+        let path = self.account_path(&format!(
+            "/documents/{}/download",
+            percent_encode(document_id)
+        ));
+        let response = self.client.raw_request(HttpMethod::Get, &path, None, false);
+        if (300..400).contains(&response.status) {
+            return response
+                .header("Location")
+                .map(|url| DocumentContent::Link {
+                    download_url: url.to_string(),
+                })
+                .ok_or_else(|| {
+                    format!("API error {}: redirect without a location", response.status)
+                });
+        }
+        if !response.is_success() {
+            return Err(format!(
+                "API error {}: {}",
+                response.status,
+                response.error.unwrap_or(response.body)
+            ));
+        }
+        let content_type = response.header("Content-Type").map(str::to_string);
+        Ok(DocumentContent::Inline {
+            content_base64: base64::Engine::encode(
+                &base64::engine::general_purpose::STANDARD,
+                response.body.as_bytes(),
+            ),
+            content_type,
+        })
+    }
+
+    /// Bank accounts linked for ACH, with account numbers masked
+    pub fn list_bank_relationships(&self) -> Result<Vec<BankRelationship>, String> {
+        let mut relationships: Vec<BankRelationship> = self
+            .client
+            .api_get(&self.account_path("/ach_relationships"))?;
+        for relationship in &mut relationships {
+            relationship.bank_account_number = relationship
+                .bank_account_number
+                .as_deref()
+                .map(mask_account);
+        }
+        Ok(relationships)
+    }
+
+    /// Transfers of the sub-account, newest first
+    pub fn list_transfers(&self, direction: Option<Direction>) -> Result<Vec<Transfer>, String> {
+        let params = QueryParams::new().push_opt("direction", direction.map(Direction::as_str));
+        self.client
+            .api_get_with(&self.account_path("/transfers"), &params)
+    }
+
+    /// Start an ACH deposit or withdrawal
+    pub fn create_transfer(&self, request: &TransferRequest) -> Result<Transfer, String> {
+        let body = serde_json::json!({
+            "transfer_type": "ach",
+            "relationship_id": request.relationship_id,
+            "amount": decimal::to_wire(request.amount),
+            "direction": request.direction.as_str(),
+        });
+        self.client
+            .api_post(&self.account_path("/transfers"), &body)
+    }
+
+    /// Move cash or shares between two sub-accounts
+    pub fn create_journal(&self, request: &JournalRequest) -> Result<Journal, String> {
+        let mut body = serde_json::json!({
+            "entry_type": request.entry_type.as_str(),
+            "from_account": request.from_account.as_deref().unwrap_or(&self.account_id),
+            "to_account": request.to_account,
+        });
+        let fields = [
+            ("amount", request.amount.map(decimal::to_wire)),
+            ("symbol", request.symbol.clone()),
+            ("qty", request.qty.map(decimal::to_wire)),
+            ("description", request.description.clone()),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                body[key] = value.into();
+            }
+        }
+        self.client.api_post("/v1/journals", &body)
+    }
+
+    /// One journal, to poll its status
+    pub fn get_journal(&self, journal_id: &str) -> Result<Journal, String> {
+        self.client
+            .api_get(&format!("/v1/journals/{}", percent_encode(journal_id)))
+    }
+
+    /// Journals from or to the configured sub-account, unless the query
+    /// names other accounts
+    pub fn list_journals(&self, query: &JournalQuery) -> Result<Vec<Journal>, String> {
+        let params = QueryParams::new()
+            .push_opt("after", query.after)
+            .push_opt("before", query.before)
+            .push_opt("status", query.status.as_deref())
+            .push_opt("entry_type", query.entry_type.map(JournalType::as_str))
+            .push_opt("from_account", query.from_account.as_deref())
+            .push_opt("to_account", query.to_account.as_deref());
+        let mut journals: Vec<Journal> = self.client.api_get_with("/v1/journals", &params)?;
+        if query.from_account.is_none() && query.to_account.is_none() {
+            journals
+                .retain(|j| j.from_account == self.account_id || j.to_account == self.account_id);
+        }
+        Ok(journals)
+    }
+
+    /// Submit a new customer account for KYC review
+    pub fn open_account(&self, application: &AccountApplication) -> Result<AccountStatus, String> {
+        self.client.api_post("/v1/accounts", application)
+    }
+
+    /// Review status of a sub-account; the configured one by default
+    pub fn account_status(&self, account_id: Option<&str>) -> Result<AccountStatus, String> {
+        let account_id = account_id.unwrap_or(&self.account_id);
+        self.client
+            .api_get(&format!("/v1/accounts/{}", percent_encode(account_id)))
+    }
+}
+
+/// Keep the last four digits of a bank account number
+fn mask_account(number: &str) -> String {
+    let chars: Vec<char> = number.chars().collect();
+    let keep = chars.len().saturating_sub(4);
+    chars
+        .iter()
+        .enumerate()
+        .map(|(i, c)| if i < keep { '*' } else { *c })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Direction {
+    /// A deposit into the sub-account
+    #[serde(alias = "incoming", alias = "deposit")]
+    Incoming,
+    /// A withdrawal to the bank
+    #[serde(alias = "outgoing", alias = "withdrawal")]
+    Outgoing,
+}
+
+impl Direction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Direction::Incoming => "INCOMING",
+            Direction::Outgoing => "OUTGOING",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TransferRequest {
+    /// Bank relationship to move money through; optional when the account
+    /// has exactly one approved relationship
+    #[serde(default)]
+    pub relationship_id: Option<String>,
+    pub amount: Decimal,
+    pub direction: Direction,
+}
+
+impl TransferRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.amount <= Decimal::ZERO {
+            return Err("amount must be positive".to_string());
+        }
+        if self.amount.normalize().scale() > 2 {
+            return Err("amount must be in whole cents".to_string());
+        }
+        Ok(())
+    }
+
+    /// Fill in the only approved relationship when none was named
+    pub fn resolve_relationship(
+        &mut self,
+        relationships: &[BankRelationship],
+    ) -> Result<(), String> {
+        if self.relationship_id.is_some() {
+            return Ok(());
+        }
+        let approved: Vec<&BankRelationship> = relationships
+            .iter()
+            .filter(|r| r.status.eq_ignore_ascii_case("APPROVED"))
+            .collect();
+        match approved.as_slice() {
+            [only] => {
+                self.relationship_id = Some(only.id.clone());
+                Ok(())
+            }
+            [] => Err("No approved bank relationship; link a bank account first".to_string()),
+            _ => Err("Several approved bank relationships; pass relationship_id".to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum JournalType {
+    /// Cash journal
+    #[serde(rename = "JNLC", alias = "cash")]
+    Cash,
+    /// Security journal
+    #[serde(rename = "JNLS", alias = "security")]
+    Security,
+}
+
+impl JournalType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JournalType::Cash => "JNLC",
+            JournalType::Security => "JNLS",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct JournalRequest {
+    pub entry_type: JournalType,
+    /// Default: the configured sub-account
+    #[serde(default)]
+    pub from_account: Option<String>,
+    pub to_account: String,
+    /// Cash journals only
+    #[serde(default)]
+    pub amount: Option<Decimal>,
+    /// Security journals only
+    #[serde(default)]
+    pub symbol: Option<String>,
+    #[serde(default)]
+    pub qty: Option<Decimal>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl JournalRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        let positive = |value: Option<Decimal>, field: &str| match value {
+            Some(v) if v > Decimal::ZERO => Ok(v),
+            _ => Err(format!("{} must be positive", field)),
+        };
+        match self.entry_type {
+            JournalType::Cash => {
+                if self.symbol.is_some() || self.qty.is_some() {
+                    return Err("cash journals take amount, not symbol and qty".to_string());
+                }
+                let amount = positive(self.amount, "amount")?;
+                if amount.normalize().scale() > 2 {
+                    return Err("amount must be in whole cents".to_string());
+                }
+            }
+            JournalType::Security => {
+                if self.amount.is_some() {
+                    return Err("security journals take symbol and qty, not amount".to_string());
+                }
+                if self.symbol.as_deref().is_none_or(str::is_empty) {
+                    return Err("symbol is required for security journals".to_string());
+                }
+                positive(self.qty, "qty")?;
+            }
+        }
+        if self.from_account.as_deref() == Some(self.to_account.as_str()) {
+            return Err("from_account and to_account must differ".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct JournalQuery {
+    #[serde(default)]
+    pub after: Option<NaiveDate>,
+    #[serde(default)]
+    pub before: Option<NaiveDate>,
+    /// `queued`, `pending`, `executed`, `rejected`, `canceled`, ...
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub entry_type: Option<JournalType>,
+    #[serde(default)]
+    pub from_account: Option<String>,
+    #[serde(default)]
+    pub to_account: Option<String>,
+}
+
+/// A journal from `/v1/journals`. `status` moves from `queued` or
+/// `pending` to `executed`, or ends `rejected`, `canceled`, or `refused`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Journal {
+    pub id: String,
+    pub entry_type: JournalType,
+    pub from_account: String,
+    pub to_account: String,
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub net_amount: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qty: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settle_date: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_date: Option<NaiveDate>,
+}
+
+/// An ACH relationship from `GET /v1/accounts/{id}/ach_relationships`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BankRelationship {
+    pub id: String,
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_owner_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bank_account_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bank_account_number: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// A transfer from `/v1/accounts/{id}/transfers`. `status` moves through
+/// `QUEUED`, `APPROVAL_PENDING`, `PENDING`, `SENT_TO_CLEARING`, and
+/// `APPROVED` to `COMPLETE`, or ends `REJECTED`, `CANCELED`, or `RETURNED`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Transfer {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relationship_id: Option<String>,
+    #[serde(rename = "type")]
+    pub transfer_type: String,
+    pub status: String,
+    pub amount: String,
+    pub direction: Direction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct DocumentQuery {
+    #[serde(default)]
+    pub start: Option<NaiveDate>,
+    #[serde(default)]
+    pub end: Option<NaiveDate>,
+    /// `account_statement`, `trade_confirmation`, `tax_statement`, ...
+    #[serde(default, rename = "type")]
+    pub document_type: Option<String>,
+}
+
+/// Document metadata from `GET /v1/accounts/{id}/documents`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Document {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub document_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub_type: Option<String>,
+    pub date: NaiveDate,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
+pub enum DocumentContent {
+    Link {
+        download_url: String,
+    },
+    Inline {
+        content_base64: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        content_type: Option<String>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{response, MockTransport};
+
+    #[test]
+    fn documents_use_basic_auth_and_the_account_path() {
+        let mock = MockTransport::new();
+        mock.on(
+            HttpMethod::Get,
+            "/v1/accounts/acct-1/documents",
+            response(
+                200,
+                r#"[{"id":"doc-1","name":"March statement","type":"account_statement","date":"2024-03-31"}]"#,
+            ),
+        );
+        let mut redirect = response(301, "");
+        redirect.headers.insert(
+            "Location".to_string(),
+            "https://files.example/doc-1".to_string(),
+        );
+        mock.on(
+            HttpMethod::Get,
+            "/v1/accounts/acct-1/documents/doc-1/download",
+            redirect,
+        );
+        let broker = BrokerApi {
+            client: Arc::new(AlpacaClient::with_transport(
+                "bk".to_string(),
+                "bs".to_string(),
+                true,
+                ClientOptions {
+                    api_base_url: Some(SANDBOX_URL.to_string()),
+                    basic_auth: true,
+                    ..ClientOptions::default()
+                },
+                mock.clone(),
+            )),
+            account_id: "acct-1".to_string(),
+        };
+
+        let documents = broker
+            .list_documents(&DocumentQuery {
+                document_type: Some("account_statement".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(documents[0].id, "doc-1");
+        let sent = &mock.requests()[0];
+        assert!(sent.url.starts_with(SANDBOX_URL));
+        assert!(sent.url.ends_with("?type=account_statement"));
+        // base64("bk:bs")
+        assert_eq!(
+            sent.headers.get("Authorization").map(String::as_str),
+            Some("Basic Yms6YnM=")
+        );
+        assert!(!sent.headers.contains_key("APCA-API-KEY-ID"));
+
+        match broker.download_document("doc-1").unwrap() {
+            DocumentContent::Link { download_url } => {
+                assert_eq!(download_url, "https://files.example/doc-1")
+            }
+            other => panic!("expected a link, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn transfers_use_the_only_approved_relationship() {
+        let relationship = |id: &str, status: &str| BankRelationship {
+            id: id.to_string(),
+            status: status.to_string(),
+            account_owner_name: None,
+            bank_account_type: None,
+            bank_account_number: None,
+            nickname: None,
+            created_at: None,
+        };
+        let mut request: TransferRequest = serde_json::from_value(serde_json::json!({
+            "amount": "250.00",
+            "direction": "deposit",
+        }))
+        .unwrap();
+        assert_eq!(request.direction, Direction::Incoming);
+        assert!(request.validate().is_ok());
+        request
+            .resolve_relationship(&[
+                relationship("r1", "CANCELED"),
+                relationship("r2", "APPROVED"),
+            ])
+            .unwrap();
+        assert_eq!(request.relationship_id.as_deref(), Some("r2"));
+
+        let mut ambiguous = TransferRequest {
+            relationship_id: None,
+            ..request.clone()
+        };
+        assert!(ambiguous
+            .resolve_relationship(&[
+                relationship("r1", "APPROVED"),
+                relationship("r2", "APPROVED")
+            ])
+            .is_err());
+        let fraction = TransferRequest {
+            amount: Decimal::new(1005, 3),
+            ..request
+        };
+        assert!(fraction.validate().is_err());
+        assert_eq!(mask_account("123456789"), "*****6789");
+    }
+
+    #[test]
+    fn journals_default_to_the_configured_account() {
+        let mock = MockTransport::new();
+        mock.on(
+            HttpMethod::Post,
+            "/v1/journals",
+            response(
+                200,
+                r#"{"id":"j1","entry_type":"JNLC","from_account":"acct-1","to_account":"acct-2","status":"queued","net_amount":"50"}"#,
+            ),
+        );
+        let broker = BrokerApi {
+            client: Arc::new(AlpacaClient::with_transport(
+                "bk".to_string(),
+                "bs".to_string(),
+                true,
+                ClientOptions {
+                    basic_auth: true,
+                    ..ClientOptions::default()
+                },
+                mock.clone(),
+            )),
+            account_id: "acct-1".to_string(),
+        };
+        let request: JournalRequest = serde_json::from_value(serde_json::json!({
+            "entry_type": "cash",
+            "to_account": "acct-2",
+            "amount": "50",
+        }))
+        .unwrap();
+        request.validate().unwrap();
+        let journal = broker.create_journal(&request).unwrap();
+        assert_eq!(journal.status, "queued");
+        let sent: serde_json::Value =
+            serde_json::from_str(mock.requests()[0].body.as_deref().unwrap()).unwrap();
+        assert_eq!(sent["from_account"], "acct-1");
+        assert_eq!(sent["entry_type"], "JNLC");
+        assert_eq!(sent["amount"], "50");
+        assert!(sent.get("qty").is_none());
+
+        let mixed = JournalRequest {
+            symbol: Some("AAPL".to_string()),
+            ..request
+        };
+        assert!(mixed.validate().is_err());
+    }
+}
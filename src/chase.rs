@@ -0,0 +1,182 @@
+//! Auto-repricing (chase) for resting limit orders
+//!
+//! A limit order with `extensions.chase` is watched by the `tick` export.
+//! Each time it has rested `after_secs` without filling completely, it is
+//! replaced (`PATCH /v2/orders/{id}`) one `step` closer to the market, up
+//! to `max_price`. Alpaca gives the replacement a new order ID, so the
+//! book follows the chain and keeps counting from the original order.
+
+use crate::decimal::{self, Decimal};
+use chrono::{DateTime, Duration, Utc};
+use models::order::{OrderRequest, OrderSide, OrderType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChasePolicy {
+    /// Seconds a price may rest before the next step
+    pub after_secs: u64,
+    /// Price increment per reprice
+    pub step: Decimal,
+    /// Highest price for buys, lowest for sells
+    pub max_price: Decimal,
+    /// Stop after this many reprices
+    #[serde(default)]
+    pub max_reprices: Option<u32>,
+}
+
+/// The order's chase policy, if it asks for one
+pub fn requested(request: &OrderRequest) -> Result<Option<ChasePolicy>, String> {
+    let Some(value) = request
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.get("chase"))
+        .filter(|v| !v.is_null())
+    else {
+        return Ok(None);
+    };
+
+    let policy: ChasePolicy = serde_json::from_value(value.clone())
+        .map_err(|e| format!("Invalid chase policy: {}", e))?;
+    if request.order_type != OrderType::Limit {
+        return Err("chase requires a limit order".to_string());
+    }
+    if policy.after_secs == 0 {
+        return Err("chase.after_secs must be positive".to_string());
+    }
+    if policy.step <= Decimal::ZERO {
+        return Err("chase.step must be positive".to_string());
+    }
+    // Checked against the limit when one is given up front; a limit set by
+    // pricing_mode is checked when the chase starts
+    if let Some(limit) = request.limit_price.and_then(decimal::from_f64) {
+        check_bound(request.side, limit, policy.max_price)?;
+    }
+    Ok(Some(policy))
+}
+
+fn check_bound(side: OrderSide, limit: Decimal, max_price: Decimal) -> Result<(), String> {
+    match side {
+        OrderSide::Buy if max_price < limit => {
+            Err("chase.max_price must be at or above the buy limit".to_string())
+        }
+        OrderSide::Sell if max_price > limit => {
+            Err("chase.max_price must be at or below the sell limit".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ChaseState {
+    /// The order that started the chain
+    pub original_order_id: String,
+    /// The order currently working
+    pub order_id: String,
+    pub side: OrderSide,
+    pub policy: ChasePolicy,
+    pub limit_price: Decimal,
+    pub reprices: u32,
+    /// When the current price was set
+    pub priced_at: DateTime<Utc>,
+}
+
+impl ChaseState {
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        now >= self.priced_at + Duration::seconds(self.policy.after_secs as i64)
+    }
+
+    /// The next price toward the market, or `None` once the policy is
+    /// exhausted
+    pub fn next_price(&self) -> Option<Decimal> {
+        if self
+            .policy
+            .max_reprices
+            .is_some_and(|max| self.reprices >= max)
+        {
+            return None;
+        }
+        let next = match self.side {
+            OrderSide::Buy => (self.limit_price + self.policy.step).min(self.policy.max_price),
+            OrderSide::Sell => (self.limit_price - self.policy.step).max(self.policy.max_price),
+        };
+        (next != self.limit_price).then_some(next)
+    }
+}
+
+/// Orders being chased, keyed by the currently working order ID
+#[derive(Default)]
+pub struct ChaseBook {
+    entries: HashMap<String, ChaseState>,
+}
+
+impl ChaseBook {
+    pub fn start(
+        &mut self,
+        order_id: &str,
+        request: &OrderRequest,
+        policy: ChasePolicy,
+        now: DateTime<Utc>,
+    ) -> Result<(), String> {
+        let limit = request
+            .limit_price
+            .and_then(decimal::from_f64)
+            .ok_or("chase requires a limit price")?;
+        check_bound(request.side, limit, policy.max_price)?;
+        self.entries.insert(
+            order_id.to_string(),
+            ChaseState {
+                original_order_id: order_id.to_string(),
+                order_id: order_id.to_string(),
+                side: request.side,
+                policy,
+                limit_price: limit,
+                reprices: 0,
+                priced_at: now,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn get(&self, order_id: &str) -> Option<&ChaseState> {
+        self.entries.get(order_id)
+    }
+
+    pub fn due(&self, now: DateTime<Utc>) -> Vec<String> {
+        let mut ids: Vec<String> = self
+            .entries
+            .values()
+            .filter(|c| c.is_due(now))
+            .map(|c| c.order_id.clone())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    pub fn remove(&mut self, order_id: &str) -> Option<ChaseState> {
+        self.entries.remove(order_id)
+    }
+
+    /// Follow a replacement to its new order ID
+    pub fn record_reprice(
+        &mut self,
+        old_id: &str,
+        new_id: &str,
+        price: Decimal,
+        now: DateTime<Utc>,
+    ) -> Option<&ChaseState> {
+        let mut state = self.entries.remove(old_id)?;
+        state.order_id = new_id.to_string();
+        state.limit_price = price;
+        state.reprices += 1;
+        state.priced_at = now;
+        self.entries.insert(new_id.to_string(), state);
+        self.entries.get(new_id)
+    }
+
+    pub fn list(&self) -> Vec<&ChaseState> {
+        let mut out: Vec<&ChaseState> = self.entries.values().collect();
+        out.sort_by(|a, b| a.original_order_id.cmp(&b.original_order_id));
+        out
+    }
+}
@@ -0,0 +1,200 @@
+//! Chunked responses for large payloads
+//!
+//! A host can add `"chunk_size": <bytes>` to any request envelope. When the
+//! response JSON is larger than that, the export answers with a small
+//! descriptor instead of the payload:
+//!
+//! ```json
+//! { "chunked": true, "cursor": 7, "total_bytes": 1843022, "chunks": 29 }
+//! ```
+//!
+//! The host then calls `next_chunk` with `{"cursor": 7}` until `done`,
+//! concatenating each `data` string, and parses the result as it would
+//! the unchunked response. `finish_chunked` drops a cursor early. Neither
+//! side ever has to hold the payload in a single host-boundary buffer.
+
+use serde::Deserialize;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Smallest chunk a host may ask for; tiny chunks only add call overhead
+pub const MIN_CHUNK_SIZE: usize = 1024;
+
+/// Cursors kept open at once; opening another drops the oldest
+const MAX_OPEN_CURSORS: usize = 8;
+
+/// A cursor not read from for this long is dropped by [`evict_idle`]
+pub const CURSOR_IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+thread_local! {
+    static REQUESTED: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(default)]
+    chunk_size: Option<usize>,
+}
+
+/// Note the envelope's `chunk_size` (if any) for this export call
+pub fn begin(request_bytes: &[u8]) {
+    let chunk_size = crate::wire::current()
+        .decode::<Envelope>(request_bytes)
+        .ok()
+        .and_then(|e| e.chunk_size)
+        .map(|size| size.max(MIN_CHUNK_SIZE));
+    REQUESTED.with(|r| r.set(chunk_size));
+}
+
+/// The chunk size requested for this call, clearing it so it cannot leak
+/// into the next one
+pub fn take_requested() -> Option<usize> {
+    REQUESTED.with(|r| r.take())
+}
+
+struct Cursor {
+    body: String,
+    /// Byte offset of the next chunk
+    offset: usize,
+    chunk_size: usize,
+    last_read: Instant,
+}
+
+#[derive(Default)]
+struct Cursors {
+    next_id: u64,
+    open: BTreeMap<u64, Cursor>,
+}
+
+lazy_static::lazy_static! {
+    static ref CURSORS: Mutex<Cursors> = Mutex::new(Cursors::default());
+}
+
+/// Keep `body` for chunked transfer and describe it for the host
+pub fn open(body: String, chunk_size: usize) -> serde_json::Value {
+    let mut cursors = CURSORS.lock().unwrap_or_else(|e| e.into_inner());
+    while cursors.open.len() >= MAX_OPEN_CURSORS {
+        cursors.open.pop_first();
+    }
+    cursors.next_id += 1;
+    let id = cursors.next_id;
+    let total_bytes = body.len();
+    cursors.open.insert(
+        id,
+        Cursor {
+            body,
+            offset: 0,
+            chunk_size,
+            last_read: Instant::now(),
+        },
+    );
+    serde_json::json!({
+        "success": true,
+        "chunked": true,
+        "cursor": id,
+        "total_bytes": total_bytes,
+        "chunks": total_bytes.div_ceil(chunk_size),
+    })
+}
+
+/// Next piece of a cursor's body. The cursor closes after its last chunk.
+pub fn next(id: u64) -> Result<serde_json::Value, String> {
+    let mut cursors = CURSORS.lock().unwrap_or_else(|e| e.into_inner());
+    let cursor = cursors
+        .open
+        .get_mut(&id)
+        .ok_or_else(|| format!("Unknown or finished cursor {}", id))?;
+
+    // Never split a UTF-8 sequence; a chunk is at least one character
+    let start = cursor.offset;
+    let mut end = (start + cursor.chunk_size).min(cursor.body.len());
+    while !cursor.body.is_char_boundary(end) {
+        end -= 1;
+    }
+    if end == start && start < cursor.body.len() {
+        end = start + 1;
+        while !cursor.body.is_char_boundary(end) {
+            end += 1;
+        }
+    }
+    let data = cursor.body[start..end].to_string();
+    cursor.offset = end;
+    cursor.last_read = Instant::now();
+    let done = end >= cursor.body.len();
+    if done {
+        cursors.open.remove(&id);
+    }
+    Ok(serde_json::json!({
+        "success": true,
+        "cursor": id,
+        "offset": start,
+        "data": data,
+        "done": done,
+    }))
+}
+
+/// Drop a cursor before its last chunk; `false` if it was not open
+pub fn finish(id: u64) -> bool {
+    let mut cursors = CURSORS.lock().unwrap_or_else(|e| e.into_inner());
+    cursors.open.remove(&id).is_some()
+}
+
+/// Drop cursors a host abandoned part way; returns how many
+pub fn evict_idle(now: Instant) -> usize {
+    let mut cursors = CURSORS.lock().unwrap_or_else(|e| e.into_inner());
+    let before = cursors.open.len();
+    cursors
+        .open
+        .retain(|_, c| now.duration_since(c.last_read) < CURSOR_IDLE_TIMEOUT);
+    before - cursors.open.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor_id(descriptor: &serde_json::Value) -> u64 {
+        descriptor["cursor"].as_u64().unwrap()
+    }
+
+    #[test]
+    fn chunks_reassemble_without_splitting_characters() {
+        let body = format!("{{\"note\":\"{}\"}}", "é€😀x".repeat(700));
+        let descriptor = open(body.clone(), MIN_CHUNK_SIZE);
+        let id = cursor_id(&descriptor);
+        assert_eq!(descriptor["total_bytes"], body.len());
+
+        let mut joined = String::new();
+        loop {
+            let chunk = next(id).unwrap();
+            let data = chunk["data"].as_str().unwrap();
+            assert!(data.len() <= MIN_CHUNK_SIZE);
+            joined.push_str(data);
+            if chunk["done"] == true {
+                break;
+            }
+        }
+        assert_eq!(joined, body);
+        assert!(next(id).is_err(), "cursor closes after the last chunk");
+    }
+
+    #[test]
+    fn finish_drops_a_cursor() {
+        let id = cursor_id(&open("x".repeat(5000), MIN_CHUNK_SIZE));
+        next(id).unwrap();
+        assert!(finish(id));
+        assert!(!finish(id));
+        assert!(next(id).is_err());
+    }
+
+    #[test]
+    fn requested_size_is_per_call() {
+        begin(br#"{"chunk_size": 10, "order_id": "x"}"#);
+        assert_eq!(take_requested(), Some(MIN_CHUNK_SIZE));
+        assert_eq!(take_requested(), None);
+        begin(br#"{"order_id": "x"}"#);
+        assert_eq!(take_requested(), None);
+    }
+}
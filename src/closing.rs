@@ -0,0 +1,219 @@
+//! Partial and full position closes for `close_position`
+//!
+//! A close is a market order on the opposite side of the position, sent
+//! through the normal order path so it gets the same checks, tracking,
+//! and events as any other order. The amount is either an absolute
+//! quantity or a percentage of the position. Alpaca only allows
+//! fractional quantities in fractionable assets, and never on the short
+//! side, so a percentage that lands on a fraction is rounded down to whole
+//! shares in those cases.
+
+use crate::alpaca::AlpacaAsset;
+use crate::decimal::{self, Decimal};
+use models::order::{OrderRequest, OrderSide, OrderType};
+use models::portfolio::Position;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CloseAmount {
+    All,
+    Qty(Decimal),
+    /// Percent of the position, above 0 and at most 100
+    Percent(Decimal),
+}
+
+impl CloseAmount {
+    pub fn from_request(qty: Option<f64>, percentage: Option<f64>) -> Result<Self, String> {
+        match (qty, percentage) {
+            (Some(_), Some(_)) => Err("Specify qty or percentage, not both".to_string()),
+            (Some(qty), None) => decimal::from_f64(qty)
+                .filter(|q| *q > Decimal::ZERO)
+                .map(CloseAmount::Qty)
+                .ok_or_else(|| "qty must be a positive number".to_string()),
+            (None, Some(pct)) => decimal::from_f64(pct)
+                .filter(|p| *p > Decimal::ZERO && *p <= Decimal::ONE_HUNDRED)
+                .map(|p| {
+                    if p == Decimal::ONE_HUNDRED {
+                        CloseAmount::All
+                    } else {
+                        CloseAmount::Percent(p)
+                    }
+                })
+                .ok_or_else(|| "percentage must be above 0 and at most 100".to_string()),
+            (None, None) => Ok(CloseAmount::All),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ClosePlan {
+    pub symbol: String,
+    pub side: OrderSide,
+    /// Signed position quantity before the close
+    pub position_qty: Decimal,
+    pub qty: Decimal,
+    /// Requested percentage, when the close was given as one
+    pub percentage: Option<Decimal>,
+    /// The percentage was rounded down to whole shares
+    pub rounded: bool,
+}
+
+impl ClosePlan {
+    /// Work out the closing quantity for `position`. `asset` decides
+    /// whether fractions are allowed; an unknown asset allows none.
+    pub fn new(
+        position: &Position,
+        amount: CloseAmount,
+        asset: Option<&AlpacaAsset>,
+    ) -> Result<Self, String> {
+        let position_qty = decimal::from_f64(position.quantity).unwrap_or_default();
+        let held = position_qty.abs();
+        if held.is_zero() {
+            return Err(format!("No open position in {}", position.symbol_id));
+        }
+        let short = position_qty < Decimal::ZERO;
+        let fractional_ok = !short && asset.is_some_and(|a| a.fractionable);
+
+        let mut rounded = false;
+        let (qty, percentage) = match amount {
+            CloseAmount::All => (held, None),
+            CloseAmount::Qty(qty) => {
+                if qty > held {
+                    return Err(format!(
+                        "qty {} exceeds the {} position of {}",
+                        decimal::to_wire(qty),
+                        position.symbol_id,
+                        decimal::to_wire(held)
+                    ));
+                }
+                if !qty.fract().is_zero() && !fractional_ok {
+                    return Err(format!(
+                        "{} cannot be closed in fractions{}",
+                        position.symbol_id,
+                        if short { " on the short side" } else { "" }
+                    ));
+                }
+                (qty, None)
+            }
+            CloseAmount::Percent(pct) => {
+                let exact = held * pct / Decimal::ONE_HUNDRED;
+                let qty = if fractional_ok {
+                    exact.round_dp(9)
+                } else {
+                    let whole = exact.floor();
+                    rounded = whole != exact;
+                    whole
+                };
+                if qty.is_zero() {
+                    return Err(format!(
+                        "{}% of the {} position is less than one share",
+                        decimal::to_wire(pct),
+                        position.symbol_id
+                    ));
+                }
+                (qty, Some(pct))
+            }
+        };
+
+        Ok(Self {
+            symbol: position.symbol_id.clone(),
+            side: if short {
+                OrderSide::Buy
+            } else {
+                OrderSide::Sell
+            },
+            position_qty,
+            qty,
+            percentage,
+            rounded,
+        })
+    }
+
+    pub fn to_request(&self, persona_id: &str, dry_run: bool) -> Result<OrderRequest, String> {
+        serde_json::from_value(serde_json::json!({
+            "symbol_id": self.symbol,
+            "quantity": decimal::to_f64(self.qty),
+            "side": self.side,
+            "order_type": OrderType::Market,
+            "limit_price": null,
+            "stop_price": null,
+            "persona_id": persona_id,
+            "extensions": { "dry_run": dry_run, "closes_position": self.link() },
+        }))
+        .map_err(|e| e.to_string())
+    }
+
+    /// `extensions.closes_position` on the closing order
+    pub fn link(&self) -> serde_json::Value {
+        let remaining = self.position_qty.abs() - self.qty;
+        serde_json::json!({
+            "symbol": self.symbol,
+            "position_qty": decimal::to_wire(self.position_qty),
+            "close_qty": decimal::to_wire(self.qty),
+            "percentage": self.percentage.map(decimal::to_wire),
+            "rounded_to_whole_shares": self.rounded,
+            "remaining_qty": decimal::to_wire(remaining),
+            "full_close": remaining.is_zero(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(qty: f64) -> Position {
+        Position {
+            symbol_id: "XYZ".to_string(),
+            quantity: qty,
+            average_price: 10.0,
+            current_price: 11.0,
+            unrealized_pnl: 0.0,
+            unrealized_pnl_percent: 0.0,
+        }
+    }
+
+    fn asset(fractionable: bool) -> AlpacaAsset {
+        serde_json::from_value(serde_json::json!({
+            "symbol": "XYZ",
+            "status": "active",
+            "tradable": true,
+            "fractionable": fractionable,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn percentages_round_down_unless_fractions_are_allowed() {
+        let pct = CloseAmount::from_request(None, Some(25.0)).unwrap();
+        let plan = ClosePlan::new(&position(10.0), pct, Some(&asset(false))).unwrap();
+        assert_eq!(plan.qty, Decimal::from(2));
+        assert!(plan.rounded);
+        assert_eq!(plan.side, OrderSide::Sell);
+        assert_eq!(plan.link()["remaining_qty"], "8");
+
+        let plan = ClosePlan::new(&position(10.0), pct, Some(&asset(true))).unwrap();
+        assert_eq!(decimal::to_wire(plan.qty), "2.5");
+
+        // Shorts never close in fractions
+        let short = ClosePlan::new(&position(-10.0), pct, Some(&asset(true))).unwrap();
+        assert_eq!(short.side, OrderSide::Buy);
+        assert_eq!(short.qty, Decimal::from(2));
+        assert!(ClosePlan::new(&position(3.0), pct, None).is_err());
+    }
+
+    #[test]
+    fn quantities_are_checked_against_the_position() {
+        let qty = |q| CloseAmount::from_request(Some(q), None).unwrap();
+        assert!(ClosePlan::new(&position(10.0), qty(11.0), None).is_err());
+        assert!(ClosePlan::new(&position(10.0), qty(1.5), Some(&asset(false))).is_err());
+        let plan = ClosePlan::new(&position(10.0), qty(10.0), None).unwrap();
+        assert_eq!(plan.link()["full_close"], true);
+
+        assert!(CloseAmount::from_request(Some(1.0), Some(50.0)).is_err());
+        assert!(CloseAmount::from_request(None, Some(150.0)).is_err());
+        assert_eq!(
+            CloseAmount::from_request(None, Some(100.0)).unwrap(),
+            CloseAmount::All
+        );
+    }
+}
@@ -0,0 +1,277 @@
+//! Conditional orders
+//!
+//! Locally held order templates that are submitted when a price condition
+//! is met. Conditions are evaluated against market data snapshots on each
+//! `tick`. A triggered order goes through the same checks as
+//! `submit_order`.
+
+use crate::decimal::Decimal;
+use crate::market_data::{PriceSource, Snapshot};
+use chrono::{DateTime, Utc};
+use models::order::{Order, OrderRequest, OrderStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    /// Price moves from below the level to at or above it
+    CrossesAbove,
+    /// Price moves from above the level to at or below it
+    CrossesBelow,
+    /// Price is at or above the level (fires on the first observation)
+    AtOrAbove,
+    /// Price is at or below the level (fires on the first observation)
+    AtOrBelow,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Condition {
+    pub symbol: String,
+    pub trigger: Trigger,
+    pub price: Decimal,
+    #[serde(default)]
+    pub price_source: PriceSource,
+}
+
+impl Condition {
+    fn validate(&self) -> Result<(), String> {
+        if self.symbol.trim().is_empty() {
+            return Err("condition.symbol is required".to_string());
+        }
+        if self.price <= Decimal::ZERO {
+            return Err("condition.price must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether moving from `previous` to `current` satisfies the condition.
+    /// Crossing triggers need a previous observation.
+    pub fn is_met(&self, previous: Option<Decimal>, current: Decimal) -> bool {
+        match self.trigger {
+            Trigger::AtOrAbove => current >= self.price,
+            Trigger::AtOrBelow => current <= self.price,
+            Trigger::CrossesAbove => {
+                previous.is_some_and(|p| p < self.price) && current >= self.price
+            }
+            Trigger::CrossesBelow => {
+                previous.is_some_and(|p| p > self.price) && current <= self.price
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConditionalStatus {
+    Pending,
+    /// Condition met and the order was accepted
+    Triggered,
+    /// Condition met but the order was rejected
+    Failed,
+    Canceled,
+    Expired,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ConditionalOrder {
+    pub id: String,
+    pub condition: Condition,
+    pub order: OrderRequest,
+    pub status: ConditionalStatus,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_at: Option<DateTime<Utc>>,
+    /// Most recent observed price
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_price: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub triggered_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trigger_price: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Something that happened to a conditional order during a tick
+#[derive(Clone, Debug, Serialize)]
+pub struct ConditionalEvent {
+    pub conditional_id: String,
+    pub status: ConditionalStatus,
+    pub symbol: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trigger_price: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<Order>,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+pub struct ConditionalBook {
+    orders: Vec<ConditionalOrder>,
+    next_id: u64,
+}
+
+impl ConditionalBook {
+    pub fn add(
+        &mut self,
+        condition: Condition,
+        order: OrderRequest,
+        expire_at: Option<DateTime<Utc>>,
+    ) -> Result<&ConditionalOrder, String> {
+        condition.validate()?;
+        // The order may trade a different symbol than the one watched
+        if order.symbol_id.trim().is_empty() {
+            return Err("order.symbol_id is required".to_string());
+        }
+        if order.quantity <= 0.0 {
+            return Err("order.quantity must be positive".to_string());
+        }
+        if expire_at.is_some_and(|e| e <= Utc::now()) {
+            return Err("expire_at is in the past".to_string());
+        }
+
+        self.next_id += 1;
+        let now = Utc::now();
+        self.orders.push(ConditionalOrder {
+            id: format!("cond_{}_{}", now.timestamp_millis(), self.next_id),
+            condition,
+            order,
+            status: ConditionalStatus::Pending,
+            created_at: now,
+            expire_at,
+            last_price: None,
+            triggered_at: None,
+            trigger_price: None,
+            order_id: None,
+            error: None,
+        });
+        Ok(self.orders.last().expect("just pushed"))
+    }
+
+    pub fn list(&self) -> &[ConditionalOrder] {
+        &self.orders
+    }
+
+    pub fn cancel(&mut self, id: &str) -> Result<&ConditionalOrder, String> {
+        let entry = self
+            .orders
+            .iter_mut()
+            .find(|c| c.id == id)
+            .ok_or_else(|| format!("Unknown conditional order: {}", id))?;
+        if entry.status != ConditionalStatus::Pending {
+            return Err(format!("Conditional order {} is no longer pending", id));
+        }
+        entry.status = ConditionalStatus::Canceled;
+        Ok(entry)
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.orders
+            .iter()
+            .any(|c| c.status == ConditionalStatus::Pending)
+    }
+
+    /// Point pending entries watching or trading `old` at `new`
+    pub fn rename_symbol(&mut self, old: &str, new: &str) -> usize {
+        let mut renamed = 0;
+        for entry in self
+            .orders
+            .iter_mut()
+            .filter(|c| c.status == ConditionalStatus::Pending)
+        {
+            let watched = entry.condition.symbol.eq_ignore_ascii_case(old);
+            if watched {
+                entry.condition.symbol = new.to_string();
+            }
+            if crate::orders::rename_symbol(&mut entry.order, old, new) || watched {
+                renamed += 1;
+            }
+        }
+        renamed
+    }
+
+    /// Symbols with pending conditions, for the snapshot request
+    pub fn pending_symbols(&self) -> Vec<String> {
+        self.orders
+            .iter()
+            .filter(|c| c.status == ConditionalStatus::Pending)
+            .map(|c| c.condition.symbol.to_ascii_uppercase())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Expire overdue entries and find the ones whose condition is met.
+    /// Returns the expiry events and the (id, order) pairs to submit; the
+    /// caller reports each submission back through `record_submission`.
+    pub fn evaluate(
+        &mut self,
+        snapshots: &HashMap<String, Snapshot>,
+        now: DateTime<Utc>,
+    ) -> (Vec<ConditionalEvent>, Vec<(String, OrderRequest)>) {
+        let mut events = Vec::new();
+        let mut triggered = Vec::new();
+
+        for entry in self
+            .orders
+            .iter_mut()
+            .filter(|c| c.status == ConditionalStatus::Pending)
+        {
+            if entry.expire_at.is_some_and(|e| e <= now) {
+                entry.status = ConditionalStatus::Expired;
+                events.push(ConditionalEvent {
+                    conditional_id: entry.id.clone(),
+                    status: entry.status,
+                    symbol: entry.condition.symbol.clone(),
+                    trigger_price: None,
+                    order: None,
+                    at: now,
+                });
+                continue;
+            }
+
+            let symbol = entry.condition.symbol.to_ascii_uppercase();
+            let Some(price) = snapshots
+                .get(&symbol)
+                .and_then(|s| s.price(entry.condition.price_source))
+            else {
+                continue;
+            };
+            let previous = entry.last_price.replace(price);
+            if entry.condition.is_met(previous, price) {
+                entry.triggered_at = Some(now);
+                entry.trigger_price = Some(price);
+                triggered.push((entry.id.clone(), entry.order.clone()));
+            }
+        }
+        (events, triggered)
+    }
+
+    /// Record the order placed for a triggered entry
+    pub fn record_submission(&mut self, id: &str, order: &Order) -> Option<ConditionalEvent> {
+        let entry = self.orders.iter_mut().find(|c| c.id == id)?;
+        if order.status == OrderStatus::Rejected {
+            entry.status = ConditionalStatus::Failed;
+            entry.error = order
+                .extensions
+                .as_ref()
+                .and_then(|ext| ext.get("error"))
+                .and_then(|v| v.as_str())
+                .map(str::to_string);
+        } else {
+            entry.status = ConditionalStatus::Triggered;
+            entry.order_id = Some(order.id.clone());
+        }
+        Some(ConditionalEvent {
+            conditional_id: entry.id.clone(),
+            status: entry.status,
+            symbol: entry.condition.symbol.clone(),
+            trigger_price: entry.trigger_price,
+            order: Some(order.clone()),
+            at: entry.triggered_at.unwrap_or_else(Utc::now),
+        })
+    }
+}
@@ -0,0 +1,792 @@
+//! Typed initialize configuration
+//!
+//! [`Config::parse`] reads every field of the initialize JSON on its own,
+//! so one mistyped value does not hide the rest, and collects every
+//! problem (wrong type, unknown option name, out-of-range number,
+//! conflicting options) before any state is touched. `initialize` returns
+//! them all as a field-by-field list. Keys the plugin does not read are
+//! reported as warnings, since a misspelled option would otherwise fall
+//! back to its default without a word.
+
+use crate::alerts::AlertRules;
+use crate::alpaca::{self, ClientOptions};
+use crate::benchmark;
+use crate::broker_api::BrokerApiConfig;
+use crate::decimal::{self, Decimal};
+use crate::delisting::DelistedMarks;
+use crate::fees::FeeSchedule;
+use crate::history::PositionHistoryConfig;
+use crate::interlock;
+use crate::kill_switch::AutoTrip;
+use crate::logging::Level;
+use crate::market_hours::ClosedMarketPolicy;
+use crate::marks::MarkSource;
+use crate::middleware::{BreakerSettings, ResponseCache};
+use crate::order_size::SizeLimits;
+use crate::paging;
+use crate::pnl::LotMethod;
+use crate::pretrade::CheckMode;
+use crate::protective::AdjustPolicy;
+use crate::raw::RawPolicy;
+use crate::recording::RecordingConfig;
+use crate::risk::RiskLimits;
+use crate::secrets;
+use crate::shadow::ShadowMode;
+use crate::simulator::SimConfig;
+use crate::stale::StaleOrderRules;
+use crate::throttle::ThrottleLimits;
+use crate::validation;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+/// Market data feeds Alpaca accepts in `feed`
+const DATA_FEEDS: &[&str] = &["iex", "sip", "delayed_sip", "boats", "overnight", "otc"];
+
+const MAX_DEDUPE_WINDOW_SECS: u64 = 86_400;
+const MAX_PAGE_PREFETCH: u64 = 16;
+const MAX_BASKET_CONCURRENCY: u64 = 16;
+const MAX_POSITIONS_CACHE_MS: u64 = 60_000;
+
+/// One thing wrong with the config
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct Problem {
+    pub field: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// A key pair given inline or as secret store references, for the second,
+/// live environment (see [`crate::environment`]) or the Broker API
+pub struct Credentials {
+    pub api_key: Option<String>,
+    pub api_secret: Option<String>,
+    pub api_key_ref: Option<String>,
+    pub api_secret_ref: Option<String>,
+}
+
+pub struct Config {
+    pub api_key: Option<String>,
+    pub api_secret: Option<String>,
+    /// Host secret store references for the credentials, see [`crate::secrets`]
+    pub api_key_ref: Option<String>,
+    pub api_secret_ref: Option<String>,
+    pub live_credentials: Option<Credentials>,
+    pub broker_api: Option<BrokerApiConfig>,
+    pub shadow_mode: ShadowMode,
+    pub is_paper: bool,
+    pub encodings: Vec<String>,
+    pub log_level: Option<Level>,
+    pub pdt_mode: CheckMode,
+    pub buying_power_mode: CheckMode,
+    pub asset_mode: CheckMode,
+    pub short_mode: CheckMode,
+    pub wash_sale_mode: CheckMode,
+    pub gfv_mode: CheckMode,
+    /// Safety margin added to estimated order cost, in percent
+    pub cost_buffer_pct: Decimal,
+    /// Fat-finger band around `reference_price`, in percent; `None` is off
+    pub price_band_pct: Option<Decimal>,
+    /// `None` when fee estimates are turned off
+    pub fees: Option<FeeSchedule>,
+    pub raw_policy: RawPolicy,
+    pub risk_limits: RiskLimits,
+    pub order_size_limits: SizeLimits,
+    pub protective_adjust: AdjustPolicy,
+    /// Follow ticker changes in cached state
+    pub symbol_changes: bool,
+    /// Fill in a missing `reference_price` from a snapshot at submission
+    pub reference_prices: bool,
+    /// Basket orders sent at once; 0 and 1 send them one at a time
+    pub basket_concurrency: usize,
+    pub order_throttle: ThrottleLimits,
+    pub kill_switch: AutoTrip,
+    pub stale_orders: StaleOrderRules,
+    pub position_history: Option<PositionHistoryConfig>,
+    /// Symbol `get_portfolio_history` compares against
+    pub benchmark_symbol: String,
+    /// `live_trading_ack` holds [`interlock::ACK`]
+    pub live_trading_ack: bool,
+    pub live_max_notional_per_day: Option<Decimal>,
+    pub alerts: AlertRules,
+    pub dedupe_window_secs: u64,
+    pub closed_market_policy: ClosedMarketPolicy,
+    pub lot_method: LotMethod,
+    pub simulation: Option<SimConfig>,
+    pub recording: Option<RecordingConfig>,
+    /// Everything but the simulator and recorder, which `initialize`
+    /// attaches
+    pub client: ClientOptions,
+    /// Keys that are not options of this plugin
+    pub unknown_fields: Vec<String>,
+}
+
+impl Config {
+    pub fn parse(value: &serde_json::Value) -> Result<Self, Vec<Problem>> {
+        let empty = serde_json::Map::new();
+        let fields = match value {
+            serde_json::Value::Object(fields) => fields,
+            serde_json::Value::Null => &empty,
+            _ => {
+                return Err(vec![Problem {
+                    field: String::new(),
+                    message: "configuration must be an object".to_string(),
+                }])
+            }
+        };
+        let mut r = Reader {
+            fields,
+            seen: BTreeSet::new(),
+            problems: Vec::new(),
+        };
+
+        let api_key = r.get::<String>("api_key", "a string");
+        let api_secret = r.get::<String>("api_secret", "a string");
+        let api_key_ref = r.get::<String>("api_key_ref", "a string");
+        let api_secret_ref = r.get::<String>("api_secret_ref", "a string");
+        for (field, inline, reference) in [
+            ("api_key_ref", &api_key, &api_key_ref),
+            ("api_secret_ref", &api_secret, &api_secret_ref),
+        ] {
+            if reference.is_none() {
+                continue;
+            }
+            if inline.is_some() {
+                r.fail(
+                    field,
+                    "set either the credential or its reference, not both",
+                );
+            } else if !secrets::AVAILABLE {
+                r.fail(
+                    field,
+                    "this build has no host secret store (host-secrets feature)",
+                );
+            }
+        }
+        if api_key_ref.is_some() != api_secret_ref.is_some() {
+            r.fail(
+                "api_key_ref",
+                "api_key_ref and api_secret_ref must be set together",
+            );
+        }
+        let is_paper = r.get("is_paper", "true or false").unwrap_or(true);
+        let live_credentials = r.live_credentials();
+        let broker_api = r.broker_api();
+        let shadow_mode = r
+            .parsed(
+                "shadow_orders",
+                ShadowMode::parse,
+                "off, live_to_paper, or both",
+            )
+            .unwrap_or_default();
+        let encodings = r.get("encodings", "a list of strings").unwrap_or_default();
+        let log_level = r.parsed(
+            "log_level",
+            Level::parse,
+            "trace, debug, info, warn, or error",
+        );
+
+        let pdt_mode = r
+            .parsed("pdt_policy", CheckMode::parse, "block, warn, or allow")
+            .unwrap_or_default();
+        let buying_power_mode = r.mode("pretrade_checks").unwrap_or(CheckMode::Off);
+        let asset_mode = r.mode("asset_checks").unwrap_or(CheckMode::Enforce);
+        let short_mode = r.mode("htb_policy").unwrap_or_default();
+        let wash_sale_mode = r.mode("wash_sale_policy").unwrap_or(CheckMode::Off);
+        let gfv_mode = r.mode("gfv_policy").unwrap_or_default();
+
+        let cost_buffer_pct = match r.get::<f64>("cost_buffer_pct", "a number") {
+            Some(pct) => match decimal::from_f64(pct)
+                .filter(|p| *p >= Decimal::ZERO && *p <= Decimal::ONE_HUNDRED)
+            {
+                Some(pct) => pct,
+                None => {
+                    r.fail("cost_buffer_pct", "must be between 0 and 100");
+                    Decimal::ONE
+                }
+            },
+            None => Decimal::ONE,
+        };
+
+        let price_band_pct = match r.get::<f64>("price_band_pct", "a number") {
+            Some(pct) => match decimal::from_f64(pct).filter(|p| *p >= Decimal::ZERO) {
+                Some(pct) => (!pct.is_zero()).then_some(pct),
+                None => {
+                    r.fail("price_band_pct", "must not be negative");
+                    None
+                }
+            },
+            None => Some(Decimal::from(validation::DEFAULT_PRICE_BAND_PCT)),
+        };
+
+        let fees = r.section("fees", FeeSchedule::from_config).flatten();
+        let raw_policy = r
+            .section("raw_requests", RawPolicy::from_config)
+            .unwrap_or_default();
+        let risk_limits = r
+            .section("risk_limits", RiskLimits::from_config)
+            .unwrap_or_default();
+        let order_size_limits = r
+            .section("order_size_limits", SizeLimits::from_config)
+            .unwrap_or_default();
+        let protective_adjust = r
+            .section("protective_adjust", AdjustPolicy::from_config)
+            .unwrap_or_default();
+        let symbol_changes = r
+            .get::<bool>("symbol_changes", "true or false")
+            .unwrap_or(true);
+        let reference_prices = r
+            .get::<bool>("reference_prices", "true or false")
+            .unwrap_or(false);
+        let basket_concurrency = r
+            .bounded("basket_concurrency", MAX_BASKET_CONCURRENCY)
+            .map_or(crate::DEFAULT_BASKET_CONCURRENCY, |n| n as usize);
+        let order_throttle = r
+            .section("order_throttle", ThrottleLimits::from_config)
+            .unwrap_or_default();
+        let kill_switch = r
+            .section("kill_switch", AutoTrip::from_config)
+            .unwrap_or_default();
+        let alerts = r
+            .section("alerts", AlertRules::from_config)
+            .unwrap_or_default();
+        let stale_orders = r
+            .section("stale_orders", StaleOrderRules::from_config)
+            .unwrap_or_default();
+        let benchmark_symbol = match r.get::<String>("benchmark_symbol", "a symbol") {
+            Some(symbol) if symbol.trim().is_empty() => {
+                r.fail("benchmark_symbol", "expected a symbol");
+                None
+            }
+            symbol => symbol,
+        }
+        .map_or_else(
+            || benchmark::DEFAULT_BENCHMARK.to_string(),
+            |s| s.trim().to_ascii_uppercase(),
+        );
+        let position_history = r
+            .section("position_history", PositionHistoryConfig::from_config)
+            .flatten();
+        let simulation = r.section("simulation", SimConfig::from_config).flatten();
+        let mock = r.section("mock", SimConfig::mock_from_config).flatten();
+        if mock.is_some() && simulation.is_some() {
+            r.fail("mock", "cannot be combined with simulation");
+        }
+        // Mock mode is the simulator with a canned book
+        let simulation = simulation.or(mock);
+        let recording = r
+            .section("recording", RecordingConfig::from_config)
+            .flatten();
+        if recording.is_some() && simulation.is_some() {
+            r.fail("recording", "cannot be combined with simulation or mock");
+        }
+
+        let live_trading_ack = match r.get::<String>("live_trading_ack", "a string") {
+            Some(ack) if ack == interlock::ACK => true,
+            Some(_) => {
+                r.fail(
+                    "live_trading_ack",
+                    format!("must be exactly \"{}\"", interlock::ACK),
+                );
+                false
+            }
+            None => false,
+        };
+        let live_max_notional_per_day = match r.get::<f64>("live_max_notional_per_day", "a number")
+        {
+            Some(max) => {
+                let max = decimal::from_f64(max).filter(|m| *m >= Decimal::ZERO);
+                if max.is_none() {
+                    r.fail("live_max_notional_per_day", "must be a non-negative number");
+                }
+                max
+            }
+            None => None,
+        };
+
+        let dedupe_window_secs = r
+            .bounded("dedupe_window_secs", MAX_DEDUPE_WINDOW_SECS)
+            .unwrap_or(0);
+        let closed_market_policy = r
+            .parsed(
+                "market_closed_policy",
+                ClosedMarketPolicy::parse,
+                "submit, queue, opg, or extended_hours",
+            )
+            .unwrap_or_default();
+        let lot_method = r
+            .parsed(
+                "lot_method",
+                LotMethod::parse,
+                "fifo, lifo, or highest_cost",
+            )
+            .unwrap_or_default();
+
+        let client = r.client_options();
+
+        if simulation.is_some() && !is_paper {
+            r.fail(
+                "is_paper",
+                "simulation always runs as a paper account; remove is_paper: false or simulation",
+            );
+        }
+        if shadow_mode != ShadowMode::Off && live_credentials.is_none() {
+            r.fail(
+                "shadow_orders",
+                "needs live_credentials to copy orders between paper and live",
+            );
+        }
+        if live_credentials.is_some() {
+            if !is_paper {
+                r.fail(
+                    "live_credentials",
+                    "the top-level credentials are the paper ones; remove is_paper: false",
+                );
+            }
+            if simulation.is_some() {
+                r.fail("live_credentials", "cannot be combined with simulation");
+            }
+            if client.api_base_url.is_some() {
+                r.fail(
+                    "live_credentials",
+                    "cannot be combined with api_base_url, which would send both environments to one host",
+                );
+            }
+        }
+
+        if broker_api.is_some() && simulation.is_some() {
+            r.fail("broker_api", "cannot be combined with simulation");
+        }
+
+        let unknown_fields = fields
+            .keys()
+            .filter(|key| !r.seen.contains(key.as_str()))
+            .cloned()
+            .collect();
+
+        if !r.problems.is_empty() {
+            return Err(r.problems);
+        }
+        Ok(Self {
+            api_key,
+            api_secret,
+            api_key_ref,
+            api_secret_ref,
+            live_credentials,
+            broker_api,
+            shadow_mode,
+            is_paper,
+            encodings,
+            log_level,
+            pdt_mode,
+            buying_power_mode,
+            asset_mode,
+            short_mode,
+            wash_sale_mode,
+            gfv_mode,
+            cost_buffer_pct,
+            price_band_pct,
+            fees,
+            raw_policy,
+            risk_limits,
+            order_size_limits,
+            protective_adjust,
+            symbol_changes,
+            reference_prices,
+            basket_concurrency,
+            order_throttle,
+            kill_switch,
+            stale_orders,
+            position_history,
+            benchmark_symbol,
+            live_trading_ack,
+            live_max_notional_per_day,
+            alerts,
+            dedupe_window_secs,
+            closed_market_policy,
+            lot_method,
+            simulation,
+            recording,
+            client,
+            unknown_fields,
+        })
+    }
+}
+
+/// Reads fields one at a time, noting which were read and what was wrong
+struct Reader<'a> {
+    fields: &'a serde_json::Map<String, serde_json::Value>,
+    seen: BTreeSet<&'static str>,
+    problems: Vec<Problem>,
+}
+
+impl Reader<'_> {
+    fn fail(&mut self, field: &str, message: impl Into<String>) {
+        self.problems.push(Problem {
+            field: field.to_string(),
+            message: message.into(),
+        });
+    }
+
+    /// The field's value; `null` counts as absent
+    fn value(&mut self, field: &'static str) -> Option<&serde_json::Value> {
+        self.seen.insert(field);
+        self.fields.get(field).filter(|v| !v.is_null())
+    }
+
+    fn get<T: DeserializeOwned>(&mut self, field: &'static str, expected: &str) -> Option<T> {
+        let value = self.value(field)?.clone();
+        match serde_json::from_value(value) {
+            Ok(parsed) => Some(parsed),
+            Err(_) => {
+                self.fail(field, format!("expected {}", expected));
+                None
+            }
+        }
+    }
+
+    /// A string option with a fixed set of values
+    fn parsed<T>(
+        &mut self,
+        field: &'static str,
+        parse: fn(&str) -> Option<T>,
+        expected: &str,
+    ) -> Option<T> {
+        let raw = self.get::<String>(field, &format!("one of {}", expected))?;
+        let parsed = parse(&raw);
+        if parsed.is_none() {
+            self.fail(
+                field,
+                format!("unknown value {:?}; expected {}", raw, expected),
+            );
+        }
+        parsed
+    }
+
+    fn mode(&mut self, field: &'static str) -> Option<CheckMode> {
+        self.parsed(field, CheckMode::parse, "off, warn, or enforce")
+    }
+
+    /// A whole number from 0 to `max`
+    fn bounded(&mut self, field: &'static str, max: u64) -> Option<u64> {
+        let n = self.get::<u64>(field, "a whole number")?;
+        if n > max {
+            self.fail(field, format!("must be at most {}", max));
+            return None;
+        }
+        Some(n)
+    }
+
+    /// A nested section with its own parser, which sees `null` as absent
+    fn section<T>(
+        &mut self,
+        field: &'static str,
+        parse: fn(Option<&serde_json::Value>) -> Result<T, String>,
+    ) -> Option<T> {
+        let value = self.value(field).cloned();
+        match parse(value.as_ref()) {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                self.fail(field, e);
+                None
+            }
+        }
+    }
+
+    /// The `live_credentials` object: inline keys or secret store
+    /// references, like the top-level ones
+    fn live_credentials(&mut self) -> Option<Credentials> {
+        let value = self.value("live_credentials")?.clone();
+        let Some(object) = value.as_object() else {
+            self.fail("live_credentials", "expected an object");
+            return None;
+        };
+        self.credentials("live_credentials", object)
+    }
+
+    /// The `broker_api` object: Broker API credentials, like
+    /// `live_credentials`, plus the sub-account to act for
+    fn broker_api(&mut self) -> Option<BrokerApiConfig> {
+        let value = self.value("broker_api")?.clone();
+        let Some(object) = value.as_object() else {
+            self.fail("broker_api", "expected an object");
+            return None;
+        };
+        let account_id = match object.get("account_id") {
+            Some(serde_json::Value::String(id)) if !id.is_empty() => Some(id.clone()),
+            _ => {
+                self.fail("broker_api.account_id", "expected a non-empty string");
+                None
+            }
+        };
+        let base_url = match object.get("base_url").filter(|v| !v.is_null()) {
+            None => None,
+            Some(serde_json::Value::String(url)) => {
+                let allow_insecure = self
+                    .fields
+                    .get("allow_insecure_urls")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                match alpaca::validate_base_url("broker_api.base_url", url, allow_insecure) {
+                    Ok(url) => Some(url),
+                    Err(e) => {
+                        self.fail("broker_api.base_url", e);
+                        return None;
+                    }
+                }
+            }
+            Some(_) => {
+                self.fail("broker_api.base_url", "expected a string");
+                return None;
+            }
+        };
+        let credentials = self.credentials("broker_api", object)?;
+        Some(BrokerApiConfig {
+            credentials,
+            account_id: account_id?,
+            base_url,
+        })
+    }
+
+    /// Inline keys or secret store references inside the object at `field`
+    fn credentials(
+        &mut self,
+        field: &str,
+        object: &serde_json::Map<String, serde_json::Value>,
+    ) -> Option<Credentials> {
+        let mut value = |name: &str| match object.get(name).filter(|v| !v.is_null()) {
+            None => None,
+            Some(serde_json::Value::String(s)) if !s.is_empty() => Some(s.clone()),
+            Some(_) => {
+                self.fail(
+                    &format!("{}.{}", field, name),
+                    "expected a non-empty string",
+                );
+                None
+            }
+        };
+        let credentials = Credentials {
+            api_key: value("api_key"),
+            api_secret: value("api_secret"),
+            api_key_ref: value("api_key_ref"),
+            api_secret_ref: value("api_secret_ref"),
+        };
+        let inline = credentials.api_key.is_some() && credentials.api_secret.is_some();
+        let by_ref = credentials.api_key_ref.is_some() && credentials.api_secret_ref.is_some();
+        if inline == by_ref {
+            self.fail(
+                field,
+                "set api_key and api_secret, or api_key_ref and api_secret_ref",
+            );
+            return None;
+        }
+        if by_ref && !secrets::AVAILABLE {
+            self.fail(
+                field,
+                "this build has no host secret store (host-secrets feature)",
+            );
+            return None;
+        }
+        Some(credentials)
+    }
+
+    fn client_options(&mut self) -> ClientOptions {
+        let defaults = ClientOptions::default();
+
+        let data_feed = self.get::<String>("data_feed", "a string");
+        if let Some(feed) = &data_feed {
+            if !DATA_FEEDS.contains(&feed.as_str()) {
+                self.fail(
+                    "data_feed",
+                    format!(
+                        "unknown feed {:?}; expected one of {}",
+                        feed,
+                        DATA_FEEDS.join(", ")
+                    ),
+                );
+            }
+        }
+
+        let response_cache = match self.value("response_cache").cloned() {
+            None | Some(serde_json::Value::Bool(true)) => {
+                Some(ResponseCache::ttls_with_overrides(&[]))
+            }
+            Some(serde_json::Value::Bool(false)) => None,
+            Some(serde_json::Value::Object(ttls)) => {
+                let mut overrides = Vec::with_capacity(ttls.len());
+                for (path, secs) in ttls {
+                    match secs.as_u64() {
+                        Some(secs) => overrides.push((path, secs)),
+                        None => self.fail(
+                            "response_cache",
+                            format!("TTL for {} must be a whole number of seconds", path),
+                        ),
+                    }
+                }
+                Some(ResponseCache::ttls_with_overrides(&overrides))
+            }
+            Some(_) => {
+                self.fail(
+                    "response_cache",
+                    "expected false or an object of path to seconds",
+                );
+                None
+            }
+        };
+
+        // Only https, unless a local mock needs plain http
+        let allow_insecure = self
+            .get("allow_insecure_urls", "true or false")
+            .unwrap_or(false);
+        let mut base_url = |field: &'static str| {
+            let url = self.get::<String>(field, "a string")?;
+            alpaca::validate_base_url(field, &url, allow_insecure)
+                .map_err(|e| self.fail(field, e))
+                .ok()
+        };
+        let api_base_url = base_url("api_base_url");
+        let data_base_url = base_url("data_base_url");
+
+        let extra_headers = match self.value("extra_headers").cloned() {
+            Some(value) => alpaca::parse_extra_headers(&value)
+                .map_err(|e| self.fail("extra_headers", e))
+                .unwrap_or_default(),
+            None => Vec::new(),
+        };
+
+        let mark_source = self
+            .parsed(
+                "position_marks",
+                MarkSource::parse,
+                "alpaca, last_trade, midpoint, or prev_close",
+            )
+            .unwrap_or_default();
+        let delisted_marks = self
+            .parsed(
+                "delisted_positions",
+                DelistedMarks::parse,
+                "flag or mark_to_zero",
+            )
+            .unwrap_or_default();
+
+        ClientOptions {
+            decompress_responses: self
+                .get("decompress_responses", "true or false")
+                .unwrap_or(defaults.decompress_responses),
+            strict_parsing: self
+                .get("strict_parsing", "true or false")
+                .unwrap_or(defaults.strict_parsing),
+            data_feed,
+            simulator: None,
+            recorder: None,
+            page_prefetch: self
+                .bounded("page_prefetch", MAX_PAGE_PREFETCH)
+                .map_or(paging::DEFAULT_PAGE_PREFETCH, |n| n as usize),
+            response_cache,
+            positions_cache: self
+                .bounded("positions_cache_ms", MAX_POSITIONS_CACHE_MS)
+                .map_or(alpaca::DEFAULT_POSITIONS_CACHE, Duration::from_millis),
+            api_base_url,
+            data_base_url,
+            extra_headers,
+            basic_auth: false,
+            mark_source,
+            delisted_marks,
+            circuit_breaker: self
+                .section("circuit_breaker", BreakerSettings::from_config)
+                .flatten(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn problems(config: serde_json::Value) -> Vec<Problem> {
+        match Config::parse(&config) {
+            Ok(_) => panic!("expected problems"),
+            Err(problems) => problems,
+        }
+    }
+
+    #[test]
+    fn every_problem_is_reported_by_field() {
+        let found = problems(json!({
+            "is_paper": "yes",
+            "pdt_policy": "sometimes",
+            "cost_buffer_pct": 250,
+            "data_feed": "nasdaq",
+            "dedupe_window_secs": -1,
+            "api_base_url": "http://localhost:9000",
+            "response_cache": { "/v2/assets": "long" },
+        }));
+        let fields: Vec<&str> = found.iter().map(|p| p.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "is_paper",
+                "pdt_policy",
+                "cost_buffer_pct",
+                "dedupe_window_secs",
+                "data_feed",
+                "response_cache",
+                "api_base_url",
+            ]
+        );
+    }
+
+    #[test]
+    fn defaults_apply_and_unknown_keys_are_flagged() {
+        let config = Config::parse(&json!({
+            "api_key": "k",
+            "api_secret": "s",
+            "log_level": null,
+            "risk_limts": {},
+        }))
+        .ok()
+        .unwrap();
+        assert!(config.is_paper);
+        assert_eq!(config.asset_mode, CheckMode::Enforce);
+        assert_eq!(config.cost_buffer_pct, Decimal::ONE);
+        assert!(config.fees.is_some());
+        assert_eq!(config.unknown_fields, ["risk_limts"]);
+
+        // Simulation is always paper
+        let conflict = problems(json!({ "simulation": {}, "is_paper": false }));
+        assert_eq!(conflict[0].field, "is_paper");
+        assert_eq!(problems(json!([1]))[0].field, "");
+
+        // A credential comes inline or by reference, never both
+        let refs = problems(json!({ "api_key": "k", "api_key_ref": "vault:alpaca/key" }));
+        let fields: Vec<&str> = refs.iter().map(|p| p.field.as_str()).collect();
+        assert_eq!(fields, ["api_key_ref", "api_key_ref"]);
+    }
+
+    #[test]
+    fn live_credentials_need_a_paper_primary() {
+        let config = Config::parse(&json!({
+            "api_key": "pk",
+            "api_secret": "ps",
+            "live_credentials": { "api_key": "lk", "api_secret": "ls" },
+        }))
+        .ok()
+        .unwrap();
+        let live = config.live_credentials.unwrap();
+        assert_eq!(live.api_key.as_deref(), Some("lk"));
+
+        let found = problems(json!({
+            "is_paper": false,
+            "live_credentials": { "api_key": "lk", "api_secret": "ls" },
+        }));
+        assert_eq!(found[0].field, "live_credentials");
+        let partial = problems(json!({ "live_credentials": { "api_key": "lk" } }));
+        assert_eq!(partial[0].field, "live_credentials");
+    }
+}
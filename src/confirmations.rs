@@ -0,0 +1,194 @@
+//! Trade confirmations
+//!
+//! Brokers send a confirmation for every execution: what was traded, at
+//! what price, on which trade date, when it settles, and the fees charged.
+//! `get_trade_confirmations` builds these records from `FILL` activities so
+//! the host can archive them for compliance. Fees are the regulatory fees
+//! the fee schedule (see [`crate::fees`]) puts on each fill; Alpaca books
+//! the actual fees as daily totals, which `get_cash_flows` reports.
+//!
+//! Settlement dates come from Alpaca's market calendar: US equities and
+//! options settle T+1 (the next trading day) and crypto settles on the
+//! trade date. Without the calendar, the next weekday is used.
+
+use crate::alpaca::CalendarDay;
+use crate::decimal::{self, Decimal};
+use crate::executions::Execution;
+use crate::fees::{AssetKind, FeeSchedule};
+use crate::market_time;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc, Weekday};
+use models::order::OrderSide;
+use serde::Serialize;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TradeConfirmation {
+    pub execution_id: String,
+    pub order_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persona_id: Option<String>,
+    pub symbol: String,
+    pub asset_class: &'static str,
+    /// As reported on the fill: `buy`, `sell`, or `sell_short`
+    pub side: String,
+    pub qty: String,
+    pub price: String,
+    /// Quantity times price (times 100 for option contracts)
+    pub gross_amount: String,
+    pub fees: serde_json::Value,
+    pub total_fees: String,
+    /// Gross amount plus fees for buys, minus fees for sells
+    pub net_amount: String,
+    pub executed_at: DateTime<Utc>,
+    pub trade_date: NaiveDate,
+    pub settlement_date: NaiveDate,
+}
+
+/// Settlement date of a trade made on `trade_date`
+pub fn settlement_date(
+    kind: AssetKind,
+    trade_date: NaiveDate,
+    calendar: &[CalendarDay],
+) -> NaiveDate {
+    if kind == AssetKind::Crypto {
+        return trade_date;
+    }
+    // A fill on a day without a session trades as of the next session
+    let mut sessions: Vec<&CalendarDay> = calendar
+        .iter()
+        .filter(|day| day.date >= trade_date)
+        .collect();
+    sessions.sort_by_key(|day| day.date);
+    match sessions.first() {
+        Some(session) => session
+            .settlement_date
+            .or_else(|| sessions.get(1).map(|next| next.date))
+            .unwrap_or_else(|| next_weekday(session.date)),
+        None => next_weekday(trade_date),
+    }
+}
+
+fn next_weekday(date: NaiveDate) -> NaiveDate {
+    let mut next = date + Duration::days(1);
+    while matches!(next.weekday(), Weekday::Sat | Weekday::Sun) {
+        next += Duration::days(1);
+    }
+    next
+}
+
+pub fn confirm(
+    fill: &Execution,
+    persona_id: Option<String>,
+    schedule: Option<&FeeSchedule>,
+    calendar: &[CalendarDay],
+) -> TradeConfirmation {
+    let kind = AssetKind::of(&fill.symbol);
+    let qty = decimal::parse_or_zero(&fill.qty);
+    let price = decimal::parse_or_zero(&fill.price);
+    let side = if fill.side.eq_ignore_ascii_case("buy") {
+        OrderSide::Buy
+    } else {
+        OrderSide::Sell
+    };
+    let contract_size = match kind {
+        AssetKind::Option => Decimal::ONE_HUNDRED,
+        _ => Decimal::ONE,
+    };
+    let gross = (qty * price * contract_size).round_dp(2);
+    let (fees, total_fees) = match schedule {
+        Some(schedule) => {
+            let estimate = schedule.estimate_fill(&fill.symbol, side, qty, price);
+            let total = estimate["total_fees"]
+                .as_str()
+                .and_then(decimal::parse)
+                .unwrap_or_default();
+            (estimate["fees"].clone(), total)
+        }
+        None => (serde_json::json!({}), Decimal::ZERO),
+    };
+    let net = match side {
+        OrderSide::Buy => gross + total_fees,
+        OrderSide::Sell => gross - total_fees,
+    };
+    let trade_date = market_time::eastern_date(fill.timestamp);
+    TradeConfirmation {
+        execution_id: fill.id.clone(),
+        order_id: fill.order_id.clone(),
+        persona_id,
+        symbol: fill.symbol.clone(),
+        asset_class: kind.name(),
+        side: fill.side.clone(),
+        qty: fill.qty.clone(),
+        price: fill.price.clone(),
+        gross_amount: decimal::to_wire(gross),
+        fees,
+        total_fees: decimal::to_wire(total_fees),
+        net_amount: decimal::to_wire(net),
+        executed_at: fill.timestamp,
+        trade_date,
+        settlement_date: settlement_date(kind, trade_date, calendar),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn sells_net_fees_and_settle_on_the_next_trading_day() {
+        let fill = Execution {
+            id: "fill-1".to_string(),
+            order_id: "o1".to_string(),
+            symbol: "AAPL".to_string(),
+            side: "sell".to_string(),
+            qty: "100".to_string(),
+            price: "150".to_string(),
+            // Friday 2024-03-01 10:00 ET
+            timestamp: "2024-03-01T15:00:00Z".parse().unwrap(),
+            fill_type: "fill".to_string(),
+            cum_qty: None,
+            leaves_qty: None,
+            venue: None,
+        };
+        let calendar = [
+            CalendarDay {
+                date: date("2024-03-01"),
+                settlement_date: Some(date("2024-03-04")),
+                close: None,
+            },
+            CalendarDay {
+                date: date("2024-03-04"),
+                settlement_date: None,
+                close: None,
+            },
+        ];
+        let confirmation = confirm(&fill, None, Some(&FeeSchedule::default()), &calendar);
+        assert_eq!(confirmation.trade_date, date("2024-03-01"));
+        assert_eq!(confirmation.settlement_date, date("2024-03-04"));
+        assert_eq!(confirmation.gross_amount, "15000");
+        let fees = decimal::parse(&confirmation.total_fees).unwrap();
+        assert!(fees > Decimal::ZERO);
+        assert_eq!(
+            decimal::parse(&confirmation.net_amount),
+            Some(Decimal::from(15000) - fees)
+        );
+
+        // Saturday trades as of Monday; without a calendar the next weekday
+        // is used; crypto settles the same day
+        assert_eq!(
+            settlement_date(AssetKind::Equity, date("2024-03-02"), &calendar[1..]),
+            date("2024-03-05")
+        );
+        assert_eq!(
+            settlement_date(AssetKind::Equity, date("2024-03-01"), &[]),
+            date("2024-03-04")
+        );
+        assert_eq!(
+            settlement_date(AssetKind::Crypto, date("2024-03-02"), &[]),
+            date("2024-03-02")
+        );
+    }
+}
@@ -0,0 +1,112 @@
+//! Local currency trading (LCT)
+//!
+//! An LCT account is denominated in a currency other than USD. Alpaca then
+//! reports order prices and position values in that local currency and
+//! adds a `swap_rate`, the local units per US dollar used for the
+//! conversion; positions may also carry a `usd` object with the USD
+//! figures. Order and position fields keep Alpaca's local values, and the
+//! currency, rate, and USD equivalents are carried alongside them so the
+//! host never has to assume USD.
+
+use crate::decimal::{self, Decimal};
+
+pub const USD: &str = "USD";
+
+/// Whether `currency` (as reported on the account) is something other than
+/// USD. An unknown currency is treated as USD.
+pub fn is_local(currency: Option<&str>) -> bool {
+    currency.is_some_and(|c| !c.is_empty() && !c.eq_ignore_ascii_case(USD))
+}
+
+/// USD equivalents of local `amounts`. A figure Alpaca reported under `usd`
+/// wins; otherwise the local amount is divided by `swap_rate`. Amounts
+/// that cannot be converted are left out.
+pub fn usd_equivalents(
+    amounts: &[(&str, Option<Decimal>)],
+    swap_rate: Option<Decimal>,
+    reported: Option<&serde_json::Value>,
+) -> serde_json::Map<String, serde_json::Value> {
+    let rate = swap_rate.filter(|r| *r > Decimal::ZERO);
+    let mut usd = serde_json::Map::new();
+    for (field, local) in amounts {
+        let from_alpaca = reported.and_then(|r| r.get(field)).and_then(|v| match v {
+            serde_json::Value::String(s) => decimal::parse(s),
+            serde_json::Value::Number(n) => n.as_f64().and_then(decimal::from_f64),
+            _ => None,
+        });
+        let converted = from_alpaca.or_else(|| Some((*local)? / rate?));
+        if let Some(amount) = converted {
+            usd.insert(
+                field.to_string(),
+                decimal::to_wire(amount.round_dp(6)).into(),
+            );
+        }
+    }
+    usd
+}
+
+/// The `currency`, `swap_rate`, and `usd` fields attached to a localized
+/// order or position; `None` when there is nothing local about it
+pub fn describe(
+    currency: Option<&str>,
+    amounts: &[(&str, Option<Decimal>)],
+    swap_rate: Option<Decimal>,
+    reported: Option<&serde_json::Value>,
+) -> Option<serde_json::Map<String, serde_json::Value>> {
+    if swap_rate.is_none() && reported.is_none() && !is_local(currency) {
+        return None;
+    }
+    let mut fields = serde_json::Map::new();
+    if let Some(currency) = currency {
+        fields.insert("currency".to_string(), currency.into());
+    }
+    if let Some(rate) = swap_rate {
+        fields.insert("swap_rate".to_string(), decimal::to_wire(rate).into());
+    }
+    fields.insert(
+        "usd".to_string(),
+        usd_equivalents(amounts, swap_rate, reported).into(),
+    );
+    Some(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Option<Decimal> {
+        decimal::parse(s)
+    }
+
+    #[test]
+    fn reported_usd_wins_over_the_swap_rate() {
+        let reported = serde_json::json!({ "market_value": "1000.50" });
+        let usd = usd_equivalents(
+            &[
+                ("market_value", dec("150000")),
+                ("current_price", dec("15000")),
+                ("unrealized_pl", None),
+            ],
+            dec("150"),
+            Some(&reported),
+        );
+        assert_eq!(usd["market_value"], "1000.5");
+        assert_eq!(usd["current_price"], "100");
+        assert!(!usd.contains_key("unrealized_pl"));
+
+        // A zero rate converts nothing
+        assert!(usd_equivalents(&[("x", dec("1"))], dec("0"), None).is_empty());
+    }
+
+    #[test]
+    fn usd_accounts_are_left_alone() {
+        let amounts = [("limit_price", dec("10"))];
+        assert!(describe(Some("USD"), &amounts, None, None).is_none());
+        assert!(describe(None, &amounts, None, None).is_none());
+
+        let fields = describe(Some("JPY"), &amounts, dec("148.5"), None).unwrap();
+        assert_eq!(fields["currency"], "JPY");
+        assert_eq!(fields["swap_rate"], "148.5");
+        assert_eq!(fields["usd"]["limit_price"], "0.06734");
+    }
+}
@@ -0,0 +1,284 @@
+//! End-of-day summary
+//!
+//! `get_daily_summary` compiles one US Eastern trading day into a single
+//! report: the orders placed, the fills, realized PnL and the change in
+//! unrealized PnL, fees charged and estimated, and why orders were
+//! rejected. Hosts that send an end-of-day email or archive a daily record
+//! can use it instead of stitching the order, fill, PnL, and cash-flow
+//! exports together.
+//!
+//! Rejections are not kept by Alpaca, so the plugin records every order it
+//! returns as `Rejected` (locally or by the broker) in a bounded log. Dry
+//! runs are previews, not rejections, and are left out.
+
+use crate::cashflows::CashFlow;
+use crate::decimal::{self, Decimal};
+use crate::executions::Execution;
+use crate::fees::FeeSchedule;
+use crate::market_time;
+use crate::orders;
+use crate::pnl::RealizedTrade;
+use chrono::{DateTime, NaiveDate, Utc};
+use models::order::{Order, OrderSide, OrderStatus};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+
+/// Rejections kept for summaries; older ones are dropped
+const MAX_REJECTIONS: usize = 1000;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Rejection {
+    pub rejected_at: DateTime<Utc>,
+    pub symbol: String,
+    pub side: OrderSide,
+    pub quantity: f64,
+    /// The finding code for local rejections, `error` otherwise
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Default)]
+pub struct RejectionLog {
+    entries: VecDeque<Rejection>,
+}
+
+impl RejectionLog {
+    /// Remember a rejected order; dry runs are skipped
+    pub fn record(&mut self, order: &Order) {
+        if order.status != OrderStatus::Rejected || is_dry_run(order) {
+            return;
+        }
+        let rejection = order
+            .extensions
+            .as_ref()
+            .and_then(|ext| ext.get("rejection"));
+        let code = rejection
+            .and_then(|r| r.get("code"))
+            .and_then(|c| c.as_str())
+            .unwrap_or("error");
+        let message = orders::ext_str(order, "error").unwrap_or_default();
+        if self.entries.len() == MAX_REJECTIONS {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Rejection {
+            rejected_at: order.created_at,
+            symbol: order.request.symbol_id.clone(),
+            side: order.request.side,
+            quantity: order.request.quantity,
+            code: code.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    pub fn on(&self, date: NaiveDate) -> Vec<Rejection> {
+        self.entries
+            .iter()
+            .filter(|r| market_time::eastern_date(r.rejected_at) == date)
+            .cloned()
+            .collect()
+    }
+}
+
+fn is_dry_run(order: &Order) -> bool {
+    orders::request_flag(&order.request, "dry_run")
+        || order
+            .extensions
+            .as_ref()
+            .and_then(|ext| ext.get("dry_run"))
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+}
+
+/// What one day's summary is built from, already limited to that day
+pub struct DayActivity<'a> {
+    pub date: NaiveDate,
+    pub orders: Vec<&'a Order>,
+    pub fills: Vec<Execution>,
+    pub realized: Vec<&'a RealizedTrade>,
+    /// Intraday change of open positions; only known for today
+    pub unrealized_change: Option<Decimal>,
+    pub fee_flows: Vec<CashFlow>,
+    pub rejections: Vec<Rejection>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SymbolFills {
+    pub bought_qty: Decimal,
+    pub bought_notional: Decimal,
+    pub sold_qty: Decimal,
+    pub sold_notional: Decimal,
+}
+
+pub fn build(day: &DayActivity, schedule: Option<&FeeSchedule>) -> serde_json::Value {
+    let mut by_status: BTreeMap<String, usize> = BTreeMap::new();
+    for order in &day.orders {
+        *by_status
+            .entry(format!("{:?}", order.status).to_ascii_lowercase())
+            .or_default() += 1;
+    }
+    let order_rows: Vec<serde_json::Value> = day
+        .orders
+        .iter()
+        .map(|o| {
+            serde_json::json!({
+                "order_id": o.id,
+                "symbol": o.request.symbol_id,
+                "side": o.request.side,
+                "quantity": o.request.quantity,
+                "status": o.status,
+                "filled_quantity": o.filled_quantity,
+                "average_filled_price": o.average_filled_price,
+                "created_at": o.created_at,
+            })
+        })
+        .collect();
+
+    let mut fills_by_symbol: BTreeMap<String, SymbolFills> = BTreeMap::new();
+    let mut estimated_fees = Decimal::ZERO;
+    for fill in &day.fills {
+        let qty = decimal::parse_or_zero(&fill.qty);
+        let price = decimal::parse_or_zero(&fill.price);
+        let entry = fills_by_symbol.entry(fill.symbol.clone()).or_default();
+        let side = if fill.side.eq_ignore_ascii_case("buy") {
+            entry.bought_qty += qty;
+            entry.bought_notional += (qty * price).round_dp(2);
+            OrderSide::Buy
+        } else {
+            entry.sold_qty += qty;
+            entry.sold_notional += (qty * price).round_dp(2);
+            OrderSide::Sell
+        };
+        if let Some(schedule) = schedule {
+            let estimate = schedule.estimate_fill(&fill.symbol, side, qty, price);
+            estimated_fees += estimate["total_fees"]
+                .as_str()
+                .and_then(decimal::parse)
+                .unwrap_or_default();
+        }
+    }
+
+    let mut realized_by_symbol: BTreeMap<String, Decimal> = BTreeMap::new();
+    for trade in &day.realized {
+        *realized_by_symbol.entry(trade.symbol.clone()).or_default() += trade.pnl;
+    }
+    let realized: Decimal = day.realized.iter().map(|t| t.pnl).sum();
+    let charged: Decimal = day.fee_flows.iter().map(|f| f.net_amount).sum();
+
+    let mut rejections_by_code: BTreeMap<String, usize> = BTreeMap::new();
+    for rejection in &day.rejections {
+        *rejections_by_code
+            .entry(rejection.code.clone())
+            .or_default() += 1;
+    }
+
+    serde_json::json!({
+        "date": day.date,
+        "orders": {
+            "count": day.orders.len(),
+            "by_status": by_status,
+            "orders": order_rows,
+        },
+        "fills": {
+            "count": day.fills.len(),
+            "by_symbol": fills_by_symbol,
+            "executions": day.fills,
+        },
+        "pnl": {
+            "realized": decimal::to_wire(realized),
+            "realized_by_symbol": realized_by_symbol
+                .into_iter()
+                .map(|(symbol, pnl)| (symbol, decimal::to_wire(pnl)))
+                .collect::<BTreeMap<_, _>>(),
+            "unrealized_change": day.unrealized_change.map(decimal::to_wire),
+            "total": day.unrealized_change.map(|u| decimal::to_wire(realized + u)),
+        },
+        "fees": {
+            // Fee activities are debits, so charged fees are reported positive
+            "charged": decimal::to_wire(-charged),
+            "estimated": schedule.map(|_| decimal::to_wire(estimated_fees)),
+        },
+        "rejections": {
+            "count": day.rejections.len(),
+            "by_code": rejections_by_code,
+            "rejections": day.rejections,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+
+    fn fill(symbol: &str, side: &str, qty: &str, price: &str) -> Execution {
+        Execution {
+            id: format!("{}-{}", symbol, side),
+            order_id: "o1".to_string(),
+            symbol: symbol.to_string(),
+            side: side.to_string(),
+            qty: qty.to_string(),
+            price: price.to_string(),
+            timestamp: Utc::now(),
+            fill_type: "fill".to_string(),
+            cum_qty: None,
+            leaves_qty: None,
+            venue: None,
+        }
+    }
+
+    #[test]
+    fn summary_groups_fills_and_rejections() {
+        let mut log = RejectionLog::default();
+        let mut rejected: Order = serde_json::from_value(serde_json::json!({
+            "id": "error_1",
+            "request": order_request("AAPL").build(),
+            "status": OrderStatus::Rejected,
+            "created_at": Utc::now(),
+            "updated_at": Utc::now(),
+            "average_filled_price": null,
+            "filled_quantity": 0.0,
+            "extensions": { "error": "Trading is halted" },
+            "persona_id": "default",
+        }))
+        .unwrap();
+        orders::set_ext(
+            &mut rejected,
+            "rejection",
+            serde_json::json!({ "code": "trading_halted" }),
+        );
+        log.record(&rejected);
+        // A dry run previewing the same order is not a rejection
+        let mut preview = rejected.clone();
+        orders::set_ext(&mut preview, "dry_run", true);
+        log.record(&preview);
+        let mut requested = rejected.clone();
+        requested.request.extensions =
+            Some([("dry_run".to_string(), true.into())].into_iter().collect());
+        log.record(&requested);
+        assert_eq!(log.entries.len(), 1);
+        let today = market_time::eastern_today();
+
+        let day = DayActivity {
+            date: today,
+            orders: Vec::new(),
+            fills: vec![
+                fill("AAPL", "buy", "10", "100"),
+                fill("AAPL", "sell", "4", "101.5"),
+            ],
+            realized: Vec::new(),
+            unrealized_change: Some(Decimal::from(-25)),
+            fee_flows: Vec::new(),
+            rejections: log.on(today),
+        };
+        let summary = build(&day, Some(&FeeSchedule::default()));
+        let aapl = &summary["fills"]["by_symbol"]["AAPL"];
+        assert_eq!(
+            aapl["sold_notional"].as_str().and_then(decimal::parse),
+            Some(Decimal::from(406))
+        );
+        assert_eq!(aapl["bought_qty"], "10");
+        assert_eq!(summary["pnl"]["total"], "-25");
+        assert_eq!(summary["rejections"]["by_code"]["trading_halted"], 1);
+        assert_ne!(summary["fees"]["estimated"], "0");
+    }
+}
@@ -0,0 +1,128 @@
+//! Decimal money and quantity handling
+//!
+//! Alpaca sends and accepts prices and quantities as decimal strings. They
+//! are kept as `Decimal` inside the plugin so values like `0.1` shares or a
+//! `1234.567` limit survive exactly, and only converted to `f64` where the
+//! plugin_api types require it.
+
+use rust_decimal::prelude::ToPrimitive;
+pub use rust_decimal::Decimal;
+use std::str::FromStr;
+
+/// Parse an Alpaca decimal string
+pub fn parse(raw: &str) -> Option<Decimal> {
+    let raw = raw.trim();
+    Decimal::from_str(raw)
+        .or_else(|_| Decimal::from_scientific(raw))
+        .ok()
+}
+
+/// Parse a display-only amount, treating malformed input as zero
+pub fn parse_or_zero(raw: &str) -> Decimal {
+    parse(raw).unwrap_or(Decimal::ZERO)
+}
+
+/// Convert a host-supplied `f64` using its shortest round-trip
+/// representation, so `0.1` becomes exactly `0.1` rather than the binary
+/// expansion `0.1000000000000000055…`.
+pub fn from_f64(value: f64) -> Option<Decimal> {
+    if !value.is_finite() {
+        return None;
+    }
+    parse(&value.to_string())
+}
+
+/// Convert to `f64` at the plugin_api boundary
+pub fn to_f64(value: Decimal) -> f64 {
+    value.to_f64().unwrap_or(0.0)
+}
+
+/// Canonical string form for Alpaca payloads (no trailing zeros)
+pub fn to_wire(value: Decimal) -> String {
+    value.normalize().to_string()
+}
+
+/// Error for a field that could not be decoded
+pub fn decode_error(field: &str, raw: &str) -> String {
+    format!(
+        "Decode error: field `{}` has invalid value {:?}",
+        field, raw
+    )
+}
+
+/// Field-aware parser honouring the strict-parsing setting.
+///
+/// In strict mode a malformed value fails with a decode error naming the
+/// field and raw value; in lenient mode it falls back to zero / `None`.
+#[derive(Clone, Copy, Debug)]
+pub struct FieldParser {
+    pub strict: bool,
+}
+
+impl FieldParser {
+    pub fn strict() -> Self {
+        Self { strict: true }
+    }
+
+    pub fn lenient() -> Self {
+        Self { strict: false }
+    }
+
+    pub fn required(&self, field: &str, raw: &str) -> Result<Decimal, String> {
+        match parse(raw) {
+            Some(value) => Ok(value),
+            None if self.strict => Err(decode_error(field, raw)),
+            None => Ok(Decimal::ZERO),
+        }
+    }
+
+    /// Absent and empty values are `None`; malformed ones follow the mode
+    pub fn optional(&self, field: &str, raw: Option<&str>) -> Result<Option<Decimal>, String> {
+        match raw.map(str::trim) {
+            None | Some("") => Ok(None),
+            Some(raw) => match parse(raw) {
+                Some(value) => Ok(Some(value)),
+                None if self.strict => Err(decode_error(field, raw)),
+                None => Ok(None),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Quantities and prices as a host would send them: up to 9 decimal
+    /// places and 12 integer digits
+    fn host_f64() -> impl Strategy<Value = f64> {
+        (1u64..1_000_000_000_000, 0u32..=9).prop_map(|(units, scale)| {
+            Decimal::from_i128_with_scale(units as i128, scale)
+                .to_string()
+                .parse::<f64>()
+                .unwrap()
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn f64_survives_the_wire(value in host_f64()) {
+            let wire = to_wire(from_f64(value).unwrap());
+            prop_assert_eq!(to_f64(parse(&wire).unwrap()), value, "wire {}", wire);
+        }
+
+        #[test]
+        fn wire_form_is_the_shortest_decimal(value in host_f64()) {
+            prop_assert_eq!(to_wire(from_f64(value).unwrap()), value.to_string());
+        }
+
+        #[test]
+        fn any_f64_converts_or_is_rejected(value in any::<f64>()) {
+            match from_f64(value) {
+                Some(d) => prop_assert!(value.is_finite() && parse(&to_wire(d)) == Some(d)),
+                None => prop_assert!(!value.is_finite() || value.abs() >= 1e28 || value.abs() < 1e-28),
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Duplicate-order guard
+//!
+//! Remembers the orders accepted in the last `dedupe_window_secs` seconds
+//! and rejects an identical one (same persona, symbol, side, quantity,
+//! type, and prices), which protects against host-side retry storms. An
+//! order with `extensions.allow_duplicate = true` bypasses the guard.
+
+use crate::pretrade::Finding;
+use chrono::{DateTime, Duration, Utc};
+use models::order::OrderRequest;
+use std::collections::VecDeque;
+
+/// The fields that make two orders identical. Numbers are compared by
+/// their string form so that floats compare exactly.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Fingerprint(String);
+
+impl Fingerprint {
+    fn of(order: &OrderRequest) -> Self {
+        Self(
+            serde_json::json!([
+                order.persona_id,
+                order.symbol_id.to_ascii_uppercase(),
+                order.side,
+                order.quantity.to_string(),
+                order.order_type,
+                order.limit_price.map(|p| p.to_string()),
+                order.stop_price.map(|p| p.to_string()),
+            ])
+            .to_string(),
+        )
+    }
+}
+
+struct Recent {
+    fingerprint: Fingerprint,
+    order_id: String,
+    submitted_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+pub struct DedupeGuard {
+    /// Zero disables the guard
+    pub window_secs: u64,
+    recent: VecDeque<Recent>,
+}
+
+impl DedupeGuard {
+    pub fn new(window_secs: u64) -> Self {
+        Self {
+            window_secs,
+            recent: VecDeque::new(),
+        }
+    }
+
+    fn expire(&mut self, now: DateTime<Utc>) {
+        let window = Duration::seconds(self.window_secs as i64);
+        while self
+            .recent
+            .front()
+            .is_some_and(|r| now - r.submitted_at >= window)
+        {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Finding for an order identical to one accepted within the window
+    pub fn check(&mut self, order: &OrderRequest) -> Option<Finding> {
+        if self.window_secs == 0 || allows_duplicate(order) {
+            return None;
+        }
+        let now = Utc::now();
+        self.expire(now);
+
+        let fingerprint = Fingerprint::of(order);
+        let previous = self
+            .recent
+            .iter()
+            .rev()
+            .find(|r| r.fingerprint == fingerprint)?;
+        Some(Finding::new(
+            "duplicate_order",
+            format!(
+                "Identical order {} was submitted {}s ago; set extensions.allow_duplicate to override",
+                previous.order_id,
+                (now - previous.submitted_at).num_seconds()
+            ),
+            serde_json::json!({
+                "original_order_id": previous.order_id,
+                "submitted_at": previous.submitted_at,
+                "window_secs": self.window_secs,
+            }),
+        ))
+    }
+
+    /// Remember an accepted order
+    pub fn record(&mut self, order: &OrderRequest, order_id: &str) {
+        if self.window_secs == 0 {
+            return;
+        }
+        let now = Utc::now();
+        self.expire(now);
+        self.recent.push_back(Recent {
+            fingerprint: Fingerprint::of(order),
+            order_id: order_id.to_string(),
+            submitted_at: now,
+        });
+    }
+}
+
+fn allows_duplicate(order: &OrderRequest) -> bool {
+    order
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.get("allow_duplicate"))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
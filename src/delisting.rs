@@ -0,0 +1,140 @@
+//! Positions in delisted assets
+//!
+//! Alpaca keeps reporting a position in a delisted asset at the last
+//! price it traded, so it looks like a live holding that never moves. It
+//! cannot be sold through the API either: the asset is no longer active.
+//! Equity positions whose price has not moved since the previous close
+//! are looked up in `GET /v2/assets/{symbol}` (served from the response
+//! cache), and those whose asset is no longer `active` get a
+//! `delisted_positions` entry with the suggested way out. With
+//! `delisted_positions: "mark_to_zero"` they are also marked at zero.
+
+use crate::alpaca::AlpacaAsset;
+use crate::decimal::{self, Decimal};
+use crate::marks;
+use models::portfolio::Position;
+use serde::Serialize;
+
+/// Alpaca support can liquidate or write off a position the API cannot
+/// trade
+pub const LIQUIDATE_VIA_SUPPORT: &str = "liquidate_via_support";
+pub const MARK_TO_ZERO: &str = "mark_to_zero";
+
+/// How delisted positions are valued
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DelistedMarks {
+    /// Flag them, keeping Alpaca's last price
+    #[default]
+    Flag,
+    MarkToZero,
+}
+
+impl DelistedMarks {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            "flag" => Some(Self::Flag),
+            "mark_to_zero" => Some(Self::MarkToZero),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DelistedFlag {
+    pub symbol: String,
+    /// The asset's `status`, e.g. `inactive`
+    pub asset_status: String,
+    pub tradable: bool,
+    /// The price Alpaca reports, frozen at the last trade
+    pub last_price: String,
+    pub marked_to_zero: bool,
+    pub suggested_actions: Vec<&'static str>,
+    pub message: String,
+}
+
+/// Whether a position is worth looking up: its price has not moved since
+/// the previous close
+pub fn price_frozen(
+    current: Option<Decimal>,
+    lastday: Option<Decimal>,
+    change_today: Option<Decimal>,
+) -> bool {
+    match (current, lastday) {
+        (Some(current), Some(lastday)) => current == lastday,
+        _ => change_today.is_some_and(|c| c.is_zero()),
+    }
+}
+
+pub fn is_delisted(asset: &AlpacaAsset) -> bool {
+    !asset.status.eq_ignore_ascii_case("active")
+}
+
+/// Flag `position` in the delisted `asset`, marking it at zero when
+/// `marks` says so
+pub fn flag(position: &mut Position, asset: &AlpacaAsset, marks: DelistedMarks) -> DelistedFlag {
+    let last_price = decimal::from_f64(position.current_price).unwrap_or_default();
+    let marked_to_zero = marks == DelistedMarks::MarkToZero;
+    if marked_to_zero {
+        marks::remark(position, Decimal::ZERO);
+    }
+    let mut suggested_actions = vec![LIQUIDATE_VIA_SUPPORT];
+    if !marked_to_zero {
+        suggested_actions.push(MARK_TO_ZERO);
+    }
+    DelistedFlag {
+        symbol: position.symbol_id.clone(),
+        asset_status: asset.status.clone(),
+        tradable: asset.tradable,
+        last_price: decimal::to_wire(last_price),
+        marked_to_zero,
+        suggested_actions,
+        message: format!(
+            "{} is {} at Alpaca and cannot be traded through the API; its price of {} will not update. Ask Alpaca support to liquidate or write off the position.",
+            position.symbol_id,
+            asset.status,
+            decimal::to_wire(last_price)
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inactive_assets_are_flagged_and_optionally_marked_to_zero() {
+        let d = |s: &str| decimal::parse(s);
+        assert!(price_frozen(d("3.1"), d("3.10"), None));
+        assert!(!price_frozen(d("3.2"), d("3.1"), d("0")));
+        assert!(price_frozen(None, None, d("0")));
+
+        let asset: AlpacaAsset = serde_json::from_value(serde_json::json!({
+            "symbol": "GONE",
+            "status": "inactive",
+            "tradable": false,
+        }))
+        .unwrap();
+        assert!(is_delisted(&asset));
+        let mut position = Position {
+            symbol_id: "GONE".to_string(),
+            quantity: 100.0,
+            average_price: 5.0,
+            current_price: 3.1,
+            unrealized_pnl: -190.0,
+            unrealized_pnl_percent: -38.0,
+        };
+
+        let flagged = flag(&mut position.clone(), &asset, DelistedMarks::Flag);
+        assert_eq!(flagged.last_price, "3.1");
+        assert_eq!(
+            flagged.suggested_actions,
+            [LIQUIDATE_VIA_SUPPORT, MARK_TO_ZERO]
+        );
+
+        let flagged = flag(&mut position, &asset, DelistedMarks::MarkToZero);
+        assert!(flagged.marked_to_zero);
+        assert_eq!(position.current_price, 0.0);
+        assert_eq!(position.unrealized_pnl, -500.0);
+    }
+}
@@ -0,0 +1,86 @@
+//! Paper and live side by side
+//!
+//! With `live_credentials` in the config, one plugin instance holds a
+//! paper client (the default) and a live client, so a host can run a
+//! strategy live and shadow it against paper fills without loading the
+//! plugin twice. Requests pick an environment with `environment`
+//! (`extensions.environment` on orders) or by naming the account ID;
+//! orders remember theirs in `extensions.environment`, so later refreshes,
+//! lookups, and cancels go to the right account.
+//!
+//! Only the default environment has the plugin's local state: fill
+//! history, PnL, cash flows, position diffs, and the order books that the
+//! plugin works itself (algos, schedules, chases, queued and GTD orders).
+//! Orders for the other environment are sent as they are.
+
+use models::order::{Order, OrderRequest};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Environment {
+    Paper,
+    Live,
+}
+
+impl Environment {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "paper" => Some(Self::Paper),
+            "live" => Some(Self::Live),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Paper => "paper",
+            Self::Live => "live",
+        }
+    }
+
+    pub fn of(is_paper: bool) -> Self {
+        if is_paper {
+            Self::Paper
+        } else {
+            Self::Live
+        }
+    }
+
+    /// An `environment` request field
+    pub fn from_field(value: Option<&str>) -> Result<Option<Self>, String> {
+        match value {
+            None => Ok(None),
+            Some(value) => Self::parse(value).map(Some).ok_or_else(|| {
+                format!(
+                    "Unsupported environment: {} (expected paper or live)",
+                    value
+                )
+            }),
+        }
+    }
+
+    /// `extensions.environment` on an order request
+    pub fn requested(request: &OrderRequest) -> Result<Option<Self>, String> {
+        let value = request
+            .extensions
+            .as_ref()
+            .and_then(|ext| ext.get("environment"))
+            .filter(|v| !v.is_null());
+        match value {
+            None => Ok(None),
+            Some(serde_json::Value::String(s)) => Self::from_field(Some(s)),
+            Some(other) => Self::from_field(Some(&other.to_string())),
+        }
+    }
+
+    /// The environment an order was placed in, when it was recorded
+    pub fn of_order(order: &Order) -> Option<Self> {
+        order
+            .extensions
+            .as_ref()
+            .and_then(|ext| ext.get("environment"))
+            .and_then(|v| v.as_str())
+            .and_then(Self::parse)
+    }
+}
@@ -0,0 +1,275 @@
+//! Push delivery of order, connectivity, and alert events to the host
+//!
+//! With the `host-events` feature the plugin hands events to the host's
+//! `emit_event(ptr, len) -> i32` import as soon as it detects them, so the
+//! host does not have to read them out of `tick` or `poll_order_updates`
+//! responses. Each event is one JSON envelope:
+//!
+//! ```json
+//! { "type": "order_event", "seq": 42, "event": { "order_id": "...", "kind": "fill", ... } }
+//! { "type": "connectivity", "seq": 3, "connected": false, "error": "...", "at": "..." }
+//! { "type": "alert", "seq": 4, "alert": { "rule": "cash_below", ... } }
+//! { "type": "protective", "seq": 5, "event": "resized", "symbol": "AAPL", ... }
+//! ```
+//!
+//! Delivery is at least once. Order events are read from the event journal
+//! past the last acknowledged `seq`; the import returning 0 acknowledges an
+//! event, anything else stops the flush and the event is offered again on
+//! the next one. Hosts dedupe on `type` and `seq`. An event that ages out of
+//! the journal (10,000 entries) before the host accepts it is lost and
+//! logged. Connectivity, alert, and protective order events share one
+//! `seq` counter and wait in a small queue of their own.
+
+use crate::logging;
+use crate::orders::EventJournal;
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+#[cfg(feature = "host-events")]
+extern "C" {
+    fn emit_event(ptr: i32, len: i32) -> i32;
+}
+
+/// Order events offered per flush; the rest follow on the next one
+const MAX_PER_FLUSH: usize = 500;
+
+/// Undelivered connectivity and alert events kept; older ones are dropped
+const MAX_PENDING_NOTICES: usize = 64;
+
+#[derive(Default)]
+pub struct Outbox {
+    /// Highest journal `seq` the host has accepted
+    delivered_seq: u64,
+    /// `None` until the first response is seen
+    connected: Option<bool>,
+    notice_seq: u64,
+    /// Connectivity and alert events not yet accepted
+    notices: VecDeque<serde_json::Value>,
+}
+
+impl Outbox {
+    /// Note the outcome of a request that reached the transport. Only
+    /// transitions are queued, so a long outage is one event.
+    pub fn observe(&mut self, connected: bool, error: Option<&str>) {
+        if self.connected == Some(connected) {
+            return;
+        }
+        // The first success is the normal state, not news
+        let first = self.connected.is_none();
+        self.connected = Some(connected);
+        if first && connected {
+            return;
+        }
+        self.push(
+            "connectivity",
+            serde_json::json!({
+                "connected": connected,
+                "error": error,
+                "at": Utc::now().to_rfc3339(),
+            }),
+        );
+    }
+
+    /// Queue a `type` envelope; `fields` (an object) are merged into it
+    pub fn push(&mut self, kind: &str, fields: serde_json::Value) {
+        if self.notices.len() == MAX_PENDING_NOTICES {
+            self.notices.pop_front();
+        }
+        self.notice_seq += 1;
+        let mut envelope = serde_json::json!({ "type": kind, "seq": self.notice_seq });
+        if let (Some(envelope), serde_json::Value::Object(fields)) =
+            (envelope.as_object_mut(), fields)
+        {
+            envelope.extend(fields);
+        }
+        self.notices.push_back(envelope);
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Delivery {
+    pub delivered: usize,
+    /// Events left for the next flush, because the host refused one or
+    /// the flush limit was reached
+    pub pending: bool,
+}
+
+lazy_static::lazy_static! {
+    // Taken after JOURNAL when both are needed
+    static ref OUTBOX: Mutex<Outbox> = Mutex::new(Outbox::default());
+}
+
+/// Record a connectivity observation for the next flush
+pub fn observe_connectivity(connected: bool, error: Option<&str>) {
+    OUTBOX
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .observe(connected, error);
+}
+
+/// Queue an event for the next flush
+pub fn push(kind: &str, fields: serde_json::Value) {
+    OUTBOX
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(kind, fields);
+}
+
+/// Offer every undelivered event to `emit`, oldest first, until it refuses
+/// one. No lock is held while `emit` runs.
+pub fn deliver(
+    journal: &Mutex<EventJournal>,
+    outbox: &Mutex<Outbox>,
+    mut emit: impl FnMut(&[u8]) -> bool,
+) -> Delivery {
+    let (order_events, notices) = {
+        let journal = journal.lock().unwrap_or_else(|e| e.into_inner());
+        let mut outbox = outbox.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(first) = journal.first_seq() {
+            if first > outbox.delivered_seq + 1 {
+                logging::warn("events", "Order events aged out before delivery")
+                    .field("lost", first - outbox.delivered_seq - 1)
+                    .emit();
+                outbox.delivered_seq = first - 1;
+            }
+        }
+        (
+            journal.since(outbox.delivered_seq, MAX_PER_FLUSH),
+            outbox.notices.clone(),
+        )
+    };
+
+    let mut report = Delivery::default();
+    let mut notices_sent = 0;
+    let mut last_seq = None;
+    let mut order_sent = 0;
+    for notice in &notices {
+        if !emit(notice.to_string().as_bytes()) {
+            report.pending = true;
+            break;
+        }
+        notices_sent += 1;
+    }
+    if !report.pending {
+        for event in &order_events {
+            let envelope = serde_json::json!({
+                "type": "order_event",
+                "seq": event.seq,
+                "event": event,
+            });
+            if !emit(envelope.to_string().as_bytes()) {
+                report.pending = true;
+                break;
+            }
+            last_seq = Some(event.seq);
+            order_sent += 1;
+        }
+        report.pending |= order_events.len() == MAX_PER_FLUSH;
+    }
+
+    let mut outbox = outbox.lock().unwrap_or_else(|e| e.into_inner());
+    // Notices are only ever appended, so the sent ones are at the front
+    // (unless the queue overflowed meanwhile)
+    let sent = notices_sent.min(outbox.notices.len());
+    outbox.notices.drain(..sent);
+    if let Some(seq) = last_seq {
+        outbox.delivered_seq = outbox.delivered_seq.max(seq);
+    }
+    report.delivered = notices_sent + order_sent;
+    report
+}
+
+/// Push pending events through the host import. Without `host-events`
+/// nothing is pushed and hosts read events from the export responses.
+pub fn flush(journal: &Mutex<EventJournal>) -> Option<Delivery> {
+    #[cfg(feature = "host-events")]
+    {
+        let delivery = deliver(journal, &OUTBOX, |bytes| {
+            // SAFETY: the host only reads `len` bytes at `ptr` during the call
+            unsafe { emit_event(bytes.as_ptr() as i32, bytes.len() as i32) == 0 }
+        });
+        Some(delivery)
+    }
+    #[cfg(not(feature = "host-events"))]
+    {
+        let _ = journal;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orders::{OrderEvent, OrderEventKind};
+
+    fn event(order_id: &str) -> OrderEvent {
+        OrderEvent {
+            seq: 0,
+            order_id: order_id.to_string(),
+            leg_id: None,
+            kind: OrderEventKind::Fill,
+            alpaca_status: "filled".to_string(),
+            filled_qty: "1".to_string(),
+            filled_avg_price: Some("100".to_string()),
+            replaces: None,
+            limit_price: None,
+            detected_at: Utc::now().to_rfc3339(),
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn refused_events_are_offered_again() {
+        let journal = Mutex::new(EventJournal::default());
+        let outbox = Mutex::new(Outbox::default());
+        journal
+            .lock()
+            .unwrap()
+            .extend([event("a"), event("b"), event("c")]);
+
+        // The host takes one event, then refuses the next
+        let mut seen = Vec::new();
+        let first = deliver(&journal, &outbox, |bytes| {
+            seen.push(serde_json::from_slice::<serde_json::Value>(bytes).unwrap());
+            seen.len() < 2
+        });
+        assert_eq!(first.delivered, 1);
+        assert!(first.pending);
+
+        seen.clear();
+        let second = deliver(&journal, &outbox, |bytes| {
+            seen.push(serde_json::from_slice::<serde_json::Value>(bytes).unwrap());
+            true
+        });
+        assert_eq!(second.delivered, 2);
+        assert!(!second.pending);
+        let seqs: Vec<_> = seen.iter().map(|e| e["seq"].as_u64().unwrap()).collect();
+        assert_eq!(seqs, [2, 3]);
+        assert_eq!(seen[0]["event"]["order_id"], "b");
+
+        assert_eq!(deliver(&journal, &outbox, |_| true).delivered, 0);
+    }
+
+    #[test]
+    fn only_connectivity_transitions_are_queued() {
+        let journal = Mutex::new(EventJournal::default());
+        let outbox = Mutex::new(Outbox::default());
+        {
+            let mut outbox = outbox.lock().unwrap();
+            outbox.observe(true, None);
+            outbox.observe(false, Some("connection refused"));
+            outbox.observe(false, Some("connection refused"));
+            outbox.observe(true, None);
+        }
+        let mut seen = Vec::new();
+        deliver(&journal, &outbox, |bytes| {
+            seen.push(serde_json::from_slice::<serde_json::Value>(bytes).unwrap());
+            true
+        });
+        let connected: Vec<_> = seen.iter().map(|e| e["connected"].clone()).collect();
+        assert_eq!(connected, [false, true]);
+        assert_eq!(seen[0]["error"], "connection refused");
+    }
+}
@@ -0,0 +1,101 @@
+//! Fill-level execution records
+//!
+//! Built from Alpaca's `FILL` account activities so the host can see each
+//! individual execution (price, quantity, time) rather than only the
+//! order's average fill price.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Execution {
+    /// Alpaca activity ID, unique per execution
+    pub id: String,
+    pub order_id: String,
+    pub symbol: String,
+    pub side: String,
+    pub qty: String,
+    pub price: String,
+    pub timestamp: DateTime<Utc>,
+    /// `fill` or `partial_fill`
+    pub fill_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cum_qty: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leaves_qty: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub venue: Option<String>,
+}
+
+/// Executions seen so far, grouped by order
+#[derive(Default)]
+pub struct ExecutionStore {
+    by_order: HashMap<String, Vec<Execution>>,
+    seen: HashSet<String>,
+    /// Latest execution time ingested, used as the next incremental cursor
+    pub last_seen: Option<DateTime<Utc>>,
+    /// After a bounded first load, the time the store is complete from.
+    /// Older fills are pulled once a caller asks for them.
+    pub floor: Option<DateTime<Utc>>,
+}
+
+impl ExecutionStore {
+    /// Whether any fetch has been ingested yet
+    pub fn loaded(&self) -> bool {
+        self.last_seen.is_some() || self.floor.is_some()
+    }
+
+    /// Add executions not seen before; returns how many were new
+    pub fn ingest(&mut self, executions: Vec<Execution>) -> usize {
+        let mut added = 0;
+        for execution in executions {
+            if !self.seen.insert(execution.id.clone()) {
+                continue;
+            }
+            if self.last_seen.is_none_or(|t| execution.timestamp > t) {
+                self.last_seen = Some(execution.timestamp);
+            }
+            let list = self.by_order.entry(execution.order_id.clone()).or_default();
+            list.push(execution);
+            list.sort_by_key(|e| e.timestamp);
+            added += 1;
+        }
+        added
+    }
+
+    pub fn for_order(&self, order_id: &str) -> Vec<Execution> {
+        self.by_order.get(order_id).cloned().unwrap_or_default()
+    }
+
+    /// Move fills in `old` to `new` after a ticker change, so lots opened
+    /// under the old symbol match later fills; returns how many moved
+    pub fn rename_symbol(&mut self, old: &str, new: &str) -> usize {
+        let mut renamed = 0;
+        for execution in self.by_order.values_mut().flatten() {
+            if execution.symbol.eq_ignore_ascii_case(old) {
+                execution.symbol = new.to_string();
+                renamed += 1;
+            }
+        }
+        renamed
+    }
+
+    /// Executions within `[start, end]`, oldest first
+    pub fn in_range(
+        &self,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+    ) -> Vec<Execution> {
+        let mut out: Vec<Execution> = self
+            .by_order
+            .values()
+            .flatten()
+            .filter(|e| start.is_none_or(|s| e.timestamp >= s))
+            .filter(|e| end.is_none_or(|t| e.timestamp <= t))
+            .cloned()
+            .collect();
+        out.sort_by_key(|e| e.timestamp);
+        out
+    }
+}
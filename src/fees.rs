@@ -0,0 +1,351 @@
+//! Estimated fees and all-in cost
+//!
+//! Alpaca charges no commission on stocks, ETFs, or options, but passes
+//! through regulatory fees, and charges a percentage fee on crypto. The
+//! estimate is attached to submitted and dry-run orders as
+//! `extensions.estimated_costs` so the host can show the all-in amount
+//! before the user confirms. Rates change from time to time; the defaults
+//! are the published ones at the time of writing and every rate can be
+//! overridden under the `fees` config key.
+
+use crate::decimal::{self, Decimal};
+use crate::pretrade::PriceEstimate;
+use models::order::{OrderRequest, OrderSide, OrderType};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AssetKind {
+    Equity,
+    Crypto,
+    Option,
+}
+
+impl AssetKind {
+    /// Crypto pairs contain a slash (`BTC/USD`); options use OCC symbols
+    /// (`AAPL240119C00150000`)
+    pub fn of(symbol: &str) -> Self {
+        if symbol.contains('/') {
+            return AssetKind::Crypto;
+        }
+        let bytes = symbol.as_bytes();
+        let n = bytes.len();
+        let is_occ = n > 15
+            && bytes[n - 8..].iter().all(u8::is_ascii_digit)
+            && matches!(bytes[n - 9], b'C' | b'P')
+            && bytes[n - 15..n - 9].iter().all(u8::is_ascii_digit);
+        if is_occ {
+            AssetKind::Option
+        } else {
+            AssetKind::Equity
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AssetKind::Equity => "us_equity",
+            AssetKind::Crypto => "crypto",
+            AssetKind::Option => "us_option",
+        }
+    }
+
+    /// Alpaca's `asset_class`, or the kind the symbol implies when Alpaca
+    /// left it out
+    pub fn from_class(class: Option<&str>, symbol: &str) -> Self {
+        match class {
+            Some("us_equity") => AssetKind::Equity,
+            Some("crypto") => AssetKind::Crypto,
+            Some("us_option") => AssetKind::Option,
+            _ => AssetKind::of(symbol),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FeeSchedule {
+    /// SEC Section 31 fee per $1M of sale proceeds
+    pub sec_per_million: Decimal,
+    /// FINRA Trading Activity Fee per share sold, and its per-trade cap
+    pub taf_per_share: Decimal,
+    pub taf_max: Decimal,
+    pub crypto_maker_bps: Decimal,
+    pub crypto_taker_bps: Decimal,
+    /// Options Regulatory Fee, OCC clearing fee, and TAF per contract
+    /// (TAF on sells only)
+    pub option_orf_per_contract: Decimal,
+    pub option_occ_per_contract: Decimal,
+    pub option_taf_per_contract: Decimal,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        Self {
+            sec_per_million: Decimal::new(2780, 2),
+            taf_per_share: Decimal::new(166, 6),
+            taf_max: Decimal::new(830, 2),
+            crypto_maker_bps: Decimal::new(15, 0),
+            crypto_taker_bps: Decimal::new(25, 0),
+            option_orf_per_contract: Decimal::new(2685, 5),
+            option_occ_per_contract: Decimal::new(2, 2),
+            option_taf_per_contract: Decimal::new(279, 5),
+        }
+    }
+}
+
+impl FeeSchedule {
+    /// `None` when estimates are turned off with `"fees": false`
+    pub fn from_config(value: Option<&serde_json::Value>) -> Result<Option<Self>, String> {
+        let mut schedule = Self::default();
+        let object = match value {
+            None | Some(serde_json::Value::Null) | Some(serde_json::Value::Bool(true)) => {
+                return Ok(Some(schedule))
+            }
+            Some(serde_json::Value::Bool(false)) => return Ok(None),
+            Some(serde_json::Value::Object(object)) => object,
+            Some(_) => return Err("fees must be a boolean or an object".to_string()),
+        };
+        let fields: [(&str, &mut Decimal); 8] = [
+            ("sec_per_million", &mut schedule.sec_per_million),
+            ("taf_per_share", &mut schedule.taf_per_share),
+            ("taf_max", &mut schedule.taf_max),
+            ("crypto_maker_bps", &mut schedule.crypto_maker_bps),
+            ("crypto_taker_bps", &mut schedule.crypto_taker_bps),
+            (
+                "option_orf_per_contract",
+                &mut schedule.option_orf_per_contract,
+            ),
+            (
+                "option_occ_per_contract",
+                &mut schedule.option_occ_per_contract,
+            ),
+            (
+                "option_taf_per_contract",
+                &mut schedule.option_taf_per_contract,
+            ),
+        ];
+        for (key, slot) in fields {
+            if let Some(v) = object.get(key) {
+                *slot = v
+                    .as_f64()
+                    .filter(|n| *n >= 0.0)
+                    .and_then(decimal::from_f64)
+                    .ok_or_else(|| format!("fees.{} must be a non-negative number", key))?;
+            }
+        }
+        Ok(Some(schedule))
+    }
+
+    /// Fee breakdown for an order at `price`. Without a price, fees that
+    /// depend on the notional are left out and `complete` is false.
+    pub fn estimate(
+        &self,
+        order: &OrderRequest,
+        price: Option<PriceEstimate>,
+    ) -> serde_json::Value {
+        let kind = AssetKind::of(&order.symbol_id);
+        let qty = decimal::from_f64(order.quantity).unwrap_or_default();
+        let sell = order.side == OrderSide::Sell;
+        let contract_size = match kind {
+            AssetKind::Option => Decimal::ONE_HUNDRED,
+            _ => Decimal::ONE,
+        };
+        let notional = price.map(|p| (qty * p.price * contract_size).round_dp(2));
+
+        let mut fees = serde_json::Map::new();
+        let mut total = Decimal::ZERO;
+        let mut complete = true;
+        let mut add = |name: &str, amount: Option<Decimal>| match amount {
+            Some(amount) => {
+                total += amount;
+                fees.insert(name.to_string(), decimal::to_wire(amount).into());
+            }
+            None => {
+                complete = false;
+                fees.insert(name.to_string(), serde_json::Value::Null);
+            }
+        };
+        match kind {
+            AssetKind::Equity if sell => {
+                add(
+                    "sec",
+                    notional
+                        .map(|n| ceil_cents(n * self.sec_per_million / Decimal::from(1_000_000))),
+                );
+                add(
+                    "taf",
+                    Some(ceil_cents((qty * self.taf_per_share).min(self.taf_max))),
+                );
+            }
+            AssetKind::Equity => {}
+            AssetKind::Crypto => {
+                // A resting limit order normally adds liquidity
+                let bps = match order.order_type {
+                    OrderType::Limit => self.crypto_maker_bps,
+                    _ => self.crypto_taker_bps,
+                };
+                add(
+                    "crypto",
+                    notional.map(|n| (n * bps / Decimal::from(10_000)).round_dp(2)),
+                );
+            }
+            AssetKind::Option => {
+                add("orf", Some(ceil_cents(qty * self.option_orf_per_contract)));
+                add("occ", Some(ceil_cents(qty * self.option_occ_per_contract)));
+                if sell {
+                    add("taf", Some(ceil_cents(qty * self.option_taf_per_contract)));
+                }
+            }
+        }
+
+        // Fees come out of sale proceeds and on top of purchases
+        let all_in = notional.map(|n| if sell { n - total } else { n + total });
+        serde_json::json!({
+            "asset_class": kind.name(),
+            "price": price.map(|p| decimal::to_wire(p.price)),
+            "price_source": price.map(|p| p.source),
+            "notional": notional.map(decimal::to_wire),
+            "commission": "0",
+            "fees": fees,
+            "total_fees": decimal::to_wire(total),
+            "all_in": all_in.map(decimal::to_wire),
+            "complete": complete,
+        })
+    }
+
+    /// Fee breakdown for an execution, priced at the fill. Fills carry no
+    /// order type, so crypto fills are charged the taker rate.
+    pub fn estimate_fill(
+        &self,
+        symbol: &str,
+        side: OrderSide,
+        qty: Decimal,
+        price: Decimal,
+    ) -> serde_json::Value {
+        let request = OrderRequest {
+            symbol_id: symbol.to_string(),
+            quantity: decimal::to_f64(qty),
+            side,
+            order_type: OrderType::Market,
+            limit_price: None,
+            stop_price: None,
+            reference_price: None,
+            time_in_force: None,
+            extensions: None,
+            persona_id: String::new(),
+        };
+        self.estimate(
+            &request,
+            Some(PriceEstimate {
+                price,
+                source: "fill",
+            }),
+        )
+    }
+}
+
+/// Regulatory fees are rounded up to the next cent
+fn ceil_cents(amount: Decimal) -> Decimal {
+    (amount * Decimal::ONE_HUNDRED).ceil() / Decimal::ONE_HUNDRED
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+
+    fn at(price: i64) -> Option<PriceEstimate> {
+        Some(PriceEstimate {
+            price: Decimal::from(price),
+            source: "limit_price",
+        })
+    }
+
+    #[test]
+    fn asset_class_wins_over_the_symbol_shape() {
+        assert_eq!(AssetKind::of("BRK.B"), AssetKind::Equity);
+        assert_eq!(AssetKind::of("BTC/USD"), AssetKind::Crypto);
+        assert_eq!(AssetKind::of("AAPL240119C00150000"), AssetKind::Option);
+        // Too short for an OCC symbol
+        assert_eq!(AssetKind::of("C00150000"), AssetKind::Equity);
+
+        // Alpaca's crypto symbols come without the slash on some endpoints
+        assert_eq!(
+            AssetKind::from_class(Some("crypto"), "BTCUSD"),
+            AssetKind::Crypto
+        );
+        assert_eq!(
+            AssetKind::from_class(Some("us_option"), "SPY240621P00500000"),
+            AssetKind::Option
+        );
+        // Missing or unknown classes fall back to the symbol
+        assert_eq!(AssetKind::from_class(None, "ETH/USD"), AssetKind::Crypto);
+        assert_eq!(AssetKind::from_class(Some("fx"), "AAPL"), AssetKind::Equity);
+        assert_eq!(AssetKind::from_class(None, "AAPL").name(), "us_equity");
+    }
+
+    #[test]
+    fn equity_sells_pay_sec_and_taf() {
+        let schedule = FeeSchedule::default();
+        let sell = schedule.estimate(
+            &order_request("AAPL")
+                .side(OrderSide::Sell)
+                .qty(100.0)
+                .build(),
+            at(200),
+        );
+        assert_eq!(sell["notional"], "20000");
+        // 20000 * 27.80 / 1M = 0.556 -> 0.56; 100 * 0.000166 = 0.0166 -> 0.02
+        assert_eq!(sell["fees"]["sec"], "0.56");
+        assert_eq!(sell["fees"]["taf"], "0.02");
+        assert_eq!(sell["all_in"], "19999.42");
+
+        let buy = schedule.estimate(
+            &order_request("AAPL")
+                .side(OrderSide::Buy)
+                .qty(100.0)
+                .build(),
+            at(200),
+        );
+        assert_eq!(buy["total_fees"], "0");
+
+        // The TAF cap applies to very large share counts
+        let big = schedule.estimate(
+            &order_request("F")
+                .side(OrderSide::Sell)
+                .qty(100_000.0)
+                .build(),
+            None,
+        );
+        assert_eq!(big["fees"]["taf"], "8.3");
+        assert_eq!(big["complete"], false);
+    }
+
+    #[test]
+    fn crypto_and_options_use_their_own_schedules() {
+        let schedule = FeeSchedule::default();
+        let crypto = schedule.estimate(
+            &order_request("BTC/USD")
+                .side(OrderSide::Buy)
+                .qty(0.5)
+                .build(),
+            at(60000),
+        );
+        assert_eq!(crypto["asset_class"], "crypto");
+        assert_eq!(crypto["fees"]["crypto"], "75");
+
+        let option = schedule.estimate(
+            &order_request("AAPL240119C00150000")
+                .side(OrderSide::Sell)
+                .order_type(OrderType::Limit)
+                .qty(10.0)
+                .build(),
+            at(2),
+        );
+        assert_eq!(option["asset_class"], "us_option");
+        assert_eq!(option["notional"], "2000");
+        assert_eq!(option["fees"]["orf"], "0.27");
+        assert_eq!(option["fees"]["occ"], "0.2");
+        assert_eq!(option["fees"]["taf"], "0.03");
+        assert!(FeeSchedule::from_config(Some(&serde_json::json!(false)))
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,318 @@
+//! Golden-file contract tests for Alpaca response parsing
+//!
+//! Each fixture in `testdata/alpaca/` is served through the mock transport,
+//! parsed by the real client, and the result compared with its golden file
+//! in `testdata/golden/`. A renamed or retyped Alpaca field then shows up
+//! as a failing diff instead of a silent zero. After an intended mapping
+//! change, rewrite the goldens with `UPDATE_GOLDEN=1 cargo test golden`.
+//!
+//! `testdata/alpaca/paper/` holds raw captures from the paper API, written
+//! by the `refresh-fixtures` feature (see [`refresh`]). Their contents
+//! change with the account, so they are only checked to parse.
+
+use crate::alpaca::{AlpacaClient, ClientOptions};
+use crate::http::HttpMethod;
+use crate::mock::{fixture, order_request, MockTransport};
+use models::order::Order;
+use models::portfolio::Position;
+use serde_json::{json, Value};
+
+fn client(mock: &MockTransport) -> AlpacaClient {
+    AlpacaClient::with_transport(
+        "key".to_string(),
+        "secret".to_string(),
+        true,
+        ClientOptions::default(),
+        mock.clone(),
+    )
+}
+
+fn assert_golden(name: &str, actual: Value) {
+    let path = format!(
+        "{}/testdata/golden/{}.json",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    );
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        let pretty = serde_json::to_string_pretty(&actual).unwrap();
+        std::fs::write(&path, pretty + "\n").unwrap();
+        return;
+    }
+    let expected: Value = std::fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|s| serde_json::from_str(&s).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| panic!("golden {}: {} (create it with UPDATE_GOLDEN=1)", path, e));
+    assert!(
+        expected == actual,
+        "{} no longer matches {}; rerun with UPDATE_GOLDEN=1 if the change is intended\nactual: {}",
+        name,
+        path,
+        serde_json::to_string_pretty(&actual).unwrap()
+    );
+}
+
+/// Enum fields are recorded with their Rust names so the goldens do not
+/// depend on how `models` chooses to serialize them
+fn order_golden(order: &Order) -> Value {
+    json!({
+        "id": order.id,
+        "symbol": order.request.symbol_id,
+        "side": format!("{:?}", order.request.side),
+        "order_type": format!("{:?}", order.request.order_type),
+        "quantity": order.request.quantity,
+        "limit_price": order.request.limit_price,
+        "stop_price": order.request.stop_price,
+        "status": format!("{:?}", order.status),
+        "filled_quantity": order.filled_quantity,
+        "average_filled_price": order.average_filled_price,
+        "created_at": order.created_at.to_rfc3339(),
+        "updated_at": order.updated_at.to_rfc3339(),
+        "extensions": order.extensions,
+    })
+}
+
+fn position_golden(position: &Position) -> Value {
+    json!({
+        "symbol": position.symbol_id,
+        "quantity": position.quantity,
+        "average_price": position.average_price,
+        "current_price": position.current_price,
+        "unrealized_pnl": position.unrealized_pnl,
+        "unrealized_pnl_percent": position.unrealized_pnl_percent,
+    })
+}
+
+/// Serve every order in a fixture array under `/v2/orders/{id}`
+fn mock_orders(mock: &MockTransport, orders: &[Value]) {
+    for order in orders {
+        let id = order["id"].as_str().unwrap();
+        mock.on(
+            HttpMethod::Get,
+            &format!("/v2/orders/{}", id),
+            crate::mock::response(200, order.to_string()),
+        );
+    }
+}
+
+fn parse_orders(name: &str) -> Vec<Value> {
+    let orders: Vec<Value> = serde_json::from_str(&fixture(name)).unwrap();
+    let mock = MockTransport::new();
+    mock_orders(&mock, &orders);
+    let client = client(&mock);
+    orders
+        .iter()
+        .map(|o| {
+            let id = o["id"].as_str().unwrap();
+            let order = client
+                .get_order(id)
+                .unwrap_or_else(|e| panic!("{} order {}: {}", name, id, e));
+            order_golden(&order)
+        })
+        .collect()
+}
+
+#[test]
+fn golden_orders_all_statuses() {
+    assert_golden(
+        "orders_all_statuses",
+        Value::Array(parse_orders("orders_all_statuses")),
+    );
+}
+
+#[test]
+fn golden_orders_nested_list() {
+    // The list endpoint maps each order exactly as the single-order one
+    let mock = MockTransport::new();
+    mock.on_fixture(HttpMethod::Get, "/v2/orders", 200, "orders_all_statuses");
+    let orders = client(&mock).list_orders("all", None, true).unwrap();
+    let request = &mock.requests_to(HttpMethod::Get, "/v2/orders")[0];
+    assert!(request.url.contains("nested=true"));
+    assert_golden(
+        "orders_all_statuses",
+        Value::Array(orders.iter().map(order_golden).collect()),
+    );
+}
+
+#[test]
+fn golden_order_option() {
+    let mock = MockTransport::new();
+    mock.on_fixture(
+        HttpMethod::Get,
+        "/v2/orders/3c6a9e12-7f4b-4d28-a1e5-8b0c2d4f6a97",
+        200,
+        "order_option",
+    );
+    let order = client(&mock)
+        .get_order("3c6a9e12-7f4b-4d28-a1e5-8b0c2d4f6a97")
+        .unwrap();
+    assert_golden("order_option", order_golden(&order));
+}
+
+#[test]
+fn golden_positions() {
+    for name in ["positions", "positions_crypto_and_options"] {
+        let mock = MockTransport::new();
+        mock.on_fixture(HttpMethod::Get, "/v2/positions", 200, name);
+        let client = client(&mock);
+        let set = client
+            .fetch_positions()
+            .unwrap_or_else(|e| panic!("{}: {}", name, e));
+        let golden = set
+            .positions
+            .iter()
+            .map(|p| {
+                let extras = &set.extras[&p.symbol_id];
+                let mut golden = position_golden(p);
+                golden["asset"] = extras.asset.clone();
+                golden["pnl"] = extras.pnl.clone();
+                golden
+            })
+            .collect();
+        assert_golden(name, golden);
+    }
+}
+
+#[test]
+fn golden_account() {
+    let mock = MockTransport::new();
+    mock.on_fixture(HttpMethod::Get, "/v2/account", 200, "account")
+        .on_fixture(HttpMethod::Get, "/v2/positions", 200, "positions");
+    let account = client(&mock).get_account().unwrap();
+    // `updated_at` is the time of the call, so it is left out
+    assert_golden(
+        "account",
+        json!({
+            "id": account.id,
+            "name": account.name,
+            "is_paper": account.is_paper,
+            "currency": account.balance.currency,
+            "total_equity": account.balance.total_equity,
+            "available_cash": account.balance.available_cash,
+            "buying_power": account.balance.buying_power,
+            "positions": account.positions.len(),
+            "extensions": account.extensions,
+        }),
+    );
+}
+
+#[test]
+fn golden_error_bodies() {
+    let request = order_request("AAPL").qty(10.0).build();
+    let cases = [
+        ("error_insufficient_buying_power", 403),
+        ("error_wash_trade", 403),
+        ("error_unauthorized", 401),
+        ("error_invalid_qty", 422),
+        ("error_unprocessable", 422),
+        ("error_rate_limited", 429),
+    ];
+    let mut errors = serde_json::Map::new();
+    for (name, status) in cases {
+        let mock = MockTransport::new();
+        mock.on_fixture(HttpMethod::Post, "/v2/orders", status, name);
+        let err = client(&mock).submit_order(&request).unwrap_err();
+        // Alpaca's error bodies must stay parseable JSON with a code and
+        // message, since callers surface the message to users
+        let body: Value = serde_json::from_str(&fixture(name)).unwrap();
+        assert!(
+            body["code"].is_u64() && body["message"].is_string(),
+            "{}",
+            name
+        );
+        errors.insert(name.to_string(), err.into());
+    }
+    assert_golden("error_bodies", Value::Object(errors));
+}
+
+#[test]
+fn paper_captures_parse() {
+    let dir = format!("{}/testdata/alpaca/paper", env!("CARGO_MANIFEST_DIR"));
+    let Ok(account) = std::fs::read_to_string(format!("{}/account.json", dir)) else {
+        return;
+    };
+    let read = |name: &str| std::fs::read_to_string(format!("{}/{}.json", dir, name)).unwrap();
+
+    let mock = MockTransport::new();
+    mock.on(
+        HttpMethod::Get,
+        "/v2/account",
+        crate::mock::response(200, account),
+    )
+    .on(
+        HttpMethod::Get,
+        "/v2/positions",
+        crate::mock::response(200, read("positions")),
+    );
+    let orders: Vec<Value> = serde_json::from_str(&read("orders")).unwrap();
+    mock_orders(&mock, &orders);
+
+    let client = client(&mock);
+    client.get_account().expect("paper account");
+    client.get_positions().expect("paper positions");
+    for order in &orders {
+        let id = order["id"].as_str().unwrap();
+        client
+            .get_order(id)
+            .unwrap_or_else(|e| panic!("paper order {}: {}", id, e));
+    }
+}
+
+/// Refresh the raw paper captures:
+///
+/// ```bash
+/// APCA_API_KEY_ID=... APCA_API_SECRET_KEY=... \
+///     cargo test --features refresh-fixtures refresh_paper_fixtures -- --ignored
+/// ```
+#[cfg(feature = "refresh-fixtures")]
+mod refresh {
+    use super::*;
+
+    const PAPER_API_URL: &str = "https://paper-api.alpaca.markets";
+
+    fn fetch(client: &reqwest::blocking::Client, path: &str) -> Value {
+        let key = std::env::var("APCA_API_KEY_ID").expect("APCA_API_KEY_ID");
+        let secret = std::env::var("APCA_API_SECRET_KEY").expect("APCA_API_SECRET_KEY");
+        let response = client
+            .get(format!("{}{}", PAPER_API_URL, path))
+            .header("APCA-API-KEY-ID", key)
+            .header("APCA-API-SECRET-KEY", secret)
+            .send()
+            .unwrap_or_else(|e| panic!("GET {}: {}", path, e));
+        let status = response.status();
+        let body = response.text().unwrap();
+        assert!(status.is_success(), "GET {}: {} {}", path, status, body);
+        serde_json::from_str(&body).unwrap()
+    }
+
+    #[test]
+    #[ignore]
+    fn refresh_paper_fixtures() {
+        let dir = format!("{}/testdata/alpaca/paper", env!("CARGO_MANIFEST_DIR"));
+        std::fs::create_dir_all(&dir).unwrap();
+        let client = reqwest::blocking::Client::new();
+
+        let mut account = fetch(&client, "/v2/account");
+        // Keep the account's identity out of the repository
+        account["id"] = "00000000-0000-0000-0000-000000000000".into();
+        account["account_number"] = "PA0000000000".into();
+        let captures = [
+            ("account", account),
+            ("positions", fetch(&client, "/v2/positions")),
+            (
+                "orders",
+                fetch(
+                    &client,
+                    &crate::http::QueryParams::new()
+                        .push("status", "all")
+                        .push("limit", 500)
+                        .push("nested", true)
+                        .apply("/v2/orders"),
+                ),
+            ),
+        ];
+        for (name, value) in captures {
+            let pretty = serde_json::to_string_pretty(&value).unwrap();
+            std::fs::write(format!("{}/{}.json", dir, name), pretty + "\n").unwrap();
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Good-faith violations and free-riding
+//!
+//! On a cash account, shares bought with unsettled sale proceeds must be
+//! held until those proceeds settle; selling them earlier is a good-faith
+//! violation. Shares bought with no funds at all and sold before the
+//! purchase is paid for is free-riding. Three good-faith violations in 12
+//! months, or one free-ride, restrict the account for 90 days.
+//!
+//! With `gfv_policy` set, sells on a cash account are compared with the
+//! recent fills. Walking back from today's settled cash (see
+//! [`crate::settlement`]), each buy's settled balance is rebuilt and the
+//! part it could not cover is put down to the sale proceeds still
+//! unsettled at the time, and beyond them to nothing. A sell that reaches
+//! into shares funded that way before their funds settle yields a
+//! `good_faith_violation` or `free_riding` finding. Settled shares are
+//! taken to be sold first.
+
+use crate::alpaca::CalendarDay;
+use crate::confirmations;
+use crate::decimal::{self, Decimal};
+use crate::executions::Execution;
+use crate::fees::AssetKind;
+use crate::market_time;
+use crate::pretrade::{CheckMode, CheckOutcome, Finding};
+use chrono::{DateTime, NaiveDate, Utc};
+use models::order::{OrderRequest, OrderSide};
+use serde::Serialize;
+
+/// Shares of a buy paid for with funds that had not settled
+#[derive(Clone, Debug, Serialize)]
+pub struct FundedBuy {
+    pub execution_id: String,
+    pub symbol: String,
+    pub bought_at: DateTime<Utc>,
+    /// Shares paid for with unsettled sale proceeds
+    pub unsettled_qty: Decimal,
+    /// Shares paid for with no funds at all
+    pub unfunded_qty: Decimal,
+    /// When the funds behind the shares settle; the shares can be sold
+    /// freely from then on
+    pub settles_on: NaiveDate,
+}
+
+struct Flow<'a> {
+    fill: &'a Execution,
+    buy: bool,
+    qty: Decimal,
+    amount: Decimal,
+    trade_date: NaiveDate,
+    settlement_date: NaiveDate,
+}
+
+/// Buys in `fills` (oldest first) that were paid for with unsettled or
+/// no funds, given the settled balance `today`, which is negative when
+/// unsettled proceeds have been spent
+pub fn funded_buys(
+    fills: &[Execution],
+    calendar: &[CalendarDay],
+    settled_now: Decimal,
+    today: NaiveDate,
+) -> Vec<FundedBuy> {
+    let flows: Vec<Flow> = fills
+        .iter()
+        .map(|fill| {
+            let kind = AssetKind::of(&fill.symbol);
+            let contract_size = match kind {
+                AssetKind::Option => Decimal::ONE_HUNDRED,
+                _ => Decimal::ONE,
+            };
+            let qty = decimal::parse_or_zero(&fill.qty);
+            let trade_date = market_time::eastern_date(fill.timestamp);
+            Flow {
+                fill,
+                buy: !fill.side.starts_with("sell"),
+                qty,
+                amount: qty * decimal::parse_or_zero(&fill.price) * contract_size,
+                trade_date,
+                settlement_date: confirmations::settlement_date(kind, trade_date, calendar),
+            }
+        })
+        .collect();
+
+    let mut funded = Vec::new();
+    for (i, buy) in flows.iter().enumerate().filter(|(_, f)| f.buy) {
+        if buy.amount.is_zero() {
+            continue;
+        }
+        // Undo this buy and every later one, and the proceeds that
+        // settled since it was made
+        let later_buys: Decimal = flows[i..].iter().filter(|f| f.buy).map(|f| f.amount).sum();
+        let settled_since: Decimal = flows
+            .iter()
+            .filter(|f| !f.buy && f.settlement_date > buy.trade_date)
+            .filter(|f| f.settlement_date <= today)
+            .map(|f| f.amount)
+            .sum();
+        let settled_before = settled_now + later_buys - settled_since;
+        let shortfall =
+            (buy.amount - settled_before.max(Decimal::ZERO)).clamp(Decimal::ZERO, buy.amount);
+        if shortfall.is_zero() {
+            continue;
+        }
+        // Proceeds of earlier sells that had not settled by the buy
+        let pending: Vec<&Flow> = flows[..i]
+            .iter()
+            .filter(|f| !f.buy && f.settlement_date > buy.trade_date)
+            .collect();
+        let available: Decimal = pending.iter().map(|f| f.amount).sum();
+        let from_unsettled = shortfall.min(available);
+        let per_share = buy.amount / buy.qty;
+        // Unfunded shares are free once the purchase itself is paid for
+        let mut settles_on = pending.iter().map(|f| f.settlement_date).max();
+        if shortfall > from_unsettled {
+            settles_on = settles_on.max(Some(buy.settlement_date));
+        }
+        let Some(settles_on) = settles_on else {
+            continue;
+        };
+        funded.push(FundedBuy {
+            execution_id: buy.fill.id.clone(),
+            symbol: buy.fill.symbol.clone(),
+            bought_at: buy.fill.timestamp,
+            unsettled_qty: (from_unsettled / per_share).round_dp(9),
+            unfunded_qty: ((shortfall - from_unsettled) / per_share).round_dp(9),
+            settles_on,
+        });
+    }
+    funded
+}
+
+pub fn check(
+    mode: CheckMode,
+    order: &OrderRequest,
+    held: Decimal,
+    funded: &[FundedBuy],
+    today: NaiveDate,
+) -> CheckOutcome {
+    if mode == CheckMode::Off || order.side != OrderSide::Sell || held <= Decimal::ZERO {
+        return CheckOutcome::Pass;
+    }
+    let at_risk: Vec<&FundedBuy> = funded
+        .iter()
+        .filter(|b| b.settles_on > today && b.symbol.eq_ignore_ascii_case(&order.symbol_id))
+        .collect();
+    let unsettled: Decimal = at_risk.iter().map(|b| b.unsettled_qty).sum();
+    let unfunded: Decimal = at_risk.iter().map(|b| b.unfunded_qty).sum();
+    let restricted = (unsettled + unfunded).min(held);
+    let qty = decimal::from_f64(order.quantity).unwrap_or_default();
+    let free = held - restricted;
+    if restricted.is_zero() || qty <= free {
+        return CheckOutcome::Pass;
+    }
+
+    let (code, message) = if unfunded > Decimal::ZERO {
+        (
+            "free_riding",
+            "This sell would dispose of shares bought without funds before the purchase is paid for",
+        )
+    } else {
+        (
+            "good_faith_violation",
+            "This sell would dispose of shares bought with unsettled funds before those funds settle",
+        )
+    };
+    let settles_on = at_risk.iter().map(|b| b.settles_on).max();
+    mode.apply(Finding::new(
+        code,
+        message,
+        serde_json::json!({
+            "symbol": order.symbol_id,
+            "qty": decimal::to_wire(qty),
+            "held_qty": decimal::to_wire(held),
+            "settled_qty": decimal::to_wire(free),
+            "unsettled_qty": decimal::to_wire(unsettled),
+            "unfunded_qty": decimal::to_wire(unfunded),
+            "settles_on": settles_on,
+            "buys": at_risk,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+
+    fn fill(side: &str, qty: &str, price: &str, timestamp: &str) -> Execution {
+        Execution {
+            id: format!("{}-{}", side, timestamp),
+            order_id: "o1".to_string(),
+            symbol: "AAPL".to_string(),
+            side: side.to_string(),
+            qty: qty.to_string(),
+            price: price.to_string(),
+            timestamp: timestamp.parse().unwrap(),
+            fill_type: "fill".to_string(),
+            cum_qty: None,
+            leaves_qty: None,
+            venue: None,
+        }
+    }
+
+    #[test]
+    fn selling_shares_bought_with_unsettled_proceeds_is_flagged() {
+        // Monday's sell settles Tuesday; a later buy that day spent its
+        // proceeds on top of $500 of settled cash
+        let date = |s: &str| s.parse::<NaiveDate>().unwrap();
+        let (monday, tuesday) = (date("2024-03-04"), date("2024-03-05"));
+        let calendar = [CalendarDay {
+            date: monday,
+            settlement_date: Some(tuesday),
+            close: None,
+        }];
+        let fills = [
+            fill("sell", "10", "100", "2024-03-04T15:00:00Z"),
+            fill("buy", "15", "100", "2024-03-04T16:00:00Z"),
+        ];
+        // Settled cash was 500; the buy took it to -1000
+        let funded = funded_buys(&fills, &calendar, Decimal::from(-1000), monday);
+        assert_eq!(funded.len(), 1);
+        assert_eq!(funded[0].unsettled_qty, Decimal::from(10));
+        assert_eq!(funded[0].unfunded_qty, Decimal::ZERO);
+        assert_eq!(funded[0].settles_on, tuesday);
+
+        let mut order = order_request("AAPL").side(OrderSide::Sell).qty(5.0).build();
+        let held = Decimal::from(15);
+        assert!(matches!(
+            check(CheckMode::Warn, &order, held, &funded, monday),
+            CheckOutcome::Pass
+        ));
+        order.quantity = 6.0;
+        let CheckOutcome::Block(finding) = check(CheckMode::Enforce, &order, held, &funded, monday)
+        else {
+            panic!("expected a block");
+        };
+        assert_eq!(finding.code, "good_faith_violation");
+        assert_eq!(finding.details["settled_qty"], "5");
+        // Free to sell once the proceeds settle
+        assert!(matches!(
+            check(CheckMode::Enforce, &order, held, &funded, tuesday),
+            CheckOutcome::Pass
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+//! Good-til-date emulation
+//!
+//! Alpaca has no GTD time in force. An order with `extensions.expire_at`
+//! is sent as GTC, its expiry is recorded here, and `poll_order_updates`
+//! cancels it once the expiry passes. Such orders report the same
+//! terminal state as a natively expired order: status `Canceled` with
+//! `status_reason: "expired"`.
+
+use crate::alpaca;
+use crate::market_time;
+use crate::orders;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use models::order::{Order, OrderRequest, OrderStatus};
+use std::collections::HashMap;
+
+/// Regular-session close, used when `expire_at` is a bare date
+const MARKET_CLOSE: (u32, u32) = (16, 0);
+
+/// Read `extensions.expire_at`: an RFC 3339 timestamp, or a date meaning
+/// that day's 16:00 US Eastern close. `Ok(None)` when absent.
+pub fn requested_expiry(order: &OrderRequest) -> Result<Option<DateTime<Utc>>, String> {
+    let raw = match order.extensions.as_ref().and_then(|e| e.get("expire_at")) {
+        None | Some(serde_json::Value::Null) => return Ok(None),
+        Some(v) => v
+            .as_str()
+            .ok_or_else(|| format!("Invalid expire_at: {}", v))?,
+    };
+
+    let expiry = if let Ok(dt) = DateTime::parse_from_rfc3339(raw) {
+        dt.with_timezone(&Utc)
+    } else if let Ok(date) = NaiveDate::parse_from_str(raw, "%Y-%m-%d") {
+        let close = NaiveTime::from_hms_opt(MARKET_CLOSE.0, MARKET_CLOSE.1, 0).expect("valid time");
+        market_time::eastern_at(date, close)
+    } else {
+        return Err(format!(
+            "Invalid expire_at: {} (expected an RFC 3339 timestamp or YYYY-MM-DD)",
+            raw
+        ));
+    };
+
+    if expiry <= Utc::now() {
+        return Err(format!("expire_at {} is in the past", raw));
+    }
+    match alpaca::requested_time_in_force(order)? {
+        "day" | "gtc" => Ok(Some(expiry)),
+        other => Err(format!(
+            "expire_at cannot be combined with time_in_force {}",
+            other
+        )),
+    }
+}
+
+/// The request to send for a GTD order: the same order as GTC
+pub fn as_gtc(order: &OrderRequest) -> OrderRequest {
+    let mut gtc = order.clone();
+    gtc.extensions
+        .get_or_insert_with(HashMap::new)
+        .insert("time_in_force".to_string(), "gtc".into());
+    gtc
+}
+
+/// Expiries of working GTD orders, keyed by order ID
+#[derive(Default)]
+pub struct GtdBook {
+    expiries: HashMap<String, DateTime<Utc>>,
+}
+
+impl GtdBook {
+    pub fn insert(&mut self, order_id: &str, expiry: DateTime<Utc>) {
+        self.expiries.insert(order_id.to_string(), expiry);
+    }
+
+    pub fn remove(&mut self, order_id: &str) {
+        self.expiries.remove(order_id);
+    }
+
+    /// Orders whose expiry is at or before `now`
+    pub fn due(&self, now: DateTime<Utc>) -> Vec<String> {
+        let mut due: Vec<_> = self
+            .expiries
+            .iter()
+            .filter(|(_, expiry)| **expiry <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+        due.sort();
+        due
+    }
+}
+
+/// Whether the plugin has asked Alpaca to cancel this order on expiry
+pub fn cancel_requested(order: &Order) -> bool {
+    order
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.get("gtd_cancel_requested"))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
+
+/// Report a GTD order canceled on expiry as expired. An order that
+/// filled before the cancel landed keeps its status.
+pub fn mark_expired(order: &mut Order) {
+    if order.status == OrderStatus::Canceled {
+        orders::set_ext(order, "status_reason", "expired");
+        orders::set_ext(order, "gtd_expired", true);
+    }
+}
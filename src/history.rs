@@ -0,0 +1,297 @@
+//! Position history
+//!
+//! With `position_history` configured, `tick` snapshots the account's
+//! holdings every `interval_minutes` into the host key-value store (see
+//! [`crate::kv`]), one snapshot per entry under `key`. `get_positions_at`
+//! then answers what was held at a past time: it starts from the nearest
+//! snapshot at or before that time and replays the fills after it, or,
+//! when the time is older than every snapshot, starts from the earliest
+//! one and unwinds the fills before it. The current positions count as a
+//! snapshot taken now, so recent times work before any snapshot is stored.
+
+use crate::decimal::{self, Decimal};
+use crate::executions::Execution;
+use crate::kv;
+use chrono::{DateTime, Duration, Utc};
+use models::portfolio::Position;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const DEFAULT_KEY: &str = "alpaca-positions";
+const DEFAULT_INTERVAL_MINUTES: u64 = 15;
+/// Snapshots loaded back at startup when `max_snapshots` is not set
+const DEFAULT_MAX_SNAPSHOTS: usize = 2000;
+const MAX_SNAPSHOTS_LIMIT: usize = 50_000;
+
+#[derive(Clone, Debug)]
+pub struct PositionHistoryConfig {
+    pub interval: Duration,
+    pub key: String,
+    pub max_snapshots: usize,
+}
+
+impl PositionHistoryConfig {
+    /// `true` for the defaults (every 15 minutes under `alpaca-positions`),
+    /// or an object overriding them
+    pub fn from_config(value: Option<&serde_json::Value>) -> Result<Option<Self>, String> {
+        let empty = serde_json::Map::new();
+        let object = match value {
+            None | Some(serde_json::Value::Null) | Some(serde_json::Value::Bool(false)) => {
+                return Ok(None)
+            }
+            Some(serde_json::Value::Bool(true)) => &empty,
+            Some(serde_json::Value::Object(object)) => object,
+            Some(_) => return Err("position_history must be true or an object".to_string()),
+        };
+        let interval = match object.get("interval_minutes") {
+            None => DEFAULT_INTERVAL_MINUTES,
+            Some(v) => v
+                .as_u64()
+                .filter(|m| (1..=1440).contains(m))
+                .ok_or("position_history.interval_minutes must be 1 to 1440")?,
+        };
+        let key = match object.get("key") {
+            None => DEFAULT_KEY.to_string(),
+            Some(v) => v
+                .as_str()
+                .filter(|k| !k.is_empty() && !k.contains('/'))
+                .ok_or("position_history.key must be a non-empty string without '/'")?
+                .to_string(),
+        };
+        let max_snapshots = match object.get("max_snapshots") {
+            None => DEFAULT_MAX_SNAPSHOTS,
+            Some(v) => v
+                .as_u64()
+                .filter(|n| (1..=MAX_SNAPSHOTS_LIMIT as u64).contains(n))
+                .ok_or_else(|| {
+                    format!(
+                        "position_history.max_snapshots must be 1 to {}",
+                        MAX_SNAPSHOTS_LIMIT
+                    )
+                })? as usize,
+        };
+        Ok(Some(Self {
+            interval: Duration::minutes(interval as i64),
+            key,
+            max_snapshots,
+        }))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Holding {
+    pub symbol: String,
+    /// Signed; negative for shorts
+    pub qty: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PositionSnapshot {
+    pub at: DateTime<Utc>,
+    pub holdings: Vec<Holding>,
+}
+
+impl PositionSnapshot {
+    pub fn of(positions: &[Position], at: DateTime<Utc>) -> Self {
+        let holdings = positions
+            .iter()
+            .filter_map(|p| {
+                let qty = decimal::from_f64(p.quantity)?;
+                Some(Holding {
+                    symbol: p.symbol_id.clone(),
+                    qty: decimal::to_wire(qty),
+                })
+            })
+            .collect();
+        Self { at, holdings }
+    }
+}
+
+/// Snapshots stored so far, oldest first
+pub struct PositionHistory {
+    pub config: PositionHistoryConfig,
+    snapshots: Vec<PositionSnapshot>,
+    /// Entries under `key`, including ones no longer loaded
+    stored: usize,
+    pub store_error: Option<String>,
+}
+
+impl PositionHistory {
+    /// Load the most recent `max_snapshots` snapshots from the store
+    pub fn load(config: PositionHistoryConfig) -> Result<Self, String> {
+        let stored = kv::entry_count(&config.key, "position history")?;
+        let first = (stored + 1).saturating_sub(config.max_snapshots);
+        let snapshots = kv::load_entries_from(&config.key, "position history", first)?;
+        Ok(Self {
+            config,
+            snapshots,
+            stored,
+            store_error: None,
+        })
+    }
+
+    pub fn snapshots(&self) -> &[PositionSnapshot] {
+        &self.snapshots
+    }
+
+    /// Whether a snapshot is due at `now`
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.snapshots
+            .last()
+            .is_none_or(|last| now - last.at >= self.config.interval)
+    }
+
+    /// Keep `snapshot` in memory and in the store. A store failure is kept
+    /// in `store_error` and the snapshot is still used.
+    pub fn record(&mut self, snapshot: PositionSnapshot) {
+        let n = self.stored + 1;
+        match kv::append_entry(&self.config.key, n, &snapshot) {
+            Ok(()) => {
+                self.stored = n;
+                self.store_error = None;
+            }
+            Err(e) => self.store_error = Some(e),
+        }
+        self.snapshots.push(snapshot);
+        if self.snapshots.len() > self.config.max_snapshots {
+            self.snapshots.remove(0);
+        }
+    }
+}
+
+/// Holdings reconstructed for a past time
+#[derive(Clone, Debug, Serialize)]
+pub struct PositionsAt {
+    pub at: DateTime<Utc>,
+    pub holdings: Vec<Holding>,
+    /// Time of the snapshot the reconstruction started from
+    pub snapshot_at: DateTime<Utc>,
+    /// Fills replayed forward from, or unwound back from, the snapshot
+    pub fills_applied: usize,
+}
+
+/// Signed quantity change a fill made to its symbol's position
+fn fill_delta(fill: &Execution) -> Decimal {
+    let qty = decimal::parse_or_zero(&fill.qty);
+    if fill.side.starts_with("sell") {
+        -qty
+    } else {
+        qty
+    }
+}
+
+/// Holdings at `at`, from the nearest of `snapshots` and `fills` (oldest
+/// first, covering the gap between them). `None` without any snapshot.
+pub fn positions_at(
+    snapshots: &[PositionSnapshot],
+    fills: &[Execution],
+    at: DateTime<Utc>,
+) -> Option<PositionsAt> {
+    let before = snapshots.iter().filter(|s| s.at <= at).max_by_key(|s| s.at);
+    let (base, forward) = match before {
+        Some(base) => (base, true),
+        None => (snapshots.iter().min_by_key(|s| s.at)?, false),
+    };
+    let mut qty: BTreeMap<String, Decimal> = base
+        .holdings
+        .iter()
+        .map(|h| (h.symbol.clone(), decimal::parse_or_zero(&h.qty)))
+        .collect();
+    let mut fills_applied = 0;
+    for fill in fills {
+        let (in_gap, sign) = match forward {
+            true => (
+                fill.timestamp > base.at && fill.timestamp <= at,
+                Decimal::ONE,
+            ),
+            false => (
+                fill.timestamp > at && fill.timestamp <= base.at,
+                -Decimal::ONE,
+            ),
+        };
+        if in_gap {
+            *qty.entry(fill.symbol.clone()).or_default() += sign * fill_delta(fill);
+            fills_applied += 1;
+        }
+    }
+    let holdings = qty
+        .into_iter()
+        .filter(|(_, q)| !q.is_zero())
+        .map(|(symbol, q)| Holding {
+            symbol,
+            qty: decimal::to_wire(q),
+        })
+        .collect();
+    Some(PositionsAt {
+        at,
+        holdings,
+        snapshot_at: base.at,
+        fills_applied,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn fill(symbol: &str, side: &str, qty: &str, timestamp: &str) -> Execution {
+        Execution {
+            id: format!("{}-{}", symbol, timestamp),
+            order_id: "o1".to_string(),
+            symbol: symbol.to_string(),
+            side: side.to_string(),
+            qty: qty.to_string(),
+            price: "100".to_string(),
+            timestamp: at(timestamp),
+            fill_type: "fill".to_string(),
+            cum_qty: None,
+            leaves_qty: None,
+            venue: None,
+        }
+    }
+
+    #[test]
+    fn holdings_are_rebuilt_forward_or_backward_from_the_nearest_snapshot() {
+        let config =
+            PositionHistoryConfig::from_config(Some(&serde_json::json!({ "key": "history-test" })))
+                .unwrap()
+                .unwrap();
+        let mut history = PositionHistory::load(config.clone()).unwrap();
+        history.record(PositionSnapshot {
+            at: at("2024-03-01T15:00:00Z"),
+            holdings: vec![Holding {
+                symbol: "AAPL".to_string(),
+                qty: "10".to_string(),
+            }],
+        });
+        assert!(!history.is_due(at("2024-03-01T15:10:00Z")));
+        assert!(history.is_due(at("2024-03-01T15:15:00Z")));
+        // Survives a reload from the store
+        let history = PositionHistory::load(config).unwrap();
+        assert_eq!(history.snapshots().len(), 1);
+
+        let fills = [
+            fill("AAPL", "buy", "5", "2024-03-01T14:00:00Z"),
+            fill("AAPL", "sell", "10", "2024-03-01T16:00:00Z"),
+            fill("MSFT", "buy", "3", "2024-03-01T16:30:00Z"),
+        ];
+        let later = positions_at(history.snapshots(), &fills, at("2024-03-01T17:00:00Z")).unwrap();
+        assert_eq!(later.fills_applied, 2);
+        assert_eq!(
+            later.holdings,
+            [Holding {
+                symbol: "MSFT".to_string(),
+                qty: "3".to_string()
+            }]
+        );
+        let earlier =
+            positions_at(history.snapshots(), &fills, at("2024-03-01T13:00:00Z")).unwrap();
+        assert_eq!(earlier.snapshot_at, at("2024-03-01T15:00:00Z"));
+        assert_eq!(earlier.holdings[0].qty, "5");
+        assert!(positions_at(&[], &fills, at("2024-03-01T13:00:00Z")).is_none());
+    }
+}
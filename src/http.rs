@@ -1,25 +1,51 @@
 //! HTTP client wrapper for host function calls
 //!
 //! This module provides HTTP functionality through WASM host functions.
+//! Outgoing requests pass through a [`Pipeline`] of [`Middleware`] steps
+//! before reaching an [`HttpTransport`], so cross-cutting behaviour (auth,
+//! rate limiting, retries, logging, metrics) lives in one place instead of
+//! every endpoint. The host import only exists on `wasm32`; native builds
+//! (such as `cargo test`) swap in another transport.
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 // Host function imports
+#[cfg(target_arch = "wasm32")]
 extern "C" {
     fn http_request(ptr: i32, len: i32) -> u64;
 }
 
-#[derive(Clone, Copy, Debug, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum HttpMethod {
     Get,
     Post,
     Put,
+    Patch,
     Delete,
+    Head,
 }
 
-#[derive(Serialize)]
+impl HttpMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpMethod::Get => "GET",
+            HttpMethod::Post => "POST",
+            HttpMethod::Put => "PUT",
+            HttpMethod::Patch => "PATCH",
+            HttpMethod::Delete => "DELETE",
+            HttpMethod::Head => "HEAD",
+        }
+    }
+
+    /// Whether repeating the request cannot create a second side effect.
+    pub fn is_idempotent(&self) -> bool {
+        !matches!(self, HttpMethod::Post | HttpMethod::Patch)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct HttpRequest {
     pub method: HttpMethod,
     pub url: String,
@@ -28,7 +54,24 @@ pub struct HttpRequest {
     pub timeout_ms: u32,
 }
 
-#[derive(Deserialize)]
+impl HttpRequest {
+    /// Path portion of the URL without scheme, host, or query string
+    pub fn path(&self) -> &str {
+        Self::path_of(&self.url)
+    }
+
+    /// [`HttpRequest::path`] for a bare URL
+    pub fn path_of(url: &str) -> &str {
+        let without_scheme = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+        let path = without_scheme
+            .find('/')
+            .map(|i| &without_scheme[i..])
+            .unwrap_or("/");
+        path.split('?').next().unwrap_or(path)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct HttpResponse {
     pub status: u16,
     pub headers: HashMap<String, String>,
@@ -37,6 +80,14 @@ pub struct HttpResponse {
 }
 
 impl HttpResponse {
+    /// Case-insensitive header lookup
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
     pub fn is_success(&self) -> bool {
         self.status >= 200 && self.status < 300
     }
@@ -52,7 +103,186 @@ impl HttpResponse {
     }
 }
 
+/// Ordered query-string builder with RFC 3986 percent-encoding
+#[derive(Clone, Debug, Default)]
+pub struct QueryParams {
+    pairs: Vec<(String, String)>,
+}
+
+impl QueryParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(mut self, key: &str, value: impl ToString) -> Self {
+        self.pairs.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Add the parameter only when a value is present
+    pub fn push_opt<V: ToString>(self, key: &str, value: Option<V>) -> Self {
+        match value {
+            Some(v) => self.push(key, v),
+            None => self,
+        }
+    }
+
+    /// Join values with commas, as Alpaca expects for list parameters
+    pub fn push_list<V: AsRef<str>>(self, key: &str, values: &[V]) -> Self {
+        if values.is_empty() {
+            return self;
+        }
+        let joined = values
+            .iter()
+            .map(|v| v.as_ref())
+            .collect::<Vec<_>>()
+            .join(",");
+        self.push(key, joined)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Encoded query string without the leading `?`
+    pub fn encode(&self) -> String {
+        self.pairs
+            .iter()
+            .map(|(k, v)| format!("{}={}", percent_encode(k), percent_encode(v)))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    /// Append the encoded query to a path
+    pub fn apply(&self, path: &str) -> String {
+        if self.is_empty() {
+            path.to_string()
+        } else {
+            format!("{}?{}", path, self.encode())
+        }
+    }
+}
+
+/// Percent-encode everything outside the RFC 3986 unreserved set
+pub fn percent_encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+/// Delivers a request once the middleware has run, normally through the
+/// host import
+pub trait HttpTransport: Send + Sync {
+    fn send(&self, request: HttpRequest) -> HttpResponse;
+}
+
+impl<F> HttpTransport for F
+where
+    F: Fn(HttpRequest) -> HttpResponse + Send + Sync,
+{
+    fn send(&self, request: HttpRequest) -> HttpResponse {
+        self(request)
+    }
+}
+
+/// The plugin host's `http_request` import
+pub struct HostTransport;
+
+impl HttpTransport for HostTransport {
+    fn send(&self, request: HttpRequest) -> HttpResponse {
+        execute(request)
+    }
+}
+
+/// A single cross-cutting step wrapped around every outgoing request.
+///
+/// Implementations may modify the request, short-circuit with their own
+/// response, or call `next.run` one or more times (e.g. for retries).
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: HttpRequest, next: Next<'_>) -> HttpResponse;
+}
+
+/// Lets the owner keep a handle to a middleware it also installs, e.g. to
+/// run housekeeping on it between requests
+impl<M: Middleware + ?Sized> Middleware for std::sync::Arc<M> {
+    fn handle(&self, request: HttpRequest, next: Next<'_>) -> HttpResponse {
+        (**self).handle(request, next)
+    }
+}
+
+/// The remainder of the pipeline after the current middleware
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    middlewares: &'a [Box<dyn Middleware>],
+    transport: &'a dyn HttpTransport,
+}
+
+impl<'a> Next<'a> {
+    pub fn run(self, request: HttpRequest) -> HttpResponse {
+        match self.middlewares.split_first() {
+            Some((head, tail)) => head.handle(
+                request,
+                Next {
+                    middlewares: tail,
+                    transport: self.transport,
+                },
+            ),
+            None => self.transport.send(request),
+        }
+    }
+}
+
+/// Ordered middleware chain terminating in a transport.
+///
+/// Middlewares run in the order they were added: the first one added sees
+/// the request first and the response last.
+pub struct Pipeline {
+    middlewares: Vec<Box<dyn Middleware>>,
+    transport: Box<dyn HttpTransport>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::with_transport(HostTransport)
+    }
+
+    /// Build a pipeline that delivers through a custom transport
+    pub fn with_transport<T: HttpTransport + 'static>(transport: T) -> Self {
+        Self {
+            middlewares: Vec::new(),
+            transport: Box::new(transport),
+        }
+    }
+
+    pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    pub fn send(&self, request: HttpRequest) -> HttpResponse {
+        Next {
+            middlewares: &self.middlewares,
+            transport: self.transport.as_ref(),
+        }
+        .run(request)
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Execute an HTTP request through the host
+#[cfg(target_arch = "wasm32")]
 pub fn execute(request: HttpRequest) -> HttpResponse {
     let req_json = serde_json::to_string(&request).expect("Failed to serialize request");
     let req_bytes = req_json.as_bytes();
@@ -62,16 +292,85 @@ pub fn execute(request: HttpRequest) -> HttpResponse {
 
     let result = unsafe { http_request(ptr, len) };
 
-    let res_ptr = (result >> 32) as i32;
-    let res_len = (result & 0xFFFFFFFF) as i32;
+    let (res_ptr, res_len) = crate::unpack_ptr_len(result);
 
     let response_slice =
         unsafe { std::slice::from_raw_parts(res_ptr as *const u8, res_len as usize) };
 
-    serde_json::from_slice(response_slice).unwrap_or_else(|e| HttpResponse {
+    let response = serde_json::from_slice(response_slice).unwrap_or_else(|e| HttpResponse {
         status: 0,
         headers: HashMap::new(),
         body: String::new(),
         error: Some(format!("Failed to parse response: {}", e)),
-    })
+    });
+    // The host wrote the response into a buffer from our `alloc`
+    unsafe { crate::arena::free(res_ptr as usize as *mut u8, res_len as usize) };
+    response
+}
+
+/// Without the host import every request fails like an unreachable host
+#[cfg(not(target_arch = "wasm32"))]
+pub fn execute(request: HttpRequest) -> HttpResponse {
+    HttpResponse {
+        status: 0,
+        headers: HashMap::new(),
+        body: String::new(),
+        error: Some(format!(
+            "No HTTP host available for {} {}: the host transport only exists on wasm32",
+            request.method.as_str(),
+            request.url
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_encode_keeps_only_the_unreserved_set() {
+        assert_eq!(percent_encode("AZaz09-_.~"), "AZaz09-_.~");
+        assert_eq!(percent_encode("BRK/B"), "BRK%2FB");
+        assert_eq!(percent_encode("a b&c=d?"), "a%20b%26c%3Dd%3F");
+        assert_eq!(percent_encode("AAPL,MSFT"), "AAPL%2CMSFT");
+        assert_eq!(
+            percent_encode("2024-01-02T09:30:00+00:00"),
+            "2024-01-02T09%3A30%3A00%2B00%3A00"
+        );
+        // Multi-byte characters are encoded byte by byte
+        assert_eq!(percent_encode("é"), "%C3%A9");
+        assert_eq!(percent_encode(""), "");
+    }
+
+    #[test]
+    fn query_params_keep_insertion_order_and_encode_keys_and_values() {
+        let query = QueryParams::new()
+            .push("status", "all")
+            .push("limit", 500)
+            .push("after", "2024-01-02T09:30:00+00:00")
+            .push("odd key", "x&y");
+        assert_eq!(
+            query.encode(),
+            "status=all&limit=500&after=2024-01-02T09%3A30%3A00%2B00%3A00&odd%20key=x%26y"
+        );
+        assert_eq!(
+            query.apply("/v2/orders"),
+            format!("/v2/orders?{}", query.encode())
+        );
+    }
+
+    #[test]
+    fn optional_and_list_params_are_skipped_when_absent() {
+        let query = QueryParams::new()
+            .push_opt("until", None::<&str>)
+            .push_list::<&str>("symbols", &[]);
+        assert!(query.is_empty());
+        assert_eq!(query.encode(), "");
+        assert_eq!(query.apply("/v2/orders"), "/v2/orders");
+
+        let query = QueryParams::new()
+            .push_opt("direction", Some("asc"))
+            .push_list("symbols", &["AAPL", "BRK/B"]);
+        assert_eq!(query.encode(), "direction=asc&symbols=AAPL%2CBRK%2FB");
+    }
 }
@@ -0,0 +1,183 @@
+//! Opening versus closing intent
+//!
+//! Alpaca's `position_intent` says whether an order opens or closes a
+//! position, which matters most for options and shorts where the side
+//! alone is ambiguous. The host sets it as `extensions.position_intent`.
+//! It must agree with the order's side. Before the order is sent, it is
+//! also checked against the current position: a close needs a position
+//! at least as large on the matching side, and an open must not run into
+//! a position in the other direction. Alpaca echoes the intent on the
+//! order, and it is kept in `extensions.position_intent` for PnL
+//! attribution.
+
+use crate::decimal::{self, Decimal};
+use crate::pretrade::{CheckOutcome, Finding};
+use models::order::{OrderRequest, OrderSide};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PositionIntent {
+    BuyToOpen,
+    BuyToClose,
+    SellToOpen,
+    SellToClose,
+}
+
+impl PositionIntent {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "buy_to_open" => Some(Self::BuyToOpen),
+            "buy_to_close" => Some(Self::BuyToClose),
+            "sell_to_open" => Some(Self::SellToOpen),
+            "sell_to_close" => Some(Self::SellToClose),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::BuyToOpen => "buy_to_open",
+            Self::BuyToClose => "buy_to_close",
+            Self::SellToOpen => "sell_to_open",
+            Self::SellToClose => "sell_to_close",
+        }
+    }
+
+    fn side(self) -> OrderSide {
+        match self {
+            Self::BuyToOpen | Self::BuyToClose => OrderSide::Buy,
+            Self::SellToOpen | Self::SellToClose => OrderSide::Sell,
+        }
+    }
+
+    /// Whether an order with this intent is allowed against a signed
+    /// position of `held`
+    fn fits(self, qty: Decimal, held: Decimal) -> bool {
+        match self {
+            Self::BuyToOpen => held >= Decimal::ZERO,
+            Self::SellToOpen => held <= Decimal::ZERO,
+            Self::BuyToClose => -held >= qty,
+            Self::SellToClose => held >= qty,
+        }
+    }
+}
+
+/// The order's intent, if it has one. An intent that contradicts the
+/// order's side is an error.
+pub fn requested(request: &OrderRequest) -> Result<Option<PositionIntent>, String> {
+    let Some(value) = request
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.get("position_intent"))
+        .filter(|v| !v.is_null())
+    else {
+        return Ok(None);
+    };
+    let intent = value.as_str().and_then(PositionIntent::parse).ok_or_else(|| {
+        format!(
+            "Unsupported position_intent: {} (expected buy_to_open, buy_to_close, sell_to_open, or sell_to_close)",
+            value
+        )
+    })?;
+    if intent.side() != request.side {
+        return Err(format!(
+            "position_intent {} does not match the order side",
+            intent.as_str()
+        ));
+    }
+    Ok(Some(intent))
+}
+
+/// Block an order whose intent does not fit the current position
+pub fn check(intent: PositionIntent, request: &OrderRequest, held: Decimal) -> CheckOutcome {
+    let qty = decimal::from_f64(request.quantity).unwrap_or_default();
+    if intent.fits(qty, held) {
+        return CheckOutcome::Pass;
+    }
+    let direction = if held > Decimal::ZERO {
+        "long"
+    } else if held < Decimal::ZERO {
+        "short"
+    } else {
+        "flat"
+    };
+    CheckOutcome::Block(Finding::new(
+        "position_intent_mismatch",
+        format!(
+            "{} of {} does not fit the {} position of {} in {}",
+            intent.as_str(),
+            decimal::to_wire(qty),
+            direction,
+            decimal::to_wire(held.abs()),
+            request.symbol_id
+        ),
+        serde_json::json!({
+            "position_intent": intent.as_str(),
+            "qty": decimal::to_wire(qty),
+            "position_qty": decimal::to_wire(held),
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+
+    #[test]
+    fn intent_must_match_side_and_position() {
+        assert!(requested(
+            &order_request("AAPL240119C00150000")
+                .side(OrderSide::Buy)
+                .qty(1.0)
+                .extension("position_intent", "sell_to_close")
+                .build()
+        )
+        .is_err());
+        assert!(requested(
+            &order_request("AAPL240119C00150000")
+                .side(OrderSide::Buy)
+                .qty(1.0)
+                .extension("position_intent", "buy_to_hold")
+                .build()
+        )
+        .is_err());
+
+        let close = order_request("AAPL240119C00150000")
+            .side(OrderSide::Sell)
+            .qty(5.0)
+            .extension("position_intent", "sell_to_close")
+            .build();
+        let intent = requested(&close).unwrap().unwrap();
+        assert!(matches!(
+            check(intent, &close, Decimal::from(5)),
+            CheckOutcome::Pass
+        ));
+        assert!(matches!(
+            check(intent, &close, Decimal::from(3)),
+            CheckOutcome::Block(_)
+        ));
+
+        let cover = order_request("AAPL240119C00150000")
+            .side(OrderSide::Buy)
+            .qty(2.0)
+            .extension("position_intent", "buy_to_close")
+            .build();
+        let intent = requested(&cover).unwrap().unwrap();
+        assert!(matches!(
+            check(intent, &cover, Decimal::from(-2)),
+            CheckOutcome::Pass
+        ));
+
+        // Opening long while short would cover instead
+        let open = order_request("AAPL240119C00150000")
+            .side(OrderSide::Buy)
+            .qty(1.0)
+            .extension("position_intent", "buy_to_open")
+            .build();
+        let intent = requested(&open).unwrap().unwrap();
+        let CheckOutcome::Block(finding) = check(intent, &open, Decimal::from(-4)) else {
+            panic!("expected a block");
+        };
+        assert_eq!(finding.code, "position_intent_mismatch");
+    }
+}
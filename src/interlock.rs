@@ -0,0 +1,181 @@
+//! Live-trading interlock
+//!
+//! `is_paper: false` alone is not enough to send orders to a live account.
+//! The config must also carry `live_trading_ack: "I_UNDERSTAND"`; until it
+//! does, every live order is rejected locally with `live_interlock`, while
+//! read-only exports and cancels keep working. An optional
+//! `live_max_notional_per_day` caps the notional of live orders accepted
+//! per US Eastern trading day. Paper and simulated accounts are never
+//! affected.
+
+use crate::decimal::{self, Decimal};
+use crate::market_time;
+use crate::pretrade::{self, CheckOutcome, Finding};
+use chrono::NaiveDate;
+use models::order::OrderRequest;
+use models::portfolio::Position;
+
+/// The exact acknowledgment `live_trading_ack` must hold
+pub const ACK: &str = "I_UNDERSTAND";
+
+#[derive(Clone, Debug, Default)]
+pub struct LiveInterlock {
+    pub acknowledged: bool,
+    pub max_notional_per_day: Option<Decimal>,
+    /// Live notional accepted so far, and on which trading day; kept
+    /// across re-initialization
+    used: Option<(NaiveDate, Decimal)>,
+}
+
+impl LiveInterlock {
+    /// Live notional accepted on the current trading day
+    pub fn used_today(&self) -> Decimal {
+        match self.used {
+            Some((date, used)) if date == market_time::eastern_today() => used,
+            _ => Decimal::ZERO,
+        }
+    }
+
+    /// Rejection for any live order while the acknowledgment is missing
+    pub fn rejection(&self, is_paper: bool) -> Option<Finding> {
+        if is_paper || self.acknowledged {
+            return None;
+        }
+        Some(Finding::new(
+            "live_interlock",
+            format!(
+                "Live trading is locked: set live_trading_ack to \"{}\" to accept live orders",
+                ACK
+            ),
+            serde_json::json!({ "missing": "live_trading_ack" }),
+        ))
+    }
+
+    /// Block a live order that would take today's notional past the cap
+    pub fn check(
+        &self,
+        order: &OrderRequest,
+        position: Option<&Position>,
+        is_paper: bool,
+    ) -> CheckOutcome {
+        let Some(max) = self.max_notional_per_day.filter(|_| !is_paper) else {
+            return CheckOutcome::Pass;
+        };
+        let Some(notional) = notional(order, position) else {
+            return CheckOutcome::Block(Finding::new(
+                "live_interlock",
+                "No limit, reference, or position price available to enforce live_max_notional_per_day",
+                serde_json::json!({ "symbol": order.symbol_id }),
+            ));
+        };
+        let used = self.used_today();
+        if used + notional <= max {
+            return CheckOutcome::Pass;
+        }
+        CheckOutcome::Block(Finding::new(
+            "live_interlock",
+            format!(
+                "Order notional {} would take today's live notional past the limit of {} ({} used)",
+                decimal::to_wire(notional),
+                decimal::to_wire(max),
+                decimal::to_wire(used)
+            ),
+            serde_json::json!({
+                "notional": decimal::to_wire(notional),
+                "used_today": decimal::to_wire(used),
+                "limit": decimal::to_wire(max),
+            }),
+        ))
+    }
+
+    /// Count an accepted live order against today's notional, priced as
+    /// `check` priced it (see [`notional`])
+    pub fn record(&mut self, notional: Option<Decimal>, is_paper: bool) {
+        if is_paper {
+            return;
+        }
+        let Some(notional) = notional else {
+            return;
+        };
+        let today = market_time::eastern_today();
+        let used = self.used_today();
+        self.used = Some((today, used + notional));
+    }
+}
+
+/// The order's notional at the price the daily cap is enforced with
+pub fn notional(order: &OrderRequest, position: Option<&Position>) -> Option<Decimal> {
+    let estimate = pretrade::estimate_price(order, position)?;
+    let qty = decimal::from_f64(order.quantity)?;
+    Some((qty * estimate.price).round_dp(2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+
+    #[test]
+    fn live_orders_need_the_ack_and_fit_the_daily_cap() {
+        let mut interlock = LiveInterlock::default();
+        assert!(interlock.rejection(true).is_none());
+        assert_eq!(interlock.rejection(false).unwrap().code, "live_interlock");
+
+        interlock.acknowledged = true;
+        interlock.max_notional_per_day = Some(Decimal::from(1000));
+        assert!(interlock.rejection(false).is_none());
+
+        let first = order_request("AAPL").qty(6.0).limit(100.0).build();
+        assert!(matches!(
+            interlock.check(&first, None, false),
+            CheckOutcome::Pass
+        ));
+        interlock.record(notional(&first, None), false);
+        assert_eq!(interlock.used_today(), Decimal::from(600));
+
+        let second = order_request("AAPL").qty(5.0).limit(100.0).build();
+        assert!(matches!(
+            interlock.check(&second, None, false),
+            CheckOutcome::Block(_)
+        ));
+        // Paper orders are neither capped nor counted
+        assert!(matches!(
+            interlock.check(&second, None, true),
+            CheckOutcome::Pass
+        ));
+        interlock.record(notional(&second, None), true);
+        assert_eq!(interlock.used_today(), Decimal::from(600));
+    }
+
+    #[test]
+    fn market_orders_are_counted_at_the_price_they_were_checked_at() {
+        let mut interlock = LiveInterlock {
+            acknowledged: true,
+            max_notional_per_day: Some(Decimal::from(1000)),
+            ..Default::default()
+        };
+        let mut market = order_request("AAPL").qty(5.0).limit(0.0).build();
+        market.order_type = models::order::OrderType::Market;
+        market.limit_price = None;
+        let position = Position {
+            symbol_id: "AAPL".to_string(),
+            quantity: 10.0,
+            average_price: 90.0,
+            current_price: 120.0,
+            unrealized_pnl: 300.0,
+            unrealized_pnl_percent: 33.3,
+        };
+
+        assert!(matches!(
+            interlock.check(&market, Some(&position), false),
+            CheckOutcome::Pass
+        ));
+        interlock.record(notional(&market, Some(&position)), false);
+        assert_eq!(interlock.used_today(), Decimal::from(600));
+        // The next one is priced the same way and no longer fits
+        assert!(matches!(
+            interlock.check(&market, Some(&position), false),
+            CheckOutcome::Block(_)
+        ));
+    }
+}
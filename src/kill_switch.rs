@@ -0,0 +1,163 @@
+//! Kill switch
+//!
+//! A broker-side emergency stop. While tripped, `submit_order` refuses new
+//! orders; cancels and read-only exports keep working. The host trips and
+//! resets it through `set_trading_enabled`, and it trips itself after too
+//! many consecutive broker rejections or when the account's drawdown from
+//! the previous close crosses a configured threshold.
+
+use crate::alpaca::AlpacaAccount;
+use crate::decimal::{self, Decimal};
+use crate::pretrade::Finding;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Auto-trip thresholds from the `kill_switch` config object
+#[derive(Clone, Debug, Default)]
+pub struct AutoTrip {
+    pub max_consecutive_rejects: Option<u32>,
+    /// Percent drop of equity from `last_equity` (previous close)
+    pub max_drawdown_pct: Option<Decimal>,
+    /// Cancel all open orders when the switch trips automatically
+    pub cancel_open_orders: bool,
+}
+
+impl AutoTrip {
+    pub fn from_config(value: Option<&serde_json::Value>) -> Result<Self, String> {
+        let Some(value) = value else {
+            return Ok(Self::default());
+        };
+        let object = value.as_object().ok_or("kill_switch must be an object")?;
+
+        let max_consecutive_rejects = match object.get("max_consecutive_rejects") {
+            None | Some(serde_json::Value::Null) => None,
+            Some(v) => Some(
+                v.as_u64()
+                    .and_then(|n| u32::try_from(n).ok())
+                    .filter(|n| *n > 0)
+                    .ok_or("kill_switch.max_consecutive_rejects must be a positive integer")?,
+            ),
+        };
+        let max_drawdown_pct = match object.get("max_drawdown_pct") {
+            None | Some(serde_json::Value::Null) => None,
+            Some(v) => Some(
+                v.as_f64()
+                    .filter(|n| *n > 0.0)
+                    .and_then(decimal::from_f64)
+                    .ok_or("kill_switch.max_drawdown_pct must be a positive number")?,
+            ),
+        };
+
+        Ok(Self {
+            max_consecutive_rejects,
+            max_drawdown_pct,
+            cancel_open_orders: object
+                .get("cancel_open_orders")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Halt {
+    pub reason: String,
+    /// `manual` or `auto`
+    pub source: &'static str,
+    pub halted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+pub struct KillSwitch {
+    pub auto: AutoTrip,
+    halt: Option<Halt>,
+    consecutive_rejects: u32,
+}
+
+impl KillSwitch {
+    pub fn new(auto: AutoTrip) -> Self {
+        Self {
+            auto,
+            ..Self::default()
+        }
+    }
+
+    pub fn trading_enabled(&self) -> bool {
+        self.halt.is_none()
+    }
+
+    pub fn halt_info(&self) -> Option<&Halt> {
+        self.halt.as_ref()
+    }
+
+    pub fn trip(&mut self, reason: impl Into<String>, source: &'static str) {
+        if self.halt.is_none() {
+            self.halt = Some(Halt {
+                reason: reason.into(),
+                source,
+                halted_at: Utc::now(),
+            });
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.halt = None;
+        self.consecutive_rejects = 0;
+    }
+
+    /// Finding used to reject orders while halted
+    pub fn rejection(&self) -> Option<Finding> {
+        self.halt.as_ref().map(|halt| {
+            Finding::new(
+                "trading_halted",
+                format!("Trading is halted: {}", halt.reason),
+                serde_json::to_value(halt).unwrap_or_default(),
+            )
+        })
+    }
+
+    /// Track broker acceptance; returns true if this rejection tripped the switch
+    pub fn record_submission(&mut self, rejected: bool) -> bool {
+        if !rejected {
+            self.consecutive_rejects = 0;
+            return false;
+        }
+        self.consecutive_rejects += 1;
+        match self.auto.max_consecutive_rejects {
+            Some(max) if self.consecutive_rejects >= max && self.trading_enabled() => {
+                self.trip(
+                    format!("{} consecutive order rejections", self.consecutive_rejects),
+                    "auto",
+                );
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Trip on drawdown from the previous close; returns true if it tripped
+    pub fn check_drawdown(&mut self, account: &AlpacaAccount) -> bool {
+        let Some(max) = self.auto.max_drawdown_pct else {
+            return false;
+        };
+        let last_equity = decimal::parse_or_zero(&account.last_equity);
+        if last_equity <= Decimal::ZERO || !self.trading_enabled() {
+            return false;
+        }
+        let equity = decimal::parse_or_zero(&account.equity);
+        let drawdown_pct =
+            ((last_equity - equity) / last_equity * Decimal::ONE_HUNDRED).round_dp(2);
+        if drawdown_pct < max {
+            return false;
+        }
+        self.trip(
+            format!(
+                "drawdown of {}% from previous close exceeds {}%",
+                decimal::to_wire(drawdown_pct),
+                decimal::to_wire(max)
+            ),
+            "auto",
+        );
+        true
+    }
+}
@@ -0,0 +1,118 @@
+//! Host key-value store
+//!
+//! With the `host-kv` feature the plugin keeps data that should outlive a
+//! run (replay logs, position history) in the host's store through the
+//! `kv_get(ptr, len) -> u64` and `kv_put(key_ptr, key_len, value_ptr,
+//! value_len) -> i32` imports: `kv_get` returns a packed pointer and length
+//! to a buffer from `alloc` holding the stored bytes, or 0 when the key is
+//! absent; `kv_put` returns 0 on success. Without the feature the store
+//! lives in plugin memory and is lost on unload.
+//!
+//! Logs are stored one entry per key, `{key}/{n}` from 1, with the count
+//! under `{key}`, so a write stays small however long the log grows.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[cfg(all(feature = "host-kv", target_arch = "wasm32"))]
+extern "C" {
+    fn kv_get(ptr: i32, len: i32) -> u64;
+    fn kv_put(key_ptr: i32, key_len: i32, value_ptr: i32, value_len: i32) -> i32;
+}
+
+#[cfg(all(feature = "host-kv", target_arch = "wasm32"))]
+pub fn get(key: &str) -> Option<Vec<u8>> {
+    // SAFETY: the host only reads `len` bytes at `ptr` during the call
+    let packed = unsafe { kv_get(key.as_ptr() as i32, key.len() as i32) };
+    if packed == 0 {
+        return None;
+    }
+    let (ptr, len) = crate::unpack_ptr_len(packed);
+    // SAFETY: the host wrote the value into a buffer from our `alloc`
+    let bytes = unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) }.to_vec();
+    unsafe { crate::arena::free(ptr as usize as *mut u8, len as usize) };
+    Some(bytes)
+}
+
+#[cfg(all(feature = "host-kv", target_arch = "wasm32"))]
+pub fn put(key: &str, value: &[u8]) -> Result<(), String> {
+    // SAFETY: the host only reads the two buffers during the call
+    let status = unsafe {
+        kv_put(
+            key.as_ptr() as i32,
+            key.len() as i32,
+            value.as_ptr() as i32,
+            value.len() as i32,
+        )
+    };
+    match status {
+        0 => Ok(()),
+        code => Err(format!("kv_put {} failed with {}", key, code)),
+    }
+}
+
+#[cfg(not(all(feature = "host-kv", target_arch = "wasm32")))]
+lazy_static::lazy_static! {
+    static ref MEMORY: std::sync::Mutex<std::collections::HashMap<String, Vec<u8>>> =
+        Default::default();
+}
+
+#[cfg(not(all(feature = "host-kv", target_arch = "wasm32")))]
+pub fn get(key: &str) -> Option<Vec<u8>> {
+    MEMORY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(key)
+        .cloned()
+}
+
+#[cfg(not(all(feature = "host-kv", target_arch = "wasm32")))]
+pub fn put(key: &str, value: &[u8]) -> Result<(), String> {
+    MEMORY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(key.to_string(), value.to_vec());
+    Ok(())
+}
+
+/// Number of entries in the log under `key`; 0 when absent
+pub fn entry_count(key: &str, what: &str) -> Result<usize, String> {
+    let Some(count) = get(key) else {
+        return Ok(0);
+    };
+    std::str::from_utf8(&count)
+        .ok()
+        .and_then(|c| c.trim().parse().ok())
+        .ok_or_else(|| format!("{} {} has an invalid entry count", what, key))
+}
+
+/// Entries `first..=count` of the log under `key`
+pub fn load_entries_from<T: DeserializeOwned>(
+    key: &str,
+    what: &str,
+    first: usize,
+) -> Result<Vec<T>, String> {
+    let count = entry_count(key, what)?;
+    (first.max(1)..=count)
+        .map(|n| {
+            let entry_key = format!("{}/{}", key, n);
+            let bytes = get(&entry_key)
+                .ok_or_else(|| format!("{} entry {} is missing", what, entry_key))?;
+            serde_json::from_slice(&bytes).map_err(|e| format!("{}: {}", entry_key, e))
+        })
+        .collect()
+}
+
+/// Every entry of the log under `key`; an absent key is an empty log
+pub fn load_entries<T: DeserializeOwned>(key: &str, what: &str) -> Result<Vec<T>, String> {
+    load_entries_from(key, what, 1)
+}
+
+/// Store `entry` as number `n` of the log under `key`
+pub fn append_entry<T: Serialize>(key: &str, n: usize, entry: &T) -> Result<(), String> {
+    let entry_key = format!("{}/{}", key, n);
+    serde_json::to_vec(entry)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| put(&entry_key, &bytes))
+        .and_then(|_| put(key, n.to_string().as_bytes()))
+}
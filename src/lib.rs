@@ -13,207 +13,5787 @@
 // Allow dead_code for structs/fields prepared for future API integration
 #![allow(dead_code)]
 
+mod alerts;
+mod algo;
 mod alpaca;
+mod arena;
+mod auction;
+mod benchmark;
+mod broker_api;
+mod cashflows;
+mod chase;
+mod chunked;
+mod closing;
+mod conditional;
+mod config;
+mod confirmations;
+mod currency;
+mod daily_summary;
+mod decimal;
+mod dedupe;
+mod delisting;
+mod environment;
+mod events;
+mod executions;
+mod fees;
+#[cfg(test)]
+mod golden;
+mod good_faith;
+mod gtd;
+mod history;
 mod http;
+mod intent;
+mod interlock;
+mod kill_switch;
+mod kv;
+mod logging;
+mod loss_limit;
+mod market_data;
+mod market_hours;
+mod market_time;
+mod marks;
+mod metadata;
+mod metrics;
+mod middleware;
+#[cfg(test)]
+mod mock;
+#[cfg(all(feature = "native", not(target_arch = "wasm32")))]
+pub mod native;
+mod onboarding;
+mod order_size;
+mod order_status;
+mod orders;
+mod paging;
+mod pdt;
+mod pnl;
+mod positions;
+mod pretrade;
+mod pricing;
+mod protective;
+mod raw;
+mod rebalance;
+mod recording;
+mod redact;
+mod reference;
+mod reserved;
+mod risk;
+mod schedule;
+mod secrets;
+mod settlement;
+mod shadow;
+mod shorting;
+mod simulator;
+mod stale;
+mod symbol_change;
+mod tags;
+mod throttle;
+mod trace;
+mod trading_status;
+mod validation;
+mod wash_sale;
+mod wire;
 
-use chrono::Utc;
-use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::slice;
-use std::sync::Mutex;
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
 
-use alpaca::AlpacaClient;
-use models::order::{Order, OrderStatus};
+use algo::{AlgoBook, AlgoSpec, AlgoStatus};
+use alpaca::{AccountPositions, AlpacaClient, ClientOptions};
+use broker_api::BrokerApi;
+use cashflows::{CashFlowLedger, CashFlowQuery};
+use chase::ChaseBook;
+use closing::{CloseAmount, ClosePlan};
+use conditional::{Condition, ConditionalBook};
+use daily_summary::{DayActivity, RejectionLog};
+use decimal::Decimal;
+use dedupe::DedupeGuard;
+use environment::Environment;
+use executions::ExecutionStore;
+use gtd::GtdBook;
+use history::{PositionHistory, PositionSnapshot};
+use kill_switch::KillSwitch;
+use loss_limit::{DailyLossLimit, IntradayPnl};
+use market_hours::{ClosedMarketPolicy, Gate, OrderQueue};
+use models::order::{Order, OrderRequest, OrderSide, OrderStatus};
 use models::portfolio::{AccountBalance, AccountSummary};
+use orders::{EventJournal, LegSummary, ReplaceChains};
 use plugin_api::{
-    GetAccountsRequest, GetAccountsResponse, GetPositionsRequest, GetPositionsResponse,
-    SubmitOrderRequest, SubmitOrderResponse,
+    GetAccountsResponse, GetPositionsRequest, GetPositionsResponse, SubmitOrderRequest,
+    SubmitOrderResponse,
 };
+use pnl::{LotMethod, LotSelection, OrderContext, PnlLedger, RealizedPnlQuery, RealizedPnlReport};
+use positions::{ChangeScope, PositionTracker};
+use pretrade::{CheckMode, CheckReport, Finding};
+use pricing::PricingMode;
+use rebalance::RebalanceRequest;
+use recording::{Recorder, RecordingMode};
+use reserved::ReservedFunds;
+use risk::{DailyOrderCount, RiskLimits};
+use schedule::{ScheduleBook, ScheduleSpec};
+use simulator::{SimQuote, Simulator};
+use stale::{StaleBook, StaleOrderRules};
+use std::sync::Arc;
 
 // --- State Management ---
 
 struct BrokerState {
-    client: Option<AlpacaClient>,
+    /// Same client as [`CLIENT`]; kept here for exports already holding
+    /// the state lock
+    client: Option<Arc<AlpacaClient>>,
+    /// Same client as [`LIVE_CLIENT`]: the live one, when `live_credentials`
+    /// runs it next to the default paper client
+    live_client: Option<Arc<AlpacaClient>>,
+    /// Set while [`Self::with_environment`] has swapped in the other
+    /// environment's client
+    environment_override: Option<Environment>,
     orders: HashMap<String, Order>,
+    /// Legs of advanced (bracket/OCO/OTO) orders, keyed by parent order ID
+    order_legs: HashMap<String, Vec<LegSummary>>,
+    /// `replaces`/`replaced_by` links between cached orders
+    chains: ReplaceChains,
+    executions: ExecutionStore,
+    lot_method: LotMethod,
+    cash_flows: CashFlowLedger,
+    pdt_mode: CheckMode,
+    buying_power_mode: CheckMode,
+    asset_mode: CheckMode,
+    short_mode: CheckMode,
+    wash_sale_mode: CheckMode,
+    gfv_mode: CheckMode,
+    /// `None` when fee estimates are turned off
+    fees: Option<fees::FeeSchedule>,
+    raw_policy: raw::RawPolicy,
+    /// `api_key_ref` and `api_secret_ref`, kept for `rotate_credentials`
+    secret_refs: Option<(String, String)>,
+    /// The same for `live_credentials`
+    live_secret_refs: Option<(String, String)>,
+    /// The same for `broker_api`
+    broker_secret_refs: Option<(String, String)>,
+    shadow_mode: shadow::ShadowMode,
+    /// Safety margin added to estimated order cost, in percent
+    cost_buffer_pct: Decimal,
+    price_band_pct: Option<Decimal>,
+    risk_limits: RiskLimits,
+    order_size_limits: order_size::SizeLimits,
+    daily_orders: DailyOrderCount,
+    /// Basket orders sent together, counted by each other's checks until
+    /// Alpaca answers
+    in_flight: Vec<InFlight>,
+    /// Orders a basket sends at once
+    basket_concurrency: usize,
+    /// Kept across re-initialization, like the kill switch
+    loss_limit: DailyLossLimit,
+    /// Orders returned as rejected, for the daily summary
+    rejections: RejectionLog,
+    throttle: throttle::OrderThrottle,
+    kill_switch: KillSwitch,
+    live_interlock: interlock::LiveInterlock,
+    dedupe: DedupeGuard,
+    closed_market_policy: ClosedMarketPolicy,
+    /// DAY orders held locally until the next open
+    order_queue: OrderQueue,
+    gtd: GtdBook,
+    stale_rules: StaleOrderRules,
+    /// Stale orders already reported
+    stale: StaleBook,
+    conditionals: ConditionalBook,
+    scheduled: ScheduleBook,
+    algos: AlgoBook,
+    chases: ChaseBook,
+    /// Protective orders kept in step with their positions
+    protections: protective::ProtectionBook,
+    protective_adjust: protective::AdjustPolicy,
+    symbol_changes: bool,
+    symbol_watch: symbol_change::SymbolChangeWatch,
+    reference_prices: bool,
+    /// Positions last returned by `get_position_changes`
+    position_changes: PositionTracker,
+    /// Set in simulation mode; shared with the client's pipeline
+    simulator: Option<Arc<Simulator>>,
+    /// Set when recording or replaying; shared with the client's pipeline
+    recorder: Option<Arc<Recorder>>,
+    /// Set with `position_history`
+    position_history: Option<PositionHistory>,
+    /// Compared against by `get_portfolio_history` with `benchmark: true`
+    benchmark_symbol: String,
 }
 
 impl BrokerState {
     fn new() -> Self {
         Self {
             client: None,
+            live_client: None,
+            environment_override: None,
             orders: HashMap::new(),
+            order_legs: HashMap::new(),
+            chains: ReplaceChains::default(),
+            executions: ExecutionStore::default(),
+            lot_method: LotMethod::default(),
+            cash_flows: CashFlowLedger::default(),
+            pdt_mode: CheckMode::default(),
+            buying_power_mode: CheckMode::Off,
+            asset_mode: CheckMode::Enforce,
+            short_mode: CheckMode::Warn,
+            wash_sale_mode: CheckMode::Off,
+            gfv_mode: CheckMode::Off,
+            fees: Some(fees::FeeSchedule::default()),
+            raw_policy: raw::RawPolicy::Off,
+            secret_refs: None,
+            live_secret_refs: None,
+            broker_secret_refs: None,
+            shadow_mode: shadow::ShadowMode::Off,
+            cost_buffer_pct: Decimal::ONE,
+            price_band_pct: Some(Decimal::from(validation::DEFAULT_PRICE_BAND_PCT)),
+            risk_limits: RiskLimits::default(),
+            order_size_limits: order_size::SizeLimits::default(),
+            daily_orders: DailyOrderCount::default(),
+            in_flight: Vec::new(),
+            basket_concurrency: DEFAULT_BASKET_CONCURRENCY,
+            loss_limit: DailyLossLimit::default(),
+            rejections: RejectionLog::default(),
+            throttle: throttle::OrderThrottle::default(),
+            kill_switch: KillSwitch::default(),
+            live_interlock: interlock::LiveInterlock::default(),
+            dedupe: DedupeGuard::default(),
+            closed_market_policy: ClosedMarketPolicy::default(),
+            order_queue: OrderQueue::default(),
+            gtd: GtdBook::default(),
+            stale_rules: StaleOrderRules::default(),
+            stale: StaleBook::default(),
+            conditionals: ConditionalBook::default(),
+            scheduled: ScheduleBook::default(),
+            algos: AlgoBook::default(),
+            chases: ChaseBook::default(),
+            protections: protective::ProtectionBook::default(),
+            protective_adjust: protective::AdjustPolicy::default(),
+            symbol_changes: true,
+            symbol_watch: symbol_change::SymbolChangeWatch::default(),
+            reference_prices: false,
+            position_changes: PositionTracker::default(),
+            simulator: None,
+            recorder: None,
+            position_history: None,
+            benchmark_symbol: benchmark::DEFAULT_BENCHMARK.to_string(),
         }
     }
+
+    /// Cache an order and its legs
+    fn track_order(&mut self, order: Order) {
+        let legs = orders::legs(&order);
+        if legs.is_empty() {
+            self.order_legs.remove(&order.id);
+        } else {
+            self.order_legs.insert(order.id.clone(), legs);
+        }
+        self.chains.record(&order);
+        self.orders.insert(order.id.clone(), order);
+    }
+
+    /// Why a raw write may not be sent: it can place or cancel orders, so
+    /// the kill switch holds it, and a live one needs the acknowledgment
+    fn raw_write_rejection(&self, is_paper: bool) -> Option<Finding> {
+        self.kill_switch
+            .rejection()
+            .or_else(|| self.live_interlock.rejection(is_paper))
+    }
+
+    /// Pull fills newer than the latest one already ingested, and any
+    /// older history a bounded first load skipped
+    fn refresh_executions(&mut self) -> Result<(), String> {
+        self.refresh_executions_from(None)
+    }
+
+    /// [`Self::refresh_executions`] for callers that only look at fills
+    /// since `start`: a first load reaches back no further than that
+    fn refresh_executions_from(&mut self, start: Option<DateTime<Utc>>) -> Result<(), String> {
+        let client = self.client.as_ref().ok_or("Plugin not initialized")?;
+        let store = &mut self.executions;
+        if !store.loaded() {
+            store.ingest(client.list_fill_activities(start, None)?);
+            store.floor = start;
+            return Ok(());
+        }
+        let after = store.last_seen.or(store.floor);
+        if let Some(floor) = store.floor {
+            if start.is_none_or(|s| s < floor) {
+                store.ingest(client.list_fill_activities(start, Some(floor))?);
+                store.floor = start;
+            }
+        }
+        store.ingest(client.list_fill_activities(after, None)?);
+        Ok(())
+    }
+
+    /// Fills of the last [`settlement::LOOKBACK_DAYS`] and the market
+    /// calendar around them. Without the calendar, settlement assumes the
+    /// next weekday.
+    fn settlement_inputs(
+        &mut self,
+        today: chrono::NaiveDate,
+    ) -> Result<(Vec<executions::Execution>, Vec<alpaca::CalendarDay>), String> {
+        self.refresh_executions()?;
+        let client = self.client.as_ref().ok_or("Plugin not initialized")?;
+        let fills = self
+            .executions
+            .in_range(Some(settlement::window_start(today)), None);
+        let calendar = if fills.is_empty() {
+            Vec::new()
+        } else {
+            let lookback = chrono::Duration::days(settlement::LOOKBACK_DAYS);
+            client
+                .get_calendar(today - lookback, today + lookback)
+                .unwrap_or_else(|e| {
+                    logging::warn("settlement", "Market calendar unavailable")
+                        .field("error", e.as_str())
+                        .emit();
+                    Vec::new()
+                })
+        };
+        Ok((fills, calendar))
+    }
+
+    /// Settled and unsettled cash, see [`settlement`]
+    fn cash_settlement(
+        &mut self,
+        account: &alpaca::AlpacaAccount,
+    ) -> Result<settlement::CashSettlement, String> {
+        let today = market_time::eastern_today();
+        let (fills, calendar) = self.settlement_inputs(today)?;
+        let unsettled = settlement::unsettled(&fills, &calendar, today);
+        Ok(settlement::CashSettlement::build(account, unsettled))
+    }
+
+    /// Good-faith and free-riding check for a sell on a cash account
+    fn check_good_faith(
+        &mut self,
+        order: &OrderRequest,
+        account: &alpaca::AlpacaAccount,
+        held: Decimal,
+    ) -> pretrade::CheckOutcome {
+        let today = market_time::eastern_today();
+        let (fills, calendar) = match self.settlement_inputs(today) {
+            Ok(inputs) => inputs,
+            Err(e) => {
+                return pretrade::CheckOutcome::Warn(Finding::new(
+                    "pretrade_unavailable",
+                    "Fill history unavailable; good-faith check skipped",
+                    serde_json::json!({ "error": e }),
+                ))
+            }
+        };
+        let unsettled = settlement::unsettled(&fills, &calendar, today);
+        let settled = settlement::settled_balance(account, &unsettled);
+        let funded = good_faith::funded_buys(&fills, &calendar, settled, today);
+        good_faith::check(self.gfv_mode, order, held, &funded, today)
+    }
+
+    /// Store a position snapshot when one is due. Returns whether one was
+    /// taken.
+    fn snapshot_positions(&mut self, now: DateTime<Utc>) -> Result<bool, String> {
+        if !self
+            .position_history
+            .as_ref()
+            .is_some_and(|h| h.is_due(now))
+        {
+            return Ok(false);
+        }
+        let client = self.client.as_ref().ok_or("Plugin not initialized")?;
+        let snapshot = PositionSnapshot::of(&client.get_positions()?, now);
+        if let Some(history) = self.position_history.as_mut() {
+            history.record(snapshot);
+            if let Some(e) = &history.store_error {
+                logging::warn("history", "Position snapshot not stored")
+                    .field("error", e.as_str())
+                    .emit();
+            }
+        }
+        Ok(true)
+    }
+
+    /// Every order in `order_id`'s replace chain, original first, fetching
+    /// members not cached yet
+    fn order_chain(&mut self, client: &AlpacaClient, order_id: &str) -> Result<Vec<Order>, String> {
+        loop {
+            let chain = self.chains.chain(order_id);
+            let missing: Vec<&String> = chain
+                .iter()
+                .filter(|id| !self.orders.contains_key(*id))
+                .collect();
+            if missing.is_empty() {
+                return Ok(chain
+                    .iter()
+                    .filter_map(|id| self.orders.get(id))
+                    .cloned()
+                    .collect());
+            }
+            // Each pass caches at least one more member, whose links may
+            // extend the chain further
+            for id in missing {
+                let order = client.get_order(id)?;
+                self.track_order(order);
+            }
+        }
+    }
+
+    /// Cached order for an order or leg ID (legs resolve to their parent)
+    fn order_or_parent(&self, order_id: &str) -> Option<&Order> {
+        self.orders.get(order_id).or_else(|| {
+            self.order_legs
+                .iter()
+                .find(|(_, legs)| legs.iter().any(|l| l.id == order_id))
+                .and_then(|(parent, _)| self.orders.get(parent))
+        })
+    }
+
+    /// Run the risk limits and local pre-trade checks for an order
+    fn pretrade_checks(&mut self, order: &OrderRequest) -> CheckReport {
+        self.run_pretrade_checks(order, false)
+    }
+
+    /// [`Self::pretrade_checks`] for an order leaving the market-hours
+    /// queue. Its daily order count and live notional were taken when it
+    /// was queued, so those two limits are not applied again.
+    fn release_checks(&mut self, order: &OrderRequest) -> CheckReport {
+        self.run_pretrade_checks(order, true)
+    }
+
+    fn run_pretrade_checks(&mut self, order: &OrderRequest, counted: bool) -> CheckReport {
+        let mut report = CheckReport::default();
+        let Some(client) = self.client.clone() else {
+            return report;
+        };
+        let is_paper = client.is_paper();
+
+        let orders_today = if counted {
+            0
+        } else {
+            self.daily_orders.get() + self.in_flight.len() as u32
+        };
+        if !report.record(risk::check_static(&self.risk_limits, order, orders_today)) {
+            return report;
+        }
+
+        let mut asset = None;
+        let short_check = self.short_mode != CheckMode::Off && order.side == OrderSide::Sell;
+        let gfv_check = self.gfv_mode != CheckMode::Off && order.side == OrderSide::Sell;
+        if self.asset_mode != CheckMode::Off || short_check {
+            match client.get_asset(&order.symbol_id) {
+                Ok(found) => {
+                    if self.asset_mode != CheckMode::Off {
+                        // The asset record only; the quote-based halt
+                        // heuristic is advisory and stays in get_trading_status
+                        let status = trading_status::from_asset(&order.symbol_id, found.clone());
+                        if !report.record(trading_status::check(self.asset_mode, &status)) {
+                            return report;
+                        }
+                    }
+                    asset = found;
+                }
+                Err(e) => {
+                    report.record(pretrade::CheckOutcome::Warn(Finding::new(
+                        "pretrade_unavailable",
+                        "Asset data unavailable; asset status check skipped",
+                        serde_json::json!({ "error": e }),
+                    )));
+                }
+            }
+        }
+
+        let position_intent = intent::requested(order).ok().flatten();
+        // Figures fetched from here on reflect the orders sent before it
+        let fetched_at = Utc::now();
+        let mut position = None;
+        let mut qty_available = None;
+        let mut position_known = false;
+        if self.risk_limits.needs_position()
+            || (self.live_interlock.max_notional_per_day.is_some() && !is_paper)
+            || self.buying_power_mode != CheckMode::Off
+            || (short_check && asset.is_some())
+            || gfv_check
+            || position_intent.is_some()
+        {
+            match client.fetch_position(&order.symbol_id) {
+                Ok(found) => {
+                    if let Some((found, extras)) = found {
+                        position = Some(found);
+                        qty_available = extras.qty_available;
+                    }
+                    position_known = true;
+                }
+                Err(e) => {
+                    report.record(pretrade::CheckOutcome::Warn(Finding::new(
+                        "pretrade_unavailable",
+                        "Position data unavailable; position-based checks use a flat position",
+                        serde_json::json!({ "error": e }),
+                    )));
+                }
+            }
+        }
+        let held = position
+            .as_ref()
+            .and_then(|p| decimal::from_f64(p.quantity))
+            .unwrap_or_default();
+        if !report.record(order_size::check(
+            &self.order_size_limits,
+            order,
+            asset.as_ref(),
+            pretrade::estimate_price(order, position.as_ref()),
+        )) {
+            return report;
+        }
+        if let Some(intent) = position_intent.filter(|_| position_known) {
+            if !report.record(intent::check(intent, order, held)) {
+                return report;
+            }
+        }
+        if self.wash_sale_mode != CheckMode::Off
+            && order.side == OrderSide::Buy
+            && !report.record(self.check_wash_sale(order))
+        {
+            return report;
+        }
+        // Fill history follows the default environment only
+        if let Some(max_loss) = self
+            .risk_limits
+            .max_daily_loss
+            .filter(|_| self.environment_override.is_none())
+        {
+            if (!position_known || loss_limit::increases_risk(order, held))
+                && !report.record(self.check_daily_loss(order, max_loss))
+            {
+                return report;
+            }
+        }
+        for outcome in risk::check_notional(&self.risk_limits, order, position.as_ref(), is_paper) {
+            report.record(outcome);
+        }
+        report.live_notional = interlock::notional(order, position.as_ref());
+        if !counted {
+            let mut interlock = self.live_interlock.clone();
+            for sending in &self.in_flight {
+                interlock.record(sending.live_notional, sending.is_paper);
+            }
+            report.record(interlock.check(order, position.as_ref(), is_paper));
+        }
+        if let Some(asset) = asset.as_ref().filter(|_| short_check) {
+            if let Some(short) = shorting::ShortSale::assess(order, asset, held) {
+                report.record(short.check(self.short_mode, &order.symbol_id));
+                report
+                    .extensions
+                    .push(("short_qty", decimal::to_wire(short.short_qty).into()));
+                report.extensions.push(("htb", short.htb().into()));
+            }
+        }
+        if report.block.is_some()
+            || (self.pdt_mode == CheckMode::Off
+                && self.buying_power_mode == CheckMode::Off
+                && !gfv_check)
+        {
+            return report;
+        }
+
+        let account = match client.fetch_account() {
+            Ok(account) => account,
+            Err(e) => {
+                report.record(pretrade::CheckOutcome::Warn(Finding::new(
+                    "pretrade_unavailable",
+                    "Account data unavailable; pre-trade checks skipped",
+                    serde_json::json!({ "error": e }),
+                )));
+                return report;
+            }
+        };
+
+        if let Some(reason) = account.trading_block() {
+            report.record(pretrade::CheckOutcome::Block(account_blocked(reason)));
+            return report;
+        }
+        let mut settled_cash = None;
+        if self.buying_power_mode != CheckMode::Off
+            && order.side == OrderSide::Buy
+            && account.is_cash_account()
+        {
+            if self.environment_override.is_some() {
+                report.record(pretrade::CheckOutcome::Warn(Finding::new(
+                    "pretrade_unavailable",
+                    "Fill history is kept for the default environment only; settled cash not checked",
+                    serde_json::json!({}),
+                )));
+            } else {
+                match self.cash_settlement(&account) {
+                    Ok(settlement) => {
+                        settled_cash =
+                            Some(settlement.settled_cash() - self.reserved_funds().buying_power)
+                    }
+                    Err(e) => {
+                        report.record(pretrade::CheckOutcome::Warn(Finding::new(
+                            "pretrade_unavailable",
+                            "Fill history unavailable; settled cash not checked",
+                            serde_json::json!({ "error": e }),
+                        )));
+                    }
+                }
+            }
+        }
+        let available = position
+            .as_ref()
+            .map(|p| pretrade::available(p, qty_available));
+        report.record(pretrade::check_buying_power(
+            self.buying_power_mode,
+            order,
+            &account,
+            available.as_ref(),
+            self.cost_buffer_pct,
+            &self.unreflected_funds(fetched_at),
+            settled_cash,
+        ));
+        if gfv_check && position_known && held > Decimal::ZERO && account.is_cash_account() {
+            if self.environment_override.is_some() {
+                report.record(pretrade::CheckOutcome::Warn(Finding::new(
+                    "pretrade_unavailable",
+                    "Fill history is kept for the default environment only; good-faith check skipped",
+                    serde_json::json!({}),
+                )));
+            } else {
+                report.record(self.check_good_faith(order, &account, held));
+            }
+        }
+
+        if self.pdt_mode != CheckMode::Off && self.environment_override.is_some() {
+            report.record(pretrade::CheckOutcome::Warn(Finding::new(
+                "pretrade_unavailable",
+                "Fill history is kept for the default environment only; PDT check skipped",
+                serde_json::json!({}),
+            )));
+        } else if self.pdt_mode != CheckMode::Off {
+            let today = market_time::eastern_today();
+            if let Err(e) = self.refresh_executions_from(Some(pdt::window_start(today))) {
+                logging::warn("pretrade", "Failed to refresh fills for PDT check")
+                    .field("error", e.as_str())
+                    .emit();
+            }
+            let fills: Vec<_> = self
+                .executions
+                .in_range(None, None)
+                .into_iter()
+                .filter(|e| market_time::eastern_date(e.timestamp) == today)
+                .collect();
+            report.record(pdt::check(self.pdt_mode, order, &account, &fills));
+        }
+        report
+    }
+
+    /// Realized losses in the order's symbol within the wash-sale window
+    fn check_wash_sale(&mut self, order: &OrderRequest) -> pretrade::CheckOutcome {
+        let unavailable = |message: &str, details: serde_json::Value| {
+            pretrade::CheckOutcome::Warn(Finding::new("pretrade_unavailable", message, details))
+        };
+        if self.environment_override.is_some() {
+            return unavailable(
+                "Fill history is kept for the default environment only; wash-sale check skipped",
+                serde_json::json!({}),
+            );
+        }
+        if let Err(e) = self.refresh_executions() {
+            return unavailable(
+                "Fill history unavailable; wash-sale check skipped",
+                serde_json::json!({ "error": e }),
+            );
+        }
+        let ledger = self.pnl_ledger();
+        wash_sale::check(self.wash_sale_mode, order, &ledger.realized, Utc::now())
+    }
+
+    /// The daily loss limit for a risk-increasing order. A dry run sees
+    /// the outcome without tripping the limit.
+    fn check_daily_loss(
+        &mut self,
+        order: &OrderRequest,
+        max_loss: Decimal,
+    ) -> pretrade::CheckOutcome {
+        if let Some(finding) = self.loss_limit.rejection() {
+            return pretrade::CheckOutcome::Block(finding);
+        }
+        let pnl = match self.intraday_pnl() {
+            Ok(pnl) => pnl,
+            Err(e) => {
+                return pretrade::CheckOutcome::Block(Finding::new(
+                    "risk_pnl_unavailable",
+                    "Intraday PnL unavailable to enforce max_daily_loss",
+                    serde_json::json!({ "error": e }),
+                ))
+            }
+        };
+        let dry_run = orders::request_flag(order, "dry_run");
+        let mut preview = self.loss_limit.clone();
+        let limit = if dry_run {
+            &mut preview
+        } else {
+            &mut self.loss_limit
+        };
+        let Some(breach) = limit.observe(&pnl, max_loss).cloned() else {
+            return pretrade::CheckOutcome::Pass;
+        };
+        let finding = limit.rejection();
+        if !dry_run {
+            logging::error("risk", "Daily loss limit reached")
+                .field("pnl", breach.pnl.as_str())
+                .field("limit", breach.limit.as_str())
+                .emit();
+            alerts::raise(
+                "daily_loss_limit",
+                format!(
+                    "Intraday PnL of {} reached the daily loss limit of {}",
+                    breach.pnl, breach.limit
+                ),
+                breach.pnl,
+                Some(breach.limit),
+            );
+        }
+        finding.map_or(pretrade::CheckOutcome::Pass, pretrade::CheckOutcome::Block)
+    }
+
+    /// Today's realized PnL from fills plus open positions' intraday PnL,
+    /// both since the previous close
+    fn intraday_pnl(&mut self) -> Result<IntradayPnl, String> {
+        self.refresh_executions()?;
+        let client = self.client.as_ref().ok_or("Plugin not initialized")?;
+        let unrealized = client.unrealized_intraday_pl()?;
+        let today = market_time::eastern_today();
+        let realized = self.pnl_ledger().realized;
+        // The snapshot endpoint is for stocks; other lots count from entry
+        let carried: Vec<String> = loss_limit::carried_symbols(&realized, today)
+            .into_iter()
+            .filter(|s| fees::AssetKind::of(s) == fees::AssetKind::Equity)
+            .collect();
+        let prev_closes = client
+            .get_snapshots(&carried)
+            .unwrap_or_else(|e| {
+                logging::warn(
+                    "risk",
+                    "Previous closes unavailable; closed lots count from entry",
+                )
+                .field("error", e.as_str())
+                .emit();
+                HashMap::new()
+            })
+            .into_iter()
+            .filter_map(|(symbol, snapshot)| {
+                let close = loss_limit::previous_close(&snapshot, today)?;
+                Some((symbol.to_ascii_uppercase(), close))
+            })
+            .collect();
+        Ok(IntradayPnl::new(&realized, unrealized, today, &prev_closes))
+    }
+
+    /// Log an automatic kill-switch trip and cancel open orders if configured
+    fn on_auto_trip(&mut self) {
+        let reason = self
+            .kill_switch
+            .halt_info()
+            .map(|h| h.reason.clone())
+            .unwrap_or_default();
+        logging::error("kill_switch", "Trading halted automatically")
+            .field("reason", reason.as_str())
+            .emit();
+        if self.kill_switch.auto.cancel_open_orders {
+            if let Err(e) = self.cancel_all_open_orders() {
+                logging::error("kill_switch", "Failed to cancel open orders")
+                    .field("error", e.as_str())
+                    .emit();
+            }
+        }
+    }
+
+    /// Cancel every open order at Alpaca; returns the per-order results
+    fn cancel_all_open_orders(&mut self) -> Result<Vec<serde_json::Value>, String> {
+        let client = self.client.as_ref().ok_or("Plugin not initialized")?;
+        let results = client.cancel_all_orders()?;
+        Ok(results
+            .into_iter()
+            .map(|(order_id, status)| {
+                serde_json::json!({
+                    "order_id": order_id,
+                    "success": (200..300).contains(&status),
+                    "status": status,
+                })
+            })
+            .collect())
+    }
+
+    /// Submit queued orders once the clock reports the market open, each
+    /// after the pre-trade checks pass again for it. Returns the released
+    /// orders and any check or submission errors; a blocked order is
+    /// dropped from the queue.
+    fn release_queued_orders(&mut self) -> (Vec<serde_json::Value>, Vec<serde_json::Value>) {
+        let mut released = Vec::new();
+        let mut errors = Vec::new();
+        if self.order_queue.is_empty() || !self.kill_switch.trading_enabled() {
+            return (released, errors);
+        }
+        let Some(client) = self.client.clone() else {
+            return (released, errors);
+        };
+        // Re-initialized without the acknowledgment since they were queued
+        if self.live_interlock.rejection(client.is_paper()).is_some() {
+            logging::warn("market_hours", "Live interlock engaged; queued orders held").emit();
+            return (released, errors);
+        }
+        match client.get_clock() {
+            Ok(clock) if clock.is_open => {}
+            Ok(_) => return (released, errors),
+            Err(e) => {
+                logging::warn("market_hours", "Clock unavailable; queued orders held")
+                    .field("error", e.as_str())
+                    .emit();
+                return (released, errors);
+            }
+        }
+
+        for queued in self.order_queue.drain() {
+            // Prices, positions, and the account may have moved overnight
+            let checks = self.release_checks(&queued.request);
+            if let Some(finding) = &checks.block {
+                logging::warn("market_hours", "Queued order blocked on release")
+                    .field("queue_id", queued.queue_id.as_str())
+                    .field("code", finding.code.as_str())
+                    .emit();
+                errors.push(serde_json::json!({
+                    "queue_id": queued.queue_id,
+                    "error": finding.message,
+                    "rejection": finding,
+                }));
+                continue;
+            }
+            match client.submit_order(&queued.request) {
+                Ok(mut order) => {
+                    orders::set_ext(&mut order, "queue_id", queued.queue_id.as_str());
+                    checks.annotate(&mut order);
+                    logging::info("market_hours", "Queued order released")
+                        .field("queue_id", queued.queue_id.as_str())
+                        .field("order_id", order.id.as_str())
+                        .emit();
+                    released.push(serde_json::json!({
+                        "queue_id": queued.queue_id,
+                        "order_id": order.id,
+                    }));
+                    // Tracked right away so the next release sees its funds
+                    self.start_chase(&order);
+                    self.track_order(order);
+                }
+                Err(e) => {
+                    logging::error("market_hours", "Queued order failed on release")
+                        .field("queue_id", queued.queue_id.as_str())
+                        .field("error", e.as_str())
+                        .emit();
+                    errors.push(serde_json::json!({ "queue_id": queued.queue_id, "error": e }));
+                }
+            }
+        }
+        (released, errors)
+    }
+
+    /// Refresh `order_ids` (every working order when `None`), journaling
+    /// and tracking whatever changed. Returns the change events, the
+    /// changed orders, and per-order fetch errors.
+    fn refresh_orders(
+        &mut self,
+        order_ids: Option<&[String]>,
+    ) -> (Vec<orders::OrderEvent>, Vec<Order>, Vec<serde_json::Value>) {
+        let candidates: Vec<Order> = self
+            .orders
+            .values()
+            .filter(|o| match order_ids {
+                Some(ids) => ids.contains(&o.id),
+                None => orders::is_working(o),
+            })
+            .cloned()
+            .collect();
+
+        let mut events = Vec::new();
+        let mut updated = Vec::new();
+        let mut errors = Vec::new();
+
+        for previous in candidates {
+            let refreshed = match self
+                .client_for_order(&previous.id)
+                .map(|c| c.get_order(&previous.id))
+            {
+                Some(Ok(order)) => {
+                    let mut refreshed = orders::merge_refresh(&previous, order);
+                    if gtd::cancel_requested(&previous) {
+                        gtd::mark_expired(&mut refreshed);
+                    }
+                    if stale::cancel_requested(&previous) {
+                        stale::mark_canceled(&mut refreshed);
+                    }
+                    refreshed
+                }
+                Some(Err(e)) => {
+                    errors.push(serde_json::json!({ "order_id": previous.id, "error": e }));
+                    continue;
+                }
+                None => continue,
+            };
+
+            let changes = orders::diff(&previous, &refreshed);
+            if !changes.is_empty() {
+                events.extend(
+                    JOURNAL
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .extend(changes),
+                );
+                updated.push(refreshed.clone());
+            }
+            self.track_order(refreshed);
+        }
+        (events, updated, errors)
+    }
+
+    /// Cancel GTD orders whose expiry has passed; returns their IDs
+    fn expire_gtd_orders(&mut self) -> Vec<String> {
+        let mut expired = Vec::new();
+        let Some(client) = self.client.as_ref() else {
+            return expired;
+        };
+        for order_id in self.gtd.due(Utc::now()) {
+            let working = self
+                .orders
+                .get(&order_id)
+                .is_some_and(orders::parent_working);
+            if working {
+                match client.cancel_order(&order_id) {
+                    Ok(()) => {
+                        logging::info("gtd", "GTD order expired")
+                            .field("order_id", order_id.as_str())
+                            .emit();
+                        if let Some(order) = self.orders.get_mut(&order_id) {
+                            orders::set_ext(order, "gtd_cancel_requested", true);
+                        }
+                        expired.push(order_id.clone());
+                    }
+                    Err(e) => {
+                        // Usually a fill that raced the expiry; the refresh reports it
+                        logging::warn("gtd", "Failed to cancel expired GTD order")
+                            .field("order_id", order_id.as_str())
+                            .field("error", e.as_str())
+                            .emit();
+                    }
+                }
+            }
+            self.gtd.remove(&order_id);
+        }
+        expired
+    }
+
+    /// Working orders that are stale by `stale_rules`, oldest deadline
+    /// first. The calendar is only fetched when a DAY order is working.
+    fn stale_orders(&self) -> Vec<stale::StaleOrder> {
+        let working: Vec<&Order> = self
+            .orders
+            .values()
+            .filter(|o| orders::parent_working(o))
+            .collect();
+        let first_day_order = working
+            .iter()
+            .filter(|o| stale::time_in_force(o) == "day")
+            .map(|o| market_time::eastern_date(o.created_at))
+            .min();
+        let calendar = match (first_day_order, self.client.as_ref()) {
+            (Some(start), Some(client)) if self.stale_rules.day_grace.is_some() => client
+                .get_calendar(start, market_time::eastern_today())
+                .map_err(|e| {
+                    logging::debug("stale", "Calendar unavailable; using weekdays")
+                        .field("error", e.as_str())
+                        .emit();
+                })
+                .ok(),
+            _ => None,
+        };
+        let now = Utc::now();
+        let mut stale: Vec<stale::StaleOrder> = working
+            .into_iter()
+            .filter_map(|o| stale::check(o, &self.stale_rules, calendar.as_deref(), now))
+            .collect();
+        stale.sort_by_key(|s| s.stale_since);
+        stale
+    }
+
+    /// Flag stale orders once each, canceling them when configured.
+    /// Returns the events and the cancel failures.
+    fn sweep_stale_orders(&mut self) -> (Vec<serde_json::Value>, Vec<serde_json::Value>) {
+        let mut events = Vec::new();
+        let mut errors = Vec::new();
+        if !self.stale_rules.is_enabled() {
+            return (events, errors);
+        }
+        let orders = &self.orders;
+        self.stale
+            .retain(|id| orders.get(id).is_some_and(orders::parent_working));
+
+        for stale in self.stale_orders() {
+            if !self.stale.first_report(&stale.order_id) {
+                continue;
+            }
+            if !self.stale_rules.cancel {
+                logging::warn("stale", "Stale order is still working")
+                    .field("order_id", stale.order_id.as_str())
+                    .field("symbol", stale.symbol.as_str())
+                    .emit();
+                events.push(serde_json::json!({
+                    "type": "stale_order",
+                    "event": "flagged",
+                    "order": stale,
+                }));
+                continue;
+            }
+            let Some(client) = self.client_for_order(&stale.order_id) else {
+                continue;
+            };
+            match client.cancel_order(&stale.order_id) {
+                Ok(()) => {
+                    logging::info("stale", "Stale order canceled")
+                        .field("order_id", stale.order_id.as_str())
+                        .field("symbol", stale.symbol.as_str())
+                        .emit();
+                    if let Some(order) = self.orders.get_mut(&stale.order_id) {
+                        orders::set_ext(order, "stale_cancel_requested", true);
+                    }
+                    events.push(serde_json::json!({
+                        "type": "stale_order",
+                        "event": "canceled",
+                        "order": stale,
+                    }));
+                }
+                Err(e) => {
+                    // Usually a fill that raced the sweep; the refresh
+                    // reports it
+                    logging::warn("stale", "Failed to cancel stale order")
+                        .field("order_id", stale.order_id.as_str())
+                        .field("error", e.as_str())
+                        .emit();
+                    errors.push(serde_json::json!({
+                        "type": "stale_order",
+                        "order_id": stale.order_id,
+                        "error": e,
+                    }));
+                }
+            }
+        }
+        (events, errors)
+    }
+
+    /// Latest NBBO quote for one symbol
+    fn latest_quote(&self, symbol: &str) -> Result<market_data::Quote, String> {
+        let client = self.client.as_ref().ok_or("Plugin not initialized")?;
+        // Snapshots come back keyed by the upper-case symbol
+        let key = symbol.to_ascii_uppercase();
+        client
+            .get_snapshots(std::slice::from_ref(&key))?
+            .remove(&key)
+            .and_then(|s| s.latest_quote)
+            .ok_or_else(|| format!("No quote available for {}", symbol))
+    }
+
+    fn price_request(
+        &self,
+        request: &OrderRequest,
+        mode: PricingMode,
+    ) -> Result<OrderRequest, String> {
+        let quote = self.latest_quote(&request.symbol_id)?;
+        let priced = pricing::apply(request, mode, &quote)?;
+        logging::info("pricing", "Limit price set from quote")
+            .field("symbol", request.symbol_id.as_str())
+            .field_opt("limit_price", priced.limit_price)
+            .emit();
+        Ok(priced)
+    }
+
+    /// Place an order, echoing its metadata on the result and on the
+    /// cached copy that later refreshes build on
+    fn place_order(&mut self, request: &OrderRequest) -> Order {
+        let order = self.place_routed(request);
+        self.rejections.record(&order);
+        order
+    }
+
+    /// [`Self::place_order`] without the rejection log
+    fn place_routed(&mut self, request: &OrderRequest) -> Order {
+        match self.prepare_placement(request) {
+            Routed::Done(order) => order,
+            Routed::Send(submission) => {
+                let sent = submission.send();
+                self.complete_placement(submission, sent)
+            }
+        }
+    }
+
+    /// Everything [`Self::place_routed`] does before the order goes to
+    /// Alpaca
+    fn prepare_placement(&mut self, request: &OrderRequest) -> Routed {
+        let metadata = match metadata::requested(request) {
+            Ok(metadata) => metadata.cloned(),
+            Err(e) => return Routed::Done(create_error_order(request, &e)),
+        };
+        let environment = match Environment::requested(request) {
+            Ok(environment) => environment,
+            Err(e) => return Routed::Done(create_error_order(request, &e)),
+        };
+        let routed = self.with_environment(environment, |state| {
+            let routed = state.prepare_route(request);
+            let placed_in = state.client.as_ref().map(|c| Environment::of(c.is_paper()));
+            (routed, placed_in)
+        });
+        match routed {
+            Ok((Routed::Done(order), placed_in)) => {
+                Routed::Done(self.placed(request, order, metadata, placed_in))
+            }
+            Ok((Routed::Send(mut submission), _)) => {
+                submission.metadata = metadata;
+                submission.environment = environment;
+                Routed::Send(submission)
+            }
+            Err(e) => Routed::Done(create_error_order(request, &e)),
+        }
+    }
+
+    /// Everything [`Self::place_routed`] does once Alpaca has answered
+    fn complete_placement(&mut self, mut submission: Box<Submission>, sent: Sent) -> Order {
+        let request = submission.requested.clone();
+        let metadata = submission.metadata.take();
+        let placed_in = Some(Environment::of(submission.is_paper));
+        let completed = self.with_environment(submission.environment, |state| {
+            state.complete_route(*submission, sent)
+        });
+        match completed {
+            Ok(order) => self.placed(&request, order, metadata, placed_in),
+            Err(e) => create_error_order(&request, &e),
+        }
+    }
+
+    /// Annotate a routed order and send its shadow copy
+    fn placed(
+        &mut self,
+        request: &OrderRequest,
+        mut order: Order,
+        metadata: Option<serde_json::Value>,
+        placed_in: Option<Environment>,
+    ) -> Order {
+        let mut annotations = Vec::new();
+        if let Some(metadata) = metadata {
+            annotations.push(("metadata", metadata));
+        }
+        if let Some(env) = placed_in.filter(|_| self.live_client.is_some()) {
+            annotations.push(("environment", env.as_str().into()));
+        }
+        for (key, value) in annotations {
+            self.annotate(&mut order, key, value);
+        }
+
+        let shadow_target = placed_in.and_then(|env| self.shadow_mode.target(env));
+        if let Some(target) = shadow_target {
+            if shadow::should_mirror(request, &order) && worked_locally(request).is_none() {
+                self.place_shadow(request, &mut order, target);
+            }
+        }
+        order
+    }
+
+    /// Set an extension on a placed order and on its cached copy
+    fn annotate(&mut self, order: &mut Order, key: &str, value: serde_json::Value) {
+        if let Some(cached) = self.orders.get_mut(&order.id) {
+            orders::set_ext(cached, key, value.clone());
+        }
+        orders::set_ext(order, key, value);
+    }
+
+    /// Send the shadow copy of `original` to `target` and link the two.
+    /// A failed copy is noted on the original and otherwise ignored.
+    fn place_shadow(&mut self, request: &OrderRequest, original: &mut Order, target: Environment) {
+        let mirror = shadow::mirror_request(request, original, target);
+        let placed = self.with_environment(Some(target), |state| state.route_order(&mirror));
+        let copy = placed.and_then(|copy| match copy.status {
+            OrderStatus::Rejected => Err(orders::ext_str(&copy, "error")
+                .unwrap_or("Shadow order rejected")
+                .to_string()),
+            _ => Ok(copy),
+        });
+        match copy {
+            Ok(mut copy) => {
+                self.annotate(&mut copy, "environment", target.as_str().into());
+                self.annotate(&mut copy, "shadow_of", original.id.clone().into());
+                self.annotate(original, "shadow_order_id", copy.id.into());
+                self.annotate(original, "shadow_environment", target.as_str().into());
+            }
+            Err(e) => {
+                logging::warn("shadow", "Shadow order failed")
+                    .field("order_id", original.id.as_str())
+                    .field("environment", target.as_str())
+                    .field("error", e.as_str())
+                    .emit();
+                self.annotate(original, "shadow_error", e.into());
+            }
+        }
+    }
+
+    /// Funds and shares held by the working orders of the environment
+    /// the current client trades in, and by basket orders in flight
+    fn reserved_funds(&self) -> ReservedFunds {
+        let mut funds = self.tracked_funds();
+        funds.merge(self.in_flight_funds());
+        funds
+    }
+
+    /// The part of [`Self::reserved_funds`] that account and position
+    /// figures fetched at `at` may not reflect: orders sent since, and
+    /// basket orders still in flight
+    fn unreflected_funds(&self, at: DateTime<Utc>) -> ReservedFunds {
+        let mut funds = self.tracked_funds().sent_since(at);
+        funds.merge(self.in_flight_funds());
+        funds
+    }
+
+    fn tracked_funds(&self) -> ReservedFunds {
+        let working = self
+            .orders
+            .values()
+            .filter(|order| self.in_current_environment(order));
+        ReservedFunds::from_orders(working, self.cost_buffer_pct)
+    }
+
+    fn in_flight_funds(&self) -> ReservedFunds {
+        let sending = self.in_flight.iter().map(|sending| &sending.order);
+        ReservedFunds::from_orders(sending, self.cost_buffer_pct)
+    }
+
+    /// Whether an order was placed in the environment currently selected
+    fn in_current_environment(&self, order: &Order) -> bool {
+        let current_live = self.environment_override == Some(Environment::Live);
+        let live =
+            self.live_client.is_some() && Environment::of_order(order) == Some(Environment::Live);
+        live == current_live
+    }
+
+    /// Everything [`daily_summary::build`] needs for one Eastern day.
+    /// Fill and cash-flow refresh failures are returned as warnings and
+    /// the summary is built from what is already known.
+    fn daily_summary(&mut self, date: chrono::NaiveDate) -> Result<serde_json::Value, String> {
+        let client = self.client.clone().ok_or("Plugin not initialized")?;
+        let mut warnings = Vec::new();
+        if let Err(e) = self.refresh_executions() {
+            warnings.push(format!("Fills may be incomplete: {}", e));
+        }
+        match client.list_cash_activities(self.cash_flows.fetch_after()) {
+            Ok(flows) => {
+                self.cash_flows.ingest(flows);
+            }
+            Err(e) => warnings.push(format!("Fees may be incomplete: {}", e)),
+        }
+        // Alpaca only reports the change since the previous close
+        let unrealized_change = if date == market_time::eastern_today() {
+            match client.unrealized_intraday_pl() {
+                Ok(change) => Some(change),
+                Err(e) => {
+                    warnings.push(format!("Unrealized PnL unavailable: {}", e));
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let midnight = chrono::NaiveTime::MIN;
+        let start = market_time::eastern_at(date, midnight);
+        let end = market_time::eastern_at(date + chrono::Duration::days(1), midnight);
+        let fills = self
+            .executions
+            .in_range(Some(start), Some(end))
+            .into_iter()
+            .filter(|e| e.timestamp < end)
+            .collect();
+        let ledger = self.pnl_ledger();
+        let fee_flows = self
+            .cash_flows
+            .query(&CashFlowQuery {
+                start: Some(date),
+                end: Some(date),
+                categories: Some(vec![cashflows::CashFlowCategory::Fee]),
+                ..Default::default()
+            })
+            .flows;
+        let mut orders: Vec<&Order> = self
+            .orders
+            .values()
+            .filter(|o| market_time::eastern_date(o.created_at) == date)
+            .filter(|o| self.in_current_environment(o))
+            .collect();
+        orders.sort_by_key(|o| o.created_at);
+
+        let day = DayActivity {
+            date,
+            orders,
+            fills,
+            realized: ledger
+                .realized
+                .iter()
+                .filter(|t| market_time::eastern_date(t.closed_at) == date)
+                .collect(),
+            unrealized_change,
+            fee_flows,
+            rejections: self.rejections.on(date),
+        };
+        let mut summary = daily_summary::build(&day, self.fees.as_ref());
+        summary["warnings"] = warnings.into();
+        Ok(summary)
+    }
+
+    /// The client for `env`; `None` is the default
+    fn client_in(&self, env: Option<Environment>) -> Result<Arc<AlpacaClient>, String> {
+        pick_client(self.client.as_ref(), self.live_client.as_ref(), env)
+    }
+
+    /// The client an order was placed with: the live one for orders
+    /// recorded as live, otherwise the default
+    fn client_for_order(&self, order_id: &str) -> Option<&Arc<AlpacaClient>> {
+        let live = self
+            .order_or_parent(order_id)
+            .and_then(Environment::of_order)
+            .is_some_and(|env| env == Environment::Live);
+        match &self.live_client {
+            Some(client) if live => Some(client),
+            _ => self.client.as_ref(),
+        }
+    }
+
+    /// Run `f` with `env`'s client standing in for the default one. The
+    /// default is put back when `f` returns, even by unwinding.
+    fn with_environment<T>(
+        &mut self,
+        env: Option<Environment>,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> Result<T, String> {
+        if env.is_none() {
+            return Ok(f(self));
+        }
+        let client = self.client_in(env)?;
+        if self
+            .client
+            .as_ref()
+            .is_some_and(|c| Arc::ptr_eq(c, &client))
+        {
+            return Ok(f(self));
+        }
+
+        struct Restore<'a> {
+            state: &'a mut BrokerState,
+            default: Option<Arc<AlpacaClient>>,
+        }
+        impl Drop for Restore<'_> {
+            fn drop(&mut self) {
+                self.state.client = self.default.take();
+                self.state.environment_override = None;
+            }
+        }
+
+        self.environment_override = Some(Environment::of(client.is_paper()));
+        let default = self.client.replace(client);
+        let restore = Restore {
+            state: self,
+            default,
+        };
+        Ok(f(&mut *restore.state))
+    }
+
+    /// Validate, check, and send (or hold) an order
+    fn route_order(&mut self, request: &OrderRequest) -> Order {
+        match self.prepare_route(request) {
+            Routed::Done(order) => order,
+            Routed::Send(submission) => {
+                let sent = submission.send();
+                self.complete_route(*submission, sent)
+            }
+        }
+    }
+
+    /// Everything [`Self::route_order`] does before the order goes to
+    /// Alpaca
+    fn prepare_route(&mut self, request: &OrderRequest) -> Routed {
+        let requested = request.clone();
+        if self.client.is_none() {
+            return Routed::Done(create_error_order(request, "Plugin not initialized"));
+        }
+        if let Some(env) = self.environment_override {
+            if let Some(feature) = worked_locally(request) {
+                return Routed::Done(create_error_order(
+                    request,
+                    &format!(
+                        "{} orders are only supported in the default environment, not {}",
+                        feature,
+                        env.as_str()
+                    ),
+                ));
+            }
+        }
+
+        let dry_run = orders::request_flag(request, "dry_run");
+        match schedule::requested(request) {
+            Ok(Some(_)) if dry_run => {
+                return Routed::Done(create_error_order(
+                    request,
+                    "dry_run cannot be combined with schedule",
+                ))
+            }
+            Ok(Some(spec)) => return Routed::Done(self.schedule_order(request, spec)),
+            Ok(None) => {}
+            Err(e) => return Routed::Done(create_error_order(request, &e)),
+        }
+
+        // A preview must not trip the switch (which cancels open orders)
+        if !dry_run
+            && self.kill_switch.trading_enabled()
+            && self.kill_switch.auto.max_drawdown_pct.is_some()
+        {
+            match self.client.as_ref().map(|c| c.fetch_account()) {
+                Some(Ok(account)) if self.kill_switch.check_drawdown(&account) => {
+                    self.on_auto_trip();
+                }
+                Some(Err(e)) => {
+                    logging::warn("kill_switch", "Account unavailable for drawdown check")
+                        .field("error", e.as_str())
+                        .emit();
+                }
+                _ => {}
+            }
+        }
+        if let Some(finding) = self.kill_switch.rejection() {
+            return Routed::Done(create_rejected_order(request, &finding, &[]));
+        }
+        if let Some(reason) = self.client.as_ref().and_then(|c| c.trading_block()) {
+            return Routed::Done(create_rejected_order(
+                request,
+                &account_blocked(reason),
+                &[],
+            ));
+        }
+        let is_paper = self.client.as_ref().is_none_or(|c| c.is_paper());
+        if let Some(finding) = self.live_interlock.rejection(is_paper) {
+            return Routed::Done(create_rejected_order(request, &finding, &[]));
+        }
+
+        // Priced here, after scheduling, so held orders use the quote at
+        // the time they are actually sent
+        let priced;
+        let request = match pricing::requested(request) {
+            Ok(Some(mode)) => match self.price_request(request, mode) {
+                Ok(r) => {
+                    priced = r;
+                    &priced
+                }
+                Err(e) => return Routed::Done(create_error_order(request, &e)),
+            },
+            Ok(None) => request,
+            Err(e) => return Routed::Done(create_error_order(request, &e)),
+        };
+        let auctioned;
+        let request = match auction::requested(request) {
+            Ok(Some(r)) => {
+                auctioned = r;
+                &auctioned
+            }
+            Ok(None) => request,
+            Err(e) => return Routed::Done(create_error_order(request, &e)),
+        };
+
+        let lot_selection = match LotSelection::from_extensions(request.extensions.as_ref()) {
+            Ok(selection) => selection,
+            Err(e) => return Routed::Done(create_error_order(request, &e)),
+        };
+
+        let expiry = match gtd::requested_expiry(request) {
+            Ok(expiry) => expiry,
+            Err(e) => return Routed::Done(create_error_order(request, &e)),
+        };
+
+        let algo_spec = match algo::requested(request) {
+            Ok(Some(_)) if expiry.is_some() => {
+                return Routed::Done(create_error_order(
+                    request,
+                    "expire_at cannot be combined with algo",
+                ))
+            }
+            Ok(Some(_)) if dry_run => {
+                return Routed::Done(create_error_order(
+                    request,
+                    "dry_run cannot be combined with algo",
+                ))
+            }
+            Ok(spec) => spec,
+            Err(e) => return Routed::Done(create_error_order(request, &e)),
+        };
+
+        match chase::requested(request) {
+            Ok(Some(_)) if algo_spec.is_some() => {
+                return Routed::Done(create_error_order(
+                    request,
+                    "chase cannot be combined with algo",
+                ))
+            }
+            Ok(_) => {}
+            Err(e) => return Routed::Done(create_error_order(request, &e)),
+        }
+        if let Err(e) = tags::requested(request) {
+            return Routed::Done(create_error_order(request, &e));
+        }
+        if let Err(e) = intent::requested(request) {
+            return Routed::Done(create_error_order(request, &e));
+        }
+
+        if let Some(finding) = validation::check(request, self.price_band_pct) {
+            return Routed::Done(create_rejected_order(request, &finding, &[]));
+        }
+
+        if let Some(finding) = self.dedupe.check(request) {
+            logging::warn("orders", "Duplicate order rejected")
+                .field("symbol", request.symbol_id.as_str())
+                .field("code", finding.code.as_str())
+                .emit();
+            return Routed::Done(create_rejected_order(request, &finding, &[]));
+        }
+        if let Some(finding) = self.throttle.check(&request.symbol_id) {
+            logging::warn("orders", "Order throttled")
+                .field("symbol", request.symbol_id.as_str())
+                .field(
+                    "scope",
+                    finding.details["scope"].as_str().unwrap_or_default(),
+                )
+                .emit();
+            return Routed::Done(create_rejected_order(request, &finding, &[]));
+        }
+
+        let checks = self.pretrade_checks(request);
+        if let Some(finding) = checks.block {
+            logging::warn("orders", "Order blocked by pre-trade check")
+                .field("symbol", request.symbol_id.as_str())
+                .field("code", finding.code.as_str())
+                .emit();
+            return Routed::Done(create_rejected_order(request, &finding, &checks.warnings));
+        }
+
+        if let Some(spec) = algo_spec {
+            let mut order = self.start_algo(request, spec);
+            checks.annotate(&mut order);
+            return Routed::Done(order);
+        }
+
+        let mut order_request = match expiry {
+            Some(_) => gtd::as_gtc(request),
+            None => request.clone(),
+        };
+        let policy = self.closed_market_policy;
+        if policy != ClosedMarketPolicy::Submit && market_hours::is_gated(&order_request) {
+            match self.client.as_ref().map(|c| c.get_clock()) {
+                Some(Ok(clock)) if !clock.is_open => {
+                    match market_hours::decide(policy, &order_request) {
+                        Gate::Submit(converted) => order_request = converted,
+                        Gate::Queue if dry_run => {
+                            let mut order = self.dry_run_order(request, &order_request, &checks);
+                            orders::set_ext(&mut order, "would_queue", true);
+                            return Routed::Done(order);
+                        }
+                        Gate::Queue if self.environment_override.is_some() => {
+                            return Routed::Done(create_error_order(
+                                request,
+                                "Orders for this environment cannot be queued while the market is closed",
+                            ));
+                        }
+                        Gate::Queue => {
+                            let mut order =
+                                self.order_queue.push(request.clone(), &clock).to_order();
+                            checks.annotate(&mut order);
+                            logging::info("orders", "Order queued until market open")
+                                .field("queue_id", order.id.as_str())
+                                .field("symbol", request.symbol_id.as_str())
+                                .emit();
+                            self.daily_orders.increment();
+                            self.throttle.record(&request.symbol_id);
+                            self.live_interlock.record(checks.live_notional, is_paper);
+                            self.dedupe.record(request, &order.id);
+                            return Routed::Done(order);
+                        }
+                    }
+                }
+                Some(Err(e)) => {
+                    logging::warn(
+                        "market_hours",
+                        "Clock unavailable; submitting without gating",
+                    )
+                    .field("error", e.as_str())
+                    .emit();
+                }
+                _ => {}
+            }
+        }
+
+        let client = match self.client.clone() {
+            Some(c) => c,
+            None => {
+                return Routed::Done(create_error_order(request, "Plugin not initialized"));
+            }
+        };
+
+        if let Some(auction) = auction::Auction::of(&order_request) {
+            if let Some(finding) = auction_cutoff(&client, auction) {
+                logging::warn("orders", "Auction order past its cutoff")
+                    .field("symbol", request.symbol_id.as_str())
+                    .emit();
+                return Routed::Done(create_rejected_order(request, &finding, &checks.warnings));
+            }
+        }
+
+        if dry_run {
+            return Routed::Done(self.dry_run_order(request, &order_request, &checks));
+        }
+
+        let reference = self.reference_quote(&order_request);
+        if let Some(price) = reference.as_ref().and_then(|r| r.price()) {
+            order_request.reference_price = Some(price);
+        }
+
+        // Counted when sent, so a loop of orders Alpaca rejects is capped too
+        self.throttle.record(&request.symbol_id);
+        Routed::Send(Box::new(Submission {
+            requested,
+            request: request.clone(),
+            order_request,
+            lot_selection,
+            reference,
+            expiry,
+            checks,
+            is_paper,
+            client,
+            metadata: None,
+            environment: None,
+        }))
+    }
+
+    /// Everything [`Self::route_order`] does once Alpaca has answered
+    fn complete_route(&mut self, submission: Submission, sent: Sent) -> Order {
+        let Submission {
+            request,
+            order_request,
+            lot_selection,
+            reference,
+            expiry,
+            checks,
+            is_paper,
+            client,
+            ..
+        } = submission;
+        let request = &request;
+        let (result, latency_ms) = sent;
+        match result {
+            Ok(mut order) => {
+                client.metrics().record_order("submitted", latency_ms);
+                let order_id = order.id.clone();
+                if order.persona_id.is_empty() {
+                    order.persona_id = request.persona_id.clone();
+                }
+                if let Some(selection) = &lot_selection {
+                    orders::set_ext(&mut order, "lot_selection", selection.to_json());
+                }
+                order.request.reference_price = order_request.reference_price;
+                if let Some(reference) = &reference {
+                    orders::set_ext(
+                        &mut order,
+                        "reference_quote",
+                        serde_json::to_value(reference).unwrap_or_default(),
+                    );
+                }
+                if let Some(expiry) = expiry {
+                    orders::set_ext(&mut order, "expire_at", expiry.to_rfc3339());
+                }
+                checks.annotate(&mut order);
+                if let Some(fees) = &self.fees {
+                    let price = pretrade::estimate_price(&order_request, None);
+                    orders::set_ext(
+                        &mut order,
+                        "estimated_costs",
+                        fees.estimate(&order_request, price),
+                    );
+                }
+                logging::info("orders", "Order submitted")
+                    .field("order_id", order_id.as_str())
+                    .field("symbol", order.request.symbol_id.as_str())
+                    .emit();
+                self.daily_orders.increment();
+                self.live_interlock.record(checks.live_notional, is_paper);
+                self.kill_switch.record_submission(false);
+                self.dedupe.record(request, &order.id);
+                if let Some(expiry) = expiry {
+                    self.gtd.insert(&order.id, expiry);
+                }
+                self.start_chase(&order);
+                self.track_order(order.clone());
+
+                order
+            }
+            Err(e) => {
+                client.metrics().record_order("rejected", latency_ms);
+                logging::error("orders", "Order failed")
+                    .field("symbol", request.symbol_id.as_str())
+                    .field("error", e.as_str())
+                    .emit();
+                if self.kill_switch.record_submission(true) {
+                    self.on_auto_trip();
+                }
+                create_error_order(request, &e)
+            }
+        }
+    }
+
+    /// Snapshot of the market for an order sent without a
+    /// `reference_price`, with `reference_prices` on. A failed snapshot
+    /// only costs the reference.
+    fn reference_quote(&self, request: &OrderRequest) -> Option<reference::ReferenceQuote> {
+        if !self.reference_prices
+            || request.reference_price.is_some()
+            || fees::AssetKind::of(&request.symbol_id) != fees::AssetKind::Equity
+        {
+            return None;
+        }
+        let client = self.client.as_ref()?;
+        // Snapshots come back keyed by the upper-case symbol
+        let symbol = request.symbol_id.to_ascii_uppercase();
+        let snapshot = client
+            .get_snapshots(std::slice::from_ref(&symbol))
+            .map_err(|e| {
+                logging::warn("orders", "Snapshot for reference price failed")
+                    .field("symbol", symbol.as_str())
+                    .field("error", e.as_str())
+                    .emit();
+            })
+            .ok()?
+            .remove(&symbol)?;
+        reference::ReferenceQuote::from_snapshot(&snapshot, Utc::now())
+    }
+
+    /// Start chasing a newly submitted order if it asked for it
+    fn start_chase(&mut self, order: &Order) {
+        let Ok(Some(policy)) = chase::requested(&order.request) else {
+            return;
+        };
+        if let Err(e) = self
+            .chases
+            .start(&order.id, &order.request, policy, Utc::now())
+        {
+            logging::warn("chase", "Chase not started")
+                .field("order_id", order.id.as_str())
+                .field("error", e.as_str())
+                .emit();
+        }
+    }
+
+    /// Step every due chase toward the market. Orders that have finished,
+    /// or whose policy is exhausted, leave the book.
+    fn tick_chases(&mut self) -> (Vec<serde_json::Value>, Vec<serde_json::Value>) {
+        let mut events = Vec::new();
+        let mut errors = Vec::new();
+        let now = Utc::now();
+
+        for order_id in self.chases.due(now) {
+            let Some(client) = self.client.as_ref() else {
+                break;
+            };
+            let current = match client.get_order(&order_id) {
+                Ok(order) => match self.orders.get(&order_id) {
+                    Some(previous) => orders::merge_refresh(previous, order),
+                    None => order,
+                },
+                Err(e) => {
+                    errors.push(
+                        serde_json::json!({ "type": "chase", "order_id": order_id, "error": e }),
+                    );
+                    continue;
+                }
+            };
+            if !orders::parent_working(&current) {
+                self.chases.remove(&order_id);
+                self.track_order(current);
+                continue;
+            }
+
+            let Some(price) = self.chases.get(&order_id).and_then(|c| c.next_price()) else {
+                if let Some(chase) = self.chases.remove(&order_id) {
+                    logging::info("chase", "Chase finished at its limit")
+                        .field("order_id", order_id.as_str())
+                        .emit();
+                    events.push(serde_json::json!({
+                        "type": "chase",
+                        "event": "exhausted",
+                        "chase": chase,
+                    }));
+                }
+                self.track_order(current);
+                continue;
+            };
+
+            match client.replace_order(&order_id, price) {
+                Ok(replacement) => {
+                    let mut replacement = orders::merge_refresh(&current, replacement);
+                    replacement.request.limit_price = Some(decimal::to_f64(price));
+                    orders::set_ext(&mut replacement, "replaces", order_id.as_str());
+                    logging::info("chase", "Order repriced")
+                        .field("order_id", order_id.as_str())
+                        .field("replacement_id", replacement.id.as_str())
+                        .field("limit_price", decimal::to_wire(price))
+                        .emit();
+
+                    let event =
+                        orders::repriced_event(&replacement, &order_id, decimal::to_wire(price));
+                    events.push(serde_json::json!({
+                        "type": "chase",
+                        "event": "repriced",
+                        "order": replacement,
+                        "chase": self.chases.record_reprice(&order_id, &replacement.id, price, now),
+                    }));
+                    JOURNAL
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .record(event);
+                    self.track_order(current);
+                    self.track_order(replacement);
+                }
+                Err(e) => {
+                    // Usually a fill racing the replace; the next tick sees
+                    // the final state
+                    logging::warn("chase", "Reprice failed")
+                        .field("order_id", order_id.as_str())
+                        .field("error", e.as_str())
+                        .emit();
+                    errors.push(
+                        serde_json::json!({ "type": "chase", "order_id": order_id, "error": e }),
+                    );
+                }
+            }
+        }
+        (events, errors)
+    }
+
+    /// Keep protective orders in step with their positions: resize them
+    /// when a position grows or shrinks, cancel them when it closes
+    fn tick_protections(&mut self) -> (Vec<serde_json::Value>, Vec<serde_json::Value>) {
+        let mut events = Vec::new();
+        let mut errors = Vec::new();
+        if self.protections.is_empty() {
+            return (events, errors);
+        }
+        let Some(client) = self.client.clone() else {
+            return (events, errors);
+        };
+        let held: HashMap<String, Decimal> = match client.get_positions() {
+            Ok(positions) => positions
+                .iter()
+                .map(|p| {
+                    let qty = decimal::from_f64(p.quantity).unwrap_or_default();
+                    (p.symbol_id.clone(), qty)
+                })
+                .collect(),
+            Err(e) => {
+                errors.push(serde_json::json!({ "type": "protective", "error": e }));
+                return (events, errors);
+            }
+        };
+
+        for protection in self.protections.list() {
+            let symbol = protection.symbol.as_str();
+            if !self.protective_adjust.applies(symbol) {
+                continue;
+            }
+            let position_qty = held.get(symbol).copied().unwrap_or_default();
+            let adjustment = protection.adjustment(position_qty);
+            if adjustment == protective::Adjustment::Keep {
+                if let Some(entry) = self.protections.get_mut(symbol) {
+                    entry.position_qty = position_qty;
+                }
+                continue;
+            }
+            let order_id = protection.order_id.as_str();
+            let current = match client.get_order(order_id) {
+                Ok(order) => match self.orders.get(order_id) {
+                    Some(previous) => orders::merge_refresh(previous, order),
+                    None => order,
+                },
+                Err(e) => {
+                    errors.push(serde_json::json!({
+                        "type": "protective",
+                        "order_id": order_id,
+                        "error": e
+                    }));
+                    continue;
+                }
+            };
+            let event = |kind: &str, order: &Order, qty: Decimal, reason: Option<&str>| {
+                serde_json::json!({
+                    "event": kind,
+                    "symbol": symbol,
+                    "reason": reason,
+                    "order_id": order.id,
+                    "previous_order_id": order_id,
+                    "qty": decimal::to_wire(qty),
+                    "previous_qty": decimal::to_wire(protection.qty),
+                    "position_qty": decimal::to_wire(position_qty),
+                    "previous_position_qty": decimal::to_wire(protection.position_qty),
+                })
+            };
+
+            // Filled or canceled since; nothing left to keep in step
+            if !orders::is_working(&current) {
+                self.protections.remove(symbol);
+                events.push(event(
+                    "released",
+                    &current,
+                    protection.qty,
+                    orders::ext_str(&current, "alpaca_status"),
+                ));
+                self.track_order(current);
+                continue;
+            }
+
+            match adjustment {
+                protective::Adjustment::Keep => {}
+                protective::Adjustment::Resize(qty) => match client.resize_order(order_id, qty) {
+                    Ok(replacement) => {
+                        let mut replacement = orders::merge_refresh(&current, replacement);
+                        replacement.request.quantity = decimal::to_f64(qty);
+                        orders::set_ext(&mut replacement, "replaces", order_id);
+                        logging::info("protective", "Protective order resized")
+                            .field("symbol", symbol)
+                            .field("order_id", order_id)
+                            .field("replacement_id", replacement.id.as_str())
+                            .field("qty", decimal::to_wire(qty))
+                            .emit();
+                        events.push(event("resized", &replacement, qty, None));
+                        if let Some(entry) = self.protections.get_mut(symbol) {
+                            entry.order_id = replacement.id.clone();
+                            entry.qty = qty;
+                            entry.position_qty = position_qty;
+                            entry.adjustments += 1;
+                        }
+                        self.track_order(current);
+                        self.track_order(replacement);
+                    }
+                    Err(e) => {
+                        logging::warn("protective", "Protective resize failed")
+                            .field("order_id", order_id)
+                            .field("error", e.as_str())
+                            .emit();
+                        errors.push(serde_json::json!({
+                            "type": "protective",
+                            "order_id": order_id,
+                            "error": e
+                        }));
+                    }
+                },
+                protective::Adjustment::Cancel(reason) => match client.cancel_order(order_id) {
+                    Ok(()) => {
+                        logging::info("protective", "Protective order canceled")
+                            .field("symbol", symbol)
+                            .field("order_id", order_id)
+                            .field("reason", reason)
+                            .emit();
+                        self.protections.remove(symbol);
+                        events.push(event("canceled", &current, Decimal::ZERO, Some(reason)));
+                        self.track_order(current);
+                    }
+                    Err(e) => {
+                        errors.push(serde_json::json!({
+                            "type": "protective",
+                            "order_id": order_id,
+                            "error": e
+                        }));
+                    }
+                },
+            }
+        }
+        for event in &events {
+            events::push("protective", event.clone());
+        }
+        let events = events
+            .into_iter()
+            .map(|mut e| {
+                e["type"] = "protective".into();
+                e
+            })
+            .collect();
+        (events, errors)
+    }
+
+    /// Remap cached state after ticker changes, see [`symbol_change`]
+    fn apply_symbol_changes(
+        &mut self,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<symbol_change::SymbolChanged>, String> {
+        // The simulator has no corporate actions
+        if !self.symbol_changes || self.simulator.is_some() || !self.symbol_watch.is_due(now) {
+            return Ok(Vec::new());
+        }
+        let client = self.client.clone().ok_or("Plugin not initialized")?;
+        self.symbol_watch.mark_checked(now);
+        let today = market_time::eastern_today();
+        let (start, end) = symbol_change::SymbolChangeWatch::window(today);
+        let changes = client.get_name_changes(start, end)?;
+
+        let mut applied = Vec::new();
+        for change in self.symbol_watch.take_new(changes, today) {
+            // Reported even when nothing was cached: hosts hold references
+            // of their own
+            let remapped = self.rename_symbol(&change.old_symbol, &change.new_symbol);
+            logging::info("symbols", "Symbol changed")
+                .field("old_symbol", change.old_symbol.as_str())
+                .field("new_symbol", change.new_symbol.as_str())
+                .field("orders", remapped.orders)
+                .field("executions", remapped.executions)
+                .emit();
+            applied.push(symbol_change::SymbolChanged::new(&change, remapped));
+        }
+        Ok(applied)
+    }
+
+    /// Move everything cached under `old` to `new`
+    fn rename_symbol(&mut self, old: &str, new: &str) -> symbol_change::Remapped {
+        let mut renamed_orders = 0;
+        for order in self.orders.values_mut() {
+            if orders::rename_symbol(&mut order.request, old, new) {
+                orders::set_ext(order, "previous_symbol", old);
+                renamed_orders += 1;
+            }
+        }
+        symbol_change::Remapped {
+            orders: renamed_orders,
+            executions: self.executions.rename_symbol(old, new),
+            queued_orders: self.order_queue.rename_symbol(old, new),
+            scheduled_orders: self.scheduled.rename_symbol(old, new),
+            algo_orders: self.algos.rename_symbol(old, new),
+            conditional_orders: self.conditionals.rename_symbol(old, new),
+            protective_orders: usize::from(self.protections.rename_symbol(old, new)),
+            position_changes: self.position_changes.rename_symbol(old, new),
+        }
+    }
+
+    /// Preview of an order that passed every check: the payload that would
+    /// be sent and its estimated cost. Nothing is submitted or recorded.
+    fn dry_run_order(
+        &self,
+        request: &OrderRequest,
+        order_request: &OrderRequest,
+        checks: &CheckReport,
+    ) -> Order {
+        let payload = match alpaca::order_payload(order_request) {
+            Ok(payload) => payload,
+            Err(e) => return create_error_order(request, &e),
+        };
+        let (account, position) = match self.client.as_ref() {
+            Some(client) => (
+                client.fetch_account().ok(),
+                client.get_position(&request.symbol_id).ok().flatten(),
+            ),
+            None => (None, None),
+        };
+        let impact = pretrade::estimate_impact(
+            order_request,
+            account.as_ref(),
+            position.as_ref(),
+            self.cost_buffer_pct,
+        );
+
+        // Not sent, so never working; a preview is not a rejection either
+        let mut order = Order {
+            id: format!("dryrun_{}", Utc::now().timestamp_millis()),
+            request: order_request.clone(),
+            status: OrderStatus::Canceled,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            average_filled_price: None,
+            filled_quantity: 0.0,
+            extensions: None,
+            persona_id: order_request.persona_id.clone(),
+        };
+        orders::set_ext(&mut order, "dry_run", true);
+        if let Some(trace_id) = trace::current() {
+            orders::set_ext(&mut order, "trace_id", trace_id);
+        }
+        orders::set_ext(&mut order, "payload", payload);
+        orders::set_ext(&mut order, "impact", impact);
+        if let Some(fees) = &self.fees {
+            let price = pretrade::estimate_price(order_request, position.as_ref());
+            orders::set_ext(
+                &mut order,
+                "estimated_costs",
+                fees.estimate(order_request, price),
+            );
+        }
+        checks.annotate(&mut order);
+        logging::info("orders", "Dry run")
+            .field("symbol", request.symbol_id.as_str())
+            .emit();
+        order
+    }
+
+    /// Hold a scheduled order until its release time (or place it now if
+    /// that time has already come)
+    fn schedule_order(&mut self, request: &OrderRequest, spec: ScheduleSpec) -> Order {
+        let clock = if spec.needs_clock() {
+            match self.client.as_ref().map(|c| c.get_clock()) {
+                Some(Ok(clock)) => Some(clock),
+                Some(Err(e)) => return create_error_order(request, &e),
+                None => None,
+            }
+        } else {
+            None
+        };
+
+        let now = Utc::now();
+        let (release_at, resolved) = match spec.resolve(request, clock.as_ref(), now) {
+            Ok(resolved) => resolved,
+            Err(e) => return create_error_order(request, &e),
+        };
+        if release_at <= now {
+            return self.place_order(&resolved);
+        }
+
+        let order = self.scheduled.push(resolved, spec, release_at).to_order();
+        logging::info("schedule", "Order scheduled")
+            .field("schedule_id", order.id.as_str())
+            .field("symbol", request.symbol_id.as_str())
+            .field("release_at", release_at.to_rfc3339())
+            .emit();
+        order
+    }
+
+    /// Place scheduled orders whose time has come
+    fn release_scheduled_orders(&mut self) -> Vec<serde_json::Value> {
+        let mut events = Vec::new();
+        for entry in self.scheduled.take_due(Utc::now()) {
+            let order = self.place_order(&entry.request);
+            logging::info("schedule", "Scheduled order released")
+                .field("schedule_id", entry.schedule_id.as_str())
+                .field("order_id", order.id.as_str())
+                .emit();
+            events.push(serde_json::json!({
+                "type": "scheduled",
+                "schedule_id": entry.schedule_id,
+                "release_at": entry.release_at,
+                "order": order,
+            }));
+        }
+        events
+    }
+
+    /// Create an algo parent and send its first slice if it is already due
+    fn start_algo(&mut self, request: &OrderRequest, spec: AlgoSpec) -> Order {
+        let id = self.algos.create(request, spec).id.clone();
+        self.dedupe.record(request, &id);
+        logging::info("algo", "Algo order started")
+            .field("algo_id", id.as_str())
+            .field("symbol", request.symbol_id.as_str())
+            .emit();
+        self.work_algo(&id);
+        self.algos
+            .get(&id)
+            .map(|a| a.to_order())
+            .unwrap_or_else(|| create_error_order(request, "Algo order not found"))
+    }
+
+    /// Refresh a child order from Alpaca if it may still change
+    fn refresh_child(&mut self, order_id: &str) -> Result<(), String> {
+        let Some(previous) = self.orders.get(order_id).filter(|o| orders::is_working(o)) else {
+            return Ok(());
+        };
+        let client = self.client.as_ref().ok_or("Plugin not initialized")?;
+        let refreshed = orders::merge_refresh(previous, client.get_order(order_id)?);
+        self.track_order(refreshed);
+        Ok(())
+    }
+
+    /// A tracked order, refreshed from its own environment if it may
+    /// still change
+    fn refresh_tracked(&mut self, order_id: &str) -> Result<Order, String> {
+        let previous = self
+            .orders
+            .get(order_id)
+            .ok_or_else(|| format!("Order {} is not tracked", order_id))?;
+        if !orders::is_working(previous) {
+            return Ok(previous.clone());
+        }
+        let client = self
+            .client_for_order(order_id)
+            .ok_or("Plugin not initialized")?;
+        let refreshed = orders::merge_refresh(previous, client.get_order(order_id)?);
+        self.track_order(refreshed.clone());
+        Ok(refreshed)
+    }
+
+    /// Advance one algo parent: refresh its children, send a due slice,
+    /// and finish it when filled or out of time. Returns tick events.
+    fn work_algo(&mut self, id: &str) -> Vec<serde_json::Value> {
+        let mut events = Vec::new();
+        let now = Utc::now();
+        let Some(children) = self.algos.get(id).map(|a| a.children.clone()) else {
+            return events;
+        };
+
+        for child in &children {
+            if let Err(e) = self.refresh_child(child) {
+                logging::warn("algo", "Failed to refresh child order")
+                    .field("algo_id", id)
+                    .field("order_id", child.as_str())
+                    .field("error", e.as_str())
+                    .emit();
+            }
+        }
+        let working_child = children
+            .iter()
+            .find(|c| self.orders.get(*c).is_some_and(orders::is_working))
+            .cloned();
+        self.apply_algo_fills(id, now);
+
+        let Some(algo) = self.algos.get(id) else {
+            return events;
+        };
+        if !algo.is_working() {
+            events.push(algo_event(algo, "completed", None));
+            return events;
+        }
+        let slice_due = algo.slice_due(now, working_child.is_some());
+        let out_of_slices = !slice_due && algo.window_over(now);
+        if !slice_due && !out_of_slices {
+            return events;
+        }
+
+        // Unfilled quantity on the working child rolls into the next slice,
+        // so it is canceled first; fills are only final once the cancel is
+        if let Some(child) = working_child.filter(|_| algo.is_timed()) {
+            let canceled = self
+                .client
+                .as_ref()
+                .map(|c| c.cancel_order(&child))
+                .unwrap_or(Err("Plugin not initialized".to_string()))
+                .and_then(|_| self.refresh_child(&child));
+            let still_working = self.orders.get(&child).is_some_and(orders::is_working);
+            if canceled.is_err() || still_working {
+                return events;
+            }
+            self.apply_algo_fills(id, now);
+        }
+
+        let Some(algo) = self.algos.get_mut(id) else {
+            return events;
+        };
+        if !algo.is_working() {
+            events.push(algo_event(algo, "completed", None));
+            return events;
+        }
+        if out_of_slices {
+            algo.status = AlgoStatus::Expired;
+            algo.updated_at = now;
+            logging::info("algo", "Algo window ended with quantity unfilled")
+                .field("algo_id", id)
+                .field("remaining_qty", decimal::to_wire(algo.remaining_qty()))
+                .emit();
+            events.push(algo_event(algo, "expired", None));
+            return events;
+        }
+
+        let avg_volume = match algo.volume_lookback() {
+            Some(minutes) => {
+                let symbol = algo.request.symbol_id.clone();
+                let bars = self
+                    .client
+                    .as_ref()
+                    .map(|c| c.get_minute_bars(&symbol, now - chrono::Duration::minutes(minutes)))
+                    .unwrap_or(Err("Plugin not initialized".to_string()));
+                match bars {
+                    // Minutes without trades have no bar, so average over the
+                    // whole lookback rather than the bars returned
+                    Ok(bars) if !bars.is_empty() => {
+                        let total: Decimal = bars.iter().map(|b| b.volume).sum();
+                        Some(total / Decimal::from(minutes))
+                    }
+                    Ok(_) => None,
+                    Err(e) => {
+                        logging::warn("algo", "Minute bars unavailable; slice skipped")
+                            .field("algo_id", id)
+                            .field("error", e.as_str())
+                            .emit();
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let reprice = match algo.reprice_source() {
+            Some(source) => {
+                let symbol = algo.request.symbol_id.to_ascii_uppercase();
+                let snapshots = self
+                    .client
+                    .as_ref()
+                    .map(|c| c.get_snapshots(std::slice::from_ref(&symbol)))
+                    .unwrap_or(Err("Plugin not initialized".to_string()));
+                match snapshots {
+                    Ok(snapshots) => snapshots.get(&symbol).and_then(|s| s.price(source)),
+                    Err(e) => {
+                        logging::warn("algo", "Quote unavailable; slice uses the parent limit")
+                            .field("algo_id", id)
+                            .field("error", e.as_str())
+                            .emit();
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let Some(algo) = self.algos.get_mut(id) else {
+            return events;
+        };
+        let qty = algo.next_slice_qty(avg_volume);
+        let child_request = algo.child_request(qty, reprice);
+        algo.next_slice += 1;
+        if qty.is_zero() {
+            return events;
+        }
+
+        let child = self.place_order(&child_request);
+        let Some(algo) = self.algos.get_mut(id) else {
+            return events;
+        };
+        let kind = if child.status == OrderStatus::Rejected {
+            logging::warn("algo", "Algo slice rejected")
+                .field("algo_id", id)
+                .field_opt(
+                    "error",
+                    orders::ext_str(&child, "error").map(str::to_string),
+                )
+                .emit();
+            // Timed algos roll the quantity into the next slice; an iceberg
+            // would resend the same slice on every tick, so it stops
+            if !algo.is_timed() {
+                algo.status = AlgoStatus::Failed;
+                algo.updated_at = now;
+            }
+            "slice_rejected"
+        } else {
+            algo.children.push(child.id.clone());
+            algo.updated_at = now;
+            "slice_submitted"
+        };
+        events.push(algo_event(algo, kind, Some(&child)));
+        events
+    }
+
+    fn apply_algo_fills(&mut self, id: &str, now: DateTime<Utc>) {
+        let Some(algo) = self.algos.get_mut(id) else {
+            return;
+        };
+        let children: Vec<&Order> = algo
+            .children
+            .iter()
+            .filter_map(|c| self.orders.get(c))
+            .collect();
+        algo.apply_fills(&children, now);
+    }
+
+    /// Advance every working algo parent
+    fn tick_algos(&mut self) -> Vec<serde_json::Value> {
+        let mut events = Vec::new();
+        for id in self.algos.working_ids() {
+            events.extend(self.work_algo(&id));
+        }
+        events
+    }
+
+    /// Cancel an algo parent and its working child
+    fn cancel_algo(&mut self, id: &str) -> Result<(), String> {
+        let children = match self.algos.get(id) {
+            Some(algo) if algo.is_working() => algo.children.clone(),
+            Some(_) => return Err(format!("Algo order {} is no longer working", id)),
+            None => return Err(format!("Unknown algo order: {}", id)),
+        };
+        let client = self.client.as_ref().ok_or("Plugin not initialized")?;
+        for child in children {
+            if self.orders.get(&child).is_some_and(orders::is_working) {
+                client.cancel_order(&child)?;
+            }
+        }
+        if let Some(algo) = self.algos.get_mut(id) {
+            algo.status = AlgoStatus::Canceled;
+            algo.updated_at = Utc::now();
+        }
+        Ok(())
+    }
+
+    /// Cancel everything working under a tag or basket: algo parents, held
+    /// and conditional orders, and Alpaca orders. Returns the canceled IDs
+    /// and per-order errors.
+    fn cancel_by_tag(
+        &mut self,
+        tag: Option<&str>,
+        basket_id: Option<&str>,
+    ) -> (Vec<String>, Vec<serde_json::Value>) {
+        let mut canceled = Vec::new();
+        let mut errors = Vec::new();
+
+        let algo_ids: Vec<String> = self
+            .algos
+            .list()
+            .iter()
+            .filter(|a| a.is_working() && tags::request_matches(&a.request, tag, basket_id))
+            .map(|a| a.id.clone())
+            .collect();
+        for id in algo_ids {
+            match self.cancel_algo(&id) {
+                Ok(()) => canceled.push(id),
+                Err(e) => errors.push(serde_json::json!({ "order_id": id, "error": e })),
+            }
+        }
+
+        let held: Vec<String> = self
+            .order_queue
+            .list()
+            .iter()
+            .filter(|q| tags::request_matches(&q.request, tag, basket_id))
+            .map(|q| q.queue_id.clone())
+            .chain(
+                self.scheduled
+                    .list()
+                    .iter()
+                    .filter(|s| tags::request_matches(&s.request, tag, basket_id))
+                    .map(|s| s.schedule_id.clone()),
+            )
+            .collect();
+        for id in held {
+            self.order_queue.remove(&id);
+            self.scheduled.remove(&id);
+            canceled.push(id);
+        }
+
+        let conditional_ids: Vec<String> = self
+            .conditionals
+            .list()
+            .iter()
+            .filter(|c| {
+                c.status == conditional::ConditionalStatus::Pending
+                    && tags::request_matches(&c.order, tag, basket_id)
+            })
+            .map(|c| c.id.clone())
+            .collect();
+        for id in conditional_ids {
+            if self.conditionals.cancel(&id).is_ok() {
+                canceled.push(id);
+            }
+        }
+
+        let Some(client) = self.client.clone() else {
+            return (canceled, errors);
+        };
+        // Alpaca's open orders include ones placed in earlier sessions,
+        // tagged through their client order ID. The cache adds what that
+        // ID cannot carry (basket IDs) and filled parents whose legs still
+        // work.
+        let listed = client.list_orders("open", None, true);
+        let (mut candidates, fallback) = match listed {
+            Ok(open) => (open, false),
+            Err(e) => {
+                errors.push(serde_json::json!({
+                    "error": format!("Open orders unavailable; using cached orders: {}", e)
+                }));
+                (Vec::new(), true)
+            }
+        };
+        for cached in self.orders.values() {
+            let legs_only = orders::is_working(cached) && !orders::parent_working(cached);
+            if (legs_only || (fallback && orders::is_working(cached)))
+                && !candidates.iter().any(|o| o.id == cached.id)
+            {
+                candidates.push(cached.clone());
+            }
+        }
+        // Algo children were handled with their parent
+        let algo_child = |o: &Order| {
+            orders::ext_str(o, "algo_parent_id").is_some()
+                || o.request
+                    .extensions
+                    .as_ref()
+                    .is_some_and(|ext| ext.contains_key("algo_parent_id"))
+        };
+        let mut order_ids: Vec<String> = Vec::new();
+        for order in &candidates {
+            let cached = self.orders.get(&order.id);
+            let matches = tags::order_matches(order, tag, basket_id)
+                || cached.is_some_and(|c| tags::order_matches(c, tag, basket_id));
+            if !matches || algo_child(order) || cached.is_some_and(algo_child) {
+                continue;
+            }
+            // Canceling a working parent cancels its legs; a filled one
+            // leaves only the legs to cancel
+            let ids = if orders::parent_working(order) {
+                vec![order.id.clone()]
+            } else {
+                orders::legs(order)
+                    .into_iter()
+                    .filter(orders::LegSummary::is_working)
+                    .map(|leg| leg.id)
+                    .collect()
+            };
+            for id in ids {
+                if !order_ids.contains(&id) {
+                    order_ids.push(id);
+                }
+            }
+        }
+        for id in order_ids {
+            match client.cancel_order(&id) {
+                Ok(()) => canceled.push(id),
+                Err(e) => errors.push(serde_json::json!({ "order_id": id, "error": e })),
+            }
+        }
+        (canceled, errors)
+    }
+
+    /// Evaluate pending conditional orders and submit the triggered ones
+    fn tick_conditionals(&mut self) -> Result<Vec<serde_json::Value>, String> {
+        if !self.conditionals.has_pending() {
+            return Ok(Vec::new());
+        }
+        let client = self.client.as_ref().ok_or("Plugin not initialized")?;
+        let snapshots = client.get_snapshots(&self.conditionals.pending_symbols())?;
+        let (mut events, triggered) = self.conditionals.evaluate(&snapshots, Utc::now());
+
+        for (id, request) in triggered {
+            logging::info("conditional", "Condition met; submitting order")
+                .field("conditional_id", id.as_str())
+                .field("symbol", request.symbol_id.as_str())
+                .emit();
+            let order = self.place_order(&request);
+            events.extend(self.conditionals.record_submission(&id, &order));
+        }
+        Ok(events
+            .into_iter()
+            .map(|e| {
+                let mut value = serde_json::to_value(e).unwrap_or_default();
+                value["type"] = "conditional".into();
+                value
+            })
+            .collect())
+    }
+
+    /// Check a basket's combined cost, then submit its orders one by one.
+    /// Each order still goes through every per-order check.
+    fn submit_basket(
+        &mut self,
+        requests: Vec<OrderRequest>,
+        mode: CheckMode,
+        stop_on_reject: bool,
+    ) -> Result<serde_json::Value, String> {
+        if requests.is_empty() {
+            return Err("orders must not be empty".to_string());
+        }
+        let client = self.client.as_ref().ok_or("Plugin not initialized")?;
+        let basket_id = format!("basket_{}", Utc::now().timestamp_millis());
+
+        let mut warnings = Vec::new();
+        if mode != CheckMode::Off {
+            let fetched_at = Utc::now();
+            let account = client.fetch_account()?;
+            let set = client.fetch_positions()?;
+            let positions: Vec<_> = set
+                .positions
+                .iter()
+                .map(|p| {
+                    let qty_available = set.extras.get(&p.symbol_id).and_then(|e| e.qty_available);
+                    pretrade::available(p, qty_available)
+                })
+                .collect();
+            match pretrade::check_basket_buying_power(
+                mode,
+                &requests,
+                &account,
+                &positions,
+                self.cost_buffer_pct,
+                &self.unreflected_funds(fetched_at),
+            ) {
+                pretrade::CheckOutcome::Pass => {}
+                pretrade::CheckOutcome::Warn(finding) => warnings.push(finding),
+                pretrade::CheckOutcome::Block(finding) => {
+                    logging::warn("basket", "Basket blocked by buying power check")
+                        .field("basket_id", basket_id.as_str())
+                        .field("orders", requests.len())
+                        .emit();
+                    return Ok(serde_json::json!({
+                        "success": false,
+                        "basket_id": basket_id,
+                        "error": finding.message,
+                        "rejection": finding
+                    }));
+                }
+            }
+        }
+
+        // One at a time when a rejection has to stop what follows it
+        let concurrency = match stop_on_reject {
+            true => 1,
+            false => self.basket_concurrency.max(1),
+        };
+        let mut results = Vec::new();
+        let mut rejected = 0;
+        let mut pending = requests.into_iter().enumerate().peekable();
+        'waves: while pending.peek().is_some() {
+            // A wave never holds two orders for the same symbol, so the
+            // per-symbol checks always see the earlier one as sent
+            let mut symbols = HashSet::new();
+            let mut wave = Vec::new();
+            while wave.len() < concurrency {
+                let Some((index, mut request)) =
+                    pending.next_if(|(_, r)| symbols.insert(r.symbol_id.to_ascii_uppercase()))
+                else {
+                    break;
+                };
+                request
+                    .extensions
+                    .get_or_insert_with(HashMap::new)
+                    .insert("basket_id".to_string(), basket_id.clone().into());
+                let routed = self.prepare_placement(&request);
+                if let Routed::Send(submission) = &routed {
+                    self.in_flight.push(submission.in_flight());
+                }
+                wave.push((index, routed));
+            }
+
+            let sending: Vec<&Submission> = wave
+                .iter()
+                .filter_map(|(_, routed)| match routed {
+                    Routed::Send(submission) => Some(&**submission),
+                    Routed::Done(_) => None,
+                })
+                .collect();
+            let mut sent = send_all(&sending).into_iter();
+            self.in_flight.clear();
+
+            for (index, routed) in wave {
+                let mut order = match routed {
+                    Routed::Done(order) => order,
+                    Routed::Send(submission) => match sent.next() {
+                        Some(sent) => self.complete_placement(submission, sent),
+                        None => create_error_order(&submission.requested, "Order was not sent"),
+                    },
+                };
+                self.rejections.record(&order);
+                orders::set_ext(&mut order, "basket_id", basket_id.as_str());
+                let failed = order.status == OrderStatus::Rejected;
+                results.push(serde_json::json!({
+                    "index": index,
+                    "order_id": order.id,
+                    "success": !failed,
+                    "error": orders::ext_str(&order, "error"),
+                    "order": order
+                }));
+                if failed {
+                    rejected += 1;
+                    if stop_on_reject {
+                        break 'waves;
+                    }
+                }
+            }
+        }
+
+        logging::info("basket", "Basket submitted")
+            .field("basket_id", basket_id.as_str())
+            .field("orders", results.len())
+            .field("rejected", rejected)
+            .emit();
+        Ok(serde_json::json!({
+            "success": true,
+            "basket_id": basket_id,
+            "results": results,
+            "rejected": rejected,
+            "warnings": warnings
+        }))
+    }
+
+    /// Close all or part of a position with a market order on the
+    /// opposite side
+    fn close_position(
+        &mut self,
+        symbol: &str,
+        amount: CloseAmount,
+        persona_id: &str,
+        dry_run: bool,
+    ) -> Result<serde_json::Value, String> {
+        let client = self.client.as_ref().ok_or("Plugin not initialized")?;
+        let position = client
+            .get_position(symbol)?
+            .ok_or_else(|| format!("No open position in {}", symbol))?;
+        let asset = client.get_asset(symbol)?;
+        let plan = ClosePlan::new(&position, amount, asset.as_ref())?;
+        let request = plan.to_request(persona_id, dry_run)?;
+
+        let mut order = self.place_order(&request);
+        orders::set_ext(&mut order, "closes_position", plan.link());
+        let failed = order.status == OrderStatus::Rejected;
+        if !failed && !dry_run {
+            logging::info("orders", "Position close submitted")
+                .field("order_id", order.id.as_str())
+                .field("symbol", symbol)
+                .field("qty", decimal::to_wire(plan.qty).as_str())
+                .emit();
+        }
+        Ok(serde_json::json!({
+            "success": !failed,
+            "order_id": order.id,
+            "error": orders::ext_str(&order, "error"),
+            "order": order
+        }))
+    }
+
+    /// Place a protective stop on the opposite side of a position
+    fn protect_position(
+        &mut self,
+        symbol: &str,
+        spec: protective::ProtectionSpec,
+        time_in_force: Option<&str>,
+        persona_id: &str,
+        dry_run: bool,
+    ) -> Result<serde_json::Value, String> {
+        let client = self.client.as_ref().ok_or("Plugin not initialized")?;
+        let position = client
+            .get_position(symbol)?
+            .ok_or_else(|| format!("No open position in {}", symbol))?;
+        let asset = client.get_asset(symbol)?;
+        // Without a quote the position's current price stands in
+        let quote = self.latest_quote(symbol).ok();
+        let plan =
+            protective::ProtectionPlan::new(&position, spec, quote.as_ref(), asset.as_ref())?;
+        let request = plan.to_request(persona_id, time_in_force, dry_run)?;
+
+        let mut order = self.place_order(&request);
+        orders::set_ext(&mut order, "protects_position", plan.link());
+        let failed = order.status == OrderStatus::Rejected;
+        // Held locally until the open; Alpaca has nothing to replace yet
+        let queued = orders::ext_str(&order, "alpaca_status") == Some("queued");
+        if !failed && !dry_run && !queued {
+            self.protections
+                .insert(protective::Protection::new(&plan, &order.id));
+        }
+        if !failed && !dry_run {
+            logging::info("orders", "Protective stop submitted")
+                .field("order_id", order.id.as_str())
+                .field("symbol", symbol)
+                .field("qty", decimal::to_wire(plan.qty).as_str())
+                .field_opt("stop_price", plan.stop_price.map(decimal::to_wire))
+                .emit();
+        }
+        Ok(serde_json::json!({
+            "success": !failed,
+            "order_id": order.id,
+            "error": orders::ext_str(&order, "error"),
+            "protection": plan.link(),
+            "order": order
+        }))
+    }
+
+    /// Plan a rebalance toward target weights and, unless `plan_only`,
+    /// submit its orders (sells first)
+    fn rebalance(&mut self, req: &RebalanceRequest) -> Result<serde_json::Value, String> {
+        req.validate()?;
+        let client = self.client.as_ref().ok_or("Plugin not initialized")?;
+
+        let mut prices: HashMap<String, Decimal> = HashMap::new();
+        let mut holdings: HashMap<String, Decimal> = HashMap::new();
+        let positions = client.get_positions()?;
+        for p in &positions {
+            if let Some(price) = decimal::from_f64(p.current_price) {
+                prices.insert(p.symbol_id.clone(), price);
+            }
+        }
+        match &req.persona_id {
+            Some(persona) => {
+                self.refresh_executions()?;
+                for lot in self.pnl_ledger().open_lots(None, Some(persona)) {
+                    *holdings.entry(lot.symbol).or_default() += lot.qty;
+                }
+            }
+            None => {
+                for p in &positions {
+                    holdings.insert(
+                        p.symbol_id.clone(),
+                        decimal::from_f64(p.quantity).unwrap_or_default(),
+                    );
+                }
+            }
+        }
+
+        let client = self.client.as_ref().ok_or("Plugin not initialized")?;
+        let symbols = req.symbols(&holdings);
+        for (symbol, snapshot) in client.get_snapshots(&symbols)? {
+            if let Some(price) = snapshot.price(market_data::PriceSource::Last) {
+                prices.insert(symbol, price);
+            }
+        }
+
+        let equity = match (req.equity, &req.persona_id) {
+            (Some(equity), _) => equity,
+            // A persona keeps no cash of its own; its holdings are its equity
+            (None, Some(_)) => rebalance::holdings_value(&holdings, &prices),
+            (None, None) => decimal::parse_or_zero(&client.fetch_account()?.equity),
+        };
+        if equity <= Decimal::ZERO {
+            return Err("Equity must be positive to rebalance".to_string());
+        }
+
+        let plan = rebalance::plan(req, equity, &holdings, &prices);
+        let rebalance_id = format!("rebal_{}", Utc::now().timestamp_millis());
+        logging::info("rebalance", "Rebalance planned")
+            .field("rebalance_id", rebalance_id.as_str())
+            .field("trades", plan.iter().filter(|l| l.is_trade()).count())
+            .emit();
+
+        let mut submitted = Vec::new();
+        let mut errors = Vec::new();
+        if !req.plan_only {
+            let persona = req.persona_id.clone().unwrap_or_default();
+            for line in plan.iter().filter(|l| l.is_trade()) {
+                match line.to_request(&persona, &rebalance_id) {
+                    Ok(request) => submitted.push(self.place_order(&request)),
+                    Err(e) => errors.push(serde_json::json!({ "symbol": line.symbol, "error": e })),
+                }
+            }
+        }
+
+        Ok(serde_json::json!({
+            "success": true,
+            "rebalance_id": rebalance_id,
+            "equity": equity,
+            "plan": plan,
+            "order_ids": submitted.iter().map(|o| o.id.clone()).collect::<Vec<_>>(),
+            "orders": submitted,
+            "errors": errors
+        }))
+    }
+
+    /// Replay every known fill through tax lots
+    fn pnl_ledger(&self) -> PnlLedger {
+        let executions = self.executions.in_range(None, None);
+        PnlLedger::build(&executions, self.lot_method, |order_id| {
+            self.order_or_parent(order_id)
+                .map(|o| OrderContext {
+                    persona_id: o.persona_id.clone(),
+                    lot_selection: LotSelection::from_extensions(o.request.extensions.as_ref())
+                        .ok()
+                        .flatten(),
+                })
+                .unwrap_or_default()
+        })
+    }
+}
+
+// Lock layout: only the client and the journal are split out. STATE
+// still guards everything else, the cached orders included, and is held
+// for the whole of submit, cancel, and tick, HTTP requests and all, so
+// those paths still run one at a time. Read-only exports that only need
+// the client (accounts, positions, metrics) go through CLIENT instead, so
+// a slow positions call never waits behind, or holds up, order
+// submission. The journal has its own lock so event reads do not take
+// STATE either, and the rate limiter keeps its window behind its own lock
+// inside the client's pipeline. Lock order when more than one is needed:
+// STATE, then CLIENT, then JOURNAL.
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<BrokerState> = Mutex::new(BrokerState::new());
+    /// Client published by `initialize`
+    static ref CLIENT: RwLock<Option<Arc<AlpacaClient>>> = RwLock::new(None);
+    /// Live client published next to the paper one by `live_credentials`
+    static ref LIVE_CLIENT: RwLock<Option<Arc<AlpacaClient>>> = RwLock::new(None);
+    /// Broker API client published by `initialize` with `broker_api`
+    static ref BROKER_API: RwLock<Option<Arc<BrokerApi>>> = RwLock::new(None);
+    /// Order events seen by polling and chasing
+    static ref JOURNAL: Mutex<EventJournal> = Mutex::new(EventJournal::default());
+}
+
+/// The current client, without touching the state lock
+fn current_client() -> Option<Arc<AlpacaClient>> {
+    CLIENT.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Install `client` in both the state and [`CLIENT`]; callers hold STATE
+fn publish_client(state: &mut BrokerState, client: Option<AlpacaClient>) {
+    let client = client.map(Arc::new);
+    *CLIENT.write().unwrap_or_else(|e| e.into_inner()) = client.clone();
+    state.client = client;
+}
+
+/// Install or clear the live client of a dual-environment setup
+fn publish_live_client(state: &mut BrokerState, client: Option<AlpacaClient>) {
+    let client = client.map(Arc::new);
+    *LIVE_CLIENT.write().unwrap_or_else(|e| e.into_inner()) = client.clone();
+    state.live_client = client;
+}
+
+/// The Broker API client, or why there is none
+fn broker_api() -> Result<Arc<BrokerApi>, String> {
+    BROKER_API
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .ok_or_else(|| "Broker API mode is not configured (broker_api)".to_string())
+}
+
+/// Pick the client for `env` out of the default and the live one; `None`
+/// is the default
+fn pick_client(
+    default: Option<&Arc<AlpacaClient>>,
+    live: Option<&Arc<AlpacaClient>>,
+    env: Option<Environment>,
+) -> Result<Arc<AlpacaClient>, String> {
+    let default = default.ok_or("Plugin not initialized")?;
+    match env {
+        Some(env) if env != Environment::of(default.is_paper()) => live
+            .filter(|c| Environment::of(c.is_paper()) == env)
+            .cloned()
+            .ok_or_else(|| format!("The {} environment is not configured", env.as_str())),
+        _ => Ok(default.clone()),
+    }
+}
+
+/// The client for `env`, without touching the state lock
+fn client_in(env: Option<Environment>) -> Result<Arc<AlpacaClient>, String> {
+    let live = LIVE_CLIENT
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    pick_client(current_client().as_ref(), live.as_ref(), env)
+}
+
+/// The client whose account is `account_id` (any account when empty),
+/// limited to `env` when one is given. `None` when no client owns it.
+fn client_for_account(
+    env: Option<Environment>,
+    account_id: &str,
+) -> Result<Option<Arc<AlpacaClient>>, String> {
+    let mut candidates = vec![client_in(env)?];
+    if env.is_none() && !account_id.is_empty() {
+        candidates.extend(
+            LIVE_CLIENT
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+        );
+    }
+    for client in candidates {
+        if client.is_own_account(account_id)? {
+            return Ok(Some(client));
+        }
+    }
+    Ok(None)
+}
+
+// --- WASM Exports ---
+//
+// Only exported (unmangled) on wasm32, so a native build of the library does
+// not put `alloc`, `initialize`, etc. into the linker's global namespace.
+
+/// Memory allocation for host communication
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn alloc(len: i32) -> i32 {
+    arena::alloc(len.max(0) as usize) as usize as i32
+}
+
+/// Return a buffer from `alloc` (e.g. a request the host has finished
+/// with). Responses need no freeing; see `arena`.
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn dealloc(ptr: i32, len: i32) {
+    // SAFETY: the host passes back a pointer and length from `alloc`
+    unsafe { arena::free(ptr as usize as *mut u8, len.max(0) as usize) }
+}
+
+/// Initialize plugin with configuration
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn initialize(ptr: i32, len: i32) -> u64 {
+    // initialize always speaks JSON; a successful one switches to the
+    // negotiated encoding once its response is written
+    wire::set(wire::Encoding::Json);
+    let config_json: serde_json::Value = parse_request(ptr, len);
+    let config = match config::Config::parse(&config_json) {
+        Ok(config) => config,
+        Err(problems) => {
+            let summary: Vec<String> = problems.iter().map(ToString::to_string).collect();
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": format!("Invalid configuration: {}", summary.join("; ")),
+                "problems": problems
+            }));
+        }
+    };
+    let encoding = wire::Encoding::negotiate(&config.encodings);
+
+    // Referenced credentials are looked up before anything is replaced, so
+    // a failed lookup leaves the previous client running
+    let secret_refs = config.api_key_ref.zip(config.api_secret_ref);
+    let (api_key, api_secret) = match &secret_refs {
+        Some((key_ref, secret_ref)) => match resolve_credentials(key_ref, secret_ref) {
+            Ok((key, secret)) => (Some(key), Some(secret)),
+            Err(e) => {
+                return serialize_response(&serde_json::json!({
+                    "success": false,
+                    "error": e,
+                    "requires_auth": true
+                }));
+            }
+        },
+        None => (config.api_key, config.api_secret),
+    };
+    let live_secret_refs = config
+        .live_credentials
+        .as_ref()
+        .and_then(|live| live.api_key_ref.clone().zip(live.api_secret_ref.clone()));
+    let live_keys = match (config.live_credentials, &live_secret_refs) {
+        (None, _) => None,
+        (Some(_), Some((key_ref, secret_ref))) => match resolve_credentials(key_ref, secret_ref) {
+            Ok(keys) => Some(keys),
+            Err(e) => {
+                return serialize_response(&serde_json::json!({
+                    "success": false,
+                    "error": format!("live_credentials: {}", e),
+                    "requires_auth": true
+                }));
+            }
+        },
+        (Some(live), None) => live.api_key.zip(live.api_secret),
+    };
+    let broker_secret_refs = config.broker_api.as_ref().and_then(|broker| {
+        let credentials = &broker.credentials;
+        credentials
+            .api_key_ref
+            .clone()
+            .zip(credentials.api_secret_ref.clone())
+    });
+    let broker_keys = match (&config.broker_api, &broker_secret_refs) {
+        (None, _) => None,
+        (Some(_), Some((key_ref, secret_ref))) => match resolve_credentials(key_ref, secret_ref) {
+            Ok(keys) => Some(keys),
+            Err(e) => {
+                return serialize_response(&serde_json::json!({
+                    "success": false,
+                    "error": format!("broker_api: {}", e),
+                    "requires_auth": true
+                }));
+            }
+        },
+        (Some(broker), None) => broker
+            .credentials
+            .api_key
+            .clone()
+            .zip(broker.credentials.api_secret.clone()),
+    };
+    let mut warnings: Vec<String> = config
+        .unknown_fields
+        .iter()
+        .map(|field| format!("Unknown configuration field: {}", field))
+        .collect();
+    let live_configured = !config.is_paper || live_keys.is_some();
+    if live_configured && config.simulation.is_none() && !config.live_trading_ack {
+        warnings.push(format!(
+            "Live orders are rejected until live_trading_ack is \"{}\"",
+            interlock::ACK
+        ));
+    }
+    // The WASM host has no threads, so every basket order goes out alone
+    if cfg!(target_arch = "wasm32") && config.basket_concurrency > 1 {
+        warnings.push(format!(
+            "basket_concurrency {} is ignored inside the WASM host; basket orders are sent one at a time",
+            config.basket_concurrency
+        ));
+    }
+
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+
+    state.pdt_mode = config.pdt_mode;
+    state.buying_power_mode = config.buying_power_mode;
+    state.asset_mode = config.asset_mode;
+    state.short_mode = config.short_mode;
+    state.wash_sale_mode = config.wash_sale_mode;
+    state.gfv_mode = config.gfv_mode;
+    state.fees = config.fees;
+    state.raw_policy = config.raw_policy;
+    state.shadow_mode = config.shadow_mode;
+    state.cost_buffer_pct = config.cost_buffer_pct;
+    state.price_band_pct = config.price_band_pct;
+    state.risk_limits = config.risk_limits;
+    state.order_size_limits = config.order_size_limits;
+    state.protective_adjust = config.protective_adjust;
+    state.symbol_changes = config.symbol_changes;
+    state.reference_prices = config.reference_prices;
+    state.basket_concurrency = config.basket_concurrency;
+    state.throttle.limits = config.order_throttle;
+    // A manual or automatic halt survives re-initialization; only the
+    // thresholds are replaced
+    state.kill_switch.auto = config.kill_switch;
+    state.stale_rules = config.stale_orders;
+    state.live_interlock.acknowledged = config.live_trading_ack;
+    state.live_interlock.max_notional_per_day = config.live_max_notional_per_day;
+    alerts::monitor().configure(config.alerts);
+    state.dedupe.window_secs = config.dedupe_window_secs;
+    state.closed_market_policy = config.closed_market_policy;
+    state.lot_method = config.lot_method;
+    state.benchmark_symbol = config.benchmark_symbol;
+    state.position_history = match config.position_history.map(PositionHistory::load) {
+        Some(Ok(history)) => Some(history),
+        Some(Err(e)) => {
+            warnings.push(format!("Position history disabled: {}", e));
+            None
+        }
+        None => None,
+    };
+
+    if let Some(level) = config.log_level {
+        logging::set_level(level);
+    }
+
+    state.secret_refs = secret_refs;
+    state.live_secret_refs = live_secret_refs;
+    state.broker_secret_refs = broker_secret_refs;
+    let is_paper = config.is_paper;
+    let options = config.client;
+
+    // Simulation needs no credentials; with them, market data is live.
+    // Mock mode never uses them.
+    if let Some(simulation) = config.simulation {
+        let mock = simulation.mock;
+        let (key, secret) = match (api_key, api_secret) {
+            (Some(key), Some(secret)) if !mock && !key.is_empty() && !secret.is_empty() => {
+                (key, secret)
+            }
+            _ => (String::new(), String::new()),
+        };
+        let mut simulator = Simulator::new(simulation, !key.is_empty());
+        if let Some(url) = &options.data_base_url {
+            simulator = simulator.with_data_url(url);
+        }
+        let simulator = Arc::new(simulator);
+        redact::clear_secrets();
+        if !key.is_empty() {
+            redact::register_secret(&key);
+            redact::register_secret(&secret);
+        }
+        let options = ClientOptions {
+            simulator: Some(simulator.clone()),
+            positions_cache: std::time::Duration::ZERO,
+            ..options
+        };
+        publish_client(
+            &mut state,
+            Some(AlpacaClient::with_options(key, secret, true, options)),
+        );
+        publish_live_client(&mut state, None);
+        *BROKER_API.write().unwrap_or_else(|e| e.into_inner()) = None;
+        state.simulator = Some(simulator);
+        state.recorder = None;
+        let _switch = wire::SwitchOnReturn(encoding);
+        return serialize_response(&serde_json::json!({
+            "success": true,
+            "message": if mock {
+                "Alpaca plugin initialized (mock)"
+            } else {
+                "Alpaca plugin initialized (simulation)"
+            },
+            "encoding": encoding.name(),
+            "warnings": warnings
+        }));
+    }
+    state.simulator = None;
+
+    // The trading client alone is recorded; replay needs no credentials
+    let recorder = match config.recording.map(Recorder::new).transpose() {
+        Ok(recorder) => recorder.map(Arc::new),
+        Err(e) => {
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": format!("recording: {}", e)
+            }));
+        }
+    };
+    let replaying = recorder
+        .as_ref()
+        .is_some_and(|r| r.mode() == RecordingMode::Replay);
+    if replaying && recorder.as_ref().is_some_and(|r| r.entries() == 0) {
+        warnings.push("The replay log is empty; every request will fail".to_string());
+    }
+    let (api_key, api_secret) = match (api_key, api_secret) {
+        (None, None) if replaying => (Some("replay".to_string()), Some("replay".to_string())),
+        keys => keys,
+    };
+    state.recorder = recorder.clone();
+
+    // Validate configuration
+    match (api_key, api_secret) {
+        (Some(key), Some(secret)) if !key.is_empty() && !secret.is_empty() => {
+            redact::clear_secrets();
+            redact::register_secret(&key);
+            redact::register_secret(&secret);
+            let live = live_keys.map(|(live_key, live_secret)| {
+                redact::register_secret(&live_key);
+                redact::register_secret(&live_secret);
+                AlpacaClient::with_options(live_key, live_secret, false, options.clone())
+            });
+            let broker =
+                config
+                    .broker_api
+                    .zip(broker_keys)
+                    .map(|(broker, (broker_key, broker_secret))| {
+                        redact::register_secret(&broker_key);
+                        redact::register_secret(&broker_secret);
+                        BrokerApi::new(
+                            broker_key,
+                            broker_secret,
+                            is_paper,
+                            broker.account_id,
+                            broker.base_url,
+                            options.clone(),
+                        )
+                    });
+            let environments = match (is_paper, live.is_some()) {
+                (true, true) => "paper + live",
+                (true, false) => "paper",
+                (false, _) => "live",
+            };
+            let environments = match recorder.as_ref().map(|r| r.mode()) {
+                Some(RecordingMode::Record) => format!("{}, recording", environments),
+                Some(RecordingMode::Replay) => format!("{}, replay", environments),
+                None => environments.to_string(),
+            };
+            let options = ClientOptions {
+                recorder,
+                ..options
+            };
+            let client = AlpacaClient::with_options(key, secret, is_paper, options);
+            publish_client(&mut state, Some(client));
+            publish_live_client(&mut state, live);
+            *BROKER_API.write().unwrap_or_else(|e| e.into_inner()) = broker.map(Arc::new);
+
+            let _switch = wire::SwitchOnReturn(encoding);
+            serialize_response(&serde_json::json!({
+                "success": true,
+                "message": format!("Alpaca plugin initialized ({})", environments),
+                "encoding": encoding.name(),
+                "warnings": warnings
+            }))
+        }
+        _ => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "Missing required configuration: api_key and api_secret (or api_key_ref and api_secret_ref)",
+            "requires_auth": true
+        })),
+    }
+}
+
+/// Look up both credentials in the host secret store
+fn resolve_credentials(key_ref: &str, secret_ref: &str) -> Result<(String, String), String> {
+    Ok((secrets::resolve(key_ref)?, secrets::resolve(secret_ref)?))
+}
+
+/// Re-resolve `api_key_ref` and `api_secret_ref` (and those in
+/// `live_credentials` and `broker_api`) after the host rotated them, and switch the clients
+/// over without re-initializing
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn rotate_credentials(ptr: i32, len: i32) -> u64 {
+    if len > 0 {
+        let _: serde_json::Value = parse_request(ptr, len);
+    } else {
+        trace::set(None);
+    }
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let broker = BROKER_API.read().unwrap_or_else(|e| e.into_inner()).clone();
+    let by_reference: Vec<_> = [
+        (&state.secret_refs, state.client.as_ref()),
+        (&state.live_secret_refs, state.live_client.as_ref()),
+        (
+            &state.broker_secret_refs,
+            broker.as_ref().map(|b| &b.client),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(refs, client)| Some((refs.as_ref()?, client?)))
+    .collect();
+    if by_reference.is_empty() {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "Credentials were not configured by reference (api_key_ref, api_secret_ref)"
+        }));
+    }
+
+    // Every lookup must succeed before any client switches
+    let mut resolved = Vec::with_capacity(by_reference.len());
+    for ((key_ref, secret_ref), client) in by_reference {
+        match resolve_credentials(key_ref, secret_ref) {
+            Ok(keys) => resolved.push((client, keys)),
+            Err(e) => {
+                return serialize_response(&serde_json::json!({
+                    "success": false,
+                    "error": e,
+                    "requires_auth": true
+                }));
+            }
+        }
+    }
+    for (client, (key, secret)) in resolved {
+        // The old values stay registered so earlier output cannot leak
+        // them either
+        redact::register_secret(&key);
+        redact::register_secret(&secret);
+        client.rotate_credentials(key, secret);
+    }
+    logging::info("auth", "Credentials rotated from the secret store").emit();
+    serialize_response(&serde_json::json!({ "success": true }))
+}
+
+/// Set the quote the simulator fills against for a symbol
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn set_simulated_quote(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct SetSimulatedQuoteRequest {
+        symbol: String,
+        #[serde(default)]
+        bid: Option<Decimal>,
+        #[serde(default)]
+        ask: Option<Decimal>,
+        #[serde(default)]
+        last: Option<Decimal>,
+    }
+
+    let req: SetSimulatedQuoteRequest = parse_request(ptr, len);
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(simulator) = state.simulator.as_ref() else {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "Not in simulation mode"
+        }));
+    };
+    if req.bid.is_none() && req.ask.is_none() && req.last.is_none() {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "At least one of bid, ask, or last is required"
+        }));
+    }
+    simulator.set_quote(
+        &req.symbol,
+        SimQuote {
+            bid: req.bid.unwrap_or_default(),
+            ask: req.ask.unwrap_or_default(),
+            last: req.last.unwrap_or_default(),
+        },
+    );
+    serialize_response(&serde_json::json!({ "success": true }))
+}
+
+/// Simulated cash, equity, and positions
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_simulation_state(ptr: i32, len: i32) -> u64 {
+    if len > 0 {
+        let _: serde_json::Value = parse_request(ptr, len);
+    } else {
+        trace::set(None);
+    }
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    match state.simulator.as_ref() {
+        Some(simulator) => serialize_response(&serde_json::json!({
+            "success": true,
+            "simulation": simulator.summary()
+        })),
+        None => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "Not in simulation mode"
+        })),
+    }
+}
+
+/// Recording or replay status, with the log itself unless `include_log`
+/// is false
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_recording(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct GetRecordingRequest {
+        #[serde(default = "default_true")]
+        include_log: bool,
+    }
+
+    fn default_true() -> bool {
+        true
+    }
+
+    let req: GetRecordingRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetRecordingRequest { include_log: true }
+    };
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    match state.recorder.as_ref() {
+        Some(recorder) => serialize_response(&serde_json::json!({
+            "success": true,
+            "recording": recorder.summary(req.include_log)
+        })),
+        None => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "Recording is not configured"
+        })),
+    }
+}
+
+/// Get available accounts.
+///
+/// `balances_only` skips the positions request; otherwise positions are
+/// reused from the last few seconds unless `refresh` is set.
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_accounts(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetAccountsRequest {
+        /// Only this account; empty for every account the keys can see
+        #[serde(default)]
+        account_id: String,
+        /// `paper` or `live` with `live_credentials`; default paper
+        #[serde(default)]
+        environment: Option<String>,
+        #[serde(default)]
+        balances_only: bool,
+        #[serde(default)]
+        refresh: bool,
+    }
+
+    let req: GetAccountsRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetAccountsRequest::default()
+    };
+    let mode = if req.balances_only {
+        AccountPositions::Omit
+    } else if req.refresh {
+        AccountPositions::Fresh
+    } else {
+        AccountPositions::Cached
+    };
+
+    if current_client().is_none() {
+        return serialize_response(&GetAccountsResponse {
+            accounts: vec![create_error_account(
+                "Plugin not initialized. Provide api_key and api_secret.",
+            )],
+        });
+    }
+
+    let client = match Environment::from_field(req.environment.as_deref())
+        .and_then(|env| client_for_account(env, &req.account_id))
+    {
+        Ok(Some(client)) => client,
+        Ok(None) => {
+            let finding = unknown_account(&req.account_id);
+            let mut account = create_error_account(&finding.message);
+            account.extensions = Some(HashMap::from([(
+                "error".to_string(),
+                serde_json::to_value(&finding).unwrap_or_default(),
+            )]));
+            return serialize_response(&GetAccountsResponse {
+                accounts: vec![account],
+            });
+        }
+        Err(e) => {
+            return serialize_response(&GetAccountsResponse {
+                accounts: vec![create_error_account(&e)],
+            });
+        }
+    };
+    let result = client.list_accounts(mode);
+    // Push any alerts this refresh raised
+    events::flush(&JOURNAL);
+    match result {
+        Ok(accounts) => serialize_response(&GetAccountsResponse { accounts }),
+        Err(e) => {
+            logging::error("accounts", "Failed to fetch accounts")
+                .field("error", e.as_str())
+                .emit();
+            serialize_response(&GetAccountsResponse {
+                accounts: vec![create_error_account(&e)],
+            })
+        }
+    }
+}
+
+/// Get positions for an account
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_positions(ptr: i32, len: i32) -> u64 {
+    let req: GetPositionsRequest = parse_request(ptr, len);
+
+    if current_client().is_none() {
+        return serialize_response(&GetPositionsResponse { positions: vec![] });
+    }
+
+    // The live account's ID selects the live environment
+    let positions = client_for_account(None, &req.account_id).and_then(|client| match client {
+        Some(client) => client.fetch_positions().map(Some),
+        None => Ok(None),
+    });
+    match positions {
+        // `Position` has no extensions, so the extra fields ride alongside
+        Ok(Some(set)) => {
+            let mut response = serde_json::Map::new();
+            response.insert(
+                "positions".to_string(),
+                serde_json::to_value(&set.positions).unwrap_or_default(),
+            );
+            for (key, sidecar) in set.sidecars() {
+                response.insert(key.to_string(), sidecar);
+            }
+            serialize_response(&response)
+        }
+        Ok(None) => serialize_response(&serde_json::json!({
+            "positions": [],
+            "success": false,
+            "error": unknown_account(&req.account_id),
+        })),
+        Err(e) => {
+            logging::error("positions", "Failed to fetch positions")
+                .field("error", e.as_str())
+                .emit();
+            serialize_response(&GetPositionsResponse { positions: vec![] })
+        }
+    }
+}
+
+/// Positions that changed since the previous call, including closed-out
+/// symbols with quantity 0. The first call (or one with `reset`) returns
+/// every position with `full: true`.
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_position_changes(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetPositionChangesRequest {
+        /// Ignore price and unrealized PnL moves
+        #[serde(default)]
+        holdings_only: bool,
+        #[serde(default)]
+        reset: bool,
+    }
+
+    let req: GetPositionChangesRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetPositionChangesRequest::default()
+    };
+    let Some(client) = current_client() else {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "Plugin not initialized"
+        }));
+    };
+
+    // Fetched outside the state lock; only the diff needs it
+    let positions = match client.get_positions() {
+        Ok(positions) => positions,
+        Err(e) => {
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": e
+            }));
+        }
+    };
+    let scope = if req.holdings_only {
+        ChangeScope::Holdings
+    } else {
+        ChangeScope::All
+    };
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if req.reset {
+        state.position_changes.reset();
+    }
+    let changes = state.position_changes.diff(positions, scope);
+    drop(state);
+
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "full": changes.full,
+        "positions": changes.changed,
+        "unchanged": changes.unchanged,
+    }))
+}
+
+/// Submit an order
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn submit_order(ptr: i32, len: i32) -> u64 {
+    let req: SubmitOrderRequest = parse_request(ptr, len);
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    // The live account's ID picks the live environment
+    let live_account = !req.account_id.is_empty()
+        && matches!(Environment::requested(&req.order), Ok(None))
+        && state
+            .live_client
+            .as_ref()
+            .is_some_and(|live| live.is_own_account(&req.account_id) == Ok(true));
+    let order = if live_account {
+        let mut request = req.order.clone();
+        request
+            .extensions
+            .get_or_insert_with(HashMap::new)
+            .insert("environment".to_string(), Environment::Live.as_str().into());
+        state.place_order(&request)
+    } else {
+        state.place_order(&req.order)
+    };
+    serialize_response(&SubmitOrderResponse { order })
+}
+
+/// Cancel an order
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn cancel_order(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct CancelOrderRequest<'a> {
+        #[serde(borrow)]
+        order_id: Cow<'a, str>,
+        /// For orders the plugin is not tracking; tracked ones use the
+        /// environment they were placed in
+        #[serde(default)]
+        environment: Option<String>,
+    }
+
+    let req: CancelOrderRequest = parse_request(ptr, len);
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+
+    if state.algos.get(&req.order_id).is_some() {
+        return match state.cancel_algo(&req.order_id) {
+            Ok(()) => serialize_response(&serde_json::json!({
+                "success": true,
+                "order_id": req.order_id
+            })),
+            Err(e) => serialize_response(&serde_json::json!({
+                "success": false,
+                "error": e
+            })),
+        };
+    }
+
+    if state.order_queue.remove(&req.order_id).is_some()
+        || state.scheduled.remove(&req.order_id).is_some()
+    {
+        return serialize_response(&serde_json::json!({
+            "success": true,
+            "order_id": req.order_id
+        }));
+    }
+
+    let client = match order_client(&state, &req.order_id, req.environment.as_deref()) {
+        Ok(c) => c,
+        Err(e) => {
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": e
+            }));
+        }
+    };
+
+    let started = Instant::now();
+    let result = client.cancel_order(&req.order_id);
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    match result {
+        Ok(()) => {
+            client.metrics().record_order("canceled", latency_ms);
+            serialize_response(&serde_json::json!({
+                "success": true,
+                "order_id": req.order_id
+            }))
+        }
+        Err(e) => {
+            client.metrics().record_order("cancel_failed", latency_ms);
+            serialize_response(&serde_json::json!({
+                "success": false,
+                "error": e
+            }))
+        }
+    }
+}
+
+/// Halt or resume order submission (the kill switch)
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn set_trading_enabled(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct SetTradingEnabledRequest {
+        enabled: bool,
+        #[serde(default)]
+        reason: Option<String>,
+        /// Also cancel all open orders when halting
+        #[serde(default)]
+        cancel_open_orders: bool,
+    }
+
+    let req: SetTradingEnabledRequest = parse_request(ptr, len);
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut canceled = Vec::new();
+    let mut cancel_error = None;
+    if req.enabled {
+        state.kill_switch.reset();
+        logging::warn("kill_switch", "Trading resumed")
+            .field_opt("reason", req.reason.clone())
+            .emit();
+    } else {
+        let reason = req
+            .reason
+            .clone()
+            .unwrap_or_else(|| "halted by host".to_string());
+        state.kill_switch.trip(reason.as_str(), "manual");
+        logging::error("kill_switch", "Trading halted")
+            .field("reason", reason)
+            .emit();
+        if req.cancel_open_orders {
+            match state.cancel_all_open_orders() {
+                Ok(results) => canceled = results,
+                Err(e) => cancel_error = Some(e),
+            }
+        }
+    }
+
+    serialize_response(&serde_json::json!({
+        "success": cancel_error.is_none(),
+        "trading_enabled": state.kill_switch.trading_enabled(),
+        "halt": state.kill_switch.halt_info(),
+        "canceled": canceled,
+        "error": cancel_error
+    }))
+}
+
+/// Clear a tripped daily loss limit so risk-increasing orders resume
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn reset_daily_loss_limit(ptr: i32, len: i32) -> u64 {
+    if len > 0 {
+        let _: serde_json::Value = parse_request(ptr, len);
+    } else {
+        trace::set(None);
+    }
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let cleared = state.loss_limit.reset();
+    if let Some(breach) = &cleared {
+        logging::warn("risk", "Daily loss limit reset")
+            .field("pnl", breach.pnl.as_str())
+            .emit();
+    }
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "was_breached": cleared.is_some(),
+        "breach": cleared
+    }))
+}
+
+/// Register an order to submit when a price condition is met
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn submit_conditional_order(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct SubmitConditionalOrderRequest {
+        condition: Condition,
+        order: OrderRequest,
+        #[serde(default)]
+        expire_at: Option<DateTime<Utc>>,
+    }
+
+    let req: SubmitConditionalOrderRequest = parse_request(ptr, len);
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+
+    if state.client.is_none() {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "Plugin not initialized"
+        }));
+    }
+
+    match state
+        .conditionals
+        .add(req.condition, req.order, req.expire_at)
+    {
+        Ok(entry) => serialize_response(&serde_json::json!({
+            "success": true,
+            "conditional_order": entry
+        })),
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
+    }
+}
+
+/// Cancel a pending conditional order
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn cancel_conditional_order(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct CancelConditionalOrderRequest {
+        id: String,
+    }
+
+    let req: CancelConditionalOrderRequest = parse_request(ptr, len);
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+
+    match state.conditionals.cancel(&req.id) {
+        Ok(entry) => serialize_response(&serde_json::json!({
+            "success": true,
+            "conditional_order": entry
+        })),
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
+    }
+}
+
+/// List conditional orders, pending and completed
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_conditional_orders(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetConditionalOrdersRequest {
+        #[serde(default)]
+        pending_only: bool,
+    }
+
+    let req: GetConditionalOrdersRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetConditionalOrdersRequest::default()
+    };
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let entries: Vec<_> = state
+        .conditionals
+        .list()
+        .iter()
+        .filter(|c| !req.pending_only || c.status == conditional::ConditionalStatus::Pending)
+        .collect();
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "conditional_orders": entries
+    }))
+}
+
+/// Periodic maintenance driven by the host, meant to be called every few
+/// seconds. One call runs every time-based subsystem in turn: queued and
+/// scheduled order release, GTD expiry, order polling, algo slices,
+/// chases, conditional orders, circuit-breaker probes, and cache and
+/// rate-limit housekeeping. `summary` counts what each step did.
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn tick(ptr: i32, len: i32) -> u64 {
+    #[derive(Default, serde::Deserialize)]
+    struct TickRequest {
+        /// Also refresh working orders, as `poll_order_updates` does. Off
+        /// by default so a host that already polls does not do it twice.
+        #[serde(default)]
+        poll_orders: bool,
+    }
+
+    let req: TickRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        TickRequest::default()
+    };
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+
+    if state.client.is_none() {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "Plugin not initialized"
+        }));
+    }
+
+    // First, so the steps below can reach Alpaca again if it recovered
+    let trading_clients: Vec<Arc<AlpacaClient>> = state
+        .client
+        .iter()
+        .chain(state.live_client.iter())
+        .cloned()
+        .collect();
+    let breaker_probes = trading_clients
+        .iter()
+        .filter_map(|c| c.probe_breaker())
+        .count();
+    let (released, release_errors) = state.release_queued_orders();
+    let expired = state.expire_gtd_orders();
+    let (order_events, updated, mut errors) = if req.poll_orders {
+        state.refresh_orders(None)
+    } else {
+        Default::default()
+    };
+    let mut events: Vec<serde_json::Value> = order_events
+        .iter()
+        .filter_map(|e| serde_json::to_value(e).ok())
+        .collect();
+    let scheduled = state.release_scheduled_orders();
+    let algo_events = state.tick_algos();
+    let (chase_events, chase_errors) = state.tick_chases();
+    let (protective_events, protective_errors) = state.tick_protections();
+    let symbol_changes = match state.apply_symbol_changes(Utc::now()) {
+        Ok(changes) => changes,
+        Err(e) => {
+            logging::warn("symbols", "Corporate actions unavailable")
+                .field("error", e.as_str())
+                .emit();
+            errors.push(serde_json::json!({ "type": "symbol_change", "error": e }));
+            Vec::new()
+        }
+    };
+    let symbol_events: Vec<serde_json::Value> = symbol_changes
+        .iter()
+        .filter_map(|change| serde_json::to_value(change).ok())
+        .collect();
+    for event in &symbol_events {
+        events::push("symbol_changed", event.clone());
+    }
+    let conditional_events = match state.tick_conditionals() {
+        Ok(e) => e,
+        Err(e) => {
+            logging::warn("tick", "Conditional order evaluation failed")
+                .field("error", e.as_str())
+                .emit();
+            errors.push(serde_json::json!({ "type": "conditional", "error": e }));
+            Vec::new()
+        }
+    };
+    let (stale_events, stale_errors) = state.sweep_stale_orders();
+    let position_snapshot = match state.snapshot_positions(Utc::now()) {
+        Ok(taken) => taken,
+        Err(e) => {
+            errors.push(serde_json::json!({ "type": "position_snapshot", "error": e }));
+            false
+        }
+    };
+    errors.extend(chase_errors);
+    errors.extend(protective_errors);
+    errors.extend(stale_errors);
+    errors.extend(release_errors.iter().cloned());
+
+    let housekeeping = state
+        .client
+        .as_ref()
+        .map(|c| c.housekeeping())
+        .unwrap_or_default();
+    let cursors_evicted = chunked::evict_idle(Instant::now());
+
+    let summary = serde_json::json!({
+        "queued_released": released.len(),
+        "gtd_expired": expired.len(),
+        "orders_polled": req.poll_orders,
+        "orders_changed": updated.len(),
+        "scheduled_events": scheduled.len(),
+        "algo_events": algo_events.len(),
+        "chase_events": chase_events.len(),
+        "protective_events": protective_events.len(),
+        "symbol_changes": symbol_events.len(),
+        "conditional_events": conditional_events.len(),
+        "stale_orders": stale_events.len(),
+        "position_snapshot": position_snapshot,
+        "cache_evicted": housekeeping.cache_evicted,
+        "positions_cache_cleared": housekeeping.positions_cache_cleared,
+        "rate_limit_in_window": housekeeping.rate_limit_in_window,
+        "chunk_cursors_evicted": cursors_evicted,
+        "breaker_probes": breaker_probes,
+        "breaker_open": trading_clients.iter().any(|c| c.breaker_open()),
+        "errors": errors.len(),
+    });
+    events.extend(scheduled);
+    events.extend(algo_events);
+    events.extend(chase_events);
+    events.extend(protective_events);
+    events.extend(symbol_events.into_iter().map(|mut e| {
+        e["type"] = "symbol_changed".into();
+        e
+    }));
+    events.extend(conditional_events);
+    events.extend(stale_events);
+    drop(state);
+    let pushed = events::flush(&JOURNAL);
+
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "events": events,
+        "errors": errors,
+        "orders": updated,
+        "released": released,
+        "expired": expired,
+        "summary": summary,
+        "pushed": pushed
+    }))
+}
+
+/// Holdings at a past `timestamp`, rebuilt from the stored position
+/// snapshots and the fills around them
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_positions_at(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct GetPositionsAtRequest {
+        timestamp: DateTime<Utc>,
+    }
+
+    let req: GetPositionsAtRequest = parse_request(ptr, len);
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(client) = state.client.clone() else {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "Plugin not initialized"
+        }));
+    };
+    let now = Utc::now();
+    if req.timestamp > now {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "timestamp is in the future"
+        }));
+    }
+
+    // The current positions are the newest snapshot
+    let mut snapshots: Vec<PositionSnapshot> = state
+        .position_history
+        .as_ref()
+        .map(|h| h.snapshots().to_vec())
+        .unwrap_or_default();
+    match client.get_positions() {
+        Ok(positions) => snapshots.push(PositionSnapshot::of(&positions, now)),
+        Err(e) if snapshots.is_empty() => {
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": e
+            }));
+        }
+        Err(_) => {}
+    }
+    if let Err(e) = state.refresh_executions() {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": format!("Fills unavailable: {}", e)
+        }));
+    }
+    let fills = state.executions.in_range(None, None);
+    match history::positions_at(&snapshots, &fills, req.timestamp) {
+        Some(positions) => serialize_response(&serde_json::json!({
+            "success": true,
+            "positions": positions
+        })),
+        None => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "No position snapshot to start from"
+        })),
+    }
+}
+
+/// Working orders that are stale by the `stale_orders` rules right now,
+/// whether or not `tick` has reported them yet
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_stale_orders(ptr: i32, len: i32) -> u64 {
+    if len > 0 {
+        let _: serde_json::Value = parse_request(ptr, len);
+    } else {
+        trace::set(None);
+    }
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if state.client.is_none() {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "Plugin not initialized"
+        }));
+    }
+    if !state.stale_rules.is_enabled() {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "stale_orders is not configured"
+        }));
+    }
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "stale_orders": state.stale_orders()
+    }))
+}
+
+/// Refresh working orders and report what changed since the last poll
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn poll_order_updates(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct PollOrderUpdatesRequest {
+        /// Restrict the refresh to these orders; defaults to every working order
+        #[serde(default)]
+        order_ids: Option<Vec<String>>,
+    }
+
+    let req: PollOrderUpdatesRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        PollOrderUpdatesRequest::default()
+    };
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+
+    if state.client.is_none() {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "Plugin not initialized"
+        }));
+    }
+
+    let (released, release_errors) = state.release_queued_orders();
+    let expired = state.expire_gtd_orders();
+
+    let (events, updated, errors) = state.refresh_orders(req.order_ids.as_deref());
+    drop(state);
+    let pushed = events::flush(&JOURNAL);
+
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "events": events,
+        "orders": updated,
+        "errors": errors,
+        "released": released,
+        "release_errors": release_errors,
+        "expired": expired,
+        "pushed": pushed
+    }))
+}
+
+/// Account alerts raised since `since_id`, oldest first, and the threshold
+/// rules breached as of the last account fetch
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_alerts(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetAlertsRequest {
+        #[serde(default)]
+        since_id: u64,
+    }
+
+    let req: GetAlertsRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetAlertsRequest::default()
+    };
+    let monitor = alerts::monitor();
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "alerts": monitor.history(req.since_id),
+        "breached": monitor.breached()
+    }))
+}
+
+/// Journaled events for one or more orders, oldest first. Includes chase
+/// reprices, so following `replaces` back gives an order's full history.
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_order_events(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetOrderEventsRequest {
+        #[serde(default)]
+        order_ids: Vec<String>,
+    }
+
+    let req: GetOrderEventsRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetOrderEventsRequest::default()
+    };
+    let journal = JOURNAL.lock().unwrap_or_else(|e| e.into_inner());
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "events": journal.for_orders(&req.order_ids)
+    }))
+}
+
+/// Cancel every working order carrying a tag or basket ID
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn cancel_orders_by_tag(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct CancelOrdersByTagRequest {
+        #[serde(default)]
+        tag: Option<String>,
+        #[serde(default)]
+        basket_id: Option<String>,
+    }
+
+    let req: CancelOrdersByTagRequest = parse_request(ptr, len);
+    if req.tag.is_none() && req.basket_id.is_none() {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "tag or basket_id is required"
+        }));
+    }
+
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if state.client.is_none() {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "Plugin not initialized"
+        }));
+    }
+    let (canceled, errors) = state.cancel_by_tag(req.tag.as_deref(), req.basket_id.as_deref());
+    logging::info("orders", "Group cancel")
+        .field_opt("tag", req.tag.clone())
+        .field_opt("basket_id", req.basket_id.clone())
+        .field("canceled", canceled.len())
+        .field("errors", errors.len())
+        .emit();
+    serialize_response(&serde_json::json!({
+        "success": errors.is_empty(),
+        "canceled": canceled,
+        "errors": errors
+    }))
+}
+
+/// Submit a group of orders under one basket ID
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn submit_basket(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct SubmitBasketRequest {
+        orders: Vec<OrderRequest>,
+        /// Aggregate buying power check: `enforce` (default), `warn`, or `off`
+        #[serde(default)]
+        buying_power_check: Option<String>,
+        /// Stop at the first rejected order
+        #[serde(default)]
+        stop_on_reject: bool,
+    }
+
+    let req: SubmitBasketRequest = parse_request(ptr, len);
+    let mode = match req.buying_power_check.as_deref() {
+        None => CheckMode::Enforce,
+        Some(raw) => match CheckMode::parse(raw) {
+            Some(mode) => mode,
+            None => {
+                return serialize_response(&serde_json::json!({
+                    "success": false,
+                    "error": format!("Unsupported buying_power_check: {} (expected enforce, warn, or off)", raw)
+                }))
+            }
+        },
+    };
+
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    match state.submit_basket(req.orders, mode, req.stop_on_reject) {
+        Ok(response) => serialize_response(&response),
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
+    }
+}
+
+/// Rebalance holdings toward target weights
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn rebalance(ptr: i32, len: i32) -> u64 {
+    let req: RebalanceRequest = parse_request(ptr, len);
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    match state.rebalance(&req) {
+        Ok(response) => serialize_response(&response),
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
+    }
+}
+
+/// Close all or part of a position: `qty` shares or `percentage` of it,
+/// or the whole position when neither is given
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn close_position(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct ClosePositionRequest {
+        symbol: String,
+        #[serde(default)]
+        qty: Option<f64>,
+        #[serde(default)]
+        percentage: Option<f64>,
+        #[serde(default)]
+        persona_id: String,
+        #[serde(default)]
+        dry_run: bool,
+    }
+
+    let req: ClosePositionRequest = parse_request(ptr, len);
+    let amount = match CloseAmount::from_request(req.qty, req.percentage) {
+        Ok(amount) => amount,
+        Err(e) => {
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": e
+            }))
+        }
+    };
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    match state.close_position(&req.symbol, amount, &req.persona_id, req.dry_run) {
+        Ok(response) => serialize_response(&response),
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
+    }
+}
+
+/// Protect a position with a stop, stop-limit, or trailing stop `percent`
+/// away from its cost or the market
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn protect_position(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct ProtectPositionRequest {
+        symbol: String,
+        percent: f64,
+        #[serde(default)]
+        order_type: protective::ProtectionType,
+        #[serde(default)]
+        basis: protective::Basis,
+        #[serde(default)]
+        limit_offset_percent: Option<f64>,
+        #[serde(default)]
+        qty: Option<f64>,
+        #[serde(default)]
+        time_in_force: Option<String>,
+        #[serde(default)]
+        persona_id: String,
+        #[serde(default)]
+        dry_run: bool,
+    }
+
+    let req: ProtectPositionRequest = parse_request(ptr, len);
+    let spec = match protective::ProtectionSpec::new(
+        req.order_type,
+        req.basis,
+        req.percent,
+        req.limit_offset_percent,
+        req.qty,
+    ) {
+        Ok(spec) => spec,
+        Err(e) => {
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": e
+            }))
+        }
+    };
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    match state.protect_position(
+        &req.symbol,
+        spec,
+        req.time_in_force.as_deref(),
+        &req.persona_id,
+        req.dry_run,
+    ) {
+        Ok(response) => serialize_response(&response),
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
+    }
+}
+
+/// Call an Alpaca endpoint the plugin has no typed export for, through
+/// the usual auth, rate limiting, and redaction. Off unless `raw_requests`
+/// is configured.
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn raw_request(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct RawRequest {
+        method: String,
+        path: String,
+        #[serde(default)]
+        body: Option<serde_json::Value>,
+        /// `trading` (default) or `data`
+        #[serde(default)]
+        api: Option<String>,
+        /// `paper` or `live` with `live_credentials`; default paper
+        #[serde(default)]
+        environment: Option<String>,
+    }
+
+    let req: RawRequest = parse_request(ptr, len);
+    let fail = |error: String| {
+        serialize_response(&serde_json::json!({
+            "success": false,
+            "error": error
+        }))
+    };
+    let Ok(method) =
+        serde_json::from_value::<http::HttpMethod>(req.method.to_ascii_uppercase().into())
+    else {
+        return fail(format!("Unsupported method: {}", req.method));
+    };
+    let data = match req.api.as_deref() {
+        None | Some("trading") => false,
+        Some("data") => true,
+        Some(other) => {
+            return fail(format!(
+                "Unsupported api: {} (expected trading or data)",
+                other
+            ))
+        }
+    };
+    let policy = STATE.lock().unwrap_or_else(|e| e.into_inner()).raw_policy;
+    if let Err(e) = policy.allow(method, &req.path) {
+        return fail(e);
+    }
+    let client = match Environment::from_field(req.environment.as_deref()).and_then(client_in) {
+        Ok(client) => client,
+        Err(e) => return fail(e),
+    };
+    if method != http::HttpMethod::Get {
+        let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(finding) = state.raw_write_rejection(client.is_paper()) {
+            return fail(finding.message);
+        }
+    }
+
+    logging::info("raw", "Raw request")
+        .field("method", method.as_str())
+        .field("path", req.path.as_str())
+        .emit();
+    let body = req.body.map(|b| b.to_string());
+    let response = client.raw_request(method, &req.path, body, data);
+    let parsed = serde_json::from_str::<serde_json::Value>(&response.body)
+        .unwrap_or_else(|_| response.body.clone().into());
+    serialize_response(&serde_json::json!({
+        "success": response.is_success(),
+        "status": response.status,
+        "body": parsed,
+        "error": response.error,
+        "request_id": response.header(trace::ALPACA_REQUEST_ID_HEADER),
+    }))
+}
+
+/// Get one order: an algo parent, a locally held queued/scheduled order,
+/// or an Alpaca order fetched fresh and merged into the cache, optionally
+/// with its replace chain
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_order(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct GetOrderRequest<'a> {
+        #[serde(borrow)]
+        order_id: Cow<'a, str>,
+        /// For orders the plugin is not tracking, as for `cancel_order`
+        #[serde(default)]
+        environment: Option<String>,
+        /// Also return every order in its replace chain
+        #[serde(default)]
+        include_chain: bool,
+    }
+
+    let req: GetOrderRequest = parse_request(ptr, len);
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+
+    let local = state
+        .algos
+        .get(&req.order_id)
+        .map(|a| a.to_order())
+        .or_else(|| {
+            state
+                .order_queue
+                .list()
+                .iter()
+                .find(|q| q.queue_id == req.order_id)
+                .map(|q| q.to_order())
+        })
+        .or_else(|| {
+            state
+                .scheduled
+                .list()
+                .iter()
+                .find(|s| s.schedule_id == req.order_id)
+                .map(|s| s.to_order())
+        });
+    if let Some(order) = local {
+        return serialize_response(&serde_json::json!({
+            "success": true,
+            "order": order
+        }));
+    }
+
+    let client = match order_client(&state, &req.order_id, req.environment.as_deref()) {
+        Ok(client) => client,
+        Err(e) => {
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": e
+            }))
+        }
+    };
+    match client.get_order(&req.order_id) {
+        Ok(order) => {
+            let order = match state.orders.get(req.order_id.as_ref()) {
+                Some(previous) => orders::merge_refresh(previous, order),
+                None => order,
+            };
+            state.track_order(order.clone());
+            if !req.include_chain {
+                return serialize_response(&serde_json::json!({
+                    "success": true,
+                    "order": order
+                }));
+            }
+            match state.order_chain(&client, &order.id) {
+                Ok(chain) => {
+                    let filled: Decimal = chain
+                        .iter()
+                        .map(|o| {
+                            orders::ext_str(o, "filled_qty")
+                                .map(decimal::parse_or_zero)
+                                .unwrap_or_default()
+                        })
+                        .sum();
+                    serialize_response(&serde_json::json!({
+                        "success": true,
+                        "order": order,
+                        "chain": chain,
+                        "chain_filled_qty": decimal::to_wire(filled)
+                    }))
+                }
+                Err(e) => serialize_response(&serde_json::json!({
+                    "success": false,
+                    "error": format!("Order chain: {}", e)
+                })),
+            }
+        }
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
+    }
+}
+
+/// Orders the plugin knows about, including algo parents and locally held
+/// orders. Served from the cache; `poll_order_updates` refreshes it.
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_orders(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetOrdersRequest {
+        /// `open` (default), `closed`, or `all`
+        #[serde(default)]
+        status: Option<String>,
+        #[serde(default)]
+        symbol: Option<String>,
+        #[serde(default)]
+        persona_id: Option<String>,
+        /// Fetch from Alpaca with bracket/OCO/OTO legs nested under their
+        /// parent, instead of listing the cache
+        #[serde(default)]
+        nested: bool,
+    }
+
+    let req: GetOrdersRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetOrdersRequest::default()
+    };
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+
+    let status = req.status.as_deref().unwrap_or("open");
+    if !matches!(status, "open" | "closed" | "all") {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": format!("Unsupported status filter: {} (expected open, closed, or all)", status)
+        }));
+    }
+
+    if req.nested {
+        let Some(client) = state.client.clone() else {
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": "Plugin not initialized"
+            }));
+        };
+        match client.list_orders(status, req.symbol.as_deref(), true) {
+            Ok(fetched) => {
+                for order in fetched {
+                    let order = match state.orders.get(&order.id) {
+                        Some(previous) => orders::merge_refresh(previous, order),
+                        None => order,
+                    };
+                    state.track_order(order);
+                }
+            }
+            Err(e) => {
+                return serialize_response(&serde_json::json!({
+                    "success": false,
+                    "error": e
+                }))
+            }
+        }
+    }
+    // Legs appear under their parent, not again on their own
+    let leg_ids: HashSet<&str> = match req.nested {
+        true => state
+            .order_legs
+            .values()
+            .flatten()
+            .map(|l| l.id.as_str())
+            .collect(),
+        false => HashSet::new(),
+    };
+
+    let mut all: Vec<Order> = state.orders.values().cloned().collect();
+    all.extend(state.algos.list().iter().map(|a| a.to_order()));
+    all.extend(state.order_queue.list().iter().map(|q| q.to_order()));
+    all.extend(state.scheduled.list().iter().map(|s| s.to_order()));
+
+    let mut selected: Vec<Order> = all
+        .into_iter()
+        .filter(|o| match status {
+            "open" => orders::is_working(o),
+            "closed" => !orders::is_working(o),
+            _ => true,
+        })
+        .filter(|o| {
+            req.symbol
+                .as_ref()
+                .is_none_or(|s| s.eq_ignore_ascii_case(&o.request.symbol_id))
+        })
+        .filter(|o| req.persona_id.as_ref().is_none_or(|p| *p == o.persona_id))
+        .filter(|o| !leg_ids.contains(o.id.as_str()))
+        .collect();
+    selected.sort_by_key(|o| std::cmp::Reverse(o.created_at));
+
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "orders": selected
+    }))
 }
 
-lazy_static::lazy_static! {
-    static ref STATE: Mutex<BrokerState> = Mutex::new(BrokerState::new());
+/// Whether each symbol can be traded right now, so the host can disable
+/// order entry for halted or delisted names
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_trading_status(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetTradingStatusRequest {
+        #[serde(default)]
+        symbols: Vec<String>,
+        /// Skip the snapshot (and halt detection), using only the cached
+        /// asset records
+        #[serde(default)]
+        assets_only: bool,
+    }
+
+    let req: GetTradingStatusRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetTradingStatusRequest::default()
+    };
+    let Some(client) = current_client() else {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "Plugin not initialized"
+        }));
+    };
+
+    let mut statuses = Vec::new();
+    let mut errors = Vec::new();
+    for symbol in &req.symbols {
+        match client.get_asset(symbol) {
+            Ok(asset) => statuses.push(trading_status::from_asset(symbol, asset)),
+            Err(e) => errors.push(serde_json::json!({ "symbol": symbol, "error": e })),
+        }
+    }
+
+    let tradable: Vec<String> = statuses
+        .iter()
+        .filter(|s| s.can_trade)
+        .map(|s| s.symbol.clone())
+        .collect();
+    if !req.assets_only && !tradable.is_empty() {
+        match client
+            .get_clock()
+            .and_then(|clock| Ok((client.get_snapshots(&tradable)?, clock)))
+        {
+            Ok((snapshots, clock)) => {
+                for status in &mut statuses {
+                    trading_status::apply_snapshot(status, snapshots.get(&status.symbol), &clock);
+                }
+            }
+            Err(e) => errors.push(serde_json::json!({ "error": e, "halt_check": false })),
+        }
+    }
+
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "statuses": statuses,
+        "errors": errors
+    }))
 }
 
-// --- WASM Exports ---
+/// Whether a symbol can be sold short, for pre-trade UI. With `qty`, the
+/// held position is taken into account and the findings `submit_order`
+/// would attach are returned.
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn check_shortable(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct CheckShortableRequest {
+        #[serde(default)]
+        symbol: String,
+        /// Quantity the host intends to sell
+        #[serde(default)]
+        qty: Option<f64>,
+    }
 
-/// Memory allocation for host communication
-#[no_mangle]
-pub extern "C" fn alloc(len: i32) -> i32 {
-    let mut buf: Vec<u8> = Vec::with_capacity(len as usize);
-    let ptr = buf.as_mut_ptr();
-    std::mem::forget(buf);
-    ptr as usize as i32
+    let req: CheckShortableRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        CheckShortableRequest::default()
+    };
+    let Some(client) = current_client() else {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "Plugin not initialized"
+        }));
+    };
+    let short_mode = STATE.lock().unwrap_or_else(|e| e.into_inner()).short_mode;
+
+    let asset = match client.get_asset(&req.symbol) {
+        Ok(Some(asset)) => asset,
+        Ok(None) => {
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": format!("Unknown symbol {}", req.symbol)
+            }));
+        }
+        Err(e) => {
+            return serialize_response(&serde_json::json!({ "success": false, "error": e }));
+        }
+    };
+
+    let mut response = serde_json::json!({
+        "success": true,
+        "symbol": asset.symbol,
+        "shortable": asset.shortable,
+        "easy_to_borrow": asset.easy_to_borrow,
+        "htb": asset.shortable && !asset.easy_to_borrow,
+    });
+    if let Some(qty) = req.qty {
+        let order: OrderRequest = match serde_json::from_value(serde_json::json!({
+            "symbol_id": req.symbol,
+            "quantity": qty,
+            "side": OrderSide::Sell,
+            "order_type": models::order::OrderType::Market,
+            "limit_price": null,
+            "stop_price": null,
+            "persona_id": "",
+        })) {
+            Ok(order) => order,
+            Err(e) => {
+                return serialize_response(&serde_json::json!({
+                    "success": false,
+                    "error": e.to_string()
+                }));
+            }
+        };
+        let held = match client.get_position(&req.symbol) {
+            Ok(position) => position
+                .and_then(|p| decimal::from_f64(p.quantity))
+                .unwrap_or_default(),
+            Err(e) => {
+                return serialize_response(&serde_json::json!({ "success": false, "error": e }));
+            }
+        };
+        let short = shorting::ShortSale::assess(&order, &asset, held);
+        let finding = match short.as_ref().map(|s| s.check(short_mode, &req.symbol)) {
+            Some(pretrade::CheckOutcome::Warn(f)) => Some((f, false)),
+            Some(pretrade::CheckOutcome::Block(f)) => Some((f, true)),
+            _ => None,
+        };
+        response["held_qty"] = decimal::to_wire(held).into();
+        response["short_qty"] = short
+            .as_ref()
+            .map_or_else(|| "0".to_string(), |s| decimal::to_wire(s.short_qty))
+            .into();
+        response["allowed"] = (!finding.as_ref().is_some_and(|(_, blocks)| *blocks)).into();
+        response["finding"] = finding
+            .map(|(f, _)| serde_json::to_value(f).unwrap_or_default())
+            .unwrap_or_default();
+    }
+    serialize_response(&response)
 }
 
-/// Initialize plugin with configuration
-#[no_mangle]
-pub extern "C" fn initialize(ptr: i32, len: i32) -> u64 {
-    let config_json: serde_json::Value = parse_request(ptr, len);
+/// Compute a limit price from the latest quote without submitting
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn price_order(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct PriceOrderRequest {
+        symbol: String,
+        side: models::order::OrderSide,
+        pricing_mode: serde_json::Value,
+    }
+
+    let req: PriceOrderRequest = parse_request(ptr, len);
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+
+    let result = PricingMode::from_value(&req.pricing_mode).and_then(|mode| {
+        let quote = state.latest_quote(&req.symbol)?;
+        let price = mode.limit_price(req.side, &quote)?;
+        Ok((mode, price, quote))
+    });
+    match result {
+        Ok((mode, price, quote)) => serialize_response(&serde_json::json!({
+            "success": true,
+            "symbol": req.symbol,
+            "mode": mode,
+            "limit_price": price,
+            "bid": quote.bid_price,
+            "ask": quote.ask_price,
+            "mid": quote.mid(),
+            "quote_time": quote.timestamp
+        })),
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
+    }
+}
+
+/// Algo parent orders with their consolidated fills
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_algo_orders(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetAlgoOrdersRequest {
+        #[serde(default)]
+        working_only: bool,
+    }
+
+    let req: GetAlgoOrdersRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetAlgoOrdersRequest::default()
+    };
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let orders: Vec<Order> = state
+        .algos
+        .list()
+        .iter()
+        .filter(|a| !req.working_only || a.is_working())
+        .map(|a| a.to_order())
+        .collect();
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "orders": orders
+    }))
+}
+
+/// Orders held locally until their scheduled time
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_scheduled_orders(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetScheduledOrdersRequest {
+        #[serde(default)]
+        persona_id: Option<String>,
+    }
+
+    let req: GetScheduledOrdersRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetScheduledOrdersRequest::default()
+    };
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let orders: Vec<Order> = state
+        .scheduled
+        .list()
+        .iter()
+        .filter(|s| {
+            req.persona_id
+                .as_ref()
+                .is_none_or(|p| *p == s.request.persona_id)
+        })
+        .map(|s| s.to_order())
+        .collect();
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "orders": orders
+    }))
+}
+
+/// Orders held locally until the market opens
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_queued_orders(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetQueuedOrdersRequest {
+        #[serde(default)]
+        persona_id: Option<String>,
+    }
+
+    let req: GetQueuedOrdersRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetQueuedOrdersRequest::default()
+    };
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let orders: Vec<Order> = state
+        .order_queue
+        .list()
+        .iter()
+        .filter(|q| {
+            req.persona_id
+                .as_ref()
+                .is_none_or(|p| *p == q.request.persona_id)
+        })
+        .map(|q| q.to_order())
+        .collect();
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "orders": orders
+    }))
+}
+
+/// Get individual executions for an order or a date range
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_executions(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct GetExecutionsRequest {
+        #[serde(default)]
+        order_id: Option<String>,
+        #[serde(default)]
+        start: Option<DateTime<Utc>>,
+        #[serde(default)]
+        end: Option<DateTime<Utc>>,
+    }
+
+    let req: GetExecutionsRequest = parse_request(ptr, len);
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+
+    let client = match state.client.as_ref() {
+        Some(c) => c,
+        None => {
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": "Plugin not initialized"
+            }));
+        }
+    };
+
+    // Order lookups fetch from the order's creation time; ranges fetch the
+    // requested window. Both merge into the cached store.
+    let after = match &req.order_id {
+        Some(id) => state.orders.get(id).map(|o| o.created_at).or(req.start),
+        None => req.start,
+    };
+    let fetched = client.list_fill_activities(after, req.end);
+
+    match fetched {
+        Ok(executions) => {
+            state.executions.ingest(executions);
+            let executions = match &req.order_id {
+                Some(id) => state.executions.for_order(id),
+                None => state.executions.in_range(req.start, req.end),
+            };
+            serialize_response(&serde_json::json!({
+                "success": true,
+                "executions": executions
+            }))
+        }
+        Err(e) => {
+            logging::error("executions", "Failed to fetch fill activities")
+                .field_opt("order_id", req.order_id.clone())
+                .field("error", e.as_str())
+                .emit();
+            serialize_response(&serde_json::json!({
+                "success": false,
+                "error": e
+            }))
+        }
+    }
+}
+
+/// List the Broker API sub-account's statements, confirmations, and tax
+/// forms, or download one with `document_id`
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_documents(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetDocumentsRequest {
+        #[serde(default)]
+        document_id: Option<String>,
+        #[serde(flatten)]
+        query: broker_api::DocumentQuery,
+    }
+
+    let req: GetDocumentsRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetDocumentsRequest::default()
+    };
+    let broker = match broker_api() {
+        Ok(broker) => broker,
+        Err(e) => {
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": e
+            }))
+        }
+    };
+    let result = match &req.document_id {
+        Some(id) => broker.download_document(id).map(|content| {
+            serde_json::json!({
+                "success": true,
+                "document_id": id,
+                "content": content
+            })
+        }),
+        None => broker.list_documents(&req.query).map(|documents| {
+            serde_json::json!({
+                "success": true,
+                "documents": documents
+            })
+        }),
+    };
+    match result {
+        Ok(body) => serialize_response(&body),
+        Err(e) => {
+            logging::error("documents", "Failed to fetch documents")
+                .field_opt("document_id", req.document_id.clone())
+                .field("error", e.as_str())
+                .emit();
+            serialize_response(&serde_json::json!({
+                "success": false,
+                "error": e
+            }))
+        }
+    }
+}
+
+/// ACH transfers of the Broker API sub-account and its linked banks
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_transfers(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetTransfersRequest {
+        #[serde(default)]
+        direction: Option<broker_api::Direction>,
+        /// Only this transfer, to poll its status
+        #[serde(default)]
+        transfer_id: Option<String>,
+    }
+
+    let req: GetTransfersRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetTransfersRequest::default()
+    };
+    let result = broker_api().and_then(|broker| {
+        let mut transfers = broker.list_transfers(req.direction)?;
+        if let Some(id) = &req.transfer_id {
+            transfers.retain(|t| t.id == *id);
+            if transfers.is_empty() {
+                return Err(format!("Transfer not found: {}", id));
+            }
+        }
+        Ok((transfers, broker.list_bank_relationships()?))
+    });
+    match result {
+        Ok((transfers, relationships)) => serialize_response(&serde_json::json!({
+            "success": true,
+            "transfers": transfers,
+            "bank_relationships": relationships
+        })),
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
+    }
+}
+
+/// Start an ACH deposit into, or withdrawal from, the Broker API
+/// sub-account
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn create_transfer(ptr: i32, len: i32) -> u64 {
+    let mut req: broker_api::TransferRequest = parse_request(ptr, len);
+    let acknowledged = STATE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .live_interlock
+        .acknowledged;
+    let result = broker_api().and_then(|broker| {
+        req.validate()?;
+        if !broker.client.is_paper() && !acknowledged {
+            return Err(format!(
+                "Live transfers are locked: set live_trading_ack to \"{}\" to move money",
+                interlock::ACK
+            ));
+        }
+        if req.relationship_id.is_none() {
+            req.resolve_relationship(&broker.list_bank_relationships()?)?;
+        }
+        broker.create_transfer(&req)
+    });
+    match result {
+        Ok(transfer) => {
+            logging::info("transfers", "Transfer created")
+                .field("transfer_id", transfer.id.as_str())
+                .field("direction", transfer.direction.as_str())
+                .field("amount", transfer.amount.as_str())
+                .emit();
+            serialize_response(&serde_json::json!({
+                "success": true,
+                "transfer": transfer
+            }))
+        }
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
+    }
+}
+
+/// Journal cash or shares between Broker API sub-accounts
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn create_journal(ptr: i32, len: i32) -> u64 {
+    let req: broker_api::JournalRequest = parse_request(ptr, len);
+    let acknowledged = STATE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .live_interlock
+        .acknowledged;
+    let result = broker_api().and_then(|broker| {
+        req.validate()?;
+        if !broker.client.is_paper() && !acknowledged {
+            return Err(format!(
+                "Live journals are locked: set live_trading_ack to \"{}\" to move assets",
+                interlock::ACK
+            ));
+        }
+        broker.create_journal(&req)
+    });
+    match result {
+        Ok(journal) => {
+            logging::info("journals", "Journal created")
+                .field("journal_id", journal.id.as_str())
+                .field("entry_type", journal.entry_type.as_str())
+                .field("to_account", journal.to_account.as_str())
+                .emit();
+            serialize_response(&serde_json::json!({
+                "success": true,
+                "journal": journal
+            }))
+        }
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
+    }
+}
+
+/// List journals involving the Broker API sub-account, or poll one with
+/// `journal_id`
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_journals(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetJournalsRequest {
+        #[serde(default)]
+        journal_id: Option<String>,
+        #[serde(flatten)]
+        query: broker_api::JournalQuery,
+    }
+
+    let req: GetJournalsRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetJournalsRequest::default()
+    };
+    let result = broker_api().and_then(|broker| match &req.journal_id {
+        Some(id) => broker.get_journal(id).map(|journal| vec![journal]),
+        None => broker.list_journals(&req.query),
+    });
+    match result {
+        Ok(journals) => serialize_response(&serde_json::json!({
+            "success": true,
+            "journals": journals
+        })),
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
+    }
+}
+
+/// Apply for a new Broker API sub-account (contact, identity,
+/// disclosures, agreements)
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn open_account(ptr: i32, len: i32) -> u64 {
+    let application: onboarding::AccountApplication = parse_request(ptr, len);
+    if let Some(tax_id) = &application.identity.tax_id {
+        redact::register_secret(tax_id);
+    }
+    let problems = application.problems();
+    if !problems.is_empty() {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": format!("Invalid application: {}", problems.join("; ")),
+            "problems": problems
+        }));
+    }
+    match broker_api().and_then(|broker| broker.open_account(&application)) {
+        Ok(account) => {
+            logging::info("onboarding", "Account application submitted")
+                .field("account_id", account.id.as_str())
+                .field("status", account.status.as_str())
+                .emit();
+            serialize_response(&serde_json::json!({
+                "success": true,
+                "account": account
+            }))
+        }
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
+    }
+}
+
+/// Poll the review status of a Broker API sub-account
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_account_status(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetAccountStatusRequest {
+        /// Default: `broker_api.account_id`
+        #[serde(default)]
+        account_id: Option<String>,
+    }
+
+    let req: GetAccountStatusRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetAccountStatusRequest::default()
+    };
+    match broker_api().and_then(|broker| broker.account_status(req.account_id.as_deref())) {
+        Ok(account) => serialize_response(&serde_json::json!({
+            "success": true,
+            "account": account
+        })),
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
+    }
+}
+
+/// Confirmation records for fills, by US Eastern trade date
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_trade_confirmations(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetTradeConfirmationsRequest {
+        /// First trade date; default today
+        #[serde(default)]
+        start: Option<chrono::NaiveDate>,
+        /// Last trade date; default `start`
+        #[serde(default)]
+        end: Option<chrono::NaiveDate>,
+        #[serde(default)]
+        symbol: Option<String>,
+        #[serde(default)]
+        order_id: Option<String>,
+    }
+
+    let req: GetTradeConfirmationsRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetTradeConfirmationsRequest::default()
+    };
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+
+    let client = match state.client.clone() {
+        Some(c) => c,
+        None => {
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": "Plugin not initialized"
+            }));
+        }
+    };
+
+    let start_date = req.start.unwrap_or_else(market_time::eastern_today);
+    let end_date = req.end.unwrap_or(start_date);
+    if end_date < start_date {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "end must not be before start"
+        }));
+    }
+    let midnight = chrono::NaiveTime::MIN;
+    let start = market_time::eastern_at(start_date, midnight);
+    let end = market_time::eastern_at(end_date + chrono::Duration::days(1), midnight);
+    match client.list_fill_activities(Some(start), Some(end)) {
+        Ok(executions) => {
+            state.executions.ingest(executions);
+        }
+        Err(e) => {
+            logging::error("confirmations", "Failed to fetch fill activities")
+                .field("error", e.as_str())
+                .emit();
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": e
+            }));
+        }
+    }
+
+    // Look far enough ahead to reach the next session after a long weekend
+    let mut warnings = Vec::new();
+    let calendar = client
+        .get_calendar(start_date, end_date + chrono::Duration::days(7))
+        .unwrap_or_else(|e| {
+            warnings.push(format!(
+                "Market calendar unavailable, settlement assumes the next weekday: {}",
+                e
+            ));
+            Vec::new()
+        });
+    let confirmations: Vec<confirmations::TradeConfirmation> = state
+        .executions
+        .in_range(Some(start), Some(end))
+        .iter()
+        .filter(|e| e.timestamp < end)
+        .filter(|e| {
+            req.symbol
+                .as_ref()
+                .is_none_or(|s| e.symbol.eq_ignore_ascii_case(s))
+        })
+        .filter(|e| req.order_id.as_ref().is_none_or(|id| e.order_id == *id))
+        .map(|e| {
+            let persona_id = state
+                .order_or_parent(&e.order_id)
+                .map(|o| o.persona_id.clone())
+                .filter(|p| !p.is_empty());
+            confirmations::confirm(e, persona_id, state.fees.as_ref(), &calendar)
+        })
+        .collect();
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "confirmations": confirmations,
+        "warnings": warnings
+    }))
+}
+
+/// Get realized PnL by symbol/persona/date range from fill history
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_realized_pnl(ptr: i32, len: i32) -> u64 {
+    let query: RealizedPnlQuery = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        RealizedPnlQuery::default()
+    };
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+
+    if let Err(e) = state.refresh_executions() {
+        logging::error("pnl", "Failed to refresh fills")
+            .field("error", e.as_str())
+            .emit();
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        }));
+    }
+
+    let ledger = state.pnl_ledger();
+    let report = RealizedPnlReport::from_trades(&ledger.realized, &query);
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "report": report
+    }))
+}
+
+/// Get open tax lots, optionally filtered by symbol and persona
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_tax_lots(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetTaxLotsRequest {
+        #[serde(default)]
+        symbol: Option<String>,
+        #[serde(default)]
+        persona_id: Option<String>,
+    }
 
+    let req: GetTaxLotsRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetTaxLotsRequest::default()
+    };
     let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
 
-    // Parse configuration
-    let api_key = config_json
-        .get("api_key")
-        .and_then(|v| v.as_str())
-        .map(|s| s.to_string());
+    if let Err(e) = state.refresh_executions() {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        }));
+    }
 
-    let api_secret = config_json
-        .get("api_secret")
-        .and_then(|v| v.as_str())
-        .map(|s| s.to_string());
+    let ledger = state.pnl_ledger();
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "lots": ledger.open_lots(req.symbol.as_deref(), req.persona_id.as_deref())
+    }))
+}
 
-    let is_paper = config_json
-        .get("is_paper")
-        .and_then(|v| v.as_bool())
-        .unwrap_or(true); // Default to paper trading for safety
+/// Get dividends, interest, fees, and transfers with optional grouping
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_cash_flows(ptr: i32, len: i32) -> u64 {
+    let query: CashFlowQuery = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        CashFlowQuery::default()
+    };
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
 
-    // Validate configuration
-    match (api_key, api_secret) {
-        (Some(key), Some(secret)) if !key.is_empty() && !secret.is_empty() => {
-            let client = AlpacaClient::new(key, secret, is_paper);
-            state.client = Some(client);
+    let client = match state.client.as_ref() {
+        Some(c) => c,
+        None => {
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": "Plugin not initialized"
+            }));
+        }
+    };
 
+    match client.list_cash_activities(state.cash_flows.fetch_after()) {
+        Ok(flows) => {
+            state.cash_flows.ingest(flows);
             serialize_response(&serde_json::json!({
                 "success": true,
-                "message": format!("Alpaca plugin initialized ({})", if is_paper { "paper" } else { "live" })
+                "report": state.cash_flows.query(&query)
+            }))
+        }
+        Err(e) => {
+            logging::error("cashflows", "Failed to fetch cash activities")
+                .field("error", e.as_str())
+                .emit();
+            serialize_response(&serde_json::json!({
+                "success": false,
+                "error": e
             }))
         }
-        _ => serialize_response(&serde_json::json!({
-            "success": false,
-            "error": "Missing required configuration: api_key and api_secret",
-            "requires_auth": true
-        })),
     }
 }
 
-/// Get available accounts
-#[no_mangle]
-pub extern "C" fn get_accounts(ptr: i32, len: i32) -> u64 {
-    let _req: GetAccountsRequest = parse_request(ptr, len);
+/// Account equity and PnL over time, optionally with a benchmark's
+/// returns over the same points
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_portfolio_history(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetPortfolioHistoryRequest {
+        /// Alpaca's `period`, e.g. `1M`; Alpaca's default when unset
+        #[serde(default)]
+        period: Option<String>,
+        /// `1Min`, `5Min`, `15Min`, `1H`, or `1D`
+        #[serde(default)]
+        timeframe: Option<String>,
+        #[serde(default)]
+        start: Option<DateTime<Utc>>,
+        #[serde(default)]
+        end: Option<DateTime<Utc>>,
+        #[serde(default)]
+        extended_hours: Option<bool>,
+        /// `true` for `benchmark_symbol`, or a symbol
+        #[serde(default)]
+        benchmark: Option<serde_json::Value>,
+    }
 
+    let req: GetPortfolioHistoryRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetPortfolioHistoryRequest::default()
+    };
     let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
-
-    let client = match state.client.as_ref() {
-        Some(c) => c,
-        None => {
-            return serialize_response(&GetAccountsResponse {
-                accounts: vec![create_error_account(
-                    "Plugin not initialized. Provide api_key and api_secret.",
-                )],
-            });
+    let Some(client) = state.client.clone() else {
+        return serialize_response(&serde_json::json!({
+            "success": false,
+            "error": "Plugin not initialized"
+        }));
+    };
+    let benchmark = match &req.benchmark {
+        None | Some(serde_json::Value::Null) | Some(serde_json::Value::Bool(false)) => None,
+        Some(serde_json::Value::Bool(true)) => Some(state.benchmark_symbol.clone()),
+        Some(serde_json::Value::String(symbol)) if !symbol.is_empty() => {
+            Some(symbol.to_ascii_uppercase())
+        }
+        Some(_) => {
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": "benchmark must be true or a symbol"
+            }));
         }
     };
+    drop(state);
 
-    match client.list_accounts() {
-        Ok(accounts) => serialize_response(&GetAccountsResponse { accounts }),
+    let rfc3339 = |t: DateTime<Utc>| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let query = http::QueryParams::new()
+        .push_opt("period", req.period.as_deref())
+        .push_opt("timeframe", req.timeframe.as_deref())
+        .push_opt("start", req.start.map(rfc3339))
+        .push_opt("end", req.end.map(rfc3339))
+        .push_opt("extended_hours", req.extended_hours);
+    let history = match client.get_portfolio_history(&query) {
+        Ok(history) => history,
         Err(e) => {
-            eprintln!("[broker-alpaca] Failed to fetch accounts: {}", e);
-            serialize_response(&GetAccountsResponse {
-                accounts: vec![create_error_account(&e)],
-            })
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": e
+            }));
         }
+    };
+
+    let mut response = serde_json::json!({
+        "success": true,
+        "history": history
+    });
+    let Some(symbol) = benchmark else {
+        return serialize_response(&response);
+    };
+    let points = history
+        .timestamp
+        .first()
+        .zip(history.timestamp.last())
+        .and_then(|(first, last)| {
+            Some((
+                DateTime::<Utc>::from_timestamp(*first, 0)?,
+                DateTime::<Utc>::from_timestamp(*last, 0)?,
+            ))
+        });
+    let aligned = match (points, benchmark::bar_timeframe(&history.timeframe)) {
+        (None, _) => Err("The history has no points".to_string()),
+        (_, None) => Err(format!("No bars for timeframe {}", history.timeframe)),
+        (Some((first, last)), Some(timeframe)) => client
+            .get_bars(
+                &symbol,
+                timeframe,
+                first - benchmark::lookback(&history.timeframe),
+                Some(last),
+            )
+            .map(|bars| benchmark::align(&symbol, &history, &bars)),
+    };
+    match aligned {
+        Ok(series) => response["benchmark"] = serde_json::to_value(series).unwrap_or_default(),
+        // The history itself is still good
+        Err(e) => response["benchmark_error"] = e.into(),
     }
+    serialize_response(&response)
 }
 
-/// Get positions for an account
-#[no_mangle]
-pub extern "C" fn get_positions(ptr: i32, len: i32) -> u64 {
-    let _req: GetPositionsRequest = parse_request(ptr, len);
-
-    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+/// Buying power and shares held by working orders
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_reserved_funds(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetReservedFundsRequest {
+        /// `paper` or `live` with `live_credentials`; default paper
+        #[serde(default)]
+        environment: Option<String>,
+    }
 
-    let client = match state.client.as_ref() {
-        Some(c) => c,
-        None => {
-            return serialize_response(&GetPositionsResponse { positions: vec![] });
-        }
+    let req: GetReservedFundsRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetReservedFundsRequest::default()
     };
-
-    match client.get_positions() {
-        Ok(positions) => serialize_response(&GetPositionsResponse { positions }),
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let environment = match Environment::from_field(req.environment.as_deref()) {
+        Ok(environment) => environment,
         Err(e) => {
-            eprintln!("[broker-alpaca] Failed to fetch positions: {}", e);
-            serialize_response(&GetPositionsResponse { positions: vec![] })
+            return serialize_response(&serde_json::json!({
+                "success": false,
+                "error": e
+            }))
         }
+    };
+    match state.with_environment(environment, |state| state.reserved_funds()) {
+        Ok(reserved) => serialize_response(&serde_json::json!({
+            "success": true,
+            "reserved": reserved
+        })),
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
     }
 }
 
-/// Submit an order
-#[no_mangle]
-pub extern "C" fn submit_order(ptr: i32, len: i32) -> u64 {
-    let req: SubmitOrderRequest = parse_request(ptr, len);
+/// Settled and unsettled cash, with the fills that have not settled
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_cash_settlement(ptr: i32, len: i32) -> u64 {
+    if len > 0 {
+        let _: serde_json::Value = parse_request(ptr, len);
+    } else {
+        trace::set(None);
+    }
     let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let result = state
+        .client
+        .clone()
+        .ok_or_else(|| "Plugin not initialized".to_string())
+        .and_then(|client| client.fetch_account())
+        .and_then(|account| state.cash_settlement(&account));
+    match result {
+        Ok(settlement) => serialize_response(&serde_json::json!({
+            "success": true,
+            "settlement": settlement
+        })),
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
+    }
+}
 
-    let client = match state.client.as_ref() {
-        Some(c) => c,
-        None => {
-            return serialize_response(&SubmitOrderResponse {
-                order: create_error_order(&req, "Plugin not initialized"),
-            });
-        }
+/// One day's orders, fills, PnL, fees, and rejections in a single report
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_daily_summary(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetDailySummaryRequest {
+        /// US Eastern trading day; default today
+        #[serde(default)]
+        date: Option<chrono::NaiveDate>,
+    }
+
+    let req: GetDailySummaryRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetDailySummaryRequest::default()
     };
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let date = req.date.unwrap_or_else(market_time::eastern_today);
+    match state.daily_summary(date) {
+        Ok(summary) => serialize_response(&serde_json::json!({
+            "success": true,
+            "summary": summary
+        })),
+        Err(e) => serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        })),
+    }
+}
 
-    match client.submit_order(&req.order) {
-        Ok(mut order) => {
-            let order_id = order.id.clone();
-            if order.persona_id.is_empty() {
-                order.persona_id = req.order.persona_id.clone();
-            }
-            state.orders.insert(order_id, order.clone());
+/// Line up a live order's fills with its shadow on paper, given the ID of
+/// either one
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_shadow_comparison(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct ShadowComparisonRequest<'a> {
+        #[serde(borrow)]
+        order_id: Cow<'a, str>,
+    }
 
-            serialize_response(&SubmitOrderResponse { order })
-        }
-        Err(e) => {
-            eprintln!("[broker-alpaca] Order failed: {}", e);
-            serialize_response(&SubmitOrderResponse {
-                order: create_error_order(&req, &e),
-            })
+    let req: ShadowComparisonRequest = parse_request(ptr, len);
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let fail = |e: String| {
+        serialize_response(&serde_json::json!({
+            "success": false,
+            "error": e
+        }))
+    };
+
+    let pair = state.orders.get(req.order_id.as_ref()).map(|order| {
+        match (
+            orders::ext_str(order, "shadow_order_id"),
+            orders::ext_str(order, "shadow_of"),
+        ) {
+            (Some(copy), _) => Some((order.id.clone(), copy.to_string())),
+            (None, Some(original)) => Some((original.to_string(), order.id.clone())),
+            (None, None) => None,
         }
-    }
+    });
+    let (original_id, copy_id) = match pair {
+        Some(Some(pair)) => pair,
+        Some(None) => return fail(format!("Order {} has no shadow order", req.order_id)),
+        None => return fail(format!("Order {} is not tracked", req.order_id)),
+    };
+    let refreshed = state
+        .refresh_tracked(&original_id)
+        .and_then(|original| Ok((original, state.refresh_tracked(&copy_id)?)));
+    let (original, copy) = match refreshed {
+        Ok(pair) => pair,
+        Err(e) => return fail(e),
+    };
+    let (live, paper) = match Environment::of_order(&original) {
+        Some(Environment::Live) => (original, copy),
+        _ => (copy, original),
+    };
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "comparison": shadow::comparison(&live, &paper)
+    }))
 }
 
-/// Cancel an order
-#[no_mangle]
-pub extern "C" fn cancel_order(ptr: i32, len: i32) -> u64 {
-    #[derive(serde::Deserialize)]
-    struct CancelOrderRequest {
-        order_id: String,
+/// Get request/order metrics as JSON (default) or Prometheus text
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn get_metrics(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize, Default)]
+    struct GetMetricsRequest {
+        #[serde(default)]
+        format: Option<String>,
+        /// `paper` or `live` with `live_credentials`; default paper
+        #[serde(default)]
+        environment: Option<String>,
     }
 
-    let req: CancelOrderRequest = parse_request(ptr, len);
-    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let req: GetMetricsRequest = if len > 0 {
+        parse_request(ptr, len)
+    } else {
+        trace::set(None);
+        GetMetricsRequest::default()
+    };
 
-    let client = match state.client.as_ref() {
-        Some(c) => c,
-        None => {
+    let client = match Environment::from_field(req.environment.as_deref()).and_then(client_in) {
+        Ok(c) => c,
+        Err(e) => {
             return serialize_response(&serde_json::json!({
                 "success": false,
-                "error": "Plugin not initialized"
+                "error": e
             }));
         }
     };
 
-    match client.cancel_order(&req.order_id) {
-        Ok(()) => serialize_response(&serde_json::json!({
+    match req.format.as_deref() {
+        Some("prometheus") => serialize_response(&serde_json::json!({
             "success": true,
-            "order_id": req.order_id
+            "format": "prometheus",
+            "metrics": client.metrics().to_prometheus()
         })),
+        _ => serialize_response(&serde_json::json!({
+            "success": true,
+            "format": "json",
+            "metrics": client.metrics().snapshot(),
+            "memory": arena::stats()
+        })),
+    }
+}
+
+/// Next piece of a chunked response; see `chunked`
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn next_chunk(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct NextChunkRequest {
+        cursor: u64,
+    }
+
+    let req: NextChunkRequest = parse_request(ptr, len);
+    // A chunk is never itself chunked
+    chunked::take_requested();
+    match chunked::next(req.cursor) {
+        Ok(chunk) => serialize_response(&chunk),
         Err(e) => serialize_response(&serde_json::json!({
             "success": false,
             "error": e
@@ -221,24 +5801,226 @@ pub extern "C" fn cancel_order(ptr: i32, len: i32) -> u64 {
     }
 }
 
+/// Release a chunked response the host no longer needs
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn finish_chunked(ptr: i32, len: i32) -> u64 {
+    #[derive(serde::Deserialize)]
+    struct FinishChunkedRequest {
+        cursor: u64,
+    }
+
+    let req: FinishChunkedRequest = parse_request(ptr, len);
+    chunked::take_requested();
+    serialize_response(&serde_json::json!({
+        "success": true,
+        "released": chunked::finish(req.cursor)
+    }))
+}
+
 // --- Helper Functions ---
 
-fn parse_request<T: serde::de::DeserializeOwned>(ptr: i32, len: i32) -> T {
-    let slice = unsafe { slice::from_raw_parts(ptr as *const u8, len as usize) };
-    serde_json::from_slice(slice).expect("Failed to parse request")
+/// Decode the request in place. Types may borrow from the request buffer
+/// (`#[serde(borrow)] Cow<'a, str>`), which the host keeps alive for the
+/// duration of the call.
+fn parse_request<'a, T: serde::Deserialize<'a>>(ptr: i32, len: i32) -> T {
+    let slice: &'a [u8] = unsafe { slice::from_raw_parts(ptr as *const u8, len as usize) };
+    trace::begin(slice);
+    chunked::begin(slice);
+    wire::current()
+        .decode(slice)
+        .expect("Failed to parse request")
 }
 
 fn serialize_response<T: serde::Serialize>(response: &T) -> u64 {
-    let res_bytes = serde_json::to_vec(response).expect("Failed to serialize response");
+    // Oversized responses are parked behind a cursor when the host asked.
+    // Chunks always carry JSON text, whatever the envelope encoding.
+    let res_bytes = match chunked::take_requested() {
+        Some(chunk_size) => {
+            let body = redact::scrub(&response_value(response).to_string());
+            if body.len() > chunk_size {
+                response_bytes(&chunked::open(body, chunk_size))
+            } else if wire::current() == wire::Encoding::Json {
+                body.into_bytes()
+            } else {
+                response_bytes(response)
+            }
+        }
+        None => response_bytes(response),
+    };
+
+    let (out_ptr, out_len) = arena::write_response(&res_bytes);
+    pack_ptr_len(out_ptr as usize as i32, out_len as i32)
+}
+
+/// The response plus the current trace ID
+fn response_value<T: serde::Serialize>(response: &T) -> serde_json::Value {
+    let mut value = serde_json::to_value(response).expect("Failed to serialize response");
+    if let (Some(trace_id), Some(obj)) = (trace::current(), value.as_object_mut()) {
+        obj.insert("trace_id".to_string(), serde_json::Value::String(trace_id));
+    }
+    value
+}
+
+/// Bytes handed to the host in the negotiated encoding, with secrets
+/// scrubbed
+fn response_bytes<T: serde::Serialize>(response: &T) -> Vec<u8> {
+    encode_response(response, wire::current())
+}
+
+fn encode_response<T: serde::Serialize>(response: &T, encoding: wire::Encoding) -> Vec<u8> {
+    let mut value = response_value(response);
+    // Every response crosses the host boundary here, so scrub secrets once
+    match encoding {
+        wire::Encoding::Json => redact::scrub(&value.to_string()).into_bytes(),
+        wire::Encoding::MessagePack => {
+            redact::scrub_value(&mut value);
+            encoding.encode(&value)
+        }
+    }
+}
 
-    let out_len = res_bytes.len() as i32;
-    let out_ptr = alloc(out_len);
+/// Pack a guest pointer and length into one `u64`: pointer in the high 32
+/// bits, length in the low 32. Pointers are addresses in the 32-bit linear
+/// memory, so the upper half of it arrives as a negative `i32`.
+fn pack_ptr_len(ptr: i32, len: i32) -> u64 {
+    ((ptr as u32 as u64) << 32) | (len as u32 as u64)
+}
+
+/// Inverse of [`pack_ptr_len`], for values returned by host imports
+pub(crate) fn unpack_ptr_len(packed: u64) -> (i32, i32) {
+    ((packed >> 32) as u32 as i32, packed as u32 as i32)
+}
+
+/// The client for an order ID: the environment a tracked order was
+/// placed in, otherwise the requested one or the default
+fn order_client(
+    state: &BrokerState,
+    order_id: &str,
+    environment: Option<&str>,
+) -> Result<Arc<AlpacaClient>, String> {
+    if state.order_or_parent(order_id).is_some() {
+        return state
+            .client_for_order(order_id)
+            .cloned()
+            .ok_or_else(|| "Plugin not initialized".to_string());
+    }
+    state.client_in(Environment::from_field(environment)?)
+}
+
+/// Basket orders sent at once unless `basket_concurrency` is configured
+const DEFAULT_BASKET_CONCURRENCY: usize = 4;
+
+/// Where routing left an order: finished locally (rejected, held,
+/// started as an algo, or a dry run), or ready to go to Alpaca. Matched
+/// as soon as it is made, so the unboxed order costs nothing.
+#[allow(clippy::large_enum_variant)]
+enum Routed {
+    Done(Order),
+    Send(Box<Submission>),
+}
+
+/// An order that has passed every check, with what its completion needs
+/// once Alpaca answers
+struct Submission {
+    /// The request as placed, before pricing
+    requested: OrderRequest,
+    request: OrderRequest,
+    /// What is sent to Alpaca
+    order_request: OrderRequest,
+    lot_selection: Option<LotSelection>,
+    reference: Option<reference::ReferenceQuote>,
+    expiry: Option<DateTime<Utc>>,
+    checks: CheckReport,
+    is_paper: bool,
+    client: Arc<AlpacaClient>,
+    metadata: Option<serde_json::Value>,
+    environment: Option<Environment>,
+}
+
+/// Alpaca's answer to a submission and its latency in milliseconds
+type Sent = (Result<Order, String>, u64);
+
+impl Submission {
+    fn send(&self) -> Sent {
+        let started = Instant::now();
+        let result = self.client.submit_order(&self.order_request);
+        (result, started.elapsed().as_millis() as u64)
+    }
+
+    /// Stand-in for the order while it is in flight, so the checks of
+    /// orders prepared alongside it count it as sent
+    fn in_flight(&self) -> InFlight {
+        let now = Utc::now();
+        let order = Order {
+            id: format!("sending_{}", self.order_request.symbol_id),
+            request: self.order_request.clone(),
+            status: OrderStatus::Submitted,
+            created_at: now,
+            updated_at: now,
+            average_filled_price: None,
+            filled_quantity: 0.0,
+            extensions: None,
+            persona_id: self.order_request.persona_id.clone(),
+        };
+        InFlight {
+            order,
+            live_notional: self.checks.live_notional,
+            is_paper: self.is_paper,
+        }
+    }
+}
+
+/// A basket order sent to Alpaca whose answer has not come back yet
+struct InFlight {
+    order: Order,
+    live_notional: Option<Decimal>,
+    is_paper: bool,
+}
 
-    unsafe {
-        std::ptr::copy_nonoverlapping(res_bytes.as_ptr(), out_ptr as *mut u8, out_len as usize);
+/// Send submissions to Alpaca, all at once on native targets. On wasm32,
+/// where the plugin has no threads, they go one at a time.
+fn send_all(submissions: &[&Submission]) -> Vec<Sent> {
+    if submissions.len() < 2 || cfg!(target_arch = "wasm32") {
+        return submissions.iter().map(|s| s.send()).collect();
     }
+    let trace_id = trace::current();
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = submissions
+            .iter()
+            .map(|submission| {
+                let trace_id = trace_id.clone();
+                scope.spawn(move || {
+                    // Requests from the sender carry the caller's trace ID
+                    trace::set(trace_id);
+                    submission.send()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| (Err("Order submission panicked".to_string()), 0))
+            })
+            .collect()
+    })
+}
 
-    ((out_ptr as u64) << 32) | (out_len as u64)
+/// The plugin-worked feature an order asks for, if any. These keep local
+/// state that only follows the default environment.
+fn worked_locally(request: &OrderRequest) -> Option<&'static str> {
+    if matches!(schedule::requested(request), Ok(Some(_))) {
+        Some("Scheduled")
+    } else if matches!(algo::requested(request), Ok(Some(_))) {
+        Some("Algo")
+    } else if matches!(chase::requested(request), Ok(Some(_))) {
+        Some("Chased")
+    } else if matches!(gtd::requested_expiry(request), Ok(Some(_))) {
+        Some("Good-til-date")
+    } else {
+        None
+    }
 }
 
 fn create_error_account(error: &str) -> AccountSummary {
@@ -260,10 +6042,79 @@ fn create_error_account(error: &str) -> AccountSummary {
     }
 }
 
-fn create_error_order(req: &SubmitOrderRequest, error: &str) -> Order {
+/// Order rejected locally by a pre-trade check
+fn algo_event(algo: &algo::AlgoOrder, kind: &str, child: Option<&Order>) -> serde_json::Value {
+    serde_json::json!({
+        "type": "algo",
+        "event": kind,
+        "algo_id": algo.id,
+        "parent": algo.to_order(),
+        "child": child,
+    })
+}
+
+/// Error for an account ID that is not the one behind the API keys
+fn unknown_account(account_id: &str) -> Finding {
+    Finding::new(
+        "unknown_account",
+        format!("Unknown account {}", account_id),
+        serde_json::json!({ "account_id": account_id }),
+    )
+}
+
+/// Rejection for an account Alpaca would refuse orders from
+fn account_blocked(reason: String) -> Finding {
+    Finding::new("account_blocked", reason, serde_json::Value::Null)
+}
+
+/// The `auction_cutoff` finding for an auction order placed too late.
+/// Without the clock the order is sent and Alpaca decides.
+fn auction_cutoff(client: &AlpacaClient, auction: auction::Auction) -> Option<Finding> {
+    let now = Utc::now();
+    let clock = match client.get_clock() {
+        Ok(clock) => clock,
+        Err(e) => {
+            logging::warn("orders", "Clock unavailable; auction cutoff not checked")
+                .field("error", e.as_str())
+                .emit();
+            return None;
+        }
+    };
+    let today = market_time::eastern_date(now);
+    let calendar = if auction::needs_calendar(&clock, now) {
+        client.get_calendar(today, today).unwrap_or_else(|e| {
+            logging::warn("orders", "Calendar unavailable; auction cutoff not checked")
+                .field("error", e.as_str())
+                .emit();
+            Vec::new()
+        })
+    } else {
+        Vec::new()
+    };
+    auction::check_cutoff(auction, &clock, calendar.first(), now)
+}
+
+fn create_rejected_order(request: &OrderRequest, finding: &Finding, warnings: &[Finding]) -> Order {
+    let mut order = create_error_order(request, &finding.message);
+    orders::set_ext(
+        &mut order,
+        "rejection",
+        serde_json::to_value(finding).unwrap_or_default(),
+    );
+    if !warnings.is_empty() {
+        orders::set_ext(
+            &mut order,
+            "warnings",
+            serde_json::to_value(warnings).unwrap_or_default(),
+        );
+    }
+    order
+}
+
+fn create_error_order(request: &OrderRequest, error: &str) -> Order {
     Order {
         id: format!("error_{}", Utc::now().timestamp_millis()),
-        request: req.order.clone(),
+        request: request.clone(),
         status: OrderStatus::Rejected,
         created_at: Utc::now(),
         updated_at: Utc::now(),
@@ -275,8 +6126,335 @@ fn create_error_order(req: &SubmitOrderRequest, error: &str) -> Order {
                 "error".to_string(),
                 serde_json::Value::String(error.to_string()),
             );
+            if let Some(trace_id) = trace::current() {
+                map.insert("trace_id".to_string(), serde_json::Value::String(trace_id));
+            }
             map
         }),
-        persona_id: req.order.persona_id.clone(),
+        persona_id: request.persona_id.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn parse_response(bytes: &[u8]) -> serde_json::Value {
+        serde_json::from_slice(bytes).expect("response is not valid JSON")
+    }
+
+    proptest! {
+        #[test]
+        fn ptr_len_packing_round_trips(ptr in any::<i32>(), len in 0..=i32::MAX) {
+            let packed = pack_ptr_len(ptr, len);
+            prop_assert_eq!(unpack_ptr_len(packed), (ptr, len));
+            prop_assert_eq!(packed as u32, len as u32);
+        }
+
+        #[test]
+        fn response_bytes_preserve_arbitrary_text(
+            fields in proptest::collection::btree_map("[a-z_]{1,12}", any::<String>(), 0..8),
+        ) {
+            // Sensitive names are masked by design; see the test below
+            let fields: std::collections::BTreeMap<_, _> = fields
+                .into_iter()
+                .filter(|(k, _)| !k.contains("api_key") && !k.contains("api_secret"))
+                .collect();
+            trace::set(None);
+            let value = parse_response(&response_bytes(&fields));
+            prop_assert_eq!(value, serde_json::to_value(&fields).unwrap());
+        }
+
+        #[test]
+        fn masked_responses_stay_valid_json(secret in any::<String>(), other in any::<String>()) {
+            trace::set(None);
+            let response = serde_json::json!({ "api_secret": secret, "note": other });
+            let value = parse_response(&response_bytes(&response));
+            if !secret.is_empty() {
+                prop_assert_eq!(&value["api_secret"], redact::REDACTED);
+            }
+            prop_assert!(value.get("note").is_some());
+        }
+
+        #[test]
+        fn trace_id_is_echoed(trace_id in "[a-zA-Z0-9-]{1,36}") {
+            trace::set(Some(trace_id.clone()));
+            let value = parse_response(&response_bytes(&serde_json::json!({ "success": true })));
+            trace::set(None);
+            prop_assert_eq!(value["trace_id"].as_str(), Some(trace_id.as_str()));
+        }
+
+        #[test]
+        fn msgpack_responses_match_json(
+            fields in proptest::collection::btree_map("[a-z]{1,8}", any::<i64>(), 0..8),
+            note in any::<String>(),
+            secret in ".{1,20}",
+        ) {
+            trace::set(None);
+            let response = serde_json::json!({ "fields": fields, "note": note, "api_secret": secret });
+            let json = parse_response(&encode_response(&response, wire::Encoding::Json));
+            let packed = encode_response(&response, wire::Encoding::MessagePack);
+            let msgpack: serde_json::Value = wire::Encoding::MessagePack.decode(&packed).unwrap();
+            prop_assert_eq!(&msgpack["api_secret"], redact::REDACTED);
+            prop_assert_eq!(msgpack, json);
+        }
+    }
+
+    #[test]
+    fn encoding_falls_back_to_json() {
+        let offer = |names: &[&str]| {
+            wire::Encoding::negotiate(&names.iter().map(|n| n.to_string()).collect::<Vec<_>>())
+        };
+        assert_eq!(
+            offer(&["cbor", "msgpack", "json"]),
+            wire::Encoding::MessagePack
+        );
+        assert_eq!(offer(&["json", "msgpack"]), wire::Encoding::Json);
+        assert_eq!(offer(&["cbor"]), wire::Encoding::Json);
+        assert_eq!(offer(&[]), wire::Encoding::Json);
+    }
+
+    /// State trading through `transport`, with the checks that need
+    /// account data turned off
+    fn mock_state(transport: impl http::HttpTransport + 'static) -> BrokerState {
+        let client = AlpacaClient::with_transport(
+            "key".to_string(),
+            "secret".to_string(),
+            true,
+            ClientOptions::default(),
+            transport,
+        );
+        let mut state = BrokerState::new();
+        state.client = Some(Arc::new(client));
+        state.pdt_mode = CheckMode::Off;
+        state
+    }
+
+    /// Runs baskets against a mock Alpaca whose order endpoint takes a
+    /// while to answer, and reports the most orders it saw at once
+    struct BasketHarness {
+        state: BrokerState,
+        most_in_flight: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl BasketHarness {
+        fn new(concurrency: usize) -> Self {
+            use http::HttpTransport;
+            use std::sync::atomic::{AtomicUsize, Ordering};
+            let mock = mock::MockTransport::new();
+            mock.on_fixture(http::HttpMethod::Post, "/v2/orders", 200, "order_new");
+            let in_flight = Arc::new(AtomicUsize::new(0));
+            let most_in_flight = Arc::new(AtomicUsize::new(0));
+            let (now, most) = (in_flight.clone(), most_in_flight.clone());
+            let transport = move |request: http::HttpRequest| {
+                if request.path() != "/v2/orders" {
+                    return mock.send(request);
+                }
+                let sending = now.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(sending, Ordering::SeqCst);
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                now.fetch_sub(1, Ordering::SeqCst);
+                mock.send(request)
+            };
+            let mut state = mock_state(transport);
+            state.asset_mode = CheckMode::Off;
+            state.basket_concurrency = concurrency;
+            Self {
+                state,
+                most_in_flight,
+            }
+        }
+
+        fn submit(&mut self, symbols: &[&str], stop_on_reject: bool) -> Vec<serde_json::Value> {
+            let requests = symbols
+                .iter()
+                .map(|symbol| mock::order_request(symbol).limit(10.0).build())
+                .collect();
+            let response = self
+                .state
+                .submit_basket(requests, CheckMode::Off, stop_on_reject)
+                .unwrap();
+            response["results"].as_array().unwrap().clone()
+        }
+
+        fn most_in_flight(&self) -> usize {
+            self.most_in_flight
+                .load(std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn basket_orders_are_sent_concurrently_up_to_the_limit() {
+        let mut harness = BasketHarness::new(2);
+        let results = harness.submit(&["AAPL", "MSFT", "NVDA", "AMZN", "TSLA"], false);
+
+        assert_eq!(harness.most_in_flight(), 2);
+        assert_eq!(results.len(), 5);
+        for (index, result) in results.iter().enumerate() {
+            assert_eq!(result["index"], index);
+            assert_eq!(result["success"], true, "{}", result);
+        }
+        assert!(harness.state.in_flight.is_empty());
+    }
+
+    #[test]
+    fn basket_orders_go_one_at_a_time_when_they_share_a_symbol_or_stop_on_reject() {
+        let mut harness = BasketHarness::new(4);
+        harness.submit(&["AAPL", "aapl", "AAPL"], false);
+        assert_eq!(harness.most_in_flight(), 1);
+
+        let mut harness = BasketHarness::new(4);
+        harness.submit(&["AAPL", "MSFT", "NVDA"], true);
+        assert_eq!(harness.most_in_flight(), 1);
+    }
+
+    #[test]
+    fn orders_in_flight_count_against_the_daily_order_limit() {
+        let mut harness = BasketHarness::new(4);
+        harness.state.risk_limits.max_daily_orders = Some(2);
+        let results = harness.submit(&["AAPL", "MSFT", "NVDA"], false);
+
+        assert_eq!(harness.most_in_flight(), 2);
+        let succeeded: Vec<bool> = results
+            .iter()
+            .map(|r| r["success"].as_bool().unwrap())
+            .collect();
+        assert_eq!(succeeded, [true, true, false]);
+        assert_eq!(harness.state.daily_orders.get(), 2);
+    }
+
+    #[test]
+    fn enforced_asset_checks_block_on_the_asset_record_alone() {
+        use http::HttpMethod;
+        let mock = mock::MockTransport::new();
+        mock.on(
+            HttpMethod::Get,
+            "/v2/assets/aapl",
+            mock::response(
+                200,
+                r#"{"symbol":"AAPL","status":"active","tradable":true}"#,
+            ),
+        )
+        .on(
+            HttpMethod::Get,
+            "/v2/assets/xyz",
+            mock::response(
+                200,
+                r#"{"symbol":"XYZ","status":"inactive","tradable":false}"#,
+            ),
+        )
+        .on_fixture(HttpMethod::Post, "/v2/orders", 200, "order_new");
+        let mut state = mock_state(mock.clone());
+        assert_eq!(state.asset_mode, CheckMode::Enforce);
+
+        let delisted = state.place_order(&mock::order_request("xyz").limit(5.0).build());
+        assert_eq!(delisted.status, OrderStatus::Rejected);
+        let rejection = &delisted.extensions.as_ref().unwrap()["rejection"];
+        assert_eq!(rejection["code"], "asset_not_tradable");
+        assert_eq!(rejection["details"]["state"], "inactive");
+
+        // No clock or snapshot on the submit path, so a one-sided quote
+        // cannot hold up a tradable symbol
+        let sent = state.place_order(&mock::order_request("aapl").limit(180.0).build());
+        assert_eq!(sent.status, OrderStatus::Submitted);
+        assert!(mock.requests_to(HttpMethod::Get, "/v2/clock").is_empty());
+        assert!(mock
+            .requests_to(HttpMethod::Get, "/v2/stocks/snapshots")
+            .is_empty());
+    }
+
+    #[test]
+    fn pdt_refresh_loads_only_the_day_trade_window_until_full_history_is_needed() {
+        use http::HttpMethod;
+        let mock = mock::MockTransport::new();
+        mock.on(
+            HttpMethod::Get,
+            "/v2/account/activities",
+            mock::response(200, "[]"),
+        );
+        let mut state = mock_state(mock.clone());
+        let start = pdt::window_start(market_time::eastern_today());
+
+        state.refresh_executions_from(Some(start)).unwrap();
+        state.refresh_executions_from(Some(start)).unwrap();
+        state.refresh_executions().unwrap();
+
+        let urls: Vec<String> = mock
+            .requests_to(HttpMethod::Get, "/v2/account/activities")
+            .into_iter()
+            .map(|r| r.url)
+            .collect();
+        assert_eq!(urls.len(), 4, "{:?}", urls);
+        for url in &urls[..2] {
+            assert!(url.contains("after=") && !url.contains("until="), "{}", url);
+        }
+        // The full history is backfilled up to the window, then topped up
+        assert!(urls[2].contains("until=") && !urls[2].contains("after="));
+        assert!(urls[3].contains("after="));
+        assert_eq!(state.executions.floor, None);
+    }
+
+    #[test]
+    fn raw_writes_are_refused_while_the_kill_switch_is_tripped() {
+        let mut state = BrokerState::new();
+        assert!(state.raw_write_rejection(true).is_none());
+
+        state.kill_switch.trip("manual stop", "manual");
+        let finding = state.raw_write_rejection(true).unwrap();
+        assert_eq!(finding.code, "trading_halted");
+
+        state.kill_switch.reset();
+        assert!(state.raw_write_rejection(true).is_none());
+    }
+
+    #[test]
+    fn released_queued_orders_pass_the_pretrade_checks_again() {
+        use http::HttpMethod;
+        let mock = mock::MockTransport::new();
+        mock.on(
+            HttpMethod::Get,
+            "/v2/clock",
+            mock::response(
+                200,
+                r#"{"timestamp":"2024-01-02T15:00:00Z","is_open":true,"next_open":"2024-01-03T14:30:00Z","next_close":"2024-01-02T21:00:00Z"}"#,
+            ),
+        )
+        .on(
+            HttpMethod::Get,
+            "/v2/assets/aapl",
+            mock::response(200, r#"{"symbol":"AAPL","status":"active","tradable":true}"#),
+        )
+        .on(
+            HttpMethod::Get,
+            "/v2/assets/xyz",
+            mock::response(200, r#"{"symbol":"XYZ","status":"inactive","tradable":false}"#),
+        )
+        .on_fixture(HttpMethod::Post, "/v2/orders", 200, "order_new");
+        let mut state = mock_state(mock.clone());
+        let closed: alpaca::MarketClock = serde_json::from_value(serde_json::json!({
+            "timestamp": "2024-01-02T02:00:00Z",
+            "is_open": false,
+            "next_open": "2024-01-02T14:30:00Z",
+            "next_close": "2024-01-02T21:00:00Z",
+        }))
+        .unwrap();
+        // Delisted overnight
+        let delisted = state
+            .order_queue
+            .push(mock::order_request("xyz").limit(5.0).build(), &closed)
+            .queue_id
+            .clone();
+        state
+            .order_queue
+            .push(mock::order_request("aapl").limit(180.0).build(), &closed);
+
+        let (released, errors) = state.release_queued_orders();
+        assert_eq!(released.len(), 1);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["queue_id"], delisted.as_str());
+        assert_eq!(errors[0]["rejection"]["code"], "asset_not_tradable");
+        assert_eq!(mock.requests_to(HttpMethod::Post, "/v2/orders").len(), 1);
+        assert!(state.order_queue.is_empty());
     }
 }
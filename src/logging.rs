@@ -0,0 +1,146 @@
+//! Structured logging
+//!
+//! Log lines are emitted as single-line JSON objects. With the `host-log`
+//! feature they are handed to the host's `host_log(level, ptr, len)` import;
+//! otherwise (or when the host does not provide it) they go to stderr.
+
+use crate::redact;
+use crate::trace;
+use serde_json::{Map, Value};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[cfg(feature = "host-log")]
+extern "C" {
+    fn host_log(level: i32, ptr: i32, len: i32);
+}
+
+const COMPONENT_PREFIX: &str = "broker-alpaca";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Trace = 0,
+    Debug = 1,
+    Info = 2,
+    Warn = 3,
+    Error = 4,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Level> {
+        match value.to_ascii_lowercase().as_str() {
+            "trace" => Some(Level::Trace),
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" | "warning" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+
+    fn from_u8(value: u8) -> Level {
+        match value {
+            0 => Level::Trace,
+            1 => Level::Debug,
+            2 => Level::Info,
+            3 => Level::Warn,
+            _ => Level::Error,
+        }
+    }
+}
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Set the minimum level that will be emitted
+pub fn set_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn level() -> Level {
+    Level::from_u8(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+pub fn enabled(level: Level) -> bool {
+    level >= self::level()
+}
+
+/// A log line under construction
+pub struct Record {
+    level: Level,
+    fields: Map<String, Value>,
+}
+
+impl Record {
+    pub fn new(level: Level, component: &str, message: impl Into<String>) -> Self {
+        let mut fields = Map::new();
+        fields.insert("level".to_string(), Value::from(level.as_str()));
+        fields.insert(
+            "component".to_string(),
+            Value::from(format!("{}::{}", COMPONENT_PREFIX, component)),
+        );
+        fields.insert("message".to_string(), Value::from(message.into()));
+        if let Some(trace_id) = trace::current() {
+            fields.insert("trace_id".to_string(), Value::from(trace_id));
+        }
+        Self { level, fields }
+    }
+
+    pub fn field(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.fields.insert(key.to_string(), value.into());
+        self
+    }
+
+    pub fn field_opt<V: Into<Value>>(self, key: &str, value: Option<V>) -> Self {
+        match value {
+            Some(v) => self.field(key, v),
+            None => self,
+        }
+    }
+
+    pub fn emit(mut self) {
+        if !enabled(self.level) {
+            return;
+        }
+        self.fields.insert(
+            "ts".to_string(),
+            Value::from(chrono::Utc::now().to_rfc3339()),
+        );
+        let line = redact::scrub(&Value::Object(self.fields).to_string());
+        write_line(self.level, &line);
+    }
+}
+
+#[cfg(feature = "host-log")]
+fn write_line(level: Level, line: &str) {
+    unsafe { host_log(level as i32, line.as_ptr() as i32, line.len() as i32) }
+}
+
+#[cfg(not(feature = "host-log"))]
+fn write_line(_level: Level, line: &str) {
+    eprintln!("{}", line);
+}
+
+pub fn debug(component: &str, message: impl Into<String>) -> Record {
+    Record::new(Level::Debug, component, message)
+}
+
+pub fn info(component: &str, message: impl Into<String>) -> Record {
+    Record::new(Level::Info, component, message)
+}
+
+pub fn warn(component: &str, message: impl Into<String>) -> Record {
+    Record::new(Level::Warn, component, message)
+}
+
+pub fn error(component: &str, message: impl Into<String>) -> Record {
+    Record::new(Level::Error, component, message)
+}
@@ -0,0 +1,289 @@
+//! Daily loss limit
+//!
+//! `risk_limits.max_daily_loss` caps how much the account may lose in one
+//! US Eastern trading day, counting realized PnL from today's closing
+//! fills plus the unrealized intraday PnL of open positions at their
+//! current marks. Both are measured from the previous close: a lot opened
+//! before today realizes only its move since then, the way Alpaca's
+//! `unrealized_intraday_pl` measures it while the position is open. A lot
+//! with no previous close available (crypto, options, or a failed
+//! snapshot) counts from its entry. It is evaluated when an order would
+//! increase risk, that is open or grow a position; orders that only
+//! reduce a position always pass.
+//!
+//! A breach latches: every risk-increasing order is rejected with
+//! `daily_loss_limit` and an `alert` event is raised, until the host calls
+//! `reset_daily_loss_limit`. The reset makes the loss at the time of the
+//! breach the new baseline for the rest of that day, so trading resumes
+//! but a further `max_daily_loss` of loss trips the limit again.
+
+use crate::decimal::{self, Decimal};
+use crate::market_data::Snapshot;
+use crate::market_time;
+use crate::pnl::RealizedTrade;
+use crate::pretrade::Finding;
+use chrono::{DateTime, NaiveDate, Utc};
+use models::order::{OrderRequest, OrderSide};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+/// Intraday PnL: today's realized trades plus open positions' moves today
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IntradayPnl {
+    pub realized: Decimal,
+    pub unrealized: Decimal,
+}
+
+impl IntradayPnl {
+    /// `prev_closes` are by upper-case symbol, see [`carried_symbols`]
+    pub fn new(
+        trades: &[RealizedTrade],
+        unrealized: Decimal,
+        today: NaiveDate,
+        prev_closes: &HashMap<String, Decimal>,
+    ) -> Self {
+        let realized = trades
+            .iter()
+            .filter(|t| market_time::eastern_date(t.closed_at) == today)
+            .map(|t| {
+                if market_time::eastern_date(t.opened_at) >= today {
+                    return t.pnl;
+                }
+                match prev_closes.get(&t.symbol.to_ascii_uppercase()) {
+                    Some(prev_close) => (t.close_price - prev_close) * t.qty * t.direction(),
+                    None => t.pnl,
+                }
+            })
+            .sum();
+        Self {
+            realized,
+            unrealized,
+        }
+    }
+
+    pub fn total(&self) -> Decimal {
+        self.realized + self.unrealized
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Breach {
+    /// Intraday PnL when the limit tripped, and its parts, as decimal
+    /// strings
+    pub pnl: String,
+    pub realized: String,
+    pub unrealized: String,
+    pub limit: String,
+    pub trading_day: NaiveDate,
+    pub breached_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DailyLossLimit {
+    breach: Option<Breach>,
+    /// PnL that losses are measured from after a reset, for that day only
+    baseline: Option<(NaiveDate, Decimal)>,
+}
+
+impl DailyLossLimit {
+    pub fn breach(&self) -> Option<&Breach> {
+        self.breach.as_ref()
+    }
+
+    /// Rejection for a risk-increasing order while the limit is tripped
+    pub fn rejection(&self) -> Option<Finding> {
+        self.breach.as_ref().map(|breach| {
+            Finding::new(
+                "daily_loss_limit",
+                format!(
+                    "Daily loss limit of {} reached (intraday PnL {}); call reset_daily_loss_limit to resume",
+                    breach.limit, breach.pnl
+                ),
+                serde_json::to_value(breach).unwrap_or_default(),
+            )
+        })
+    }
+
+    /// Latch a breach when `pnl` is at or past the limit; returns the new
+    /// breach, or `None` when within the limit or already tripped
+    pub fn observe(&mut self, pnl: &IntradayPnl, max_loss: Decimal) -> Option<&Breach> {
+        if self.breach.is_some() {
+            return None;
+        }
+        let today = market_time::eastern_today();
+        let baseline = match self.baseline {
+            Some((day, baseline)) if day == today => baseline.min(Decimal::ZERO),
+            _ => Decimal::ZERO,
+        };
+        if baseline - pnl.total() < max_loss {
+            return None;
+        }
+        self.breach = Some(Breach {
+            pnl: decimal::to_wire(pnl.total()),
+            realized: decimal::to_wire(pnl.realized),
+            unrealized: decimal::to_wire(pnl.unrealized),
+            limit: decimal::to_wire(max_loss),
+            trading_day: today,
+            breached_at: Utc::now(),
+        });
+        self.breach.as_ref()
+    }
+
+    /// Clear a breach; the loss it recorded becomes today's baseline.
+    /// Returns the cleared breach.
+    pub fn reset(&mut self) -> Option<Breach> {
+        let breach = self.breach.take()?;
+        if breach.trading_day == market_time::eastern_today() {
+            self.baseline = decimal::parse(&breach.pnl).map(|pnl| (breach.trading_day, pnl));
+        }
+        Some(breach)
+    }
+}
+
+/// Symbols of lots opened before `today` and closed today, whose previous
+/// close [`IntradayPnl::new`] measures from
+pub fn carried_symbols(trades: &[RealizedTrade], today: NaiveDate) -> Vec<String> {
+    trades
+        .iter()
+        .filter(|t| market_time::eastern_date(t.closed_at) == today)
+        .filter(|t| market_time::eastern_date(t.opened_at) < today)
+        .map(|t| t.symbol.to_ascii_uppercase())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// The close of the last session before `today`: the previous daily bar
+/// once today's bar has started, the latest daily bar before that
+pub fn previous_close(snapshot: &Snapshot, today: NaiveDate) -> Option<Decimal> {
+    let daily = snapshot.daily_bar.as_ref()?;
+    let bar = if market_time::eastern_date(daily.timestamp) >= today {
+        snapshot.prev_daily_bar.as_ref()?
+    } else {
+        daily
+    };
+    Some(bar.close).filter(|c| *c > Decimal::ZERO)
+}
+
+/// Whether an order opens or grows a position, given the quantity held
+/// (negative when short). A sell larger than the long position, or a buy
+/// larger than the short one, flips it and counts as increasing.
+pub fn increases_risk(order: &OrderRequest, held: Decimal) -> bool {
+    let qty = decimal::from_f64(order.quantity).unwrap_or_default();
+    match order.side {
+        OrderSide::Buy => held >= Decimal::ZERO || qty > -held,
+        OrderSide::Sell => held <= Decimal::ZERO || qty > held,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+
+    fn pnl(total: i64) -> IntradayPnl {
+        IntradayPnl {
+            realized: Decimal::from(total),
+            unrealized: Decimal::ZERO,
+        }
+    }
+
+    #[test]
+    fn lots_carried_overnight_realize_only_todays_move() {
+        use crate::pnl::LotMethod;
+        let today: NaiveDate = "2024-03-04".parse().unwrap();
+        let closed_at: DateTime<Utc> = "2024-03-04T15:00:00Z".parse().unwrap();
+        let trade = |symbol: &str, opened_at: &str, open: i64, close: i64, short: bool| {
+            let qty = Decimal::from(10);
+            let direction = if short { -Decimal::ONE } else { Decimal::ONE };
+            RealizedTrade {
+                symbol: symbol.to_string(),
+                persona_id: "default".to_string(),
+                order_id: "o".to_string(),
+                execution_id: "e".to_string(),
+                lot_id: "l".to_string(),
+                lot_method: LotMethod::Fifo,
+                qty,
+                open_price: Decimal::from(open),
+                close_price: Decimal::from(close),
+                pnl: (Decimal::from(close - open)) * qty * direction,
+                opened_at: opened_at.parse().unwrap(),
+                closed_at,
+                short,
+            }
+        };
+        let trades = [
+            // Bought at 150 last month, closed at 100 today after closing
+            // at 105 yesterday: 50 of the loss is today's
+            trade("aapl", "2024-02-01T15:00:00Z", 150, 100, false),
+            // A short carried from Friday, covered at 60 after a 62 close
+            trade("TSLA", "2024-03-01T15:00:00Z", 50, 60, true),
+            // Opened and closed today: all of it counts
+            trade("MSFT", "2024-03-04T14:40:00Z", 400, 398, false),
+            // No previous close known: counts from entry
+            trade("BTC/USD", "2024-03-01T15:00:00Z", 100, 90, false),
+        ];
+        assert_eq!(carried_symbols(&trades, today), ["AAPL", "BTC/USD", "TSLA"]);
+
+        let prev_closes = HashMap::from([
+            ("AAPL".to_string(), Decimal::from(105)),
+            ("TSLA".to_string(), Decimal::from(62)),
+        ]);
+        let pnl = IntradayPnl::new(&trades, Decimal::ZERO, today, &prev_closes);
+        // -50 + 20 - 20 - 100
+        assert_eq!(pnl.realized, Decimal::from(-150));
+
+        let snapshot: Snapshot = serde_json::from_value(serde_json::json!({
+            "dailyBar": { "t": "2024-03-04T05:00:00Z", "o": 104, "h": 106, "l": 99, "c": 100, "v": 1 },
+            "prevDailyBar": { "t": "2024-03-01T05:00:00Z", "o": 103, "h": 106, "l": 102, "c": 105, "v": 1 },
+        }))
+        .unwrap();
+        assert_eq!(previous_close(&snapshot, today), Some(Decimal::from(105)));
+        // Before today's first bar the latest daily bar is the previous close
+        let next_day: NaiveDate = "2024-03-05".parse().unwrap();
+        assert_eq!(
+            previous_close(&snapshot, next_day),
+            Some(Decimal::from(100))
+        );
+    }
+
+    #[test]
+    fn breach_latches_until_reset_and_reset_moves_the_baseline() {
+        let max = Decimal::from(500);
+        let mut limit = DailyLossLimit::default();
+        assert!(limit.observe(&pnl(-499), max).is_none());
+        assert!(limit.observe(&pnl(-500), max).is_some());
+        assert_eq!(limit.rejection().unwrap().code, "daily_loss_limit");
+        // Recovering does not clear it
+        assert!(limit.observe(&pnl(100), max).is_none());
+        assert!(limit.rejection().is_some());
+
+        assert_eq!(limit.reset().unwrap().pnl, "-500");
+        assert!(limit.rejection().is_none());
+        assert!(limit.observe(&pnl(-900), max).is_none());
+        assert!(limit.observe(&pnl(-1000), max).is_some());
+
+        assert!(increases_risk(
+            &order_request("AAPL").side(OrderSide::Buy).qty(10.0).build(),
+            Decimal::ZERO
+        ));
+        assert!(!increases_risk(
+            &order_request("AAPL")
+                .side(OrderSide::Sell)
+                .qty(10.0)
+                .build(),
+            Decimal::from(10)
+        ));
+        assert!(increases_risk(
+            &order_request("AAPL")
+                .side(OrderSide::Sell)
+                .qty(11.0)
+                .build(),
+            Decimal::from(10)
+        ));
+        assert!(!increases_risk(
+            &order_request("AAPL").side(OrderSide::Buy).qty(5.0).build(),
+            Decimal::from(-10)
+        ));
+    }
+}
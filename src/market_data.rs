@@ -0,0 +1,101 @@
+//! Market data models
+//!
+//! Latest trade, quote, and bars from Alpaca's market data API
+//! (`data.alpaca.markets`), used by the local order emulation features.
+
+use crate::decimal::Decimal;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Trade {
+    #[serde(rename = "t")]
+    pub timestamp: DateTime<Utc>,
+    #[serde(rename = "p")]
+    pub price: Decimal,
+    #[serde(rename = "s", default)]
+    pub size: Decimal,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Quote {
+    #[serde(rename = "t")]
+    pub timestamp: DateTime<Utc>,
+    #[serde(rename = "bp")]
+    pub bid_price: Decimal,
+    #[serde(rename = "bs", default)]
+    pub bid_size: Decimal,
+    #[serde(rename = "ap")]
+    pub ask_price: Decimal,
+    #[serde(rename = "as", default)]
+    pub ask_size: Decimal,
+}
+
+impl Quote {
+    /// Both sides are present (Alpaca reports an empty side as zero)
+    pub fn is_two_sided(&self) -> bool {
+        self.bid_price > Decimal::ZERO && self.ask_price > Decimal::ZERO
+    }
+
+    pub fn mid(&self) -> Option<Decimal> {
+        self.is_two_sided()
+            .then(|| (self.bid_price + self.ask_price) / Decimal::TWO)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Bar {
+    #[serde(rename = "t")]
+    pub timestamp: DateTime<Utc>,
+    #[serde(rename = "o")]
+    pub open: Decimal,
+    #[serde(rename = "h")]
+    pub high: Decimal,
+    #[serde(rename = "l")]
+    pub low: Decimal,
+    #[serde(rename = "c")]
+    pub close: Decimal,
+    #[serde(rename = "v")]
+    pub volume: Decimal,
+    #[serde(rename = "vw", default)]
+    pub vwap: Option<Decimal>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    #[serde(default)]
+    pub latest_trade: Option<Trade>,
+    #[serde(default)]
+    pub latest_quote: Option<Quote>,
+    #[serde(default)]
+    pub minute_bar: Option<Bar>,
+    #[serde(default)]
+    pub daily_bar: Option<Bar>,
+    #[serde(default)]
+    pub prev_daily_bar: Option<Bar>,
+}
+
+/// Which snapshot price a feature watches
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceSource {
+    #[default]
+    Last,
+    Mid,
+    Bid,
+    Ask,
+}
+
+impl Snapshot {
+    pub fn price(&self, source: PriceSource) -> Option<Decimal> {
+        let quote = self.latest_quote.as_ref();
+        match source {
+            PriceSource::Last => self.latest_trade.as_ref().map(|t| t.price),
+            PriceSource::Mid => quote.and_then(Quote::mid),
+            PriceSource::Bid => quote.map(|q| q.bid_price),
+            PriceSource::Ask => quote.map(|q| q.ask_price),
+        }
+        .filter(|p| *p > Decimal::ZERO)
+    }
+}
@@ -0,0 +1,153 @@
+//! Market-hours gating
+//!
+//! By default a DAY order placed while the market is closed is sent to
+//! Alpaca, which holds it until the open. `market_closed_policy` can
+//! instead hold it locally until the clock reports the market open, or
+//! convert it to an at-the-open (OPG) or extended-hours order.
+
+use crate::alpaca::{self, MarketClock};
+use chrono::{DateTime, Utc};
+use models::order::{Order, OrderRequest, OrderStatus, OrderType};
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClosedMarketPolicy {
+    /// Send as-is and let Alpaca hold the order
+    #[default]
+    Submit,
+    /// Hold locally and submit at the next open
+    Queue,
+    /// Convert to `opg` (market-on-open / limit-on-open)
+    Opg,
+    /// Mark limit orders `extended_hours` so they can fill pre-market
+    ExtendedHours,
+}
+
+impl ClosedMarketPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "submit" => Some(Self::Submit),
+            "queue" => Some(Self::Queue),
+            "opg" => Some(Self::Opg),
+            "extended_hours" => Some(Self::ExtendedHours),
+            _ => None,
+        }
+    }
+}
+
+/// What to do with an order while the market is closed
+pub enum Gate {
+    Submit(OrderRequest),
+    Queue,
+}
+
+/// Whether the policy applies: only plain DAY orders that are not already
+/// extended-hours are gated
+pub fn is_gated(order: &OrderRequest) -> bool {
+    alpaca::requested_time_in_force(order) == Ok("day") && !alpaca::requested_extended_hours(order)
+}
+
+/// Decide how a gated order is handled while the market is closed.
+/// Orders the conversion cannot express are queued instead.
+pub fn decide(policy: ClosedMarketPolicy, order: &OrderRequest) -> Gate {
+    let mut converted = order.clone();
+    let ext = converted.extensions.get_or_insert_with(HashMap::new);
+    match policy {
+        ClosedMarketPolicy::Submit => Gate::Submit(order.clone()),
+        ClosedMarketPolicy::Queue => Gate::Queue,
+        ClosedMarketPolicy::Opg => match order.order_type {
+            OrderType::Market | OrderType::Limit => {
+                ext.insert("time_in_force".to_string(), "opg".into());
+                Gate::Submit(converted)
+            }
+            _ => Gate::Queue,
+        },
+        ClosedMarketPolicy::ExtendedHours => match order.order_type {
+            OrderType::Limit => {
+                ext.insert("extended_hours".to_string(), true.into());
+                Gate::Submit(converted)
+            }
+            _ => Gate::Queue,
+        },
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct QueuedOrder {
+    pub queue_id: String,
+    pub request: OrderRequest,
+    pub queued_at: DateTime<Utc>,
+    /// The next open reported by the clock when the order was queued
+    pub release_at: DateTime<Utc>,
+}
+
+impl QueuedOrder {
+    /// Host-facing view of the held order
+    pub fn to_order(&self) -> Order {
+        let mut extensions = HashMap::new();
+        extensions.insert("queued".to_string(), true.into());
+        extensions.insert(
+            "release_at".to_string(),
+            self.release_at.to_rfc3339().into(),
+        );
+        extensions.insert("alpaca_status".to_string(), "queued".into());
+        Order {
+            id: self.queue_id.clone(),
+            request: self.request.clone(),
+            status: OrderStatus::Submitted,
+            created_at: self.queued_at,
+            updated_at: self.queued_at,
+            average_filled_price: None,
+            filled_quantity: 0.0,
+            extensions: Some(extensions),
+            persona_id: self.request.persona_id.clone(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct OrderQueue {
+    orders: Vec<QueuedOrder>,
+    next_id: u64,
+}
+
+impl OrderQueue {
+    pub fn push(&mut self, request: OrderRequest, clock: &MarketClock) -> &QueuedOrder {
+        self.next_id += 1;
+        self.orders.push(QueuedOrder {
+            queue_id: format!("queued_{}_{}", Utc::now().timestamp_millis(), self.next_id),
+            request,
+            queued_at: Utc::now(),
+            release_at: clock.next_open,
+        });
+        self.orders.last().expect("just pushed")
+    }
+
+    pub fn list(&self) -> &[QueuedOrder] {
+        &self.orders
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    pub fn remove(&mut self, queue_id: &str) -> Option<QueuedOrder> {
+        let index = self.orders.iter().position(|q| q.queue_id == queue_id)?;
+        Some(self.orders.remove(index))
+    }
+
+    pub fn rename_symbol(&mut self, old: &str, new: &str) -> usize {
+        self.orders
+            .iter_mut()
+            .map(|q| crate::orders::rename_symbol(&mut q.request, old, new))
+            .filter(|renamed| *renamed)
+            .count()
+    }
+
+    /// Take every queued order, oldest first
+    pub fn drain(&mut self) -> Vec<QueuedOrder> {
+        std::mem::take(&mut self.orders)
+    }
+}
@@ -0,0 +1,64 @@
+//! US Eastern market time
+//!
+//! US equity sessions and trading days are defined in America/New_York.
+//! No tz database is available inside the plugin, so the offset is derived
+//! from the US DST rules (second Sunday of March to first Sunday of
+//! November, switching at 02:00 local time).
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc, Weekday};
+
+/// nth (1-based) occurrence of `weekday` in the month
+fn nth_weekday(year: i32, month: u32, weekday: Weekday, n: u32) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, n as u8)
+        .expect("valid nth weekday of month")
+}
+
+fn is_dst(utc: DateTime<Utc>) -> bool {
+    let year = utc.year();
+    // 02:00 EST = 07:00 UTC; 02:00 EDT = 06:00 UTC
+    let start = nth_weekday(year, 3, Weekday::Sun, 2)
+        .and_hms_opt(7, 0, 0)
+        .expect("valid time")
+        .and_utc();
+    let end = nth_weekday(year, 11, Weekday::Sun, 1)
+        .and_hms_opt(6, 0, 0)
+        .expect("valid time")
+        .and_utc();
+    utc >= start && utc < end
+}
+
+/// UTC offset of US Eastern time at the given instant
+pub fn eastern_offset(utc: DateTime<Utc>) -> Duration {
+    if is_dst(utc) {
+        Duration::hours(-4)
+    } else {
+        Duration::hours(-5)
+    }
+}
+
+/// Wall-clock Eastern time for a UTC instant
+pub fn to_eastern(utc: DateTime<Utc>) -> NaiveDateTime {
+    utc.naive_utc() + eastern_offset(utc)
+}
+
+/// Convert an Eastern wall-clock time to UTC
+pub fn from_eastern(local: NaiveDateTime) -> DateTime<Utc> {
+    // Guess with standard time, then correct if that instant is in DST
+    let guess = (local + Duration::hours(5)).and_utc();
+    (local - eastern_offset(guess)).and_utc()
+}
+
+/// Current trading date in New York
+pub fn eastern_today() -> NaiveDate {
+    to_eastern(Utc::now()).date()
+}
+
+/// Eastern trading date of a UTC instant
+pub fn eastern_date(utc: DateTime<Utc>) -> NaiveDate {
+    to_eastern(utc).date()
+}
+
+/// UTC instant for an Eastern date and time
+pub fn eastern_at(date: NaiveDate, time: NaiveTime) -> DateTime<Utc> {
+    from_eastern(date.and_time(time))
+}
@@ -0,0 +1,146 @@
+//! Position marks
+//!
+//! Alpaca's `current_price` on positions stops moving outside market hours
+//! and can lag the tape. With `position_marks` set, positions are re-marked
+//! from a market data snapshot instead, and `current_price`,
+//! `unrealized_pnl`, and `unrealized_pnl_percent` are recomputed from the
+//! new price. Each position's mark source, price, and time are reported in
+//! `position_marks`. A position the snapshot has no price for keeps
+//! Alpaca's mark and says so.
+
+use crate::decimal::{self, Decimal};
+use crate::market_data::{PriceSource, Snapshot};
+use chrono::{DateTime, Utc};
+use models::portfolio::Position;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkSource {
+    /// Alpaca's own `current_price`
+    #[default]
+    Alpaca,
+    LastTrade,
+    /// Midpoint of the latest quote
+    Midpoint,
+    /// The previous session's closing price
+    PrevClose,
+}
+
+impl MarkSource {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "alpaca" => Some(Self::Alpaca),
+            "last_trade" => Some(Self::LastTrade),
+            "midpoint" => Some(Self::Midpoint),
+            "prev_close" => Some(Self::PrevClose),
+            _ => None,
+        }
+    }
+
+    /// Whether positions need a snapshot to be marked
+    pub fn uses_snapshots(self) -> bool {
+        self != Self::Alpaca
+    }
+
+    /// The price and its time from `snapshot`
+    pub fn price(self, snapshot: &Snapshot) -> Option<(Decimal, Option<DateTime<Utc>>)> {
+        match self {
+            Self::Alpaca => None,
+            Self::LastTrade => snapshot
+                .price(PriceSource::Last)
+                .zip(snapshot.latest_trade.as_ref().map(|t| Some(t.timestamp))),
+            Self::Midpoint => snapshot
+                .price(PriceSource::Mid)
+                .zip(snapshot.latest_quote.as_ref().map(|q| Some(q.timestamp))),
+            Self::PrevClose => snapshot
+                .prev_daily_bar
+                .as_ref()
+                .filter(|b| b.close > Decimal::ZERO)
+                .map(|b| (b.close, Some(b.timestamp))),
+        }
+    }
+}
+
+/// How one position was marked
+#[derive(Clone, Debug, Serialize)]
+pub struct Mark {
+    pub source: MarkSource,
+    pub price: String,
+    /// Time of the trade, quote, or bar the price came from; `None` for
+    /// Alpaca's marks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub at: Option<DateTime<Utc>>,
+}
+
+impl Mark {
+    /// Alpaca's own mark, for positions left as fetched
+    pub fn alpaca(position: &Position) -> Self {
+        Self {
+            source: MarkSource::Alpaca,
+            price: decimal::from_f64(position.current_price)
+                .map(decimal::to_wire)
+                .unwrap_or_default(),
+            at: None,
+        }
+    }
+}
+
+/// Re-mark `position` at `price`, recomputing its unrealized PnL the way
+/// Alpaca does: against the cost basis, with shorts gaining as the price
+/// falls
+pub fn remark(position: &mut Position, price: Decimal) {
+    let qty = decimal::from_f64(position.quantity).unwrap_or_default();
+    let entry = decimal::from_f64(position.average_price).unwrap_or_default();
+    let pnl = (price - entry) * qty;
+    let cost = (entry * qty).abs();
+    position.current_price = decimal::to_f64(price);
+    position.unrealized_pnl = decimal::to_f64(pnl);
+    if !cost.is_zero() {
+        position.unrealized_pnl_percent =
+            decimal::to_f64((pnl / cost * Decimal::ONE_HUNDRED).round_dp(6));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shorts_and_longs_are_remarked_against_their_cost_basis() {
+        let snapshot: Snapshot = serde_json::from_value(serde_json::json!({
+            "latestTrade": { "t": "2024-03-01T21:59:00Z", "p": 190.0 },
+            "latestQuote": { "t": "2024-03-02T00:30:00Z", "bp": 191.0, "ap": 191.5 },
+            "prevDailyBar": {
+                "t": "2024-02-29T05:00:00Z",
+                "o": 180.0, "h": 182.0, "l": 179.0, "c": 181.0, "v": 1000,
+            },
+        }))
+        .unwrap();
+        let (mid, at) = MarkSource::Midpoint.price(&snapshot).unwrap();
+        assert_eq!(mid, Decimal::new(19125, 2));
+        assert_eq!(at, Some("2024-03-02T00:30:00Z".parse().unwrap()));
+        let (close, _) = MarkSource::PrevClose.price(&snapshot).unwrap();
+        assert_eq!(close, Decimal::from(181));
+
+        let mut long = Position {
+            symbol_id: "AAPL".to_string(),
+            quantity: 10.0,
+            average_price: 200.0,
+            current_price: 0.0,
+            unrealized_pnl: 0.0,
+            unrealized_pnl_percent: 0.0,
+        };
+        let mut short = Position {
+            quantity: -10.0,
+            ..long.clone()
+        };
+        remark(&mut long, Decimal::from(190));
+        remark(&mut short, Decimal::from(190));
+        assert_eq!(long.current_price, 190.0);
+        assert_eq!(long.unrealized_pnl, -100.0);
+        assert_eq!(long.unrealized_pnl_percent, -5.0);
+        assert_eq!(short.unrealized_pnl, 100.0);
+        assert_eq!(short.unrealized_pnl_percent, 5.0);
+    }
+}
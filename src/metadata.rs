@@ -0,0 +1,90 @@
+//! Free-form order metadata for signal attribution
+//!
+//! The host may attach any JSON object as `extensions.metadata` (a
+//! strategy name, a signal ID, model inputs). It is not sent to Alpaca.
+//! It stays on the order's request, is copied to `extensions.metadata` on
+//! the returned order and every later refresh and replacement of it, and
+//! rides along on each order event. Only the `tag` survives a restart,
+//! because it is encoded in the client order ID (see [`crate::tags`]).
+
+use models::order::{Order, OrderRequest};
+
+/// Serialized size limit, so metadata cannot bloat every event
+const MAX_BYTES: usize = 4096;
+
+/// The order's metadata, if it has any
+pub fn requested(request: &OrderRequest) -> Result<Option<&serde_json::Value>, String> {
+    let Some(value) = request
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.get("metadata"))
+        .filter(|v| !v.is_null())
+    else {
+        return Ok(None);
+    };
+    if !value.is_object() {
+        return Err("metadata must be an object".to_string());
+    }
+    let size = value.to_string().len();
+    if size > MAX_BYTES {
+        return Err(format!(
+            "metadata is {} bytes; the limit is {}",
+            size, MAX_BYTES
+        ));
+    }
+    Ok(Some(value))
+}
+
+/// Metadata carried by an order, from its extensions or its request
+pub fn of(order: &Order) -> Option<serde_json::Value> {
+    order
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.get("metadata"))
+        .or_else(|| {
+            order
+                .request
+                .extensions
+                .as_ref()
+                .and_then(|ext| ext.get("metadata"))
+        })
+        .filter(|v| !v.is_null())
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+
+    #[test]
+    fn metadata_must_be_a_small_object() {
+        let ok = order_request("AAPL")
+            .extension(
+                "metadata",
+                serde_json::json!({ "strategy": "momo", "signal_id": 42 }),
+            )
+            .build();
+        assert_eq!(requested(&ok).unwrap().unwrap()["signal_id"], 42);
+        assert!(requested(
+            &order_request("AAPL")
+                .extension("metadata", serde_json::json!("momo"))
+                .build()
+        )
+        .is_err());
+        let big = "x".repeat(MAX_BYTES);
+        assert!(requested(
+            &order_request("AAPL")
+                .extension("metadata", serde_json::json!({ "notes": big }))
+                .build()
+        )
+        .is_err());
+        assert!(requested(
+            &order_request("AAPL")
+                .extension("metadata", serde_json::Value::Null)
+                .build()
+        )
+        .unwrap()
+        .is_none());
+    }
+}
@@ -0,0 +1,216 @@
+//! Broker health metrics
+//!
+//! Per-endpoint request counts, error rates, retry counts and latency
+//! histograms (fed by the HTTP middleware), plus order lifecycle timings
+//! recorded by the exports. Rendered as JSON or Prometheus text by the
+//! `get_metrics` export.
+
+use crate::http::HttpRequest;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+/// Upper bounds (inclusive, milliseconds) of the latency histogram buckets.
+/// A final implicit `+Inf` bucket catches everything slower.
+pub const LATENCY_BUCKETS_MS: [u64; 8] = [50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Histogram {
+    /// Non-cumulative counts, one per bucket plus a trailing `+Inf` bucket
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum_ms: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; LATENCY_BUCKETS_MS.len() + 1],
+            count: 0,
+            sum_ms: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&mut self, value_ms: u64) {
+        let index = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&bound| value_ms <= bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.buckets[index] += 1;
+        self.count += 1;
+        self.sum_ms += value_ms;
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct EndpointStats {
+    pub requests: u64,
+    pub errors: u64,
+    pub retries: u64,
+    /// GETs answered from the response cache without reaching Alpaca
+    pub cache_hits: u64,
+    pub error_rate: f64,
+    pub status_counts: BTreeMap<u16, u64>,
+    pub latency_ms: Histogram,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct OrderStats {
+    pub count: u64,
+    pub latency_ms: Histogram,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MetricsSnapshot {
+    pub endpoints: BTreeMap<String, EndpointStats>,
+    /// Keyed by lifecycle event: `submitted`, `rejected`, `canceled`, ...
+    pub orders: BTreeMap<String, OrderStats>,
+}
+
+/// Thread-safe metrics store shared between the pipeline and the exports
+#[derive(Default)]
+pub struct MetricsRegistry {
+    data: Mutex<MetricsSnapshot>,
+}
+
+impl MetricsRegistry {
+    pub fn record_request(&self, endpoint: &str, status: u16, latency_ms: u64) {
+        let mut data = self.data.lock().unwrap_or_else(|e| e.into_inner());
+        let stats = data.endpoints.entry(endpoint.to_string()).or_default();
+        stats.requests += 1;
+        if !(200..300).contains(&status) {
+            stats.errors += 1;
+        }
+        stats.error_rate = stats.errors as f64 / stats.requests as f64;
+        *stats.status_counts.entry(status).or_default() += 1;
+        stats.latency_ms.observe(latency_ms);
+    }
+
+    pub fn record_retry(&self, endpoint: &str) {
+        let mut data = self.data.lock().unwrap_or_else(|e| e.into_inner());
+        data.endpoints
+            .entry(endpoint.to_string())
+            .or_default()
+            .retries += 1;
+    }
+
+    pub fn record_cache_hit(&self, endpoint: &str) {
+        let mut data = self.data.lock().unwrap_or_else(|e| e.into_inner());
+        data.endpoints
+            .entry(endpoint.to_string())
+            .or_default()
+            .cache_hits += 1;
+    }
+
+    pub fn record_order(&self, event: &str, latency_ms: u64) {
+        let mut data = self.data.lock().unwrap_or_else(|e| e.into_inner());
+        let stats = data.orders.entry(event.to_string()).or_default();
+        stats.count += 1;
+        stats.latency_ms.observe(latency_ms);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.data.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Render in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let snapshot = self.snapshot();
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# TYPE alpaca_http_requests_total counter");
+        for (endpoint, stats) in &snapshot.endpoints {
+            for (status, count) in &stats.status_counts {
+                let _ = writeln!(
+                    out,
+                    "alpaca_http_requests_total{{endpoint=\"{}\",status=\"{}\"}} {}",
+                    endpoint, status, count
+                );
+            }
+        }
+        let _ = writeln!(out, "# TYPE alpaca_http_errors_total counter");
+        for (endpoint, stats) in &snapshot.endpoints {
+            let _ = writeln!(
+                out,
+                "alpaca_http_errors_total{{endpoint=\"{}\"}} {}",
+                endpoint, stats.errors
+            );
+        }
+        let _ = writeln!(out, "# TYPE alpaca_http_retries_total counter");
+        for (endpoint, stats) in &snapshot.endpoints {
+            let _ = writeln!(
+                out,
+                "alpaca_http_retries_total{{endpoint=\"{}\"}} {}",
+                endpoint, stats.retries
+            );
+        }
+        let _ = writeln!(out, "# TYPE alpaca_http_cache_hits_total counter");
+        for (endpoint, stats) in &snapshot.endpoints {
+            let _ = writeln!(
+                out,
+                "alpaca_http_cache_hits_total{{endpoint=\"{}\"}} {}",
+                endpoint, stats.cache_hits
+            );
+        }
+        let _ = writeln!(out, "# TYPE alpaca_http_latency_ms histogram");
+        for (endpoint, stats) in &snapshot.endpoints {
+            write_histogram(
+                &mut out,
+                "alpaca_http_latency_ms",
+                "endpoint",
+                endpoint,
+                &stats.latency_ms,
+            );
+        }
+        let _ = writeln!(out, "# TYPE alpaca_order_latency_ms histogram");
+        for (event, stats) in &snapshot.orders {
+            write_histogram(
+                &mut out,
+                "alpaca_order_latency_ms",
+                "event",
+                event,
+                &stats.latency_ms,
+            );
+        }
+        out
+    }
+}
+
+fn write_histogram(out: &mut String, name: &str, label: &str, value: &str, h: &Histogram) {
+    let mut cumulative = 0;
+    for (i, count) in h.buckets.iter().enumerate() {
+        cumulative += count;
+        let le = LATENCY_BUCKETS_MS
+            .get(i)
+            .map(|b| b.to_string())
+            .unwrap_or_else(|| "+Inf".to_string());
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}=\"{}\",le=\"{}\"}} {}",
+            name, label, value, le, cumulative
+        );
+    }
+    let _ = writeln!(out, "{}_sum{{{}=\"{}\"}} {}", name, label, value, h.sum_ms);
+    let _ = writeln!(out, "{}_count{{{}=\"{}\"}} {}", name, label, value, h.count);
+}
+
+/// Method plus path with ID-like segments collapsed, e.g. `GET /v2/orders/{id}`
+pub fn endpoint_key(request: &HttpRequest) -> String {
+    let path = request
+        .path()
+        .split('/')
+        .map(|segment| {
+            let looks_like_id = segment.len() >= 16 && segment.chars().any(|c| c.is_ascii_digit());
+            if looks_like_id {
+                "{id}"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    format!("{} {}", request.method.as_str(), path)
+}
@@ -0,0 +1,876 @@
+//! Standard middleware for the Alpaca HTTP pipeline
+//!
+//! Each middleware handles one cross-cutting concern so it can be composed
+//! (and exercised) independently of the endpoint code in `alpaca.rs`.
+
+use crate::http::{HttpMethod, HttpRequest, HttpResponse, Middleware, Next};
+use crate::logging;
+use crate::metrics::{endpoint_key, MetricsRegistry};
+use crate::trace::ALPACA_REQUEST_ID_HEADER;
+use base64::Engine;
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Alpaca's documented REST limit per API key
+pub const DEFAULT_RATE_LIMIT_PER_MINUTE: usize = 200;
+
+// --- Auth ---
+
+/// Attaches the APCA key headers to every request, or HTTP Basic auth for
+/// the Broker API
+pub struct AuthHeaders {
+    /// Key ID and secret; replaced in place when credentials rotate
+    credentials: RwLock<(String, String)>,
+    basic: bool,
+}
+
+impl AuthHeaders {
+    pub fn new(api_key: String, api_secret: String) -> Self {
+        Self {
+            credentials: RwLock::new((api_key, api_secret)),
+            basic: false,
+        }
+    }
+
+    /// Send the key and secret as `Authorization: Basic`, as the Broker API
+    /// expects
+    pub fn basic(api_key: String, api_secret: String) -> Self {
+        Self {
+            basic: true,
+            ..Self::new(api_key, api_secret)
+        }
+    }
+
+    /// Use new credentials from the next request on
+    pub fn rotate(&self, api_key: String, api_secret: String) {
+        *self.credentials.write().unwrap_or_else(|e| e.into_inner()) = (api_key, api_secret);
+    }
+}
+
+impl Middleware for AuthHeaders {
+    fn handle(&self, mut request: HttpRequest, next: Next<'_>) -> HttpResponse {
+        let (api_key, api_secret) = self
+            .credentials
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        if self.basic {
+            let token = base64::engine::general_purpose::STANDARD
+                .encode(format!("{}:{}", api_key, api_secret));
+            request
+                .headers
+                .insert("Authorization".to_string(), format!("Basic {}", token));
+            return next.run(request);
+        }
+        request
+            .headers
+            .insert("APCA-API-KEY-ID".to_string(), api_key);
+        request
+            .headers
+            .insert("APCA-API-SECRET-KEY".to_string(), api_secret);
+        next.run(request)
+    }
+}
+
+// --- Rate limiting ---
+
+/// Sliding-window limiter that delays requests once the window is full
+pub struct RateLimit {
+    max_requests: usize,
+    window: Duration,
+    sent: Mutex<VecDeque<Instant>>,
+}
+
+impl RateLimit {
+    pub fn new(max_requests: usize, window: Duration) -> Self {
+        Self {
+            max_requests: max_requests.max(1),
+            window,
+            sent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn per_minute(max_requests: usize) -> Self {
+        Self::new(max_requests, Duration::from_secs(60))
+    }
+
+    fn prune_window(&self, sent: &mut VecDeque<Instant>, now: Instant) {
+        while let Some(&oldest) = sent.front() {
+            if now.duration_since(oldest) >= self.window {
+                sent.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Drop requests that have left the window; returns how many are still
+    /// in it. Sending does this too, so this only matters when idle.
+    pub fn prune(&self, now: Instant) -> usize {
+        let mut sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+        self.prune_window(&mut sent, now);
+        sent.len()
+    }
+
+    /// Reserve a slot, returning how long the caller must wait before sending
+    fn acquire(&self, now: Instant) -> Duration {
+        let mut sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+        self.prune_window(&mut sent, now);
+
+        let wait = if sent.len() >= self.max_requests {
+            let oldest = sent[sent.len() - self.max_requests];
+            self.window.saturating_sub(now.duration_since(oldest))
+        } else {
+            Duration::ZERO
+        };
+        sent.push_back(now + wait);
+        wait
+    }
+}
+
+impl Middleware for RateLimit {
+    fn handle(&self, request: HttpRequest, next: Next<'_>) -> HttpResponse {
+        let wait = self.acquire(Instant::now());
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+        next.run(request)
+    }
+}
+
+// --- Retry ---
+
+/// Retries transport failures, 429s, and 5xx responses on idempotent methods.
+///
+/// Order submission (POST) is never retried here; a duplicate order is far
+/// worse than a failed one.
+pub struct Retry {
+    max_retries: u32,
+    base_delay: Duration,
+    metrics: Option<Arc<MetricsRegistry>>,
+}
+
+impl Retry {
+    pub fn new(max_retries: u32, base_delay: Duration) -> Self {
+        Self {
+            max_retries,
+            base_delay,
+            metrics: None,
+        }
+    }
+
+    /// Count each retry attempt against the request's endpoint
+    pub fn with_metrics(mut self, registry: Arc<MetricsRegistry>) -> Self {
+        self.metrics = Some(registry);
+        self
+    }
+
+    fn should_retry(response: &HttpResponse) -> bool {
+        response.status == 0 || response.status == 429 || response.status >= 500
+    }
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self::new(2, Duration::from_millis(250))
+    }
+}
+
+impl Middleware for Retry {
+    fn handle(&self, request: HttpRequest, next: Next<'_>) -> HttpResponse {
+        if !request.method.is_idempotent() {
+            return next.run(request);
+        }
+
+        let mut attempt = 0;
+        loop {
+            let response = next.run(request.clone());
+            if attempt >= self.max_retries || !Self::should_retry(&response) {
+                return response;
+            }
+            if let Some(metrics) = &self.metrics {
+                metrics.record_retry(&endpoint_key(&request));
+            }
+            std::thread::sleep(self.base_delay * 2u32.pow(attempt));
+            attempt += 1;
+        }
+    }
+}
+
+// --- Caching ---
+
+/// Freshness of semi-static endpoints, in seconds. A rule covers its path
+/// and everything below it (`/v2/assets` also covers `/v2/assets/AAPL`).
+pub const DEFAULT_CACHE_TTLS: &[(&str, u64)] = &[
+    ("/v2/clock", 5),
+    ("/v2/calendar", 6 * 60 * 60),
+    ("/v2/assets", 60 * 60),
+    ("/v2/account/configurations", 60),
+];
+
+/// Paths whose data an order, cancel, or configuration change can alter;
+/// any successful write drops their cached entries
+const WRITE_INVALIDATES: &[&str] = &["/v2/account", "/v2/positions"];
+
+/// How long an expired entry with an `ETag` is kept for revalidation
+/// before [`ResponseCache::evict_expired`] drops it
+pub const STALE_ETAG_GRACE: Duration = Duration::from_secs(10 * 60);
+
+fn covers(rule: &str, path: &str) -> bool {
+    path.strip_prefix(rule)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+struct CacheEntry {
+    response: HttpResponse,
+    etag: Option<String>,
+    expires: Instant,
+}
+
+/// Serves GETs of semi-static endpoints from memory while fresh.
+///
+/// Entries are keyed by the full URL, query included. Once an entry
+/// expires it is revalidated with `If-None-Match` when Alpaca sent an
+/// `ETag`; a 304 renews the entry without transferring the body again.
+/// Endpoints without a rule always pass through.
+pub struct ResponseCache {
+    ttls: Vec<(String, Duration)>,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    metrics: Option<Arc<MetricsRegistry>>,
+}
+
+impl ResponseCache {
+    pub fn new(ttls: Vec<(String, Duration)>) -> Self {
+        Self {
+            ttls,
+            entries: Mutex::new(HashMap::new()),
+            metrics: None,
+        }
+    }
+
+    /// [`DEFAULT_CACHE_TTLS`] with per-path overrides applied; a TTL of 0
+    /// turns caching off for that path
+    pub fn ttls_with_overrides(overrides: &[(String, u64)]) -> Vec<(String, Duration)> {
+        let mut ttls: Vec<(String, u64)> = DEFAULT_CACHE_TTLS
+            .iter()
+            .map(|(path, secs)| (path.to_string(), *secs))
+            .collect();
+        for (path, secs) in overrides {
+            match ttls.iter_mut().find(|(p, _)| p == path) {
+                Some(rule) => rule.1 = *secs,
+                None => ttls.push((path.clone(), *secs)),
+            }
+        }
+        // Longest rule first so `/v2/account/configurations` wins over
+        // `/v2/account`
+        ttls.sort_by_key(|(path, _)| std::cmp::Reverse(path.len()));
+        ttls.into_iter()
+            .map(|(path, secs)| (path, Duration::from_secs(secs)))
+            .collect()
+    }
+
+    /// Count hits against the request's endpoint
+    pub fn with_metrics(mut self, registry: Arc<MetricsRegistry>) -> Self {
+        self.metrics = Some(registry);
+        self
+    }
+
+    fn ttl_for(&self, path: &str) -> Option<Duration> {
+        self.ttls
+            .iter()
+            .find(|(rule, _)| covers(rule, path))
+            .map(|(_, ttl)| *ttl)
+            .filter(|ttl| !ttl.is_zero())
+    }
+
+    /// Drop expired entries that can no longer save a transfer: those
+    /// without an `ETag`, and those stale for longer than
+    /// [`STALE_ETAG_GRACE`]. Returns how many were dropped.
+    pub fn evict_expired(&self, now: Instant) -> usize {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let before = entries.len();
+        entries.retain(|_, entry| {
+            entry.expires > now
+                || (entry.etag.is_some() && now.duration_since(entry.expires) < STALE_ETAG_GRACE)
+        });
+        before - entries.len()
+    }
+
+    fn invalidate_after_write(&self) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|url, _| {
+            let path = HttpRequest::path_of(url);
+            !WRITE_INVALIDATES.iter().any(|rule| covers(rule, path))
+        });
+    }
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        Self::new(Self::ttls_with_overrides(&[]))
+    }
+}
+
+impl Middleware for ResponseCache {
+    fn handle(&self, request: HttpRequest, next: Next<'_>) -> HttpResponse {
+        if request.method != HttpMethod::Get {
+            let response = next.run(request);
+            if response.is_success() {
+                self.invalidate_after_write();
+            }
+            return response;
+        }
+        // A breaker probe has to reach Alpaca
+        let ttl = self
+            .ttl_for(request.path())
+            .filter(|_| !request.headers.contains_key(PROBE_HEADER));
+        let Some(ttl) = ttl else {
+            return next.run(request);
+        };
+
+        let key = request.url.clone();
+        let etag = {
+            let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            match entries.get(&key) {
+                Some(entry) if entry.expires > Instant::now() => {
+                    if let Some(metrics) = &self.metrics {
+                        metrics.record_cache_hit(&endpoint_key(&request));
+                    }
+                    return entry.response.clone();
+                }
+                Some(entry) => entry.etag.clone(),
+                None => None,
+            }
+        };
+
+        let mut conditional = request.clone();
+        if let Some(etag) = etag {
+            conditional
+                .headers
+                .insert("If-None-Match".to_string(), etag);
+        }
+        let response = next.run(conditional);
+
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if response.status == 304 {
+            match entries.get_mut(&key) {
+                Some(entry) => {
+                    entry.expires = Instant::now() + ttl;
+                    return entry.response.clone();
+                }
+                // Invalidated while revalidating: fetch it outright
+                None => {
+                    drop(entries);
+                    return next.run(request);
+                }
+            }
+        }
+        if response.is_success() {
+            entries.insert(
+                key,
+                CacheEntry {
+                    response: response.clone(),
+                    etag: response.header("ETag").map(str::to_string),
+                    expires: Instant::now() + ttl,
+                },
+            );
+        }
+        response
+    }
+}
+
+// --- Connectivity ---
+
+/// Reports whether requests reach Alpaca, for `connectivity` events.
+///
+/// Sits outside the retry layer, so one failed attempt that a retry
+/// recovers is not an outage.
+pub struct Connectivity;
+
+impl Middleware for Connectivity {
+    fn handle(&self, request: HttpRequest, next: Next<'_>) -> HttpResponse {
+        let response = next.run(request);
+        let reached = response.status != 0;
+        let error = response.error.as_deref().filter(|_| !reached);
+        crate::events::observe_connectivity(reached, error);
+        response
+    }
+}
+
+// --- Circuit breaker ---
+
+/// Status of a request the open circuit breaker refused without sending.
+/// Like [`UNDECODABLE`] it is not a status a server sends, and [`Retry`]
+/// leaves it alone.
+pub const CIRCUIT_OPEN: u16 = 2;
+
+/// Marks the half-open probe, the one request an open breaker lets
+/// through; removed before the request goes out
+const PROBE_HEADER: &str = "X-Breaker-Probe";
+
+/// When the circuit breaker opens and how long it stays open
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BreakerSettings {
+    /// Consecutive failed requests that open the breaker
+    pub failures: u32,
+    /// How long the breaker stays open before a probe is due
+    pub cooldown: Duration,
+}
+
+impl Default for BreakerSettings {
+    fn default() -> Self {
+        Self {
+            failures: 5,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+impl BreakerSettings {
+    /// The `circuit_breaker` setting: `true` for the defaults, an object
+    /// with `failures` and `cooldown_secs` to override them, or absent /
+    /// `false` for no breaker
+    pub fn from_config(value: Option<&serde_json::Value>) -> Result<Option<Self>, String> {
+        let object = match value {
+            None | Some(serde_json::Value::Null) | Some(serde_json::Value::Bool(false)) => {
+                return Ok(None)
+            }
+            Some(serde_json::Value::Bool(true)) => return Ok(Some(Self::default())),
+            Some(serde_json::Value::Object(object)) => object,
+            Some(_) => return Err("circuit_breaker must be true or an object".to_string()),
+        };
+
+        let mut settings = Self::default();
+        if let Some(v) = object.get("failures") {
+            settings.failures = v
+                .as_u64()
+                .filter(|n| (1..=u32::MAX as u64).contains(n))
+                .ok_or("circuit_breaker.failures must be a positive integer")?
+                as u32;
+        }
+        if let Some(v) = object.get("cooldown_secs") {
+            settings.cooldown = Duration::from_secs(
+                v.as_u64()
+                    .ok_or("circuit_breaker.cooldown_secs must be a non-negative integer")?,
+            );
+        }
+        Ok(Some(settings))
+    }
+}
+
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { since: Instant },
+}
+
+/// Stops sending once Alpaca looks down, instead of letting every call
+/// wait out its own timeout.
+///
+/// After `failures` consecutive requests got no response or a 5xx, the
+/// breaker opens and refuses requests with [`CIRCUIT_OPEN`]. Once the
+/// cooldown has passed it is half-open: requests are still refused, but
+/// the owner's periodic probe (see [`CircuitBreaker::mark_probe`]) goes
+/// through, and closes the breaker if it succeeds or restarts the
+/// cooldown if it fails. Sits outside the retry layer, so a failure that a
+/// retry recovers does not count.
+pub struct CircuitBreaker {
+    settings: BreakerSettings,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(settings: BreakerSettings) -> Self {
+        Self {
+            settings,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        matches!(*state, BreakerState::Open { .. })
+    }
+
+    /// Whether the breaker is open and its cooldown has passed
+    pub fn probe_due(&self, now: Instant) -> bool {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match *state {
+            BreakerState::Open { since } => now.duration_since(since) >= self.settings.cooldown,
+            BreakerState::Closed { .. } => false,
+        }
+    }
+
+    /// Let `request` through an open breaker as the half-open probe
+    pub fn mark_probe(request: &mut HttpRequest) {
+        request
+            .headers
+            .insert(PROBE_HEADER.to_string(), "1".to_string());
+    }
+
+    fn record(&self, failed: bool, now: Instant) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let was_open = matches!(*state, BreakerState::Open { .. });
+        *state = match *state {
+            _ if !failed => BreakerState::Closed { failures: 0 },
+            BreakerState::Closed { failures } if failures + 1 < self.settings.failures => {
+                BreakerState::Closed {
+                    failures: failures + 1,
+                }
+            }
+            // Threshold reached, or a failed probe restarting the cooldown
+            _ => BreakerState::Open { since: now },
+        };
+        match (was_open, failed) {
+            (false, true) if matches!(*state, BreakerState::Open { .. }) => {
+                logging::warn("http", "Circuit breaker opened")
+                    .field("failures", self.settings.failures)
+                    .emit();
+            }
+            (true, false) => logging::info("http", "Circuit breaker closed").emit(),
+            _ => {}
+        }
+    }
+}
+
+impl Middleware for CircuitBreaker {
+    fn handle(&self, mut request: HttpRequest, next: Next<'_>) -> HttpResponse {
+        let probe = request.headers.remove(PROBE_HEADER).is_some();
+        if !probe && self.is_open() {
+            return HttpResponse {
+                status: CIRCUIT_OPEN,
+                headers: HashMap::new(),
+                body: String::new(),
+                error: Some("Circuit breaker open: Alpaca is not responding".to_string()),
+            };
+        }
+        let response = next.run(request);
+        let failed = response.status == 0 || response.status >= 500;
+        self.record(failed, Instant::now());
+        response
+    }
+}
+
+// --- Compression ---
+
+/// Negotiates gzip/deflate and decompresses encoded bodies.
+///
+/// Only enable this when the host hands bodies through untouched: compressed
+/// bytes cannot travel in the JSON string field, so the host is expected to
+/// base64-encode any body that still carries a `Content-Encoding`.
+pub struct Compression;
+
+/// Status of a response whose body could not be decoded. It is not a
+/// status a server sends, and unlike 0 (no response at all) [`Retry`]
+/// leaves it alone: asking again gets the same body back.
+pub const UNDECODABLE: u16 = 1;
+
+impl Compression {
+    fn decode(encoding: &str, body: &str) -> Result<String, String> {
+        let raw = base64::engine::general_purpose::STANDARD
+            .decode(body.trim())
+            .map_err(|e| format!("Invalid base64 body for {} response: {}", encoding, e))?;
+
+        let mut out = String::new();
+        let result = match encoding {
+            "gzip" | "x-gzip" => GzDecoder::new(raw.as_slice()).read_to_string(&mut out),
+            // "deflate" is zlib-wrapped per RFC 9110, but some servers send raw deflate
+            "deflate" => ZlibDecoder::new(raw.as_slice())
+                .read_to_string(&mut out)
+                .or_else(|_| {
+                    out.clear();
+                    DeflateDecoder::new(raw.as_slice()).read_to_string(&mut out)
+                }),
+            other => return Err(format!("Unsupported Content-Encoding: {}", other)),
+        };
+        result.map_err(|e| format!("Failed to decompress {} response: {}", encoding, e))?;
+        Ok(out)
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, mut request: HttpRequest, next: Next<'_>) -> HttpResponse {
+        request
+            .headers
+            .insert("Accept-Encoding".to_string(), "gzip, deflate".to_string());
+
+        let mut response = next.run(request);
+
+        let encoding = match response.header("Content-Encoding") {
+            Some(e) if !e.eq_ignore_ascii_case("identity") => e.trim().to_ascii_lowercase(),
+            _ => return response,
+        };
+
+        match Self::decode(&encoding, &response.body) {
+            Ok(body) => {
+                response.body = body;
+                response
+                    .headers
+                    .retain(|k, _| !k.eq_ignore_ascii_case("Content-Encoding"));
+            }
+            Err(e) => {
+                response.status = UNDECODABLE;
+                response.error = Some(e);
+            }
+        }
+        response
+    }
+}
+
+// --- Logging ---
+
+/// Emits one structured line per request: debug on success, warn on failure
+pub struct Logging;
+
+impl Middleware for Logging {
+    fn handle(&self, request: HttpRequest, next: Next<'_>) -> HttpResponse {
+        let method = request.method;
+        let path = request.path().to_string();
+        let started = Instant::now();
+
+        let response = next.run(request);
+
+        let record = if response.is_success() {
+            logging::debug("http", "Request completed")
+        } else {
+            logging::warn("http", "Request failed")
+        };
+        record
+            .field("method", method.as_str())
+            .field("endpoint", path)
+            .field("status", response.status)
+            .field("latency_ms", started.elapsed().as_millis() as u64)
+            .field_opt("error", response.error.clone())
+            .field_opt(
+                "alpaca_request_id",
+                response
+                    .header(ALPACA_REQUEST_ID_HEADER)
+                    .map(str::to_string),
+            )
+            .emit();
+        response
+    }
+}
+
+// --- Metrics ---
+
+/// Records request counts, status codes, and latency per endpoint
+pub struct Metrics {
+    registry: Arc<MetricsRegistry>,
+}
+
+impl Metrics {
+    pub fn new(registry: Arc<MetricsRegistry>) -> Self {
+        Self { registry }
+    }
+}
+
+impl Middleware for Metrics {
+    fn handle(&self, request: HttpRequest, next: Next<'_>) -> HttpResponse {
+        let key = endpoint_key(&request);
+        let started = Instant::now();
+
+        let response = next.run(request);
+
+        self.registry
+            .record_request(&key, response.status, started.elapsed().as_millis() as u64);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{HttpMethod, Pipeline};
+    use crate::mock::{response, MockTransport};
+
+    fn get(url: &str) -> HttpRequest {
+        HttpRequest {
+            method: HttpMethod::Get,
+            url: url.to_string(),
+            headers: HashMap::new(),
+            body: None,
+            timeout_ms: 1000,
+        }
+    }
+
+    #[test]
+    fn undecodable_bodies_fail_without_a_retry() {
+        let mock = MockTransport::new();
+        let mut garbled = response(200, "not base64!");
+        garbled
+            .headers
+            .insert("Content-Encoding".to_string(), "gzip".to_string());
+        mock.on(HttpMethod::Get, "/v2/account", garbled);
+        let pipeline = Pipeline::with_transport(mock.clone())
+            .with(Retry::new(2, Duration::ZERO))
+            .with(Compression);
+
+        let failed = pipeline.send(get("https://example.test/v2/account"));
+
+        assert_eq!(failed.status, UNDECODABLE);
+        assert!(!failed.is_success());
+        assert!(failed.error.unwrap().contains("Invalid base64 body"));
+        assert_eq!(mock.requests_to(HttpMethod::Get, "/v2/account").len(), 1);
+    }
+
+    #[test]
+    fn breaker_opens_after_consecutive_failures_until_a_probe_succeeds() {
+        let mock = MockTransport::new();
+        mock.on(HttpMethod::Get, "/v2/account", response(503, "down"))
+            .on(HttpMethod::Get, "/v2/account", response(503, "down"))
+            .on(HttpMethod::Get, "/v2/account", response(200, "{}"));
+        let breaker = Arc::new(CircuitBreaker::new(BreakerSettings {
+            failures: 2,
+            cooldown: Duration::ZERO,
+        }));
+        let pipeline = Pipeline::with_transport(mock.clone()).with(breaker.clone());
+        let url = "https://example.test/v2/account";
+
+        assert_eq!(pipeline.send(get(url)).status, 503);
+        assert!(!breaker.is_open());
+        assert_eq!(pipeline.send(get(url)).status, 503);
+        assert!(breaker.is_open());
+
+        let refused = pipeline.send(get(url));
+        assert_eq!(refused.status, CIRCUIT_OPEN);
+        assert_eq!(mock.requests().len(), 2);
+
+        assert!(breaker.probe_due(Instant::now()));
+        let mut probe = get(url);
+        CircuitBreaker::mark_probe(&mut probe);
+        assert_eq!(pipeline.send(probe).status, 200);
+        assert!(!breaker.is_open());
+        assert!(!mock.requests()[2].headers.contains_key(PROBE_HEADER));
+    }
+
+    #[test]
+    fn breaker_settings_parse_from_config() {
+        use serde_json::json;
+        assert_eq!(BreakerSettings::from_config(None), Ok(None));
+        assert_eq!(
+            BreakerSettings::from_config(Some(&json!(true))),
+            Ok(Some(BreakerSettings::default()))
+        );
+        assert_eq!(
+            BreakerSettings::from_config(Some(&json!({ "failures": 3, "cooldown_secs": 10 }))),
+            Ok(Some(BreakerSettings {
+                failures: 3,
+                cooldown: Duration::from_secs(10),
+            }))
+        );
+        assert!(BreakerSettings::from_config(Some(&json!({ "failures": 0 }))).is_err());
+    }
+
+    #[test]
+    fn cache_serves_fresh_entries_and_skips_uncached_paths() {
+        let mock = MockTransport::new();
+        mock.on(HttpMethod::Get, "/v2/clock", response(200, "1"))
+            .on(HttpMethod::Get, "/v2/clock", response(200, "2"))
+            .on(HttpMethod::Get, "/v2/orders", response(200, "[]"));
+        let pipeline = Pipeline::with_transport(mock.clone()).with(ResponseCache::default());
+
+        let clock = "https://example.test/v2/clock";
+        assert_eq!(pipeline.send(get(clock)).body, "1");
+        assert_eq!(pipeline.send(get(clock)).body, "1");
+        pipeline.send(get("https://example.test/v2/orders"));
+        pipeline.send(get("https://example.test/v2/orders"));
+
+        assert_eq!(mock.requests_to(HttpMethod::Get, "/v2/clock").len(), 1);
+        assert_eq!(mock.requests_to(HttpMethod::Get, "/v2/orders").len(), 2);
+    }
+
+    #[test]
+    fn cache_revalidates_expired_entries_with_etag() {
+        let mock = MockTransport::new();
+        let mut first = response(200, r#"{"symbol":"AAPL"}"#);
+        first
+            .headers
+            .insert("ETag".to_string(), "\"v1\"".to_string());
+        mock.on(HttpMethod::Get, "/v2/assets/AAPL", first).on(
+            HttpMethod::Get,
+            "/v2/assets/AAPL",
+            response(304, ""),
+        );
+        let cache = ResponseCache::new(vec![("/v2/assets".to_string(), Duration::from_millis(1))]);
+        let pipeline = Pipeline::with_transport(mock.clone()).with(cache);
+
+        let url = "https://example.test/v2/assets/AAPL";
+        pipeline.send(get(url));
+        std::thread::sleep(Duration::from_millis(5));
+        let revalidated = pipeline.send(get(url));
+
+        assert_eq!(revalidated.status, 200);
+        assert_eq!(revalidated.body, r#"{"symbol":"AAPL"}"#);
+        let sent = mock.requests_to(HttpMethod::Get, "/v2/assets/AAPL");
+        assert_eq!(sent.len(), 2);
+        assert!(!sent[0].headers.contains_key("If-None-Match"));
+        assert_eq!(
+            sent[1].headers.get("If-None-Match").map(String::as_str),
+            Some("\"v1\"")
+        );
+    }
+
+    #[test]
+    fn eviction_keeps_entries_worth_revalidating() {
+        let mock = MockTransport::new();
+        let mut tagged = response(200, "{}");
+        tagged
+            .headers
+            .insert("ETag".to_string(), "\"v1\"".to_string());
+        mock.on(HttpMethod::Get, "/v2/assets/AAPL", tagged).on(
+            HttpMethod::Get,
+            "/v2/clock",
+            response(200, "{}"),
+        );
+        let cache = Arc::new(ResponseCache::new(vec![
+            ("/v2/assets".to_string(), Duration::from_secs(60)),
+            ("/v2/clock".to_string(), Duration::from_secs(5)),
+        ]));
+        let pipeline = Pipeline::with_transport(mock.clone()).with(cache.clone());
+        pipeline.send(get("https://example.test/v2/assets/AAPL"));
+        pipeline.send(get("https://example.test/v2/clock"));
+
+        let now = Instant::now();
+        assert_eq!(cache.evict_expired(now), 0);
+        // The clock entry has expired and has no ETag; the asset is stale
+        // but can still be revalidated
+        assert_eq!(cache.evict_expired(now + Duration::from_secs(61)), 1);
+        assert_eq!(
+            cache.evict_expired(now + Duration::from_secs(61) + STALE_ETAG_GRACE),
+            1
+        );
+    }
+
+    #[test]
+    fn writes_invalidate_account_entries() {
+        let mock = MockTransport::new();
+        mock.on(HttpMethod::Get, "/v2/account", response(200, "{}"))
+            .on(HttpMethod::Get, "/v2/clock", response(200, "{}"))
+            .on(HttpMethod::Post, "/v2/orders", response(200, "{}"));
+        let ttls = ResponseCache::ttls_with_overrides(&[("/v2/account".to_string(), 60)]);
+        let pipeline = Pipeline::with_transport(mock.clone()).with(ResponseCache::new(ttls));
+
+        for url in [
+            "https://example.test/v2/account",
+            "https://example.test/v2/clock",
+        ] {
+            pipeline.send(get(url));
+        }
+        pipeline.send(HttpRequest {
+            method: HttpMethod::Post,
+            ..get("https://example.test/v2/orders")
+        });
+        for url in [
+            "https://example.test/v2/account",
+            "https://example.test/v2/clock",
+        ] {
+            pipeline.send(get(url));
+        }
+
+        assert_eq!(mock.requests_to(HttpMethod::Get, "/v2/account").len(), 2);
+        assert_eq!(mock.requests_to(HttpMethod::Get, "/v2/clock").len(), 1);
+    }
+}
@@ -0,0 +1,426 @@
+//! Recordable mock transport for native tests
+//!
+//! [`MockTransport`] stands in for the host import: responses are queued
+//! per method and path, and every request that reaches it is recorded so
+//! tests can assert on what the client actually sent. Canned Alpaca
+//! payloads live in `testdata/alpaca/` and are loaded with [`fixture`].
+
+use crate::http::{HttpMethod, HttpRequest, HttpResponse, HttpTransport};
+use models::order::{OrderRequest, OrderSide, OrderType};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Body of a fixture file in `testdata/alpaca/`, e.g. `fixture("account")`
+pub fn fixture(name: &str) -> String {
+    let path = format!(
+        "{}/testdata/alpaca/{}.json",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    );
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("fixture {}: {}", path, e))
+}
+
+pub fn response(status: u16, body: impl Into<String>) -> HttpResponse {
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type".to_string(), "application/json".to_string());
+    HttpResponse {
+        status,
+        headers,
+        body: body.into(),
+        error: None,
+    }
+}
+
+/// Start an order request for tests: a market buy of one share of
+/// `symbol` for the `default` persona
+pub fn order_request(symbol: &str) -> RequestBuilder {
+    let request = serde_json::from_value(serde_json::json!({
+        "symbol_id": symbol,
+        "quantity": 1.0,
+        "side": OrderSide::Buy,
+        "order_type": OrderType::Market,
+        "limit_price": null,
+        "stop_price": null,
+        "persona_id": "default",
+    }))
+    .unwrap();
+    RequestBuilder(request)
+}
+
+/// See [`order_request`]
+pub struct RequestBuilder(OrderRequest);
+
+impl RequestBuilder {
+    pub fn side(mut self, side: OrderSide) -> Self {
+        self.0.side = side;
+        self
+    }
+
+    pub fn qty(mut self, quantity: f64) -> Self {
+        self.0.quantity = quantity;
+        self
+    }
+
+    /// Limit price; makes the order a limit (or stop limit) order
+    pub fn limit(mut self, price: f64) -> Self {
+        self.0.limit_price = Some(price);
+        self.0.order_type = match self.0.stop_price {
+            Some(_) => OrderType::StopLimit,
+            None => OrderType::Limit,
+        };
+        self
+    }
+
+    /// Stop price; makes the order a stop (or stop limit) order
+    pub fn stop(mut self, price: f64) -> Self {
+        self.0.stop_price = Some(price);
+        self.0.order_type = match self.0.limit_price {
+            Some(_) => OrderType::StopLimit,
+            None => OrderType::Stop,
+        };
+        self
+    }
+
+    /// Order type regardless of the prices, e.g. a limit order missing
+    /// its limit
+    pub fn order_type(mut self, order_type: OrderType) -> Self {
+        self.0.order_type = order_type;
+        self
+    }
+
+    pub fn reference(mut self, price: f64) -> Self {
+        self.0.reference_price = Some(price);
+        self
+    }
+
+    pub fn extension(mut self, key: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.0
+            .extensions
+            .get_or_insert_with(HashMap::new)
+            .insert(key.to_string(), value.into());
+        self
+    }
+
+    pub fn build(self) -> OrderRequest {
+        self.0
+    }
+}
+
+struct Route {
+    method: HttpMethod,
+    path: String,
+    /// Served in order; the last one repeats once the rest are used up
+    responses: VecDeque<HttpResponse>,
+}
+
+#[derive(Default)]
+struct MockState {
+    routes: Vec<Route>,
+    requests: Vec<HttpRequest>,
+}
+
+/// Cloning shares the routes and the request log, so a test keeps one
+/// handle while the client owns another
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a response for `method` on `path` (matched without the query
+    /// string)
+    pub fn on(&self, method: HttpMethod, path: &str, response: HttpResponse) -> &Self {
+        let mut state = self.state.lock().unwrap();
+        match state
+            .routes
+            .iter_mut()
+            .find(|r| r.method == method && r.path == path)
+        {
+            Some(route) => route.responses.push_back(response),
+            None => state.routes.push(Route {
+                method,
+                path: path.to_string(),
+                responses: VecDeque::from([response]),
+            }),
+        }
+        self
+    }
+
+    /// Queue a fixture file as the response
+    pub fn on_fixture(&self, method: HttpMethod, path: &str, status: u16, name: &str) -> &Self {
+        self.on(method, path, response(status, fixture(name)))
+    }
+
+    /// Every request received, oldest first
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Requests received for one method and path
+    pub fn requests_to(&self, method: HttpMethod, path: &str) -> Vec<HttpRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|r| r.method == method && r.path() == path)
+            .collect()
+    }
+}
+
+impl HttpTransport for MockTransport {
+    fn send(&self, request: HttpRequest) -> HttpResponse {
+        let mut state = self.state.lock().unwrap();
+        state.requests.push(request.clone());
+        let path = request.path();
+        let Some(route) = state
+            .routes
+            .iter_mut()
+            .find(|r| r.method == request.method && r.path == path)
+        else {
+            return response(
+                404,
+                format!(
+                    r#"{{"code":40410000,"message":"mock: no route for {} {}"}}"#,
+                    request.method.as_str(),
+                    path
+                ),
+            );
+        };
+        if route.responses.len() > 1 {
+            route.responses.pop_front().unwrap()
+        } else {
+            route.responses[0].clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alpaca::{AccountPositions, AlpacaClient, ClientOptions};
+    use crate::http::Pipeline;
+    use crate::middleware::AuthHeaders;
+    use models::order::OrderStatus;
+
+    fn client(mock: &MockTransport) -> AlpacaClient {
+        AlpacaClient::with_transport(
+            "key".to_string(),
+            "secret".to_string(),
+            true,
+            ClientOptions::default(),
+            mock.clone(),
+        )
+    }
+
+    #[test]
+    fn unmatched_route_is_404_and_still_recorded() {
+        let mock = MockTransport::new();
+        let pipeline = Pipeline::with_transport(mock.clone());
+        let response = pipeline.send(HttpRequest {
+            method: HttpMethod::Get,
+            url: "https://paper-api.alpaca.markets/v2/clock?x=1".to_string(),
+            headers: HashMap::new(),
+            body: None,
+            timeout_ms: 1000,
+        });
+        assert_eq!(response.status, 404);
+        assert_eq!(mock.requests_to(HttpMethod::Get, "/v2/clock").len(), 1);
+    }
+
+    #[test]
+    fn queued_responses_are_served_in_order_then_repeat() {
+        let mock = MockTransport::new();
+        mock.on(HttpMethod::Get, "/v2/clock", response(200, "1"))
+            .on(HttpMethod::Get, "/v2/clock", response(200, "2"));
+        let pipeline = Pipeline::with_transport(mock.clone());
+        let bodies: Vec<String> = (0..3)
+            .map(|_| {
+                pipeline
+                    .send(HttpRequest {
+                        method: HttpMethod::Get,
+                        url: "https://example.test/v2/clock".to_string(),
+                        headers: HashMap::new(),
+                        body: None,
+                        timeout_ms: 1000,
+                    })
+                    .body
+            })
+            .collect();
+        assert_eq!(bodies, ["1", "2", "2"]);
+    }
+
+    #[test]
+    fn middleware_runs_before_the_transport() {
+        let mock = MockTransport::new();
+        let pipeline = Pipeline::with_transport(mock.clone())
+            .with(AuthHeaders::new("key".to_string(), "secret".to_string()));
+        pipeline.send(HttpRequest {
+            method: HttpMethod::Get,
+            url: "https://example.test/v2/account".to_string(),
+            headers: HashMap::new(),
+            body: None,
+            timeout_ms: 1000,
+        });
+        let sent = &mock.requests()[0];
+        assert_eq!(
+            sent.headers.get("APCA-API-KEY-ID").map(String::as_str),
+            Some("key")
+        );
+    }
+
+    #[test]
+    fn account_maps_balances_and_margin() {
+        let mock = MockTransport::new();
+        mock.on_fixture(HttpMethod::Get, "/v2/account", 200, "account")
+            .on_fixture(HttpMethod::Get, "/v2/positions", 200, "positions");
+        let account = client(&mock).get_account().unwrap();
+
+        assert_eq!(account.id, "PA3ZQ8X1KD2M");
+        assert!(account.is_paper);
+        assert_eq!(account.balance.currency, "USD");
+        assert_eq!(account.balance.total_equity, 102_345.67);
+        assert_eq!(account.balance.available_cash, 48_210.12);
+        assert_eq!(account.positions.len(), 3);
+        let ext = account.extensions.unwrap();
+        assert_eq!(ext["status"], "ACTIVE");
+        assert_eq!(ext["daytrade_count"], 1);
+        assert_eq!(ext["margin"]["maintenance_margin"], "16251.33");
+    }
+
+    #[test]
+    fn positions_keep_side_and_fractions() {
+        let mock = MockTransport::new();
+        mock.on_fixture(HttpMethod::Get, "/v2/positions", 200, "positions");
+        let positions = client(&mock).get_positions().unwrap();
+
+        let by_symbol = |s: &str| positions.iter().find(|p| p.symbol_id == s).unwrap();
+        assert_eq!(by_symbol("AAPL").quantity, 100.0);
+        assert_eq!(by_symbol("TSLA").quantity, -25.0);
+        assert_eq!(by_symbol("VOO").quantity, 3.518_274_5);
+        assert_eq!(by_symbol("AAPL").unrealized_pnl_percent, 4.5);
+    }
+
+    #[test]
+    fn missing_position_is_none() {
+        let mock = MockTransport::new();
+        mock.on_fixture(
+            HttpMethod::Get,
+            "/v2/positions/MSFT",
+            404,
+            "error_position_not_found",
+        );
+        assert!(client(&mock).get_position("MSFT").unwrap().is_none());
+    }
+
+    #[test]
+    fn submit_order_sends_wire_payload_and_maps_response() {
+        let mock = MockTransport::new();
+        mock.on_fixture(HttpMethod::Post, "/v2/orders", 200, "order_new");
+        let order = client(&mock)
+            .submit_order(&order_request("AAPL").qty(10.0).limit(187.25).build())
+            .unwrap();
+
+        assert_eq!(order.id, "61e69015-8549-4bfd-b9c3-01e75843f47d");
+        assert_eq!(order.status, OrderStatus::Submitted);
+        assert_eq!(order.persona_id, "default");
+        let ext = order.extensions.unwrap();
+        assert_eq!(ext["alpaca_status"], "new");
+        assert_eq!(ext["is_terminal"], false);
+
+        let sent = mock.requests_to(HttpMethod::Post, "/v2/orders");
+        let body: serde_json::Value =
+            serde_json::from_str(sent[0].body.as_deref().unwrap()).unwrap();
+        assert_eq!(body["symbol"], "AAPL");
+        assert_eq!(body["qty"], "10");
+        assert_eq!(body["type"], "limit");
+        assert_eq!(body["limit_price"], "187.25");
+        assert_eq!(body["time_in_force"], "day");
+    }
+
+    #[test]
+    fn partially_filled_and_filled_orders_map_fill_fields() {
+        let mock = MockTransport::new();
+        mock.on_fixture(
+            HttpMethod::Get,
+            "/v2/orders/ord-partial",
+            200,
+            "order_partially_filled",
+        )
+        .on_fixture(
+            HttpMethod::Get,
+            "/v2/orders/ord-filled",
+            200,
+            "order_filled",
+        );
+        let client = client(&mock);
+
+        let partial = client.get_order("ord-partial").unwrap();
+        assert_eq!(partial.status, OrderStatus::PartiallyFilled);
+        assert_eq!(partial.filled_quantity, 40.0);
+        assert_eq!(partial.average_filled_price, Some(187.21));
+
+        let filled = client.get_order("ord-filled").unwrap();
+        assert_eq!(filled.status, OrderStatus::Filled);
+        assert_eq!(filled.filled_quantity, 10.0);
+        assert_eq!(filled.extensions.unwrap()["filled_avg_price"], "187.2");
+    }
+
+    #[test]
+    fn error_bodies_surface_status_and_message() {
+        let mock = MockTransport::new();
+        mock.on_fixture(
+            HttpMethod::Post,
+            "/v2/orders",
+            403,
+            "error_insufficient_buying_power",
+        );
+        let err = client(&mock)
+            .submit_order(&order_request("AAPL").qty(1000.0).limit(187.25).build())
+            .unwrap_err();
+        assert!(err.starts_with("API error 403"), "{}", err);
+        assert!(err.contains("insufficient buying power"), "{}", err);
+
+        let mock = MockTransport::new();
+        mock.on_fixture(
+            HttpMethod::Patch,
+            "/v2/orders/ord-filled",
+            422,
+            "error_unprocessable",
+        );
+        let err = client(&mock)
+            .replace_order("ord-filled", rust_decimal::Decimal::new(18800, 2))
+            .unwrap_err();
+        assert!(err.starts_with("API error 422"), "{}", err);
+    }
+
+    #[test]
+    fn account_polling_reuses_or_omits_positions() {
+        let mock = MockTransport::new();
+        mock.on_fixture(HttpMethod::Get, "/v2/account", 200, "account")
+            .on_fixture(HttpMethod::Get, "/v2/positions", 200, "positions")
+            .on_fixture(HttpMethod::Post, "/v2/orders", 200, "order_new");
+        let client = client(&mock);
+        let positions_fetched = || mock.requests_to(HttpMethod::Get, "/v2/positions").len();
+
+        let balances = client.get_account_with(AccountPositions::Omit).unwrap();
+        assert!(balances.positions.is_empty());
+        assert_eq!(balances.extensions.unwrap()["positions_omitted"], true);
+        assert_eq!(positions_fetched(), 0);
+
+        assert_eq!(client.get_account().unwrap().positions.len(), 3);
+        assert_eq!(client.get_account().unwrap().positions.len(), 3);
+        assert_eq!(positions_fetched(), 1);
+
+        client.get_account_with(AccountPositions::Fresh).unwrap();
+        assert_eq!(positions_fetched(), 2);
+
+        // A write may have changed them
+        client
+            .submit_order(&order_request("AAPL").qty(1.0).limit(187.25).build())
+            .unwrap();
+        client.get_account().unwrap();
+        assert_eq!(positions_fetched(), 3);
+    }
+}
@@ -0,0 +1,231 @@
+//! Native (non-WASM) use of the client
+//!
+//! With the `native` feature the crate also builds as an ordinary Rust
+//! library, so CLI tools and integration tests can drive [`AlpacaClient`]
+//! directly. Requests go through the same middleware as in the plugin and
+//! are delivered by [`ReqwestTransport`] instead of the host import.
+//!
+//! ```no_run
+//! use broker_alpaca::native::{AlpacaClient, ClientOptions};
+//!
+//! let client = AlpacaClient::native(
+//!     std::env::var("APCA_API_KEY_ID").unwrap(),
+//!     std::env::var("APCA_API_SECRET_KEY").unwrap(),
+//!     true,
+//!     ClientOptions::default(),
+//! );
+//! println!("{:?}", client.get_clock());
+//! ```
+
+use base64::Engine;
+use std::collections::HashMap;
+use std::time::Duration;
+
+pub use crate::alpaca::{AlpacaAccount, AlpacaClient, ClientOptions, MarketClock};
+pub use crate::decimal::Decimal;
+pub use crate::http::{HttpMethod, HttpRequest, HttpResponse, HttpTransport};
+pub use crate::market_data::{Bar, PriceSource, Quote, Snapshot};
+
+/// Delivers requests with a blocking reqwest client
+pub struct ReqwestTransport {
+    client: reqwest::blocking::Client,
+}
+
+impl ReqwestTransport {
+    pub fn new() -> Self {
+        Self::with_client(reqwest::blocking::Client::new())
+    }
+
+    /// Use a preconfigured client (proxy, TLS roots, connection pool)
+    pub fn with_client(client: reqwest::blocking::Client) -> Self {
+        Self { client }
+    }
+}
+
+impl Default for ReqwestTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpTransport for ReqwestTransport {
+    fn send(&self, request: HttpRequest) -> HttpResponse {
+        let method = match request.method {
+            HttpMethod::Get => reqwest::Method::GET,
+            HttpMethod::Post => reqwest::Method::POST,
+            HttpMethod::Put => reqwest::Method::PUT,
+            HttpMethod::Patch => reqwest::Method::PATCH,
+            HttpMethod::Delete => reqwest::Method::DELETE,
+            HttpMethod::Head => reqwest::Method::HEAD,
+        };
+        let mut builder = self
+            .client
+            .request(method, &request.url)
+            .timeout(Duration::from_millis(request.timeout_ms as u64));
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(body) = request.body {
+            builder = builder.body(body);
+        }
+
+        // Transport failures look like the host's: status 0 with an error,
+        // which Retry treats as retryable
+        let failed = |error: String| HttpResponse {
+            status: 0,
+            headers: HashMap::new(),
+            body: String::new(),
+            error: Some(error),
+        };
+        let response = match builder.send() {
+            Ok(response) => response,
+            Err(e) => return failed(format!("HTTP request failed: {}", e)),
+        };
+
+        let status = response.status().as_u16();
+        let headers: HashMap<String, String> = response
+            .headers()
+            .iter()
+            .filter_map(|(k, v)| Some((k.as_str().to_string(), v.to_str().ok()?.to_string())))
+            .collect();
+        let encoded = headers.iter().any(|(k, v)| {
+            k.eq_ignore_ascii_case("Content-Encoding") && !v.eq_ignore_ascii_case("identity")
+        });
+        let bytes = match response.bytes() {
+            Ok(bytes) => bytes,
+            Err(e) => return failed(format!("Failed to read response body: {}", e)),
+        };
+        // Same contract as the host: a body still carrying a
+        // Content-Encoding is handed over base64-encoded for Compression
+        let body = if encoded {
+            base64::engine::general_purpose::STANDARD.encode(&bytes)
+        } else {
+            String::from_utf8_lossy(&bytes).into_owned()
+        };
+
+        HttpResponse {
+            status,
+            headers,
+            body,
+            error: None,
+        }
+    }
+}
+
+impl AlpacaClient {
+    /// Client that talks to Alpaca over reqwest
+    pub fn native(
+        api_key: String,
+        api_secret: String,
+        is_paper: bool,
+        options: ClientOptions,
+    ) -> Self {
+        Self::with_transport(
+            api_key,
+            api_secret,
+            is_paper,
+            options,
+            ReqwestTransport::new(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Serve one canned HTTP response and hand back the raw request
+    fn serve_once(response: &'static str) -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut raw = Vec::new();
+            let mut buf = [0u8; 4096];
+            // Read until the headers and the announced body have arrived
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                raw.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&raw).into_owned();
+                let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                    continue;
+                };
+                let length = head
+                    .lines()
+                    .find_map(|l| {
+                        l.to_ascii_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|v| v.trim().parse::<usize>().unwrap())
+                    })
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    break;
+                }
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8_lossy(&raw).into_owned()
+        });
+        (url, handle)
+    }
+
+    fn request(method: HttpMethod, url: String, body: Option<&str>) -> HttpRequest {
+        let mut headers = HashMap::new();
+        headers.insert("APCA-API-KEY-ID".to_string(), "key".to_string());
+        HttpRequest {
+            method,
+            url,
+            headers,
+            body: body.map(str::to_string),
+            timeout_ms: 5000,
+        }
+    }
+
+    #[test]
+    fn maps_status_headers_and_body() {
+        let (url, server) = serve_once(
+            "HTTP/1.1 422 Unprocessable Entity\r\nContent-Type: application/json\r\n\
+             X-Request-ID: req-1\r\nContent-Length: 13\r\nConnection: close\r\n\r\n\
+             {\"code\":4221}",
+        );
+        let response = ReqwestTransport::new().send(request(
+            HttpMethod::Post,
+            format!("{}/v2/orders", url),
+            Some("{\"qty\":\"1\"}"),
+        ));
+        let raw = server.join().unwrap();
+
+        assert_eq!(response.status, 422);
+        assert_eq!(response.header("x-request-id"), Some("req-1"));
+        assert_eq!(response.body, "{\"code\":4221}");
+        assert!(response.error.is_none());
+        assert!(raw.starts_with("POST /v2/orders HTTP/1.1"), "{}", raw);
+        assert!(raw.to_ascii_lowercase().contains("apca-api-key-id: key"));
+        assert!(raw.ends_with("{\"qty\":\"1\"}"));
+    }
+
+    #[test]
+    fn encoded_bodies_are_passed_on_as_base64() {
+        let (url, server) = serve_once(
+            "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: 3\r\n\
+             Connection: close\r\n\r\nabc",
+        );
+        let response = ReqwestTransport::new().send(request(HttpMethod::Get, url, None));
+        server.join().unwrap();
+        assert_eq!(response.body, "YWJj");
+    }
+
+    #[test]
+    fn connection_failure_is_status_zero() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let response = ReqwestTransport::new().send(request(HttpMethod::Get, url, None));
+        assert_eq!(response.status, 0);
+        assert!(response.error.is_some());
+    }
+}
@@ -0,0 +1,237 @@
+//! Account opening through the Broker API
+//!
+//! `open_account` submits a customer's application (contact details,
+//! identity, regulatory disclosures, and signed agreements) to
+//! `POST /v1/accounts`, and `get_account_status` polls where KYC review
+//! stands. The payload is typed so that missing or malformed fields are
+//! reported by name before anything is sent; fields this plugin does not
+//! model are passed through to Alpaca as given. Tax IDs are masked in
+//! every response and log line.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Agreement every account needs
+const CUSTOMER_AGREEMENT: &str = "customer_agreement";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AccountApplication {
+    pub contact: Contact,
+    pub identity: Identity,
+    pub disclosures: Disclosures,
+    pub agreements: Vec<Agreement>,
+    /// `documents`, `trusted_contact`, `enabled_assets`, ...
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Contact {
+    pub email_address: String,
+    pub phone_number: String,
+    pub street_address: Vec<String>,
+    pub city: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub postal_code: Option<String>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Identity {
+    pub given_name: String,
+    pub family_name: String,
+    pub date_of_birth: NaiveDate,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tax_id: Option<String>,
+    /// `USA_SSN`, `NOT_SPECIFIED`, ...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tax_id_type: Option<String>,
+    pub country_of_tax_residence: String,
+    /// `employment_income`, `investments`, `savings`, ...
+    pub funding_source: Vec<String>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Disclosures {
+    pub is_control_person: bool,
+    pub is_affiliated_exchange_or_finra: bool,
+    pub is_politically_exposed: bool,
+    pub immediate_family_exposed: bool,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Agreement {
+    /// `customer_agreement`, `margin_agreement`, `crypto_agreement`, ...
+    pub agreement: String,
+    pub signed_at: DateTime<Utc>,
+    pub ip_address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+}
+
+impl AccountApplication {
+    /// Every problem with the application, by field
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut require = |field: &str, ok: bool, message: &str| {
+            if !ok {
+                problems.push(format!("{}: {}", field, message));
+            }
+        };
+        let contact = &self.contact;
+        require(
+            "contact.email_address",
+            contact.email_address.contains('@'),
+            "expected an email address",
+        );
+        require(
+            "contact.phone_number",
+            !contact.phone_number.trim().is_empty(),
+            "required",
+        );
+        require(
+            "contact.street_address",
+            contact
+                .street_address
+                .iter()
+                .any(|line| !line.trim().is_empty()),
+            "at least one line is required",
+        );
+        require("contact.city", !contact.city.trim().is_empty(), "required");
+
+        let identity = &self.identity;
+        require(
+            "identity.given_name",
+            !identity.given_name.trim().is_empty(),
+            "required",
+        );
+        require(
+            "identity.family_name",
+            !identity.family_name.trim().is_empty(),
+            "required",
+        );
+        require(
+            "identity.date_of_birth",
+            identity.date_of_birth < Utc::now().date_naive(),
+            "must be in the past",
+        );
+        require(
+            "identity.country_of_tax_residence",
+            identity.country_of_tax_residence.len() == 3,
+            "expected an ISO 3166-1 alpha-3 code",
+        );
+        require(
+            "identity.funding_source",
+            !identity.funding_source.is_empty(),
+            "at least one source is required",
+        );
+        require(
+            "identity.tax_id",
+            identity.country_of_tax_residence != "USA" || identity.tax_id.is_some(),
+            "required for US tax residents",
+        );
+
+        require(
+            "agreements",
+            self.agreements
+                .iter()
+                .any(|a| a.agreement == CUSTOMER_AGREEMENT),
+            "customer_agreement must be signed",
+        );
+        for (i, agreement) in self.agreements.iter().enumerate() {
+            require(
+                &format!("agreements[{}].ip_address", i),
+                agreement.ip_address.parse::<std::net::IpAddr>().is_ok(),
+                "expected an IP address",
+            );
+        }
+        problems
+    }
+}
+
+/// Where an account stands, from `POST /v1/accounts` or
+/// `GET /v1/accounts/{id}`. `status` moves from `SUBMITTED` through
+/// `APPROVAL_PENDING` (or `ACTION_REQUIRED`, when KYC needs more from the
+/// customer) to `APPROVED` and `ACTIVE`, or ends `REJECTED`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AccountStatus {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_number: Option<String>,
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crypto_status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kyc_results: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn application() -> serde_json::Value {
+        serde_json::json!({
+            "contact": {
+                "email_address": "jo@example.com",
+                "phone_number": "555-666-7788",
+                "street_address": ["20 N San Mateo Dr"],
+                "city": "San Mateo",
+                "state": "CA",
+                "postal_code": "94401",
+            },
+            "identity": {
+                "given_name": "Jo",
+                "family_name": "Doe",
+                "date_of_birth": "1990-01-01",
+                "tax_id": "666-55-4321",
+                "tax_id_type": "USA_SSN",
+                "country_of_tax_residence": "USA",
+                "funding_source": ["employment_income"],
+            },
+            "disclosures": {
+                "is_control_person": false,
+                "is_affiliated_exchange_or_finra": false,
+                "is_politically_exposed": false,
+                "immediate_family_exposed": false,
+            },
+            "agreements": [
+                { "agreement": "customer_agreement", "signed_at": "2024-03-01T15:00:00Z", "ip_address": "185.13.21.99" },
+            ],
+            "enabled_assets": ["us_equity"],
+        })
+    }
+
+    #[test]
+    fn applications_are_checked_field_by_field_and_extras_pass_through() {
+        let parsed: AccountApplication = serde_json::from_value(application()).unwrap();
+        assert!(parsed.problems().is_empty());
+        let sent = serde_json::to_value(&parsed).unwrap();
+        assert_eq!(sent["enabled_assets"][0], "us_equity");
+        assert_eq!(sent["contact"]["state"], "CA");
+
+        let mut bad = application();
+        bad["contact"]["email_address"] = "nobody".into();
+        bad["identity"]["tax_id"] = serde_json::Value::Null;
+        bad["agreements"][0]["agreement"] = "margin_agreement".into();
+        let problems = serde_json::from_value::<AccountApplication>(bad)
+            .unwrap()
+            .problems();
+        let fields: Vec<&str> = problems
+            .iter()
+            .map(|p| p.split(':').next().unwrap())
+            .collect();
+        assert_eq!(
+            fields,
+            ["contact.email_address", "identity.tax_id", "agreements"]
+        );
+    }
+}
@@ -0,0 +1,429 @@
+//! Order size limits
+//!
+//! Alpaca rejects orders outside its size rules only after they are sent:
+//! fractional equity orders below $1 of notional or in a symbol that is
+//! not fractionable, option orders in fractions of a contract, crypto
+//! orders below the pair's `min_order_size` or off its
+//! `min_trade_increment`, and crypto orders above $200,000. These are
+//! checked locally, together with the minimums and maximums configured
+//! per asset class under `order_size_limits`. A breach always rejects the
+//! order with an `order_size` finding naming the constraint and the
+//! allowed range.
+
+use crate::alpaca::AlpacaAsset;
+use crate::decimal::{self, Decimal};
+use crate::fees::AssetKind;
+use crate::pretrade::{CheckOutcome, Finding, PriceEstimate};
+use models::order::OrderRequest;
+
+/// Smallest notional Alpaca accepts for a fractional equity order
+pub const FRACTIONAL_MIN_NOTIONAL: i64 = 1;
+/// Decimal places Alpaca accepts in an equity quantity
+pub const MAX_QTY_PLACES: u32 = 9;
+/// Largest notional Alpaca accepts for a crypto order
+pub const CRYPTO_MAX_NOTIONAL: i64 = 200_000;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SizeRule {
+    pub min_qty: Option<Decimal>,
+    pub max_qty: Option<Decimal>,
+    pub min_notional: Option<Decimal>,
+    pub max_notional: Option<Decimal>,
+}
+
+impl SizeRule {
+    fn from_object(
+        class: &str,
+        object: &serde_json::Map<String, serde_json::Value>,
+        mut rule: Self,
+    ) -> Result<Self, String> {
+        for (key, slot) in [
+            ("min_qty", &mut rule.min_qty),
+            ("max_qty", &mut rule.max_qty),
+            ("min_notional", &mut rule.min_notional),
+            ("max_notional", &mut rule.max_notional),
+        ] {
+            match object.get(key) {
+                None => {}
+                Some(serde_json::Value::Null) => *slot = None,
+                Some(v) => {
+                    *slot = Some(
+                        v.as_f64()
+                            .filter(|n| *n > 0.0)
+                            .and_then(decimal::from_f64)
+                            .ok_or_else(|| {
+                                format!(
+                                    "order_size_limits.{}.{} must be a positive number or null",
+                                    class, key
+                                )
+                            })?,
+                    )
+                }
+            }
+        }
+        if let Some(other) = object
+            .keys()
+            .find(|k| !["min_qty", "max_qty", "min_notional", "max_notional"].contains(&k.as_str()))
+        {
+            return Err(format!(
+                "order_size_limits.{} has unknown key {}",
+                class, other
+            ));
+        }
+        for (min, max, what) in [
+            (rule.min_qty, rule.max_qty, "qty"),
+            (rule.min_notional, rule.max_notional, "notional"),
+        ] {
+            if let Some((min, max)) = min.zip(max).filter(|(min, max)| min > max) {
+                return Err(format!(
+                    "order_size_limits.{}: min_{} {} is above max_{} {}",
+                    class,
+                    what,
+                    decimal::to_wire(min),
+                    what,
+                    decimal::to_wire(max)
+                ));
+            }
+        }
+        Ok(rule)
+    }
+}
+
+/// Configured size rules per asset class
+#[derive(Clone, Debug, PartialEq)]
+pub struct SizeLimits {
+    pub equity: SizeRule,
+    pub crypto: SizeRule,
+    pub option: SizeRule,
+}
+
+impl Default for SizeLimits {
+    fn default() -> Self {
+        Self {
+            equity: SizeRule::default(),
+            crypto: SizeRule {
+                max_notional: Some(Decimal::from(CRYPTO_MAX_NOTIONAL)),
+                ..SizeRule::default()
+            },
+            option: SizeRule::default(),
+        }
+    }
+}
+
+impl SizeLimits {
+    /// Parse `order_size_limits`: an object keyed by `us_equity`, `crypto`,
+    /// or `us_option`, each overriding that class's defaults
+    pub fn from_config(value: Option<&serde_json::Value>) -> Result<Self, String> {
+        let mut limits = Self::default();
+        let Some(value) = value else {
+            return Ok(limits);
+        };
+        let object = value
+            .as_object()
+            .ok_or("order_size_limits must be an object")?;
+        for (class, rules) in object {
+            let rules = rules
+                .as_object()
+                .ok_or_else(|| format!("order_size_limits.{} must be an object", class))?;
+            let slot = match class.as_str() {
+                "us_equity" => &mut limits.equity,
+                "crypto" => &mut limits.crypto,
+                "us_option" => &mut limits.option,
+                other => {
+                    return Err(format!(
+                        "order_size_limits has unknown asset class {} (expected us_equity, crypto, or us_option)",
+                        other
+                    ))
+                }
+            };
+            *slot = SizeRule::from_object(class, rules, slot.clone())?;
+        }
+        Ok(limits)
+    }
+
+    fn rule(&self, kind: AssetKind) -> &SizeRule {
+        match kind {
+            AssetKind::Equity => &self.equity,
+            AssetKind::Crypto => &self.crypto,
+            AssetKind::Option => &self.option,
+        }
+    }
+}
+
+fn range(min: Option<Decimal>, max: Option<Decimal>) -> String {
+    match (min, max) {
+        (Some(min), Some(max)) => format!("{} to {}", decimal::to_wire(min), decimal::to_wire(max)),
+        (Some(min), None) => format!("at least {}", decimal::to_wire(min)),
+        (None, Some(max)) => format!("at most {}", decimal::to_wire(max)),
+        (None, None) => "any".to_string(),
+    }
+}
+
+fn breach(
+    constraint: &str,
+    kind: AssetKind,
+    order: &OrderRequest,
+    value: Decimal,
+    min: Option<Decimal>,
+    max: Option<Decimal>,
+    message: String,
+) -> CheckOutcome {
+    CheckOutcome::Block(Finding::new(
+        "order_size",
+        message,
+        serde_json::json!({
+            "symbol": order.symbol_id,
+            "asset_class": kind.name(),
+            "constraint": constraint,
+            "value": decimal::to_wire(value),
+            "min": min.map(decimal::to_wire),
+            "max": max.map(decimal::to_wire),
+        }),
+    ))
+}
+
+/// Check `order` against Alpaca's size rules and `limits`. The asset
+/// supplies fractionability and crypto minimums when it was fetched;
+/// notional limits need a price and are skipped without one.
+pub fn check(
+    limits: &SizeLimits,
+    order: &OrderRequest,
+    asset: Option<&AlpacaAsset>,
+    price: Option<PriceEstimate>,
+) -> CheckOutcome {
+    let kind = AssetKind::from_class(asset.and_then(|a| a.class.as_deref()), &order.symbol_id);
+    let Some(qty) = decimal::from_f64(order.quantity).filter(|q| *q > Decimal::ZERO) else {
+        // Reported by order validation
+        return CheckOutcome::Pass;
+    };
+    let qty = qty.normalize();
+    let fractional = !qty.fract().is_zero();
+    let rule = limits.rule(kind);
+    let mut min_qty = rule.min_qty;
+    let mut min_notional = rule.min_notional;
+
+    match kind {
+        AssetKind::Option if fractional => {
+            return breach(
+                "whole_contracts",
+                kind,
+                order,
+                qty,
+                Some(Decimal::ONE),
+                None,
+                format!(
+                    "Option quantity {} must be a whole number of contracts",
+                    decimal::to_wire(qty)
+                ),
+            );
+        }
+        AssetKind::Equity if fractional => {
+            if asset.is_some_and(|a| !a.fractionable) {
+                return breach(
+                    "fractionable",
+                    kind,
+                    order,
+                    qty,
+                    Some(Decimal::ONE),
+                    None,
+                    format!(
+                        "{} is not fractionable; quantity {} must be a whole number of shares",
+                        order.symbol_id,
+                        decimal::to_wire(qty)
+                    ),
+                );
+            }
+            if qty.scale() > MAX_QTY_PLACES {
+                let increment = Decimal::new(1, MAX_QTY_PLACES);
+                return breach(
+                    "qty_increment",
+                    kind,
+                    order,
+                    qty,
+                    Some(increment),
+                    None,
+                    format!(
+                        "Quantity {} has more than {} decimal places",
+                        decimal::to_wire(qty),
+                        MAX_QTY_PLACES
+                    ),
+                );
+            }
+            let fractional_min = Decimal::from(FRACTIONAL_MIN_NOTIONAL);
+            min_notional = Some(min_notional.map_or(fractional_min, |m| m.max(fractional_min)));
+        }
+        AssetKind::Crypto => {
+            let field = |raw: Option<&String>| raw.and_then(|s| decimal::parse(s));
+            if let Some(asset_min) = field(asset.and_then(|a| a.min_order_size.as_ref())) {
+                min_qty = Some(min_qty.map_or(asset_min, |m| m.max(asset_min)));
+            }
+            if let Some(increment) = field(asset.and_then(|a| a.min_trade_increment.as_ref()))
+                .filter(|i| *i > Decimal::ZERO)
+            {
+                if !(qty % increment).is_zero() {
+                    return breach(
+                        "qty_increment",
+                        kind,
+                        order,
+                        qty,
+                        Some(increment),
+                        None,
+                        format!(
+                            "Quantity {} is not a multiple of {}'s minimum increment {}",
+                            decimal::to_wire(qty),
+                            order.symbol_id,
+                            decimal::to_wire(increment)
+                        ),
+                    );
+                }
+            }
+        }
+        _ => {}
+    }
+
+    if min_qty.is_some_and(|min| qty < min) || rule.max_qty.is_some_and(|max| qty > max) {
+        return breach(
+            "qty",
+            kind,
+            order,
+            qty,
+            min_qty,
+            rule.max_qty,
+            format!(
+                "Quantity {} is outside the allowed range for {} orders: {}",
+                decimal::to_wire(qty),
+                kind.name(),
+                range(min_qty, rule.max_qty)
+            ),
+        );
+    }
+
+    let Some(estimate) = price else {
+        return CheckOutcome::Pass;
+    };
+    let contract_size = match kind {
+        AssetKind::Option => Decimal::ONE_HUNDRED,
+        _ => Decimal::ONE,
+    };
+    let notional = (qty * estimate.price * contract_size).round_dp(2);
+    if min_notional.is_some_and(|min| notional < min)
+        || rule.max_notional.is_some_and(|max| notional > max)
+    {
+        let scope = if fractional && kind == AssetKind::Equity {
+            "fractional us_equity".to_string()
+        } else {
+            kind.name().to_string()
+        };
+        return breach(
+            "notional",
+            kind,
+            order,
+            notional,
+            min_notional,
+            rule.max_notional,
+            format!(
+                "Notional {} (at {} {}) is outside the allowed range for {} orders: {}",
+                decimal::to_wire(notional),
+                estimate.source,
+                decimal::to_wire(estimate.price),
+                scope,
+                range(min_notional, rule.max_notional)
+            ),
+        );
+    }
+    CheckOutcome::Pass
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+
+    fn price(order: &OrderRequest) -> Option<PriceEstimate> {
+        crate::pretrade::estimate_price(order, None)
+    }
+
+    fn constraint(outcome: CheckOutcome) -> Option<String> {
+        match outcome {
+            CheckOutcome::Block(f) => Some(f.details["constraint"].as_str()?.to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn alpaca_minimums_and_configured_maximums_are_enforced() {
+        let limits = SizeLimits::from_config(Some(&serde_json::json!({
+            "us_equity": { "max_notional": 50000 },
+        })))
+        .unwrap();
+        let run = |o: &OrderRequest, asset: Option<&AlpacaAsset>| {
+            constraint(check(&limits, o, asset, price(o)))
+        };
+
+        // Fractional equity orders need $1 of notional
+        assert_eq!(
+            run(&order_request("AAPL").qty(0.004).limit(200.0).build(), None).as_deref(),
+            Some("notional")
+        );
+        assert_eq!(
+            run(&order_request("AAPL").qty(0.01).limit(200.0).build(), None),
+            None
+        );
+        assert_eq!(
+            run(&order_request("AAPL").qty(300.0).limit(200.0).build(), None).as_deref(),
+            Some("notional")
+        );
+
+        let asset: AlpacaAsset = serde_json::from_value(serde_json::json!({
+            "symbol": "BTC/USD", "class": "crypto", "status": "active", "tradable": true,
+            "min_order_size": "0.0001", "min_trade_increment": "0.000000001",
+        }))
+        .unwrap();
+        assert_eq!(
+            run(
+                &order_request("BTC/USD").qty(0.00005).limit(60000.0).build(),
+                Some(&asset)
+            )
+            .as_deref(),
+            Some("qty")
+        );
+        assert_eq!(
+            run(
+                &order_request("BTC/USD").qty(4.0).limit(60000.0).build(),
+                Some(&asset)
+            )
+            .as_deref(),
+            Some("notional")
+        );
+        assert_eq!(
+            run(
+                &order_request("BTC/USD").qty(0.5).limit(60000.0).build(),
+                Some(&asset)
+            ),
+            None
+        );
+        assert_eq!(
+            run(
+                &order_request("AAPL240119C00150000")
+                    .qty(1.5)
+                    .limit(2.0)
+                    .build(),
+                None
+            )
+            .as_deref(),
+            Some("whole_contracts")
+        );
+
+        let CheckOutcome::Block(finding) = check(
+            &limits,
+            &order_request("AAPL").qty(300.0).limit(200.0).build(),
+            None,
+            price(&order_request("AAPL").qty(300.0).limit(200.0).build()),
+        ) else {
+            panic!("expected a block");
+        };
+        assert_eq!(finding.details["max"], "50000");
+        assert!(finding.message.ends_with("at most 50000"));
+        assert!(SizeLimits::from_config(Some(&serde_json::json!({ "forex": {} }))).is_err());
+    }
+}
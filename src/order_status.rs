@@ -0,0 +1,94 @@
+//! Alpaca order status mapping
+//!
+//! Alpaca reports far more order states than plugin_api's `OrderStatus`
+//! distinguishes. `AlpacaOrderStatus` covers every documented value; it
+//! maps onto the closest `OrderStatus` while the raw status and, for
+//! terminal states, the reason are carried in the order extensions.
+
+use models::order::OrderStatus;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlpacaOrderStatus {
+    New,
+    PartiallyFilled,
+    Filled,
+    DoneForDay,
+    Canceled,
+    Expired,
+    Replaced,
+    PendingCancel,
+    PendingReplace,
+    PendingReview,
+    Accepted,
+    PendingNew,
+    AcceptedForBidding,
+    Stopped,
+    Rejected,
+    Suspended,
+    Calculated,
+    Held,
+    Unknown,
+}
+
+impl AlpacaOrderStatus {
+    pub fn parse(raw: &str) -> Self {
+        match raw {
+            "new" => Self::New,
+            "partially_filled" => Self::PartiallyFilled,
+            "filled" => Self::Filled,
+            "done_for_day" => Self::DoneForDay,
+            "canceled" => Self::Canceled,
+            "expired" => Self::Expired,
+            "replaced" => Self::Replaced,
+            "pending_cancel" => Self::PendingCancel,
+            "pending_replace" => Self::PendingReplace,
+            "pending_review" => Self::PendingReview,
+            "accepted" => Self::Accepted,
+            "pending_new" => Self::PendingNew,
+            "accepted_for_bidding" => Self::AcceptedForBidding,
+            "stopped" => Self::Stopped,
+            "rejected" => Self::Rejected,
+            "suspended" => Self::Suspended,
+            "calculated" => Self::Calculated,
+            "held" => Self::Held,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// No further fills or state changes will happen
+    pub fn is_terminal(self) -> bool {
+        matches!(
+            self,
+            Self::Filled | Self::Canceled | Self::Expired | Self::Replaced | Self::Rejected
+        )
+    }
+
+    /// Why a terminal order stopped working without filling completely
+    pub fn terminal_reason(self) -> Option<&'static str> {
+        match self {
+            Self::Canceled => Some("canceled"),
+            Self::Expired => Some("expired"),
+            Self::Replaced => Some("replaced"),
+            Self::Rejected => Some("rejected"),
+            _ => None,
+        }
+    }
+
+    /// Closest plugin_api status. States that can carry fills while still
+    /// working (pending cancel/replace, done for day) report
+    /// `PartiallyFilled` once anything has filled.
+    pub fn to_order_status(self, has_fills: bool) -> OrderStatus {
+        match self {
+            Self::Filled => OrderStatus::Filled,
+            Self::PartiallyFilled => OrderStatus::PartiallyFilled,
+            Self::Rejected => OrderStatus::Rejected,
+            Self::Canceled | Self::Expired | Self::Replaced => OrderStatus::Canceled,
+            Self::DoneForDay | Self::PendingCancel | Self::PendingReplace | Self::Stopped
+                if has_fills =>
+            {
+                OrderStatus::PartiallyFilled
+            }
+            _ => OrderStatus::Submitted,
+        }
+    }
+}
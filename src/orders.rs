@@ -0,0 +1,563 @@
+//! Order tracking
+//!
+//! Cached orders are refreshed by `poll_order_updates`; this module diffs
+//! the previous and refreshed views into lifecycle events, including the
+//! legs of bracket/OCO/OTO orders, whose fills happen on the legs rather
+//! than on the parent. Each refresh also records how much filled since the
+//! previous one, and how fast, in `extensions.fill_delta`. Replacements
+//! are linked through `replaces`/`replaced_by` into chains.
+
+use crate::decimal::{self, Decimal};
+use crate::metadata;
+use crate::order_status::AlpacaOrderStatus;
+use chrono::{DateTime, Utc};
+use models::order::Order;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Per-leg view of an advanced (bracket/OCO/OTO) order
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LegSummary {
+    pub id: String,
+    pub symbol: String,
+    pub side: String,
+    pub order_type: String,
+    pub alpaca_status: String,
+    pub filled_qty: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filled_avg_price: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit_price: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_price: Option<String>,
+    /// `take_profit` for limit legs, `stop_loss` for stop legs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qty: Option<String>,
+    /// The leg's own legs, when Alpaca nests them further
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub legs: Vec<LegSummary>,
+}
+
+impl LegSummary {
+    /// Whether the leg, or a leg nested under it, can still fill
+    pub fn is_working(&self) -> bool {
+        !AlpacaOrderStatus::parse(&self.alpaca_status).is_terminal()
+            || self.legs.iter().any(Self::is_working)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderEventKind {
+    StatusChanged,
+    Fill,
+    LegStatusChanged,
+    LegFill,
+    /// The order was replaced at a new limit price (chase)
+    Repriced,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OrderEvent {
+    /// Position in the journal, assigned when the event is recorded
+    pub seq: u64,
+    pub order_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leg_id: Option<String>,
+    pub kind: OrderEventKind,
+    pub alpaca_status: String,
+    pub filled_qty: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filled_avg_price: Option<String>,
+    /// For `repriced`: the order this one replaced
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replaces: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit_price: Option<String>,
+    pub detected_at: String,
+    /// The order's `extensions.metadata`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+}
+
+/// Recent order events, oldest first, for hosts that look up an order's
+/// history after the fact. Bounded so a long session cannot grow it
+/// without limit.
+pub struct EventJournal {
+    events: std::collections::VecDeque<OrderEvent>,
+    capacity: usize,
+    last_seq: u64,
+}
+
+impl Default for EventJournal {
+    fn default() -> Self {
+        Self {
+            events: std::collections::VecDeque::new(),
+            capacity: 10_000,
+            last_seq: 0,
+        }
+    }
+}
+
+impl EventJournal {
+    /// Record an event, numbering it; returns the recorded copy
+    pub fn record(&mut self, mut event: OrderEvent) -> OrderEvent {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.last_seq += 1;
+        event.seq = self.last_seq;
+        self.events.push_back(event.clone());
+        event
+    }
+
+    pub fn extend(&mut self, events: impl IntoIterator<Item = OrderEvent>) -> Vec<OrderEvent> {
+        events.into_iter().map(|e| self.record(e)).collect()
+    }
+
+    /// Events recorded after `seq`, oldest first, at most `limit`
+    pub fn since(&self, seq: u64, limit: usize) -> Vec<OrderEvent> {
+        // Sequence numbers are contiguous, so the start is found by offset
+        let first = self.events.front().map_or(0, |e| e.seq);
+        let skip = (seq + 1).saturating_sub(first) as usize;
+        self.events.iter().skip(skip).take(limit).cloned().collect()
+    }
+
+    /// Sequence number of the oldest event still held
+    pub fn first_seq(&self) -> Option<u64> {
+        self.events.front().map(|e| e.seq)
+    }
+
+    /// Events for any of `order_ids` (all events when empty)
+    pub fn for_orders(&self, order_ids: &[String]) -> Vec<OrderEvent> {
+        self.events
+            .iter()
+            .filter(|e| order_ids.is_empty() || order_ids.contains(&e.order_id))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Journal entry for a chase replacement
+pub fn repriced_event(order: &Order, replaces: &str, limit_price: String) -> OrderEvent {
+    OrderEvent {
+        seq: 0,
+        order_id: order.id.clone(),
+        leg_id: None,
+        kind: OrderEventKind::Repriced,
+        alpaca_status: ext_str(order, "alpaca_status")
+            .unwrap_or_default()
+            .to_string(),
+        filled_qty: ext_str(order, "filled_qty").unwrap_or("0").to_string(),
+        filled_avg_price: ext_str(order, "filled_avg_price").map(str::to_string),
+        replaces: Some(replaces.to_string()),
+        limit_price: Some(limit_price),
+        detected_at: Utc::now().to_rfc3339(),
+        metadata: metadata::of(order),
+    }
+}
+
+/// Read a string extension
+pub fn ext_str<'a>(order: &'a Order, key: &str) -> Option<&'a str> {
+    order
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.get(key))
+        .and_then(|v| v.as_str())
+}
+
+/// Read a boolean flag from a request's extensions (absent is false)
+pub fn request_flag(request: &models::order::OrderRequest, key: &str) -> bool {
+    request
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.get(key))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
+
+/// Point a request at `new` if it trades `old`; true when it did
+pub fn rename_symbol(request: &mut models::order::OrderRequest, old: &str, new: &str) -> bool {
+    if !request.symbol_id.eq_ignore_ascii_case(old) {
+        return false;
+    }
+    request.symbol_id = new.to_string();
+    true
+}
+
+/// Insert or replace an extension value
+pub fn set_ext(order: &mut Order, key: &str, value: impl Into<serde_json::Value>) {
+    order
+        .extensions
+        .get_or_insert_with(Default::default)
+        .insert(key.to_string(), value.into());
+}
+
+/// Whether the order itself can still change, leaving its legs aside;
+/// only such an order can be replaced or canceled
+pub fn parent_working(order: &Order) -> bool {
+    !order
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.get("is_terminal"))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
+
+/// Whether the cached order can still change. A bracket whose parent has
+/// filled keeps changing until its take-profit and stop-loss legs finish.
+pub fn is_working(order: &Order) -> bool {
+    parent_working(order) || legs(order).iter().any(LegSummary::is_working)
+}
+
+/// Legs recorded in the order's extensions
+pub fn legs(order: &Order) -> Vec<LegSummary> {
+    order
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.get("legs"))
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default()
+}
+
+/// Longest replace chain followed, in case of a malformed loop
+const MAX_CHAIN_LEN: usize = 100;
+
+/// Replacement links seen on cached orders, so a chain can be walked even
+/// when only one end has been refreshed since the replace
+#[derive(Default)]
+pub struct ReplaceChains {
+    /// Replacement ID to the order it replaced
+    replaces: HashMap<String, String>,
+    /// Replaced order ID to its replacement
+    replaced_by: HashMap<String, String>,
+}
+
+impl ReplaceChains {
+    /// Note the links carried by `order`
+    pub fn record(&mut self, order: &Order) {
+        if let Some(previous) = ext_str(order, "replaces").filter(|p| *p != order.id) {
+            self.link(previous, &order.id);
+        }
+        if let Some(next) = ext_str(order, "replaced_by").filter(|n| *n != order.id) {
+            self.link(&order.id, next);
+        }
+    }
+
+    fn link(&mut self, previous: &str, next: &str) {
+        self.replaces.insert(next.to_string(), previous.to_string());
+        self.replaced_by
+            .insert(previous.to_string(), next.to_string());
+    }
+
+    /// The order `order_id` replaced, if known
+    pub fn previous(&self, order_id: &str) -> Option<&str> {
+        self.replaces.get(order_id).map(String::as_str)
+    }
+
+    /// The order that replaced `order_id`, if known
+    pub fn next(&self, order_id: &str) -> Option<&str> {
+        self.replaced_by.get(order_id).map(String::as_str)
+    }
+
+    /// IDs in `order_id`'s chain, original order first. Just `order_id`
+    /// when it was never replaced and replaces nothing.
+    pub fn chain(&self, order_id: &str) -> Vec<String> {
+        let mut seen = HashSet::from([order_id.to_string()]);
+        let mut root = order_id;
+        while let Some(previous) = self.previous(root) {
+            if seen.len() >= MAX_CHAIN_LEN || !seen.insert(previous.to_string()) {
+                break;
+            }
+            root = previous;
+        }
+        let mut chain = vec![root.to_string()];
+        let mut seen = HashSet::from([root.to_string()]);
+        let mut last = root;
+        while let Some(next) = self.next(last) {
+            if chain.len() >= MAX_CHAIN_LEN || !seen.insert(next.to_string()) {
+                break;
+            }
+            chain.push(next.to_string());
+            last = next;
+        }
+        chain
+    }
+}
+
+/// Fill progress between two observations of the same order
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FillDelta {
+    /// Quantity filled since the previous observation
+    pub filled_qty: String,
+    /// When the previous observation was made
+    pub since: DateTime<Utc>,
+    pub elapsed_secs: f64,
+    /// `filled_qty` per minute over the interval; absent when no time passed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_per_min: Option<String>,
+}
+
+/// When the cached copy was last fetched: the plugin's own note, else
+/// Alpaca's update time
+fn observed_at(order: &Order) -> DateTime<Utc> {
+    ext_str(order, "observed_at")
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .map_or(order.updated_at, |t| t.with_timezone(&Utc))
+}
+
+fn exact_filled_qty(order: &Order) -> Decimal {
+    ext_str(order, "filled_qty")
+        .and_then(decimal::parse)
+        .or_else(|| decimal::from_f64(order.filled_quantity))
+        .unwrap_or_default()
+}
+
+/// Fill progress from `previous` to `current`, observed at `now`
+pub fn fill_delta(previous: &Order, current: &Order, now: DateTime<Utc>) -> FillDelta {
+    let since = observed_at(previous);
+    let delta = (exact_filled_qty(current) - exact_filled_qty(previous)).max(Decimal::ZERO);
+    let elapsed_ms = (now - since).num_milliseconds();
+    let rate = (elapsed_ms > 0)
+        .then(|| (delta * Decimal::from(60_000) / Decimal::from(elapsed_ms)).round_dp(6));
+    FillDelta {
+        filled_qty: decimal::to_wire(delta),
+        since,
+        elapsed_secs: elapsed_ms as f64 / 1000.0,
+        rate_per_min: rate.map(decimal::to_wire),
+    }
+}
+
+/// Carry host-side context (request, persona, plugin extensions) from the
+/// cached order onto a freshly fetched copy, noting the fill progress
+/// since the cached copy was fetched
+pub fn merge_refresh(previous: &Order, mut refreshed: Order) -> Order {
+    let now = Utc::now();
+    refreshed.request = previous.request.clone();
+    refreshed.persona_id = previous.persona_id.clone();
+    let mut extensions = previous.extensions.clone().unwrap_or_default();
+    extensions.extend(refreshed.extensions.take().unwrap_or_default());
+    refreshed.extensions = Some(extensions);
+    // A replacement is a different order with its own fills
+    if previous.id == refreshed.id {
+        let delta = fill_delta(previous, &refreshed, now);
+        set_ext(
+            &mut refreshed,
+            "fill_delta",
+            serde_json::to_value(delta).unwrap_or_default(),
+        );
+    } else if let Some(ext) = refreshed.extensions.as_mut() {
+        ext.remove("fill_delta");
+    }
+    set_ext(&mut refreshed, "observed_at", now.to_rfc3339());
+    refreshed
+}
+
+/// Events implied by moving from `previous` to `current`
+pub fn diff(previous: &Order, current: &Order) -> Vec<OrderEvent> {
+    let detected_at = Utc::now().to_rfc3339();
+    let metadata = metadata::of(current);
+    let mut events = Vec::new();
+
+    let prev_status = ext_str(previous, "alpaca_status").unwrap_or_default();
+    let status = ext_str(current, "alpaca_status").unwrap_or_default();
+    let filled_qty = ext_str(current, "filled_qty").unwrap_or("0").to_string();
+    let filled_avg_price = ext_str(current, "filled_avg_price").map(str::to_string);
+
+    if current.filled_quantity > previous.filled_quantity {
+        events.push(OrderEvent {
+            seq: 0,
+            order_id: current.id.clone(),
+            leg_id: None,
+            kind: OrderEventKind::Fill,
+            alpaca_status: status.to_string(),
+            filled_qty: filled_qty.clone(),
+            filled_avg_price: filled_avg_price.clone(),
+            replaces: None,
+            limit_price: None,
+            detected_at: detected_at.clone(),
+            metadata: metadata.clone(),
+        });
+    }
+    if prev_status != status {
+        events.push(OrderEvent {
+            seq: 0,
+            order_id: current.id.clone(),
+            leg_id: None,
+            kind: OrderEventKind::StatusChanged,
+            alpaca_status: status.to_string(),
+            filled_qty,
+            filled_avg_price,
+            replaces: None,
+            limit_price: None,
+            detected_at: detected_at.clone(),
+            metadata: metadata.clone(),
+        });
+    }
+
+    let previous_legs = legs(previous);
+    for leg in legs(current) {
+        let before = previous_legs.iter().find(|l| l.id == leg.id);
+        let filled_before = before
+            .and_then(|l| crate::decimal::parse(&l.filled_qty))
+            .unwrap_or_default();
+        let filled_now = crate::decimal::parse(&leg.filled_qty).unwrap_or_default();
+
+        if filled_now > filled_before {
+            events.push(leg_event(
+                current,
+                &leg,
+                OrderEventKind::LegFill,
+                &detected_at,
+            ));
+        }
+        if before
+            .map(|l| l.alpaca_status != leg.alpaca_status)
+            .unwrap_or(true)
+        {
+            events.push(leg_event(
+                current,
+                &leg,
+                OrderEventKind::LegStatusChanged,
+                &detected_at,
+            ));
+        }
+    }
+
+    events
+}
+
+fn leg_event(parent: &Order, leg: &LegSummary, kind: OrderEventKind, at: &str) -> OrderEvent {
+    OrderEvent {
+        seq: 0,
+        order_id: parent.id.clone(),
+        leg_id: Some(leg.id.clone()),
+        kind,
+        alpaca_status: leg.alpaca_status.clone(),
+        filled_qty: leg.filled_qty.clone(),
+        filled_avg_price: leg.filled_avg_price.clone(),
+        replaces: None,
+        limit_price: None,
+        detected_at: at.to_string(),
+        metadata: metadata::of(parent),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+    use chrono::Duration;
+    use models::order::OrderStatus;
+
+    fn order(filled_qty: &str, updated_at: DateTime<Utc>) -> Order {
+        serde_json::from_value(serde_json::json!({
+            "id": "o1",
+            "request": order_request("AAPL").qty(100.0).build(),
+            "status": OrderStatus::PartiallyFilled,
+            "created_at": updated_at,
+            "updated_at": updated_at,
+            "average_filled_price": null,
+            "filled_quantity": filled_qty.parse::<f64>().unwrap(),
+            "extensions": { "filled_qty": filled_qty },
+            "persona_id": "default",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn refreshes_record_fill_progress_since_the_last_observation() {
+        let start = Utc::now() - Duration::seconds(30);
+        let previous = order("10", start);
+        let delta = fill_delta(
+            &previous,
+            &order("25", start),
+            start + Duration::seconds(30),
+        );
+        assert_eq!(delta.filled_qty, "15");
+        assert_eq!(delta.elapsed_secs, 30.0);
+        assert_eq!(
+            delta.rate_per_min.as_deref().and_then(decimal::parse),
+            Some(Decimal::from(30))
+        );
+
+        // The refresh stamps when it was observed, which the next delta
+        // measures from
+        let refreshed = merge_refresh(&previous, order("25", start));
+        let since = observed_at(&refreshed);
+        assert!(since > start);
+        let next = fill_delta(&refreshed, &order("25", start), since);
+        assert_eq!(next.filled_qty, "0");
+        assert_eq!(next.rate_per_min, None);
+    }
+
+    #[test]
+    fn replace_links_from_either_end_form_one_chain() {
+        let now = Utc::now();
+        let mut chains = ReplaceChains::default();
+        let mut first = order("0", now);
+        first.id = "a".to_string();
+        set_ext(&mut first, "replaced_by", "b");
+        // "c" was refreshed after the second replace, "b" never was
+        let mut third = order("0", now);
+        third.id = "c".to_string();
+        set_ext(&mut third, "replaces", "b");
+        chains.record(&first);
+        chains.record(&third);
+        assert_eq!(chains.chain("b"), ["a", "b", "c"]);
+        assert_eq!(chains.chain("c"), ["a", "b", "c"]);
+        assert_eq!(chains.chain("z"), ["z"]);
+
+        // A malformed loop still ends
+        let mut loop_back = order("0", now);
+        loop_back.id = "c".to_string();
+        set_ext(&mut loop_back, "replaced_by", "a");
+        chains.record(&loop_back);
+        assert_eq!(chains.chain("b").len(), 3);
+    }
+
+    #[test]
+    fn bracket_stays_working_until_its_legs_finish() {
+        let now = Utc::now();
+        let leg = |id: &str, role: &str, status: &str, filled: &str| {
+            serde_json::json!({
+                "id": id,
+                "symbol": "AAPL",
+                "side": "sell",
+                "order_type": if role == "take_profit" { "limit" } else { "stop" },
+                "alpaca_status": status,
+                "filled_qty": filled,
+                "role": role,
+            })
+        };
+        let bracket = |legs: serde_json::Value| {
+            let mut order = order("100", now);
+            order.status = OrderStatus::Filled;
+            set_ext(&mut order, "alpaca_status", "filled");
+            set_ext(&mut order, "is_terminal", true);
+            set_ext(&mut order, "legs", legs);
+            order
+        };
+
+        // The parent filled; both exits are resting
+        let parent_filled = bracket(serde_json::json!([
+            leg("tp", "take_profit", "new", "0"),
+            leg("sl", "stop_loss", "held", "0"),
+        ]));
+        assert!(!parent_working(&parent_filled));
+        assert!(is_working(&parent_filled));
+
+        // Then the take-profit fills and the stop-loss is canceled
+        let leg_filled = bracket(serde_json::json!([
+            leg("tp", "take_profit", "filled", "100"),
+            leg("sl", "stop_loss", "canceled", "0"),
+        ]));
+        let events = diff(&parent_filled, &leg_filled);
+        assert!(events.iter().any(
+            |e| matches!(e.kind, OrderEventKind::LegFill) && e.leg_id.as_deref() == Some("tp")
+        ));
+        assert!(!is_working(&leg_filled));
+    }
+}
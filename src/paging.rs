@@ -0,0 +1,255 @@
+//! Pipelined pagination
+//!
+//! Alpaca's paginated endpoints (account activities, bars) hand out the
+//! next page token with each page, so pages must be requested one after
+//! another. What can overlap is the work in between: [`fetch_pages`]
+//! requests page N+1 while the caller is still decoding and mapping page
+//! N, which on multi-page pulls hides most of the mapping time behind the
+//! network.
+//!
+//! `prefetch` caps how many pages may be fetched ahead of the caller. Every
+//! request still goes through the client's pipeline, so the rate limiter
+//! sees them like any other call: when the budget is spent the fetcher
+//! waits there rather than getting further ahead. With `prefetch` at 0, and
+//! always on wasm32 where the plugin has no threads, pages are fetched and
+//! mapped in turn.
+
+use crate::trace;
+
+/// Pages fetched ahead of the mapper unless `page_prefetch` is configured
+pub const DEFAULT_PAGE_PREFETCH: usize = 2;
+
+/// Walk a paginated endpoint from the first page.
+///
+/// `fetch` gets the page token (`None` for the first page), `next_token`
+/// reads the following token off a fetched page (`None` on the last page),
+/// and `map` consumes pages in order. The first error from either `fetch`
+/// or `map` ends the walk and is returned.
+pub fn fetch_pages<P, F, N, M>(
+    prefetch: usize,
+    fetch: F,
+    next_token: N,
+    map: M,
+) -> Result<(), String>
+where
+    P: Send,
+    F: Fn(Option<String>) -> Result<P, String> + Sync,
+    N: Fn(&P) -> Option<String> + Sync,
+    M: FnMut(P) -> Result<(), String>,
+{
+    if prefetch == 0 || cfg!(target_arch = "wasm32") {
+        sequential(fetch, next_token, map)
+    } else {
+        pipelined(prefetch, fetch, next_token, map)
+    }
+}
+
+fn sequential<P, F, N, M>(fetch: F, next_token: N, mut map: M) -> Result<(), String>
+where
+    F: Fn(Option<String>) -> Result<P, String>,
+    N: Fn(&P) -> Option<String>,
+    M: FnMut(P) -> Result<(), String>,
+{
+    let mut token = None;
+    loop {
+        let page = fetch(token)?;
+        token = next_token(&page);
+        map(page)?;
+        if token.is_none() {
+            return Ok(());
+        }
+    }
+}
+
+fn pipelined<P, F, N, M>(prefetch: usize, fetch: F, next_token: N, mut map: M) -> Result<(), String>
+where
+    P: Send,
+    F: Fn(Option<String>) -> Result<P, String> + Sync,
+    N: Fn(&P) -> Option<String> + Sync,
+    M: FnMut(P) -> Result<(), String>,
+{
+    // One page is in flight while the channel holds the rest, so the
+    // fetcher is at most `prefetch` pages ahead
+    let (tx, rx) = std::sync::mpsc::sync_channel::<Result<P, String>>(prefetch - 1);
+    let trace_id = trace::current();
+    let (fetch, next_token) = (&fetch, &next_token);
+
+    std::thread::scope(|scope| {
+        // The sender moves into the fetcher so the channel closes when it
+        // returns
+        scope.spawn(move || {
+            // Requests from the fetcher carry the caller's trace ID
+            trace::set(trace_id);
+            let mut token = None;
+            loop {
+                let page = fetch(token);
+                token = page.as_ref().ok().and_then(next_token);
+                let last = page.is_err() || token.is_none();
+                // A closed channel means the mapper stopped early
+                if tx.send(page).is_err() || last {
+                    return;
+                }
+            }
+        });
+
+        // Dropping the receiver on an error stops the fetcher at its next
+        // send
+        for page in rx {
+            map(page?)?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alpaca::{AlpacaClient, ClientOptions};
+    use crate::http::HttpMethod;
+    use crate::mock::{response, MockTransport};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    const PAGES: usize = 5;
+
+    fn fetch_numbered(token: Option<String>) -> Result<usize, String> {
+        Ok(token.map(|t| t.parse().unwrap()).unwrap_or(0))
+    }
+
+    fn next_numbered(page: &usize) -> Option<String> {
+        (page + 1 < PAGES).then(|| (page + 1).to_string())
+    }
+
+    #[test]
+    fn pages_are_mapped_in_order() {
+        for prefetch in [0, 1, 3] {
+            let mut seen = Vec::new();
+            fetch_pages(prefetch, fetch_numbered, next_numbered, |page| {
+                seen.push(page);
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(seen, [0, 1, 2, 3, 4], "prefetch {}", prefetch);
+        }
+    }
+
+    #[test]
+    fn next_page_is_fetched_while_mapping() {
+        let (started_tx, started_rx) = mpsc::channel();
+        let started_tx = std::sync::Mutex::new(started_tx);
+        let fetch = |token: Option<String>| {
+            let page = fetch_numbered(token)?;
+            started_tx.lock().unwrap().send(page).unwrap();
+            Ok(page)
+        };
+        fetch_pages(1, fetch, next_numbered, |page| {
+            // Mapping page N does not finish until page N+1 was requested
+            if page + 1 < PAGES {
+                let mut next = started_rx.recv_timeout(Duration::from_secs(5));
+                while next == Ok(page) {
+                    next = started_rx.recv_timeout(Duration::from_secs(5));
+                }
+                assert_eq!(next, Ok(page + 1));
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn prefetch_bounds_pages_ahead() {
+        let fetched = AtomicUsize::new(0);
+        let fetch = |token| {
+            fetched.fetch_add(1, Ordering::SeqCst);
+            fetch_numbered(token)
+        };
+        let mut mapped = 0;
+        fetch_pages(2, fetch, next_numbered, |_| {
+            std::thread::sleep(Duration::from_millis(20));
+            mapped += 1;
+            assert!(fetched.load(Ordering::SeqCst) <= mapped + 2);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(fetched.load(Ordering::SeqCst), PAGES);
+    }
+
+    #[test]
+    fn errors_stop_the_walk() {
+        for prefetch in [0, 2] {
+            let fetched = AtomicUsize::new(0);
+            let fetch = |token: Option<String>| {
+                fetched.fetch_add(1, Ordering::SeqCst);
+                match fetch_numbered(token)? {
+                    2 => Err("API error 500".to_string()),
+                    page => Ok(page),
+                }
+            };
+            let mut seen = Vec::new();
+            let err = fetch_pages(prefetch, fetch, next_numbered, |page| {
+                seen.push(page);
+                Ok(())
+            })
+            .unwrap_err();
+            assert_eq!(err, "API error 500");
+            assert_eq!(seen, [0, 1]);
+            assert_eq!(fetched.load(Ordering::SeqCst), 3);
+
+            let err = fetch_pages(prefetch, fetch_numbered, next_numbered, |page| match page {
+                1 => Err("Decode error".to_string()),
+                _ => Ok(()),
+            })
+            .unwrap_err();
+            assert_eq!(err, "Decode error");
+        }
+    }
+
+    fn fill(n: usize) -> serde_json::Value {
+        serde_json::json!({
+            "id": format!("20240102093000000::{:08}", n),
+            "activity_type": "FILL",
+            "order_id": "ord-1",
+            "symbol": "AAPL",
+            "side": "buy",
+            "qty": "1",
+            "price": "187.25",
+            "transaction_time": "2024-01-02T14:30:00Z",
+            "type": "partial_fill",
+        })
+    }
+
+    #[test]
+    fn activities_follow_page_tokens() {
+        let mock = MockTransport::new();
+        let first: Vec<_> = (0..100).map(fill).collect();
+        let second: Vec<_> = (100..130).map(fill).collect();
+        mock.on(
+            HttpMethod::Get,
+            "/v2/account/activities",
+            response(200, serde_json::to_string(&first).unwrap()),
+        )
+        .on(
+            HttpMethod::Get,
+            "/v2/account/activities",
+            response(200, serde_json::to_string(&second).unwrap()),
+        );
+        let client = AlpacaClient::with_transport(
+            "key".to_string(),
+            "secret".to_string(),
+            true,
+            ClientOptions::default(),
+            mock.clone(),
+        );
+
+        let fills = client.list_fill_activities(None, None).unwrap();
+        assert_eq!(fills.len(), 130);
+        assert_eq!(fills[129].id, "20240102093000000::00000129");
+        let sent = mock.requests_to(HttpMethod::Get, "/v2/account/activities");
+        assert_eq!(sent.len(), 2);
+        assert!(!sent[0].url.contains("page_token"));
+        assert!(sent[1]
+            .url
+            .contains("page_token=20240102093000000%3A%3A00000099"));
+    }
+}
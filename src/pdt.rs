@@ -0,0 +1,134 @@
+//! Pattern-day-trader protection
+//!
+//! FINRA flags margin accounts under $25k that make four or more day
+//! trades in five business days. This check spots an order that would
+//! close a position opened today and, given the account's day-trade count
+//! and equity, reports it before Alpaca rejects it (or flags the account).
+
+use crate::alpaca::AlpacaAccount;
+use crate::decimal::{self, Decimal};
+use crate::executions::Execution;
+use crate::market_time;
+use crate::pretrade::{CheckMode, CheckOutcome, Finding};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc, Weekday};
+use models::order::{OrderRequest, OrderSide};
+
+pub const PDT_EQUITY_THRESHOLD: i64 = 25_000;
+
+/// Day trades allowed in the rolling five-day window before PDT flagging
+pub const MAX_DAY_TRADES: i32 = 3;
+
+/// Business days in the rolling day-trade window
+pub const WINDOW_BUSINESS_DAYS: u32 = 5;
+
+/// Whether `order` would round-trip a position opened today. Short-sale
+/// fills (`sell_short`) count as sells, and symbols match in any case.
+pub fn would_day_trade(order: &OrderRequest, fills: &[Execution]) -> bool {
+    let today = market_time::eastern_today();
+    let closes_sell = matches!(order.side, OrderSide::Buy);
+    fills.iter().any(|f| {
+        f.symbol.eq_ignore_ascii_case(&order.symbol_id)
+            && f.side.starts_with("sell") == closes_sell
+            && market_time::eastern_date(f.timestamp) == today
+    })
+}
+
+/// Start of the rolling window of [`WINDOW_BUSINESS_DAYS`] weekdays
+/// ending `today`. Holidays are not skipped, so the window can reach a
+/// little further back than FINRA's.
+pub fn window_start(today: NaiveDate) -> DateTime<Utc> {
+    let mut start = today;
+    let mut weekdays = 1;
+    while weekdays < WINDOW_BUSINESS_DAYS {
+        start -= Duration::days(1);
+        if !matches!(start.weekday(), Weekday::Sat | Weekday::Sun) {
+            weekdays += 1;
+        }
+    }
+    market_time::eastern_at(start, NaiveTime::MIN)
+}
+
+pub fn check(
+    mode: CheckMode,
+    order: &OrderRequest,
+    account: &AlpacaAccount,
+    fills: &[Execution],
+) -> CheckOutcome {
+    if mode == CheckMode::Off || !would_day_trade(order, fills) {
+        return CheckOutcome::Pass;
+    }
+
+    let equity = decimal::parse_or_zero(&account.equity);
+    if equity >= Decimal::from(PDT_EQUITY_THRESHOLD) {
+        return CheckOutcome::Pass;
+    }
+
+    let daytrade_count = account.daytrade_count.unwrap_or(0);
+    let flagged = account.pattern_day_trader.unwrap_or(false);
+    if !flagged && daytrade_count < MAX_DAY_TRADES {
+        return CheckOutcome::Pass;
+    }
+
+    let message = if flagged {
+        "Account is flagged as a pattern day trader with equity below $25,000; this order would be a day trade"
+    } else {
+        "This order would be a day trade and exceed the 3 day trades allowed in 5 business days for accounts under $25,000"
+    };
+    mode.apply(Finding::new(
+        "pdt_risk",
+        message,
+        serde_json::json!({
+            "symbol": order.symbol_id,
+            "daytrade_count": daytrade_count,
+            "pattern_day_trader": flagged,
+            "equity": decimal::to_wire(equity),
+            "equity_threshold": PDT_EQUITY_THRESHOLD,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+    use chrono::TimeZone;
+
+    fn fill(symbol: &str, side: &str) -> Execution {
+        Execution {
+            id: format!("{}-{}", symbol, side),
+            order_id: "o1".to_string(),
+            symbol: symbol.to_string(),
+            side: side.to_string(),
+            qty: "10".to_string(),
+            price: "100".to_string(),
+            timestamp: Utc::now(),
+            fill_type: "fill".to_string(),
+            cum_qty: None,
+            leaves_qty: None,
+            venue: None,
+        }
+    }
+
+    #[test]
+    fn short_sales_and_symbol_case_still_count_as_day_trades() {
+        let cover = order_request("aapl").side(OrderSide::Buy).build();
+        assert!(would_day_trade(&cover, &[fill("AAPL", "sell_short")]));
+        assert!(!would_day_trade(&cover, &[fill("AAPL", "buy")]));
+
+        let sell = order_request("AAPL").side(OrderSide::Sell).build();
+        assert!(would_day_trade(&sell, &[fill("aapl", "buy")]));
+        assert!(!would_day_trade(&sell, &[fill("AAPL", "sell_short")]));
+    }
+
+    #[test]
+    fn window_spans_five_weekdays() {
+        // Monday back to the Tuesday before
+        let monday = NaiveDate::from_ymd_opt(2024, 3, 11).unwrap();
+        let start = window_start(monday);
+        assert_eq!(
+            start,
+            market_time::eastern_at(NaiveDate::from_ymd_opt(2024, 3, 5).unwrap(), NaiveTime::MIN)
+        );
+        assert!(start < Utc.with_ymd_and_hms(2024, 3, 5, 6, 0, 0).unwrap());
+    }
+}
@@ -0,0 +1,371 @@
+//! Realized PnL and tax lots from fills
+//!
+//! Replays executions in time order through per-persona, per-symbol tax
+//! lots. A fill against the open direction closes lots (FIFO by default,
+//! or the order's own lot selection) and realizes PnL; any remainder opens
+//! a new lot, so shorts are handled the same way as longs. Alpaca does not
+//! support lot selection itself, so it only affects the plugin's books.
+
+use crate::decimal::{self, Decimal};
+use crate::executions::Execution;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Which open lots a closing fill consumes first
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LotMethod {
+    #[default]
+    Fifo,
+    Lifo,
+    HighestCost,
+}
+
+impl LotMethod {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "fifo" => Some(Self::Fifo),
+            "lifo" => Some(Self::Lifo),
+            "highest_cost" | "hifo" => Some(Self::HighestCost),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Fifo => "fifo",
+            Self::Lifo => "lifo",
+            Self::HighestCost => "highest_cost",
+        }
+    }
+}
+
+/// Per-order lot selection: explicit lot IDs first, then the method
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct LotSelection {
+    pub method: LotMethod,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lot_ids: Vec<String>,
+}
+
+impl LotSelection {
+    /// Read `lot_selection` (and `lot_ids` for specific lots) from order
+    /// extensions. `Ok(None)` when the order does not choose.
+    pub fn from_extensions(
+        extensions: Option<&HashMap<String, serde_json::Value>>,
+    ) -> Result<Option<Self>, String> {
+        let Some(ext) = extensions else {
+            return Ok(None);
+        };
+        let method = ext.get("lot_selection").and_then(|v| v.as_str());
+        let lot_ids: Vec<String> = match ext.get("lot_ids") {
+            None | Some(serde_json::Value::Null) => Vec::new(),
+            Some(v) => serde_json::from_value(v.clone())
+                .map_err(|_| "lot_ids must be an array of lot ID strings".to_string())?,
+        };
+
+        match method {
+            None if lot_ids.is_empty() => Ok(None),
+            None | Some("specific") => {
+                if lot_ids.is_empty() {
+                    return Err("lot_selection \"specific\" requires lot_ids".to_string());
+                }
+                Ok(Some(Self {
+                    method: LotMethod::default(),
+                    lot_ids,
+                }))
+            }
+            Some(name) => {
+                let method = LotMethod::parse(name).ok_or_else(|| {
+                    format!(
+                        "Unsupported lot_selection: {} (expected fifo, lifo, highest_cost, or specific)",
+                        name
+                    )
+                })?;
+                Ok(Some(Self { method, lot_ids }))
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "method": if self.lot_ids.is_empty() { self.method.as_str() } else { "specific" },
+            "lot_ids": self.lot_ids,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Lot {
+    /// Execution ID of the opening fill; used for specific-lot selection
+    pub lot_id: String,
+    pub symbol: String,
+    pub persona_id: String,
+    /// Positive for long lots, negative for short lots
+    pub qty: Decimal,
+    pub price: Decimal,
+    pub opened_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RealizedTrade {
+    pub symbol: String,
+    pub persona_id: String,
+    pub order_id: String,
+    pub execution_id: String,
+    pub lot_id: String,
+    pub lot_method: LotMethod,
+    pub qty: Decimal,
+    pub open_price: Decimal,
+    pub close_price: Decimal,
+    pub pnl: Decimal,
+    pub opened_at: DateTime<Utc>,
+    pub closed_at: DateTime<Utc>,
+    /// The closed lot was short
+    #[serde(skip)]
+    pub short: bool,
+}
+
+impl RealizedTrade {
+    /// PnL per unit of price move: 1 for a long lot, -1 for a short one
+    pub fn direction(&self) -> Decimal {
+        if self.short {
+            -Decimal::ONE
+        } else {
+            Decimal::ONE
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PnlLedger {
+    /// Open lots keyed by (persona_id, symbol), oldest first
+    pub lots: HashMap<(String, String), Vec<Lot>>,
+    pub realized: Vec<RealizedTrade>,
+}
+
+/// What the ledger needs to know about the order behind a fill
+#[derive(Clone, Debug, Default)]
+pub struct OrderContext {
+    pub persona_id: String,
+    pub lot_selection: Option<LotSelection>,
+}
+
+impl PnlLedger {
+    /// Replay `executions` (any order) into lots and realized trades.
+    /// `context_of` describes the order behind each fill; orders without
+    /// their own lot selection use `default_method`.
+    pub fn build<F>(executions: &[Execution], default_method: LotMethod, context_of: F) -> Self
+    where
+        F: Fn(&str) -> OrderContext,
+    {
+        let mut sorted: Vec<&Execution> = executions.iter().collect();
+        sorted.sort_by_key(|e| e.timestamp);
+
+        let mut ledger = Self::default();
+        for execution in sorted {
+            let context = context_of(&execution.order_id);
+            let selection = context.lot_selection.unwrap_or(LotSelection {
+                method: default_method,
+                lot_ids: Vec::new(),
+            });
+            ledger.apply(execution, &selection, context.persona_id);
+        }
+        ledger
+    }
+
+    /// Open lots, optionally filtered by symbol and persona
+    pub fn open_lots(&self, symbol: Option<&str>, persona_id: Option<&str>) -> Vec<Lot> {
+        let mut out: Vec<Lot> = self
+            .lots
+            .iter()
+            .filter(|((persona, sym), _)| {
+                symbol.is_none_or(|s| s == sym) && persona_id.is_none_or(|p| p == persona)
+            })
+            .flat_map(|(_, lots)| lots.iter().cloned())
+            .collect();
+        out.sort_by(|a, b| (&a.symbol, a.opened_at).cmp(&(&b.symbol, b.opened_at)));
+        out
+    }
+
+    fn pick_lot(lots: &[Lot], selection: &LotSelection, closing_short: bool) -> Option<usize> {
+        let eligible = |l: &Lot| l.qty.is_sign_negative() == closing_short;
+
+        for id in &selection.lot_ids {
+            if let Some(i) = lots.iter().position(|l| eligible(l) && &l.lot_id == id) {
+                return Some(i);
+            }
+        }
+        match selection.method {
+            LotMethod::Fifo => lots.iter().position(eligible),
+            LotMethod::Lifo => lots.iter().rposition(eligible),
+            LotMethod::HighestCost => lots
+                .iter()
+                .enumerate()
+                .filter(|(_, l)| eligible(l))
+                // Highest cost for longs; for shorts the lowest proceeds
+                .max_by(|(_, a), (_, b)| {
+                    if closing_short {
+                        b.price.cmp(&a.price)
+                    } else {
+                        a.price.cmp(&b.price)
+                    }
+                })
+                .map(|(i, _)| i),
+        }
+    }
+
+    fn apply(&mut self, execution: &Execution, selection: &LotSelection, persona_id: String) {
+        let qty = decimal::parse_or_zero(&execution.qty);
+        let price = decimal::parse_or_zero(&execution.price);
+        let signed = if execution.side.starts_with("sell") {
+            -qty
+        } else {
+            qty
+        };
+        if signed.is_zero() {
+            return;
+        }
+
+        let lots = self
+            .lots
+            .entry((persona_id.clone(), execution.symbol.clone()))
+            .or_default();
+        let mut remaining = signed;
+
+        while !remaining.is_zero() {
+            // Only lots on the opposite side of this fill can be closed:
+            // buys close short lots, sells close long lots
+            let closing_short = !remaining.is_sign_negative();
+            let Some(index) = Self::pick_lot(lots, selection, closing_short) else {
+                break;
+            };
+
+            let lot = &mut lots[index];
+            let closing = remaining.abs().min(lot.qty.abs());
+            let direction = if lot.qty.is_sign_negative() {
+                -Decimal::ONE
+            } else {
+                Decimal::ONE
+            };
+
+            self.realized.push(RealizedTrade {
+                symbol: execution.symbol.clone(),
+                persona_id: persona_id.clone(),
+                order_id: execution.order_id.clone(),
+                execution_id: execution.id.clone(),
+                lot_id: lot.lot_id.clone(),
+                lot_method: selection.method,
+                qty: closing,
+                open_price: lot.price,
+                close_price: price,
+                pnl: (price - lot.price) * closing * direction,
+                opened_at: lot.opened_at,
+                closed_at: execution.timestamp,
+                short: lot.qty.is_sign_negative(),
+            });
+
+            lot.qty -= closing * direction;
+            remaining += closing * direction;
+            if lot.qty.is_zero() {
+                lots.remove(index);
+            }
+        }
+
+        if !remaining.is_zero() {
+            lots.push(Lot {
+                lot_id: execution.id.clone(),
+                symbol: execution.symbol.clone(),
+                persona_id,
+                qty: remaining,
+                price,
+                opened_at: execution.timestamp,
+            });
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RealizedPnlReport {
+    pub total: Decimal,
+    pub by_symbol: BTreeMap<String, Decimal>,
+    pub by_persona: BTreeMap<String, Decimal>,
+    pub trades: Vec<RealizedTrade>,
+}
+
+/// Filter for `get_realized_pnl`; every field is optional
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct RealizedPnlQuery {
+    #[serde(default)]
+    pub symbol: Option<String>,
+    #[serde(default)]
+    pub persona_id: Option<String>,
+    #[serde(default)]
+    pub start: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub end: Option<DateTime<Utc>>,
+}
+
+impl RealizedPnlReport {
+    pub fn from_trades(trades: &[RealizedTrade], query: &RealizedPnlQuery) -> Self {
+        let mut report = Self::default();
+        for trade in trades {
+            let matches = query.symbol.as_ref().is_none_or(|s| *s == trade.symbol)
+                && query
+                    .persona_id
+                    .as_ref()
+                    .is_none_or(|p| *p == trade.persona_id)
+                && query.start.is_none_or(|s| trade.closed_at >= s)
+                && query.end.is_none_or(|e| trade.closed_at <= e);
+            if !matches {
+                continue;
+            }
+            report.total += trade.pnl;
+            *report.by_symbol.entry(trade.symbol.clone()).or_default() += trade.pnl;
+            *report
+                .by_persona
+                .entry(trade.persona_id.clone())
+                .or_default() += trade.pnl;
+            report.trades.push(trade.clone());
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(id: &str, side: &str, qty: &str, price: &str, timestamp: &str) -> Execution {
+        Execution {
+            id: id.to_string(),
+            order_id: id.to_string(),
+            symbol: "AAPL".to_string(),
+            side: side.to_string(),
+            qty: qty.to_string(),
+            price: price.to_string(),
+            timestamp: timestamp.parse().unwrap(),
+            fill_type: "fill".to_string(),
+            cum_qty: None,
+            leaves_qty: None,
+            venue: None,
+        }
+    }
+
+    #[test]
+    fn sell_short_fills_open_short_lots_that_a_buy_covers() {
+        let fills = vec![
+            fill("e1", "sell_short", "10", "100", "2024-03-04T15:00:00Z"),
+            fill("e2", "buy", "10", "90", "2024-03-04T16:00:00Z"),
+        ];
+        let ledger = PnlLedger::build(&fills, LotMethod::Fifo, |_| OrderContext::default());
+
+        assert_eq!(ledger.realized.len(), 1);
+        let trade = &ledger.realized[0];
+        assert!(trade.short);
+        assert_eq!(trade.qty, Decimal::from(10));
+        assert_eq!(trade.pnl, Decimal::from(100));
+        assert!(ledger.open_lots(None, None).is_empty());
+    }
+}
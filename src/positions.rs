@@ -0,0 +1,156 @@
+//! Position change tracking for `get_position_changes`
+//!
+//! The last positions handed to the host are kept by symbol. Each call
+//! diffs a fresh fetch against them and returns only what moved, plus a
+//! zero-quantity entry for every symbol that was closed out, so a large
+//! account does not resend hundreds of unchanged positions each poll.
+
+use models::portfolio::Position;
+use std::collections::BTreeMap;
+
+/// Which fields make a position count as changed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChangeScope {
+    /// Any field, including price and unrealized PnL
+    #[default]
+    All,
+    /// Quantity and average price only, ignoring mark-to-market moves
+    Holdings,
+}
+
+impl ChangeScope {
+    fn differs(self, a: &Position, b: &Position) -> bool {
+        match self {
+            ChangeScope::All => {
+                a.quantity != b.quantity
+                    || a.average_price != b.average_price
+                    || a.current_price != b.current_price
+                    || a.unrealized_pnl != b.unrealized_pnl
+                    || a.unrealized_pnl_percent != b.unrealized_pnl_percent
+            }
+            ChangeScope::Holdings => a.quantity != b.quantity || a.average_price != b.average_price,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PositionTracker {
+    /// `None` until the first call, which reports every position
+    last: Option<BTreeMap<String, Position>>,
+}
+
+pub struct PositionChanges {
+    pub changed: Vec<Position>,
+    pub unchanged: usize,
+    /// Nothing had been sent before, so `changed` is the full set
+    pub full: bool,
+}
+
+impl PositionTracker {
+    /// Forget the last snapshot; the next diff reports everything
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+
+    /// Carry the last snapshot of `old` over to `new` after a ticker
+    /// change, so the next diff does not report a close and a new position
+    pub fn rename_symbol(&mut self, old: &str, new: &str) -> bool {
+        let Some(last) = self.last.as_mut() else {
+            return false;
+        };
+        let Some(mut position) = last.remove(old) else {
+            return false;
+        };
+        position.symbol_id = new.to_string();
+        last.insert(new.to_string(), position);
+        true
+    }
+
+    /// Diff `current` against the last snapshot and make it the new one
+    pub fn diff(&mut self, current: Vec<Position>, scope: ChangeScope) -> PositionChanges {
+        let current: BTreeMap<String, Position> = current
+            .into_iter()
+            .map(|p| (p.symbol_id.clone(), p))
+            .collect();
+        let Some(last) = self.last.replace(current.clone()) else {
+            return PositionChanges {
+                changed: current.into_values().collect(),
+                unchanged: 0,
+                full: true,
+            };
+        };
+
+        let mut changed = Vec::new();
+        let mut unchanged = 0;
+        for (symbol, position) in &current {
+            match last.get(symbol) {
+                Some(previous) if !scope.differs(previous, position) => unchanged += 1,
+                _ => changed.push(position.clone()),
+            }
+        }
+        for (symbol, previous) in last {
+            if !current.contains_key(&symbol) {
+                changed.push(Position {
+                    quantity: 0.0,
+                    unrealized_pnl: 0.0,
+                    unrealized_pnl_percent: 0.0,
+                    ..previous
+                });
+            }
+        }
+        PositionChanges {
+            changed,
+            unchanged,
+            full: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(symbol: &str, quantity: f64, current_price: f64) -> Position {
+        serde_json::from_value(serde_json::json!({
+            "symbol_id": symbol,
+            "quantity": quantity,
+            "average_price": 100.0,
+            "current_price": current_price,
+            "unrealized_pnl": (current_price - 100.0) * quantity,
+            "unrealized_pnl_percent": current_price - 100.0,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn reports_changes_and_closed_symbols() {
+        let mut tracker = PositionTracker::default();
+        let first = tracker.diff(
+            vec![position("AAPL", 10.0, 101.0), position("MSFT", 5.0, 102.0)],
+            ChangeScope::All,
+        );
+        assert!(first.full);
+        assert_eq!(first.changed.len(), 2);
+
+        let second = tracker.diff(
+            vec![position("AAPL", 10.0, 103.0), position("TSLA", 1.0, 100.0)],
+            ChangeScope::All,
+        );
+        assert!(!second.full);
+        let symbols: Vec<_> = second
+            .changed
+            .iter()
+            .map(|p| p.symbol_id.as_str())
+            .collect();
+        assert_eq!(symbols, ["AAPL", "TSLA", "MSFT"]);
+        assert_eq!(second.changed[2].quantity, 0.0);
+
+        // A price-only move is not a holdings change
+        let third = tracker.diff(
+            vec![position("AAPL", 10.0, 99.0), position("TSLA", 1.0, 100.0)],
+            ChangeScope::Holdings,
+        );
+        assert!(third.changed.is_empty());
+        assert_eq!(third.unchanged, 2);
+    }
+}
@@ -0,0 +1,396 @@
+//! Pre-trade checks
+//!
+//! Local validations run by `submit_order` before anything is sent to
+//! Alpaca. Each check yields a `CheckOutcome`; warnings are attached to the
+//! resulting order's `extensions.warnings`, and a block rejects the order
+//! locally with the finding in `extensions.rejection`.
+
+use crate::alpaca::AlpacaAccount;
+use crate::decimal::{self, Decimal};
+use crate::reserved::ReservedFunds;
+use models::order::{OrderRequest, OrderSide};
+use models::portfolio::Position;
+use serde::Serialize;
+
+/// How a check reacts to a finding
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckMode {
+    Off,
+    #[default]
+    Warn,
+    Enforce,
+}
+
+impl CheckMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "off" | "allow" => Some(Self::Off),
+            "warn" => Some(Self::Warn),
+            "enforce" | "block" => Some(Self::Enforce),
+            _ => None,
+        }
+    }
+
+    /// Turn a finding into the outcome this mode calls for
+    pub fn apply(self, finding: Finding) -> CheckOutcome {
+        match self {
+            Self::Off => CheckOutcome::Pass,
+            Self::Warn => CheckOutcome::Warn(finding),
+            Self::Enforce => CheckOutcome::Block(finding),
+        }
+    }
+}
+
+/// A structured problem found by a check
+#[derive(Clone, Debug, Serialize)]
+pub struct Finding {
+    /// Stable machine-readable identifier, e.g. `pdt_risk`
+    pub code: String,
+    pub message: String,
+    pub details: serde_json::Value,
+}
+
+impl Finding {
+    pub fn new(code: &str, message: impl Into<String>, details: serde_json::Value) -> Self {
+        Self {
+            code: code.to_string(),
+            message: message.into(),
+            details,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum CheckOutcome {
+    Pass,
+    Warn(Finding),
+    Block(Finding),
+}
+
+/// Accumulates outcomes across checks
+#[derive(Default)]
+pub struct CheckReport {
+    pub warnings: Vec<Finding>,
+    pub block: Option<Finding>,
+    /// Extensions the checks want on the resulting order
+    pub extensions: Vec<(&'static str, serde_json::Value)>,
+    /// The notional the live daily cap priced the order at, counted once
+    /// the order is accepted
+    pub live_notional: Option<Decimal>,
+}
+
+impl CheckReport {
+    /// Record an outcome; returns false once the order is blocked
+    pub fn record(&mut self, outcome: CheckOutcome) -> bool {
+        match outcome {
+            CheckOutcome::Pass => {}
+            CheckOutcome::Warn(finding) => self.warnings.push(finding),
+            CheckOutcome::Block(finding) => {
+                if self.block.is_none() {
+                    self.block = Some(finding);
+                }
+            }
+        }
+        self.block.is_none()
+    }
+
+    /// Put the warnings and any check extensions on the resulting order
+    pub fn annotate(&self, order: &mut models::order::Order) {
+        if !self.warnings.is_empty() {
+            crate::orders::set_ext(
+                order,
+                "warnings",
+                serde_json::to_value(&self.warnings).unwrap_or_default(),
+            );
+        }
+        for (key, value) in &self.extensions {
+            crate::orders::set_ext(order, key, value.clone());
+        }
+    }
+}
+
+/// Price used to estimate an order's cost, and where it came from
+#[derive(Clone, Copy, Debug)]
+pub struct PriceEstimate {
+    pub price: Decimal,
+    pub source: &'static str,
+}
+
+/// Best available price for cost estimation: the order's own limit, then
+/// the host's reference price, then the stop, then the position mark
+pub fn estimate_price(order: &OrderRequest, position: Option<&Position>) -> Option<PriceEstimate> {
+    let candidates = [
+        (order.limit_price, "limit_price"),
+        (order.reference_price, "reference_price"),
+        (order.stop_price, "stop_price"),
+        (position.map(|p| p.current_price), "position_price"),
+    ];
+    candidates.into_iter().find_map(|(price, source)| {
+        price
+            .filter(|p| *p > 0.0)
+            .and_then(decimal::from_f64)
+            .map(|price| PriceEstimate { price, source })
+    })
+}
+
+/// Quantity that needs buying power, and the part of it that opens a
+/// short. Sells up to the long position reduce risk and need none;
+/// anything beyond it opens a short that does.
+pub fn cost_quantities(order: &OrderRequest, held: Decimal) -> (Decimal, Decimal) {
+    let qty = decimal::from_f64(order.quantity).unwrap_or_default();
+    match order.side {
+        OrderSide::Buy => (qty, Decimal::ZERO),
+        OrderSide::Sell => {
+            let closable = held.max(Decimal::ZERO).min(qty);
+            (qty - closable, qty - closable)
+        }
+    }
+}
+
+/// Estimated cost and margin impact of an order, for previews. Initial
+/// margin assumes Reg T: 50% on a margin account (even with the 4x
+/// intraday multiplier), 100% on a cash account.
+pub fn estimate_impact(
+    order: &OrderRequest,
+    account: Option<&AlpacaAccount>,
+    position: Option<&Position>,
+    buffer_pct: Decimal,
+) -> serde_json::Value {
+    let qty = decimal::from_f64(order.quantity).unwrap_or_default();
+    let held = position
+        .and_then(|p| decimal::from_f64(p.quantity))
+        .unwrap_or_default();
+    let (cost_qty, short_qty) = cost_quantities(order, held);
+    let Some(estimate) = estimate_price(order, position) else {
+        return serde_json::json!({
+            "price": null,
+            "opening_qty": decimal::to_wire(cost_qty),
+            "short_qty": decimal::to_wire(short_qty),
+        });
+    };
+
+    let multiplier = Decimal::ONE + buffer_pct / Decimal::ONE_HUNDRED;
+    let estimated_cost = (cost_qty * estimate.price * multiplier).round_dp(2);
+    let mut impact = serde_json::json!({
+        "price": decimal::to_wire(estimate.price),
+        "price_source": estimate.source,
+        "notional": decimal::to_wire((qty * estimate.price).round_dp(2)),
+        "opening_qty": decimal::to_wire(cost_qty),
+        "short_qty": decimal::to_wire(short_qty),
+        "buffer_pct": decimal::to_wire(buffer_pct),
+        "estimated_cost": decimal::to_wire(estimated_cost),
+    });
+    if let Some(account) = account {
+        let buying_power = decimal::parse_or_zero(&account.buying_power);
+        let leverage = account
+            .multiplier
+            .as_deref()
+            .and_then(decimal::parse)
+            .unwrap_or(Decimal::ONE)
+            .clamp(Decimal::ONE, Decimal::TWO);
+        impact["buying_power"] = decimal::to_wire(buying_power).into();
+        impact["buying_power_after"] = decimal::to_wire(buying_power - estimated_cost).into();
+        impact["initial_margin"] = decimal::to_wire((estimated_cost / leverage).round_dp(2)).into();
+    }
+    impact
+}
+
+/// `position` as far as new sells are concerned: Alpaca's `qty_available`
+/// already leaves out the shares open sells will deliver
+pub fn available(position: &Position, qty_available: Option<Decimal>) -> Position {
+    let mut available = position.clone();
+    if let Some(qty) = qty_available {
+        available.quantity = decimal::to_f64(qty);
+    }
+    available
+}
+
+/// Compare the combined estimated cost of a group of orders with buying
+/// power. Sells within a position are netted against it as the group
+/// goes, so two sells of the same holding cannot both count as closing.
+pub fn check_basket_buying_power(
+    mode: CheckMode,
+    orders: &[OrderRequest],
+    account: &AlpacaAccount,
+    positions: &[Position],
+    buffer_pct: Decimal,
+    reserved: &ReservedFunds,
+) -> CheckOutcome {
+    if mode == CheckMode::Off {
+        return CheckOutcome::Pass;
+    }
+
+    let mut held: std::collections::HashMap<&str, Decimal> = positions
+        .iter()
+        .map(|p| {
+            let qty = decimal::from_f64(p.quantity).unwrap_or_default();
+            let qty = if qty > Decimal::ZERO {
+                (qty - reserved.shares_of(&p.symbol_id)).max(Decimal::ZERO)
+            } else {
+                qty
+            };
+            (p.symbol_id.as_str(), qty)
+        })
+        .collect();
+    let multiplier = Decimal::ONE + buffer_pct / Decimal::ONE_HUNDRED;
+    let mut total = Decimal::ZERO;
+    let mut unpriced = Vec::new();
+
+    for order in orders {
+        let position = positions.iter().find(|p| p.symbol_id == order.symbol_id);
+        let holding = held.entry(order.symbol_id.as_str()).or_default();
+        let (cost_qty, _) = cost_quantities(order, *holding);
+        let qty = decimal::from_f64(order.quantity).unwrap_or_default();
+        match order.side {
+            OrderSide::Buy => *holding += qty,
+            OrderSide::Sell => *holding -= qty,
+        }
+        if cost_qty.is_zero() {
+            continue;
+        }
+        match estimate_price(order, position) {
+            Some(estimate) => total += cost_qty * estimate.price * multiplier,
+            None => unpriced.push(order.symbol_id.clone()),
+        }
+    }
+
+    let buying_power = decimal::parse_or_zero(&account.buying_power) - reserved.buying_power;
+    let total = total.round_dp(2);
+    let details = serde_json::json!({
+        "orders": orders.len(),
+        "buffer_pct": decimal::to_wire(buffer_pct),
+        "estimated_cost": decimal::to_wire(total),
+        "reserved_buying_power": decimal::to_wire(reserved.buying_power),
+        "buying_power": decimal::to_wire(buying_power),
+        "shortfall": decimal::to_wire((total - buying_power).max(Decimal::ZERO)),
+        "unpriced_symbols": unpriced,
+    });
+
+    if total > buying_power {
+        return mode.apply(Finding::new(
+            "basket_insufficient_buying_power",
+            format!(
+                "Estimated basket cost {} exceeds buying power {}",
+                decimal::to_wire(total),
+                decimal::to_wire(buying_power)
+            ),
+            details,
+        ));
+    }
+    if !unpriced.is_empty() {
+        return CheckOutcome::Warn(Finding::new(
+            "cost_estimate_unavailable",
+            "No limit, reference, or position price available for some basket orders",
+            details,
+        ));
+    }
+    CheckOutcome::Pass
+}
+
+/// Compare the order's estimated cost with buying power (buys and short
+/// sales) and the held quantity (sells), less what `reserved` holds: the
+/// orders the account and position may not reflect yet. With
+/// `settled_cash` (cash accounts, see [`crate::settlement`], already net
+/// of every working buy) buys are held to settled cash as well.
+pub fn check_buying_power(
+    mode: CheckMode,
+    order: &OrderRequest,
+    account: &AlpacaAccount,
+    position: Option<&Position>,
+    buffer_pct: Decimal,
+    reserved: &ReservedFunds,
+    settled_cash: Option<Decimal>,
+) -> CheckOutcome {
+    if mode == CheckMode::Off {
+        return CheckOutcome::Pass;
+    }
+
+    let qty = decimal::from_f64(order.quantity).unwrap_or_default();
+    let reserved_shares = reserved.shares_of(&order.symbol_id);
+    // Shares that working sells will deliver cannot be sold again
+    let held = position
+        .and_then(|p| decimal::from_f64(p.quantity))
+        .map(|held| {
+            if held > Decimal::ZERO {
+                (held - reserved_shares).max(Decimal::ZERO)
+            } else {
+                held
+            }
+        })
+        .unwrap_or_default();
+    let (cost_qty, short_qty) = cost_quantities(order, held);
+    if cost_qty.is_zero() {
+        return CheckOutcome::Pass;
+    }
+
+    let Some(estimate) = estimate_price(order, position) else {
+        return CheckOutcome::Warn(Finding::new(
+            "cost_estimate_unavailable",
+            "No limit, reference, or position price available to estimate order cost",
+            serde_json::json!({ "symbol": order.symbol_id }),
+        ));
+    };
+
+    let account_buying_power =
+        decimal::parse_or_zero(&account.buying_power) - reserved.buying_power;
+    let buying_power = settled_cash.map_or(account_buying_power, |s| s.min(account_buying_power));
+    let multiplier = Decimal::ONE + buffer_pct / Decimal::ONE_HUNDRED;
+    let estimated_cost = (cost_qty * estimate.price * multiplier).round_dp(2);
+    let mut details = serde_json::json!({
+        "symbol": order.symbol_id,
+        "qty": decimal::to_wire(cost_qty),
+        "short_qty": decimal::to_wire(short_qty),
+        "held_qty": decimal::to_wire(held),
+        "reserved_shares": decimal::to_wire(reserved_shares),
+        "reserved_buying_power": decimal::to_wire(reserved.buying_power),
+        "price": decimal::to_wire(estimate.price),
+        "price_source": estimate.source,
+        "buffer_pct": decimal::to_wire(buffer_pct),
+        "estimated_cost": decimal::to_wire(estimated_cost),
+        "buying_power": decimal::to_wire(buying_power),
+        "shortfall": decimal::to_wire((estimated_cost - buying_power).max(Decimal::ZERO)),
+    });
+    if let Some(settled) = settled_cash {
+        details["settled_cash"] = decimal::to_wire(settled).into();
+    }
+
+    if !short_qty.is_zero() {
+        let outcome = mode.apply(Finding::new(
+            "exceeds_position",
+            format!(
+                "Sell quantity {} exceeds the held quantity {}; the remainder would open a short",
+                decimal::to_wire(qty),
+                decimal::to_wire(held.max(Decimal::ZERO))
+            ),
+            details.clone(),
+        ));
+        if estimated_cost <= buying_power {
+            return outcome;
+        }
+    }
+
+    if estimated_cost > buying_power && estimated_cost <= account_buying_power {
+        return mode.apply(Finding::new(
+            "insufficient_settled_funds",
+            format!(
+                "Estimated cost {} exceeds settled cash {}; buying with unsettled funds risks a good-faith violation",
+                decimal::to_wire(estimated_cost),
+                decimal::to_wire(buying_power)
+            ),
+            details,
+        ));
+    }
+    if estimated_cost > buying_power {
+        return mode.apply(Finding::new(
+            "insufficient_buying_power",
+            format!(
+                "Estimated cost {} exceeds buying power {}",
+                decimal::to_wire(estimated_cost),
+                decimal::to_wire(buying_power)
+            ),
+            details,
+        ));
+    }
+    CheckOutcome::Pass
+}
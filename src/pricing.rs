@@ -0,0 +1,126 @@
+//! Quote-based limit pricing
+//!
+//! Turns a side and an aggressiveness setting into a limit price from the
+//! latest NBBO quote. Orders opt in with `extensions.pricing_mode` instead
+//! of a `limit_price`; the `price_order` export runs the same calculation
+//! without submitting anything.
+
+use crate::decimal::{self, Decimal};
+use crate::market_data::Quote;
+use crate::validation;
+use models::order::{OrderRequest, OrderSide, OrderType};
+use serde::{Deserialize, Serialize};
+
+/// How aggressively to price against the quote
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum PricingMode {
+    /// Midpoint, moved `offset_bps` toward the far side (negative values
+    /// move it toward the near side). Never priced past either touch.
+    Mid {
+        #[serde(default)]
+        offset_bps: Decimal,
+    },
+    /// Rest at the near touch: the bid for buys, the ask for sells
+    Join,
+    /// Take the far touch: the ask for buys, the bid for sells
+    Cross,
+}
+
+impl PricingMode {
+    /// Accepts `"mid"`, `"join"`, `"cross"`, or an object such as
+    /// `{"mode": "mid", "offset_bps": 5}`
+    pub fn from_value(value: &serde_json::Value) -> Result<Self, String> {
+        let value = match value {
+            serde_json::Value::String(name) => serde_json::json!({ "mode": name }),
+            other => other.clone(),
+        };
+        serde_json::from_value(value)
+            .map_err(|e| format!("Invalid pricing_mode (expected mid, join, or cross): {}", e))
+    }
+
+    /// Limit price for `side` against `quote`
+    pub fn limit_price(&self, side: OrderSide, quote: &Quote) -> Result<Decimal, String> {
+        if !quote.is_two_sided() {
+            return Err("Quote is not two-sided; cannot price the order".to_string());
+        }
+        let (near, far) = match side {
+            OrderSide::Buy => (quote.bid_price, quote.ask_price),
+            OrderSide::Sell => (quote.ask_price, quote.bid_price),
+        };
+
+        let price = match self {
+            Self::Join => near,
+            Self::Cross => far,
+            Self::Mid { offset_bps } => {
+                let mid = (quote.bid_price + quote.ask_price) / Decimal::TWO;
+                let shift = mid * *offset_bps / Decimal::from(10_000);
+                let price = match side {
+                    OrderSide::Buy => mid + shift,
+                    OrderSide::Sell => mid - shift,
+                };
+                price.clamp(quote.bid_price, quote.ask_price)
+            }
+        };
+        Ok(round_passive(price, side))
+    }
+}
+
+/// Round to a valid increment ($0.01, or $0.0001 below $1.00), away from
+/// the market so rounding never makes the order more aggressive
+fn round_passive(price: Decimal, side: OrderSide) -> Decimal {
+    let places = validation::max_places(price);
+    let strategy = match side {
+        OrderSide::Buy => rust_decimal::RoundingStrategy::ToNegativeInfinity,
+        OrderSide::Sell => rust_decimal::RoundingStrategy::ToPositiveInfinity,
+    };
+    price.round_dp_with_strategy(places, strategy)
+}
+
+/// The order's pricing mode, if it asks for one. It must be a limit or
+/// stop-limit order without an explicit `limit_price`.
+pub fn requested(request: &OrderRequest) -> Result<Option<PricingMode>, String> {
+    let Some(value) = request
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.get("pricing_mode"))
+        .filter(|v| !v.is_null())
+    else {
+        return Ok(None);
+    };
+
+    if !matches!(request.order_type, OrderType::Limit | OrderType::StopLimit) {
+        return Err("pricing_mode requires a limit or stop-limit order".to_string());
+    }
+    if request.limit_price.is_some() {
+        return Err("pricing_mode cannot be combined with limit_price".to_string());
+    }
+    PricingMode::from_value(value).map(Some)
+}
+
+/// Copy of `request` with the computed limit price filled in, and the
+/// quote it was priced from recorded in `extensions.pricing`
+pub fn apply(
+    request: &OrderRequest,
+    mode: PricingMode,
+    quote: &Quote,
+) -> Result<OrderRequest, String> {
+    let price = mode.limit_price(request.side, quote)?;
+    let mut priced = request.clone();
+    priced.limit_price = Some(decimal::to_f64(price));
+    // The mode is replaced by its result, so children and resubmissions
+    // of this request keep the price instead of asking for a new one
+    let ext = priced.extensions.get_or_insert_with(Default::default);
+    ext.remove("pricing_mode");
+    ext.insert(
+        "pricing".to_string(),
+        serde_json::json!({
+            "mode": mode,
+            "limit_price": price,
+            "bid": quote.bid_price,
+            "ask": quote.ask_price,
+            "quote_time": quote.timestamp,
+        }),
+    );
+    Ok(priced)
+}
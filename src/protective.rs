@@ -0,0 +1,615 @@
+//! Protective stops for `protect_position`
+//!
+//! A protective order sits on the opposite side of a position and fires
+//! when the price moves against it: a sell stop below a long, a buy stop
+//! above a short. Its level is `percent` away from either the position's
+//! cost (average entry price) or the market (the bid for longs, the ask
+//! for shorts, falling back to the position's current price). A trailing
+//! stop trails the market by `percent`, or, from cost, by that percent of
+//! the cost in dollars.
+//!
+//! Stop prices are rounded away from the market to a valid increment, so
+//! rounding never tightens the stop. A stop from cost that the market has
+//! already crossed would fire at once, so it is refused. Crypto only takes
+//! stop-limit orders, and option positions cannot be protected this way.
+//!
+//! Protective orders are then kept in step with their position. On every
+//! `tick` the position is compared with the size last seen; when it grew
+//! or shrank, the order's quantity is replaced to match (the whole
+//! position, or at most the position for a partial protection), and when
+//! the position is closed or reversed the order is canceled so it cannot
+//! open a new one. `protective_adjust` turns this off, for all symbols or
+//! some.
+
+use crate::alpaca::{AlpacaAsset, Trail};
+use crate::decimal::{self, Decimal};
+use crate::fees::AssetKind;
+use crate::market_data::Quote;
+use crate::validation;
+use models::order::{OrderRequest, OrderSide, OrderType};
+use models::portfolio::Position;
+use rust_decimal::RoundingStrategy;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Stop-limit orders default to a limit 1% beyond the stop
+pub const DEFAULT_LIMIT_OFFSET_PCT: i64 = 1;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProtectionType {
+    #[default]
+    Stop,
+    StopLimit,
+    TrailingStop,
+}
+
+/// What the stop level is measured from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Basis {
+    Cost,
+    #[default]
+    Market,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProtectionSpec {
+    pub order_type: ProtectionType,
+    pub basis: Basis,
+    /// Distance from the basis, above 0 and below 100
+    pub percent: Decimal,
+    /// Stop-limit only: how far past the stop the limit sits, in percent
+    pub limit_offset_percent: Option<Decimal>,
+    /// Shares to protect; the whole position when `None`
+    pub qty: Option<Decimal>,
+}
+
+impl ProtectionSpec {
+    pub fn new(
+        order_type: ProtectionType,
+        basis: Basis,
+        percent: f64,
+        limit_offset_percent: Option<f64>,
+        qty: Option<f64>,
+    ) -> Result<Self, String> {
+        let percent = decimal::from_f64(percent)
+            .filter(|p| *p > Decimal::ZERO && *p < Decimal::ONE_HUNDRED)
+            .ok_or_else(|| "percent must be above 0 and below 100".to_string())?;
+        let limit_offset_percent = match limit_offset_percent {
+            None => None,
+            Some(_) if order_type != ProtectionType::StopLimit => {
+                return Err("limit_offset_percent only applies to stop_limit".to_string())
+            }
+            Some(raw) => Some(
+                decimal::from_f64(raw)
+                    .filter(|p| *p >= Decimal::ZERO && *p < Decimal::ONE_HUNDRED)
+                    .ok_or_else(|| {
+                        "limit_offset_percent must be at least 0 and below 100".to_string()
+                    })?,
+            ),
+        };
+        let qty = qty
+            .map(|q| {
+                decimal::from_f64(q)
+                    .filter(|q| *q > Decimal::ZERO)
+                    .ok_or_else(|| "qty must be a positive number".to_string())
+            })
+            .transpose()?;
+        Ok(Self {
+            order_type,
+            basis,
+            percent,
+            limit_offset_percent,
+            qty,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ProtectionPlan {
+    pub symbol: String,
+    pub side: OrderSide,
+    /// Signed position quantity
+    pub position_qty: Decimal,
+    pub qty: Decimal,
+    pub spec: ProtectionSpec,
+    pub basis_price: Decimal,
+    pub market_price: Option<Decimal>,
+    pub stop_price: Option<Decimal>,
+    pub limit_price: Option<Decimal>,
+    pub trail: Option<Trail>,
+    /// Fractional equity orders only rest for the day
+    pub fractional: bool,
+}
+
+impl ProtectionPlan {
+    /// Work out the protective order for `position`. `quote` gives the
+    /// market price; `asset` decides whether fractions are allowed.
+    pub fn new(
+        position: &Position,
+        spec: ProtectionSpec,
+        quote: Option<&Quote>,
+        asset: Option<&AlpacaAsset>,
+    ) -> Result<Self, String> {
+        let symbol = &position.symbol_id;
+        let position_qty = decimal::from_f64(position.quantity).unwrap_or_default();
+        let held = position_qty.abs();
+        if held.is_zero() {
+            return Err(format!("No open position in {}", symbol));
+        }
+        let kind = AssetKind::of(symbol);
+        if kind == AssetKind::Option {
+            return Err(format!(
+                "{} is an option; option positions cannot be protected with stop orders",
+                symbol
+            ));
+        }
+        if kind == AssetKind::Crypto && spec.order_type != ProtectionType::StopLimit {
+            return Err(format!(
+                "{} is crypto; crypto positions can only be protected with stop_limit",
+                symbol
+            ));
+        }
+        let short = position_qty < Decimal::ZERO;
+        let qty = spec.qty.unwrap_or(held);
+        if qty > held {
+            return Err(format!(
+                "qty {} exceeds the {} position of {}",
+                decimal::to_wire(qty),
+                symbol,
+                decimal::to_wire(held)
+            ));
+        }
+        let fractional = kind == AssetKind::Equity && !qty.fract().is_zero();
+        if fractional && (short || !asset.is_some_and(|a| a.fractionable)) {
+            return Err(format!("{} cannot be protected in fractions", symbol));
+        }
+        if fractional && spec.order_type == ProtectionType::TrailingStop {
+            return Err("trailing stops need a whole number of shares".to_string());
+        }
+
+        let market_price = quote
+            .map(|q| if short { q.ask_price } else { q.bid_price })
+            .filter(|p| *p > Decimal::ZERO)
+            .or_else(|| decimal::from_f64(position.current_price))
+            .filter(|p| *p > Decimal::ZERO);
+        let basis_price = match spec.basis {
+            Basis::Cost => decimal::from_f64(position.average_price),
+            Basis::Market => market_price,
+        }
+        .filter(|p| *p > Decimal::ZERO)
+        .ok_or_else(|| match spec.basis {
+            Basis::Cost => format!("No cost basis for {}", symbol),
+            Basis::Market => format!("No market price for {}", symbol),
+        })?;
+
+        // Longs are protected below the basis, shorts above it
+        let direction = if short { Decimal::ONE } else { -Decimal::ONE };
+        let away = |price: Decimal, pct: Decimal| {
+            let moved = price * (Decimal::ONE + direction * pct / Decimal::ONE_HUNDRED);
+            let strategy = if short {
+                RoundingStrategy::ToPositiveInfinity
+            } else {
+                RoundingStrategy::ToNegativeInfinity
+            };
+            moved.round_dp_with_strategy(validation::max_places(moved), strategy)
+        };
+
+        let (stop_price, limit_price, trail) = match spec.order_type {
+            ProtectionType::TrailingStop => {
+                let trail = match spec.basis {
+                    Basis::Market => Trail::Percent(spec.percent),
+                    Basis::Cost => Trail::Price(
+                        (basis_price * spec.percent / Decimal::ONE_HUNDRED)
+                            .round_dp(2)
+                            .max(Decimal::new(1, 2)),
+                    ),
+                };
+                (None, None, Some(trail))
+            }
+            ProtectionType::Stop => (Some(away(basis_price, spec.percent)), None, None),
+            ProtectionType::StopLimit => {
+                let stop = away(basis_price, spec.percent);
+                let offset = spec
+                    .limit_offset_percent
+                    .unwrap_or(Decimal::from(DEFAULT_LIMIT_OFFSET_PCT));
+                (Some(stop), Some(away(stop, offset)), None)
+            }
+        };
+
+        if let Some((stop, market)) = stop_price.zip(market_price) {
+            let crossed = if short {
+                stop <= market
+            } else {
+                stop >= market
+            };
+            if crossed {
+                return Err(format!(
+                    "stop price {} is already {} the market price {} for {}",
+                    decimal::to_wire(stop),
+                    if short { "at or below" } else { "at or above" },
+                    decimal::to_wire(market),
+                    symbol
+                ));
+            }
+        }
+
+        Ok(Self {
+            symbol: symbol.clone(),
+            side: if short {
+                OrderSide::Buy
+            } else {
+                OrderSide::Sell
+            },
+            position_qty,
+            qty,
+            spec,
+            basis_price,
+            market_price,
+            stop_price,
+            limit_price,
+            trail,
+            fractional,
+        })
+    }
+
+    /// The protective order; good-til-canceled unless fractional, or
+    /// `time_in_force` says otherwise
+    pub fn to_request(
+        &self,
+        persona_id: &str,
+        time_in_force: Option<&str>,
+        dry_run: bool,
+    ) -> Result<OrderRequest, String> {
+        let order_type = match self.spec.order_type {
+            ProtectionType::Stop | ProtectionType::TrailingStop => OrderType::Stop,
+            ProtectionType::StopLimit => OrderType::StopLimit,
+        };
+        let default_tif = if self.fractional { "day" } else { "gtc" };
+        let mut extensions = serde_json::json!({
+            "dry_run": dry_run,
+            "time_in_force": time_in_force.unwrap_or(default_tif),
+            // The level is set on purpose, away from the market
+            "allow_price_deviation": true,
+            "protects_position": self.link(),
+        });
+        match self.trail {
+            Some(Trail::Percent(pct)) => {
+                extensions["trail_percent"] = decimal::to_wire(pct).into();
+            }
+            Some(Trail::Price(price)) => {
+                extensions["trail_price"] = decimal::to_wire(price).into();
+            }
+            None => {}
+        }
+        serde_json::from_value(serde_json::json!({
+            "symbol_id": self.symbol,
+            "quantity": decimal::to_f64(self.qty),
+            "side": self.side,
+            "order_type": order_type,
+            "limit_price": self.limit_price.map(decimal::to_f64),
+            "stop_price": self.stop_price.map(decimal::to_f64),
+            "reference_price": self.market_price.map(decimal::to_f64),
+            "persona_id": persona_id,
+            "extensions": extensions,
+        }))
+        .map_err(|e| e.to_string())
+    }
+
+    /// `extensions.protects_position` on the protective order
+    pub fn link(&self) -> serde_json::Value {
+        let wire = |d: Option<Decimal>| d.map(decimal::to_wire);
+        serde_json::json!({
+            "symbol": self.symbol,
+            "position_qty": decimal::to_wire(self.position_qty),
+            "qty": decimal::to_wire(self.qty),
+            "order_type": self.spec.order_type,
+            "basis": self.spec.basis,
+            "basis_price": decimal::to_wire(self.basis_price),
+            "percent": decimal::to_wire(self.spec.percent),
+            "market_price": wire(self.market_price),
+            "stop_price": wire(self.stop_price),
+            "limit_price": wire(self.limit_price),
+            "trail_percent": match self.trail {
+                Some(Trail::Percent(pct)) => Some(decimal::to_wire(pct)),
+                _ => None,
+            },
+            "trail_price": match self.trail {
+                Some(Trail::Price(price)) => Some(decimal::to_wire(price)),
+                _ => None,
+            },
+        })
+    }
+}
+
+/// Whether protective orders follow their position's size, by symbol
+#[derive(Clone, Debug)]
+pub struct AdjustPolicy {
+    pub default: bool,
+    /// Per-symbol overrides, upper case
+    pub symbols: HashMap<String, bool>,
+}
+
+impl Default for AdjustPolicy {
+    fn default() -> Self {
+        Self {
+            default: true,
+            symbols: HashMap::new(),
+        }
+    }
+}
+
+impl AdjustPolicy {
+    /// Parse `protective_adjust`: `true`, `false`, or
+    /// `{ "default": bool, "symbols": { "AAPL": bool } }`
+    pub fn from_config(value: Option<&serde_json::Value>) -> Result<Self, String> {
+        let Some(value) = value else {
+            return Ok(Self::default());
+        };
+        if let Some(on) = value.as_bool() {
+            return Ok(Self {
+                default: on,
+                symbols: HashMap::new(),
+            });
+        }
+        let object = value
+            .as_object()
+            .ok_or("protective_adjust must be true, false, or an object")?;
+        let default = match object.get("default") {
+            None | Some(serde_json::Value::Null) => true,
+            Some(v) => v
+                .as_bool()
+                .ok_or("protective_adjust.default must be true or false")?,
+        };
+        let symbols = match object.get("symbols") {
+            None | Some(serde_json::Value::Null) => HashMap::new(),
+            Some(v) => serde_json::from_value::<HashMap<String, bool>>(v.clone())
+                .map_err(|_| {
+                    "protective_adjust.symbols must map symbols to true or false".to_string()
+                })?
+                .into_iter()
+                .map(|(symbol, on)| (symbol.to_ascii_uppercase(), on))
+                .collect(),
+        };
+        Ok(Self { default, symbols })
+    }
+
+    pub fn applies(&self, symbol: &str) -> bool {
+        self.symbols
+            .get(&symbol.to_ascii_uppercase())
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// A protective order being kept in step with its position
+#[derive(Clone, Debug, Serialize)]
+pub struct Protection {
+    pub symbol: String,
+    /// The order currently working; replacements get new IDs
+    pub order_id: String,
+    pub side: OrderSide,
+    /// Signed position quantity when last seen
+    pub position_qty: Decimal,
+    pub qty: Decimal,
+    /// The order covers the whole position, rather than part of it
+    pub whole_position: bool,
+    /// The order must stay in whole shares (good-til-canceled equities)
+    pub whole_shares: bool,
+    pub adjustments: u32,
+}
+
+/// What a position change means for its protective order
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Adjustment {
+    Keep,
+    Resize(Decimal),
+    Cancel(&'static str),
+}
+
+impl Protection {
+    pub fn new(plan: &ProtectionPlan, order_id: &str) -> Self {
+        Self {
+            symbol: plan.symbol.clone(),
+            order_id: order_id.to_string(),
+            side: plan.side,
+            position_qty: plan.position_qty,
+            qty: plan.qty,
+            whole_position: plan.qty == plan.position_qty.abs(),
+            whole_shares: AssetKind::of(&plan.symbol) == AssetKind::Equity && !plan.fractional,
+            adjustments: 0,
+        }
+    }
+
+    /// The change `position_qty` calls for
+    pub fn adjustment(&self, position_qty: Decimal) -> Adjustment {
+        if position_qty == self.position_qty {
+            return Adjustment::Keep;
+        }
+        let still_open = match self.side {
+            OrderSide::Sell => position_qty > Decimal::ZERO,
+            OrderSide::Buy => position_qty < Decimal::ZERO,
+        };
+        if position_qty.is_zero() {
+            return Adjustment::Cancel("position_closed");
+        }
+        if !still_open {
+            return Adjustment::Cancel("position_reversed");
+        }
+        let held = position_qty.abs();
+        let mut qty = if self.whole_position {
+            held
+        } else {
+            self.qty.min(held)
+        };
+        if self.whole_shares {
+            qty = qty.floor();
+        }
+        if qty.is_zero() {
+            Adjustment::Cancel("position_below_one_share")
+        } else if qty == self.qty {
+            Adjustment::Keep
+        } else {
+            Adjustment::Resize(qty)
+        }
+    }
+}
+
+/// Protective orders by symbol; one per symbol, the latest replacing any
+/// earlier one
+#[derive(Default)]
+pub struct ProtectionBook {
+    entries: BTreeMap<String, Protection>,
+}
+
+impl ProtectionBook {
+    pub fn insert(&mut self, protection: Protection) {
+        self.entries.insert(protection.symbol.clone(), protection);
+    }
+
+    pub fn get_mut(&mut self, symbol: &str) -> Option<&mut Protection> {
+        self.entries.get_mut(symbol)
+    }
+
+    pub fn remove(&mut self, symbol: &str) -> Option<Protection> {
+        self.entries.remove(symbol)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn rename_symbol(&mut self, old: &str, new: &str) -> bool {
+        let Some(mut protection) = self.entries.remove(old) else {
+            return false;
+        };
+        protection.symbol = new.to_string();
+        self.entries.insert(new.to_string(), protection);
+        true
+    }
+
+    pub fn list(&self) -> Vec<Protection> {
+        self.entries.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(qty: f64, cost: f64, current: f64) -> Position {
+        Position {
+            symbol_id: "XYZ".to_string(),
+            quantity: qty,
+            average_price: cost,
+            current_price: current,
+            unrealized_pnl: 0.0,
+            unrealized_pnl_percent: 0.0,
+        }
+    }
+
+    fn quote(bid: &str, ask: &str) -> Quote {
+        serde_json::from_value(serde_json::json!({
+            "t": "2024-03-01T15:00:00Z", "bp": bid, "ap": ask,
+        }))
+        .unwrap()
+    }
+
+    fn spec(order_type: ProtectionType, basis: Basis, percent: f64) -> ProtectionSpec {
+        ProtectionSpec::new(order_type, basis, percent, None, None).unwrap()
+    }
+
+    #[test]
+    fn stops_sit_percent_away_from_cost_or_market() {
+        let q = quote("110.00", "110.10");
+        let long = position(10.0, 100.0, 110.05);
+
+        // 5% below the bid, rounded down to a cent
+        let plan = ProtectionPlan::new(
+            &long,
+            spec(ProtectionType::Stop, Basis::Market, 5.0),
+            Some(&q),
+            None,
+        )
+        .unwrap();
+        assert_eq!(plan.side, OrderSide::Sell);
+        assert_eq!(plan.stop_price, Some(Decimal::new(10450, 2)));
+        let request = plan.to_request("default", None, false).unwrap();
+        assert_eq!(request.order_type, OrderType::Stop);
+        assert_eq!(request.extensions.unwrap()["time_in_force"], "gtc");
+
+        // Stop-limit from cost: stop at 90, limit 1% under it
+        let plan = ProtectionPlan::new(
+            &long,
+            spec(ProtectionType::StopLimit, Basis::Cost, 10.0),
+            Some(&q),
+            None,
+        )
+        .unwrap();
+        assert_eq!(plan.stop_price, Some(Decimal::from(90)));
+        assert_eq!(plan.limit_price, Some(Decimal::new(8910, 2)));
+
+        // Shorts are protected above, and a trailing stop from cost
+        // trails by dollars
+        let short = position(-10.0, 100.0, 110.05);
+        let plan = ProtectionPlan::new(
+            &short,
+            spec(ProtectionType::TrailingStop, Basis::Cost, 5.0),
+            Some(&q),
+            None,
+        )
+        .unwrap();
+        assert_eq!(plan.side, OrderSide::Buy);
+        assert_eq!(plan.trail, Some(Trail::Price(Decimal::from(5))));
+
+        // A short already 10% under water is past a 5% stop from cost
+        assert!(ProtectionPlan::new(
+            &short,
+            spec(ProtectionType::Stop, Basis::Cost, 5.0),
+            Some(&q),
+            None
+        )
+        .is_err());
+        assert!(
+            ProtectionSpec::new(ProtectionType::Stop, Basis::Market, 100.0, None, None).is_err()
+        );
+    }
+
+    #[test]
+    fn protection_follows_the_position_size() {
+        let long = position(10.0, 100.0, 110.0);
+        let plan = ProtectionPlan::new(
+            &long,
+            spec(ProtectionType::Stop, Basis::Market, 5.0),
+            None,
+            None,
+        )
+        .unwrap();
+        let protection = Protection::new(&plan, "o1");
+        let qty = |q: i64| Decimal::from(q);
+        assert_eq!(protection.adjustment(qty(10)), Adjustment::Keep);
+        assert_eq!(protection.adjustment(qty(15)), Adjustment::Resize(qty(15)));
+        // Good-til-canceled stops stay in whole shares
+        assert_eq!(
+            protection.adjustment(Decimal::new(65, 1)),
+            Adjustment::Resize(qty(6))
+        );
+        assert_eq!(
+            protection.adjustment(Decimal::ZERO),
+            Adjustment::Cancel("position_closed")
+        );
+        assert_eq!(
+            protection.adjustment(qty(-5)),
+            Adjustment::Cancel("position_reversed")
+        );
+
+        let policy = AdjustPolicy::from_config(Some(&serde_json::json!({
+            "default": false,
+            "symbols": { "xyz": true },
+        })))
+        .unwrap();
+        assert!(policy.applies("XYZ"));
+        assert!(!policy.applies("AAPL"));
+    }
+}
@@ -0,0 +1,97 @@
+//! Raw passthrough to Alpaca endpoints the plugin does not wrap yet
+//!
+//! `raw_request` sends a method, path, and body to the trading or market
+//! data API through the same pipeline as every typed call, so it gets the
+//! auth headers, rate limiting, retries, metrics, and logging. Its
+//! response is scrubbed of secrets like any other. It is off unless the
+//! `raw_requests` config enables it, either read-only (`GET` only) or for
+//! every method.
+
+use crate::http::HttpMethod;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RawPolicy {
+    #[default]
+    Off,
+    ReadOnly,
+    Full,
+}
+
+impl RawPolicy {
+    /// `false`/absent, `"read_only"`, or `true`
+    pub fn from_config(value: Option<&serde_json::Value>) -> Result<Self, String> {
+        match value {
+            None | Some(serde_json::Value::Null) | Some(serde_json::Value::Bool(false)) => {
+                Ok(Self::Off)
+            }
+            Some(serde_json::Value::Bool(true)) => Ok(Self::Full),
+            Some(serde_json::Value::String(s)) if s == "read_only" => Ok(Self::ReadOnly),
+            Some(_) => Err("raw_requests must be true, false, or \"read_only\"".to_string()),
+        }
+    }
+
+    /// Check that a request may be sent under this policy
+    pub fn allow(self, method: HttpMethod, path: &str) -> Result<(), String> {
+        match self {
+            Self::Off => {
+                return Err("raw_request is disabled; set raw_requests in the config".to_string())
+            }
+            Self::ReadOnly if method != HttpMethod::Get => {
+                return Err(format!(
+                    "raw_requests is read_only; {} is not allowed",
+                    method.as_str()
+                ))
+            }
+            _ => {}
+        }
+        validate_path(path)
+    }
+}
+
+/// Only a path (with an optional query) on the configured host: no
+/// scheme or host, no `..` segments
+fn validate_path(path: &str) -> Result<(), String> {
+    let route = path.split('?').next().unwrap_or_default();
+    let valid = route.starts_with("/v")
+        && !route.contains("//")
+        && !route.split('/').any(|segment| segment == "..")
+        && !path.chars().any(|c| c.is_whitespace() || c.is_control());
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid raw_request path {:?}: expected an API path such as /v2/watchlists",
+            path
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_gates_methods_and_paths() {
+        let off = RawPolicy::from_config(None).unwrap();
+        assert!(off.allow(HttpMethod::Get, "/v2/watchlists").is_err());
+
+        let read_only = RawPolicy::from_config(Some(&serde_json::json!("read_only"))).unwrap();
+        assert!(read_only
+            .allow(HttpMethod::Get, "/v2/watchlists?x=1")
+            .is_ok());
+        assert!(read_only.allow(HttpMethod::Post, "/v2/watchlists").is_err());
+
+        let full = RawPolicy::from_config(Some(&serde_json::json!(true))).unwrap();
+        assert!(full.allow(HttpMethod::Delete, "/v2/watchlists/abc").is_ok());
+        for path in [
+            "https://evil.test/v2/account",
+            "//evil.test/v2",
+            "/v2/../admin",
+            "v2/account",
+            "/v2/a b",
+        ] {
+            assert!(full.allow(HttpMethod::Get, path).is_err(), "{}", path);
+        }
+        assert!(RawPolicy::from_config(Some(&serde_json::json!("yes"))).is_err());
+    }
+}
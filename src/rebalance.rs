@@ -0,0 +1,342 @@
+//! Target-weight rebalancing
+//!
+//! Compares current holdings with target weights of equity and turns the
+//! difference into market orders. Symbols whose weight is within the band,
+//! or whose trade would be below the minimum notional, are left alone.
+//! Sells are listed before buys so the cash they free is available first.
+
+use crate::decimal::{self, Decimal};
+use models::order::{OrderRequest, OrderSide, OrderType};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Input to the `rebalance` export
+#[derive(Clone, Debug, Deserialize)]
+pub struct RebalanceRequest {
+    /// Target weight per symbol as a fraction of equity (0.25 = 25%)
+    pub targets: BTreeMap<String, Decimal>,
+    /// Rebalance only this persona's holdings (from its tax lots) and tag
+    /// the orders with it
+    #[serde(default)]
+    pub persona_id: Option<String>,
+    /// Equity to weight against; defaults to the account equity, or with
+    /// `persona_id` to the value of the persona's holdings
+    #[serde(default)]
+    pub equity: Option<Decimal>,
+    /// Skip symbols whose weight is within this many percentage points of
+    /// the target
+    #[serde(default)]
+    pub band_pct: Decimal,
+    /// Skip trades smaller than this notional
+    #[serde(default)]
+    pub min_trade_notional: Decimal,
+    /// Sell held symbols that are not in `targets`
+    #[serde(default)]
+    pub liquidate_unlisted: bool,
+    /// Trade fractional shares instead of whole shares
+    #[serde(default)]
+    pub fractional: bool,
+    /// Only return the plan
+    #[serde(default)]
+    pub plan_only: bool,
+}
+
+impl RebalanceRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.targets.is_empty() {
+            return Err("targets must not be empty".to_string());
+        }
+        if let Some((symbol, _)) = self.targets.iter().find(|(_, w)| w.is_sign_negative()) {
+            return Err(format!("Target weight for {} must not be negative", symbol));
+        }
+        let total: Decimal = self.targets.values().sum();
+        if total > Decimal::ONE {
+            return Err(format!(
+                "Target weights sum to {}, above 1",
+                total.normalize()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Every symbol the plan needs a price for
+    pub fn symbols(&self, holdings: &HashMap<String, Decimal>) -> Vec<String> {
+        let mut symbols: Vec<String> = self.targets.keys().cloned().collect();
+        if self.liquidate_unlisted {
+            symbols.extend(
+                holdings
+                    .keys()
+                    .filter(|s| !self.targets.contains_key(*s))
+                    .cloned(),
+            );
+        }
+        symbols.sort();
+        symbols.dedup();
+        symbols
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanAction {
+    Buy,
+    Sell,
+    /// Within the band
+    Hold,
+    /// Trade below the minimum notional (or rounds to zero shares)
+    BelowMinimum,
+    /// No price available
+    NoPrice,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PlanLine {
+    pub symbol: String,
+    pub action: PlanAction,
+    pub current_qty: Decimal,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price: Option<Decimal>,
+    pub current_weight: Decimal,
+    pub target_weight: Decimal,
+    /// Shares to trade (positive for both buys and sells)
+    pub qty: Decimal,
+    pub notional: Decimal,
+}
+
+/// Market value of `holdings` at `prices`; symbols without a price count
+/// as zero
+pub fn holdings_value(
+    holdings: &HashMap<String, Decimal>,
+    prices: &HashMap<String, Decimal>,
+) -> Decimal {
+    holdings
+        .iter()
+        .filter_map(|(symbol, qty)| prices.get(symbol).map(|price| qty * price))
+        .sum()
+}
+
+/// Build the plan: sells first, then buys, then untouched symbols
+pub fn plan(
+    req: &RebalanceRequest,
+    equity: Decimal,
+    holdings: &HashMap<String, Decimal>,
+    prices: &HashMap<String, Decimal>,
+) -> Vec<PlanLine> {
+    let band = req.band_pct / Decimal::ONE_HUNDRED;
+    let mut lines: Vec<PlanLine> = req
+        .symbols(holdings)
+        .into_iter()
+        .map(|symbol| {
+            let current_qty = holdings.get(&symbol).copied().unwrap_or_default();
+            let target_weight = req.targets.get(&symbol).copied().unwrap_or_default();
+            let Some(price) = prices.get(&symbol).copied().filter(|p| *p > Decimal::ZERO) else {
+                return PlanLine {
+                    symbol,
+                    action: PlanAction::NoPrice,
+                    current_qty,
+                    price: None,
+                    current_weight: Decimal::ZERO,
+                    target_weight,
+                    qty: Decimal::ZERO,
+                    notional: Decimal::ZERO,
+                };
+            };
+
+            let current_weight = (current_qty * price / equity).round_dp(6);
+            let delta = (target_weight * equity - current_qty * price) / price;
+            let qty = if req.fractional {
+                delta
+                    .abs()
+                    .round_dp_with_strategy(4, rust_decimal::RoundingStrategy::ToZero)
+            } else {
+                delta.abs().trunc()
+            };
+            let notional = (qty * price).round_dp(2);
+            let action = if (target_weight - current_weight).abs() <= band {
+                PlanAction::Hold
+            } else if qty.is_zero() || notional < req.min_trade_notional {
+                PlanAction::BelowMinimum
+            } else if delta.is_sign_negative() {
+                PlanAction::Sell
+            } else {
+                PlanAction::Buy
+            };
+            PlanLine {
+                symbol,
+                action,
+                current_qty,
+                price: Some(price),
+                current_weight,
+                target_weight,
+                qty,
+                notional,
+            }
+        })
+        .collect();
+
+    let rank = |a: PlanAction| match a {
+        PlanAction::Sell => 0,
+        PlanAction::Buy => 1,
+        _ => 2,
+    };
+    lines.sort_by_key(|l| rank(l.action));
+    lines
+}
+
+impl PlanLine {
+    pub fn is_trade(&self) -> bool {
+        matches!(self.action, PlanAction::Buy | PlanAction::Sell)
+    }
+
+    /// Market order for this line, tagged with the rebalance ID
+    pub fn to_request(&self, persona_id: &str, rebalance_id: &str) -> Result<OrderRequest, String> {
+        let side = match self.action {
+            PlanAction::Sell => OrderSide::Sell,
+            _ => OrderSide::Buy,
+        };
+        serde_json::from_value(serde_json::json!({
+            "symbol_id": self.symbol,
+            "quantity": decimal::to_f64(self.qty),
+            "side": side,
+            "order_type": OrderType::Market,
+            "limit_price": null,
+            "stop_price": null,
+            "persona_id": persona_id,
+            "extensions": { "rebalance_id": rebalance_id },
+        }))
+        .map_err(|e| format!("Failed to build order for {}: {}", self.symbol, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(s: &str) -> Decimal {
+        decimal::parse(s).unwrap()
+    }
+
+    fn map(entries: &[(&str, &str)]) -> HashMap<String, Decimal> {
+        entries.iter().map(|(k, v)| (k.to_string(), d(v))).collect()
+    }
+
+    fn request(targets: &[(&str, &str)]) -> RebalanceRequest {
+        serde_json::from_value(serde_json::json!({
+            "targets": targets.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<BTreeMap<_, _>>(),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn plans_trade_toward_the_targets_sells_first() {
+        let prices = map(&[("AAPL", "100"), ("MSFT", "200"), ("SPY", "50")]);
+        type Pairs = &'static [(&'static str, &'static str)];
+        type Expected = &'static [(&'static str, PlanAction, &'static str)];
+        // (targets, holdings, equity, expected lines in order)
+        let cases: &[(Pairs, Pairs, &str, Expected)] = &[
+            (
+                &[("AAPL", "0.5"), ("MSFT", "0.5")],
+                &[],
+                "10000",
+                &[
+                    ("AAPL", PlanAction::Buy, "50"),
+                    ("MSFT", PlanAction::Buy, "25"),
+                ],
+            ),
+            (
+                &[("AAPL", "0.2"), ("SPY", "0.3")],
+                &[("AAPL", "60")],
+                "10000",
+                &[
+                    ("AAPL", PlanAction::Sell, "40"),
+                    ("SPY", PlanAction::Buy, "60"),
+                ],
+            ),
+            // Whole shares, rounded toward zero
+            (
+                &[("MSFT", "0.33")],
+                &[],
+                "1000",
+                &[("MSFT", PlanAction::Buy, "1")],
+            ),
+            (
+                &[("SPY", "1")],
+                &[],
+                "20",
+                &[("SPY", PlanAction::BelowMinimum, "0")],
+            ),
+            (
+                &[("AAPL", "0.5"), ("TSLA", "0.5")],
+                &[("AAPL", "50")],
+                "10000",
+                &[
+                    ("AAPL", PlanAction::Hold, "0"),
+                    ("TSLA", PlanAction::NoPrice, "0"),
+                ],
+            ),
+        ];
+        for (targets, holdings, equity, expected) in cases {
+            let lines = plan(&request(targets), d(equity), &map(holdings), &prices);
+            let got: Vec<(&str, PlanAction, Decimal)> = lines
+                .iter()
+                .map(|l| (l.symbol.as_str(), l.action, l.qty))
+                .collect();
+            let want: Vec<(&str, PlanAction, Decimal)> =
+                expected.iter().map(|(s, a, q)| (*s, *a, d(q))).collect();
+            assert_eq!(got, want, "targets {:?}", targets);
+        }
+    }
+
+    #[test]
+    fn band_minimum_and_fractional_settings_apply() {
+        let prices = map(&[("AAPL", "100")]);
+        let holdings = map(&[("AAPL", "49")]);
+        let mut req = request(&[("AAPL", "0.5")]);
+        assert_eq!(
+            plan(&req, d("10000"), &holdings, &prices)[0].action,
+            PlanAction::Buy
+        );
+        req.band_pct = d("1");
+        assert_eq!(
+            plan(&req, d("10000"), &holdings, &prices)[0].action,
+            PlanAction::Hold
+        );
+
+        let mut req = request(&[("AAPL", "0.5")]);
+        req.min_trade_notional = d("150");
+        assert_eq!(
+            plan(&req, d("10000"), &holdings, &prices)[0].action,
+            PlanAction::BelowMinimum
+        );
+
+        let mut req = request(&[("AAPL", "0.5")]);
+        req.fractional = true;
+        let line = &plan(&req, d("10000.5"), &holdings, &prices)[0];
+        assert_eq!(line.qty, d("1.0025"));
+    }
+
+    #[test]
+    fn a_persona_is_weighted_against_its_own_holdings() {
+        let prices = map(&[("AAPL", "100"), ("MSFT", "200")]);
+        // The persona holds 2000 of the account's equity
+        let holdings = map(&[("AAPL", "20")]);
+        let equity = holdings_value(&holdings, &prices);
+        assert_eq!(equity, d("2000"));
+
+        let mut req = request(&[("AAPL", "0.5"), ("MSFT", "0.5")]);
+        req.persona_id = Some("growth".to_string());
+        let lines = plan(&req, equity, &holdings, &prices);
+        let trades: Vec<(&str, PlanAction, Decimal)> = lines
+            .iter()
+            .map(|l| (l.symbol.as_str(), l.action, l.qty))
+            .collect();
+        assert_eq!(
+            trades,
+            [
+                ("AAPL", PlanAction::Sell, d("10")),
+                ("MSFT", PlanAction::Buy, d("5"))
+            ]
+        );
+        assert_eq!(lines[0].current_weight, Decimal::ONE);
+    }
+}
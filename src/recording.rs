@@ -0,0 +1,441 @@
+//! Record and replay of HTTP traffic
+//!
+//! With `recording: { "mode": "record" }` every request the trading client
+//! sends and the response it gets back are captured, redacted, into a
+//! replay log. With `"mode": "replay"` the same log answers the client's
+//! requests instead of Alpaca, so an incident captured in production can be
+//! reproduced offline, step by step.
+//!
+//! The log is kept under `key` in the host's key-value store (see
+//! [`crate::kv`]), one exchange per entry. Without the `host-kv` feature
+//! the log lives in plugin memory, `get_recording` returns it, and a replay
+//! config can carry it inline as `log`.
+//!
+//! Replay matches on method, URL, and request body, then on method and URL
+//! alone (order bodies carry fresh client order IDs). Identical requests
+//! are answered in the order they were recorded; once a sequence runs out,
+//! its last response repeats.
+
+use crate::http::{HttpRequest, HttpResponse, Middleware, Next};
+use crate::kv;
+use crate::redact;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const DEFAULT_KEY: &str = "alpaca-replay";
+
+/// Exchanges recorded when `max_entries` is not set
+const DEFAULT_MAX_ENTRIES: usize = 1000;
+
+/// Upper bound for `max_entries`
+const MAX_ENTRIES_LIMIT: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingMode {
+    Record,
+    Replay,
+}
+
+#[derive(Clone, Debug)]
+pub struct RecordingConfig {
+    pub mode: RecordingMode,
+    pub key: String,
+    /// Recording stops after this many exchanges, so the start of an
+    /// incident is never overwritten
+    pub max_entries: usize,
+    /// Replay log given inline instead of read from the store
+    pub log: Option<Vec<Exchange>>,
+}
+
+impl RecordingConfig {
+    /// `"record"`, `"replay"`, or an object with `mode` and optional `key`,
+    /// `max_entries`, and (for replay) `log`; absent or `false` for neither
+    pub fn from_config(value: Option<&serde_json::Value>) -> Result<Option<Self>, String> {
+        let empty = serde_json::Map::new();
+        let (mode, object) = match value {
+            None | Some(serde_json::Value::Null) | Some(serde_json::Value::Bool(false)) => {
+                return Ok(None)
+            }
+            Some(serde_json::Value::String(mode)) => (mode.as_str(), &empty),
+            Some(serde_json::Value::Object(object)) => (
+                object
+                    .get("mode")
+                    .and_then(|m| m.as_str())
+                    .ok_or("recording.mode must be record or replay")?,
+                object,
+            ),
+            Some(_) => return Err("recording must be record, replay, or an object".to_string()),
+        };
+        let mode = match mode {
+            "record" => RecordingMode::Record,
+            "replay" => RecordingMode::Replay,
+            other => {
+                return Err(format!(
+                    "recording.mode: unknown value {:?}; expected record or replay",
+                    other
+                ))
+            }
+        };
+        let key = match object.get("key") {
+            None => DEFAULT_KEY.to_string(),
+            Some(key) => key
+                .as_str()
+                .filter(|k| !k.is_empty() && !k.contains('/'))
+                .ok_or("recording.key must be a non-empty string without '/'")?
+                .to_string(),
+        };
+        let max_entries = match object.get("max_entries") {
+            None => DEFAULT_MAX_ENTRIES,
+            Some(v) => v
+                .as_u64()
+                .filter(|n| (1..=MAX_ENTRIES_LIMIT as u64).contains(n))
+                .ok_or_else(|| {
+                    format!(
+                        "recording.max_entries must be between 1 and {}",
+                        MAX_ENTRIES_LIMIT
+                    )
+                })? as usize,
+        };
+        let log = match object.get("log") {
+            None => None,
+            Some(_) if mode == RecordingMode::Record => {
+                return Err("recording.log is only read in replay mode".to_string())
+            }
+            Some(log) => Some(
+                serde_json::from_value(log.clone()).map_err(|e| format!("recording.log: {}", e))?,
+            ),
+        };
+        Ok(Some(Self {
+            mode,
+            key,
+            max_entries,
+            log,
+        }))
+    }
+}
+
+/// One request and the response it got, as stored in the replay log.
+/// Request headers are not kept: they are the client's own plus the
+/// credentials.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Exchange {
+    pub method: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_body: Option<String>,
+    pub status: u16,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+impl Exchange {
+    fn capture(request: &HttpRequest, response: &HttpResponse) -> Self {
+        Self {
+            method: request.method.as_str().to_string(),
+            url: redact::scrub(&request.url),
+            request_body: request.body.as_deref().map(redact::scrub),
+            status: response.status,
+            headers: response
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), redact::scrub(value)))
+                .collect(),
+            body: redact::scrub(&response.body),
+            error: response.error.as_deref().map(redact::scrub),
+            recorded_at: Utc::now(),
+        }
+    }
+
+    fn response(&self) -> HttpResponse {
+        HttpResponse {
+            status: self.status,
+            headers: self.headers.clone(),
+            body: self.body.clone(),
+            error: self.error.clone(),
+        }
+    }
+}
+
+/// Read the log stored under `key`; an absent key is an empty log
+pub fn load(key: &str) -> Result<Vec<Exchange>, String> {
+    kv::load_entries(key, "replay log")
+}
+
+#[derive(Default)]
+struct ReplayIndex {
+    /// Entry positions by method, URL, and body, in recorded order
+    exact: HashMap<(String, String, Option<String>), Vec<usize>>,
+    /// The same by method and URL only
+    loose: HashMap<(String, String), Vec<usize>>,
+    /// Responses served so far, by key
+    served: HashMap<(String, String, Option<String>), usize>,
+    served_loose: HashMap<(String, String), usize>,
+}
+
+impl ReplayIndex {
+    fn build(entries: &[Exchange]) -> Self {
+        let mut index = Self::default();
+        for (i, entry) in entries.iter().enumerate() {
+            index
+                .exact
+                .entry((
+                    entry.method.clone(),
+                    entry.url.clone(),
+                    entry.request_body.clone(),
+                ))
+                .or_default()
+                .push(i);
+            index
+                .loose
+                .entry((entry.method.clone(), entry.url.clone()))
+                .or_default()
+                .push(i);
+        }
+        index
+    }
+
+    /// Next recorded response for a request, if any matches
+    fn next(&mut self, method: &str, url: &str, body: Option<String>) -> Option<usize> {
+        let exact = (method.to_string(), url.to_string(), body);
+        if let Some(positions) = self.exact.get(&exact) {
+            let served = self.served.entry(exact).or_default();
+            let position = positions[(*served).min(positions.len() - 1)];
+            *served += 1;
+            return Some(position);
+        }
+        let loose = (method.to_string(), url.to_string());
+        let positions = self.loose.get(&loose)?;
+        let served = self.served_loose.entry(loose).or_default();
+        let position = positions[(*served).min(positions.len() - 1)];
+        *served += 1;
+        Some(position)
+    }
+}
+
+struct Log {
+    entries: Vec<Exchange>,
+    index: ReplayIndex,
+    /// Requests replay had no answer for
+    unmatched: Vec<String>,
+    /// Recording stopped at `max_entries`
+    full: bool,
+    /// Last failure writing to the store
+    store_error: Option<String>,
+}
+
+/// Middleware that records the client's traffic or answers it from a log
+pub struct Recorder {
+    config: RecordingConfig,
+    log: Mutex<Log>,
+}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder")
+            .field("mode", &self.config.mode)
+            .field("key", &self.config.key)
+            .finish()
+    }
+}
+
+impl Recorder {
+    /// A recorder for `config`; replay reads the inline log or the stored
+    /// one. Recording starts a fresh log under the key.
+    pub fn new(mut config: RecordingConfig) -> Result<Self, String> {
+        let entries = match config.mode {
+            RecordingMode::Record => Vec::new(),
+            RecordingMode::Replay => match config.log.take() {
+                Some(log) => log,
+                None => load(&config.key)?,
+            },
+        };
+        Ok(Self {
+            log: Mutex::new(Log {
+                index: ReplayIndex::build(&entries),
+                entries,
+                unmatched: Vec::new(),
+                full: false,
+                store_error: None,
+            }),
+            config,
+        })
+    }
+
+    pub fn mode(&self) -> RecordingMode {
+        self.config.mode
+    }
+
+    /// Exchanges recorded, or available to replay
+    pub fn entries(&self) -> usize {
+        self.log().entries.len()
+    }
+
+    fn log(&self) -> std::sync::MutexGuard<'_, Log> {
+        self.log.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Mode, counts, and, with `include_log`, the log itself
+    pub fn summary(&self, include_log: bool) -> serde_json::Value {
+        let log = self.log();
+        let mut summary = serde_json::json!({
+            "mode": self.config.mode,
+            "key": self.config.key,
+            "entries": log.entries.len(),
+            "max_entries": self.config.max_entries,
+            "full": log.full,
+            "unmatched": log.unmatched,
+            "store_error": log.store_error,
+        });
+        if include_log {
+            summary["log"] = serde_json::to_value(&log.entries).unwrap_or_default();
+        }
+        summary
+    }
+
+    fn record(&self, exchange: Exchange) {
+        let mut log = self.log();
+        if log.entries.len() >= self.config.max_entries {
+            log.full = true;
+            return;
+        }
+        let stored = kv::append_entry(&self.config.key, log.entries.len() + 1, &exchange);
+        if let Err(e) = stored {
+            crate::logging::warn("recording", format!("Replay log not stored: {}", e)).emit();
+            log.store_error = Some(e);
+        }
+        log.entries.push(exchange);
+    }
+
+    fn replay(&self, request: &HttpRequest) -> HttpResponse {
+        let url = redact::scrub(&request.url);
+        let body = request.body.as_deref().map(redact::scrub);
+        let mut log = self.log();
+        let method = request.method.as_str();
+        match log.index.next(method, &url, body) {
+            Some(position) => log.entries[position].response(),
+            None => {
+                let path = HttpRequest::path_of(&url).to_string();
+                log.unmatched.push(format!("{} {}", method, url));
+                HttpResponse {
+                    status: 404,
+                    headers: HashMap::new(),
+                    body: serde_json::json!({
+                        "code": 40400000,
+                        "message": format!("{} {} is not in the replay log", method, path),
+                    })
+                    .to_string(),
+                    error: None,
+                }
+            }
+        }
+    }
+}
+
+impl Middleware for Recorder {
+    fn handle(&self, request: HttpRequest, next: Next<'_>) -> HttpResponse {
+        match self.config.mode {
+            RecordingMode::Replay => self.replay(&request),
+            RecordingMode::Record => {
+                let captured = HttpRequest {
+                    headers: HashMap::new(),
+                    ..request.clone()
+                };
+                let response = next.run(request);
+                self.record(Exchange::capture(&captured, &response));
+                response
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alpaca::{AlpacaClient, ClientOptions};
+    use crate::http::HttpMethod;
+    use crate::mock::{response, MockTransport};
+    use std::sync::Arc;
+
+    fn client(recorder: &Arc<Recorder>, transport: MockTransport) -> AlpacaClient {
+        AlpacaClient::with_transport(
+            "PKRECORDTEST".to_string(),
+            "recording-test-secret".to_string(),
+            true,
+            ClientOptions {
+                recorder: Some(recorder.clone()),
+                response_cache: None,
+                ..ClientOptions::default()
+            },
+            transport,
+        )
+    }
+
+    #[test]
+    fn recorded_traffic_replays_offline_in_order() {
+        redact::register_secret("recording-test-secret");
+        let config = |mode: &str| {
+            RecordingConfig::from_config(Some(&serde_json::json!({
+                "mode": mode,
+                "key": "recording-test",
+            })))
+            .unwrap()
+            .unwrap()
+        };
+        let live = MockTransport::new();
+        live.on(
+            HttpMethod::Get,
+            "/v2/orders/o1",
+            response(
+                200,
+                r#"{"id":"o1","status":"new","echo":"recording-test-secret"}"#,
+            ),
+        )
+        .on(
+            HttpMethod::Get,
+            "/v2/orders/o1",
+            response(200, r#"{"id":"o1","status":"filled"}"#),
+        );
+        let recorder = Arc::new(Recorder::new(config("record")).unwrap());
+        let recording = client(&recorder, live.clone());
+        for _ in 0..2 {
+            recording.raw_request(HttpMethod::Get, "/v2/orders/o1", None, false);
+        }
+        assert_eq!(recorder.summary(false)["entries"], 2);
+
+        let stored = load("recording-test").unwrap();
+        assert!(!stored[0].body.contains("recording-test-secret"));
+
+        let offline = MockTransport::new();
+        let replayer = Arc::new(Recorder::new(config("replay")).unwrap());
+        let replaying = client(&replayer, offline.clone());
+        let statuses: Vec<String> = (0..3)
+            .map(|_| {
+                let body = replaying
+                    .raw_request(HttpMethod::Get, "/v2/orders/o1", None, false)
+                    .body;
+                let order: serde_json::Value = serde_json::from_str(&body).unwrap();
+                order["status"].as_str().unwrap_or_default().to_string()
+            })
+            .collect();
+        assert_eq!(statuses, ["new", "filled", "filled"]);
+        let missing = replaying.raw_request(HttpMethod::Get, "/v2/account", None, false);
+        assert_eq!(missing.status, 404);
+        assert!(missing.body.contains("not in the replay log"));
+        assert_eq!(
+            replayer.summary(false)["unmatched"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(offline.requests().is_empty());
+    }
+}
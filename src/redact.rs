@@ -0,0 +1,150 @@
+//! Secret redaction
+//!
+//! Scrubs credentials from anything that leaves the plugin as text: log
+//! lines and responses returned to the host. Two layers are applied:
+//! literal replacement of every secret registered at initialize, and
+//! key-based masking of values that follow sensitive names such as
+//! `APCA-API-SECRET-KEY` or `api_secret` (covers header dumps and echoed
+//! config even for values we were never told about).
+
+use std::sync::RwLock;
+
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Names whose following value is always masked (matched case-insensitively)
+const SENSITIVE_KEYS: &[&str] = &[
+    "apca-api-key-id",
+    "apca-api-secret-key",
+    "api_key",
+    "api_secret",
+    "authorization",
+    "tax_id",
+];
+
+/// Registered secrets shorter than this are ignored to avoid mangling
+/// ordinary text
+const MIN_SECRET_LEN: usize = 6;
+
+lazy_static::lazy_static! {
+    static ref SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+}
+
+/// Register a literal value that must never appear in output
+pub fn register_secret(secret: &str) {
+    if secret.len() < MIN_SECRET_LEN {
+        return;
+    }
+    let mut secrets = SECRETS.write().unwrap_or_else(|e| e.into_inner());
+    if !secrets.iter().any(|s| s == secret) {
+        secrets.push(secret.to_string());
+        // Longest first so a secret containing another is fully replaced
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+    }
+}
+
+/// Forget all registered secrets (used when credentials are replaced)
+pub fn clear_secrets() {
+    SECRETS.write().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Return `text` with all known secrets and sensitive values masked
+pub fn scrub(text: &str) -> String {
+    let mut out = text.to_string();
+    {
+        let secrets = SECRETS.read().unwrap_or_else(|e| e.into_inner());
+        for secret in secrets.iter() {
+            if out.contains(secret.as_str()) {
+                out = out.replace(secret.as_str(), REDACTED);
+            }
+        }
+    }
+    for key in SENSITIVE_KEYS {
+        out = mask_after_key(&out, key);
+    }
+    out
+}
+
+/// [`scrub`] for a JSON value that will not be rendered as JSON text:
+/// every string is scrubbed, and values under sensitive keys are masked
+pub fn scrub_value(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::String(text) => *text = scrub(text),
+        serde_json::Value::Array(items) => items.iter_mut().for_each(scrub_value),
+        serde_json::Value::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                let sensitive = SENSITIVE_KEYS.contains(&key.to_ascii_lowercase().as_str());
+                match item {
+                    serde_json::Value::Null => {}
+                    _ if sensitive => *item = REDACTED.into(),
+                    _ => scrub_value(item),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Mask the value following each occurrence of `key`, e.g.
+/// `APCA-API-KEY-ID: abc`, `"api_secret":"abc"` or `api_key=abc`.
+fn mask_after_key(text: &str, key: &str) -> String {
+    let lower = text.to_ascii_lowercase();
+    let bytes = text.as_bytes();
+    let mut out = String::with_capacity(text.len());
+    let mut cursor = 0;
+    let mut search_from = 0;
+
+    while let Some(found) = lower[search_from..].find(key) {
+        let key_end = search_from + found + key.len();
+        search_from = key_end;
+
+        // Skip closing quote, separator, whitespace, and opening quote
+        let mut i = key_end;
+        if i < bytes.len() && bytes[i] == b'"' {
+            i += 1;
+        }
+        while i < bytes.len() && bytes[i] == b' ' {
+            i += 1;
+        }
+        if i >= bytes.len() || !matches!(bytes[i], b':' | b'=') {
+            continue;
+        }
+        i += 1;
+        while i < bytes.len() && bytes[i] == b' ' {
+            i += 1;
+        }
+        let quoted = i < bytes.len() && bytes[i] == b'"';
+        if quoted {
+            i += 1;
+        }
+
+        let value_start = i;
+        while i < bytes.len() {
+            let b = bytes[i];
+            // An escaped quote inside a JSON string does not end the value
+            if quoted && b == b'\\' {
+                i = (i + 2).min(bytes.len());
+                continue;
+            }
+            let ends = if quoted {
+                b == b'"'
+            } else {
+                matches!(b, b'"' | b',' | b'&' | b';' | b'}' | b' ' | b'\n' | b'\r')
+            };
+            if ends {
+                break;
+            }
+            i += 1;
+        }
+        if i == value_start || text[value_start..i] == *REDACTED {
+            continue;
+        }
+
+        out.push_str(&text[cursor..value_start]);
+        out.push_str(REDACTED);
+        cursor = i;
+        search_from = i;
+    }
+
+    out.push_str(&text[cursor..]);
+    out
+}
@@ -0,0 +1,104 @@
+//! Reference prices at submission
+//!
+//! An order's `reference_price` is what its fill is later measured
+//! against. When the caller leaves it out and `reference_prices` is on,
+//! the plugin takes a snapshot of
+//! the symbol just before sending the order and uses the quote midpoint,
+//! or the latest trade when the quote is one-sided. The snapshot itself is
+//! kept in `extensions.reference_quote` so slippage can be worked out
+//! afterwards from either side of the book. Only stock orders are priced:
+//! the snapshot endpoint is for stocks.
+
+use crate::decimal::{self, Decimal};
+use crate::market_data::Snapshot;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferenceSource {
+    Midpoint,
+    LastTrade,
+}
+
+/// The market when an order was sent
+#[derive(Clone, Debug, Serialize)]
+pub struct ReferenceQuote {
+    pub price: String,
+    pub source: ReferenceSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ask: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quote_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trade_at: Option<DateTime<Utc>>,
+    /// When the snapshot was taken
+    pub taken_at: DateTime<Utc>,
+}
+
+impl ReferenceQuote {
+    /// `None` when the snapshot has neither a two-sided quote nor a trade
+    pub fn from_snapshot(snapshot: &Snapshot, taken_at: DateTime<Utc>) -> Option<Self> {
+        let quote = snapshot.latest_quote.as_ref();
+        let trade = snapshot
+            .latest_trade
+            .as_ref()
+            .filter(|t| t.price > Decimal::ZERO);
+        let (price, source) = match (quote.and_then(|q| q.mid()), trade) {
+            (Some(mid), _) => (mid, ReferenceSource::Midpoint),
+            (None, Some(trade)) => (trade.price, ReferenceSource::LastTrade),
+            (None, None) => return None,
+        };
+        let side = |price: Decimal| (price > Decimal::ZERO).then(|| decimal::to_wire(price));
+        Some(Self {
+            price: decimal::to_wire(price),
+            source,
+            bid: quote.and_then(|q| side(q.bid_price)),
+            ask: quote.and_then(|q| side(q.ask_price)),
+            last: trade.map(|t| decimal::to_wire(t.price)),
+            quote_at: quote.map(|q| q.timestamp),
+            trade_at: trade.map(|t| t.timestamp),
+            taken_at,
+        })
+    }
+
+    pub fn price(&self) -> Option<f64> {
+        decimal::parse(&self.price).map(decimal::to_f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn midpoint_is_preferred_and_a_one_sided_quote_falls_back_to_the_trade() {
+        let taken_at: DateTime<Utc> = "2024-03-01T15:00:01Z".parse().unwrap();
+        let snapshot: Snapshot = serde_json::from_value(serde_json::json!({
+            "latestTrade": { "t": "2024-03-01T14:59:58Z", "p": 190.02 },
+            "latestQuote": { "t": "2024-03-01T15:00:00Z", "bp": 190.0, "ap": 190.1 },
+        }))
+        .unwrap();
+        let reference = ReferenceQuote::from_snapshot(&snapshot, taken_at).unwrap();
+        assert_eq!(reference.price, "190.05");
+        assert_eq!(reference.source, ReferenceSource::Midpoint);
+        assert_eq!(reference.last.as_deref(), Some("190.02"));
+        assert_eq!(reference.price(), Some(190.05));
+
+        let one_sided: Snapshot = serde_json::from_value(serde_json::json!({
+            "latestTrade": { "t": "2024-03-01T14:59:58Z", "p": 190.02 },
+            "latestQuote": { "t": "2024-03-01T15:00:00Z", "bp": 0.0, "ap": 190.1 },
+        }))
+        .unwrap();
+        let reference = ReferenceQuote::from_snapshot(&one_sided, taken_at).unwrap();
+        assert_eq!(reference.source, ReferenceSource::LastTrade);
+        assert_eq!(reference.bid, None);
+        assert_eq!(reference.ask.as_deref(), Some("190.1"));
+
+        assert!(ReferenceQuote::from_snapshot(&Snapshot::default(), taken_at).is_none());
+    }
+}
@@ -0,0 +1,243 @@
+//! Funds and shares held by working orders
+//!
+//! The reservation ledger is rebuilt from the tracked working orders: the
+//! unfilled part of each buy reserves its estimated cost (with the cost
+//! buffer), and the unfilled part of each sell reserves the shares it
+//! would deliver. `get_reserved_funds` reports the whole ledger.
+//!
+//! Alpaca's `buying_power` already deducts open buys, and a position's
+//! `qty_available` already excludes shares held by open sells, so the
+//! buying power check only holds back what was sent after the account and
+//! position were fetched ([`ReservedFunds::sent_since`]): orders the
+//! figures may not reflect yet. Settled cash deducts nothing for open
+//! orders, so it is held to the whole ledger.
+
+use crate::decimal::{self, Decimal};
+use crate::orders;
+use crate::pretrade;
+use chrono::{DateTime, Utc};
+use models::order::{Order, OrderSide, OrderStatus};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Reservation {
+    pub order_id: String,
+    pub symbol: String,
+    pub side: OrderSide,
+    /// Unfilled quantity
+    pub qty: Decimal,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price: Option<Decimal>,
+    /// Buying power held by a buy; zero for sells and unpriced buys
+    pub amount: Decimal,
+    /// When Alpaca accepted the order
+    pub sent_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ReservedFunds {
+    /// Estimated cost of the unfilled part of working buys
+    pub buying_power: Decimal,
+    /// Unfilled quantity of working sells, by symbol
+    pub shares: BTreeMap<String, Decimal>,
+    pub orders: Vec<Reservation>,
+    /// Working buys with no limit, reference, or stop price, which reserve
+    /// nothing
+    pub unpriced: Vec<String>,
+}
+
+impl ReservedFunds {
+    pub fn from_orders<'a>(
+        working: impl IntoIterator<Item = &'a Order>,
+        buffer_pct: Decimal,
+    ) -> Self {
+        let multiplier = Decimal::ONE + buffer_pct / Decimal::ONE_HUNDRED;
+        let mut funds = Self::default();
+        for order in working {
+            if !holds_reservation(order) {
+                continue;
+            }
+            let requested = decimal::from_f64(order.request.quantity).unwrap_or_default();
+            let filled = decimal::from_f64(order.filled_quantity).unwrap_or_default();
+            let qty = (requested - filled).max(Decimal::ZERO);
+            if qty.is_zero() {
+                continue;
+            }
+            let price = pretrade::estimate_price(&order.request, None).map(|e| e.price);
+            let amount = match (order.request.side, price) {
+                (OrderSide::Buy, Some(price)) => (qty * price * multiplier).round_dp(2),
+                _ => Decimal::ZERO,
+            };
+            funds.add(Reservation {
+                order_id: order.id.clone(),
+                symbol: order.request.symbol_id.to_ascii_uppercase(),
+                side: order.request.side,
+                qty,
+                price,
+                amount,
+                sent_at: order.created_at,
+            });
+        }
+        funds
+    }
+
+    fn add(&mut self, reservation: Reservation) {
+        match reservation.side {
+            OrderSide::Buy if reservation.price.is_none() => {
+                self.unpriced.push(reservation.order_id.clone())
+            }
+            OrderSide::Buy => self.buying_power += reservation.amount,
+            OrderSide::Sell => {
+                *self.shares.entry(reservation.symbol.clone()).or_default() += reservation.qty
+            }
+        }
+        self.orders.push(reservation);
+    }
+
+    /// The part of the ledger sent at or after `at`, which account and
+    /// position figures fetched at `at` may not reflect yet
+    pub fn sent_since(&self, at: DateTime<Utc>) -> Self {
+        let mut funds = Self::default();
+        for reservation in self.orders.iter().filter(|r| r.sent_at >= at) {
+            funds.add(reservation.clone());
+        }
+        funds
+    }
+
+    /// Add the reservations of another ledger to this one
+    pub fn merge(&mut self, other: ReservedFunds) {
+        for reservation in other.orders {
+            self.add(reservation);
+        }
+    }
+
+    /// Shares of `symbol` that working sells will deliver
+    pub fn shares_of(&self, symbol: &str) -> Decimal {
+        self.shares
+            .get(&symbol.to_ascii_uppercase())
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+fn holds_reservation(order: &Order) -> bool {
+    matches!(
+        order.status,
+        OrderStatus::Submitted | OrderStatus::PartiallyFilled
+    ) && orders::is_working(order)
+        && !orders::request_flag(&order.request, "dry_run")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+    use models::order::{OrderRequest, OrderType};
+
+    /// `request` as a working order, `filled` shares in
+    fn working(id: &str, request: OrderRequest, filled: f64) -> Order {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "request": request,
+            "status": if filled > 0.0 { OrderStatus::PartiallyFilled } else { OrderStatus::Submitted },
+            "created_at": "2024-03-01T15:00:00Z",
+            "updated_at": "2024-03-01T15:00:00Z",
+            "average_filled_price": null,
+            "filled_quantity": filled,
+            "extensions": null,
+            "persona_id": "default",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn unfilled_buys_reserve_cost_and_sells_reserve_shares() {
+        let mut done = working(
+            "done",
+            order_request("aapl").qty(5.0).limit(100.0).build(),
+            0.0,
+        );
+        done.status = OrderStatus::Filled;
+        let orders = [
+            working(
+                "b1",
+                order_request("aapl").qty(10.0).limit(100.0).build(),
+                4.0,
+            ),
+            working(
+                "b2",
+                order_request("aapl").order_type(OrderType::Limit).build(),
+                0.0,
+            ),
+            working(
+                "s1",
+                order_request("aapl")
+                    .side(OrderSide::Sell)
+                    .qty(3.0)
+                    .limit(110.0)
+                    .build(),
+                1.0,
+            ),
+            done,
+        ];
+        let funds = ReservedFunds::from_orders(&orders, Decimal::ONE);
+        assert_eq!(funds.buying_power, Decimal::from(606));
+        assert_eq!(funds.shares_of("AAPL"), Decimal::from(2));
+        assert_eq!(funds.unpriced, ["b2"]);
+        assert_eq!(funds.orders.len(), 3);
+
+        // Figures fetched after every order was sent already reflect them
+        let fetched: DateTime<Utc> = "2024-03-01T15:00:01Z".parse().unwrap();
+        let unseen = funds.sent_since(fetched);
+        assert!(unseen.buying_power.is_zero());
+        assert!(unseen.orders.is_empty());
+        assert!(unseen.unpriced.is_empty());
+    }
+
+    #[test]
+    fn buying_power_that_reflects_open_orders_is_not_reduced_again() {
+        // $10,000 of buying power left after Alpaca deducted the resting
+        // 10 x $100 buy
+        let account: crate::alpaca::AlpacaAccount = serde_json::from_value(serde_json::json!({
+            "id": "a",
+            "account_number": "PA1",
+            "status": "ACTIVE",
+            "currency": "USD",
+            "cash": "11000",
+            "portfolio_value": "11000",
+            "buying_power": "10000",
+            "equity": "11000",
+            "last_equity": "11000",
+            "daytrade_count": 0,
+            "pattern_day_trader": false,
+        }))
+        .unwrap();
+        let resting = [working(
+            "b1",
+            order_request("aapl").qty(10.0).limit(100.0).build(),
+            0.0,
+        )];
+        let ledger = ReservedFunds::from_orders(&resting, Decimal::ZERO);
+        let fetched: DateTime<Utc> = "2024-03-01T15:00:05Z".parse().unwrap();
+        let new_buy = order_request("aapl").qty(95.0).limit(100.0).build();
+
+        let check = |reserved: &ReservedFunds| {
+            pretrade::check_buying_power(
+                pretrade::CheckMode::Enforce,
+                &new_buy,
+                &account,
+                None,
+                Decimal::ZERO,
+                reserved,
+                None,
+            )
+        };
+        assert!(matches!(
+            check(&ledger.sent_since(fetched)),
+            pretrade::CheckOutcome::Pass
+        ));
+        // Counting the resting order a second time would block it
+        assert!(matches!(check(&ledger), pretrade::CheckOutcome::Block(_)));
+    }
+}
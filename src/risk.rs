@@ -0,0 +1,239 @@
+//! Risk limits
+//!
+//! Hard limits configured at initialize under `risk_limits` and enforced
+//! by `submit_order` before the pre-trade checks. Unlike those checks,
+//! limits have no warn mode: a breach always rejects the order locally.
+
+use crate::decimal::{self, Decimal};
+use crate::market_time;
+use crate::pretrade::{self, CheckOutcome, Finding};
+use chrono::NaiveDate;
+use models::order::{OrderRequest, OrderSide};
+use models::portfolio::Position;
+
+#[derive(Clone, Debug, Default)]
+pub struct RiskLimits {
+    /// Largest notional value of a single order
+    pub max_order_notional: Option<Decimal>,
+    /// Largest absolute notional value of the resulting position, per symbol
+    pub max_position_notional: Option<Decimal>,
+    /// Orders accepted per US Eastern trading day
+    pub max_daily_orders: Option<u32>,
+    /// When non-empty, only these symbols may be traded
+    pub allowed_symbols: Vec<String>,
+    pub denied_symbols: Vec<String>,
+    /// Single-order notional cap that applies only to live accounts
+    pub live_max_order_notional: Option<Decimal>,
+    /// Intraday loss at which risk-increasing orders stop, see
+    /// [`crate::loss_limit`]
+    pub max_daily_loss: Option<Decimal>,
+}
+
+impl RiskLimits {
+    /// Parse the `risk_limits` config object; absent keys leave a limit off
+    pub fn from_config(value: Option<&serde_json::Value>) -> Result<Self, String> {
+        let Some(value) = value else {
+            return Ok(Self::default());
+        };
+        let object = value.as_object().ok_or("risk_limits must be an object")?;
+
+        let notional = |key: &str| -> Result<Option<Decimal>, String> {
+            match object.get(key) {
+                None | Some(serde_json::Value::Null) => Ok(None),
+                Some(v) => v
+                    .as_f64()
+                    .filter(|n| *n >= 0.0)
+                    .and_then(decimal::from_f64)
+                    .map(Some)
+                    .ok_or_else(|| format!("risk_limits.{} must be a non-negative number", key)),
+            }
+        };
+        let symbols = |key: &str| -> Result<Vec<String>, String> {
+            match object.get(key) {
+                None | Some(serde_json::Value::Null) => Ok(Vec::new()),
+                Some(v) => serde_json::from_value::<Vec<String>>(v.clone())
+                    .map(|list| list.iter().map(|s| s.to_ascii_uppercase()).collect())
+                    .map_err(|_| format!("risk_limits.{} must be an array of symbols", key)),
+            }
+        };
+
+        let max_daily_orders = match object.get("max_daily_orders") {
+            None | Some(serde_json::Value::Null) => None,
+            Some(v) => Some(
+                v.as_u64()
+                    .and_then(|n| u32::try_from(n).ok())
+                    .ok_or("risk_limits.max_daily_orders must be a non-negative integer")?,
+            ),
+        };
+
+        Ok(Self {
+            max_order_notional: notional("max_order_notional")?,
+            max_position_notional: notional("max_position_notional")?,
+            max_daily_orders,
+            allowed_symbols: symbols("allowed_symbols")?,
+            denied_symbols: symbols("denied_symbols")?,
+            live_max_order_notional: notional("live_max_order_notional")?,
+            max_daily_loss: notional("max_daily_loss")?,
+        })
+    }
+
+    /// Whether any limit needs the current position to evaluate
+    pub fn needs_position(&self) -> bool {
+        self.max_position_notional.is_some() || self.max_daily_loss.is_some()
+    }
+
+    fn needs_price(&self, is_paper: bool) -> bool {
+        self.max_order_notional.is_some()
+            || self.max_position_notional.is_some()
+            || (!is_paper && self.live_max_order_notional.is_some())
+    }
+}
+
+/// Orders accepted so far on the current trading day
+#[derive(Clone, Debug, Default)]
+pub struct DailyOrderCount {
+    date: Option<NaiveDate>,
+    count: u32,
+}
+
+impl DailyOrderCount {
+    pub fn get(&self) -> u32 {
+        if self.date == Some(market_time::eastern_today()) {
+            self.count
+        } else {
+            0
+        }
+    }
+
+    pub fn increment(&mut self) {
+        let today = market_time::eastern_today();
+        if self.date != Some(today) {
+            self.date = Some(today);
+            self.count = 0;
+        }
+        self.count += 1;
+    }
+}
+
+fn breach(code: &str, message: String, details: serde_json::Value) -> CheckOutcome {
+    CheckOutcome::Block(Finding::new(code, message, details))
+}
+
+/// Limits that need no market data: symbol lists and the daily order count
+pub fn check_static(limits: &RiskLimits, order: &OrderRequest, orders_today: u32) -> CheckOutcome {
+    let symbol = order.symbol_id.to_ascii_uppercase();
+
+    if limits.denied_symbols.contains(&symbol) {
+        return breach(
+            "symbol_denied",
+            format!("{} is on the denied symbol list", symbol),
+            serde_json::json!({ "symbol": symbol }),
+        );
+    }
+    if !limits.allowed_symbols.is_empty() && !limits.allowed_symbols.contains(&symbol) {
+        return breach(
+            "symbol_not_allowed",
+            format!("{} is not on the allowed symbol list", symbol),
+            serde_json::json!({ "symbol": symbol }),
+        );
+    }
+    if let Some(max) = limits.max_daily_orders {
+        if orders_today >= max {
+            return breach(
+                "max_daily_orders",
+                format!("Daily order limit of {} reached", max),
+                serde_json::json!({ "orders_today": orders_today, "max_daily_orders": max }),
+            );
+        }
+    }
+    CheckOutcome::Pass
+}
+
+/// Notional limits for the order and for the position it would leave
+pub fn check_notional(
+    limits: &RiskLimits,
+    order: &OrderRequest,
+    position: Option<&Position>,
+    is_paper: bool,
+) -> Vec<CheckOutcome> {
+    if !limits.needs_price(is_paper) {
+        return Vec::new();
+    }
+
+    let Some(estimate) = pretrade::estimate_price(order, position) else {
+        return vec![breach(
+            "risk_price_unavailable",
+            "No limit, reference, or position price available to enforce notional limits"
+                .to_string(),
+            serde_json::json!({ "symbol": order.symbol_id }),
+        )];
+    };
+
+    let qty = decimal::from_f64(order.quantity).unwrap_or_default();
+    let notional = (qty * estimate.price).round_dp(2);
+    let base = |limit: Decimal| {
+        serde_json::json!({
+            "symbol": order.symbol_id,
+            "qty": decimal::to_wire(qty),
+            "price": decimal::to_wire(estimate.price),
+            "price_source": estimate.source,
+            "notional": decimal::to_wire(notional),
+            "limit": decimal::to_wire(limit),
+        })
+    };
+
+    let mut outcomes = Vec::new();
+    if let Some(max) = limits.max_order_notional.filter(|max| notional > *max) {
+        outcomes.push(breach(
+            "max_order_notional",
+            format!(
+                "Order notional {} exceeds the limit of {}",
+                decimal::to_wire(notional),
+                decimal::to_wire(max)
+            ),
+            base(max),
+        ));
+    }
+    if !is_paper {
+        if let Some(max) = limits.live_max_order_notional.filter(|max| notional > *max) {
+            outcomes.push(breach(
+                "live_max_order_notional",
+                format!(
+                    "Order notional {} exceeds the live trading limit of {}",
+                    decimal::to_wire(notional),
+                    decimal::to_wire(max)
+                ),
+                base(max),
+            ));
+        }
+    }
+    if let Some(max) = limits.max_position_notional {
+        let held = position
+            .and_then(|p| decimal::from_f64(p.quantity))
+            .unwrap_or_default();
+        let resulting = match order.side {
+            OrderSide::Buy => held + qty,
+            OrderSide::Sell => held - qty,
+        };
+        let resulting_notional = (resulting.abs() * estimate.price).round_dp(2);
+        // Orders that shrink the position are always allowed, even when it
+        // is already over the limit
+        if resulting_notional > max && resulting.abs() > held.abs() {
+            let mut details = base(max);
+            details["held_qty"] = decimal::to_wire(held).into();
+            details["resulting_qty"] = decimal::to_wire(resulting).into();
+            details["resulting_notional"] = decimal::to_wire(resulting_notional).into();
+            outcomes.push(breach(
+                "max_position_notional",
+                format!(
+                    "Resulting {} position notional {} exceeds the limit of {}",
+                    order.symbol_id,
+                    decimal::to_wire(resulting_notional),
+                    decimal::to_wire(max)
+                ),
+                details,
+            ));
+        }
+    }
+    outcomes
+}
@@ -0,0 +1,248 @@
+//! Scheduled orders
+//!
+//! An order with `extensions.schedule` is held in the plugin and submitted
+//! by the `tick` export once its time arrives. The time is absolute or
+//! relative to the market open or close. Orders for the open or close
+//! itself become OPG/CLS orders, so that they take part in the auction.
+
+use crate::alpaca::{self, MarketClock};
+use crate::market_time;
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use models::order::{Order, OrderRequest, OrderStatus, OrderType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Alpaca accepts OPG orders from 19:00 ET the evening before the open
+const OPG_WINDOW_HOURS: i64 = 14;
+
+/// Alpaca rejects CLS orders in the last 10 minutes before the close
+const CLS_CUTOFF_MINUTES: i64 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Anchor {
+    Open,
+    Close,
+}
+
+/// `extensions.schedule`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ScheduleSpec {
+    /// `{"at": "2024-06-14T19:50:00Z"}` or `{"at": "15:50"}` (today, US Eastern)
+    At { at: String },
+    /// `{"relative_to": "close", "offset_minutes": -10}`
+    Relative {
+        relative_to: Anchor,
+        #[serde(default)]
+        offset_minutes: i64,
+    },
+}
+
+/// Read `extensions.schedule`; `Ok(None)` when the order is not scheduled
+pub fn requested(order: &OrderRequest) -> Result<Option<ScheduleSpec>, String> {
+    match order.extensions.as_ref().and_then(|e| e.get("schedule")) {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(v) => serde_json::from_value(v.clone())
+            .map(Some)
+            .map_err(|_| format!("Invalid schedule: {} (expected `at` or `relative_to`)", v)),
+    }
+}
+
+impl ScheduleSpec {
+    pub fn needs_clock(&self) -> bool {
+        matches!(self, Self::Relative { .. })
+    }
+
+    /// Work out when to submit and with which time in force. Returns the
+    /// release time (possibly now) and the request to send then.
+    pub fn resolve(
+        &self,
+        order: &OrderRequest,
+        clock: Option<&MarketClock>,
+        now: DateTime<Utc>,
+    ) -> Result<(DateTime<Utc>, OrderRequest), String> {
+        let mut request = without_schedule(order);
+        let auction_eligible = matches!(order.order_type, OrderType::Market | OrderType::Limit)
+            && alpaca::requested_time_in_force(order)? == "day"
+            && !alpaca::requested_extended_hours(order);
+
+        let release_at = match self {
+            Self::At { at } => {
+                let target = parse_at(at, now)?;
+                if target <= now {
+                    return Err(format!("Scheduled time {} is in the past", at));
+                }
+                target
+            }
+            Self::Relative {
+                relative_to,
+                offset_minutes,
+            } => {
+                let clock = clock.ok_or("Market clock unavailable for relative schedule")?;
+                match (relative_to, offset_minutes) {
+                    (Anchor::Open, 0) if auction_eligible => {
+                        set_time_in_force(&mut request, "opg");
+                        (clock.next_open - Duration::hours(OPG_WINDOW_HOURS)).max(now)
+                    }
+                    (Anchor::Close, 0) if auction_eligible => {
+                        set_time_in_force(&mut request, "cls");
+                        if clock.is_open {
+                            let cutoff = clock.next_close - Duration::minutes(CLS_CUTOFF_MINUTES);
+                            if now >= cutoff {
+                                return Err(
+                                    "Too late for a market-on-close order today".to_string()
+                                );
+                            }
+                            now
+                        } else {
+                            clock.next_open
+                        }
+                    }
+                    (anchor, offset) => {
+                        let base = match anchor {
+                            Anchor::Open => clock.next_open,
+                            // While closed, the next close is that of the next session
+                            Anchor::Close => clock.next_close,
+                        };
+                        let target = base + Duration::minutes(*offset);
+                        if target <= now {
+                            return Err(format!(
+                                "Scheduled time {} is in the past",
+                                target.to_rfc3339()
+                            ));
+                        }
+                        target
+                    }
+                }
+            }
+        };
+        Ok((release_at, request))
+    }
+}
+
+fn parse_at(at: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(at) {
+        return Ok(dt.with_timezone(&Utc));
+    }
+    let time = NaiveTime::parse_from_str(at, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(at, "%H:%M:%S"))
+        .map_err(|_| {
+            format!(
+                "Invalid schedule.at: {} (expected RFC 3339 or HH:MM US Eastern)",
+                at
+            )
+        })?;
+    Ok(market_time::eastern_at(
+        market_time::eastern_date(now),
+        time,
+    ))
+}
+
+fn without_schedule(order: &OrderRequest) -> OrderRequest {
+    let mut request = order.clone();
+    if let Some(ext) = request.extensions.as_mut() {
+        ext.remove("schedule");
+    }
+    request
+}
+
+fn set_time_in_force(request: &mut OrderRequest, tif: &str) {
+    request
+        .extensions
+        .get_or_insert_with(HashMap::new)
+        .insert("time_in_force".to_string(), tif.into());
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ScheduledOrder {
+    pub schedule_id: String,
+    pub request: OrderRequest,
+    pub schedule: ScheduleSpec,
+    pub created_at: DateTime<Utc>,
+    pub release_at: DateTime<Utc>,
+}
+
+impl ScheduledOrder {
+    /// Host-facing view of the held order
+    pub fn to_order(&self) -> Order {
+        let mut extensions = HashMap::new();
+        extensions.insert("scheduled".to_string(), true.into());
+        extensions.insert(
+            "release_at".to_string(),
+            self.release_at.to_rfc3339().into(),
+        );
+        extensions.insert(
+            "schedule".to_string(),
+            serde_json::to_value(&self.schedule).unwrap_or_default(),
+        );
+        extensions.insert("alpaca_status".to_string(), "scheduled".into());
+        Order {
+            id: self.schedule_id.clone(),
+            request: self.request.clone(),
+            status: OrderStatus::Submitted,
+            created_at: self.created_at,
+            updated_at: self.created_at,
+            average_filled_price: None,
+            filled_quantity: 0.0,
+            extensions: Some(extensions),
+            persona_id: self.request.persona_id.clone(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ScheduleBook {
+    orders: Vec<ScheduledOrder>,
+    next_id: u64,
+}
+
+impl ScheduleBook {
+    pub fn rename_symbol(&mut self, old: &str, new: &str) -> usize {
+        self.orders
+            .iter_mut()
+            .map(|s| crate::orders::rename_symbol(&mut s.request, old, new))
+            .filter(|renamed| *renamed)
+            .count()
+    }
+
+    pub fn push(
+        &mut self,
+        request: OrderRequest,
+        schedule: ScheduleSpec,
+        release_at: DateTime<Utc>,
+    ) -> &ScheduledOrder {
+        self.next_id += 1;
+        let now = Utc::now();
+        self.orders.push(ScheduledOrder {
+            schedule_id: format!("sched_{}_{}", now.timestamp_millis(), self.next_id),
+            request,
+            schedule,
+            created_at: now,
+            release_at,
+        });
+        self.orders.last().expect("just pushed")
+    }
+
+    pub fn list(&self) -> &[ScheduledOrder] {
+        &self.orders
+    }
+
+    pub fn remove(&mut self, schedule_id: &str) -> Option<ScheduledOrder> {
+        let index = self
+            .orders
+            .iter()
+            .position(|o| o.schedule_id == schedule_id)?;
+        Some(self.orders.remove(index))
+    }
+
+    /// Take the entries due at `now`, earliest first
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> Vec<ScheduledOrder> {
+        let (mut due, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.orders)
+            .into_iter()
+            .partition(|o| o.release_at <= now);
+        self.orders = pending;
+        due.sort_by_key(|o| o.release_at);
+        due
+    }
+}
@@ -0,0 +1,82 @@
+//! Credentials from the host's secret store
+//!
+//! Instead of putting `api_key` and `api_secret` in the config, where the
+//! host may persist them in clear text, the config can name them with
+//! `api_key_ref` and `api_secret_ref`. With the `host-secrets` feature the
+//! plugin resolves each reference through the host's
+//! `get_secret(ptr, len) -> u64` import at initialize and again on
+//! `rotate_credentials`. The import receives the reference as UTF-8 and
+//! returns a packed pointer and length to a buffer from `alloc` holding
+//! `{"value": "..."}` or `{"error": "..."}`; 0 means no such secret.
+
+#[cfg(all(feature = "host-secrets", target_arch = "wasm32"))]
+extern "C" {
+    fn get_secret(ptr: i32, len: i32) -> u64;
+}
+
+/// Whether this build can resolve references at all
+pub const AVAILABLE: bool = cfg!(all(feature = "host-secrets", target_arch = "wasm32"));
+
+#[derive(serde::Deserialize)]
+struct SecretResponse {
+    #[serde(default)]
+    value: Option<String>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// Look up one secret by reference
+#[cfg(all(feature = "host-secrets", target_arch = "wasm32"))]
+pub fn resolve(reference: &str) -> Result<String, String> {
+    // SAFETY: the host only reads `len` bytes at `ptr` during the call
+    let packed = unsafe { get_secret(reference.as_ptr() as i32, reference.len() as i32) };
+    if packed == 0 {
+        return decode(reference, None);
+    }
+    let (ptr, len) = crate::unpack_ptr_len(packed);
+    // SAFETY: the host wrote the response into a buffer from our `alloc`
+    let bytes = unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) }.to_vec();
+    unsafe { crate::arena::free(ptr as usize as *mut u8, len as usize) };
+    decode(reference, Some(&bytes))
+}
+
+#[cfg(not(all(feature = "host-secrets", target_arch = "wasm32")))]
+pub fn resolve(reference: &str) -> Result<String, String> {
+    Err(format!(
+        "Cannot resolve {}: this build has no host secret store (host-secrets feature)",
+        reference
+    ))
+}
+
+/// Turn the host's answer into the secret or an error naming the reference
+fn decode(reference: &str, bytes: Option<&[u8]>) -> Result<String, String> {
+    let Some(bytes) = bytes else {
+        return Err(format!("Secret {} not found", reference));
+    };
+    let response: SecretResponse = serde_json::from_slice(bytes)
+        .map_err(|e| format!("Invalid get_secret response for {}: {}", reference, e))?;
+    match (response.value, response.error) {
+        (Some(value), None) if !value.is_empty() => Ok(value),
+        (_, Some(error)) => Err(format!("Secret {}: {}", reference, error)),
+        _ => Err(format!("Secret {} is empty", reference)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_answers_decode_to_the_secret_or_an_error() {
+        assert_eq!(
+            decode("vault:alpaca/key", Some(br#"{"value":"PKTEST123"}"#)).unwrap(),
+            "PKTEST123"
+        );
+        let err = decode("vault:alpaca/key", Some(br#"{"error":"access denied"}"#)).unwrap_err();
+        assert!(err.contains("access denied"), "{}", err);
+        assert!(decode("vault:alpaca/key", Some(br#"{"value":""}"#)).is_err());
+        assert!(decode("vault:alpaca/key", None)
+            .unwrap_err()
+            .contains("not found"));
+    }
+}
@@ -0,0 +1,213 @@
+//! Cash settlement
+//!
+//! Sale proceeds show up in `cash` at once but only settle on the
+//! settlement date: T+1 for US equities and options, the trade date for
+//! crypto. On a cash account (multiplier 1), buying with unsettled
+//! proceeds and selling before they settle is a good-faith violation, so
+//! with `pretrade_checks` on, buys on a cash account are checked against
+//! settled cash rather than buying power.
+//!
+//! Alpaca does not report unsettled amounts, so they are rebuilt from the
+//! fills ledger: every sell whose settlement date (from the market
+//! calendar, see [`crate::confirmations::settlement_date`]) is after today
+//! holds its proceeds back. Cash pending transfer out is held back too.
+
+use crate::alpaca::{AlpacaAccount, CalendarDay};
+use crate::confirmations;
+use crate::decimal::{self, Decimal};
+use crate::executions::Execution;
+use crate::fees::AssetKind;
+use crate::market_time;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::Serialize;
+
+/// How far back fills can still be unsettled, with room for long weekends
+pub const LOOKBACK_DAYS: i64 = 7;
+
+/// A fill whose cash has not settled yet
+#[derive(Clone, Debug, Serialize)]
+pub struct UnsettledTrade {
+    pub execution_id: String,
+    pub symbol: String,
+    pub side: String,
+    pub qty: String,
+    /// Quantity times price (times 100 for option contracts)
+    pub amount: String,
+    pub executed_at: DateTime<Utc>,
+    pub trade_date: NaiveDate,
+    pub settlement_date: NaiveDate,
+}
+
+/// Fills in `fills` that settle after `today`, oldest first
+pub fn unsettled(
+    fills: &[Execution],
+    calendar: &[CalendarDay],
+    today: NaiveDate,
+) -> Vec<UnsettledTrade> {
+    fills
+        .iter()
+        .filter_map(|fill| {
+            let kind = AssetKind::of(&fill.symbol);
+            let trade_date = market_time::eastern_date(fill.timestamp);
+            let settlement_date = confirmations::settlement_date(kind, trade_date, calendar);
+            if settlement_date <= today {
+                return None;
+            }
+            let contract_size = match kind {
+                AssetKind::Option => Decimal::ONE_HUNDRED,
+                _ => Decimal::ONE,
+            };
+            let amount = decimal::parse_or_zero(&fill.qty)
+                * decimal::parse_or_zero(&fill.price)
+                * contract_size;
+            Some(UnsettledTrade {
+                execution_id: fill.id.clone(),
+                symbol: fill.symbol.clone(),
+                side: fill.side.clone(),
+                qty: fill.qty.clone(),
+                amount: decimal::to_wire(amount.round_dp(2)),
+                executed_at: fill.timestamp,
+                trade_date,
+                settlement_date,
+            })
+        })
+        .collect()
+}
+
+/// Settled and unsettled cash on an account
+#[derive(Clone, Debug, Serialize)]
+pub struct CashSettlement {
+    /// Multiplier 1: no margin, so only settled cash should be spent
+    pub cash_account: bool,
+    pub cash: String,
+    /// Cash less unsettled sale proceeds and pending transfers out
+    pub settled_cash: String,
+    /// Proceeds of sells that have not settled
+    pub unsettled_cash: String,
+    pub pending_transfer_in: String,
+    pub pending_transfer_out: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub non_marginable_buying_power: Option<String>,
+    /// Unsettled sells, oldest first
+    pub unsettled_sales: Vec<UnsettledTrade>,
+    /// Unsettled buys, oldest first
+    pub unsettled_purchases: Vec<UnsettledTrade>,
+}
+
+impl CashSettlement {
+    pub fn build(account: &AlpacaAccount, unsettled: Vec<UnsettledTrade>) -> Self {
+        let amount = |raw: &Option<String>| raw.as_deref().and_then(decimal::parse);
+        let cash = decimal::parse_or_zero(&account.cash);
+        let pending_in = amount(&account.pending_transfer_in).unwrap_or_default();
+        let pending_out = amount(&account.pending_transfer_out).unwrap_or_default();
+        let (unsettled_sales, unsettled_purchases): (Vec<_>, Vec<_>) = unsettled
+            .into_iter()
+            .partition(|trade| trade.side.starts_with("sell"));
+        let unsettled_cash = unsettled_proceeds(&unsettled_sales);
+        let settled = settled_balance(account, &unsettled_sales).max(Decimal::ZERO);
+        Self {
+            cash_account: account.is_cash_account(),
+            cash: decimal::to_wire(cash),
+            settled_cash: decimal::to_wire(settled),
+            unsettled_cash: decimal::to_wire(unsettled_cash),
+            pending_transfer_in: decimal::to_wire(pending_in),
+            pending_transfer_out: decimal::to_wire(pending_out),
+            non_marginable_buying_power: amount(&account.non_marginable_buying_power)
+                .map(decimal::to_wire),
+            unsettled_sales,
+            unsettled_purchases,
+        }
+    }
+
+    pub fn settled_cash(&self) -> Decimal {
+        decimal::parse_or_zero(&self.settled_cash)
+    }
+}
+
+fn unsettled_proceeds(unsettled: &[UnsettledTrade]) -> Decimal {
+    unsettled
+        .iter()
+        .filter(|trade| trade.side.starts_with("sell"))
+        .map(|trade| decimal::parse_or_zero(&trade.amount))
+        .sum()
+}
+
+/// Cash less unsettled sale proceeds and pending transfers out; negative
+/// when unsettled proceeds have been spent
+pub fn settled_balance(account: &AlpacaAccount, unsettled: &[UnsettledTrade]) -> Decimal {
+    let pending_out = account
+        .pending_transfer_out
+        .as_deref()
+        .and_then(decimal::parse)
+        .unwrap_or_default();
+    decimal::parse_or_zero(&account.cash) - unsettled_proceeds(unsettled) - pending_out.abs()
+}
+
+/// Start of the fill window that can still hold unsettled trades
+pub fn window_start(today: NaiveDate) -> DateTime<Utc> {
+    market_time::eastern_at(
+        today - Duration::days(LOOKBACK_DAYS),
+        chrono::NaiveTime::MIN,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(symbol: &str, side: &str, qty: &str, price: &str, timestamp: &str) -> Execution {
+        Execution {
+            id: format!("{}-{}", symbol, timestamp),
+            order_id: "o1".to_string(),
+            symbol: symbol.to_string(),
+            side: side.to_string(),
+            qty: qty.to_string(),
+            price: price.to_string(),
+            timestamp: timestamp.parse().unwrap(),
+            fill_type: "fill".to_string(),
+            cum_qty: None,
+            leaves_qty: None,
+            venue: None,
+        }
+    }
+
+    #[test]
+    fn sale_proceeds_are_held_back_until_the_settlement_date() {
+        let date = |s: &str| s.parse::<NaiveDate>().unwrap();
+        let calendar = [
+            CalendarDay {
+                date: date("2024-03-01"),
+                settlement_date: Some(date("2024-03-04")),
+                close: None,
+            },
+            CalendarDay {
+                date: date("2024-03-04"),
+                settlement_date: Some(date("2024-03-05")),
+                close: None,
+            },
+        ];
+        let fills = [
+            // Friday's sell settles Monday
+            fill("AAPL", "sell", "10", "180", "2024-03-01T15:00:00Z"),
+            fill("MSFT", "buy", "2", "400", "2024-03-01T16:00:00Z"),
+            // Crypto settles on the trade date
+            fill("BTC/USD", "sell", "0.1", "60000", "2024-03-01T17:00:00Z"),
+        ];
+        let pending = unsettled(&fills, &calendar, date("2024-03-01"));
+        assert_eq!(pending.len(), 2);
+        assert!(unsettled(&fills, &calendar, date("2024-03-04")).is_empty());
+
+        let account: AlpacaAccount = serde_json::from_value(serde_json::json!({
+            "id": "a1", "account_number": "PA1", "status": "ACTIVE", "currency": "USD",
+            "cash": "5000", "portfolio_value": "5000", "buying_power": "5000",
+            "equity": "5000", "last_equity": "5000", "multiplier": "1",
+            "pending_transfer_out": "500",
+        }))
+        .unwrap();
+        let settlement = CashSettlement::build(&account, pending);
+        assert!(settlement.cash_account);
+        assert_eq!(settlement.unsettled_cash, "1800");
+        assert_eq!(settlement.settled_cash, "2700");
+        assert_eq!(settlement.unsettled_purchases[0].symbol, "MSFT");
+    }
+}
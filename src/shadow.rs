@@ -0,0 +1,159 @@
+//! Shadow orders
+//!
+//! With `live_credentials` and `shadow_orders`, every order accepted by the
+//! live account is sent again, unchanged, to the paper account (and with
+//! `both`, paper orders to live as well). The two orders point at each
+//! other through extensions: the original carries `shadow_order_id` and
+//! `shadow_environment`, the copy carries `shadow_of`. Once both have
+//! filled, `get_shadow_comparison` lines up their fills to show how far the
+//! paper fills are from the real ones.
+//!
+//! A copy that fails does not affect the original; the reason is kept in
+//! the original's `shadow_error`.
+
+use crate::decimal::{self, Decimal};
+use crate::environment::Environment;
+use crate::orders;
+use models::order::{Order, OrderRequest, OrderSide, OrderStatus};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShadowMode {
+    #[default]
+    Off,
+    /// Live orders are copied to paper
+    LiveToPaper,
+    /// Live orders are copied to paper, and paper orders to live
+    Both,
+}
+
+impl ShadowMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(Self::Off),
+            "live_to_paper" => Some(Self::LiveToPaper),
+            "both" => Some(Self::Both),
+            _ => None,
+        }
+    }
+
+    /// Where an order placed in `placed_in` is copied to, if anywhere
+    pub fn target(self, placed_in: Environment) -> Option<Environment> {
+        match (self, placed_in) {
+            (Self::Off, _) => None,
+            (_, Environment::Live) => Some(Environment::Paper),
+            (Self::Both, Environment::Paper) => Some(Environment::Live),
+            (Self::LiveToPaper, Environment::Paper) => None,
+        }
+    }
+}
+
+/// Whether an order the original placement returned should be copied:
+/// accepted, not a preview, and not itself a copy
+pub fn should_mirror(request: &OrderRequest, order: &Order) -> bool {
+    order.status != OrderStatus::Rejected
+        && !orders::request_flag(request, "dry_run")
+        && !request
+            .extensions
+            .as_ref()
+            .is_some_and(|ext| ext.contains_key("shadow_of"))
+}
+
+/// The copy of `request` for `target`, linked back to `original`
+pub fn mirror_request(
+    request: &OrderRequest,
+    original: &Order,
+    target: Environment,
+) -> OrderRequest {
+    let mut mirror = request.clone();
+    let ext = mirror.extensions.get_or_insert_with(Default::default);
+    ext.insert("environment".to_string(), target.as_str().into());
+    ext.insert("shadow_of".to_string(), original.id.clone().into());
+    // The copy is identical by design
+    ext.insert("allow_duplicate".to_string(), true.into());
+    mirror
+}
+
+/// Fills of a live order and its paper shadow side by side.
+/// `slippage_bps` is how much worse the live average price is than the
+/// paper one for the order's side (negative when live did better).
+pub fn comparison(live: &Order, paper: &Order) -> serde_json::Value {
+    let live_price = live.average_filled_price.and_then(decimal::from_f64);
+    let paper_price = paper.average_filled_price.and_then(decimal::from_f64);
+    let slippage_bps = match (live_price, paper_price) {
+        (Some(live_price), Some(paper_price)) if !paper_price.is_zero() => {
+            let diff = match live.request.side {
+                OrderSide::Buy => live_price - paper_price,
+                OrderSide::Sell => paper_price - live_price,
+            };
+            Some((diff / paper_price * Decimal::from(10_000)).round_dp(2))
+        }
+        _ => None,
+    };
+    serde_json::json!({
+        "symbol": live.request.symbol_id,
+        "live": side(live, live_price),
+        "paper": side(paper, paper_price),
+        "price_difference": live_price
+            .zip(paper_price)
+            .map(|(l, p)| decimal::to_wire(l - p)),
+        "slippage_bps": slippage_bps.map(decimal::to_wire),
+        "filled_quantity_difference": live.filled_quantity - paper.filled_quantity,
+    })
+}
+
+fn side(order: &Order, price: Option<Decimal>) -> serde_json::Value {
+    serde_json::json!({
+        "order_id": order.id,
+        "status": order.status,
+        "filled_quantity": order.filled_quantity,
+        "average_filled_price": price.map(decimal::to_wire),
+        "updated_at": order.updated_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+
+    fn filled(id: &str, side: OrderSide, price: f64) -> Order {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "request": order_request("AAPL").side(side).qty(10.0).build(),
+            "status": OrderStatus::Filled,
+            "created_at": "2024-03-01T15:00:00Z",
+            "updated_at": "2024-03-01T15:00:01Z",
+            "average_filled_price": price,
+            "filled_quantity": 10.0,
+            "extensions": null,
+            "persona_id": "default",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn modes_pick_the_copy_target_and_slippage_follows_the_side() {
+        assert_eq!(
+            ShadowMode::LiveToPaper.target(Environment::Live),
+            Some(Environment::Paper)
+        );
+        assert_eq!(ShadowMode::LiveToPaper.target(Environment::Paper), None);
+        assert_eq!(
+            ShadowMode::Both.target(Environment::Paper),
+            Some(Environment::Live)
+        );
+        assert_eq!(ShadowMode::Off.target(Environment::Live), None);
+
+        let buy = comparison(
+            &filled("l", OrderSide::Buy, 100.05),
+            &filled("p", OrderSide::Buy, 100.0),
+        );
+        assert_eq!(buy["slippage_bps"], "5");
+        assert_eq!(buy["price_difference"], "0.05");
+        let sell = comparison(
+            &filled("l", OrderSide::Sell, 100.05),
+            &filled("p", OrderSide::Sell, 100.0),
+        );
+        assert_eq!(sell["slippage_bps"], "-5");
+    }
+}
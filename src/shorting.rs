@@ -0,0 +1,139 @@
+//! Short sale checks
+//!
+//! A sell beyond the long position opens a short. Alpaca only lets a
+//! symbol be shorted when its asset is `shortable`, and a name that is not
+//! `easy_to_borrow` (hard to borrow, HTB) needs a locate and may carry
+//! borrow fees or be rejected. Both are surfaced before the order goes out
+//! rather than as a bare rejection afterwards.
+
+use crate::alpaca::AlpacaAsset;
+use crate::decimal::{self, Decimal};
+use crate::pretrade::{self, CheckMode, CheckOutcome, Finding};
+use models::order::{OrderRequest, OrderSide};
+
+/// Borrow facts for a sell, given the held quantity
+#[derive(Clone, Debug)]
+pub struct ShortSale {
+    pub short_qty: Decimal,
+    pub shortable: bool,
+    pub easy_to_borrow: bool,
+}
+
+impl ShortSale {
+    /// `None` unless the order would open or add to a short
+    pub fn assess(order: &OrderRequest, asset: &AlpacaAsset, held: Decimal) -> Option<Self> {
+        if order.side != OrderSide::Sell {
+            return None;
+        }
+        let (_, short_qty) = pretrade::cost_quantities(order, held);
+        (!short_qty.is_zero()).then_some(Self {
+            short_qty,
+            shortable: asset.shortable,
+            easy_to_borrow: asset.easy_to_borrow,
+        })
+    }
+
+    pub fn htb(&self) -> bool {
+        self.shortable && !self.easy_to_borrow
+    }
+
+    fn details(&self, symbol: &str) -> serde_json::Value {
+        serde_json::json!({
+            "symbol": symbol,
+            "short_qty": decimal::to_wire(self.short_qty),
+            "shortable": self.shortable,
+            "easy_to_borrow": self.easy_to_borrow,
+        })
+    }
+
+    /// A symbol that cannot be shorted at all is blocked unless checks are
+    /// off; a hard-to-borrow one follows `mode`
+    pub fn check(&self, mode: CheckMode, symbol: &str) -> CheckOutcome {
+        if mode == CheckMode::Off {
+            return CheckOutcome::Pass;
+        }
+        if !self.shortable {
+            return CheckOutcome::Block(Finding::new(
+                "not_shortable",
+                format!("{} cannot be sold short on Alpaca", symbol),
+                self.details(symbol),
+            ));
+        }
+        if self.htb() {
+            return mode.apply(Finding::new(
+                "hard_to_borrow",
+                format!(
+                    "{} is hard to borrow; the short needs a locate and may be rejected",
+                    symbol
+                ),
+                self.details(symbol),
+            ));
+        }
+        CheckOutcome::Pass
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+
+    fn asset(shortable: bool, easy_to_borrow: bool) -> AlpacaAsset {
+        serde_json::from_value(serde_json::json!({
+            "symbol": "GME",
+            "status": "active",
+            "tradable": true,
+            "shortable": shortable,
+            "easy_to_borrow": easy_to_borrow,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn only_the_short_part_of_a_sell_counts() {
+        let held = Decimal::from(10);
+        assert!(ShortSale::assess(
+            &order_request("GME").side(OrderSide::Sell).qty(10.0).build(),
+            &asset(true, false),
+            held
+        )
+        .is_none());
+
+        let short = ShortSale::assess(
+            &order_request("GME").side(OrderSide::Sell).qty(15.0).build(),
+            &asset(true, false),
+            held,
+        )
+        .unwrap();
+        assert_eq!(short.short_qty, Decimal::from(5));
+        assert!(short.htb());
+        assert!(matches!(
+            short.check(CheckMode::Warn, "GME"),
+            CheckOutcome::Warn(_)
+        ));
+        assert!(matches!(
+            short.check(CheckMode::Enforce, "GME"),
+            CheckOutcome::Block(_)
+        ));
+
+        let blocked = ShortSale::assess(
+            &order_request("GME").side(OrderSide::Sell).qty(15.0).build(),
+            &asset(false, false),
+            held,
+        )
+        .unwrap();
+        assert!(
+            matches!(blocked.check(CheckMode::Warn, "GME"), CheckOutcome::Block(f) if f.code == "not_shortable")
+        );
+        let easy = ShortSale::assess(
+            &order_request("GME").side(OrderSide::Sell).qty(15.0).build(),
+            &asset(true, true),
+            held,
+        )
+        .unwrap();
+        assert!(matches!(
+            easy.check(CheckMode::Enforce, "GME"),
+            CheckOutcome::Pass
+        ));
+    }
+}
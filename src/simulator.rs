@@ -0,0 +1,1429 @@
+//! Local paper fill simulator
+//!
+//! With `simulation` configured, the [`Simulate`] middleware answers the
+//! trading API (`/v2/orders`, `/v2/account`, `/v2/positions`, `/v2/clock`,
+//! `/v2/assets/{symbol}`, and fill activities) in place of Alpaca, so every other part of the
+//! plugin runs unchanged. Orders are matched against the latest quote on
+//! each request once `latency_ms` has passed since submission, with
+//! `slippage_bps` applied against the trader, and only while the
+//! simulated regular session is open. Cash and positions are kept
+//! in memory.
+//!
+//! Quotes come from `set_simulated_quote` or, for symbols without one, from
+//! the market data API when credentials were given; data API requests are
+//! passed through untouched.
+//!
+//! `mock` is the same simulator with nothing left to the network: the book
+//! opens with a few canned positions and orders, every symbol has a fixed
+//! quote (see [`mock_quote`]), and snapshot requests are answered locally,
+//! so a host can be developed or demoed without Alpaca credentials.
+
+use crate::alpaca::DATA_API_URL;
+use crate::decimal::{self, Decimal};
+use crate::http::{HttpMethod, HttpRequest, HttpResponse, Middleware, Next};
+use crate::market_data::Snapshot;
+use crate::market_time;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc, Weekday};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SimConfig {
+    pub starting_cash: Decimal,
+    /// Adverse price adjustment applied to every fill
+    pub slippage_bps: Decimal,
+    /// Minimum time between submission and the first fill
+    pub latency_ms: u64,
+    /// Canned book and quotes, no market data API
+    pub mock: bool,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            starting_cash: Decimal::from(100_000),
+            slippage_bps: Decimal::ZERO,
+            latency_ms: 0,
+            mock: false,
+        }
+    }
+}
+
+impl SimConfig {
+    /// `true` for the defaults, an object to override them, or absent /
+    /// `false` for no simulation
+    pub fn from_config(value: Option<&serde_json::Value>) -> Result<Option<Self>, String> {
+        let object = match value {
+            None | Some(serde_json::Value::Null) | Some(serde_json::Value::Bool(false)) => {
+                return Ok(None)
+            }
+            Some(serde_json::Value::Bool(true)) => return Ok(Some(Self::default())),
+            Some(serde_json::Value::Object(object)) => object,
+            Some(_) => return Err("simulation must be true or an object".to_string()),
+        };
+
+        let mut config = Self::default();
+        let decimal_field = |key: &str| -> Result<Option<Decimal>, String> {
+            match object.get(key) {
+                None => Ok(None),
+                Some(v) => v
+                    .as_f64()
+                    .and_then(decimal::from_f64)
+                    .filter(|d| !d.is_sign_negative())
+                    .map(Some)
+                    .ok_or_else(|| format!("simulation.{} must be a non-negative number", key)),
+            }
+        };
+        if let Some(cash) = decimal_field("starting_cash")? {
+            config.starting_cash = cash;
+        }
+        if let Some(bps) = decimal_field("slippage_bps")? {
+            config.slippage_bps = bps;
+        }
+        if let Some(v) = object.get("latency_ms") {
+            config.latency_ms = v
+                .as_u64()
+                .ok_or("simulation.latency_ms must be a non-negative integer")?;
+        }
+        Ok(Some(config))
+    }
+
+    /// The `mock` setting: `true` for the defaults, an object with
+    /// `fill_delay_ms` and `starting_cash` to override them, or absent /
+    /// `false` for no mock
+    pub fn mock_from_config(value: Option<&serde_json::Value>) -> Result<Option<Self>, String> {
+        let object = match value {
+            None | Some(serde_json::Value::Null) | Some(serde_json::Value::Bool(false)) => {
+                return Ok(None)
+            }
+            Some(serde_json::Value::Bool(true)) => &serde_json::Map::new(),
+            Some(serde_json::Value::Object(object)) => object,
+            Some(_) => return Err("mock must be true or an object".to_string()),
+        };
+
+        let mut config = Self {
+            latency_ms: MOCK_FILL_DELAY_MS,
+            mock: true,
+            ..Self::default()
+        };
+        if let Some(v) = object.get("starting_cash") {
+            config.starting_cash = v
+                .as_f64()
+                .and_then(decimal::from_f64)
+                .filter(|d| !d.is_sign_negative())
+                .ok_or("mock.starting_cash must be a non-negative number")?;
+        }
+        if let Some(v) = object.get("fill_delay_ms") {
+            config.latency_ms = v
+                .as_u64()
+                .ok_or("mock.fill_delay_ms must be a non-negative integer")?;
+        }
+        Ok(Some(config))
+    }
+}
+
+/// Default time before a mock order fills
+const MOCK_FILL_DELAY_MS: u64 = 1000;
+
+/// Quotes every mock book starts with: symbol, bid, ask, last
+const MOCK_QUOTES: [(&str, &str, &str, &str); 5] = [
+    ("AAPL", "189.98", "190.02", "190.00"),
+    ("MSFT", "414.95", "415.05", "415.00"),
+    ("SPY", "511.99", "512.01", "512.00"),
+    ("TSLA", "174.95", "175.05", "175.00"),
+    ("NVDA", "874.90", "875.10", "875.00"),
+];
+
+/// Positions every mock book starts with: symbol, qty, average entry
+const MOCK_POSITIONS: [(&str, &str, &str); 3] = [
+    ("AAPL", "50", "175.20"),
+    ("MSFT", "10", "402.50"),
+    ("SPY", "25", "498.10"),
+];
+
+/// Fixed quote for `symbol` in mock mode: the canned one, or a price
+/// between 10 and 500 derived from the symbol's name so the same symbol
+/// always trades at the same price
+pub fn mock_quote(symbol: &str) -> SimQuote {
+    let canned = MOCK_QUOTES.iter().find(|(s, ..)| *s == symbol);
+    if let Some((_, bid, ask, last)) = canned {
+        let parse = |v: &str| decimal::parse(v).expect("valid canned price");
+        return SimQuote {
+            bid: parse(bid),
+            ask: parse(ask),
+            last: parse(last),
+        };
+    }
+    // FNV-1a, which unlike the std hasher is stable across builds
+    let hash = symbol.bytes().fold(0xcbf2_9ce4_8422_2325u64, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    let last = Decimal::new(1_000 + (hash % 49_000) as i64, 2);
+    let tick = Decimal::new(1, 2);
+    SimQuote {
+        bid: last - tick,
+        ask: last + tick,
+        last,
+    }
+}
+
+/// Quote pushed by the host with `set_simulated_quote`
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct SimQuote {
+    pub bid: Decimal,
+    pub ask: Decimal,
+    pub last: Decimal,
+}
+
+impl SimQuote {
+    fn from_snapshot(snapshot: &Snapshot) -> Option<Self> {
+        let last = snapshot
+            .latest_trade
+            .as_ref()
+            .map(|t| t.price)
+            .unwrap_or_default();
+        let (bid, ask) = snapshot
+            .latest_quote
+            .as_ref()
+            .map(|q| (q.bid_price, q.ask_price))
+            .unwrap_or_default();
+        (last > Decimal::ZERO || bid > Decimal::ZERO || ask > Decimal::ZERO).then_some(Self {
+            bid,
+            ask,
+            last,
+        })
+    }
+
+    /// Price a buy (or sell) would trade at before slippage
+    fn touch(&self, buy: bool) -> Option<Decimal> {
+        let side = if buy { self.ask } else { self.bid };
+        [side, self.last].into_iter().find(|p| *p > Decimal::ZERO)
+    }
+
+    fn mark(&self) -> Option<Decimal> {
+        if self.last > Decimal::ZERO {
+            Some(self.last)
+        } else if self.bid > Decimal::ZERO && self.ask > Decimal::ZERO {
+            Some((self.bid + self.ask) / Decimal::TWO)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct SimOrder {
+    id: String,
+    client_order_id: String,
+    symbol: String,
+    qty: Decimal,
+    side: String,
+    order_type: String,
+    time_in_force: String,
+    limit_price: Option<Decimal>,
+    stop_price: Option<Decimal>,
+    status: String,
+    filled_qty: Decimal,
+    filled_avg_price: Option<Decimal>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    filled_at: Option<DateTime<Utc>>,
+    canceled_at: Option<DateTime<Utc>>,
+    replaced_at: Option<DateTime<Utc>>,
+    replaced_by: Option<String>,
+    replaces: Option<String>,
+    stop_triggered: bool,
+}
+
+impl SimOrder {
+    fn is_open(&self) -> bool {
+        matches!(
+            self.status.as_str(),
+            "new" | "accepted" | "partially_filled"
+        )
+    }
+
+    fn is_buy(&self) -> bool {
+        self.side == "buy"
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let wire = |d: Option<Decimal>| d.map(decimal::to_wire);
+        let time = |t: Option<DateTime<Utc>>| t.map(|t| t.to_rfc3339());
+        serde_json::json!({
+            "id": self.id,
+            "client_order_id": self.client_order_id,
+            "status": self.status,
+            "symbol": self.symbol,
+            "asset_class": "us_equity",
+            "qty": decimal::to_wire(self.qty),
+            "side": self.side,
+            "type": self.order_type,
+            "time_in_force": self.time_in_force,
+            "filled_qty": decimal::to_wire(self.filled_qty),
+            "filled_avg_price": wire(self.filled_avg_price),
+            "limit_price": wire(self.limit_price),
+            "stop_price": wire(self.stop_price),
+            "created_at": self.created_at.to_rfc3339(),
+            "updated_at": self.updated_at.to_rfc3339(),
+            "submitted_at": self.created_at.to_rfc3339(),
+            "filled_at": time(self.filled_at),
+            "canceled_at": time(self.canceled_at),
+            "replaced_at": time(self.replaced_at),
+            "replaced_by": self.replaced_by,
+            "replaces": self.replaces,
+            "order_class": "",
+            "legs": null,
+        })
+    }
+
+    fn cancel(&mut self, now: DateTime<Utc>) {
+        self.status = "canceled".to_string();
+        self.canceled_at = Some(now);
+        self.updated_at = now;
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SimPosition {
+    /// Negative for shorts
+    pub qty: Decimal,
+    pub avg_entry_price: Decimal,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct SimFill {
+    id: String,
+    order_id: String,
+    symbol: String,
+    side: String,
+    qty: Decimal,
+    price: Decimal,
+    time: DateTime<Utc>,
+}
+
+struct SimBook {
+    cash: Decimal,
+    positions: BTreeMap<String, SimPosition>,
+    orders: Vec<SimOrder>,
+    fills: Vec<SimFill>,
+    quotes: HashMap<String, SimQuote>,
+    /// Latest known price per symbol, for valuation
+    marks: HashMap<String, Decimal>,
+    /// Equity at the start of the current trading day
+    last_equity: (NaiveDate, Decimal),
+    next_id: u64,
+}
+
+impl SimBook {
+    fn new(config: &SimConfig) -> Self {
+        let mut book = Self {
+            cash: config.starting_cash,
+            positions: BTreeMap::new(),
+            orders: Vec::new(),
+            fills: Vec::new(),
+            quotes: HashMap::new(),
+            marks: HashMap::new(),
+            last_equity: (market_time::eastern_today(), config.starting_cash),
+            next_id: 0,
+        };
+        if config.mock {
+            book.seed_mock(Utc::now());
+        }
+        book
+    }
+
+    /// Canned quotes, the positions bought yesterday (on top of the
+    /// starting cash), and a resting limit order
+    fn seed_mock(&mut self, now: DateTime<Utc>) {
+        for (symbol, ..) in MOCK_QUOTES {
+            let quote = mock_quote(symbol);
+            self.quotes.insert(symbol.to_string(), quote);
+            if let Some(mark) = quote.mark() {
+                self.marks.insert(symbol.to_string(), mark);
+            }
+        }
+        let yesterday = now - Duration::days(1);
+        let order = |id: String, symbol: &str, qty: Decimal, limit: Option<Decimal>| SimOrder {
+            id,
+            client_order_id: String::new(),
+            symbol: symbol.to_string(),
+            qty,
+            side: "buy".to_string(),
+            order_type: if limit.is_some() { "limit" } else { "market" }.to_string(),
+            time_in_force: if limit.is_some() { "gtc" } else { "day" }.to_string(),
+            limit_price: limit,
+            stop_price: None,
+            status: "new".to_string(),
+            filled_qty: Decimal::ZERO,
+            filled_avg_price: None,
+            created_at: yesterday,
+            updated_at: yesterday,
+            filled_at: None,
+            canceled_at: None,
+            replaced_at: None,
+            replaced_by: None,
+            replaces: None,
+            stop_triggered: false,
+        };
+        for (symbol, qty, price) in MOCK_POSITIONS {
+            let qty = decimal::parse(qty).expect("valid canned quantity");
+            let price = decimal::parse(price).expect("valid canned price");
+            let id = self.next_id("order");
+            self.orders.push(order(id, symbol, qty, None));
+            self.cash += qty * price;
+            self.apply_fill(self.orders.len() - 1, price, yesterday);
+        }
+        // Fills move the mark; put the canned quotes back
+        for (symbol, ..) in MOCK_QUOTES {
+            if let Some(mark) = mock_quote(symbol).mark() {
+                self.marks.insert(symbol.to_string(), mark);
+            }
+        }
+        let id = self.next_id("order");
+        self.orders
+            .push(order(id, "TSLA", Decimal::TEN, Some(Decimal::from(150))));
+        self.last_equity = (market_time::eastern_today(), self.equity());
+    }
+
+    fn next_id(&mut self, kind: &str) -> String {
+        self.next_id += 1;
+        format!("sim-{}-{:010}", kind, self.next_id)
+    }
+
+    fn mark(&self, symbol: &str, position: &SimPosition) -> Decimal {
+        self.marks
+            .get(symbol)
+            .copied()
+            .unwrap_or(position.avg_entry_price)
+    }
+
+    fn equity(&self) -> Decimal {
+        self.cash
+            + self
+                .positions
+                .iter()
+                .map(|(symbol, p)| p.qty * self.mark(symbol, p))
+                .sum::<Decimal>()
+    }
+
+    /// Cash not committed to open buy orders
+    fn free_cash(&self, skip_order: Option<&str>) -> Decimal {
+        let committed: Decimal = self
+            .orders
+            .iter()
+            .filter(|o| o.is_open() && o.is_buy() && Some(o.id.as_str()) != skip_order)
+            .map(|o| {
+                let price = o
+                    .limit_price
+                    .or_else(|| self.marks.get(&o.symbol).copied())
+                    .unwrap_or_default();
+                (o.qty - o.filled_qty) * price
+            })
+            .sum();
+        self.cash - committed
+    }
+
+    fn apply_fill(&mut self, index: usize, price: Decimal, now: DateTime<Utc>) {
+        let id = self.next_id("fill");
+        let order = &mut self.orders[index];
+        let qty = order.qty - order.filled_qty;
+        order.filled_qty = order.qty;
+        order.filled_avg_price = Some(price);
+        order.status = "filled".to_string();
+        order.filled_at = Some(now);
+        order.updated_at = now;
+
+        let signed = if order.is_buy() { qty } else { -qty };
+        self.cash -= signed * price;
+        let position = self.positions.entry(order.symbol.clone()).or_default();
+        let new_qty = position.qty + signed;
+        if new_qty.is_zero() {
+            self.positions.remove(&order.symbol);
+        } else {
+            // Average in when adding to (or flipping) a position; a
+            // reduction keeps the entry price
+            if position.qty.is_zero()
+                || position.qty.is_sign_negative() != new_qty.is_sign_negative()
+            {
+                position.avg_entry_price = price;
+            } else if position.qty.is_sign_negative() == signed.is_sign_negative() {
+                position.avg_entry_price =
+                    ((position.qty * position.avg_entry_price + signed * price) / new_qty)
+                        .round_dp(6);
+            }
+            position.qty = new_qty;
+        }
+        self.marks.insert(order.symbol.clone(), price);
+        self.fills.push(SimFill {
+            id,
+            order_id: order.id.clone(),
+            symbol: order.symbol.clone(),
+            side: order.side.clone(),
+            qty,
+            price,
+            time: now,
+        });
+    }
+}
+
+pub struct Simulator {
+    config: SimConfig,
+    /// Fall back to the market data API for symbols without a pushed
+    /// quote (needs credentials)
+    use_market_data: bool,
+    /// Market data API base URL, for the fallback and for telling market
+    /// data requests apart from trading ones
+    data_url: String,
+    book: Mutex<SimBook>,
+}
+
+impl std::fmt::Debug for Simulator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Simulator")
+            .field("config", &self.config)
+            .field("use_market_data", &self.use_market_data)
+            .finish()
+    }
+}
+
+impl Simulator {
+    pub fn new(config: SimConfig, use_market_data: bool) -> Self {
+        let book = Mutex::new(SimBook::new(&config));
+        Self {
+            config,
+            use_market_data,
+            data_url: DATA_API_URL.to_string(),
+            book,
+        }
+    }
+
+    /// Use a market data base URL other than Alpaca's
+    pub fn with_data_url(mut self, url: &str) -> Self {
+        self.data_url = url.to_string();
+        self
+    }
+
+    fn book(&self) -> std::sync::MutexGuard<'_, SimBook> {
+        self.book.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set_quote(&self, symbol: &str, quote: SimQuote) {
+        let mut book = self.book();
+        if let Some(mark) = quote.mark() {
+            book.marks.insert(symbol.to_string(), mark);
+        }
+        book.quotes.insert(symbol.to_string(), quote);
+    }
+
+    /// Cash, equity, and positions, for the host
+    pub fn summary(&self) -> serde_json::Value {
+        let book = self.book();
+        serde_json::json!({
+            "config": self.config,
+            "cash": book.cash,
+            "equity": book.equity(),
+            "positions": book.positions,
+            "open_orders": book.orders.iter().filter(|o| o.is_open()).count(),
+            "fills": book.fills.len(),
+        })
+    }
+
+    /// Quotes for `symbols`: pushed ones first, then the data API
+    fn quotes(
+        &self,
+        book: &SimBook,
+        symbols: &[String],
+        next: Next<'_>,
+    ) -> HashMap<String, SimQuote> {
+        let mut quotes: HashMap<String, SimQuote> = HashMap::new();
+        let mut missing = Vec::new();
+        for symbol in symbols {
+            match book.quotes.get(symbol) {
+                Some(quote) => {
+                    quotes.insert(symbol.clone(), *quote);
+                }
+                None => missing.push(symbol.clone()),
+            }
+        }
+        if self.config.mock {
+            for symbol in missing {
+                quotes.insert(symbol.clone(), mock_quote(&symbol));
+            }
+            return quotes;
+        }
+        if missing.is_empty() || !self.use_market_data {
+            return quotes;
+        }
+
+        let response = next.run(HttpRequest {
+            method: HttpMethod::Get,
+            url: crate::http::QueryParams::new()
+                .push_list("symbols", &missing)
+                .apply(&format!("{}/v2/stocks/snapshots", self.data_url)),
+            headers: HashMap::new(),
+            body: None,
+            timeout_ms: 30000,
+        });
+        if response.is_success() {
+            if let Ok(snapshots) = response.json::<HashMap<String, Snapshot>>() {
+                for (symbol, snapshot) in snapshots {
+                    if let Some(quote) = SimQuote::from_snapshot(&snapshot) {
+                        quotes.insert(symbol, quote);
+                    }
+                }
+            }
+        }
+        quotes
+    }
+
+    /// Fill or expire every open order that has a price to trade at
+    fn match_orders(&self, book: &mut SimBook, now: DateTime<Utc>, next: Next<'_>) {
+        let ready = now - Duration::milliseconds(self.config.latency_ms as i64);
+        let mut symbols: Vec<String> = book
+            .orders
+            .iter()
+            .filter(|o| o.is_open() && o.created_at <= ready)
+            .map(|o| o.symbol.clone())
+            .chain(book.positions.keys().cloned())
+            .collect();
+        symbols.sort();
+        symbols.dedup();
+        if symbols.is_empty() {
+            return;
+        }
+        let quotes = self.quotes(book, &symbols, next);
+        for (symbol, quote) in &quotes {
+            if let Some(mark) = quote.mark() {
+                book.marks.insert(symbol.clone(), mark);
+            }
+        }
+        // Orders rest until the simulated session opens
+        if !market_open(now) {
+            return;
+        }
+
+        let slippage = self.config.slippage_bps / Decimal::from(10_000);
+        for index in 0..book.orders.len() {
+            let order = &book.orders[index];
+            if !order.is_open() || order.created_at > ready {
+                continue;
+            }
+            let Some(quote) = quotes.get(&order.symbol) else {
+                continue;
+            };
+            let buy = order.is_buy();
+            let Some(touch) = quote.touch(buy) else {
+                continue;
+            };
+
+            if let Some(stop) = order.stop_price.filter(|_| !order.stop_triggered) {
+                let trigger = if quote.last > Decimal::ZERO {
+                    quote.last
+                } else {
+                    touch
+                };
+                let hit = if buy {
+                    trigger >= stop
+                } else {
+                    trigger <= stop
+                };
+                if !hit {
+                    continue;
+                }
+                book.orders[index].stop_triggered = true;
+            }
+
+            let order = &book.orders[index];
+            let price = if buy {
+                touch * (Decimal::ONE + slippage)
+            } else {
+                touch * (Decimal::ONE - slippage)
+            };
+            let price = match order.limit_price {
+                Some(limit) if buy && touch > limit => None,
+                Some(limit) if !buy && touch < limit => None,
+                Some(limit) if buy => Some(price.min(limit)),
+                Some(limit) => Some(price.max(limit)),
+                None => Some(price),
+            }
+            .map(|p| p.round_dp(4));
+
+            match price {
+                Some(price) => book.apply_fill(index, price, now),
+                None if matches!(order.time_in_force.as_str(), "ioc" | "fok") => {
+                    book.orders[index].cancel(now);
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn submit(&self, book: &mut SimBook, body: &str, now: DateTime<Utc>) -> HttpResponse {
+        let request: serde_json::Value = match serde_json::from_str(body) {
+            Ok(v) => v,
+            Err(e) => return error(400, &format!("invalid order body: {}", e)),
+        };
+        let text = |key: &str| {
+            request
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+        let price = |key: &str| text(key).as_deref().and_then(decimal::parse);
+
+        let Some(qty) = price("qty").filter(|q| *q > Decimal::ZERO) else {
+            return error(422, "qty must be positive");
+        };
+        let Some(symbol) = text("symbol") else {
+            return error(422, "symbol is required");
+        };
+        if request.get("order_class").is_some_and(|c| c != "simple") {
+            return error(
+                422,
+                "advanced order classes are not supported in simulation",
+            );
+        }
+        if text("type").as_deref() == Some("trailing_stop") {
+            return error(422, "trailing stops are not supported in simulation");
+        }
+        let side = text("side").unwrap_or_default();
+        let limit_price = price("limit_price");
+        if side == "buy" {
+            let estimate = limit_price
+                .or_else(|| book.marks.get(&symbol).copied())
+                .unwrap_or_default();
+            if qty * estimate > book.free_cash(None) {
+                return error(403, "insufficient buying power");
+            }
+        }
+
+        let order = SimOrder {
+            id: book.next_id("order"),
+            client_order_id: text("client_order_id").unwrap_or_default(),
+            symbol,
+            qty,
+            side,
+            order_type: text("type").unwrap_or_else(|| "market".to_string()),
+            time_in_force: text("time_in_force").unwrap_or_else(|| "day".to_string()),
+            limit_price,
+            stop_price: price("stop_price"),
+            status: "new".to_string(),
+            filled_qty: Decimal::ZERO,
+            filled_avg_price: None,
+            created_at: now,
+            updated_at: now,
+            filled_at: None,
+            canceled_at: None,
+            replaced_at: None,
+            replaced_by: None,
+            replaces: None,
+            stop_triggered: false,
+        };
+        let json = order.to_json();
+        book.orders.push(order);
+        ok(200, json)
+    }
+
+    fn replace(
+        &self,
+        book: &mut SimBook,
+        id: &str,
+        body: &str,
+        now: DateTime<Utc>,
+    ) -> HttpResponse {
+        let Some(index) = book.orders.iter().position(|o| o.id == id) else {
+            return error(404, "order not found");
+        };
+        if !book.orders[index].is_open() {
+            return error(422, "order is not open");
+        }
+        let patch: serde_json::Value = serde_json::from_str(body).unwrap_or_default();
+        let price = |key: &str| {
+            patch
+                .get(key)
+                .and_then(|v| v.as_str())
+                .and_then(decimal::parse)
+        };
+
+        let new_id = book.next_id("order");
+        let old = &mut book.orders[index];
+        let mut replacement = old.clone();
+        old.status = "replaced".to_string();
+        old.replaced_at = Some(now);
+        old.replaced_by = Some(new_id.clone());
+        old.updated_at = now;
+
+        replacement.id = new_id;
+        replacement.replaces = Some(id.to_string());
+        replacement.status = "new".to_string();
+        replacement.created_at = now;
+        replacement.updated_at = now;
+        if let Some(qty) = price("qty") {
+            replacement.qty = qty;
+        }
+        if let Some(limit) = price("limit_price") {
+            replacement.limit_price = Some(limit);
+        }
+        if let Some(stop) = price("stop_price") {
+            replacement.stop_price = Some(stop);
+        }
+        let json = replacement.to_json();
+        book.orders.push(replacement);
+        ok(200, json)
+    }
+
+    fn account(&self, book: &mut SimBook) -> HttpResponse {
+        let equity = book.equity();
+        let today = market_time::eastern_today();
+        if book.last_equity.0 != today {
+            book.last_equity = (today, equity);
+        }
+        let free_cash = book.free_cash(None).max(Decimal::ZERO);
+        ok(
+            200,
+            serde_json::json!({
+                "id": "sim-account",
+                "account_number": "SIMULATED",
+                "status": "ACTIVE",
+                "currency": "USD",
+                "cash": decimal::to_wire(book.cash.round_dp(2)),
+                "portfolio_value": decimal::to_wire(equity.round_dp(2)),
+                "equity": decimal::to_wire(equity.round_dp(2)),
+                "last_equity": decimal::to_wire(book.last_equity.1.round_dp(2)),
+                "buying_power": decimal::to_wire(free_cash.round_dp(2)),
+                "regt_buying_power": decimal::to_wire(free_cash.round_dp(2)),
+                "daytrading_buying_power": "0",
+                "non_marginable_buying_power": decimal::to_wire(free_cash.round_dp(2)),
+                "multiplier": "1",
+                "initial_margin": "0",
+                "maintenance_margin": "0",
+                "daytrade_count": 0,
+                "pattern_day_trader": false,
+                "trading_blocked": false,
+                "account_blocked": false,
+                "trade_suspended_by_user": false,
+                "shorting_enabled": true,
+            }),
+        )
+    }
+
+    fn position_json(book: &SimBook, symbol: &str, p: &SimPosition) -> serde_json::Value {
+        let mark = book.mark(symbol, p);
+        let cost = p.qty * p.avg_entry_price;
+        let unrealized = p.qty * mark - cost;
+        let plpc = if cost.is_zero() {
+            Decimal::ZERO
+        } else {
+            (unrealized / cost.abs()).round_dp(6)
+        };
+        serde_json::json!({
+            "symbol": symbol,
+            "asset_class": "us_equity",
+            "qty": decimal::to_wire(p.qty),
+            "side": if p.qty.is_sign_negative() { "short" } else { "long" },
+            "avg_entry_price": decimal::to_wire(p.avg_entry_price),
+            "current_price": decimal::to_wire(mark),
+            "market_value": decimal::to_wire((p.qty * mark).round_dp(2)),
+            "cost_basis": decimal::to_wire(cost.round_dp(2)),
+            "unrealized_pl": decimal::to_wire(unrealized.round_dp(2)),
+            "unrealized_plpc": decimal::to_wire(plpc),
+        })
+    }
+
+    fn activities(book: &SimBook, query: &HashMap<String, String>) -> HttpResponse {
+        let wants_fills = query
+            .get("activity_types")
+            .is_none_or(|types| types.split(',').any(|t| t == "FILL"));
+        if !wants_fills {
+            return ok(200, serde_json::json!([]));
+        }
+        let after = query
+            .get("after")
+            .and_then(|a| DateTime::parse_from_rfc3339(a).ok())
+            .map(|a| a.with_timezone(&Utc));
+        let until = query
+            .get("until")
+            .and_then(|u| DateTime::parse_from_rfc3339(u).ok())
+            .map(|u| u.with_timezone(&Utc));
+        let page_token = query.get("page_token");
+        let page_size = query
+            .get("page_size")
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(100);
+
+        let page: Vec<serde_json::Value> = book
+            .fills
+            .iter()
+            .filter(|f| after.is_none_or(|a| f.time > a))
+            .filter(|f| until.is_none_or(|u| f.time <= u))
+            .filter(|f| page_token.is_none_or(|t| f.id.as_str() > t.as_str()))
+            .take(page_size)
+            .map(|f| {
+                serde_json::json!({
+                    "id": f.id,
+                    "activity_type": "FILL",
+                    "type": "fill",
+                    "order_id": f.order_id,
+                    "symbol": f.symbol,
+                    "side": f.side,
+                    "qty": decimal::to_wire(f.qty),
+                    "cum_qty": decimal::to_wire(f.qty),
+                    "leaves_qty": "0",
+                    "price": decimal::to_wire(f.price),
+                    "transaction_time": f.time.to_rfc3339(),
+                })
+            })
+            .collect();
+        ok(200, serde_json::Value::Array(page))
+    }
+
+    /// Market data in mock mode: snapshots from the book's quotes, nothing
+    /// else
+    fn mock_data(&self, request: &HttpRequest, next: Next<'_>) -> HttpResponse {
+        let path = request.path().trim_end_matches('/');
+        if request.method != HttpMethod::Get || path != "/v2/stocks/snapshots" {
+            return error(404, &format!("{} is not supported in mock mode", path));
+        }
+        let symbols: Vec<String> = parse_query(&request.url)
+            .get("symbols")
+            .map(|s| s.split(',').map(str::to_string).collect())
+            .unwrap_or_default();
+        let now = Utc::now().to_rfc3339();
+        let book = self.book();
+        let snapshots: serde_json::Map<String, serde_json::Value> = self
+            .quotes(&book, &symbols, next)
+            .into_iter()
+            .map(|(symbol, quote)| {
+                let snapshot = serde_json::json!({
+                    "latestTrade": { "t": now, "p": quote.last, "s": 100 },
+                    "latestQuote": {
+                        "t": now,
+                        "bp": quote.bid,
+                        "bs": 1,
+                        "ap": quote.ask,
+                        "as": 1,
+                    },
+                });
+                (symbol, snapshot)
+            })
+            .collect();
+        ok(200, serde_json::Value::Object(snapshots))
+    }
+
+    fn route(&self, request: &HttpRequest, now: DateTime<Utc>, next: Next<'_>) -> HttpResponse {
+        let mut book = self.book();
+        self.match_orders(&mut book, now, next);
+
+        let path = request.path().trim_end_matches('/');
+        let query = parse_query(&request.url);
+        let body = request.body.as_deref().unwrap_or_default();
+        let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+
+        match (request.method, segments.as_slice()) {
+            (HttpMethod::Get, ["v2", "account"]) => self.account(&mut book),
+            (HttpMethod::Get, ["v2", "clock"]) => ok(200, clock(now)),
+            (HttpMethod::Get, ["v2", "positions"]) => ok(
+                200,
+                serde_json::Value::Array(
+                    book.positions
+                        .iter()
+                        .map(|(symbol, p)| Self::position_json(&book, symbol, p))
+                        .collect(),
+                ),
+            ),
+            (HttpMethod::Get, ["v2", "positions", symbol]) => {
+                let symbol = percent_decode(symbol);
+                match book.positions.get(&symbol) {
+                    Some(p) => ok(200, Self::position_json(&book, &symbol, p)),
+                    None => error(404, "position does not exist"),
+                }
+            }
+            (HttpMethod::Get, ["v2", "account", "activities"]) => Self::activities(&book, &query),
+            // Every symbol is treated as an active, fully featured asset
+            (HttpMethod::Get, ["v2", "assets", symbol]) => ok(
+                200,
+                serde_json::json!({
+                    "symbol": percent_decode(symbol).to_ascii_uppercase(),
+                    "class": "us_equity",
+                    "status": "active",
+                    "tradable": true,
+                    "marginable": true,
+                    "shortable": true,
+                    "easy_to_borrow": true,
+                    "fractionable": true,
+                }),
+            ),
+            (HttpMethod::Post, ["v2", "orders"]) => self.submit(&mut book, body, now),
+            (HttpMethod::Get, ["v2", "orders"]) => {
+                let status = query.get("status").map(String::as_str).unwrap_or("open");
+                let orders: Vec<serde_json::Value> = book
+                    .orders
+                    .iter()
+                    .rev()
+                    .filter(|o| match status {
+                        "open" => o.is_open(),
+                        "closed" => !o.is_open(),
+                        _ => true,
+                    })
+                    .map(SimOrder::to_json)
+                    .collect();
+                ok(200, serde_json::Value::Array(orders))
+            }
+            (HttpMethod::Get, ["v2", "orders", id]) => {
+                let id = percent_decode(id);
+                match book.orders.iter().find(|o| o.id == id) {
+                    Some(order) => ok(200, order.to_json()),
+                    None => error(404, "order not found"),
+                }
+            }
+            (HttpMethod::Patch, ["v2", "orders", id]) => {
+                self.replace(&mut book, &percent_decode(id), body, now)
+            }
+            (HttpMethod::Delete, ["v2", "orders"]) => {
+                let results: Vec<serde_json::Value> = book
+                    .orders
+                    .iter_mut()
+                    .filter(|o| o.is_open())
+                    .map(|o| {
+                        o.cancel(now);
+                        serde_json::json!({ "id": o.id, "status": 200 })
+                    })
+                    .collect();
+                ok(207, serde_json::Value::Array(results))
+            }
+            (HttpMethod::Delete, ["v2", "orders", id]) => {
+                let id = percent_decode(id);
+                match book.orders.iter_mut().find(|o| o.id == id) {
+                    Some(order) if order.is_open() => {
+                        order.cancel(now);
+                        ok(204, serde_json::Value::Null)
+                    }
+                    Some(_) => error(422, "order is not cancelable"),
+                    None => error(404, "order not found"),
+                }
+            }
+            _ => error(
+                404,
+                &format!(
+                    "{} {} is not supported in simulation",
+                    request.method.as_str(),
+                    path
+                ),
+            ),
+        }
+    }
+}
+
+/// Answers trading API requests from the [`Simulator`]; market data
+/// requests continue down the pipeline
+pub struct Simulate(pub Arc<Simulator>);
+
+impl Middleware for Simulate {
+    fn handle(&self, request: HttpRequest, next: Next<'_>) -> HttpResponse {
+        if request.url.starts_with(&self.0.data_url) {
+            if self.0.config.mock {
+                return self.0.mock_data(&request, next);
+            }
+            return next.run(request);
+        }
+        self.0.route(&request, Utc::now(), next)
+    }
+}
+
+fn ok(status: u16, body: serde_json::Value) -> HttpResponse {
+    HttpResponse {
+        status,
+        headers: HashMap::new(),
+        body: if body.is_null() {
+            String::new()
+        } else {
+            body.to_string()
+        },
+        error: None,
+    }
+}
+
+fn error(status: u16, message: &str) -> HttpResponse {
+    HttpResponse {
+        status,
+        headers: HashMap::new(),
+        body: serde_json::json!({ "code": status as u32 * 100_000, "message": message })
+            .to_string(),
+        error: None,
+    }
+}
+
+/// The next regular session open and close after `now` (09:30-16:00 ET
+/// on weekdays); holidays are not modelled
+fn session_bounds(now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let at = |date: NaiveDate, h: u32, m: u32| {
+        market_time::eastern_at(date, NaiveTime::from_hms_opt(h, m, 0).expect("valid time"))
+    };
+    let open = |date: NaiveDate| at(date, 9, 30);
+    let close = |date: NaiveDate| at(date, 16, 0);
+
+    // First weekday session boundary after now
+    let next = |boundary: &dyn Fn(NaiveDate) -> DateTime<Utc>| {
+        let mut date = market_time::eastern_date(now);
+        loop {
+            let weekday = !matches!(date.weekday(), Weekday::Sat | Weekday::Sun);
+            if weekday && boundary(date) > now {
+                return boundary(date);
+            }
+            date = date.succ_opt().expect("valid date");
+        }
+    };
+    (next(&open), next(&close))
+}
+
+fn market_open(now: DateTime<Utc>) -> bool {
+    let (next_open, next_close) = session_bounds(now);
+    next_close < next_open
+}
+
+fn clock(now: DateTime<Utc>) -> serde_json::Value {
+    let (next_open, next_close) = session_bounds(now);
+    serde_json::json!({
+        "timestamp": now.to_rfc3339(),
+        "is_open": next_close < next_open,
+        "next_open": next_open.to_rfc3339(),
+        "next_close": next_close.to_rfc3339(),
+    })
+}
+
+fn parse_query(url: &str) -> HashMap<String, String> {
+    url.split_once('?')
+        .map(|(_, query)| {
+            query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(k, v)| (percent_decode(k), percent_decode(v)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%' && i + 2 < bytes.len())
+            .then(|| std::str::from_utf8(&bytes[i + 1..i + 3]).ok())
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alpaca::{AlpacaClient, ClientOptions};
+    use crate::http::Pipeline;
+    use crate::mock::MockTransport;
+
+    /// Tuesday 19:00 ET, and the open of the next session
+    const CLOSED: &str = "2024-03-06T00:00:00Z";
+    const OPEN: &str = "2024-03-06T15:00:00Z";
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    /// Routes every request to the simulator at a fixed time
+    struct At(Arc<Simulator>, DateTime<Utc>);
+
+    impl Middleware for At {
+        fn handle(&self, request: HttpRequest, next: Next<'_>) -> HttpResponse {
+            self.0.route(&request, self.1, next)
+        }
+    }
+
+    fn simulator(starting_cash: i64, slippage_bps: i64) -> Arc<Simulator> {
+        let simulator = Simulator::new(
+            SimConfig {
+                starting_cash: Decimal::from(starting_cash),
+                slippage_bps: Decimal::from(slippage_bps),
+                ..SimConfig::default()
+            },
+            false,
+        );
+        simulator.set_quote("AAPL", quote("99.9", "100.1", "100"));
+        Arc::new(simulator)
+    }
+
+    fn quote(bid: &str, ask: &str, last: &str) -> SimQuote {
+        SimQuote {
+            bid: decimal::parse(bid).unwrap(),
+            ask: decimal::parse(ask).unwrap(),
+            last: decimal::parse(last).unwrap(),
+        }
+    }
+
+    fn send(
+        simulator: &Arc<Simulator>,
+        time: &str,
+        method: HttpMethod,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> HttpResponse {
+        Pipeline::with_transport(MockTransport::new())
+            .with(At(simulator.clone(), at(time)))
+            .send(HttpRequest {
+                method,
+                url: format!("https://paper-api.alpaca.markets{}", path),
+                headers: HashMap::new(),
+                body: body.map(|b| b.to_string()),
+                timeout_ms: 30000,
+            })
+    }
+
+    fn submit(
+        simulator: &Arc<Simulator>,
+        time: &str,
+        order: serde_json::Value,
+    ) -> serde_json::Value {
+        let response = send(simulator, time, HttpMethod::Post, "/v2/orders", Some(order));
+        assert_eq!(response.status, 200, "{}", response.body);
+        response.json().unwrap()
+    }
+
+    fn order(simulator: &Arc<Simulator>, time: &str, id: &str) -> serde_json::Value {
+        send(
+            simulator,
+            time,
+            HttpMethod::Get,
+            &format!("/v2/orders/{}", id),
+            None,
+        )
+        .json()
+        .unwrap()
+    }
+
+    #[test]
+    fn market_orders_wait_for_the_session_and_fill_at_the_touch() {
+        let simulator = simulator(100_000, 10);
+        let buy = submit(
+            &simulator,
+            CLOSED,
+            serde_json::json!({ "symbol": "AAPL", "qty": "10", "side": "buy", "type": "market" }),
+        );
+        let id = buy["id"].as_str().unwrap();
+        assert_eq!(order(&simulator, CLOSED, id)["status"], "new");
+        assert_eq!(simulator.summary()["fills"], 0);
+
+        // Ask plus 10 bps of slippage once the market opens
+        let filled = order(&simulator, OPEN, id);
+        assert_eq!(filled["status"], "filled");
+        assert_eq!(filled["filled_avg_price"], "100.2001");
+        assert_eq!(simulator.book().cash, decimal::parse("98997.999").unwrap());
+    }
+
+    #[test]
+    fn limit_orders_never_fill_worse_than_the_limit_and_ioc_cancels() {
+        let simulator = simulator(100_000, 0);
+        let limit = |tif: &str| {
+            serde_json::json!({
+                "symbol": "AAPL", "qty": "5", "side": "buy", "type": "limit",
+                "limit_price": "99", "time_in_force": tif,
+            })
+        };
+        let resting = submit(&simulator, OPEN, limit("gtc"));
+        let ioc = submit(&simulator, OPEN, limit("ioc"));
+        let resting = resting["id"].as_str().unwrap();
+        assert_eq!(order(&simulator, OPEN, resting)["status"], "new");
+        assert_eq!(
+            order(&simulator, OPEN, ioc["id"].as_str().unwrap())["status"],
+            "canceled"
+        );
+
+        simulator.set_quote("AAPL", quote("98.4", "98.5", "98.45"));
+        let filled = order(&simulator, OPEN, resting);
+        assert_eq!(filled["status"], "filled");
+        assert_eq!(filled["filled_avg_price"], "98.5");
+    }
+
+    #[test]
+    fn fills_average_in_keep_the_entry_on_reductions_and_flip_through_zero() {
+        let simulator = simulator(100_000, 0);
+        let mut book = simulator.book();
+        let now = at(OPEN);
+        let fill = |book: &mut SimBook, side: &str, qty: &str, price: &str| {
+            let body = serde_json::json!({ "symbol": "AAPL", "qty": qty, "side": side, "type": "limit", "limit_price": price });
+            simulator.submit(book, &body.to_string(), now);
+            let index = book.orders.len() - 1;
+            book.apply_fill(index, decimal::parse(price).unwrap(), now);
+            book.positions
+                .get("AAPL")
+                .map(|p| (decimal::to_wire(p.qty), decimal::to_wire(p.avg_entry_price)))
+        };
+        let position = |qty: &str, avg: &str| Some((qty.to_string(), avg.to_string()));
+
+        assert_eq!(fill(&mut book, "buy", "10", "100"), position("10", "100"));
+        assert_eq!(fill(&mut book, "buy", "10", "110"), position("20", "105"));
+        assert_eq!(fill(&mut book, "sell", "5", "120"), position("15", "105"));
+        // Selling past zero opens a short at the fill price
+        assert_eq!(fill(&mut book, "sell", "25", "90"), position("-10", "90"));
+        assert_eq!(fill(&mut book, "sell", "10", "80"), position("-20", "85"));
+        assert_eq!(fill(&mut book, "buy", "5", "70"), position("-15", "85"));
+        assert_eq!(fill(&mut book, "buy", "15", "75"), None);
+        // -1000 - 1100 + 600 + 2250 + 800 - 350 - 1125
+        assert_eq!(book.cash, Decimal::from(100_075));
+        assert_eq!(book.fills.len(), 7);
+    }
+
+    #[test]
+    fn buys_beyond_the_uncommitted_cash_are_refused() {
+        let simulator = simulator(1000, 0);
+        let buy = |qty: &str| {
+            serde_json::json!({
+                "symbol": "AAPL", "qty": qty, "side": "buy", "type": "limit", "limit_price": "50",
+            })
+        };
+        submit(&simulator, CLOSED, buy("15"));
+        // 750 is committed to the resting buy
+        let refused = send(
+            &simulator,
+            CLOSED,
+            HttpMethod::Post,
+            "/v2/orders",
+            Some(buy("6")),
+        );
+        assert_eq!(refused.status, 403);
+        submit(&simulator, CLOSED, buy("5"));
+        // Sells never need cash
+        submit(
+            &simulator,
+            CLOSED,
+            serde_json::json!({ "symbol": "AAPL", "qty": "100", "side": "sell", "type": "market" }),
+        );
+    }
+
+    #[test]
+    fn a_replacement_links_both_orders_and_only_open_orders_are_replaced() {
+        let simulator = simulator(100_000, 0);
+        let original = submit(
+            &simulator,
+            CLOSED,
+            serde_json::json!({
+                "symbol": "AAPL", "qty": "10", "side": "buy", "type": "limit", "limit_price": "95",
+            }),
+        );
+        let id = original["id"].as_str().unwrap();
+        let path = format!("/v2/orders/{}", id);
+        let replaced = send(
+            &simulator,
+            CLOSED,
+            HttpMethod::Patch,
+            &path,
+            Some(serde_json::json!({ "qty": "4", "limit_price": "96" })),
+        );
+        let replacement: serde_json::Value = replaced.json().unwrap();
+        assert_eq!(replacement["replaces"], id);
+        assert_eq!(replacement["qty"], "4");
+        assert_eq!(replacement["limit_price"], "96");
+
+        let old = order(&simulator, CLOSED, id);
+        assert_eq!(old["status"], "replaced");
+        assert_eq!(old["replaced_by"], replacement["id"]);
+        let again = send(
+            &simulator,
+            CLOSED,
+            HttpMethod::Patch,
+            &path,
+            Some(serde_json::json!({ "qty": "2" })),
+        );
+        assert_eq!(again.status, 422);
+    }
+
+    #[test]
+    fn fill_activities_page_by_token_and_filter_by_time_and_type() {
+        let simulator = simulator(100_000, 0);
+        {
+            let mut book = simulator.book();
+            for minute in 0..3 {
+                let now = at(OPEN) + Duration::minutes(minute);
+                let body = serde_json::json!({ "symbol": "AAPL", "qty": "1", "side": "buy", "type": "market" });
+                simulator.submit(&mut book, &body.to_string(), now);
+                let index = book.orders.len() - 1;
+                book.apply_fill(index, Decimal::from(100), now);
+            }
+        }
+        let activities = |query: &str| -> Vec<serde_json::Value> {
+            send(
+                &simulator,
+                CLOSED,
+                HttpMethod::Get,
+                &format!("/v2/account/activities?{}", query),
+                None,
+            )
+            .json()
+            .unwrap()
+        };
+
+        let first = activities("activity_types=FILL&page_size=2");
+        assert_eq!(first.len(), 2);
+        let token = first[1]["id"].as_str().unwrap();
+        let rest = activities(&format!(
+            "activity_types=FILL&page_size=2&page_token={}",
+            token
+        ));
+        assert_eq!(rest.len(), 1);
+        assert_ne!(rest[0]["id"], first[1]["id"]);
+
+        let after = (at(OPEN) + Duration::seconds(30)).to_rfc3339();
+        let later = activities(&format!("after={}", crate::http::percent_encode(&after)));
+        assert_eq!(later.len(), 2);
+        assert!(activities("activity_types=DIV").is_empty());
+    }
+
+    #[test]
+    fn mock_mode_serves_a_canned_book_without_the_network() {
+        let config = SimConfig::mock_from_config(Some(&serde_json::json!({ "fill_delay_ms": 0 })))
+            .unwrap()
+            .unwrap();
+        let simulator = Arc::new(Simulator::new(config, false));
+        let transport = MockTransport::new();
+        let client = AlpacaClient::with_transport(
+            String::new(),
+            String::new(),
+            true,
+            ClientOptions {
+                simulator: Some(simulator.clone()),
+                ..ClientOptions::default()
+            },
+            transport.clone(),
+        );
+
+        let positions = client.get_positions().unwrap();
+        let aapl = positions.iter().find(|p| p.symbol_id == "AAPL").unwrap();
+        assert_eq!(positions.len(), 3);
+        assert_eq!(aapl.current_price, 190.0);
+
+        let snapshots = client
+            .get_snapshots(&["AAPL".to_string(), "ZZZ".to_string()])
+            .unwrap();
+        let zzz = mock_quote("ZZZ");
+        assert_eq!(zzz.last, mock_quote("ZZZ").last);
+        assert!(zzz.last >= Decimal::TEN && zzz.last < Decimal::from(500));
+        assert_eq!(
+            snapshots["ZZZ"].latest_trade.as_ref().unwrap().price,
+            zzz.last
+        );
+        assert_eq!(simulator.summary()["open_orders"], 1);
+        assert!(transport.requests().is_empty());
+    }
+}
@@ -0,0 +1,306 @@
+//! Stale-order sweep
+//!
+//! A GTC order nobody remembers can sit on the book for months, and a DAY
+//! order that is still working after its session closed means the local
+//! view and Alpaca's disagree. With `stale_orders` configured, `tick`
+//! flags such orders once each, and with `cancel: true` cancels them. A
+//! canceled one then reports `Canceled` with `status_reason: "stale"`.
+//!
+//! DAY deadlines follow Alpaca's calendar, so early closes and holidays are
+//! respected: the close of the first session ending after the order was
+//! placed (20:00 ET for extended-hours orders). Without the calendar the
+//! next weekday's 16:00 ET close is used. GTD orders (`expire_at`) have
+//! their own expiry and are left alone.
+
+use crate::alpaca::{self, CalendarDay};
+use crate::market_time;
+use crate::orders;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc, Weekday};
+use models::order::{Order, OrderStatus};
+use serde::Serialize;
+use std::collections::HashSet;
+
+const DEFAULT_GTC_MAX_AGE_DAYS: u64 = 30;
+const DEFAULT_GRACE_MINUTES: u64 = 15;
+
+#[derive(Clone, Debug, Default)]
+pub struct StaleOrderRules {
+    /// GTC orders older than this are stale
+    pub gtc_max_age: Option<Duration>,
+    /// DAY orders still working this long after their session closed are
+    /// stale
+    pub day_grace: Option<Duration>,
+    /// Cancel stale orders instead of only flagging them
+    pub cancel: bool,
+}
+
+impl StaleOrderRules {
+    /// `true` for the defaults (GTC after 30 days, DAY 15 minutes past the
+    /// close, flag only), or an object overriding them
+    pub fn from_config(value: Option<&serde_json::Value>) -> Result<Self, String> {
+        let empty = serde_json::Map::new();
+        let object = match value {
+            None | Some(serde_json::Value::Null) | Some(serde_json::Value::Bool(false)) => {
+                return Ok(Self::default())
+            }
+            Some(serde_json::Value::Bool(true)) => &empty,
+            Some(serde_json::Value::Object(object)) => object,
+            Some(_) => return Err("stale_orders must be true or an object".to_string()),
+        };
+
+        let days = |key: &str, default: u64| -> Result<Option<u64>, String> {
+            match object.get(key) {
+                None => Ok(Some(default)),
+                Some(serde_json::Value::Null) => Ok(None),
+                Some(v) => v.as_u64().filter(|n| *n > 0).map(Some).ok_or_else(|| {
+                    format!("stale_orders.{} must be a positive integer or null", key)
+                }),
+            }
+        };
+        let gtc_max_age =
+            days("gtc_max_age_days", DEFAULT_GTC_MAX_AGE_DAYS)?.map(|d| Duration::days(d as i64));
+        let day_grace = match object.get("day_orders_past_close") {
+            None => true,
+            Some(v) => v
+                .as_bool()
+                .ok_or("stale_orders.day_orders_past_close must be true or false")?,
+        }
+        .then(|| days("grace_minutes", DEFAULT_GRACE_MINUTES))
+        .transpose()?
+        .flatten()
+        .map(|m| Duration::minutes(m as i64));
+        let cancel = match object.get("cancel") {
+            None => false,
+            Some(v) => v
+                .as_bool()
+                .ok_or("stale_orders.cancel must be true or false")?,
+        };
+        Ok(Self {
+            gtc_max_age,
+            day_grace,
+            cancel,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.gtc_max_age.is_some() || self.day_grace.is_some()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StaleReason {
+    /// A GTC order past `gtc_max_age_days`
+    GtcAge,
+    /// A DAY order still working after its session closed
+    DayPastClose,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StaleOrder {
+    pub order_id: String,
+    pub symbol: String,
+    pub time_in_force: String,
+    pub created_at: DateTime<Utc>,
+    pub reason: StaleReason,
+    /// When the order became stale
+    pub stale_since: DateTime<Utc>,
+}
+
+/// `time_in_force` as requested, or as Alpaca reported it for orders the
+/// plugin did not place
+pub fn time_in_force(order: &Order) -> String {
+    let requested = order
+        .request
+        .extensions
+        .as_ref()
+        .and_then(|e| e.get("time_in_force"))
+        .and_then(|v| v.as_str());
+    requested
+        .or(order.request.time_in_force.as_deref())
+        .unwrap_or("day")
+        .to_ascii_lowercase()
+}
+
+fn extended_hours(order: &Order) -> bool {
+    alpaca::requested_extended_hours(&order.request)
+        || order
+            .extensions
+            .as_ref()
+            .and_then(|e| e.get("extended_hours"))
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+}
+
+/// Close of the first session ending after `placed`. `None` when the
+/// calendar has no such session yet.
+pub fn session_close(
+    placed: DateTime<Utc>,
+    extended_hours: bool,
+    calendar: Option<&[CalendarDay]>,
+) -> Option<DateTime<Utc>> {
+    let at = |date: NaiveDate, close: NaiveTime| market_time::eastern_at(date, close);
+    let after_hours = NaiveTime::from_hms_opt(20, 0, 0).expect("valid time");
+    let regular = NaiveTime::from_hms_opt(16, 0, 0).expect("valid time");
+    let placed_on = market_time::eastern_date(placed);
+
+    let Some(calendar) = calendar else {
+        let close = if extended_hours { after_hours } else { regular };
+        let mut date = placed_on;
+        loop {
+            let weekday = !matches!(date.weekday(), Weekday::Sat | Weekday::Sun);
+            if weekday && at(date, close) > placed {
+                return Some(at(date, close));
+            }
+            date = date.succ_opt()?;
+        }
+    };
+    let mut sessions: Vec<&CalendarDay> = calendar.iter().filter(|d| d.date >= placed_on).collect();
+    sessions.sort_by_key(|d| d.date);
+    sessions.into_iter().find_map(|day| {
+        let close = match extended_hours {
+            true => after_hours,
+            false => day
+                .close
+                .as_deref()
+                .and_then(|c| NaiveTime::parse_from_str(c, "%H:%M").ok())
+                .unwrap_or(regular),
+        };
+        Some(at(day.date, close)).filter(|close| *close > placed)
+    })
+}
+
+/// Why `order` is stale as of `now`, if it is
+pub fn check(
+    order: &Order,
+    rules: &StaleOrderRules,
+    calendar: Option<&[CalendarDay]>,
+    now: DateTime<Utc>,
+) -> Option<StaleOrder> {
+    let has_expiry = order
+        .request
+        .extensions
+        .as_ref()
+        .is_some_and(|e| e.get("expire_at").is_some_and(|v| !v.is_null()));
+    if !orders::parent_working(order) || has_expiry {
+        return None;
+    }
+    let time_in_force = time_in_force(order);
+    let (reason, stale_since) = match time_in_force.as_str() {
+        "gtc" => (StaleReason::GtcAge, order.created_at + rules.gtc_max_age?),
+        "day" => (
+            StaleReason::DayPastClose,
+            session_close(order.created_at, extended_hours(order), calendar)? + rules.day_grace?,
+        ),
+        _ => return None,
+    };
+    (now >= stale_since).then(|| StaleOrder {
+        order_id: order.id.clone(),
+        symbol: order.request.symbol_id.clone(),
+        time_in_force,
+        created_at: order.created_at,
+        reason,
+        stale_since,
+    })
+}
+
+/// Orders already flagged or canceled, so each is reported once
+#[derive(Default)]
+pub struct StaleBook {
+    reported: HashSet<String>,
+}
+
+impl StaleBook {
+    /// Whether `order_id` has not been reported before; it is from now on
+    pub fn first_report(&mut self, order_id: &str) -> bool {
+        self.reported.insert(order_id.to_string())
+    }
+
+    /// Drop orders that are no longer working
+    pub fn retain(&mut self, working: impl Fn(&str) -> bool) {
+        self.reported.retain(|id| working(id));
+    }
+}
+
+/// Whether the plugin has asked Alpaca to cancel this order as stale
+pub fn cancel_requested(order: &Order) -> bool {
+    order
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.get("stale_cancel_requested"))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
+
+/// Report an order canceled by the sweep as stale. An order that filled
+/// before the cancel landed keeps its status.
+pub fn mark_canceled(order: &mut Order) {
+    if order.status == OrderStatus::Canceled {
+        orders::set_ext(order, "status_reason", "stale");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+
+    fn order(time_in_force: &str, created_at: &str) -> Order {
+        serde_json::from_value(serde_json::json!({
+            "id": format!("{}-order", time_in_force),
+            "request": order_request("AAPL")
+                .limit(100.0)
+                .extension("time_in_force", time_in_force)
+                .build(),
+            "status": OrderStatus::Submitted,
+            "created_at": created_at,
+            "updated_at": created_at,
+            "average_filled_price": null,
+            "filled_quantity": 0.0,
+            "extensions": { "is_terminal": false },
+            "persona_id": "default",
+        }))
+        .unwrap()
+    }
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn gtc_orders_age_out_and_day_orders_expire_after_their_session() {
+        let rules = StaleOrderRules::from_config(Some(&serde_json::json!(true))).unwrap();
+        let gtc = order("gtc", "2024-03-01T15:00:00Z");
+        assert!(check(&gtc, &rules, None, at("2024-03-30T15:00:00Z")).is_none());
+        let stale = check(&gtc, &rules, None, at("2024-03-31T15:00:00Z")).unwrap();
+        assert_eq!(stale.reason, StaleReason::GtcAge);
+
+        // Placed Wednesday 2024-07-03 after the 13:00 ET early close:
+        // July 4th is a holiday, so it works until Friday's close
+        let day = order("day", "2024-07-03T18:00:00Z");
+        let calendar = [
+            CalendarDay {
+                date: NaiveDate::from_ymd_opt(2024, 7, 3).unwrap(),
+                settlement_date: None,
+                close: Some("13:00".to_string()),
+            },
+            CalendarDay {
+                date: NaiveDate::from_ymd_opt(2024, 7, 5).unwrap(),
+                settlement_date: None,
+                close: Some("16:00".to_string()),
+            },
+        ];
+        let friday_close = at("2024-07-05T20:00:00Z");
+        assert_eq!(
+            session_close(day.created_at, false, Some(&calendar)),
+            Some(friday_close)
+        );
+        let before = friday_close + Duration::minutes(10);
+        assert!(check(&day, &rules, Some(&calendar), before).is_none());
+        let after = friday_close + Duration::minutes(15);
+        let stale = check(&day, &rules, Some(&calendar), after).unwrap();
+        assert_eq!(stale.reason, StaleReason::DayPastClose);
+        // The calendar does not reach a close yet
+        assert!(check(&day, &rules, Some(&calendar[..1]), after).is_none());
+    }
+}
@@ -0,0 +1,137 @@
+//! Ticker changes
+//!
+//! When a company changes its ticker, Alpaca moves the position to the
+//! new symbol, but everything the plugin cached under the old one would
+//! go stale: orders, the fills that attribute positions to personas, and
+//! requests still waiting to be sent. `tick` checks the corporate actions
+//! API for name changes at most once an hour and, for each one processed
+//! since the last [`LOOKBACK_DAYS`], remaps the cached state from the old
+//! symbol to the new and reports a `symbol_changed` event. Changes that
+//! touch nothing the plugin knows about are skipped quietly.
+
+use crate::alpaca::NameChange;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::Serialize;
+use std::collections::HashSet;
+
+pub const CHECK_INTERVAL_MINUTES: i64 = 60;
+/// How far back each check looks, so a weekend or an outage is covered
+pub const LOOKBACK_DAYS: i64 = 7;
+
+/// Cached entries moved to the new symbol, by kind
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Remapped {
+    pub orders: usize,
+    pub executions: usize,
+    pub queued_orders: usize,
+    pub scheduled_orders: usize,
+    pub algo_orders: usize,
+    pub conditional_orders: usize,
+    pub protective_orders: usize,
+    /// The last `get_position_changes` snapshot held the old symbol
+    pub position_changes: bool,
+}
+
+/// A ticker change applied to the cached state
+#[derive(Clone, Debug, Serialize)]
+pub struct SymbolChanged {
+    pub old_symbol: String,
+    pub new_symbol: String,
+    pub process_date: NaiveDate,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_cusip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_cusip: Option<String>,
+    pub remapped: Remapped,
+}
+
+impl SymbolChanged {
+    pub fn new(change: &NameChange, remapped: Remapped) -> Self {
+        Self {
+            old_symbol: change.old_symbol.clone(),
+            new_symbol: change.new_symbol.clone(),
+            process_date: change.process_date,
+            old_cusip: change.old_cusip.clone(),
+            new_cusip: change.new_cusip.clone(),
+            remapped,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SymbolChangeWatch {
+    checked_at: Option<DateTime<Utc>>,
+    /// Corporate action IDs already applied
+    applied: HashSet<String>,
+}
+
+impl SymbolChangeWatch {
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.checked_at
+            .is_none_or(|t| now - t >= Duration::minutes(CHECK_INTERVAL_MINUTES))
+    }
+
+    /// Date range to ask for
+    pub fn window(today: NaiveDate) -> (NaiveDate, NaiveDate) {
+        (today - Duration::days(LOOKBACK_DAYS), today)
+    }
+
+    /// Start a check; a failed one waits for the next interval too
+    pub fn mark_checked(&mut self, now: DateTime<Utc>) {
+        self.checked_at = Some(now);
+    }
+
+    /// The changes in effect by `today` that were not applied before, in
+    /// the order they took effect
+    pub fn take_new(&mut self, changes: Vec<NameChange>, today: NaiveDate) -> Vec<NameChange> {
+        let mut fresh: Vec<NameChange> = changes
+            .into_iter()
+            .filter(|c| c.process_date <= today)
+            .filter(|c| !c.old_symbol.eq_ignore_ascii_case(&c.new_symbol))
+            .filter(|c| self.applied.insert(c.id.clone()))
+            .collect();
+        fresh.sort_by_key(|c| c.process_date);
+        fresh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(id: &str, old: &str, new: &str, date: &str) -> NameChange {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "old_symbol": old,
+            "new_symbol": new,
+            "process_date": date,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn each_change_is_applied_once_it_takes_effect() {
+        let today: NaiveDate = "2024-03-04".parse().unwrap();
+        let now: DateTime<Utc> = "2024-03-04T15:00:00Z".parse().unwrap();
+        let mut watch = SymbolChangeWatch::default();
+        assert!(watch.is_due(now));
+
+        let changes = vec![
+            change("c2", "FB", "META", "2024-03-04"),
+            change("c1", "OLD", "NEW", "2024-03-01"),
+            // Announced, not yet in effect
+            change("c3", "ABC", "XYZ", "2024-03-08"),
+        ];
+        watch.mark_checked(now);
+        let fresh = watch.take_new(changes.clone(), today);
+        let ids: Vec<&str> = fresh.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["c1", "c2"]);
+        assert!(!watch.is_due(now + Duration::minutes(30)));
+
+        // The next check sees the same window again
+        let later: NaiveDate = "2024-03-08".parse().unwrap();
+        let fresh = watch.take_new(changes, later);
+        assert_eq!(fresh.len(), 1);
+        assert_eq!(fresh[0].new_symbol, "XYZ");
+    }
+}
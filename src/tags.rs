@@ -0,0 +1,75 @@
+//! Strategy tags for group cancels
+//!
+//! An order's `extensions.tag` is encoded into its Alpaca client order ID
+//! (`KL-<tag>-<random>`), so the tag is recovered from any order Alpaca
+//! returns, including ones placed before the plugin was restarted.
+
+use models::order::{Order, OrderRequest};
+
+const PREFIX: &str = "KL";
+const MAX_TAG_LEN: usize = 32;
+
+/// The order's tag, if it has one
+pub fn requested(request: &OrderRequest) -> Result<Option<&str>, String> {
+    let Some(value) = request
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.get("tag"))
+        .filter(|v| !v.is_null())
+    else {
+        return Ok(None);
+    };
+    let tag = value.as_str().ok_or("tag must be a string")?;
+    let valid = !tag.is_empty()
+        && tag.len() <= MAX_TAG_LEN
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid {
+        return Err(format!(
+            "Invalid tag {:?}: use 1-{} letters, digits, '_' or '-'",
+            tag, MAX_TAG_LEN
+        ));
+    }
+    Ok(Some(tag))
+}
+
+/// Fresh client order ID, carrying the tag when there is one
+pub fn client_order_id(tag: Option<&str>) -> String {
+    let random = rand::random::<u64>();
+    match tag {
+        Some(tag) => format!("{}-{}-{:016x}", PREFIX, tag, random),
+        None => format!("{}{:016x}", PREFIX, random),
+    }
+}
+
+/// Tag encoded in a client order ID created by this plugin
+pub fn from_client_order_id(client_order_id: &str) -> Option<&str> {
+    let rest = client_order_id.strip_prefix(PREFIX)?.strip_prefix('-')?;
+    let (tag, random) = rest.rsplit_once('-')?;
+    (random.len() == 16 && !tag.is_empty()).then_some(tag)
+}
+
+fn request_ext<'a>(request: &'a OrderRequest, key: &str) -> Option<&'a str> {
+    request
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.get(key))
+        .and_then(|v| v.as_str())
+}
+
+/// Whether a request belongs to the tag or basket
+pub fn request_matches(request: &OrderRequest, tag: Option<&str>, basket_id: Option<&str>) -> bool {
+    tag.is_none_or(|t| request_ext(request, "tag") == Some(t))
+        && basket_id.is_none_or(|b| request_ext(request, "basket_id") == Some(b))
+}
+
+/// Whether an order belongs to the tag or basket, looking at the order's
+/// own extensions (recovered from Alpaca) as well as its request
+pub fn order_matches(order: &Order, tag: Option<&str>, basket_id: Option<&str>) -> bool {
+    let own = |key: &str| crate::orders::ext_str(order, key);
+    tag.is_none_or(|t| own("tag") == Some(t) || request_ext(&order.request, "tag") == Some(t))
+        && basket_id.is_none_or(|b| {
+            own("basket_id") == Some(b) || request_ext(&order.request, "basket_id") == Some(b)
+        })
+}
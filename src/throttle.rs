@@ -0,0 +1,181 @@
+//! Order-rate throttle
+//!
+//! Caps how many orders are sent per rolling minute, overall and per
+//! symbol, configured under `order_throttle`. It protects the account
+//! from a strategy stuck in a loop: once a cap is reached, further orders
+//! are rejected locally with a `throttled` finding whose `retry_after_ms`
+//! says when the oldest counted order leaves the window. Dry runs are
+//! checked but not counted.
+
+use crate::pretrade::Finding;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::VecDeque;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ThrottleLimits {
+    pub max_orders_per_minute: Option<usize>,
+    pub max_orders_per_symbol_per_minute: Option<usize>,
+}
+
+impl ThrottleLimits {
+    /// Parse the `order_throttle` config object; absent keys leave a cap off
+    pub fn from_config(value: Option<&serde_json::Value>) -> Result<Self, String> {
+        let Some(value) = value else {
+            return Ok(Self::default());
+        };
+        let object = value
+            .as_object()
+            .ok_or("order_throttle must be an object")?;
+        let cap = |key: &str| -> Result<Option<usize>, String> {
+            match object.get(key) {
+                None | Some(serde_json::Value::Null) => Ok(None),
+                Some(v) => v
+                    .as_u64()
+                    .filter(|n| *n > 0)
+                    .and_then(|n| usize::try_from(n).ok())
+                    .map(Some)
+                    .ok_or_else(|| format!("order_throttle.{} must be a positive integer", key)),
+            }
+        };
+        Ok(Self {
+            max_orders_per_minute: cap("max_orders_per_minute")?,
+            max_orders_per_symbol_per_minute: cap("max_orders_per_symbol_per_minute")?,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct OrderThrottle {
+    pub limits: ThrottleLimits,
+    /// Orders sent in the last minute, oldest first; kept across
+    /// re-initialization
+    sent: VecDeque<(DateTime<Utc>, String)>,
+}
+
+impl OrderThrottle {
+    /// Finding for an order that would go past a cap
+    pub fn check(&mut self, symbol: &str) -> Option<Finding> {
+        self.check_at(symbol, Utc::now())
+    }
+
+    /// Count an order sent (or queued) for `symbol`
+    pub fn record(&mut self, symbol: &str) {
+        self.record_at(symbol, Utc::now());
+    }
+
+    fn check_at(&mut self, symbol: &str, now: DateTime<Utc>) -> Option<Finding> {
+        self.expire(now);
+        let symbol = symbol.to_ascii_uppercase();
+        let all: Vec<DateTime<Utc>> = self.sent.iter().map(|(at, _)| *at).collect();
+        let same: Vec<DateTime<Utc>> = self
+            .sent
+            .iter()
+            .filter(|(_, s)| *s == symbol)
+            .map(|(at, _)| *at)
+            .collect();
+        if let Some(retry) = retry_after(&all, self.limits.max_orders_per_minute, now) {
+            let max = self.limits.max_orders_per_minute.unwrap_or_default();
+            return Some(throttled(
+                format!("Order rate limit of {} per minute reached", max),
+                serde_json::json!({ "scope": "global", "limit": max }),
+                retry,
+            ));
+        }
+        if let Some(retry) = retry_after(&same, self.limits.max_orders_per_symbol_per_minute, now) {
+            let max = self
+                .limits
+                .max_orders_per_symbol_per_minute
+                .unwrap_or_default();
+            return Some(throttled(
+                format!(
+                    "Order rate limit of {} per minute reached for {}",
+                    max, symbol
+                ),
+                serde_json::json!({ "scope": "symbol", "symbol": symbol, "limit": max }),
+                retry,
+            ));
+        }
+        None
+    }
+
+    fn record_at(&mut self, symbol: &str, now: DateTime<Utc>) {
+        if self.limits == ThrottleLimits::default() {
+            return;
+        }
+        self.expire(now);
+        self.sent.push_back((now, symbol.to_ascii_uppercase()));
+    }
+
+    fn expire(&mut self, now: DateTime<Utc>) {
+        while self
+            .sent
+            .front()
+            .is_some_and(|(at, _)| now - *at >= window())
+        {
+            self.sent.pop_front();
+        }
+    }
+}
+
+fn window() -> Duration {
+    Duration::seconds(60)
+}
+
+/// Time until enough of `sent` (oldest first) leaves the window for one
+/// more order under `max`, or `None` when there is room now
+fn retry_after(sent: &[DateTime<Utc>], max: Option<usize>, now: DateTime<Utc>) -> Option<i64> {
+    let max = max?;
+    if sent.len() < max {
+        return None;
+    }
+    let frees_slot = sent[sent.len() - max] + window();
+    Some((frees_slot - now).num_milliseconds().max(1))
+}
+
+fn throttled(message: String, mut details: serde_json::Value, retry_after_ms: i64) -> Finding {
+    if let Some(obj) = details.as_object_mut() {
+        obj.insert("window_ms".to_string(), window().num_milliseconds().into());
+        obj.insert("retry_after_ms".to_string(), retry_after_ms.into());
+    }
+    Finding::new("throttled", message, details)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caps_apply_per_minute_and_report_when_to_retry() {
+        let mut throttle = OrderThrottle {
+            limits: ThrottleLimits::from_config(Some(&serde_json::json!({
+                "max_orders_per_minute": 3,
+                "max_orders_per_symbol_per_minute": 2,
+            })))
+            .unwrap(),
+            ..Default::default()
+        };
+        let start = Utc::now();
+        let at = |secs: i64| start + Duration::seconds(secs);
+
+        throttle.record_at("AAPL", at(0));
+        throttle.record_at("aapl", at(10));
+        let symbol = throttle.check_at("AAPL", at(20)).unwrap();
+        assert_eq!(symbol.code, "throttled");
+        assert_eq!(symbol.details["scope"], "symbol");
+        assert_eq!(symbol.details["retry_after_ms"], 40_000);
+        assert!(throttle.check_at("MSFT", at(20)).is_none());
+
+        throttle.record_at("MSFT", at(20));
+        let global = throttle.check_at("TSLA", at(30)).unwrap();
+        assert_eq!(global.details["scope"], "global");
+        assert_eq!(global.details["retry_after_ms"], 30_000);
+
+        // The first AAPL order has left the window
+        assert!(throttle.check_at("AAPL", at(60)).is_none());
+
+        assert!(ThrottleLimits::from_config(Some(&serde_json::json!({
+            "max_orders_per_minute": 0
+        })))
+        .is_err());
+    }
+}
@@ -0,0 +1,55 @@
+//! Trace context propagation
+//!
+//! Hosts may include a `trace_id` in any request envelope. It is held for
+//! the duration of the export call, sent to Alpaca as `X-Trace-Id`, added to
+//! every log line, and echoed in the response so a failed order can be
+//! followed across host, plugin, and Alpaca support tickets.
+
+use crate::http::{HttpRequest, HttpResponse, Middleware, Next};
+use serde::Deserialize;
+use std::cell::RefCell;
+
+pub const TRACE_HEADER: &str = "X-Trace-Id";
+
+/// Header Alpaca uses to identify a request in support tickets
+pub const ALPACA_REQUEST_ID_HEADER: &str = "X-Request-ID";
+
+thread_local! {
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(default)]
+    trace_id: Option<String>,
+}
+
+/// Make the envelope's `trace_id` (if any) current for this export call
+pub fn begin(request_bytes: &[u8]) {
+    let trace_id = crate::wire::current()
+        .decode::<Envelope>(request_bytes)
+        .ok()
+        .and_then(|e| e.trace_id)
+        .filter(|id| !id.is_empty());
+    set(trace_id);
+}
+
+pub fn set(trace_id: Option<String>) {
+    CURRENT.with(|c| *c.borrow_mut() = trace_id);
+}
+
+pub fn current() -> Option<String> {
+    CURRENT.with(|c| c.borrow().clone())
+}
+
+/// Forwards the current trace ID to Alpaca
+pub struct Propagate;
+
+impl Middleware for Propagate {
+    fn handle(&self, mut request: HttpRequest, next: Next<'_>) -> HttpResponse {
+        if let Some(trace_id) = current() {
+            request.headers.insert(TRACE_HEADER.to_string(), trace_id);
+        }
+        next.run(request)
+    }
+}
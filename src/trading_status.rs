@@ -0,0 +1,171 @@
+//! Whether a symbol can be traded right now
+//!
+//! Alpaca's asset record says whether a symbol is active and tradable; an
+//! inactive asset has usually been delisted. Alpaca does not publish halts
+//! or LULD pauses as a flag, so during market hours a snapshot whose
+//! latest quote has neither a bid nor an ask is reported as halted. The
+//! quote's own time is included so the host can judge it.
+
+use crate::alpaca::{AlpacaAsset, MarketClock};
+use crate::decimal::Decimal;
+use crate::market_data::Snapshot;
+use crate::pretrade::{CheckMode, CheckOutcome, Finding};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TradingState {
+    Tradable,
+    /// Market open but no two-sided quote
+    Halted,
+    /// The asset is inactive, usually because it was delisted
+    Inactive,
+    /// Active but flagged not tradable by Alpaca
+    NotTradable,
+    /// Alpaca does not know the symbol
+    Unknown,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TradingStatus {
+    pub symbol: String,
+    pub state: TradingState,
+    /// Whether the host should allow new orders
+    pub can_trade: bool,
+    pub reasons: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asset: Option<AlpacaAsset>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quote_at: Option<DateTime<Utc>>,
+}
+
+/// Status from the asset record alone
+pub fn from_asset(symbol: &str, asset: Option<AlpacaAsset>) -> TradingStatus {
+    let mut reasons = Vec::new();
+    let state = match &asset {
+        None => {
+            reasons.push(format!("{} is not a known asset", symbol));
+            TradingState::Unknown
+        }
+        Some(a) if !a.status.eq_ignore_ascii_case("active") => {
+            reasons.push(format!("Asset status is {}", a.status));
+            TradingState::Inactive
+        }
+        Some(a) if !a.tradable => {
+            reasons.push("Asset is not tradable on Alpaca".to_string());
+            TradingState::NotTradable
+        }
+        Some(_) => TradingState::Tradable,
+    };
+    TradingStatus {
+        symbol: symbol.to_string(),
+        state,
+        can_trade: state == TradingState::Tradable,
+        reasons,
+        asset,
+        quote_at: None,
+    }
+}
+
+/// Refine a tradable status with the latest quote while the market is open
+pub fn apply_snapshot(
+    status: &mut TradingStatus,
+    snapshot: Option<&Snapshot>,
+    clock: &MarketClock,
+) {
+    if status.state != TradingState::Tradable || !clock.is_open {
+        return;
+    }
+    let quote = snapshot.and_then(|s| s.latest_quote.as_ref());
+    status.quote_at = quote.map(|q| q.timestamp);
+    let two_sided =
+        quote.is_some_and(|q| q.bid_price > Decimal::ZERO && q.ask_price > Decimal::ZERO);
+    if !two_sided {
+        status.state = TradingState::Halted;
+        status.can_trade = false;
+        status
+            .reasons
+            .push("No two-sided quote while the market is open".to_string());
+    }
+}
+
+/// Pre-trade finding for an asset that cannot be traded
+pub fn check(mode: CheckMode, status: &TradingStatus) -> CheckOutcome {
+    if status.can_trade {
+        return CheckOutcome::Pass;
+    }
+    mode.apply(Finding::new(
+        "asset_not_tradable",
+        status.reasons.join("; "),
+        serde_json::json!({ "symbol": status.symbol, "state": status.state }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn asset(status: &str, tradable: bool) -> AlpacaAsset {
+        serde_json::from_value(serde_json::json!({
+            "symbol": "XYZ",
+            "status": status,
+            "tradable": tradable,
+        }))
+        .unwrap()
+    }
+
+    fn clock(is_open: bool) -> MarketClock {
+        serde_json::from_value(serde_json::json!({
+            "timestamp": "2024-01-02T15:00:00Z",
+            "is_open": is_open,
+            "next_open": "2024-01-03T14:30:00Z",
+            "next_close": "2024-01-02T21:00:00Z",
+        }))
+        .unwrap()
+    }
+
+    fn snapshot(bid: f64, ask: f64) -> Snapshot {
+        serde_json::from_value(serde_json::json!({
+            "latestQuote": { "t": "2024-01-02T15:00:00Z", "bp": bid, "ap": ask },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn asset_record_decides_first() {
+        assert_eq!(from_asset("XYZ", None).state, TradingState::Unknown);
+        assert_eq!(
+            from_asset("XYZ", Some(asset("inactive", false))).state,
+            TradingState::Inactive
+        );
+        assert_eq!(
+            from_asset("XYZ", Some(asset("active", false))).state,
+            TradingState::NotTradable
+        );
+        let status = from_asset("XYZ", Some(asset("active", true)));
+        assert!(status.can_trade);
+        assert!(matches!(
+            check(CheckMode::Enforce, &status),
+            CheckOutcome::Pass
+        ));
+        let blocked = from_asset("XYZ", Some(asset("inactive", true)));
+        assert!(matches!(
+            check(CheckMode::Enforce, &blocked),
+            CheckOutcome::Block(_)
+        ));
+    }
+
+    #[test]
+    fn one_sided_quote_while_open_reads_as_halted() {
+        let mut status = from_asset("XYZ", Some(asset("active", true)));
+        apply_snapshot(&mut status, Some(&snapshot(0.0, 0.0)), &clock(false));
+        assert!(status.can_trade, "closed markets are not halts");
+
+        apply_snapshot(&mut status, Some(&snapshot(10.0, 10.02)), &clock(true));
+        assert!(status.can_trade);
+        apply_snapshot(&mut status, Some(&snapshot(0.0, 0.0)), &clock(true));
+        assert_eq!(status.state, TradingState::Halted);
+        assert!(!status.can_trade);
+    }
+}
@@ -0,0 +1,171 @@
+//! Order field validation
+//!
+//! Checks Alpaca would otherwise reject with a 422, plus a fat-finger
+//! guard, run locally before any other check so a bad order comes back
+//! with every offending field at once. Quantities and prices must be
+//! positive. Equity prices follow the sub-penny rule: whole cents at $1.00
+//! and above, at most four decimals below. A limit or stop price more than
+//! `price_band_pct` away from the order's `reference_price` is taken for a
+//! typo unless `extensions.allow_price_deviation` is set.
+
+use crate::decimal::{self, Decimal};
+use crate::fees::AssetKind;
+use crate::orders;
+use crate::pretrade::Finding;
+use models::order::OrderRequest;
+use serde::Serialize;
+
+pub const DEFAULT_PRICE_BAND_PCT: i64 = 20;
+
+/// Decimal places allowed in an equity price: $0.01 increments, or
+/// $0.0001 below $1.00
+pub fn max_places(price: Decimal) -> u32 {
+    if price < Decimal::ONE {
+        4
+    } else {
+        2
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(field: &'static str, code: &'static str, message: String) -> Self {
+        Self {
+            field,
+            code,
+            message,
+        }
+    }
+}
+
+/// Every field problem with `order`; `band_pct` of `None` turns the price
+/// band off
+pub fn field_errors(order: &OrderRequest, band_pct: Option<Decimal>) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    if decimal::from_f64(order.quantity).is_none_or(|q| q <= Decimal::ZERO) {
+        errors.push(FieldError::new(
+            "quantity",
+            "non_positive_quantity",
+            format!("quantity must be positive, got {}", order.quantity),
+        ));
+    }
+
+    let equity = AssetKind::of(&order.symbol_id) == AssetKind::Equity;
+    let reference = order
+        .reference_price
+        .and_then(decimal::from_f64)
+        .filter(|r| *r > Decimal::ZERO);
+    let allow_deviation = orders::request_flag(order, "allow_price_deviation");
+    for (field, price) in [
+        ("limit_price", order.limit_price),
+        ("stop_price", order.stop_price),
+    ] {
+        let Some(raw) = price else {
+            continue;
+        };
+        let price = match decimal::from_f64(raw).filter(|p| *p > Decimal::ZERO) {
+            Some(price) => price.normalize(),
+            None => {
+                errors.push(FieldError::new(
+                    field,
+                    "non_positive_price",
+                    format!("{} must be positive, got {}", field, raw),
+                ));
+                continue;
+            }
+        };
+        let places = max_places(price);
+        if equity && price.scale() > places {
+            let increment = if places == 2 { "$0.01" } else { "$0.0001" };
+            errors.push(FieldError::new(
+                field,
+                "sub_penny_price",
+                format!(
+                    "{} {} is not a multiple of {}",
+                    field,
+                    decimal::to_wire(price),
+                    increment
+                ),
+            ));
+        }
+        if let Some((reference, band)) = reference.zip(band_pct).filter(|_| !allow_deviation) {
+            let deviation = ((price - reference) / reference * Decimal::ONE_HUNDRED)
+                .abs()
+                .round_dp(2);
+            if deviation > band {
+                errors.push(FieldError::new(
+                    field,
+                    "price_band",
+                    format!(
+                        "{} {} is {}% away from the reference price {}, beyond the {}% band; set extensions.allow_price_deviation to send it anyway",
+                        field,
+                        decimal::to_wire(price),
+                        decimal::to_wire(deviation),
+                        decimal::to_wire(reference),
+                        decimal::to_wire(band)
+                    ),
+                ));
+            }
+        }
+    }
+    errors
+}
+
+/// The `invalid_order` finding for `errors`, if there are any
+pub fn check(order: &OrderRequest, band_pct: Option<Decimal>) -> Option<Finding> {
+    let errors = field_errors(order, band_pct);
+    let first = errors.first()?;
+    let message = match errors.len() {
+        1 => first.message.clone(),
+        n => format!("{} (and {} more)", first.message, n - 1),
+    };
+    Some(Finding::new(
+        "invalid_order",
+        message,
+        serde_json::json!({ "errors": errors }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+
+    #[test]
+    fn each_bad_field_is_reported() {
+        let limit =
+            |qty: f64, price: f64| order_request("AAPL").qty(qty).limit(price).reference(100.0);
+        let band = Some(Decimal::from(DEFAULT_PRICE_BAND_PCT));
+        assert!(field_errors(&limit(10.0, 101.25).stop(99.0).build(), band).is_empty());
+
+        let errors = field_errors(&limit(0.0, 101.255).stop(-1.0).build(), band);
+        let found: Vec<_> = errors.iter().map(|e| (e.field, e.code)).collect();
+        assert_eq!(
+            found,
+            [
+                ("quantity", "non_positive_quantity"),
+                ("limit_price", "sub_penny_price"),
+                ("stop_price", "non_positive_price"),
+            ]
+        );
+
+        // Four decimals are fine below $1, and the band needs the override
+        let penny = limit(10.0, 0.1234).reference(0.12).build();
+        assert!(field_errors(&penny, band).is_empty());
+        let mut typo = limit(10.0, 1000.0).build();
+        assert_eq!(field_errors(&typo, band)[0].code, "price_band");
+        assert!(field_errors(&typo, None).is_empty());
+        typo.extensions = Some(
+            [("allow_price_deviation".to_string(), true.into())]
+                .into_iter()
+                .collect(),
+        );
+        assert!(field_errors(&typo, band).is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+//! Wash-sale warnings
+//!
+//! A loss realized on a sale is disallowed for tax purposes when the same
+//! security is bought back within 30 days. With `wash_sale_policy` set,
+//! every buy is compared with the realized losses in the fill ledger (see
+//! [`crate::pnl`]) and one in the same symbol closed within the last 30
+//! days yields a `wash_sale` finding, a warning or a block depending on
+//! the mode. The rule applies per taxpayer, so losses from every persona
+//! count. A loss taken covering a short is not a loss on a sale and is
+//! left out.
+
+use crate::decimal::{self, Decimal};
+use crate::pnl::RealizedTrade;
+use crate::pretrade::{CheckMode, CheckOutcome, Finding};
+use chrono::{DateTime, Duration, Utc};
+use models::order::{OrderRequest, OrderSide};
+
+/// Days after a loss in which a purchase triggers the rule
+pub const WINDOW_DAYS: i64 = 30;
+
+pub fn check(
+    mode: CheckMode,
+    order: &OrderRequest,
+    trades: &[RealizedTrade],
+    now: DateTime<Utc>,
+) -> CheckOutcome {
+    if mode == CheckMode::Off || order.side != OrderSide::Buy {
+        return CheckOutcome::Pass;
+    }
+    let symbol = order.symbol_id.to_ascii_uppercase();
+    let since = now - Duration::days(WINDOW_DAYS);
+    let losses: Vec<&RealizedTrade> = trades
+        .iter()
+        .filter(|t| {
+            !t.short
+                && t.pnl < Decimal::ZERO
+                && t.closed_at >= since
+                && t.symbol.eq_ignore_ascii_case(&symbol)
+        })
+        .collect();
+    let Some(latest) = losses.iter().map(|t| t.closed_at).max() else {
+        return CheckOutcome::Pass;
+    };
+
+    let total: Decimal = losses.iter().map(|t| t.pnl).sum();
+    let details = serde_json::json!({
+        "symbol": symbol,
+        "disallowed_loss": decimal::to_wire(-total),
+        "last_loss_at": latest,
+        "window_ends_at": latest + Duration::days(WINDOW_DAYS),
+        "losses": losses
+            .iter()
+            .map(|t| serde_json::json!({
+                "order_id": t.order_id,
+                "lot_id": t.lot_id,
+                "qty": decimal::to_wire(t.qty),
+                "pnl": decimal::to_wire(t.pnl),
+                "closed_at": t.closed_at,
+            }))
+            .collect::<Vec<_>>(),
+    });
+    mode.apply(Finding::new(
+        "wash_sale",
+        format!(
+            "Buying {} within {} days of a realized loss of {} may be a wash sale",
+            symbol,
+            WINDOW_DAYS,
+            decimal::to_wire(-total)
+        ),
+        details,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::order_request;
+    use crate::pnl::LotMethod;
+
+    fn trade(symbol: &str, pnl: i64, days_ago: i64, now: DateTime<Utc>) -> RealizedTrade {
+        RealizedTrade {
+            symbol: symbol.to_string(),
+            persona_id: "default".to_string(),
+            order_id: format!("sell-{}", days_ago),
+            execution_id: format!("fill-{}", days_ago),
+            lot_id: "lot".to_string(),
+            lot_method: LotMethod::Fifo,
+            qty: Decimal::from(10),
+            open_price: Decimal::from(100),
+            close_price: Decimal::from(100) + Decimal::from(pnl) / Decimal::from(10),
+            pnl: Decimal::from(pnl),
+            opened_at: now - Duration::days(90),
+            closed_at: now - Duration::days(days_ago),
+            short: false,
+        }
+    }
+
+    #[test]
+    fn buys_after_a_recent_loss_are_flagged() {
+        let now = Utc::now();
+        let trades = [
+            trade("AAPL", -200, 10, now),
+            trade("AAPL", -50, 40, now),
+            trade("AAPL", 300, 5, now),
+            trade("MSFT", -100, 1, now),
+        ];
+        match check(
+            CheckMode::Warn,
+            &order_request("aapl").side(OrderSide::Buy).qty(5.0).build(),
+            &trades,
+            now,
+        ) {
+            CheckOutcome::Warn(finding) => {
+                assert_eq!(finding.code, "wash_sale");
+                assert_eq!(finding.details["disallowed_loss"], "200");
+                assert_eq!(finding.details["losses"].as_array().unwrap().len(), 1);
+            }
+            _ => panic!("expected a warning"),
+        }
+        assert!(matches!(
+            check(
+                CheckMode::Enforce,
+                &order_request("aapl").side(OrderSide::Buy).qty(5.0).build(),
+                &trades,
+                now
+            ),
+            CheckOutcome::Block(_)
+        ));
+        assert!(matches!(
+            check(
+                CheckMode::Enforce,
+                &order_request("aapl").side(OrderSide::Sell).qty(5.0).build(),
+                &trades,
+                now
+            ),
+            CheckOutcome::Pass
+        ));
+        let old = [trade("AAPL", -200, 31, now)];
+        assert!(matches!(
+            check(
+                CheckMode::Enforce,
+                &order_request("aapl").side(OrderSide::Buy).qty(5.0).build(),
+                &old,
+                now
+            ),
+            CheckOutcome::Pass
+        ));
+    }
+    #[test]
+    fn losses_covering_a_short_are_not_wash_sales() {
+        let now = Utc::now();
+        let buy = order_request("aapl").side(OrderSide::Buy).qty(5.0).build();
+        let mut covered = trade("AAPL", -200, 10, now);
+        covered.short = true;
+        assert!(matches!(
+            check(CheckMode::Enforce, &buy, &[covered.clone()], now),
+            CheckOutcome::Pass
+        ));
+        match check(
+            CheckMode::Warn,
+            &buy,
+            &[covered, trade("AAPL", -50, 5, now)],
+            now,
+        ) {
+            CheckOutcome::Warn(finding) => {
+                assert_eq!(finding.details["disallowed_loss"], "50");
+            }
+            _ => panic!("expected a warning"),
+        }
+    }
+}
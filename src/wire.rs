@@ -0,0 +1,81 @@
+//! Encoding of request and response envelopes at the host boundary
+//!
+//! JSON unless the host lists `"msgpack"` in `encodings` at initialize, in
+//! which case every later request and response is MessagePack (maps with
+//! field names, so the shapes match the JSON ones). `initialize` itself,
+//! and the `http_request`/`host_log` imports, always use JSON.
+
+use serde::Deserialize;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    MessagePack,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::MessagePack => "msgpack",
+        }
+    }
+
+    /// First encoding in the host's preference list that the plugin
+    /// speaks, JSON when none is
+    pub fn negotiate(offered: &[String]) -> Self {
+        offered
+            .iter()
+            .find_map(|name| match name.to_ascii_lowercase().as_str() {
+                "msgpack" | "messagepack" => Some(Encoding::MessagePack),
+                "json" => Some(Encoding::Json),
+                _ => None,
+            })
+            .unwrap_or(Encoding::Json)
+    }
+
+    pub fn decode<'a, T: Deserialize<'a>>(self, bytes: &'a [u8]) -> Result<T, String> {
+        match self {
+            Encoding::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            Encoding::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Encode an already-scrubbed response
+    pub fn encode(self, value: &serde_json::Value) -> Vec<u8> {
+        match self {
+            Encoding::Json => value.to_string().into_bytes(),
+            Encoding::MessagePack => {
+                rmp_serde::to_vec_named(value).expect("Failed to encode MessagePack response")
+            }
+        }
+    }
+}
+
+static CURRENT: AtomicU8 = AtomicU8::new(0);
+
+pub fn current() -> Encoding {
+    match CURRENT.load(Ordering::Relaxed) {
+        1 => Encoding::MessagePack,
+        _ => Encoding::Json,
+    }
+}
+
+pub fn set(encoding: Encoding) {
+    let raw = match encoding {
+        Encoding::Json => 0,
+        Encoding::MessagePack => 1,
+    };
+    CURRENT.store(raw, Ordering::Relaxed);
+}
+
+/// Switches to the negotiated encoding when dropped, so `initialize` can
+/// still answer in JSON from any of its return paths
+pub struct SwitchOnReturn(pub Encoding);
+
+impl Drop for SwitchOnReturn {
+    fn drop(&mut self) {
+        set(self.0);
+    }
+}
@@ -0,0 +1,184 @@
+//! End-to-end smoke test against the Alpaca paper API
+//!
+//! Ignored by default because it places real (paper) orders. Run it before
+//! a release with paper credentials in the environment:
+//!
+//! ```bash
+//! APCA_API_KEY_ID=... APCA_API_SECRET_KEY=... \
+//!     cargo test --features native --test paper_lifecycle -- --ignored --nocapture
+//! ```
+//!
+//! `APCA_TEST_SYMBOL` picks the symbol (default `SPY`). The resting-order
+//! steps work at any time; the liquidation step needs the market open and
+//! is skipped otherwise.
+
+#![cfg(feature = "native")]
+
+use broker_alpaca::native::{AlpacaClient, ClientOptions, Decimal, PriceSource};
+use models::order::{Order, OrderRequest, OrderSide, OrderStatus, OrderType};
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+const WAIT: Duration = Duration::from_secs(30);
+
+fn env(name: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| panic!("{} must be set to run paper tests", name))
+}
+
+fn paper_client() -> AlpacaClient {
+    let client = AlpacaClient::native(
+        env("APCA_API_KEY_ID"),
+        env("APCA_API_SECRET_KEY"),
+        true,
+        ClientOptions::default(),
+    );
+    assert!(client.is_paper());
+    client
+}
+
+fn request(symbol: &str, side: OrderSide, qty: u32, limit: Option<Decimal>) -> OrderRequest {
+    let order_type = if limit.is_some() {
+        OrderType::Limit
+    } else {
+        OrderType::Market
+    };
+    serde_json::from_value(json!({
+        "symbol_id": symbol,
+        "quantity": qty as f64,
+        "side": side,
+        "order_type": order_type,
+        "limit_price": limit.map(|p| p.to_string().parse::<f64>().unwrap()),
+        "stop_price": null,
+        "persona_id": "paper-smoke-test",
+        "extensions": { "tag": "paper-smoke", "time_in_force": "day" },
+    }))
+    .unwrap()
+}
+
+fn ext_str<'a>(order: &'a Order, key: &str) -> Option<&'a str> {
+    order
+        .extensions
+        .as_ref()
+        .and_then(|e| e.get(key))
+        .and_then(|v| v.as_str())
+}
+
+/// Poll the order until `done` holds, failing after [`WAIT`]
+fn wait_for(client: &AlpacaClient, id: &str, what: &str, done: impl Fn(&Order) -> bool) -> Order {
+    let started = Instant::now();
+    loop {
+        let order = client.get_order(id).expect("get_order");
+        if done(&order) {
+            return order;
+        }
+        assert!(
+            started.elapsed() < WAIT,
+            "order {} never became {} (alpaca status {:?})",
+            id,
+            what,
+            ext_str(&order, "alpaca_status")
+        );
+        std::thread::sleep(Duration::from_millis(500));
+    }
+}
+
+fn is_working(order: &Order) -> bool {
+    matches!(
+        ext_str(order, "alpaca_status"),
+        Some("new" | "accepted" | "partially_filled")
+    )
+}
+
+fn position_qty(client: &AlpacaClient, symbol: &str) -> f64 {
+    client
+        .get_position(symbol)
+        .expect("get_position")
+        .map(|p| p.quantity)
+        .unwrap_or(0.0)
+}
+
+#[test]
+#[ignore]
+fn paper_order_lifecycle() {
+    let client = paper_client();
+    let symbol = std::env::var("APCA_TEST_SYMBOL").unwrap_or_else(|_| "SPY".to_string());
+    let snapshot = client
+        .get_snapshots(std::slice::from_ref(&symbol))
+        .expect("snapshot")
+        .remove(&symbol)
+        .expect("no snapshot for test symbol");
+    let last = snapshot
+        .price(PriceSource::Last)
+        .or(snapshot.price(PriceSource::Mid))
+        .expect("no price for test symbol");
+
+    // Submit: a buy far below the market so it rests
+    let limit = (last / Decimal::TWO).round_dp(2);
+    let original = client
+        .submit_order(&request(&symbol, OrderSide::Buy, 3, Some(limit)))
+        .expect("submit");
+    assert_eq!(ext_str(&original, "tag"), Some("paper-smoke"));
+    wait_for(&client, &original.id, "working", is_working);
+
+    // Replace: one cent higher; Alpaca issues a new order ID
+    let replaced = client
+        .replace_order(&original.id, limit + Decimal::new(1, 2))
+        .expect("replace");
+    assert_ne!(replaced.id, original.id);
+    assert_eq!(
+        replaced.request.limit_price,
+        Some((limit + Decimal::new(1, 2)).to_string().parse().unwrap())
+    );
+    wait_for(&client, &original.id, "replaced", |o| {
+        ext_str(o, "alpaca_status") == Some("replaced")
+    });
+    wait_for(&client, &replaced.id, "working", is_working);
+
+    // Partial cancel: 3 shares down to 1
+    let reduced = client
+        .reduce_order(&replaced.id, Decimal::ONE)
+        .expect("reduce");
+    assert_eq!(ext_str(&reduced, "qty"), Some("1"));
+    wait_for(&client, &reduced.id, "working", is_working);
+
+    client.cancel_order(&reduced.id).expect("cancel");
+    wait_for(&client, &reduced.id, "canceled", |o| {
+        ext_str(o, "alpaca_status") == Some("canceled")
+    });
+
+    // Reconcile: every order in the chain is terminal and nothing filled
+    for id in [&original.id, &replaced.id, &reduced.id] {
+        let order = client.get_order(id).expect("get_order");
+        assert_eq!(order.status, OrderStatus::Canceled, "order {}", id);
+        assert_eq!(order.filled_quantity, 0.0, "order {}", id);
+        assert_eq!(
+            order.extensions.as_ref().and_then(|e| e.get("is_terminal")),
+            Some(&json!(true))
+        );
+    }
+    client.get_account().expect("account");
+
+    // Liquidate: open one share and close it again
+    if !client.get_clock().expect("clock").is_open {
+        eprintln!("market closed; skipping the liquidation step");
+        return;
+    }
+    let before = position_qty(&client, &symbol);
+    let buy = client
+        .submit_order(&request(&symbol, OrderSide::Buy, 1, None))
+        .expect("market buy");
+    let filled = wait_for(&client, &buy.id, "filled", |o| {
+        o.status == OrderStatus::Filled
+    });
+    assert_eq!(filled.filled_quantity, 1.0);
+    assert!(filled.average_filled_price.is_some());
+    assert_eq!(position_qty(&client, &symbol), before + 1.0);
+
+    let sell = client
+        .submit_order(&request(&symbol, OrderSide::Sell, 1, None))
+        .expect("market sell");
+    wait_for(&client, &sell.id, "filled", |o| {
+        o.status == OrderStatus::Filled
+    });
+    assert_eq!(position_qty(&client, &symbol), before);
+}